@@ -12,7 +12,8 @@ use ipa_core::{
     protocol::{step::ProtocolStep::IpaPrf, Gate},
     test_fixture::{
         ipa::{ipa_in_the_clear, test_oprf_ipa, CappingOrder, IpaSecurityModel},
-        EventGenerator, EventGeneratorConfig, TestWorld, TestWorldConfig,
+        EventDistribution, EventGenerator, EventGeneratorConfig, TestWorld, TestWorldConfig,
+        TimestampPattern,
     },
 };
 use ipa_step::StepNarrow;
@@ -49,6 +50,15 @@ struct Args {
     /// The maximum trigger value.
     #[arg(short = 't', long, default_value = "5")]
     max_trigger_value: u32,
+    /// Distribution of the number of records for each person.
+    #[arg(long, value_enum, default_value_t = EventDistribution::Uniform)]
+    records_per_user_distribution: EventDistribution,
+    /// Distribution of trigger values.
+    #[arg(long, value_enum, default_value_t = EventDistribution::Uniform)]
+    trigger_value_distribution: EventDistribution,
+    /// How record timestamps are spread over time.
+    #[arg(long, value_enum, default_value_t = TimestampPattern::Uniform)]
+    timestamp_pattern: TimestampPattern,
     /// The size of the attribution window, in seconds.
     #[arg(
         short = 'w',
@@ -128,6 +138,9 @@ async fn run(args: Args) -> Result<(), Error> {
         max_trigger_value: NonZeroU32::try_from(args.max_trigger_value).unwrap(),
         max_breakdown_key: NonZeroU32::try_from(args.breakdown_keys).unwrap(),
         max_events_per_user: NonZeroU32::try_from(args.records_per_user).unwrap(),
+        events_per_user_distribution: args.records_per_user_distribution,
+        trigger_value_distribution: args.trigger_value_distribution,
+        timestamp_pattern: args.timestamp_pattern,
         ..Default::default()
     };
     let raw_data = EventGenerator::with_config(rng, event_gen_config)
@@ -11,17 +11,26 @@
 //! There is also a panic in the `plotters` crate used by Criterion to produce HTML reports that can
 //! occur with very fast-running routines. This can be worked around by passing the `-n` option to
 //! Criterion to disable HTML reports.
+//!
+//! The aggregation benchmarks transpose 256 columns of trigger values into rows, like breakdown
+//! reveal aggregation does. Built with the `alloc-profiling` feature, they also print the peak
+//! heap usage of transposing all values at once and of transposing them in blocks.
 
-use std::{array, iter::repeat_with, time::Duration};
+use std::{array, iter::repeat_with, num::NonZeroUsize, time::Duration};
 
 use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
 use ipa_core::{
     error::UnwrapInfallible,
-    ff::boolean_array::BA64,
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BA64, BA8},
+    },
     secret_sharing::{
-        vector::{transpose_16x16, transpose_8x8},
-        SharedValue, TransposeFrom,
+        replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+        vector::{transpose_16x16, transpose_8x8, BlockedColumns},
+        BitDecomposed, SharedValue, TransposeFrom,
     },
+    telemetry::memory::{stage, MemoryProfile},
 };
 use rand::{
     distributions::{Distribution, Standard},
@@ -108,7 +117,104 @@ fn bench_64x64(c: &mut Criterion) {
     );
 }
 
+#[cfg(feature = "alloc-profiling")]
+#[global_allocator]
+static ALLOC: ipa_core::telemetry::memory::TrackingAllocator<std::alloc::System> =
+    ipa_core::telemetry::memory::TrackingAllocator(std::alloc::System);
+
+const AGGREGATION_ROWS: usize = 4096;
+
+type Columns = [Vec<AdditiveShare<BA8>>; 256];
+type Row = BitDecomposed<AdditiveShare<Boolean, 256>>;
+
+fn aggregation_columns() -> Columns {
+    let mut rng = thread_rng();
+    array::from_fn(|_| {
+        repeat_with(|| AdditiveShare::new(rng.gen(), rng.gen()))
+            .take(AGGREGATION_ROWS)
+            .collect()
+    })
+}
+
+fn transpose_whole(mut columns: Columns) -> Vec<Row> {
+    (0..AGGREGATION_ROWS)
+        .map(|_| {
+            let row = columns
+                .each_mut()
+                .map(|column| column.pop().unwrap_or_default());
+            Row::transposed_from(&row).unwrap_infallible()
+        })
+        .collect()
+}
+
+fn blocked(columns: Columns, block_rows: usize) -> BlockedColumns<AdditiveShare<BA8>, 256> {
+    let mut blocked = BlockedColumns::new(NonZeroUsize::new(block_rows).unwrap());
+    for (i, column) in columns.into_iter().enumerate() {
+        for value in column {
+            blocked.push(i, value);
+        }
+    }
+    blocked
+}
+
+/// Prints the peak heap usage of transposing the aggregation input all at once, and in blocks of
+/// each of `block_sizes` rows. Without the `alloc-profiling` feature, nothing is measured.
+fn report_peak_memory(block_sizes: &[usize]) {
+    let profile = MemoryProfile::default();
+    futures::executor::block_on(profile.scope(async {
+        let columns = aggregation_columns();
+        stage("whole", async { transpose_whole(columns).len() }).await;
+        for &block_rows in block_sizes {
+            let columns = blocked(aggregation_columns(), block_rows);
+            stage(&format!("blocked/{block_rows}"), async {
+                columns.into_rows::<Row>().count()
+            })
+            .await;
+        }
+    }));
+    for stage in profile.stages() {
+        println!(
+            "{}: peak heap {} KiB above the {} KiB at start",
+            stage.step,
+            (stage.peak_bytes - stage.start_bytes) / 1024,
+            stage.start_bytes / 1024,
+        );
+    }
+}
+
+fn bench_aggregation_256(c: &mut Criterion) {
+    const BLOCK_SIZES: [usize; 3] = [256, 1024, 4096];
+    report_peak_memory(&BLOCK_SIZES);
+
+    let mut group = c.benchmark_group(format!("aggregation {AGGREGATION_ROWS}x256"));
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((AGGREGATION_ROWS * 256) as u64));
+    group.bench_function("whole", |b| {
+        b.iter_batched(aggregation_columns, transpose_whole, BatchSize::LargeInput);
+    });
+    for block_rows in BLOCK_SIZES {
+        group.bench_with_input(
+            BenchmarkId::new("blocked", block_rows),
+            &block_rows,
+            |b, &block_rows| {
+                b.iter_batched(
+                    || blocked(aggregation_columns(), block_rows),
+                    |columns| columns.into_rows::<Row>().collect::<Vec<_>>(),
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
 criterion_group!(benches_8x8, bench_8x8);
 criterion_group!(benches_16x16, bench_16x16);
 criterion_group!(benches_64x64, bench_64x64);
-criterion_main!(benches_8x8, benches_16x16, benches_64x64);
+criterion_group!(benches_aggregation_256, bench_aggregation_256);
+criterion_main!(
+    benches_8x8,
+    benches_16x16,
+    benches_64x64,
+    benches_aggregation_256
+);
@@ -38,6 +38,18 @@ fn main() {
         build_gate::<protocol::step::ProtocolStep>();
     }
 
+    // C side of the FFI round trip tests. Build scripts can't tell test builds apart, so it is
+    // behind its own feature rather than compiled into every build of the C ABI.
+    #[cfg(feature = "ffi-test")]
+    {
+        println!("cargo::rerun-if-changed=include/ipa_core.h");
+        println!("cargo::rerun-if-changed=tests/ffi/round_trip.c");
+        cc::Build::new()
+            .include("include")
+            .file("tests/ffi/round_trip.c")
+            .compile("ipa_ffi_round_trip");
+    }
+
     // test is not supported because cfg_aliases is based on
     // https://docs.rs/tectonic_cfg_support macro and that only supports features, target_os, family
     // env, etc.
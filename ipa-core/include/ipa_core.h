@@ -0,0 +1,111 @@
+#ifndef IPA_CORE_H
+#define IPA_CORE_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Length of a helper's public key, in bytes.
+#define IPA_PUBLIC_KEY_LEN 32
+
+// Value of [`IpaReport::event_type`] for impressions.
+#define IPA_EVENT_IMPRESSION 0
+
+// Value of [`IpaReport::event_type`] for conversions.
+#define IPA_EVENT_CONVERSION 1
+
+// Outcome of a call into this library.
+typedef enum IpaStatus {
+  IPA_STATUS_OK,
+  // A pointer argument was null.
+  IPA_STATUS_NULL_POINTER,
+  // An argument is out of range, or a string is not ASCII.
+  IPA_STATUS_INVALID_ARGUMENT,
+  // A public key is not a valid X25519 public key.
+  IPA_STATUS_INVALID_PUBLIC_KEY,
+  IPA_STATUS_ENCRYPTION_FAILED,
+  // The helper outputs can't be parsed, or the helpers' shares are not consistent.
+  IPA_STATUS_RECONSTRUCTION_FAILED,
+  // There is a bug in this library.
+  IPA_STATUS_PANIC,
+} IpaStatus;
+
+// A report in the clear, as the report collector sees it.
+//
+// Impressions use `breakdown_key`, conversions use `value`, `conversion_site_domain`,
+// `timestamp`, `epsilon` and `sensitivity`. Fields that the event type does not use are
+// ignored.
+typedef struct IpaReport {
+  // [`IPA_EVENT_IMPRESSION`] or [`IPA_EVENT_CONVERSION`].
+  uint8_t event_type;
+  uint64_t match_key;
+  // Must fit into 8 bits.
+  uint32_t breakdown_key;
+  // Must fit into 3 bits.
+  uint32_t value;
+  // Null-terminated ASCII string.
+  const char *conversion_site_domain;
+  uint64_t timestamp;
+  double epsilon;
+  double sensitivity;
+} IpaReport;
+
+// Bytes owned by this library, released with [`ipa_buffer_free`].
+typedef struct IpaBuffer {
+  uint8_t *data;
+  uintptr_t len;
+} IpaBuffer;
+
+// Values owned by this library, released with [`ipa_values_free`].
+typedef struct IpaValues {
+  uint64_t *data;
+  uintptr_t len;
+} IpaValues;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Secret shares `report` and encrypts one share towards each helper.
+//
+// `public_keys` holds the public keys of the three helpers, [`IPA_PUBLIC_KEY_LEN`] bytes each,
+// in helper order. On success, the encrypted share for helper `i` is written to `out[i]`. It is
+// in the format that the helpers expect for one report of a hybrid query, that is one line of
+// `helper{i+1}.enc` before hex encoding. Nothing is written to `out` on failure.
+//
+// # Safety
+// `report` must point to a valid [`IpaReport`], `public_keys` must point to
+// `3 * IPA_PUBLIC_KEY_LEN` readable bytes and `out` must point to 3 writable [`IpaBuffer`]s.
+IpaStatus ipa_encrypt_report(const IpaReport *report, const uint8_t *public_keys, IpaBuffer *out);
+
+// Reconstructs the query results from the outputs of the three helpers, in helper order.
+//
+// `value_bits` is the width of the output values, as configured for the query: 16 or 32. On
+// success, the values are written to `results`.
+//
+// # Safety
+// `outputs` must point to 3 [`IpaBuffer`]s, each of them null or pointing to `len` readable
+// bytes. These buffers do not need to be allocated by this library. `results` must point to a
+// writable [`IpaValues`].
+IpaStatus ipa_reconstruct_results(const IpaBuffer *outputs, uint32_t value_bits, IpaValues *results);
+
+// Releases a buffer returned by this library.
+//
+// # Safety
+// `buffer` must have been returned by this library, and must not be used after this call.
+void ipa_buffer_free(IpaBuffer buffer);
+
+// Releases values returned by this library.
+//
+// # Safety
+// `values` must have been returned by this library, and must not be used after this call.
+void ipa_values_free(IpaValues values);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IPA_CORE_H */
@@ -1,4 +1,4 @@
-use std::sync::Weak;
+use std::{sync::Weak, time::Duration};
 
 use async_trait::async_trait;
 
@@ -6,24 +6,43 @@ use crate::{
     cli::LoggingHandle,
     executor::IpaRuntime,
     helpers::{
-        query::{CompareStatusRequest, PrepareQuery, QueryConfig, QueryInput},
+        query::{
+            AppendInput, CompareStatusRequest, CreateFromTemplate, InputContribution,
+            PeerUnavailable, PrepareQuery, QueryConfig, QueryInput, QueryPolicy, QueryTemplates,
+            SealInput, TemplateCommand,
+        },
         routing::{Addr, RouteId},
         ApiError, BodyStream, HandlerBox, HandlerRef, HelperIdentity, HelperResponse,
-        MpcTransportImpl, RequestHandler, ShardTransportImpl, Transport, TransportIdentity,
+        MpcTransportImpl, RequestHandler, ShardTransportImpl, StageConcurrency, Transport,
+        TransportIdentity,
     },
     hpke::{KeyRegistry, PrivateKeyOnly},
     protocol::QueryId,
-    query::{NewQueryError, QueryProcessor, QueryStatus},
+    query::{
+        placement::NetworkMeasurements, InputRetention, ListQueries, NewQueryError, PrfCache,
+        Quarantine, QueryExecutors, QueryProcessor, QueryStatus, Readiness, Redaction, Workspace,
+    },
     sharding::ShardIndex,
     sync::Arc,
-    utils::NonZeroU32PowerOfTwo,
+    utils::{rng::CryptoRngProvider, NonZeroU32PowerOfTwo},
 };
 
 #[derive(Default)]
 pub struct AppConfig {
     active_work: Option<NonZeroU32PowerOfTwo>,
+    stage_concurrency: StageConcurrency,
     key_registry: Option<KeyRegistry<PrivateKeyOnly>>,
+    redaction: Redaction,
+    policy: QueryPolicy,
+    templates: QueryTemplates,
+    quarantine: Option<Quarantine>,
+    prf_cache: Option<Arc<PrfCache>>,
+    input_retention: InputRetention,
     runtime: IpaRuntime,
+    rng_provider: Option<Arc<dyn CryptoRngProvider>>,
+    max_completed_queries: Option<usize>,
+    network: Option<NetworkMeasurements>,
+    peer_timeout: Option<Duration>,
 }
 
 impl AppConfig {
@@ -33,17 +52,103 @@ impl AppConfig {
         self
     }
 
+    /// Limits active work of individual stages of IPA below the active work of the query, in
+    /// queries this helper leads. See [`QueryProcessor::with_stage_concurrency`].
+    #[must_use]
+    pub fn with_stage_concurrency(mut self, stage_concurrency: StageConcurrency) -> Self {
+        self.stage_concurrency = stage_concurrency;
+        self
+    }
+
     #[must_use]
     pub fn with_key_registry(mut self, key_registry: KeyRegistry<PrivateKeyOnly>) -> Self {
         self.key_registry = Some(key_registry);
         self
     }
 
+    #[must_use]
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    #[must_use]
+    pub fn with_query_policy(mut self, policy: QueryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn with_query_templates(mut self, templates: QueryTemplates) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Keeps the reports that fail to decrypt in the given quarantine, for queries that ask for
+    /// it. Without a quarantine, these reports are skipped.
+    #[must_use]
+    pub fn with_quarantine(mut self, quarantine: Quarantine) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Keeps the PRF'd input of queries that ask for it in `prf_cache`, so that later queries
+    /// over the same reports can skip padding, shuffling and PRF evaluation.
+    #[must_use]
+    pub fn with_prf_cache(mut self, prf_cache: PrfCache) -> Self {
+        self.prf_cache = Some(Arc::new(prf_cache));
+        self
+    }
+
+    /// Sets how long the data kept for the input of a query outlives the query. By default, it
+    /// is kept until the report collector deletes it.
+    #[must_use]
+    pub fn with_input_retention(mut self, input_retention: InputRetention) -> Self {
+        self.input_retention = input_retention;
+        self
+    }
+
     #[must_use]
     pub fn with_runtime(mut self, runtime: IpaRuntime) -> Self {
         self.runtime = runtime;
         self
     }
+
+    /// Sets the source of randomness for the helper, e.g. a hardware RNG.
+    #[must_use]
+    pub fn with_rng_provider(mut self, rng_provider: Arc<dyn CryptoRngProvider>) -> Self {
+        self.rng_provider = Some(rng_provider);
+        self
+    }
+
+    /// Sets how many completed queries the helper keeps before it prunes the oldest ones. By
+    /// default, it keeps [`DEFAULT_MAX_COMPLETED_QUERIES`].
+    ///
+    /// [`DEFAULT_MAX_COMPLETED_QUERIES`]: crate::query::DEFAULT_MAX_COMPLETED_QUERIES
+    #[must_use]
+    pub fn with_max_completed_queries(mut self, max_completed_queries: usize) -> Self {
+        self.max_completed_queries = Some(max_completed_queries);
+        self
+    }
+
+    /// Sets the measured links between helpers, which the helper assigns roles of the queries
+    /// it creates from.
+    #[must_use]
+    pub fn with_network_measurements(mut self, network: NetworkMeasurements) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Sets how long the helper waits on its peers before it fails the query because one of
+    /// them is unavailable. By default, it waits for as long as it takes, see
+    /// [`QueryProcessor::with_peer_timeout`].
+    ///
+    /// [`QueryProcessor::with_peer_timeout`]: crate::query::QueryProcessor::with_peer_timeout
+    #[must_use]
+    pub fn with_peer_timeout(mut self, peer_timeout: Duration) -> Self {
+        self.peer_timeout = Some(peer_timeout);
+        self
+    }
 }
 
 pub struct Setup {
@@ -73,7 +178,37 @@ impl Setup {
     #[must_use]
     pub fn new(config: AppConfig) -> (Self, HandlerRef<HelperIdentity>, HandlerRef<ShardIndex>) {
         let key_registry = config.key_registry.unwrap_or_else(KeyRegistry::empty);
-        let query_processor = QueryProcessor::new(key_registry, config.active_work, config.runtime);
+        let mut query_processor =
+            QueryProcessor::new(key_registry, config.active_work, config.runtime)
+                .with_redaction(config.redaction)
+                .with_policy(config.policy)
+                .with_templates(config.templates)
+                .with_stage_concurrency(config.stage_concurrency);
+        if let Some(rng_provider) = config.rng_provider {
+            query_processor = query_processor.with_rng_provider(rng_provider);
+        }
+        if let Some(max_completed_queries) = config.max_completed_queries {
+            query_processor = query_processor.with_max_completed_queries(max_completed_queries);
+        }
+        if let Some(network) = config.network {
+            query_processor = query_processor.with_network_measurements(network);
+        }
+        if let Some(peer_timeout) = config.peer_timeout {
+            query_processor = query_processor.with_peer_timeout(Some(peer_timeout));
+        }
+        // The workspace is installed even if nothing is kept on disk, so that it always applies
+        // the configured retention and deleting the input of a query reports that nothing was
+        // stored.
+        query_processor = query_processor.with_workspace(Workspace::new(
+            config.quarantine.clone(),
+            config.prf_cache.clone(),
+            config.input_retention,
+        ));
+        if config.quarantine.is_some() || config.prf_cache.is_some() {
+            query_processor = query_processor.with_executors(
+                QueryExecutors::with_oprf_ipa_storage(config.quarantine, config.prf_cache),
+            );
+        }
         let mpc_handler = HandlerBox::empty();
         let shard_handler = HandlerBox::empty();
         let this = Self {
@@ -242,6 +377,25 @@ impl RequestHandler<HelperIdentity> for Inner {
                     .await?,
                 )
             }
+            RouteId::ReceiveQueryFromTemplate => {
+                let req = req.into::<CreateFromTemplate>()?;
+                HelperResponse::from(
+                    qp.new_query_from_template(
+                        self.mpc_transport.clone_ref(),
+                        self.shard_transport.clone_ref(),
+                        req,
+                    )
+                    .await?,
+                )
+            }
+            RouteId::QueryTemplates => {
+                let req = req.into::<TemplateCommand>()?;
+                HelperResponse::from(qp.execute_template_command(req)?)
+            }
+            RouteId::ValidateQuery => {
+                let req = req.into::<QueryConfig>()?;
+                HelperResponse::from(qp.validate_query(&req))
+            }
             RouteId::PrepareQuery => {
                 let req = req.into::<PrepareQuery>()?;
                 HelperResponse::from(
@@ -255,20 +409,40 @@ impl RequestHandler<HelperIdentity> for Inner {
             }
             RouteId::QueryInput => {
                 let query_id = ext_query_id(&req)?;
+                let contribution = if req.params.is_empty() {
+                    None
+                } else {
+                    Some(req.into::<InputContribution>()?)
+                };
                 HelperResponse::from(qp.receive_inputs(
                     Transport::clone_ref(&self.mpc_transport),
                     Transport::clone_ref(&self.shard_transport),
                     QueryInput {
                         query_id,
                         input_stream: data,
+                        contribution,
                     },
                 )?)
             }
+            RouteId::AppendQueryInput => {
+                let query_id = ext_query_id(&req)?;
+                let append = req.into::<AppendInput>()?;
+                HelperResponse::from(qp.append_inputs(query_id, append, data).await?)
+            }
+            RouteId::SealQueryInput => {
+                let query_id = ext_query_id(&req)?;
+                let seal = req.into::<SealInput>()?;
+                HelperResponse::from(qp.seal_inputs(
+                    Transport::clone_ref(&self.mpc_transport),
+                    Transport::clone_ref(&self.shard_transport),
+                    query_id,
+                    seal,
+                )?)
+            }
             RouteId::QueryStatus => {
                 let query_id = ext_query_id(&req)?;
                 let shard_transport = Transport::clone_ref(&self.shard_transport);
-                let query_status = qp.query_status(shard_transport, query_id).await?;
-                HelperResponse::from(query_status)
+                HelperResponse::from(qp.query_status_report(shard_transport, query_id).await?)
             }
             RouteId::CompleteQuery => {
                 let query_id = ext_query_id(&req)?;
@@ -281,11 +455,28 @@ impl RequestHandler<HelperIdentity> for Inner {
                 let query_id = ext_query_id(&req)?;
                 HelperResponse::from(qp.kill(query_id)?)
             }
+            RouteId::PeerUnavailable => {
+                let req = req.into::<PeerUnavailable>()?;
+                HelperResponse::from(qp.peer_unavailable(&req)?)
+            }
+            RouteId::DeleteQueryInput => {
+                let query_id = ext_query_id(&req)?;
+                HelperResponse::from(qp.delete_input(query_id)?)
+            }
+            RouteId::ListQueries => {
+                let req = req.into::<ListQueries>()?;
+                HelperResponse::from(qp.list_queries(&req))
+            }
+            RouteId::PruneQuery => {
+                let query_id = ext_query_id(&req)?;
+                HelperResponse::from(qp.prune(query_id)?)
+            }
             RouteId::Metrics => {
                 let logging_handler = &self.logging_handle;
                 let metrics_handle = &logging_handler.metrics_handle;
                 HelperResponse::from(metrics_handle.scrape_metrics())
             }
+            RouteId::Readiness => HelperResponse::from(Readiness::new(qp.readiness())),
         })
     }
 }
@@ -2,9 +2,11 @@ use std::{
     fs,
     io::BufReader,
     net::TcpListener,
+    num::NonZeroUsize,
     os::fd::{FromRawFd, RawFd},
     path::{Path, PathBuf},
     process,
+    time::Duration,
 };
 
 use clap::{self, Parser, Subcommand};
@@ -12,28 +14,46 @@ use futures::future::join;
 use hyper::http::uri::Scheme;
 use ipa_core::{
     cli::{
-        client_config_setup, keygen, sharded_client_config_setup, sharded_server_from_toml_str,
-        test_setup, ConfGenArgs, KeygenArgs, LoggingHandle, ShardedConfGenArgs, TestSetupArgs,
-        Verbosity,
+        client_config_setup, keygen, plan_roles, self_test, sharded_client_config_setup,
+        sharded_server_from_toml_str, test_setup, ConfGenArgs, KeygenArgs, LoggingHandle,
+        PlanRolesArgs, ShardedConfGenArgs, TestSetupArgs, Verbosity,
     },
-    config::{hpke_registry, HpkeServerConfig, ServerConfig, TlsConfig},
+    config::{hpke_registry, AdminToken, HpkeServerConfig, ServerConfig, TlsConfig},
     error::BoxError,
     executor::IpaRuntime,
-    helpers::HelperIdentity,
+    helpers::{
+        query::{QueryPolicy, QueryTemplates, TemplateList},
+        HelperIdentity, StageConcurrency,
+    },
     net::{
-        ClientIdentity, ConnectionFlavor, IpaHttpClient, MpcHttpTransport, Shard,
-        ShardHttpTransport,
+        ClientIdentity, ConnectionFlavor, IpaHttpClient, MpcHttpTransport, OpenFiles,
+        RequestLimits, Shard, ShardHttpTransport, DEFAULT_MAX_HEADER_BYTES, DEFAULT_MAX_URI_LEN,
+    },
+    query::{
+        placement::NetworkMeasurements, InputRetention, PrfCache, Quarantine, Redaction,
+        SensitiveField, DEFAULT_MAX_COMPLETED_QUERIES,
     },
     sharding::ShardIndex,
+    utils::cooperative,
     AppConfig, AppSetup, NonZeroU32PowerOfTwo,
 };
 use tokio::runtime::Runtime;
 use tracing::{error, info};
 
-#[cfg(jemalloc)]
+#[cfg(all(jemalloc, not(feature = "alloc-profiling")))]
 #[global_allocator]
 static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
+#[cfg(all(jemalloc, feature = "alloc-profiling"))]
+#[global_allocator]
+static ALLOC: ipa_core::telemetry::memory::TrackingAllocator<tikv_jemallocator::Jemalloc> =
+    ipa_core::telemetry::memory::TrackingAllocator(tikv_jemallocator::Jemalloc);
+
+#[cfg(all(not(jemalloc), feature = "alloc-profiling"))]
+#[global_allocator]
+static ALLOC: ipa_core::telemetry::memory::TrackingAllocator<std::alloc::System> =
+    ipa_core::telemetry::memory::TrackingAllocator(std::alloc::System);
+
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
@@ -122,6 +142,117 @@ struct ServerArgs {
     /// Override the amount of active work processed in parallel
     #[arg(long)]
     active_work: Option<NonZeroU32PowerOfTwo>,
+
+    /// Limit the active work of match key conversion below the active work of the query, in
+    /// queries this helper leads
+    #[arg(long)]
+    conversion_active_work: Option<NonZeroU32PowerOfTwo>,
+
+    /// Limit the active work of PRF evaluation below the active work of the query, in queries
+    /// this helper leads
+    #[arg(long)]
+    prf_active_work: Option<NonZeroU32PowerOfTwo>,
+
+    /// Limit the active work of aggregation below the active work of the query, to lower its
+    /// memory use, in queries this helper leads
+    #[arg(long)]
+    aggregation_active_work: Option<NonZeroU32PowerOfTwo>,
+
+    /// Number of records processed by local computations, such as transposes, before they let
+    /// the transport run. Lower values reduce network latency at the cost of throughput.
+    #[arg(long)]
+    yield_chunk_size: Option<NonZeroUsize>,
+
+    /// Sensitive fields to include in the privacy parameters logged at query start. All of
+    /// them are redacted by default.
+    #[arg(long, value_enum)]
+    unredact: Vec<SensitiveField>,
+
+    /// Smallest epsilon this helper accepts for queries that add DP noise
+    #[arg(long)]
+    min_epsilon: Option<f64>,
+
+    /// Largest breakdown domain this helper accepts
+    #[arg(long)]
+    max_breakdown_key: Option<u32>,
+
+    /// Largest number of channels, estimated from the query parameters, a query may need for
+    /// this helper to accept it
+    #[arg(long)]
+    max_channels: Option<u64>,
+
+    /// JSON file with the query templates this helper starts with, as a map from template id
+    /// to template. Changes made through the admin API are written back to this file.
+    #[arg(long)]
+    query_templates: Option<PathBuf>,
+
+    /// Only create queries from query templates
+    #[arg(long)]
+    require_query_templates: bool,
+
+    /// File containing the token helper administrators use to manage query templates and to
+    /// inspect certificate pins. These APIs are disabled without it.
+    #[arg(long)]
+    admin_token_file: Option<PathBuf>,
+
+    /// Directory where reports that fail to decrypt are kept, for queries that ask to
+    /// quarantine them. Such reports are skipped without it.
+    #[arg(long)]
+    quarantine_dir: Option<PathBuf>,
+
+    /// Directory where the PRF'd input of queries that ask for it is cached, so that later
+    /// queries over the same reports can skip PRF evaluation
+    #[arg(long, requires = "prf_cache_key_file")]
+    prf_cache_dir: Option<PathBuf>,
+
+    /// File with the hex-encoded 32 byte secret that protects PRF cache entries at rest
+    #[arg(long)]
+    prf_cache_key_file: Option<PathBuf>,
+
+    /// Total epsilon that queries over the same input may spend when using the PRF cache
+    #[arg(long, default_value = "10.0")]
+    prf_cache_epsilon_budget: f64,
+
+    /// How long the quarantined reports and PRF cache entries of a query are kept. Report
+    /// collectors can always delete them earlier. Without `--quarantine-dir` and
+    /// `--prf-cache-dir` nothing is kept, and deleting the input of a query reports that.
+    #[arg(long, value_enum, default_value_t)]
+    input_retention: InputRetention,
+
+    /// Number of completed queries kept until their results are collected. Once there are more,
+    /// the oldest ones are pruned.
+    #[arg(long, default_value_t = DEFAULT_MAX_COMPLETED_QUERIES)]
+    max_completed_queries: usize,
+
+    /// Seconds the helper waits on a peer, to accept a query or to send the next record, before
+    /// it fails the query because the peer is unavailable. Must be longer than any stage in
+    /// which a peer computes without sending. If not set, the helper waits for as long as it
+    /// takes
+    #[arg(long)]
+    peer_timeout_secs: Option<u64>,
+
+    /// TOML file with the round trip time and bandwidth of every link between helpers. If set,
+    /// the helper assigns roles of the queries it creates so that stages that send more data
+    /// between some helpers than between others finish soonest. See the `plan-roles` command.
+    #[arg(long)]
+    links: Option<PathBuf>,
+
+    /// Number of connections, incoming and outgoing, the helper keeps open. The helper refuses
+    /// to start if the OS limit
+    /// on open files does not leave room for them. Defaults to what the OS limit allows.
+    #[arg(long)]
+    max_open_files: Option<usize>,
+
+    /// Longest request target, in bytes, the helper accepts. Longer requests are refused with
+    /// `414 URI Too Long`.
+    #[arg(long, default_value_t = DEFAULT_MAX_URI_LEN)]
+    max_uri_len: usize,
+
+    /// Largest request headers, in bytes, the helper accepts. Every header counts the length of
+    /// its name and value plus 32 bytes. Larger requests are refused with `431 Request Header
+    /// Fields Too Large`.
+    #[arg(long, default_value_t = DEFAULT_MAX_HEADER_BYTES)]
+    max_header_bytes: usize,
 }
 
 #[derive(Debug, Subcommand)]
@@ -130,6 +261,10 @@ enum HelperCommand {
     Confgen(ConfGenArgs),
     Keygen(KeygenArgs),
     TestSetup(TestSetupArgs),
+    /// Check that field arithmetic, HPKE, PRSS and serialization work correctly on this
+    /// machine. Prints a report and exits with an error if any check fails.
+    SelfTest,
+    PlanRoles(PlanRolesArgs),
 }
 
 fn read_file(path: &Path) -> Result<BufReader<fs::File>, BoxError> {
@@ -205,11 +340,79 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
         private_key_file: sk_path,
     });
 
+    let query_templates = args
+        .query_templates
+        .map(|path| -> Result<TemplateList, BoxError> {
+            Ok(serde_json::from_reader(read_file(&path)?)?)
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let query_policy = QueryPolicy {
+        min_epsilon: args.min_epsilon,
+        max_breakdown_key: args.max_breakdown_key,
+        max_channels: args.max_channels,
+    };
+    let mut query_templates = QueryTemplates::new(query_templates, args.require_query_templates)?;
+    query_templates.check_policy(&query_policy)?;
+    if let Some(path) = args.query_templates {
+        query_templates = query_templates.stored_in(path);
+    }
+    let admin_token = args
+        .admin_token_file
+        .map(|path| fs::read_to_string(path).map(|token| AdminToken::new(token.trim().into())))
+        .transpose()?;
+
+    let open_files = OpenFiles::from_os_limit(args.max_open_files)?;
+    info!("helper keeps up to {} connections open", open_files.limit());
+    let client_open_files = open_files.clone();
+    let request_limits = RequestLimits {
+        max_uri_len: args.max_uri_len,
+        max_header_bytes: args.max_header_bytes,
+    };
+
+    if let Some(size) = args.yield_chunk_size {
+        cooperative::set_chunk_size(size);
+    }
+
     let query_runtime = new_query_runtime(&logging_handle);
-    let app_config = AppConfig::default()
+    let mut app_config = AppConfig::default()
         .with_key_registry(hpke_registry(mk_encryption.as_ref()).await?)
         .with_active_work(args.active_work)
+        .with_stage_concurrency(StageConcurrency {
+            conversion: args.conversion_active_work,
+            prf: args.prf_active_work,
+            aggregation: args.aggregation_active_work,
+        })
+        .with_redaction(Redaction::fields(
+            SensitiveField::ALL
+                .into_iter()
+                .filter(|field| !args.unredact.contains(field)),
+        ))
+        .with_query_policy(query_policy)
+        .with_query_templates(query_templates)
         .with_runtime(IpaRuntime::from_tokio_runtime(&query_runtime));
+    if let Some(dir) = args.quarantine_dir {
+        fs::create_dir_all(&dir)?;
+        app_config = app_config.with_quarantine(Quarantine::new(dir));
+    }
+    if let (Some(dir), Some(key_file)) = (args.prf_cache_dir, args.prf_cache_key_file) {
+        let secret = <[u8; 32]>::try_from(hex::decode(fs::read_to_string(key_file)?.trim())?)
+            .map_err(|_| "PRF cache key must be 32 bytes")?;
+        fs::create_dir_all(&dir)?;
+        app_config =
+            app_config.with_prf_cache(PrfCache::new(dir, &secret, args.prf_cache_epsilon_budget));
+    }
+
+    app_config = app_config
+        .with_input_retention(args.input_retention)
+        .with_max_completed_queries(args.max_completed_queries);
+    if let Some(secs) = args.peer_timeout_secs {
+        app_config = app_config.with_peer_timeout(Duration::from_secs(secs));
+    }
+    if let Some(path) = args.links {
+        let network: NetworkMeasurements = toml::from_str(&fs::read_to_string(path)?)?;
+        app_config = app_config.with_network_measurements(network);
+    }
 
     let (setup, handler, shard_handler) = AppSetup::new(app_config);
 
@@ -218,6 +421,9 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
         disable_https: args.disable_https,
         tls: server_tls,
         hpke_config: mk_encryption.clone(),
+        admin_token,
+        open_files: Some(open_files.clone()),
+        request_limits,
     };
 
     let shard_server_config = ServerConfig {
@@ -225,6 +431,9 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
         disable_https: args.disable_https,
         tls: shard_server_tls,
         hpke_config: mk_encryption,
+        admin_token: None,
+        open_files: Some(open_files),
+        request_limits,
     };
 
     let scheme = if args.disable_https {
@@ -244,6 +453,8 @@ async fn server(args: ServerArgs, logging_handle: LoggingHandle) -> Result<(), B
     )?;
     mpc_network = mpc_network.override_scheme(&scheme);
     shard_network = shard_network.override_scheme(&scheme);
+    mpc_network.client.open_files = Some(client_open_files.clone());
+    shard_network.client.open_files = Some(client_open_files);
 
     let http_runtime = new_http_runtime(&logging_handle);
     let clients = IpaHttpClient::from_conf(
@@ -378,6 +589,8 @@ pub async fn main() {
         Some(HelperCommand::TestSetup(args)) => test_setup(&args),
         Some(HelperCommand::Confgen(args)) => client_config_setup(args),
         Some(HelperCommand::ShardedConfgen(args)) => sharded_client_config_setup(args),
+        Some(HelperCommand::SelfTest) => self_test(),
+        Some(HelperCommand::PlanRoles(args)) => plan_roles(&args),
     };
 
     if let Err(e) = res {
@@ -21,7 +21,10 @@ use ipa_core::{
         CsvSerializer, IpaQueryResult, Verbosity,
     },
     config::{KeyRegistries, NetworkConfig},
-    ff::{boolean_array::BA32, FieldType},
+    ff::{
+        boolean_array::{BA16, BA32},
+        FieldType,
+    },
     helpers::{
         query::{
             DpMechanism, HybridQueryParams, IpaQueryConfig, QueryConfig, QuerySize, QueryType,
@@ -31,7 +34,10 @@ use ipa_core::{
     net::{Helper, IpaHttpClient},
     report::{EncryptedOprfReportStreams, DEFAULT_KEY_ID},
     test_fixture::{
-        ipa::{ipa_in_the_clear, CappingOrder, IpaSecurityModel, TestRawDataRecord},
+        ipa::{
+            can_pre_aggregate_sources, ipa_in_the_clear, pre_aggregate_sources, CappingOrder,
+            IpaSecurityModel, TestRawDataRecord,
+        },
         EventGenerator, EventGeneratorConfig, HybridEventGenerator, HybridGeneratorConfig,
     },
 };
@@ -67,10 +73,28 @@ struct Args {
     #[arg(long, default_value_t = 1)]
     shard_count: usize,
 
+    /// Drop the source events of the test input that can't get any credit under last-touch
+    /// attribution before running IPA tests. This makes queries smaller without changing
+    /// their results.
+    #[arg(long)]
+    pre_aggregate_sources: bool,
+
+    /// Helper (1, 2 or 3) that creates the query. Any helper can create queries, the one that
+    /// does assigns roles to the others and leads the query.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=3))]
+    leader: u8,
+
     #[command(subcommand)]
     action: ReportCollectorCommand,
 }
 
+impl Args {
+    /// Position of the leader in the helper clients.
+    fn leader(&self) -> usize {
+        usize::from(self.leader - 1)
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct CommandInput {
     #[arg(
@@ -463,7 +487,7 @@ async fn hybrid(
         query_type,
     };
 
-    let query_id = helper_clients[0][0]
+    let query_id = helper_clients[0][args.leader()]
         .create_query(query_config)
         .await
         .expect("Unable to create query!");
@@ -506,7 +530,8 @@ async fn ipa(
         &encrypted_inputs.enc_input_file3,
     ];
 
-    let encrypted_oprf_report_streams = EncryptedOprfReportStreams::from(files);
+    let encrypted_oprf_report_streams =
+        EncryptedOprfReportStreams::from_files(files, ipa_query_config.input_manifest);
 
     let query_config = QueryConfig {
         size: QuerySize::try_from(encrypted_oprf_report_streams.query_size).unwrap(),
@@ -514,23 +539,37 @@ async fn ipa(
         query_type,
     };
 
-    let query_id = helper_clients[0]
+    let query_id = helper_clients[args.leader()]
         .create_query(query_config)
         .await
         .expect("Unable to create query!");
 
     tracing::info!("Starting query for OPRF");
-    // the value for histogram values (BA32) must be kept in sync with the server-side
-    // implementation, otherwise a runtime reconstruct error will be generated.
+    // the histogram value type must be kept in sync with the server-side implementation,
+    // otherwise a runtime reconstruct error will be generated.
     // see ipa-core/src/query/executor.rs
-    let actual = run_query_and_validate::<BA32>(
-        encrypted_oprf_report_streams.streams,
-        encrypted_oprf_report_streams.query_size,
-        helper_clients,
-        query_id,
-        ipa_query_config,
-    )
-    .await;
+    let actual = match ipa_query_config.histogram_value_bits_for(query_config.size) {
+        16 => {
+            run_query_and_validate::<BA16>(
+                encrypted_oprf_report_streams.streams,
+                encrypted_oprf_report_streams.query_size,
+                helper_clients,
+                query_id,
+                ipa_query_config,
+            )
+            .await
+        }
+        _ => {
+            run_query_and_validate::<BA32>(
+                encrypted_oprf_report_streams.streams,
+                encrypted_oprf_report_streams.query_size,
+                helper_clients,
+                query_id,
+                ipa_query_config,
+            )
+            .await
+        }
+    };
 
     if let Some(ref path) = args.output_file {
         write_ipa_output_file(path, &actual)?;
@@ -550,13 +589,24 @@ async fn ipa_test(
     let input = InputSource::from(&args.input);
     let query_type = get_query_type(security_model, ipa_query_config);
 
-    let input_rows = input.iter::<TestRawDataRecord>().collect::<Vec<_>>();
+    let mut input_rows = input.iter::<TestRawDataRecord>().collect::<Vec<_>>();
+    if args.pre_aggregate_sources {
+        if !can_pre_aggregate_sources(&ipa_query_config) {
+            return Err("the query configuration does not allow pre-aggregating sources".into());
+        }
+        let before = input_rows.len();
+        input_rows = pre_aggregate_sources(&input_rows);
+        tracing::info!(
+            "pre-aggregation dropped {} of {before} input rows",
+            before - input_rows.len()
+        );
+    }
     let query_config = QueryConfig {
         size: QuerySize::try_from(input_rows.len()).unwrap(),
         field_type: FieldType::Fp32BitPrime,
         query_type,
     };
-    let query_id = helper_clients[0]
+    let query_id = helper_clients[args.leader()]
         .create_query(query_config)
         .await
         .expect("Unable to create query!");
@@ -583,17 +633,32 @@ async fn ipa_test(
     let Some(key_registries) = key_registries.init_from(network) else {
         panic!("could not load network file")
     };
-    // the value for histogram values (BA32) must be kept in sync with the server-side
-    // implementation, otherwise a runtime reconstruct error will be generated.
+    // the histogram value type must be kept in sync with the server-side implementation,
+    // otherwise a runtime reconstruct error will be generated.
     // see ipa-core/src/query/executor.rs
-    let actual = playbook_oprf_ipa::<BA32, _>(
-        input_rows,
-        helper_clients,
-        query_id,
-        ipa_query_config,
-        Some((DEFAULT_KEY_ID, key_registries.each_ref())),
-    )
-    .await;
+    let encryption = Some((DEFAULT_KEY_ID, key_registries.each_ref()));
+    let actual = match ipa_query_config.histogram_value_bits_for(query_config.size) {
+        16 => {
+            playbook_oprf_ipa::<BA16, _>(
+                input_rows,
+                helper_clients,
+                query_id,
+                ipa_query_config,
+                encryption,
+            )
+            .await
+        }
+        _ => {
+            playbook_oprf_ipa::<BA32, _>(
+                input_rows,
+                helper_clients,
+                query_id,
+                ipa_query_config,
+                encryption,
+            )
+            .await
+        }
+    };
 
     if let Some(ref path) = args.output_file {
         write_ipa_output_file(path, &actual)?;
@@ -603,6 +668,7 @@ async fn ipa_test(
 
     match ipa_query_config.with_dp {
         0 => {
+            let expected = expected.into_iter().map(i128::from).collect::<Vec<_>>();
             validate(&expected, &actual.breakdowns);
         }
         _ => {
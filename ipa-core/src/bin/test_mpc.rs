@@ -1,4 +1,4 @@
-use std::{error::Error, fmt::Debug, ops::Add, path::PathBuf};
+use std::{error::Error, fmt::Debug, ops::Add, path::PathBuf, time::Instant};
 
 use clap::{Parser, Subcommand};
 use generic_array::ArrayLength;
@@ -6,8 +6,8 @@ use hyper::http::uri::Scheme;
 use ipa_core::{
     cli::{
         playbook::{
-            make_clients, make_sharded_clients, secure_add, secure_mul, secure_shuffle, validate,
-            InputSource,
+            make_clients, make_sharded_clients, secure_add, secure_mul, secure_share_conversion,
+            secure_shuffle, validate, InputSource,
         },
         Verbosity,
     },
@@ -16,7 +16,8 @@ use ipa_core::{
     },
     helpers::query::{
         QueryConfig,
-        QueryType::{TestAddInPrimeField, TestMultiply, TestShardedShuffle},
+        QueryType::{TestAddInPrimeField, TestMultiply, TestShardedShuffle, TestShareConversion},
+        ShareConversionParams,
     },
     net::{Helper, IpaHttpClient},
     secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
@@ -95,6 +96,17 @@ enum TestAction {
     /// This is exactly what shuffle does and that's why it is picked
     /// for this purpose.
     ShardedShuffle,
+    /// Benchmarks the conversion of Boolean shares into Fp25519 shares on its own, which is what
+    /// OPRF IPA does to match keys before evaluating the PRF. Helpers generate the input, so
+    /// nothing is read from the input options. Prints the time and bytes the conversion took on
+    /// every helper.
+    ShareConversion {
+        #[clap(flatten)]
+        params: ShareConversionParams,
+        /// Number of values to convert.
+        #[arg(long, default_value = "100000")]
+        size: usize,
+    },
 }
 
 #[tokio::main]
@@ -129,6 +141,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .await;
             sharded_shuffle(&args, clients).await
         }
+        TestAction::ShareConversion { params, size } => {
+            let (clients, _) = make_clients(args.network.as_deref(), scheme, args.wait).await;
+            share_conversion(&args, &clients, params, size).await
+        }
     };
 
     Ok(())
@@ -204,3 +220,36 @@ async fn sharded_shuffle(args: &Args, helper_clients: Vec<[IpaHttpClient<Helper>
     assert_eq!(shuffled.len(), input_rows.len());
     assert_ne!(shuffled, input_rows);
 }
+
+async fn share_conversion(
+    args: &Args,
+    helper_clients: &[IpaHttpClient<Helper>; 3],
+    params: ShareConversionParams,
+    size: usize,
+) {
+    let query_config =
+        QueryConfig::new(TestShareConversion(params), args.input.field, size).unwrap();
+    let start = Instant::now();
+    let query_id = helper_clients[0].create_query(query_config).await.unwrap();
+    let (values, reports) = secure_share_conversion(helper_clients, query_id).await;
+    let elapsed = start.elapsed();
+
+    assert_eq!(values.len(), size);
+    println!(
+        "converted {size} values of {bits} bits in {elapsed:?}",
+        bits = params.bits
+    );
+    for (i, report) in reports.iter().enumerate() {
+        let Some(report) = report else {
+            println!("H{}: no tuning report", i + 1);
+            continue;
+        };
+        println!(
+            "H{}: {} ms, {} bytes sent by the slowest steps",
+            i + 1,
+            report.duration_ms,
+            report.stages.iter().map(|stage| stage.bytes).sum::<usize>()
+        );
+        println!("{}", serde_json::to_string_pretty(report).unwrap());
+    }
+}
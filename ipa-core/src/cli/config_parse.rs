@@ -387,8 +387,9 @@ mod tests {
             config_parse::{parse_sharded_network_toml, Error},
             sharded_server_from_toml_str,
         },
-        config::HttpClientConfigurator,
+        config::{HttpClientConfigurator, TlsVersion},
         helpers::HelperIdentity,
+        net::{CertificatePin, PeerSigningKey},
         sharding::ShardIndex,
     };
 
@@ -561,6 +562,58 @@ mod tests {
         assert_eq!("helper3.org:443", entire_network.peers[2].config.url);
     }
 
+    /// Keys shared with peers for request signing are read from the network file.
+    #[test]
+    fn parse_network_toml_signing_key() {
+        let network = parse_sharded_network_toml(&NON_SHARDED_SIGNING_KEY).unwrap();
+        assert_eq!(
+            Some(PeerSigningKey::new([0x2a; PeerSigningKey::LEN])),
+            network.peers[0].config.signing_key
+        );
+        assert_eq!(None, network.peers[1].config.signing_key);
+    }
+
+    /// Certificate pins of peers are read from the network file.
+    #[test]
+    fn parse_network_toml_certificate_pins() {
+        let network = parse_sharded_network_toml(&NON_SHARDED_CERTIFICATE_PINS).unwrap();
+        let pins = network.peers[0].config.certificate_pins.as_ref().unwrap();
+        assert_eq!("2a".repeat(CertificatePin::LEN), pins.current.to_string());
+        assert_eq!(
+            1_767_225_600,
+            pins.previous.as_ref().unwrap().accepted_until
+        );
+        assert!(network.peers[1].config.certificate_pins.is_none());
+    }
+
+    /// TLS settings of peers are read from the network file.
+    #[test]
+    fn parse_network_toml_peer_tls() {
+        let network = parse_sharded_network_toml(&NON_SHARDED_PEER_TLS).unwrap();
+        let tls = &network.peers[0].config.tls;
+        assert_eq!(2, tls.ca_bundle.as_ref().unwrap().len());
+        assert_eq!(
+            "helper1.internal",
+            tls.server_name.as_ref().unwrap().to_str()
+        );
+        assert_eq!(TlsVersion::Tls13, tls.min_version);
+
+        let tls = &network.peers[1].config.tls;
+        assert!(tls.ca_bundle.is_none());
+        assert!(tls.server_name.is_none());
+        assert_eq!(TlsVersion::Tls12, tls.min_version);
+    }
+
+    /// A CA bundle must contain at least one certificate.
+    #[test]
+    fn parse_network_toml_empty_ca_bundle() {
+        let network = format!("{CLIENT}{P1}\n[peers.tls]\nca_bundle = \"\"\n{REST}");
+        assert!(matches!(
+            parse_sharded_network_toml(&network),
+            Err(Error::ParseError(_))
+        ));
+    }
+
     // Following are some large &str const used for tests
 
     /// Valid: A non-sharded network toml, just how they used to be
@@ -570,6 +623,35 @@ mod tests {
     static SHARDED_COMPAT_ONE_URL: Lazy<String> =
         Lazy::new(|| format!("{CLIENT}{P1}\nshard_url = \"helper1.org:777\"\n{REST}"));
 
+    /// Valid: Same as [`NON_SHARDED_COMPAT`] but the first peer has a signing key.
+    static NON_SHARDED_SIGNING_KEY: Lazy<String> = Lazy::new(|| {
+        format!(
+            "{CLIENT}{P1}\nsigning_key = \"{}\"\n{REST}",
+            "2a".repeat(PeerSigningKey::LEN)
+        )
+    });
+
+    /// Valid: Same as [`NON_SHARDED_COMPAT`] but the first peer is pinned, and is rotating its
+    /// certificate.
+    static NON_SHARDED_CERTIFICATE_PINS: Lazy<String> = Lazy::new(|| {
+        let pin = "2a".repeat(CertificatePin::LEN);
+        format!(
+            "{CLIENT}{P1}\n[peers.certificate_pins]\ncurrent = \"{pin}\"\n\
+             [peers.certificate_pins.previous]\npin = \"{pin}\"\naccepted_until = 1767225600\n{REST}"
+        )
+    });
+
+    /// Valid: Same as [`NON_SHARDED_COMPAT`] but the first peer has its own CA bundle, made of
+    /// its certificate twice, and TLS settings.
+    static NON_SHARDED_PEER_TLS: Lazy<String> = Lazy::new(|| {
+        const END: &str = "-----END CERTIFICATE-----";
+        let cert = &P1[P1.find("-----BEGIN CERTIFICATE-----").unwrap()..P1.find(END).unwrap()];
+        format!(
+            "{CLIENT}{P1}\n[peers.tls]\nca_bundle = \"\"\"\n{cert}{END}\n{cert}{END}\n\"\"\"\n\
+             server_name = \"helper1.internal\"\nmin_version = \"1.3\"\n{REST}"
+        )
+    });
+
     /// Helper const used to create client configs
     const CLIENT: &str = r#"[client.http_config]
 ping_interval_secs = 90.0
@@ -207,7 +207,7 @@ mod tests {
         .await;
 
         assert_eq!(
-            results.reconstruct()[0..3]
+            results.map(|r| r.histogram).reconstruct()[0..3]
                 .iter()
                 .map(U128Conversions::as_u128)
                 .collect::<Vec<u128>>(),
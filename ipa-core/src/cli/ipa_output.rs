@@ -2,7 +2,10 @@ use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-use crate::helpers::query::{IpaQueryConfig, QuerySize};
+use crate::{
+    helpers::query::{IpaQueryConfig, QuerySize},
+    protocol::dp::NoiseReport,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QueryResult {
@@ -13,5 +16,13 @@ pub struct QueryResult {
         deserialize_with = "crate::serde::duration::from_secs"
     )]
     pub latency: Duration,
-    pub breakdowns: Vec<u32>,
+    /// Total trigger value per breakdown key. Totals are signed: noise and signed trigger
+    /// values can make them negative.
+    pub breakdowns: Vec<i128>,
+    /// Number of attributed conversions per breakdown key, if the query asked for them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counts: Option<Vec<i128>>,
+    /// Noise added to `breakdowns` and `counts`, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<NoiseReport>,
 }
@@ -5,15 +5,20 @@ use std::{
 };
 
 use clap::Args;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use rand_core::CryptoRng;
 use rcgen::{
     Certificate, CertificateParams, DistinguishedName, ExtendedKeyUsagePurpose, IsCa,
     KeyUsagePurpose, SanType, SerialNumber, PKCS_ECDSA_P256_SHA256,
 };
 use time::{Duration, OffsetDateTime};
+use zeroize::Zeroizing;
 
-use crate::{error::BoxError, hpke::KeyPair};
+use crate::{
+    error::BoxError,
+    hpke::KeyPair,
+    utils::rng::{CryptoRngProvider, SystemRngProvider},
+};
 
 #[derive(Debug, Clone, Args)]
 #[clap(
@@ -106,7 +111,7 @@ fn keygen_matchkey<R: Rng + CryptoRng>(args: &KeygenArgs, mut rng: &mut R) -> Re
         create_new(args.mk_public_key.as_ref().unwrap())?
             .write_all(hex::encode(keypair.pk_bytes()).as_bytes())?;
         create_new(args.mk_private_key.as_ref().unwrap())?
-            .write_all(hex::encode(keypair.sk_bytes()).as_bytes())?;
+            .write_all(Zeroizing::new(hex::encode(keypair.sk_bytes())).as_bytes())?;
     }
 
     Ok(())
@@ -120,7 +125,21 @@ fn keygen_matchkey<R: Rng + CryptoRng>(args: &KeygenArgs, mut rng: &mut R) -> Re
 /// # Panics
 /// If something that shouldn't happen goes wrong during key generation.
 pub fn keygen(args: &KeygenArgs) -> Result<(), BoxError> {
-    let mut rng = thread_rng();
+    keygen_with_rng_provider(args, &SystemRngProvider)
+}
+
+/// Same as [`keygen`], but takes randomness from `rng_provider`, e.g. a hardware RNG.
+///
+/// # Errors
+/// If a problem is encountered during key generation.
+///
+/// # Panics
+/// If something that shouldn't happen goes wrong during key generation.
+pub fn keygen_with_rng_provider(
+    args: &KeygenArgs,
+    rng_provider: &dyn CryptoRngProvider,
+) -> Result<(), BoxError> {
+    let mut rng = rng_provider.rng();
     keygen_tls(args, &mut rng)?;
     keygen_matchkey(args, &mut rng)?;
     Ok(())
@@ -10,8 +10,11 @@ mod ipa_output;
 mod keygen;
 mod metric_collector;
 mod paths;
+#[cfg(feature = "web-app")]
+mod plan_roles;
 #[cfg(all(feature = "test-fixture", feature = "web-app", feature = "cli"))]
 pub mod playbook;
+mod self_test;
 #[cfg(feature = "web-app")]
 mod test_setup;
 mod verbosity;
@@ -25,9 +28,12 @@ pub use config_parse::sharded_server_from_toml_str;
 pub use csv::Serializer as CsvSerializer;
 pub use ipa_output::QueryResult as IpaQueryResult;
 #[cfg(feature = "web-app")]
-pub use keygen::{keygen, KeygenArgs};
+pub use keygen::{keygen, keygen_with_rng_provider, KeygenArgs};
 pub use metric_collector::{install_collector, CollectorHandle};
 pub use paths::PathExt as CliPaths;
 #[cfg(feature = "web-app")]
+pub use plan_roles::{plan_roles, PlanRolesArgs};
+pub use self_test::self_test;
+#[cfg(feature = "web-app")]
 pub use test_setup::{test_setup, TestSetupArgs};
 pub use verbosity::{LoggingHandle, Verbosity};
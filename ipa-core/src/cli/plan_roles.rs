@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use clap::Args;
+
+use crate::{
+    error::BoxError,
+    ff::FieldType,
+    helpers::{
+        query::{IpaQueryConfig, QueryConfig, QueryType},
+        Role,
+    },
+    protocol::ipa_prf::AggregationMethod,
+    query::placement::{recommend, NetworkMeasurements, Plan},
+};
+
+#[derive(Debug, Args)]
+#[clap(
+    name = "plan-roles",
+    about = "Recommend which helper takes which role in IPA queries, from measured links between helpers",
+    next_help_heading = "Role Planning Options"
+)]
+pub struct PlanRolesArgs {
+    /// TOML file with the round trip time and bandwidth of every link between helpers. Helpers
+    /// started with the same file assign roles of the queries they create by it.
+    #[arg(long)]
+    links: PathBuf,
+
+    /// Number of records in the query
+    #[arg(long)]
+    query_size: u32,
+
+    /// Plan for malicious queries
+    #[arg(long)]
+    malicious: bool,
+
+    /// How the query adds up attributed values
+    #[arg(long, value_enum, default_value_t)]
+    aggregation_method: AggregationMethod,
+}
+
+/// Prints every assignment of roles to helpers for an IPA query, from the fastest to the slowest
+/// one.
+///
+/// ## Errors
+/// If the links file can't be read or is not valid, or the query size is zero.
+pub fn plan_roles(args: &PlanRolesArgs) -> Result<(), BoxError> {
+    let network: NetworkMeasurements = toml::from_str(&fs::read_to_string(&args.links)?)?;
+    let ipa_config = IpaQueryConfig {
+        aggregation_method: args.aggregation_method,
+        ..IpaQueryConfig::default()
+    };
+    let query_type = if args.malicious {
+        QueryType::MaliciousOprfIpa(ipa_config)
+    } else {
+        QueryType::SemiHonestOprfIpa(ipa_config)
+    };
+    let config = QueryConfig::new(query_type, FieldType::Fp32BitPrime, args.query_size)?;
+
+    write_plans(&mut io::stdout().lock(), &recommend(&config, &network))?;
+    Ok(())
+}
+
+fn write_plans<W: Write>(w: &mut W, plans: &[Plan]) -> io::Result<()> {
+    writeln!(
+        w,
+        "Create queries on helper {}, so that it takes {}.",
+        u8::from(plans[0].roles.identity(Role::H1)),
+        Role::H1,
+    )?;
+    for plan in plans {
+        writeln!(w, "{plan}")?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use crate::{
+        ff::FieldType,
+        helpers::query::{IpaQueryConfig, QueryConfig, QueryType},
+        query::placement::{recommend, NetworkMeasurements},
+    };
+
+    #[test]
+    fn links_file() {
+        let network: NetworkMeasurements = toml::from_str(
+            r"
+            [[link]]
+            helpers = [1, 2]
+            rtt_ms = 150.0
+            bandwidth_mbps = 100.0
+
+            [[link]]
+            helpers = [2, 3]
+            rtt_ms = 10.0
+            bandwidth_mbps = 1000.0
+
+            [[link]]
+            helpers = [3, 1]
+            rtt_ms = 10.0
+            bandwidth_mbps = 1000.0
+            ",
+        )
+        .unwrap();
+        let config = QueryConfig::new(
+            QueryType::SemiHonestOprfIpa(IpaQueryConfig::default()),
+            FieldType::Fp32BitPrime,
+            100_000,
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        super::write_plans(&mut output, &recommend(&config, &network)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Create queries on helper 1, so that it takes H1.\n"));
+        assert_eq!(7, output.lines().count());
+    }
+
+    #[test]
+    fn rejects_missing_link() {
+        assert!(toml::from_str::<NetworkMeasurements>(
+            r"
+            [[link]]
+            helpers = [1, 2]
+            rtt_ms = 10.0
+            bandwidth_mbps = 1000.0
+            "
+        )
+        .is_err());
+    }
+}
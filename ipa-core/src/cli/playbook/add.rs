@@ -50,6 +50,7 @@ where
                 client.query_input(QueryInput {
                     query_id,
                     input_stream,
+                    contribution: None,
                 })
             }),
     )
@@ -18,8 +18,8 @@ use crate::{
     net::{Helper, IpaHttpClient},
     protocol::QueryId,
     query::QueryStatus,
-    secret_sharing::{replicated::semi_honest::AdditiveShare, SharedValue},
-    test_fixture::Reconstruct,
+    results::{reconstruct, ResultValue},
+    secret_sharing::replicated::semi_honest::AdditiveShare,
 };
 
 /// # Panics
@@ -34,7 +34,7 @@ pub async fn run_hybrid_query_and_validate<HV>(
     set_fixed_polling_ms: Option<u64>,
 ) -> HybridQueryResult
 where
-    HV: SharedValue + U128Conversions,
+    HV: ResultValue + U128Conversions,
     AdditiveShare<HV>: Serializable,
 {
     let mpc_time = Instant::now();
@@ -47,6 +47,7 @@ where
                     client.query_input(QueryInput {
                         query_id,
                         input_stream: input,
+                        contribution: None,
                     })
                 },
             ))
@@ -95,13 +96,7 @@ where
     .try_into()
     .unwrap();
 
-    let results: Vec<HV> = results
-        .map(|bytes| {
-            AdditiveShare::<HV>::from_byte_slice(&bytes)
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap()
-        })
-        .reconstruct();
+    let results: Vec<HV> = reconstruct(results.each_ref().map(AsRef::as_ref)).unwrap();
 
     let lat = mpc_time.elapsed();
 
@@ -20,14 +20,15 @@ use crate::{
     ff::{Serializable, U128Conversions},
     helpers::{
         query::{IpaQueryConfig, QueryInput, QuerySize},
-        BodyStream,
+        BodyStream, InputManifest,
     },
     hpke::PublicKeyRegistry,
     net::{Helper, IpaHttpClient},
     protocol::{ipa_prf::OPRFIPAInputRow, QueryId},
     query::QueryStatus,
     report::{KeyIdentifier, OprfReport},
-    secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares, SharedValue},
+    results::{IpaResults, PostProcessing, ResultValue},
+    secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
     test_fixture::{ipa::TestRawDataRecord, Reconstruct},
 };
 
@@ -43,7 +44,7 @@ pub async fn playbook_oprf_ipa<HV, KR>(
     encryption: Option<(KeyIdentifier, [&KR; 3])>,
 ) -> IpaQueryResult
 where
-    HV: SharedValue + U128Conversions,
+    HV: ResultValue + U128Conversions,
     AdditiveShare<HV>: Serializable,
     KR: PublicKeyRegistry,
 {
@@ -89,6 +90,12 @@ where
         )
     }
 
+    if query_config.input_manifest {
+        for buffer in &mut buffers {
+            InputManifest::append_to(buffer, query_size);
+        }
+    }
+
     let inputs = buffers.map(BodyStream::from);
     tracing::info!("Starting query for OPRF");
 
@@ -106,7 +113,7 @@ pub async fn run_query_and_validate<HV>(
     query_config: IpaQueryConfig,
 ) -> IpaQueryResult
 where
-    HV: SharedValue + U128Conversions,
+    HV: ResultValue + U128Conversions,
     AdditiveShare<HV>: Serializable,
 {
     let mpc_time = Instant::now();
@@ -118,6 +125,7 @@ where
                 client.query_input(QueryInput {
                     query_id,
                     input_stream,
+                    contribution: None,
                 })
             }),
     )
@@ -141,6 +149,19 @@ where
         // the status API so we can check whether the query is making progress.
     }
 
+    // Helpers forget the noise once results are collected. Each helper samples its own share
+    // of the noise, but they all describe it with the same mechanism and parameters.
+    let [noise, rest @ ..]: [_; 3] =
+        try_join_all(clients.iter().map(|client| client.query_noise(query_id)))
+            .await
+            .unwrap()
+            .try_into()
+            .unwrap();
+    assert!(
+        rest.iter().all(|other| *other == noise),
+        "helpers disagree on the noise added to query {query_id}"
+    );
+
     // wait until helpers have processed the query and get the results from them
     let results: [_; 3] = try_join_all(clients.iter().map(|client| client.query_results(query_id)))
         .await
@@ -148,39 +169,32 @@ where
         .try_into()
         .unwrap();
 
-    let results: Vec<HV> = results
-        .map(|bytes| {
-            AdditiveShare::<HV>::from_byte_slice(&bytes)
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap()
-        })
-        .reconstruct();
+    let results = IpaResults::reconstruct::<HV>(
+        &query_config,
+        results.each_ref().map(AsRef::as_ref),
+        PostProcessing {
+            rescale: true,
+            clamp: None,
+        },
+    )
+    .unwrap();
+    if results.partial {
+        tracing::warn!(
+            "Noise could not be added to some of the histograms of the query results. They \
+             are all zeros."
+        );
+    }
 
     let lat = mpc_time.elapsed();
 
     tracing::info!("Running IPA for {query_size:?} records took {t:?}", t = lat);
-    let mut breakdowns = vec![0; usize::try_from(query_config.max_breakdown_key).unwrap()];
-    for (breakdown_key, trigger_value) in results.into_iter().enumerate() {
-        // TODO: make the data type used consistent with `ipa_in_the_clear`
-        // I think using u32 is wrong, we should move to u128
-        if query_config.with_dp == 0 {
-            // otherwise if DP is added trigger_values will not be zero due to noise
-            assert!(
-                breakdown_key < query_config.max_breakdown_key.try_into().unwrap()
-                    || trigger_value == HV::ZERO,
-                "trigger values were attributed to buckets more than max breakdown key"
-            );
-        }
-
-        if breakdown_key < query_config.max_breakdown_key.try_into().unwrap() {
-            breakdowns[breakdown_key] += u32::try_from(trigger_value.as_u128()).unwrap();
-        }
-    }
 
     IpaQueryResult {
         input_size: QuerySize::try_from(query_size).unwrap(),
         config: query_config,
         latency: lat,
-        breakdowns,
+        breakdowns: results.breakdowns,
+        counts: results.counts,
+        noise,
     }
 }
@@ -5,6 +5,7 @@ mod input;
 mod ipa;
 mod multiply;
 mod sharded_shuffle;
+mod share_conversion;
 #[allow(dead_code)]
 mod streaming;
 
@@ -17,6 +18,7 @@ use hyper::http::uri::Scheme;
 pub use input::InputSource;
 pub use multiply::secure_mul;
 pub use sharded_shuffle::secure_shuffle;
+pub use share_conversion::secure_share_conversion;
 use tokio::time::sleep;
 
 pub use self::{
@@ -93,14 +95,15 @@ where
 ///
 /// ## Panics
 /// If results don't match.
+#[allow(clippy::cast_precision_loss)]
 pub fn validate_dp(
     expected: Vec<u32>,
-    actual: Vec<u32>,
+    actual: Vec<i128>,
     epsilon: f64,
     per_user_credit_cap: u32,
     dp_mechanism: DpMechanism,
 ) {
-    let mut expected = expected.into_iter().fuse();
+    let mut expected = expected.into_iter().map(i128::from).fuse();
     let mut actual = actual.into_iter().fuse();
     let mut mismatch = Vec::new();
 
@@ -122,8 +125,8 @@ pub fn validate_dp(
             all_equal = false;
         }
 
-        let next_expected_f64: f64 = next_expected.unwrap().into();
-        let next_actual_f64: f64 = next_actual.unwrap().into();
+        let next_expected_f64 = next_expected.unwrap() as f64;
+        let next_actual_f64 = next_actual.unwrap() as f64;
 
         let noise_params = NoiseParams {
             epsilon,
@@ -148,20 +151,10 @@ pub fn validate_dp(
                 )
                 .unwrap();
 
-                // This needs to be kept in sync with histogram values being BA32.
-                // Here we are shifting the representation of negative noise values
-                // from being large values close to 2^32 to being negative when we look
-                // at them as floats.
-                let next_actual_f64_shifted = if next_actual_f64 > 2.0_f64.powf(31.0) {
-                    next_actual_f64 - 2.0_f64.powf(32.0)
-                } else {
-                    next_actual_f64
-                };
-
                 let (_, std) = truncated_discrete_laplace.mean_and_std();
                 let tolerance_factor = 20.0; // set so this fails randomly with small probability
                                              // println!("mean = {mean}, std = {std}, tolerance_factor * std = {}",tolerance_factor * std);
-                (next_actual_f64_shifted - next_expected_f64).abs() < tolerance_factor * 3.0 * std
+                (next_actual_f64 - next_expected_f64).abs() < tolerance_factor * 3.0 * std
             }
             DpMechanism::NoDp => next_expected == next_actual,
         };
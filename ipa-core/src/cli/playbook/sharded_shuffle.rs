@@ -48,6 +48,7 @@ where
                         mpc_client.query_input(QueryInput {
                             query_id,
                             input_stream: BodyStream::from_serializable_iter(input),
+                            contribution: None,
                         })
                     },
                 ))
@@ -0,0 +1,73 @@
+use std::{cmp::min, time::Duration};
+
+use futures_util::future::try_join_all;
+
+use crate::{
+    ff::ec_prime_field::Fp25519,
+    helpers::{query::QueryInput, BodyStream},
+    net::{Helper, IpaHttpClient},
+    protocol::QueryId,
+    query::QueryStatus,
+    secret_sharing::replicated::semi_honest::AdditiveShare,
+    telemetry::tuning::TuningReport,
+    test_fixture::Reconstruct,
+};
+
+/// Runs the share conversion benchmark. Helpers generate the values to convert themselves, so
+/// nothing is uploaded. Returns the converted values and the tuning report of every helper,
+/// which shows how long the conversion took and how much data it sent.
+///
+/// ## Panics
+/// If any of the helpers fails to run the query.
+#[allow(clippy::disallowed_methods)] // allow try_join_all
+pub async fn secure_share_conversion(
+    clients: &[IpaHttpClient<Helper>; 3],
+    query_id: QueryId,
+) -> (Vec<Fp25519>, [Option<TuningReport>; 3]) {
+    try_join_all(clients.iter().map(|client| {
+        client.query_input(QueryInput {
+            query_id,
+            input_stream: BodyStream::empty(),
+            contribution: None,
+        })
+    }))
+    .await
+    .unwrap();
+
+    // Tuning reports are dropped once the results are collected, so wait for the query to
+    // complete and get them first.
+    let mut delay = Duration::from_millis(125);
+    while !try_join_all(clients.iter().map(|client| client.query_status(query_id)))
+        .await
+        .unwrap()
+        .into_iter()
+        .all(|status| status == QueryStatus::Completed)
+    {
+        tokio::time::sleep(delay).await;
+        delay = min(Duration::from_secs(5), delay * 2);
+    }
+    let reports: [_; 3] = try_join_all(
+        clients
+            .iter()
+            .map(|client| client.query_tuning_report(query_id)),
+    )
+    .await
+    .unwrap()
+    .try_into()
+    .unwrap();
+
+    let results: [_; 3] = try_join_all(clients.iter().map(|client| client.query_results(query_id)))
+        .await
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let values = results
+        .map(|bytes| {
+            AdditiveShare::<Fp25519>::from_byte_slice(&bytes)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        })
+        .reconstruct();
+
+    (values, reports)
+}
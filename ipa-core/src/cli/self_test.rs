@@ -0,0 +1,321 @@
+//! Local correctness checks of the primitives helpers rely on. They are meant to validate a
+//! helper build on new hardware or a new OS before it joins a deployment, so they do not need
+//! any configuration, keys, or peers.
+
+use std::{
+    fmt::{Debug, Display, Formatter},
+    panic::catch_unwind,
+};
+
+use generic_array::GenericArray;
+use rand::{thread_rng, Rng};
+
+use crate::{
+    error::BoxError,
+    ff::{
+        boolean_array::{BA20, BA3, BA64, BA8},
+        Field, Fp32BitPrime, Fp61BitPrime, Gf8Bit, PrimeField, Serializable, U128Conversions,
+    },
+    helpers::Direction,
+    hpke::{Deserializable, IpaPrivateKey, IpaPublicKey, KeyPair, KeyRegistry},
+    protocol::{
+        prss::{Endpoint as PrssEndpoint, SharedRandomness},
+        Gate,
+    },
+    report::{EncryptedOprfReport, EventType, OprfReport},
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        SharedValue,
+    },
+};
+
+type Check = fn() -> Result<(), String>;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("field arithmetic", field_arithmetic),
+    ("serialization", serialization),
+    ("hpke known answer", hpke_known_answer),
+    ("hpke round trip", hpke_round_trip),
+    ("prss determinism", prss_determinism),
+];
+
+/// Outcome of every self-test check.
+struct SelfTestReport {
+    results: Vec<(&'static str, Result<(), String>)>,
+}
+
+impl SelfTestReport {
+    fn passed(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+
+    fn failures(&self) -> usize {
+        self.results.iter().filter(|(_, r)| r.is_err()).count()
+    }
+}
+
+impl Display for SelfTestReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "PASS {name}")?,
+                Err(e) => writeln!(f, "FAIL {name}: {e}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs all checks. A check that panics fails, the remaining checks still run.
+fn run_checks() -> SelfTestReport {
+    SelfTestReport {
+        results: CHECKS
+            .iter()
+            .map(|&(name, check)| {
+                let result =
+                    catch_unwind(check).unwrap_or_else(|_| Err("check panicked".to_string()));
+                (name, result)
+            })
+            .collect(),
+    }
+}
+
+/// Runs all checks and prints the report.
+///
+/// ## Errors
+/// If any of the checks fails.
+pub fn self_test() -> Result<(), BoxError> {
+    let report = run_checks();
+    print!("{report}");
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} of {} self-test checks failed",
+            report.failures(),
+            report.results.len()
+        )
+        .into())
+    }
+}
+
+fn expect_eq<T: PartialEq + Debug>(what: &str, expected: T, actual: T) -> Result<(), String> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!("{what}: expected {expected:?}, got {actual:?}"))
+    }
+}
+
+fn field_arithmetic() -> Result<(), String> {
+    let p32: u128 = Fp32BitPrime::PRIME.into();
+    let minus_one = Fp32BitPrime::truncate_from(p32 - 1);
+    expect_eq(
+        "Fp32BitPrime (p-1) + 2",
+        Fp32BitPrime::ONE,
+        minus_one + Fp32BitPrime::truncate_from(2_u128),
+    )?;
+    expect_eq(
+        "Fp32BitPrime (p-1) * (p-1)",
+        Fp32BitPrime::ONE,
+        minus_one * minus_one,
+    )?;
+    expect_eq(
+        "Fp32BitPrime 2^31 * 2",
+        Fp32BitPrime::truncate_from(5_u128),
+        Fp32BitPrime::truncate_from(1_u128 << 31) * Fp32BitPrime::truncate_from(2_u128),
+    )?;
+    expect_eq(
+        "Fp32BitPrime 2^-1",
+        Fp32BitPrime::truncate_from(2_147_483_646_u128),
+        Fp32BitPrime::truncate_from(2_u128).invert(),
+    )?;
+    expect_eq(
+        "Fp61BitPrime 2^60 * 2",
+        Fp61BitPrime::ONE,
+        Fp61BitPrime::truncate_from(1_u128 << 60) * Fp61BitPrime::truncate_from(2_u128),
+    )?;
+    expect_eq(
+        "Fp61BitPrime 2^-1",
+        Fp61BitPrime::truncate_from(1_u128 << 60),
+        Fp61BitPrime::truncate_from(2_u128).invert(),
+    )?;
+    // Examples from FIPS 197, Gf8Bit uses the AES polynomial.
+    expect_eq(
+        "Gf8Bit 0x57 * 0x83",
+        Gf8Bit::truncate_from(0xc1_u128),
+        Gf8Bit::truncate_from(0x57_u128) * Gf8Bit::truncate_from(0x83_u128),
+    )?;
+    expect_eq(
+        "Gf8Bit 0x53 * 0xca",
+        Gf8Bit::ONE,
+        Gf8Bit::truncate_from(0x53_u128) * Gf8Bit::truncate_from(0xca_u128),
+    )
+}
+
+fn serialization() -> Result<(), String> {
+    let mut buf = GenericArray::default();
+    Fp32BitPrime::truncate_from(0x0102_0304_u128).serialize(&mut buf);
+    expect_eq("Fp32BitPrime bytes", &[4_u8, 3, 2, 1][..], buf.as_slice())?;
+    if Fp32BitPrime::deserialize(&GenericArray::from([0xff; 4])).is_ok() {
+        return Err("Fp32BitPrime accepted a value greater than the prime".to_string());
+    }
+
+    let value = BA64::truncate_from(0x0102_0304_0506_0708_u128);
+    let mut buf = GenericArray::default();
+    value.serialize(&mut buf);
+    expect_eq(
+        "BA64 bytes",
+        &[8_u8, 7, 6, 5, 4, 3, 2, 1][..],
+        buf.as_slice(),
+    )?;
+    expect_eq(
+        "BA64 round trip",
+        Ok(value),
+        BA64::deserialize(&buf).map_err(|e| e.to_string()),
+    )
+}
+
+/// Reports encrypted by an earlier build of this library, with the public and private keys of
+/// the helpers they were encrypted for. They are the ones the report decryption tests use. They
+/// catch a build that can't decrypt what other builds encrypted, but not a bug shared by all
+/// builds, as they were never checked against another HPKE implementation.
+const KNOWN_REPORTS: [(&str, &str, &str); 3] = [
+    (
+        "92a6fb666c37c008defd74abf3204ebea685742eab8347b08e2f7c759893947a",
+        "53d58e022981f2edbf55fec1b45dbabd08a3442cb7b7c598839de5d7a5888bff",
+        "12854879d86ef277cd70806a7f6bad269877adc95ee107380381caf15b841a7e995e\
+         414c63a9d82f834796cdd6c40529189fca82720714d24200d8a916a1e090b123f27e\
+         af24f047f3930a77e5bcd33eeb823b73b0e9546c59d3d6e69383c74ae72b79645698\
+         fe1422f83886bd3cbca9fbb63f7019e2139191dd000000007777772e6d6574612e63\
+         6f6d",
+    ),
+    (
+        "cfdbaaff16b30aa8a4ab07eaad2cdd80458208a1317aefbb807e46dce596617e",
+        "3a0a993a3cfc7e8d381addac586f37de50c2a14b1a6356d71e94ca2afaeb2569",
+        "1d85741b3edf3f49e8ed5824b8ea0ed156301fb6d450fc30ad76785fc3b281775937\
+         d0275efc237d3e3ac92e22cf60ebd8dc09a41abaa20c0a7ee9e5e1c736708c01dd65\
+         f592e5683f8ca0e23f8bfcd3a7736335cc5bec95beceb6474abb816b01f9adf7cc12\
+         c344c1538bb84c98b089b24733790032e70c7406000000007777772e6d6574612e63\
+         6f6d",
+    ),
+    (
+        "b900be35da06106a83ed73c33f733e03e4ea5888b7ea4c912ab270b0b0f8381e",
+        "1fb5c5274bf85fbe6c7935684ef05499f6cfb89ac21640c28330135cc0e8a0f7",
+        "545f9df229a16c70497dd1f93ac75bef8ad33e836bb20f2ff37297bd814a091389d8\
+         5db9007e7b95231a3e5a0055ae59dc56d431849c0aaf5e01e66c8e6b7888bf299f66\
+         907861798097aba96aae193d59b7fcafd5655e745f4b4ae51631c6342e36ee3b6f16\
+         82385b46295b7ce0128af02f6828cba562bf0c12000000007777772e6d6574612e63\
+         6f6d",
+    ),
+];
+
+fn hpke_known_answer() -> Result<(), String> {
+    let mut match_key = BA64::ZERO;
+    let mut breakdown_key = BA8::ZERO;
+    let mut timestamp = BA20::ZERO;
+    for (pk, sk, report) in KNOWN_REPORTS {
+        let key = |hex_key: &str| hex::decode(hex_key).map_err(|e| e.to_string());
+        let registry = KeyRegistry::<KeyPair>::from_keys([KeyPair::from((
+            IpaPrivateKey::from_bytes(&key(sk)?).map_err(|e| e.to_string())?,
+            IpaPublicKey::from_bytes(&key(pk)?).map_err(|e| e.to_string())?,
+        ))]);
+        let bytes = hex::decode(report).map_err(|e| e.to_string())?;
+        let report: OprfReport<BA8, BA3, BA20> =
+            EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(bytes.as_slice())
+                .and_then(|report| report.decrypt(&registry))
+                .map_err(|e| e.to_string())?;
+
+        expect_eq("event type", EventType::Source, report.event_type)?;
+        expect_eq("epoch", 0, report.epoch)?;
+        expect_eq("site domain", "www.meta.com", report.site_domain.as_str())?;
+        match_key += report.match_key.left();
+        breakdown_key += report.breakdown_key.left();
+        timestamp += report.timestamp.left();
+    }
+
+    expect_eq("match key", 1, match_key.as_u128())?;
+    expect_eq("breakdown key", 45, breakdown_key.as_u128())?;
+    expect_eq("timestamp", 456, timestamp.as_u128())
+}
+
+fn hpke_round_trip() -> Result<(), String> {
+    let mut rng = thread_rng();
+    let registry = KeyRegistry::<KeyPair>::from_keys([KeyPair::gen(&mut rng)]);
+    let report = OprfReport::<BA8, BA3, BA20> {
+        match_key: Replicated::new(rng.gen(), rng.gen()),
+        event_type: EventType::Trigger,
+        breakdown_key: Replicated::new(rng.gen(), rng.gen()),
+        trigger_value: Replicated::new(rng.gen(), rng.gen()),
+        timestamp: Replicated::new(rng.gen(), rng.gen()),
+        epoch: rng.gen(),
+        site_domain: "www.example.com".to_string(),
+    };
+    let mut bytes = report
+        .encrypt(0, &registry, &mut rng)
+        .map_err(|e| e.to_string())?;
+    let decrypt = |bytes: &[u8]| {
+        EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(bytes)
+            .and_then(|report| report.decrypt(&registry))
+    };
+
+    expect_eq(
+        "decrypted report",
+        Ok(&report),
+        decrypt(&bytes).as_ref().map_err(ToString::to_string),
+    )?;
+    bytes[0] ^= 1;
+    if decrypt(&bytes).is_ok() {
+        return Err("corrupted report decrypted successfully".to_string());
+    }
+
+    Ok(())
+}
+
+fn prss_determinism() -> Result<(), String> {
+    let mut rng = thread_rng();
+    let (a, b) = (
+        PrssEndpoint::prepare(&mut rng),
+        PrssEndpoint::prepare(&mut rng),
+    );
+    let (a_left, a_right) = a.public_keys();
+    let (b_left, b_right) = b.public_keys();
+    // With two endpoints, each one is both to the left and to the right of the other.
+    let a = a.setup(&b_right, &b_left);
+    let b = b.setup(&a_right, &a_left);
+
+    let gate = Gate::default();
+    let (a, b) = (a.indexed(&gate), b.indexed(&gate));
+    for i in 0..16_u32 {
+        let a_left: BA64 = a.generate_one_side(i, Direction::Left);
+        let a_right: BA64 = a.generate_one_side(i, Direction::Right);
+        expect_eq(
+            "left of one endpoint, right of the other",
+            a_left,
+            b.generate_one_side(i, Direction::Right),
+        )?;
+        expect_eq(
+            "right of one endpoint, left of the other",
+            a_right,
+            b.generate_one_side(i, Direction::Left),
+        )?;
+        if a_left == a_right {
+            return Err(format!(
+                "left and right randomness are the same at index {i}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::run_checks;
+
+    #[test]
+    fn checks_pass() {
+        let report = run_checks();
+        assert!(report.passed(), "{report}");
+    }
+}
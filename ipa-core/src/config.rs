@@ -10,18 +10,22 @@ use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use hyper::{http::uri::Scheme, Uri};
 use hyper_util::client::legacy::Builder;
 use rustls_pemfile::Item;
-use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
 use serde::{Deserialize, Deserializer, Serialize};
+use subtle::ConstantTimeEq;
 use tokio::fs;
 
 use crate::{
     error::BoxError,
-    helpers::HelperIdentity,
+    helpers::{HelperIdentity, StepTransfer},
     hpke::{
         Deserializable as _, IpaPrivateKey, IpaPublicKey, KeyRegistry, PrivateKeyOnly,
         PublicKeyOnly, Serializable as _,
     },
-    net::{ConnectionFlavor, Helper, Shard},
+    net::{
+        CertificatePins, ConnectionFlavor, Helper, OpenFiles, PeerPinStatus, PeerSigningKey,
+        RequestLimits, Shard,
+    },
     sharding::ShardIndex,
 };
 
@@ -103,6 +107,41 @@ impl<F: ConnectionFlavor> NetworkConfig<F> {
         );
         None
     }
+
+    /// Returns the key shared with peer `id`, if request signing is configured for that peer.
+    #[must_use]
+    pub fn signing_key(&self, id: F::Identity) -> Option<&PeerSigningKey> {
+        zip(self.identities.iter(), self.peers.iter())
+            .find(|(peer_id, _)| **peer_id == id)
+            .and_then(|(_, p)| p.signing_key.as_ref())
+    }
+
+    /// Returns the status of the certificate pins of every peer at time `now`, given in seconds
+    /// since the Unix epoch.
+    #[must_use]
+    pub fn pin_status(&self, now: u64) -> Vec<PeerPinStatus<F::Identity>> {
+        zip(self.identities.iter(), self.peers.iter())
+            .map(|(id, p)| PeerPinStatus {
+                identity: *id,
+                pins: p.certificate_pins.as_ref().map(|pins| pins.status(now)),
+            })
+            .collect()
+    }
+
+    /// Returns the peers that receive step data in [`StepTransfer::Pull`] mode.
+    #[must_use]
+    pub fn pull_receivers(&self) -> Vec<F::Identity> {
+        zip(self.identities.iter(), self.peers.iter())
+            .filter(|(_, p)| p.step_transfer == StepTransfer::Pull)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Returns `true` if requests exchanged with any of the peers are signed.
+    #[must_use]
+    pub fn has_signing_keys(&self) -> bool {
+        self.peers.iter().any(|p| p.signing_key.is_some())
+    }
 }
 
 impl NetworkConfig<Shard> {
@@ -166,14 +205,36 @@ pub struct PeerConfig {
     /// In `network.toml`, the certificate must be in PEM format. It is converted to DER
     /// when the config is loaded.
     ///
-    /// Verifying a peer's TLS certificate against the system truststore or a custom root of
-    /// trust is not currently supported.
+    /// If neither it nor [`PeerTlsConfig::ca_bundle`] is specified, the certificate presented by
+    /// the peer when connecting to it is verified against the system truststore.
     #[serde(default, deserialize_with = "certificate_from_pem")]
     pub certificate: Option<OwnedCertificate>,
 
+    /// Pins of the peer's TLS certificate.
+    ///
+    /// If specified, the certificate presented by the peer must also match one of the pins.
+    #[serde(default)]
+    pub certificate_pins: Option<CertificatePins>,
+
     /// Match key encryption configuration.
     #[serde(default, rename = "hpke")]
     pub hpke_config: Option<HpkeClientConfig>,
+
+    /// Key shared with this peer to sign and verify requests exchanged with it.
+    ///
+    /// This is an alternative to TLS client authentication for deployments where TLS is
+    /// terminated before the request reaches the helper. It is only used when HTTPS is disabled.
+    #[serde(default)]
+    pub signing_key: Option<PeerSigningKey>,
+
+    /// How this peer wants to receive step data. Helpers on slow links should set this to
+    /// `pull`, so the other helpers only send data when this one asks for it.
+    #[serde(default)]
+    pub step_transfer: StepTransfer,
+
+    /// TLS settings for connections to this peer. Only used when HTTPS is enabled.
+    #[serde(default)]
+    pub tls: PeerTlsConfig,
 }
 
 impl PeerConfig {
@@ -181,7 +242,52 @@ impl PeerConfig {
         Self {
             url,
             certificate,
+            certificate_pins: None,
             hpke_config: None,
+            signing_key: None,
+            step_transfer: StepTransfer::Push,
+            tls: PeerTlsConfig::default(),
+        }
+    }
+}
+
+/// TLS settings for connections to a single peer, for deployments in which every organization
+/// runs its own PKI.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PeerTlsConfig {
+    /// CA certificates that the peer's certificate is verified against, in PEM format.
+    ///
+    /// If specified, they are trusted along with [`PeerConfig::certificate`], and the system
+    /// truststore is not used for this peer.
+    #[serde(default, deserialize_with = "certificates_from_pem")]
+    pub ca_bundle: Option<Vec<OwnedCertificate>>,
+
+    /// Name sent in SNI and verified against the peer's certificate, if it is not the host of
+    /// the peer URL. This is needed when peers are reached through an IP address or a proxy.
+    #[serde(default, deserialize_with = "server_name_from_str")]
+    pub server_name: Option<ServerName<'static>>,
+
+    /// Lowest TLS version accepted for connections to this peer.
+    #[serde(default)]
+    pub min_version: TlsVersion,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    /// TLS versions that can be negotiated if this is the lowest one accepted.
+    #[must_use]
+    pub fn protocol_versions(self) -> &'static [&'static rustls::SupportedProtocolVersion] {
+        match self {
+            Self::Tls12 => rustls::DEFAULT_VERSIONS,
+            Self::Tls13 => &[&rustls::version::TLS13],
         }
     }
 }
@@ -226,6 +332,36 @@ where
     }
 }
 
+/// Reads one or more Certificates in PEM format using Serde Serialization
+fn certificates_from_pem<'de, D>(deserializer: D) -> Result<Option<Vec<OwnedCertificate>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(s) = <Option<String> as Deserialize>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let certs = rustls_pemfile::certs(&mut s.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(serde::de::Error::custom)?;
+    if certs.is_empty() {
+        return Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(s.as_ref()),
+            &"one or more certificates",
+        ));
+    }
+    Ok(Some(certs))
+}
+
+fn server_name_from_str<'de, D>(deserializer: D) -> Result<Option<ServerName<'static>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <Option<String> as Deserialize>::deserialize(deserializer)?
+        .map(ServerName::try_from)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
 fn pk_from_str<'de, D>(deserializer: D) -> Result<IpaPublicKey, D::Error>
 where
     D: Deserializer<'de>,
@@ -307,6 +443,39 @@ pub struct ServerConfig {
 
     /// Configuration needed for decrypting match keys
     pub hpke_config: Option<HpkeServerConfig>,
+
+    /// Token that helper administrators present to manage query templates and to inspect the
+    /// certificate pins of peers. These APIs are disabled if it is not set.
+    pub admin_token: Option<AdminToken>,
+
+    /// Limits the number of connections the server keeps open. Unlimited if not set.
+    pub open_files: Option<OpenFiles>,
+
+    /// Limits the size of request targets and headers the server accepts.
+    pub request_limits: RequestLimits,
+}
+
+/// Shared secret that authenticates helper administrators.
+#[derive(Clone)]
+pub struct AdminToken(String);
+
+impl AdminToken {
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        Self(token)
+    }
+
+    /// Checks `presented` against this token in constant time.
+    #[must_use]
+    pub fn matches(&self, presented: &str) -> bool {
+        self.0.as_bytes().ct_eq(presented.as_bytes()).into()
+    }
+}
+
+impl Debug for AdminToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AdminToken(..)")
+    }
 }
 
 pub trait HyperClientConfigurator {
@@ -316,6 +485,10 @@ pub trait HyperClientConfigurator {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
     pub http_config: HttpClientConfigurator,
+    /// Limits the number of connections clients keep open. Unlimited if not set. Helpers share
+    /// it with their servers, so that sockets in both directions count against the same limit.
+    #[serde(skip)]
+    pub open_files: Option<OpenFiles>,
 }
 
 impl Default for ClientConfig {
@@ -334,6 +507,7 @@ impl ClientConfig {
     pub fn configure_http2(conf: Http2Configurator) -> Self {
         Self {
             http_config: HttpClientConfigurator::Http2(conf),
+            open_files: None,
         }
     }
 
@@ -341,6 +515,7 @@ impl ClientConfig {
     pub fn use_http1() -> Self {
         Self {
             http_config: HttpClientConfigurator::http1(),
+            open_files: None,
         }
     }
 }
@@ -0,0 +1,420 @@
+//! All three helpers in one process.
+//!
+//! [`EmbeddedIpa`] runs IPA queries on events in the clear, for demos, notebooks and research
+//! code that wants the output of the real protocol without deploying helpers. It shares the
+//! events between three helpers that talk to each other over in-memory channels instead of
+//! HTTP, runs the query on them as a helper deployment would, and reconstructs the results.
+//! The helpers use the in-memory transport, so this module needs the `in-memory-infra` feature
+//! as well as `embedded`.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), ipa_core::embedded::Error> {
+//! use ipa_core::{
+//!     embedded::{EmbeddedIpa, Event},
+//!     helpers::query::IpaQueryConfig,
+//! };
+//!
+//! let events = vec![
+//!     Event::source(1, 0, 2),
+//!     Event::trigger(1, 10, 5),
+//! ];
+//! let config = IpaQueryConfig {
+//!     max_breakdown_key: 4,
+//!     with_dp: 0,
+//!     ..IpaQueryConfig::default()
+//! };
+//! let results = EmbeddedIpa::new()?.run(events, config).await?;
+//! assert_eq!(vec![0, 0, 5, 0], results.breakdowns);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything runs in the calling process, so this offers none of the privacy of a helper
+//! deployment. It is built on the same query APIs as helpers, not on the test fixtures, and
+//! supports the same input widths as the query runner.
+
+use std::{io, iter::zip};
+
+use futures::future::try_join3;
+use generic_array::GenericArray;
+use typenum::Unsigned;
+
+use crate::{
+    app::AppConfig,
+    cli::{install_collector, LoggingHandle},
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BA16, BA32, BA64},
+        FieldType, Serializable, U128Conversions,
+    },
+    helpers::{
+        query::{IpaQueryConfig, QueryConfig, QueryConfigError, QueryInput, QueryType},
+        ApiError, HelperIdentity, InMemoryMpcNetwork, InMemoryShardNetwork, InMemoryTransport,
+        Transport,
+    },
+    protocol::ipa_prf::OPRFIPAInputRow,
+    query::dispatch_input_widths,
+    rand::Rng,
+    results::{self, IpaResults, PostProcessing},
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
+    AppSetup, HelperApp,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("embedded helpers do not support {0}")]
+    Unsupported(&'static str),
+    #[error("unsupported input widths: {breakdown_key_bits} bit breakdown keys, {trigger_value_bits} bit trigger values and {timestamp_bits} bit timestamps")]
+    UnsupportedWidths {
+        breakdown_key_bits: u32,
+        trigger_value_bits: u32,
+        timestamp_bits: u32,
+    },
+    #[error("{field} of event {index} does not fit in {bits} bits")]
+    EventOutOfRange {
+        index: usize,
+        field: &'static str,
+        bits: u32,
+    },
+    #[error("failed to start embedded helpers: {0}")]
+    Start(#[from] io::Error),
+    #[error(transparent)]
+    Config(#[from] QueryConfigError),
+    #[error(transparent)]
+    Query(#[from] ApiError),
+    #[error(transparent)]
+    Results(#[from] results::Error),
+}
+
+/// An event in the clear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Identifies the user. Trigger events are attributed to source events of the same user.
+    pub match_key: u64,
+    /// Must fit in [`IpaQueryConfig::timestamp_bits`].
+    pub timestamp: u64,
+    pub is_trigger: bool,
+    /// Breakdown key of a source event. Must fit in [`IpaQueryConfig::breakdown_key_bits`].
+    pub breakdown_key: u32,
+    /// Value of a trigger event. Must fit in [`IpaQueryConfig::trigger_value_bits`].
+    pub trigger_value: u32,
+}
+
+impl Event {
+    #[must_use]
+    pub fn source(match_key: u64, timestamp: u64, breakdown_key: u32) -> Self {
+        Self {
+            match_key,
+            timestamp,
+            is_trigger: false,
+            breakdown_key,
+            trigger_value: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn trigger(match_key: u64, timestamp: u64, trigger_value: u32) -> Self {
+        Self {
+            match_key,
+            timestamp,
+            is_trigger: true,
+            breakdown_key: 0,
+            trigger_value,
+        }
+    }
+
+    fn check(&self, index: usize, config: &IpaQueryConfig) -> Result<(), Error> {
+        let fields = [
+            ("timestamp", self.timestamp, config.timestamp_bits),
+            (
+                "breakdown key",
+                u64::from(self.breakdown_key),
+                config.breakdown_key_bits,
+            ),
+            (
+                "trigger value",
+                u64::from(self.trigger_value),
+                config.trigger_value_bits,
+            ),
+        ];
+        for (field, value, bits) in fields {
+            if value.checked_shr(bits).unwrap_or(0) != 0 {
+                return Err(Error::EventOutOfRange { index, field, bits });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Events are checked against the query config before they are shared, so every field fits.
+impl<BK, TV, TS> IntoShares<OPRFIPAInputRow<BK, TV, TS>> for Event
+where
+    BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+    TV: BooleanArray + U128Conversions + IntoShares<Replicated<TV>>,
+    TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+{
+    fn share_with<R: Rng>(self, rng: &mut R) -> [OPRFIPAInputRow<BK, TV, TS>; 3] {
+        let [m0, m1, m2] = BA64::truncate_from(self.match_key).share_with(rng);
+        let [i0, i1, i2] = Boolean::from(self.is_trigger).share_with(rng);
+        let [b0, b1, b2] = BK::truncate_from(self.breakdown_key).share_with(rng);
+        let [v0, v1, v2] = TV::truncate_from(self.trigger_value).share_with(rng);
+        let [t0, t1, t2] = TS::truncate_from(self.timestamp).share_with(rng);
+
+        [
+            (m0, i0, b0, v0, t0),
+            (m1, i1, b1, v1, t1),
+            (m2, i2, b2, v2, t2),
+        ]
+        .map(
+            |(match_key, is_trigger, breakdown_key, trigger_value, timestamp)| OPRFIPAInputRow {
+                match_key,
+                is_trigger,
+                breakdown_key,
+                trigger_value,
+                timestamp,
+            },
+        )
+    }
+}
+
+/// Three helpers that run in this process. Queries run one at a time; helpers are ready for the
+/// next query as soon as one completes.
+pub struct EmbeddedIpa {
+    helpers: [HelperApp; 3],
+    mpc_network: InMemoryMpcNetwork,
+    shard_network: InMemoryShardNetwork,
+}
+
+impl EmbeddedIpa {
+    /// Starts the helpers.
+    ///
+    /// ## Errors
+    /// If the metrics collectors of the helpers can't be started.
+    pub fn new() -> Result<Self, Error> {
+        let [(s0, h0, _), (s1, h1, _), (s2, h2, _)] =
+            [(); 3].map(|()| AppSetup::new(AppConfig::default()));
+        let mpc_network = InMemoryMpcNetwork::new([Some(h0), Some(h1), Some(h2)]);
+        let shard_network = InMemoryShardNetwork::with_shards(1);
+        let connect = |setup: AppSetup, transport: InMemoryTransport<HelperIdentity>| {
+            let logging_handle = LoggingHandle {
+                metrics_handle: install_collector()?,
+            };
+            let shard_transport = shard_network.transport(transport.identity(), 0);
+            Ok::<_, Error>(setup.connect(transport, shard_transport, logging_handle))
+        };
+        let [t0, t1, t2] = mpc_network.transports();
+        let helpers = [connect(s0, t0)?, connect(s1, t1)?, connect(s2, t2)?];
+
+        Ok(Self {
+            helpers,
+            mpc_network,
+            shard_network,
+        })
+    }
+
+    /// Runs a semi-honest IPA query on `events` and returns its results.
+    ///
+    /// Events are shared in the clear, so `plaintext_match_keys` is ignored. Queries with paired
+    /// arms, source sites, public tags or an input manifest need inputs that this does not build,
+    /// and are rejected.
+    ///
+    /// ## Errors
+    /// If `config` is not supported, an event does not fit in it, or the query fails.
+    pub async fn run(
+        &self,
+        events: Vec<Event>,
+        config: IpaQueryConfig,
+    ) -> Result<IpaResults, Error> {
+        self.execute(events, config, QueryType::SemiHonestOprfIpa)
+            .await
+    }
+
+    /// Same as [`Self::run`], with security against a malicious helper.
+    ///
+    /// ## Errors
+    /// If `config` is not supported, an event does not fit in it, or the query fails.
+    pub async fn run_malicious(
+        &self,
+        events: Vec<Event>,
+        config: IpaQueryConfig,
+    ) -> Result<IpaResults, Error> {
+        self.execute(events, config, QueryType::MaliciousOprfIpa)
+            .await
+    }
+
+    async fn execute(
+        &self,
+        events: Vec<Event>,
+        config: IpaQueryConfig,
+        query_type: fn(IpaQueryConfig) -> QueryType,
+    ) -> Result<IpaResults, Error> {
+        if config.paired_arms {
+            return Err(Error::Unsupported("paired arms"));
+        }
+        if config.source_sites.is_some() {
+            return Err(Error::Unsupported("source sites"));
+        }
+        if config.public_tags.is_some() {
+            return Err(Error::Unsupported("public tags"));
+        }
+        if config.input_manifest {
+            return Err(Error::Unsupported("input manifests"));
+        }
+        for (index, event) in events.iter().enumerate() {
+            event.check(index, &config)?;
+        }
+
+        let query_config = QueryConfig::new(
+            query_type(IpaQueryConfig {
+                plaintext_match_keys: true,
+                ..config
+            }),
+            FieldType::Fp32BitPrime,
+            events.len(),
+        )?;
+        let config = config.resolve_histogram_value_bits(query_config.size);
+
+        let outputs = dispatch_input_widths!(
+            (
+                config.breakdown_key_bits,
+                config.trigger_value_bits,
+                config.timestamp_bits,
+            ),
+            (BK, TV, TS) => self.query(input::<BK, TV, TS>(events), query_config).await?,
+            (breakdown_key_bits, trigger_value_bits, timestamp_bits) => {
+                return Err(Error::UnsupportedWidths {
+                    breakdown_key_bits,
+                    trigger_value_bits,
+                    timestamp_bits,
+                })
+            }
+        );
+
+        let outputs = outputs.each_ref().map(Vec::as_slice);
+        let results = match config.histogram_value_bits {
+            16 => IpaResults::reconstruct::<BA16>(&config, outputs, PostProcessing::default()),
+            _ => IpaResults::reconstruct::<BA32>(&config, outputs, PostProcessing::default()),
+        }?;
+        Ok(results)
+    }
+
+    /// Runs a query on `inputs`, the input of each helper, and returns the outputs of the helpers.
+    async fn query(
+        &self,
+        inputs: [Vec<u8>; 3],
+        config: QueryConfig,
+    ) -> Result<[Vec<u8>; 3], ApiError> {
+        let query_id = self.helpers[0].start_query(config).await?;
+        for (helper, input) in zip(&self.helpers, inputs) {
+            helper.execute_query(QueryInput {
+                query_id,
+                input_stream: input.into(),
+                contribution: None,
+            })?;
+        }
+        let [h0, h1, h2] = self.helpers.each_ref().map(|h| h.complete_query(query_id));
+        let outputs = try_join3(h0, h1, h2).await;
+        self.mpc_network.reset();
+        self.shard_network.reset();
+        let (o0, o1, o2) = outputs?;
+
+        Ok([o0, o1, o2])
+    }
+}
+
+/// Shares `events` and serializes the shares of each helper.
+fn input<BK, TV, TS>(events: Vec<Event>) -> [Vec<u8>; 3]
+where
+    BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+    TV: BooleanArray + U128Conversions + IntoShares<Replicated<TV>>,
+    TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+    OPRFIPAInputRow<BK, TV, TS>: Serializable,
+{
+    let rows: [Vec<OPRFIPAInputRow<BK, TV, TS>>; 3] = events.into_iter().share();
+    rows.map(|rows| {
+        let size = <OPRFIPAInputRow<BK, TV, TS> as Serializable>::Size::USIZE;
+        let mut buf = vec![0; rows.len() * size];
+        for (row, chunk) in zip(rows, buf.chunks_mut(size)) {
+            row.serialize(GenericArray::from_mut_slice(chunk));
+        }
+        buf
+    })
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{EmbeddedIpa, Error, Event};
+    use crate::helpers::query::IpaQueryConfig;
+
+    fn config() -> IpaQueryConfig {
+        IpaQueryConfig {
+            per_user_credit_cap: 8,
+            max_breakdown_key: 4,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        }
+    }
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::source(1, 0, 1),
+            Event::source(2, 0, 2),
+            Event::trigger(1, 10, 3),
+            Event::trigger(2, 10, 4),
+            Event::trigger(2, 20, 1),
+            // Not attributed: user 3 has no source events.
+            Event::trigger(3, 20, 7),
+        ]
+    }
+
+    #[tokio::test]
+    async fn semi_honest() {
+        let results = EmbeddedIpa::new()
+            .unwrap()
+            .run(events(), config())
+            .await
+            .unwrap();
+        assert_eq!(vec![0, 3, 5, 0], results.breakdowns);
+    }
+
+    #[tokio::test]
+    async fn malicious_twice() {
+        let ipa = EmbeddedIpa::new().unwrap();
+        for _ in 0..2 {
+            let results = ipa.run_malicious(events(), config()).await.unwrap();
+            assert_eq!(vec![0, 3, 5, 0], results.breakdowns);
+        }
+    }
+
+    #[tokio::test]
+    async fn event_out_of_range() {
+        let events = vec![Event::source(1, 0, 1), Event::trigger(1, 1 << 20, 3)];
+        let err = EmbeddedIpa::new()
+            .unwrap()
+            .run(events, config())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::EventOutOfRange {
+                index: 1,
+                field: "timestamp",
+                bits: 20
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_paired_arms() {
+        let config = IpaQueryConfig {
+            paired_arms: true,
+            ..config()
+        };
+        let err = EmbeddedIpa::new()
+            .unwrap()
+            .run(events(), config)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unsupported("paired arms")));
+    }
+}
@@ -7,7 +7,7 @@ use std::{
 use thiserror::Error;
 
 use crate::{
-    helpers::{Role, ZeroRecordsError},
+    helpers::{query::UnsupportedCap, InputIntegrityError, Role, ZeroRecordsError},
     protocol::RecordId,
     report::{hybrid::InvalidHybridReportError, InvalidReportError},
     sharding::ShardIndex,
@@ -65,10 +65,22 @@ pub enum Error {
     FieldValueTruncation(String),
     #[error("Invalid query parameter: {0}")]
     InvalidQueryParameter(BoxError),
+    #[error(transparent)]
+    UnsupportedCap(#[from] UnsupportedCap),
     #[error("invalid report: {0}")]
     InvalidReport(#[from] InvalidReportError),
     #[error("invalid hybrid report: {0}")]
     InvalidHybridReport(#[from] InvalidHybridReportError),
+    #[error("{failures} of {total} reports failed to decrypt, more than the query allows")]
+    TooManyDecryptionFailures { failures: usize, total: usize },
+    #[error("the DP budget of the query input is exhausted on at least one helper")]
+    InputBudgetExhausted,
+    #[error(
+        "noise could not be added to the histogram of attributed values on at least one helper"
+    )]
+    NoiseFailed,
+    #[error("input integrity error: {0}")]
+    InputIntegrity(#[from] InputIntegrityError),
     #[error("unsupported: {0}")]
     Unsupported(String),
     #[error("Decompressing invalid elliptic curve point: {0}")]
@@ -106,6 +118,12 @@ pub enum Error {
     ShuffleValidationFailed(String),
     #[error("Duplicate bytes found after {0} checks")]
     DuplicateBytes(usize),
+    #[error("the query input has more than the {0} reports the query was created for")]
+    TooManyReports(usize),
+    #[error("helper {0:?} ran the query with a different configuration")]
+    QueryConfigMismatch(Role),
+    #[error("helper {0:?} is unavailable, queries can't run on the two remaining helpers")]
+    HelperUnavailable(Role),
 }
 
 impl Default for Error {
@@ -119,6 +137,22 @@ impl Error {
     pub fn path_parse_error(source: &str) -> Error {
         Error::ParseError(format!("unexpected value \"{source}\" in path").into())
     }
+
+    /// Turns the failures to hear from a peer helper into [`Error::HelperUnavailable`] that
+    /// names it. Other errors are returned as is.
+    #[must_use]
+    pub fn blame_unavailable_helper(self) -> Self {
+        match self {
+            Error::MpcInfraError(e) => match e.unavailable_peer() {
+                Some(role) => {
+                    tracing::warn!("{role:?} is considered unavailable: {e}");
+                    Error::HelperUnavailable(role)
+                }
+                None => Error::MpcInfraError(e),
+            },
+            e => e,
+        }
+    }
 }
 
 impl From<std::num::ParseIntError> for Error {
@@ -21,6 +21,8 @@ impl Block for bool {
 #[derive(Clone, Copy, Default, PartialEq, Debug, Eq)]
 pub struct Boolean(bool);
 
+impl zeroize::DefaultIsZeroes for Boolean {}
+
 impl Boolean {
     pub const TRUE: Boolean = Self(true);
     pub const FALSE: Boolean = Self(false);
@@ -8,7 +8,7 @@ use bitvec::{
     slice::Iter,
 };
 use generic_array::GenericArray;
-use typenum::{Unsigned, U12, U14, U18, U2, U32, U8};
+use typenum::{Unsigned, U12, U128, U14, U18, U2, U32, U512, U8};
 
 use crate::{
     error::LengthError,
@@ -361,6 +361,8 @@ macro_rules! boolean_array_impl {
                 }
             }
 
+            impl zeroize::DefaultIsZeroes for $name {}
+
             impl Debug for $name {
                 fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
                     f.write_str(stringify!($name))?;
@@ -970,6 +972,12 @@ store_impl!(U18, 144);
 //impl store for U32
 store_impl!(U32, 256);
 
+//impl store for U128
+store_impl!(U128, 1024);
+
+//impl store for U512
+store_impl!(U512, 4096);
+
 // These macro invocations define the supported boolean array sizes. Sizes ≤ 128 should use
 // `boolean_array_impl_small!` to get `u128` conversions and helpers. Larger sizes must
 // use `boolean_array_impl!`. At any size, you may need to add `store_impl!`, and for large
@@ -985,14 +993,19 @@ boolean_array_impl_small!(boolean_array_5, BA5, 5, fallible);
 boolean_array_impl_small!(boolean_array_6, BA6, 6, fallible);
 boolean_array_impl_small!(boolean_array_7, BA7, 7, fallible);
 boolean_array_impl_small!(boolean_array_8, BA8, 8, infallible);
+boolean_array_impl_small!(boolean_array_10, BA10, 10, fallible);
+boolean_array_impl_small!(boolean_array_12, BA12, 12, fallible);
 boolean_array_impl_small!(boolean_array_16, BA16, 16, infallible);
 boolean_array_impl_small!(boolean_array_20, BA20, 20, fallible);
+boolean_array_impl_small!(boolean_array_24, BA24, 24, infallible);
 boolean_array_impl_small!(boolean_array_32, BA32, 32, infallible);
 boolean_array_impl_small!(boolean_array_64, BA64, 64, infallible);
 boolean_array_impl_small!(boolean_array_96, BA96, 96, infallible);
 boolean_array_impl_small!(boolean_array_112, BA112, 112, infallible);
 boolean_array_impl_large!(boolean_array_144, BA144, 144, infallible, U18, U2);
 boolean_array_impl_large!(boolean_array_256, BA256, 256, infallible, U32, U2);
+boolean_array_impl_large!(boolean_array_1024, BA1024, 1024, infallible, U128, U8);
+boolean_array_impl_large!(boolean_array_4096, BA4096, 4096, infallible, U512, U32);
 
 impl Vectorizable<256> for BA64 {
     type Array = StdArray<BA64, 256>;
@@ -0,0 +1,99 @@
+//! Constant-time helpers for control flow that depends on revealed values.
+//!
+//! Some values are revealed to the helpers in the middle of a protocol: comparison results in
+//! quicksort, or PRF pseudonyms that group rows by user. All helpers learn them, but other
+//! tenants of the hosts they run on must not. Comparing and branching on these values with `==`
+//! and `if` leaks them through timing, which these helpers avoid.
+//!
+//! The quicksort partition and the grouping of rows by user don't use them. They move rows to
+//! positions that depend on the revealed values, so their memory accesses leak those values
+//! whatever the comparisons do, and they are not constant time.
+
+use subtle::{Choice, ConditionallySelectable};
+
+/// Converts a revealed bit into a [`Choice`].
+#[must_use]
+pub fn choice(value: bool) -> Choice {
+    Choice::from(u8::from(value))
+}
+
+/// Returns `a` if `choice` is set and `b` otherwise. `subtle` does not implement
+/// [`ConditionallySelectable`] for `usize`.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)] // usize is never wider than 64 bits
+pub fn select_usize(choice: Choice, a: usize, b: usize) -> usize {
+    u64::conditional_select(&(b as u64), &(a as u64), choice) as usize
+}
+
+/// Swaps elements `i` and `j` of `slice` if `choice` is set. Both elements are read and written
+/// either way, so the memory that is touched does not depend on `choice`.
+///
+/// ## Panics
+/// If `i` or `j` is out of bounds, or if they are equal.
+pub fn conditional_swap<T: ConditionallySelectable>(
+    slice: &mut [T],
+    i: usize,
+    j: usize,
+    choice: Choice,
+) {
+    assert!(i != j, "can't swap element {i} with itself");
+    let (lo, hi) = (i.min(j), i.max(j));
+    assert!(hi < slice.len(), "index {hi} is out of bounds");
+    let (left, right) = slice.split_at_mut(hi);
+    T::conditional_swap(&mut left[lo], &mut right[0], choice);
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use subtle::{Choice, ConditionallySelectable};
+
+    use super::{choice, conditional_swap, select_usize};
+
+    #[test]
+    fn select() {
+        assert_eq!(7, select_usize(choice(true), 7, usize::MAX));
+        assert_eq!(usize::MAX, select_usize(choice(false), 7, usize::MAX));
+    }
+
+    #[test]
+    fn swap() {
+        let mut v = [1, 2, 3];
+        conditional_swap(&mut v, 0, 2, choice(false));
+        assert_eq!([1, 2, 3], v);
+        conditional_swap(&mut v, 0, 2, choice(true));
+        assert_eq!([3, 2, 1], v);
+    }
+
+    /// Counts how many times it was written by a conditional select.
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Tracked {
+        value: u8,
+        writes: u8,
+    }
+
+    impl ConditionallySelectable for Tracked {
+        fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+            Self {
+                value: u8::conditional_select(&a.value, &b.value, choice),
+                writes: a.writes + 1,
+            }
+        }
+    }
+
+    #[test]
+    fn swap_writes_both() {
+        for swap in [false, true] {
+            let mut v = [1, 2, 3].map(|value| Tracked { value, writes: 0 });
+            conditional_swap(&mut v, 2, 0, choice(swap));
+            assert_eq!([1, 1, 0], v.map(|t| t.writes));
+            let expected = if swap { [3, 2, 1] } else { [1, 2, 3] };
+            assert_eq!(expected, v.map(|t| t.value));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "index 3 is out of bounds")]
+    fn swap_out_of_bounds() {
+        conditional_swap(&mut [1, 2, 3], 0, 3, choice(false));
+    }
+}
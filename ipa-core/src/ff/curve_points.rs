@@ -61,6 +61,8 @@ impl Default for RP25519 {
     }
 }
 
+impl zeroize::DefaultIsZeroes for RP25519 {}
+
 /// Implementing trait for secret sharing
 impl SharedValue for RP25519 {
     type Storage = RistrettoPoint;
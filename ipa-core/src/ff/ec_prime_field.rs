@@ -46,6 +46,8 @@ impl Default for Fp25519 {
     }
 }
 
+impl zeroize::DefaultIsZeroes for Fp25519 {}
+
 ///trait for secret sharing
 impl SharedValue for Fp25519 {
     type Storage = Scalar;
@@ -158,6 +158,8 @@ macro_rules! bit_array_impl {
                 }
             }
 
+            impl zeroize::DefaultIsZeroes for $name {}
+
             impl SharedValue for $name {
                 type Storage = $store;
                 const BITS: u32 = $bits;
@@ -5,6 +5,7 @@
 mod accumulator;
 pub mod boolean;
 pub mod boolean_array;
+pub mod constant_time;
 pub mod curve_points;
 pub mod ec_prime_field;
 mod field;
@@ -296,6 +296,8 @@ macro_rules! field_impl {
             }
         }
 
+        impl zeroize::DefaultIsZeroes for $field {}
+
         impl Serializable for $field {
             type Size = <<Self as SharedValue>::Storage as Block>::Size;
             type DeserializationError = GreaterThanPrimeError<$backend_store>;
@@ -0,0 +1,558 @@
+//! C ABI for report collectors that are not written in Rust.
+//!
+//! It covers the parts of a query that happen outside of the helpers: secret sharing a report
+//! and encrypting the shares towards the helpers, and reconstructing the query results from the
+//! shares returned by the helpers. The declarations for C are in `include/ipa_core.h`, which is
+//! generated by [cbindgen] and must be regenerated whenever this module changes:
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/ipa_core.h
+//! ```
+//!
+//! C callers link against the shared or static library of the `ipa-ffi` crate, which exports
+//! these functions. The tests that call them from C, in `tests/ffi`, need the `ffi-test`
+//! feature.
+//!
+//! Memory allocated by this library is returned to it with the matching `ipa_*_free` function.
+//! Panics do not unwind into the caller, they are reported as [`IpaStatus::Panic`].
+//!
+//! [cbindgen]: https://github.com/mozilla/cbindgen
+
+use std::{
+    ffi::{c_char, CStr},
+    iter::zip,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use ::hpke::Deserializable;
+use rand::thread_rng;
+use typenum::Unsigned;
+
+use crate::{
+    const_assert_eq,
+    ff::{
+        boolean_array::{BA16, BA3, BA32, BA64, BA8},
+        Serializable, U128Conversions,
+    },
+    hpke::{IpaPublicKey, KeyRegistry, PublicKeyOnly},
+    report::{
+        hybrid::{
+            HybridConversionReport, HybridEventType, HybridImpressionReport, HybridReport,
+            DEFAULT_KEY_ID,
+        },
+        hybrid_info::{HybridConversionInfo, HybridImpressionInfo},
+    },
+    results,
+    secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+};
+
+/// Same types as the hybrid query uses.
+type BreakdownKey = BA8;
+type TriggerValue = BA3;
+
+/// Length of a helper's public key, in bytes.
+pub const IPA_PUBLIC_KEY_LEN: usize = 32;
+const_assert_eq!(
+    IPA_PUBLIC_KEY_LEN,
+    <IpaPublicKey as ::hpke::Serializable>::OutputSize::USIZE
+);
+
+/// Value of [`IpaReport::event_type`] for impressions.
+pub const IPA_EVENT_IMPRESSION: u8 = 0;
+/// Value of [`IpaReport::event_type`] for conversions.
+pub const IPA_EVENT_CONVERSION: u8 = 1;
+const_assert_eq!(IPA_EVENT_IMPRESSION, HybridEventType::Impression as u8);
+const_assert_eq!(IPA_EVENT_CONVERSION, HybridEventType::Conversion as u8);
+
+/// Outcome of a call into this library.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpaStatus {
+    Ok,
+    /// A pointer argument was null.
+    NullPointer,
+    /// An argument is out of range, or a string is not ASCII.
+    InvalidArgument,
+    /// A public key is not a valid X25519 public key.
+    InvalidPublicKey,
+    EncryptionFailed,
+    /// The helper outputs can't be parsed, or the helpers' shares are not consistent.
+    ReconstructionFailed,
+    /// There is a bug in this library.
+    Panic,
+}
+
+/// Bytes owned by this library, released with [`ipa_buffer_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct IpaBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl IpaBuffer {
+    /// ## Safety
+    /// `data` must be null or point to `len` readable bytes that outlive the returned slice.
+    unsafe fn as_slice<'a>(&self) -> &'a [u8] {
+        if self.data.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(self.data, self.len)
+        }
+    }
+}
+
+impl From<Vec<u8>> for IpaBuffer {
+    fn from(value: Vec<u8>) -> Self {
+        let (data, len) = into_raw(value);
+        Self { data, len }
+    }
+}
+
+/// Values owned by this library, released with [`ipa_values_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct IpaValues {
+    pub data: *mut u64,
+    pub len: usize,
+}
+
+/// A report in the clear, as the report collector sees it.
+///
+/// Impressions use `breakdown_key`, conversions use `value`, `conversion_site_domain`,
+/// `timestamp`, `epsilon` and `sensitivity`. Fields that the event type does not use are
+/// ignored.
+#[repr(C)]
+#[derive(Debug)]
+pub struct IpaReport {
+    /// [`IPA_EVENT_IMPRESSION`] or [`IPA_EVENT_CONVERSION`].
+    pub event_type: u8,
+    pub match_key: u64,
+    /// Must fit into 8 bits.
+    pub breakdown_key: u32,
+    /// Must fit into 3 bits.
+    pub value: u32,
+    /// Null-terminated ASCII string.
+    pub conversion_site_domain: *const c_char,
+    pub timestamp: u64,
+    pub epsilon: f64,
+    pub sensitivity: f64,
+}
+
+/// Secret shares `report` and encrypts one share towards each helper.
+///
+/// `public_keys` holds the public keys of the three helpers, [`IPA_PUBLIC_KEY_LEN`] bytes each,
+/// in helper order. On success, the encrypted share for helper `i` is written to `out[i]`. It is
+/// in the format that the helpers expect for one report of a hybrid query, that is one line of
+/// `helper{i+1}.enc` before hex encoding. Nothing is written to `out` on failure.
+///
+/// # Safety
+/// `report` must point to a valid [`IpaReport`], `public_keys` must point to
+/// `3 * IPA_PUBLIC_KEY_LEN` readable bytes and `out` must point to 3 writable [`IpaBuffer`]s.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_encrypt_report(
+    report: *const IpaReport,
+    public_keys: *const u8,
+    out: *mut IpaBuffer,
+) -> IpaStatus {
+    guard(|| {
+        let report = report.as_ref().ok_or(IpaStatus::NullPointer)?;
+        if public_keys.is_null() || out.is_null() {
+            return Err(IpaStatus::NullPointer);
+        }
+        let public_keys = slice::from_raw_parts(public_keys, 3 * IPA_PUBLIC_KEY_LEN);
+        let encrypted = encrypt_report(report, public_keys)?;
+        for (i, share) in encrypted.into_iter().enumerate() {
+            out.add(i).write(IpaBuffer::from(share));
+        }
+        Ok(())
+    })
+}
+
+/// Reconstructs the query results from the outputs of the three helpers, in helper order.
+///
+/// `value_bits` is the width of the output values, as configured for the query: 16 or 32. On
+/// success, the values are written to `results`.
+///
+/// # Safety
+/// `outputs` must point to 3 [`IpaBuffer`]s, each of them null or pointing to `len` readable
+/// bytes. These buffers do not need to be allocated by this library. `results` must point to a
+/// writable [`IpaValues`].
+#[no_mangle]
+pub unsafe extern "C" fn ipa_reconstruct_results(
+    outputs: *const IpaBuffer,
+    value_bits: u32,
+    results: *mut IpaValues,
+) -> IpaStatus {
+    guard(|| {
+        if outputs.is_null() || results.is_null() {
+            return Err(IpaStatus::NullPointer);
+        }
+        let outputs = [0, 1, 2].map(|i| (*outputs.add(i)).as_slice());
+        let values = match value_bits {
+            16 => reconstruct::<BA16>(outputs)?,
+            32 => reconstruct::<BA32>(outputs)?,
+            _ => return Err(IpaStatus::InvalidArgument),
+        };
+        let (data, len) = into_raw(values);
+        results.write(IpaValues { data, len });
+        Ok(())
+    })
+}
+
+/// Releases a buffer returned by this library.
+///
+/// # Safety
+/// `buffer` must have been returned by this library, and must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_buffer_free(buffer: IpaBuffer) {
+    free_raw(buffer.data, buffer.len);
+}
+
+/// Releases values returned by this library.
+///
+/// # Safety
+/// `values` must have been returned by this library, and must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_values_free(values: IpaValues) {
+    free_raw(values.data, values.len);
+}
+
+fn guard<F: FnOnce() -> Result<(), IpaStatus>>(f: F) -> IpaStatus {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => IpaStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => IpaStatus::Panic,
+    }
+}
+
+fn into_raw<T>(values: Vec<T>) -> (*mut T, usize) {
+    let values = values.into_boxed_slice();
+    let len = values.len();
+    (Box::into_raw(values).cast::<T>(), len)
+}
+
+/// ## Safety
+/// `data` and `len` must come from [`into_raw`], or `data` must be null.
+unsafe fn free_raw<T>(data: *mut T, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// ## Safety
+/// `report.conversion_site_domain` must be null or point to a null-terminated string, if the
+/// report is a conversion.
+unsafe fn encrypt_report(
+    report: &IpaReport,
+    public_keys: &[u8],
+) -> Result<Vec<Vec<u8>>, IpaStatus> {
+    let registries = public_keys
+        .chunks_exact(IPA_PUBLIC_KEY_LEN)
+        .map(|pk| {
+            IpaPublicKey::from_bytes(pk)
+                .map(|pk| KeyRegistry::from_keys([PublicKeyOnly(pk)]))
+                .map_err(|_| IpaStatus::InvalidPublicKey)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut rng = thread_rng();
+    let match_key = BA64::truncate_from(report.match_key).share_with(&mut rng);
+    let shares: [HybridReport<BreakdownKey, TriggerValue>; 3] =
+        match HybridEventType::try_from(report.event_type)
+            .map_err(|_| IpaStatus::InvalidArgument)?
+        {
+            HybridEventType::Impression => {
+                let breakdown_key = BreakdownKey::try_from(u128::from(report.breakdown_key))
+                    .map_err(|_| IpaStatus::InvalidArgument)?
+                    .share_with(&mut rng);
+                let info = HybridImpressionInfo::new(DEFAULT_KEY_ID);
+                zip(match_key, breakdown_key)
+                    .map(|(match_key, breakdown_key)| {
+                        HybridReport::Impression(HybridImpressionReport {
+                            match_key,
+                            breakdown_key,
+                            info: info.clone(),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap()
+            }
+            HybridEventType::Conversion => {
+                let value = TriggerValue::try_from(u128::from(report.value))
+                    .map_err(|_| IpaStatus::InvalidArgument)?
+                    .share_with(&mut rng);
+                if report.conversion_site_domain.is_null() {
+                    return Err(IpaStatus::NullPointer);
+                }
+                let domain = CStr::from_ptr(report.conversion_site_domain)
+                    .to_str()
+                    .map_err(|_| IpaStatus::InvalidArgument)?;
+                let info = HybridConversionInfo::new(
+                    DEFAULT_KEY_ID,
+                    domain,
+                    report.timestamp,
+                    report.epsilon,
+                    report.sensitivity,
+                )
+                .map_err(|_| IpaStatus::InvalidArgument)?;
+                zip(match_key, value)
+                    .map(|(match_key, value)| {
+                        HybridReport::Conversion(HybridConversionReport {
+                            match_key,
+                            value,
+                            info: info.clone(),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap()
+            }
+        };
+
+    zip(shares, &registries)
+        .map(|(share, registry)| {
+            share
+                .encrypt(DEFAULT_KEY_ID, registry, &mut rng)
+                .map_err(|_| IpaStatus::EncryptionFailed)
+        })
+        .collect()
+}
+
+fn reconstruct<V>(outputs: [&[u8]; 3]) -> Result<Vec<u64>, IpaStatus>
+where
+    V: results::ResultValue + U128Conversions,
+    AdditiveShare<V>: Serializable,
+{
+    let values = results::reconstruct::<V>(outputs).map_err(|_| IpaStatus::ReconstructionFailed)?;
+    // Output values are at most 32 bits wide.
+    Ok(values
+        .iter()
+        .map(|v| u64::try_from(v.as_u128()).unwrap())
+        .collect())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{ffi::CString, iter::zip, mem::MaybeUninit, ptr, slice};
+
+    use ::hpke::Serializable as _;
+    use bytes::Bytes;
+    use generic_array::GenericArray;
+
+    use super::{
+        ipa_buffer_free, ipa_encrypt_report, ipa_reconstruct_results, ipa_values_free, thread_rng,
+        BreakdownKey, IpaBuffer, IpaReport, IpaStatus, IpaValues, TriggerValue,
+        IPA_EVENT_CONVERSION, IPA_EVENT_IMPRESSION,
+    };
+    use crate::{
+        ff::{boolean_array::BA32, Serializable, U128Conversions},
+        hpke::{KeyPair, KeyRegistry, PublicKeyRegistry},
+        report::hybrid::{
+            EncryptedHybridReport,
+            HybridReport::{self, Conversion, Impression},
+        },
+        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+        test_fixture::Reconstruct,
+    };
+
+    #[cfg(feature = "ffi-test")]
+    extern "C" {
+        // Defined in `tests/ffi/round_trip.c`.
+        fn ipa_test_encrypt_conversion(public_keys: *const u8, out: *mut IpaBuffer) -> IpaStatus;
+        fn ipa_test_reconstruct(
+            outputs: *const IpaBuffer,
+            expected: *const u64,
+            expected_len: usize,
+        ) -> IpaStatus;
+    }
+
+    fn registries() -> ([KeyRegistry<KeyPair>; 3], Vec<u8>) {
+        let mut rng = thread_rng();
+        let registries = [(); 3].map(|()| KeyRegistry::<KeyPair>::random(1, &mut rng));
+        let public_keys = registries
+            .iter()
+            .flat_map(|registry| registry.public_key(0).unwrap().to_bytes())
+            .collect();
+        (registries, public_keys)
+    }
+
+    /// Decrypts the encrypted shares and releases them.
+    fn decrypt(
+        registries: &[KeyRegistry<KeyPair>; 3],
+        encrypted: [IpaBuffer; 3],
+    ) -> Vec<HybridReport<BreakdownKey, TriggerValue>> {
+        zip(registries, encrypted)
+            .map(|(registry, buffer)| {
+                let bytes = Bytes::copy_from_slice(unsafe { buffer.as_slice() });
+                unsafe { ipa_buffer_free(buffer) };
+                EncryptedHybridReport::<BreakdownKey, TriggerValue>::from_bytes(bytes)
+                    .unwrap()
+                    .decrypt(registry)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn encrypt(report: &IpaReport, public_keys: &[u8]) -> Result<[IpaBuffer; 3], IpaStatus> {
+        let mut out = MaybeUninit::<[IpaBuffer; 3]>::uninit();
+        match unsafe { ipa_encrypt_report(report, public_keys.as_ptr(), out.as_mut_ptr().cast()) } {
+            IpaStatus::Ok => Ok(unsafe { out.assume_init() }),
+            status => Err(status),
+        }
+    }
+
+    fn outputs(values: &[u64]) -> [Vec<u8>; 3] {
+        let shares: [Vec<AdditiveShare<BA32>>; 3] =
+            values.iter().map(|&v| BA32::truncate_from(v)).share();
+        shares.map(|shares| {
+            let mut bytes = Vec::new();
+            for share in shares {
+                let mut buf =
+                    GenericArray::<u8, <AdditiveShare<BA32> as Serializable>::Size>::default();
+                share.serialize(&mut buf);
+                bytes.extend_from_slice(&buf);
+            }
+            bytes
+        })
+    }
+
+    fn views(outputs: &mut [Vec<u8>; 3]) -> [IpaBuffer; 3] {
+        outputs.each_mut().map(|output| IpaBuffer {
+            data: output.as_mut_ptr(),
+            len: output.len(),
+        })
+    }
+
+    #[test]
+    fn encrypt_impression() {
+        let (registries, public_keys) = registries();
+        let report = IpaReport {
+            event_type: IPA_EVENT_IMPRESSION,
+            match_key: 0xDEAD_BEEF,
+            breakdown_key: 7,
+            value: 0,
+            conversion_site_domain: ptr::null(),
+            timestamp: 0,
+            epsilon: 0.0,
+            sensitivity: 0.0,
+        };
+
+        let reports = decrypt(&registries, encrypt(&report, &public_keys).unwrap());
+        let [Impression(r0), Impression(r1), Impression(r2)] = <[_; 3]>::try_from(reports).unwrap()
+        else {
+            panic!("expected impressions");
+        };
+        assert_eq!(
+            0xDEAD_BEEF,
+            [&r0.match_key, &r1.match_key, &r2.match_key]
+                .reconstruct()
+                .as_u128()
+        );
+        assert_eq!(
+            7,
+            [&r0.breakdown_key, &r1.breakdown_key, &r2.breakdown_key]
+                .reconstruct()
+                .as_u128()
+        );
+    }
+
+    #[test]
+    fn invalid_reports() {
+        let (_, public_keys) = registries();
+        let domain = CString::new("www.example.com").unwrap();
+        let report = |event_type, breakdown_key, value, domain: *const _| IpaReport {
+            event_type,
+            match_key: 1,
+            breakdown_key,
+            value,
+            conversion_site_domain: domain,
+            timestamp: 0,
+            epsilon: 1.0,
+            sensitivity: 1.0,
+        };
+
+        for (report, status) in [
+            (report(2, 0, 0, domain.as_ptr()), IpaStatus::InvalidArgument),
+            (
+                report(IPA_EVENT_IMPRESSION, 256, 0, domain.as_ptr()),
+                IpaStatus::InvalidArgument,
+            ),
+            (
+                report(IPA_EVENT_CONVERSION, 0, 8, domain.as_ptr()),
+                IpaStatus::InvalidArgument,
+            ),
+            (
+                report(IPA_EVENT_CONVERSION, 0, 1, ptr::null()),
+                IpaStatus::NullPointer,
+            ),
+        ] {
+            assert_eq!(status, encrypt(&report, &public_keys).unwrap_err());
+        }
+        assert_eq!(IpaStatus::NullPointer, unsafe {
+            ipa_encrypt_report(ptr::null(), public_keys.as_ptr(), ptr::null_mut())
+        });
+    }
+
+    #[test]
+    fn reconstruct() {
+        let mut outputs = outputs(&[1, 2, u64::from(u32::MAX)]);
+        let mut values = MaybeUninit::<IpaValues>::uninit();
+        let status = unsafe {
+            ipa_reconstruct_results(views(&mut outputs).as_ptr(), 32, values.as_mut_ptr())
+        };
+        assert_eq!(IpaStatus::Ok, status);
+
+        let reconstructed = unsafe { values.assume_init() };
+        assert_eq!(&[1, 2, u64::from(u32::MAX)], unsafe {
+            slice::from_raw_parts(reconstructed.data, reconstructed.len)
+        });
+        unsafe { ipa_values_free(reconstructed) };
+
+        let mut values = MaybeUninit::<IpaValues>::uninit();
+        assert_eq!(IpaStatus::InvalidArgument, unsafe {
+            ipa_reconstruct_results(views(&mut outputs).as_ptr(), 8, values.as_mut_ptr())
+        });
+        outputs[1].pop();
+        assert_eq!(IpaStatus::ReconstructionFailed, unsafe {
+            ipa_reconstruct_results(views(&mut outputs).as_ptr(), 32, values.as_mut_ptr())
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "ffi-test")]
+    fn round_trip_from_c() {
+        let (registries, public_keys) = registries();
+        let mut out = MaybeUninit::<[IpaBuffer; 3]>::uninit();
+        let status =
+            unsafe { ipa_test_encrypt_conversion(public_keys.as_ptr(), out.as_mut_ptr().cast()) };
+        assert_eq!(IpaStatus::Ok, status);
+
+        let reports = decrypt(&registries, unsafe { out.assume_init() });
+        let [Conversion(r0), Conversion(r1), Conversion(r2)] = <[_; 3]>::try_from(reports).unwrap()
+        else {
+            panic!("expected conversions");
+        };
+        assert_eq!(
+            0xDEAD_BEEF,
+            [&r0.match_key, &r1.match_key, &r2.match_key]
+                .reconstruct()
+                .as_u128()
+        );
+        assert_eq!(5, [&r0.value, &r1.value, &r2.value].reconstruct().as_u128());
+        assert_eq!("www.example.com", r0.info.conversion_site_domain);
+        assert_eq!(100, r0.info.timestamp);
+
+        let expected = [3, 0, 42];
+        let mut outputs = outputs(&expected);
+        assert_eq!(IpaStatus::Ok, unsafe {
+            ipa_test_reconstruct(
+                views(&mut outputs).as_ptr(),
+                expected.as_ptr(),
+                expected.len(),
+            )
+        });
+    }
+}
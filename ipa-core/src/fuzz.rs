@@ -0,0 +1,139 @@
+//! Entry points for the fuzz targets in `ipa-core/fuzz`.
+//!
+//! Helpers decode reports, input streams, step paths and query parameters straight from bytes
+//! that peers and report collectors send over the network. Each function here runs one of those
+//! decoders on arbitrary input and checks that it either rejects the input or decodes it into
+//! something consistent with it. Any panic is a finding: release builds abort on panic, so a
+//! panicking decoder lets anyone who can reach a helper take it down.
+//!
+//! To run a target, install `cargo-fuzz` and run `cargo +nightly fuzz run <target>` from the
+//! `ipa-core` directory. Targets are listed in `fuzz/Cargo.toml`.
+
+use std::sync::LazyLock;
+
+use axum::{extract::FromRequestParts, http::Request};
+use bytes::Bytes;
+use futures::{executor::block_on, stream, TryStreamExt};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{
+    de::value::{BorrowedStrDeserializer, Error as DeError},
+    Deserialize,
+};
+
+use crate::{
+    error::BoxError,
+    ff::boolean_array::{BA20, BA3, BA8},
+    helpers::{
+        query::{QueryConfig, QueryPolicy},
+        LengthDelimitedStream,
+    },
+    hpke::{KeyPair, KeyRegistry},
+    net::QueryConfigQueryParams,
+    protocol::{Gate, ProtocolVersion},
+    report::EncryptedOprfReport,
+};
+
+static KEY_REGISTRY: LazyLock<KeyRegistry<KeyPair>> =
+    LazyLock::new(|| KeyRegistry::random(1, &mut StdRng::seed_from_u64(0)));
+
+/// Parses a report with the same widths the helpers use for IPA queries and, if it is
+/// accepted, reads every field of it and tries to decrypt it.
+///
+/// ## Panics
+/// If an accepted report does not hold the input bytes, or reading it panics.
+pub fn encrypted_oprf_report(data: &[u8]) {
+    let Ok(report) = EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(data) else {
+        return;
+    };
+    assert_eq!(data, report.as_bytes());
+    // Accessors rely on `from_bytes` to validate the report.
+    let _ = (
+        report.event_type(),
+        report.key_id(),
+        report.epoch(),
+        report.site_domain(),
+    );
+    // Random bytes won't decrypt, but getting to the authentication check must not panic.
+    let _ = report.decrypt(&*KEY_REGISTRY);
+}
+
+/// Splits the input into length-delimited records. The first byte sets the size of the chunks
+/// the rest of the input is delivered in, to exercise records that span several chunks. If the
+/// whole input decodes, encoding the records again must give back the same bytes.
+///
+/// ## Panics
+/// If the records of a decoded stream do not add up to its input.
+pub fn length_delimited_stream(data: &[u8]) {
+    let Some((&chunk_size, data)) = data.split_first() else {
+        return;
+    };
+    let chunks = data
+        .chunks(usize::from(chunk_size).max(1))
+        .map(|chunk| Ok::<_, BoxError>(Bytes::copy_from_slice(chunk)))
+        .collect::<Vec<_>>();
+    let stream = LengthDelimitedStream::<Bytes, _>::new(stream::iter(chunks));
+    let Ok(batches) = block_on(stream.try_collect::<Vec<_>>()) else {
+        return;
+    };
+
+    let mut encoded = Vec::with_capacity(data.len());
+    for record in batches.iter().flatten() {
+        let len = u16::try_from(record.len()).expect("records are at most u16::MAX bytes long");
+        encoded.extend_from_slice(&len.to_le_bytes());
+        encoded.extend_from_slice(record);
+    }
+    assert_eq!(data, encoded);
+}
+
+/// Parses the part of a step request path that follows the `ipa` namespace, i.e.
+/// `<protocol version>/<gate>`. Whatever parses must print back as the same string, so that
+/// helpers never route data of one step to another.
+///
+/// ## Panics
+/// If a protocol version or a gate parses from a string other than its own.
+pub fn step_request(data: &[u8]) {
+    let Ok(path) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Some((version, gate)) = path.split_once('/') else {
+        return;
+    };
+
+    if let Ok(parsed) = version.parse::<ProtocolVersion>() {
+        assert_eq!(version, parsed.to_string());
+    }
+    if let Ok(parsed) = Gate::deserialize(BorrowedStrDeserializer::<DeError>::new(gate)) {
+        #[cfg(compact_gate)]
+        assert_eq!(gate, parsed.as_ref());
+        #[cfg(descriptive_gate)]
+        assert_eq!(gate.strip_prefix('/').unwrap_or(gate), parsed.as_ref());
+    }
+}
+
+/// Parses a query string the way helpers parse the parameters of a query creation request and
+/// validates the result. A parsed config must survive being sent to the other helpers, which
+/// parse the query string the leader prints for it.
+///
+/// ## Panics
+/// If validation panics, or the printed config does not parse back into the same config.
+pub fn query_config(data: &[u8]) {
+    fn parse(query: &str) -> Option<QueryConfig> {
+        let request = Request::get(format!("/?{query}")).body(()).ok()?;
+        let (mut parts, ()) = request.into_parts();
+        block_on(QueryConfigQueryParams::from_request_parts(&mut parts, &()))
+            .ok()
+            .map(|params| params.0)
+    }
+
+    let Ok(query) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Some(config) = parse(query) else {
+        return;
+    };
+    let _ = config.validate(&QueryPolicy::default());
+
+    let printed = QueryConfigQueryParams(config).to_string();
+    let reparsed = parse(&printed).unwrap_or_else(|| panic!("failed to parse {printed}"));
+    assert_eq!(printed, QueryConfigQueryParams(reparsed).to_string());
+}
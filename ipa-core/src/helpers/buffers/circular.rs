@@ -240,6 +240,14 @@ impl CircularBuf {
     }
 }
 
+/// The buffer holds serialized shares, which must not outlive it in memory.
+#[cfg(feature = "zeroize-secrets")]
+impl Drop for CircularBuf {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.data);
+    }
+}
+
 /// A handle to write chunks of data directly inside [`CircularBuf`] using [`CircularBuf::next`]
 /// method.
 pub struct Next<'a> {
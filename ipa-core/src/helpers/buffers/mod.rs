@@ -3,7 +3,8 @@ mod unordered_receiver;
 
 mod circular;
 
-pub use ordering_sender::OrderingSender;
+pub use ordering_sender::{OrderingSender, SendStats};
 pub use unordered_receiver::{
-    DeserializeError, EndOfStreamError, Error as UnorderedReceiverError, UnorderedReceiver,
+    DeserializeError, EndOfStreamError, Error as UnorderedReceiverError, OutsideWindowError,
+    UnorderedReceiver,
 };
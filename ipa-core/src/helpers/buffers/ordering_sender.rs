@@ -7,6 +7,7 @@ use std::{
     num::NonZeroUsize,
     pin::Pin,
     task::{Context, Poll},
+    time::Instant,
 };
 
 use futures::{task::Waker, Future, Stream};
@@ -22,6 +23,50 @@ use crate::{
     },
 };
 
+/// Measurements an [`OrderingSender`] takes over its lifetime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendStats {
+    /// Number of messages written.
+    pub writes: usize,
+    /// Number of times a message could not be written because the buffer was full.
+    pub blocked_writes: usize,
+    /// Number of chunks taken off the buffer.
+    pub flushes: usize,
+    /// Total size of the chunks taken off the buffer, in bytes.
+    pub bytes: usize,
+    /// Largest number of bytes held in the buffer at once.
+    pub peak_occupancy: usize,
+    /// Number of bytes held in the buffer when these stats were taken.
+    pub occupancy: usize,
+    /// Index of the next message to be written, when these stats were taken. Messages with
+    /// larger indices can't be written before this one.
+    pub next: usize,
+    pub capacity: usize,
+    pub write_size: usize,
+    pub first_write: Option<Instant>,
+    pub last_write: Option<Instant>,
+    pub last_flush: Option<Instant>,
+}
+
+impl SendStats {
+    fn new(capacity: usize, write_size: usize) -> Self {
+        Self {
+            writes: 0,
+            blocked_writes: 0,
+            flushes: 0,
+            bytes: 0,
+            peak_occupancy: 0,
+            occupancy: 0,
+            next: 0,
+            capacity,
+            write_size,
+            first_write: None,
+            last_write: None,
+            last_flush: None,
+        }
+    }
+}
+
 /// The operating state for an `OrderingSender`.
 struct State {
     /// A store of bytes to write into.
@@ -65,7 +110,9 @@ impl State {
     // It is harder to prove through assertions and/or static analysis that every message ever
     // sent will be the same size, so we settle for a less strict check that should at least
     // prevent reaching a deadlock.
-    fn write<M: Message>(&mut self, m: &M, cx: &Context<'_>) -> Poll<()> {
+    //
+    // Returns the number of bytes held in the buffer after the write.
+    fn write<M: Message>(&mut self, m: &M, cx: &Context<'_>) -> Poll<usize> {
         if !self.buf.can_write() {
             Self::save_waker(&mut self.write_ready, cx);
             return Poll::Pending;
@@ -77,7 +124,7 @@ impl State {
             Self::wake(&mut self.stream_ready);
         }
 
-        Poll::Ready(())
+        Poll::Ready(self.buf.len())
     }
 
     fn take(&mut self, cx: &Context<'_>) -> Poll<Vec<u8>> {
@@ -272,6 +319,9 @@ impl Waiting {
 pub struct OrderingSender {
     next: AtomicUsize,
     state: Mutex<State>,
+    /// Kept apart from `state` so that taking measurements doesn't extend the time
+    /// writers and readers hold the state lock.
+    stats: Mutex<SendStats>,
     waiting: Waiting,
 }
 
@@ -292,6 +342,7 @@ impl OrderingSender {
                 write_size.get(),
                 read_threshold.get(),
             )),
+            stats: Mutex::new(SendStats::new(capacity.get(), write_size.get())),
             waiting: Waiting::default(),
         }
     }
@@ -334,10 +385,55 @@ impl OrderingSender {
         self.state.lock().unwrap().is_closed()
     }
 
+    /// Returns `true` if [`Self::take_next`] would return data right away.
+    ///
+    /// ## Panics
+    /// If the underlying mutex is poisoned or locked by the same thread.
+    pub fn has_data(&self) -> bool {
+        self.state.lock().unwrap().buf.can_read()
+    }
+
+    /// Returns what this sender has measured so far.
+    ///
+    /// ## Panics
+    /// If the underlying mutex is poisoned or locked by the same thread.
+    pub fn stats(&self) -> SendStats {
+        let occupancy = self.state.lock().unwrap().buf.len();
+        SendStats {
+            occupancy,
+            next: self.next.load(Acquire),
+            ..*self.stats.lock().unwrap()
+        }
+    }
+
+    /// Records a write that left `occupancy` bytes in the buffer.
+    fn record_write(&self, occupancy: usize) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().unwrap();
+        stats.writes += 1;
+        stats.first_write.get_or_insert(now);
+        stats.last_write = Some(now);
+        stats.peak_occupancy = std::cmp::max(stats.peak_occupancy, occupancy);
+    }
+
+    /// Records a write that found the buffer full.
+    fn record_blocked_write(&self) {
+        self.stats.lock().unwrap().blocked_writes += 1;
+    }
+
+    /// Records a chunk of `len` bytes taken off the buffer.
+    fn record_flush(&self, len: usize) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().unwrap();
+        stats.flushes += 1;
+        stats.bytes += len;
+        stats.last_flush = Some(now);
+    }
+
     /// Perform the next `send` or `close` operation.
-    fn next_op<F>(&self, i: usize, cx: &Context<'_>, f: F) -> Poll<()>
+    fn next_op<T, F>(&self, i: usize, cx: &Context<'_>, f: F) -> Poll<T>
     where
-        F: FnOnce(&mut MutexGuard<'_, State>) -> Poll<()>,
+        F: FnOnce(&mut MutexGuard<'_, State>) -> Poll<T>,
     {
         // This load here is on the hot path.
         // Don't acquire the state mutex unless this test passes.
@@ -398,6 +494,8 @@ impl OrderingSender {
                 len = v.len(),
                 "take_next ready"
             );
+            drop(b);
+            self.record_flush(v.len());
             self.waiting.wake(next);
             Poll::Ready(Some(v))
         } else if b.is_closed() {
@@ -455,16 +553,28 @@ impl<'a, M: Message, B: Borrow<M> + 'a> Future for Send<'a, M, B> {
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.as_mut();
 
+        let mut blocked = false;
         let res = this.sender.next_op(this.i, cx, |b| {
             assert!(!b.is_closed(), "writing on a closed stream");
-            b.write(this.m.borrow(), cx)
+            let res = b.write(this.m.borrow(), cx);
+            blocked = res.is_pending();
+            res
         });
         // A successful write: wake the next in line.
         // But not while holding the lock on state.
-        if res.is_ready() {
-            this.sender.waiting.wake(this.i + 1);
+        match res {
+            Poll::Ready(occupancy) => {
+                this.sender.waiting.wake(this.i + 1);
+                this.sender.record_write(occupancy);
+                Poll::Ready(())
+            }
+            Poll::Pending => {
+                if blocked {
+                    this.sender.record_blocked_write();
+                }
+                Poll::Pending
+            }
         }
-        res
     }
 }
 
@@ -556,6 +666,35 @@ mod test {
         });
     }
 
+    #[test]
+    fn stats() {
+        run(|| async {
+            let sender = sender::<Fp31>();
+            join_all((0..6_u8).map(|i| sender.send(usize::from(i), Fp31::truncate_from(i)))).await;
+            let mut blocked = pin!(sender.send(6, Fp31::truncate_from(6_u128)));
+            assert!(poll_immediate(&mut blocked).await.is_none());
+            assert_eq!(6, sender.as_stream().next().await.unwrap().len());
+            blocked.await;
+
+            let stats = sender.stats();
+            assert_eq!(
+                (7, 1, 1, 6),
+                (
+                    stats.writes,
+                    stats.blocked_writes,
+                    stats.flushes,
+                    stats.bytes
+                )
+            );
+            assert_eq!(
+                (6, 1, 7),
+                (stats.peak_occupancy, stats.occupancy, stats.next)
+            );
+            assert!(stats.first_write <= stats.last_write);
+            assert!(stats.last_flush.is_some());
+        });
+    }
+
     /// Generate a send and close the stream.
     #[test]
     fn send_close_recv() {
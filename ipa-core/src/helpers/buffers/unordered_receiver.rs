@@ -25,12 +25,25 @@ pub struct EndOfStreamError(pub RecordId);
 #[error("Error deserializing {0:?} record: {1}")]
 pub struct DeserializeError(RecordId, BoxError);
 
+/// A read was requested for a record that is too far ahead of the read cursor, or the sender
+/// sent records that are. The sender is expected to slow down, or the receive window needs to
+/// be made larger.
+#[derive(Debug, thiserror::Error)]
+#[error("{record_id:?} is outside of the receive window of {window} records from {next:?}")]
+pub struct OutsideWindowError {
+    pub record_id: RecordId,
+    pub next: RecordId,
+    pub window: NonZeroUsize,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
     DeserializeFailed(#[from] DeserializeError),
     #[error(transparent)]
     EndOfStream(#[from] EndOfStreamError),
+    #[error(transparent)]
+    OutsideWindow(#[from] OutsideWindowError),
 }
 
 /// A future for receiving item `i` from an `UnorderedReceiver`.
@@ -58,6 +71,8 @@ where
         let mut recv = this.shared_state.lock().unwrap();
         if recv.is_next(this.i) {
             recv.poll_next(cx)
+        } else if let Some(e) = recv.check_window(this.i) {
+            Poll::Ready(Err(e.into()))
         } else {
             recv.add_waker(this.i, cx.waker());
             Poll::Pending
@@ -85,6 +100,11 @@ impl Spare {
         }
     }
 
+    /// Number of bytes that have been received, but not read yet.
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
     /// Replace the stored value with the given slice.
     fn replace(&mut self, v: &[u8]) {
         self.offset = 0;
@@ -98,11 +118,13 @@ impl Spare {
     /// received chunks don't align with messages.
     fn extend<M: Message>(&mut self, v: &[u8]) -> Option<Result<M, M::DeserializationError>> {
         let sz = <M::Size as Unsigned>::USIZE;
-        let remainder = self.buf.len() - self.offset;
+        let remainder = self.remaining();
         if remainder + v.len() < sz {
             // Not enough data: save it.
             // If we're working from the tail of a longer buffer, only retain the tail.
-            self.buf = self.buf.split_off(self.offset);
+            // This is done in place, so that no copies of received data are left behind in
+            // released allocations.
+            self.buf.drain(..self.offset);
             self.buf.extend_from_slice(v);
             self.offset = 0;
             return None;
@@ -124,6 +146,14 @@ impl Spare {
     }
 }
 
+/// Received data contains shares, so it is wiped once the receiver is gone.
+#[cfg(feature = "zeroize-secrets")]
+impl Drop for Spare {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.buf);
+    }
+}
+
 pub struct OperatingState<S, C>
 where
     S: Stream<Item = C>,
@@ -169,6 +199,10 @@ where
     overflow_wakers: Vec<(Waker, usize)>,
     #[cfg(not(feature = "stall-detection"))]
     overflow_wakers: Vec<Waker>,
+    /// If set, reads at `next + window` or beyond are rejected instead of being parked in
+    /// `overflow_wakers`, and so are chunks that carry records at or beyond that index. This
+    /// puts an upper bound on the memory used to track reads and hold data that arrive early.
+    window: Option<NonZeroUsize>,
     _marker: PhantomData<C>,
 }
 
@@ -182,6 +216,30 @@ where
         i == self.next
     }
 
+    /// Check whether `i` fits in the receive window, if there is one.
+    fn check_window(&self, i: usize) -> Option<OutsideWindowError> {
+        self.window
+            .filter(|window| i >= self.next + window.get())
+            .map(|window| OutsideWindowError {
+                record_id: RecordId::from(i),
+                next: RecordId::from(self.next),
+                window,
+            })
+    }
+
+    /// Check whether a chunk of `len` bytes fits in the receive window, together with the data
+    /// that was received before it and is not read yet.
+    fn check_incoming<M: Message>(&self, len: usize) -> Option<OutsideWindowError> {
+        let records = (self.spare.remaining() + len) / M::Size::USIZE;
+        self.window
+            .filter(|window| records > window.get())
+            .map(|window| OutsideWindowError {
+                record_id: RecordId::from(self.next + window.get()),
+                next: RecordId::from(self.next),
+                window,
+            })
+    }
+
     /// Track a waker from a future that was invoked before data was ready.
     ///
     /// # Panics
@@ -249,6 +307,9 @@ where
                 Poll::Ready(Some(b)) => {
                     let b = b.as_ref();
                     tracing::trace!(len = b.len(), "next chunk");
+                    if let Some(e) = self.check_incoming::<M>(b.len()) {
+                        return Poll::Ready(Err(e.into()));
+                    }
                     if let Some(m) = self.spare.extend(b) {
                         self.wake_next();
                         return Poll::Ready(
@@ -309,6 +370,30 @@ where
     ///
     /// The `capacity` needs to be at least 2.
     pub fn new(stream: Pin<Box<S>>, capacity: NonZeroUsize) -> Self {
+        Self::with_window(stream, capacity, None)
+    }
+
+    /// Wrap a stream for unordered reading, rejecting reads that are `window` or more
+    /// records ahead of the next record to be received with [`Error::OutsideWindow`].
+    /// Reads within the window are held until all the records before them have been
+    /// received, so they are still delivered in order. If `window` is `None`, reads
+    /// can be arbitrarily far ahead.
+    ///
+    /// Received data is held to the same limit: if the stream yields a chunk that carries
+    /// records `window` or more ahead of the next one, the read of the next record fails
+    /// with [`Error::OutsideWindow`] instead of buffering the chunk.
+    ///
+    /// The window should not be smaller than `capacity`, otherwise reads that the sender
+    /// is allowed to have in flight will be rejected.
+    ///
+    /// # Panics
+    ///
+    /// The `capacity` needs to be at least 2.
+    pub fn with_window(
+        stream: Pin<Box<S>>,
+        capacity: NonZeroUsize,
+        window: Option<NonZeroUsize>,
+    ) -> Self {
         // We use `c/2` as a divisor, so `c == 1` would be bad.
         assert!(capacity.get() > 1, "a capacity of 1 is too small");
         let wakers = vec![None; capacity.get()];
@@ -320,6 +405,7 @@ where
                 spare: Spare::default(),
                 wakers,
                 overflow_wakers: Vec::new(),
+                window,
                 _marker: PhantomData,
             })),
         }
@@ -340,6 +426,15 @@ where
         }
     }
 
+    /// Returns the number of records read from the stream so far. Records are read in order, so
+    /// this is also the index of the next record to be received.
+    ///
+    /// # Panics
+    /// If the underlying mutex is poisoned.
+    pub fn received(&self) -> usize {
+        self.inner.lock().unwrap().next
+    }
+
     #[cfg(feature = "stall-detection")]
     pub fn waiting(&self) -> Vec<usize> {
         let state = self.inner.lock().unwrap();
@@ -402,6 +497,20 @@ mod test {
         UnorderedReceiver::new(Box::pin(iter(it)), capacity)
     }
 
+    fn windowed_receiver<I, T>(it: I, window: usize) -> UnorderedReceiver<impl Stream<Item = T>, T>
+    where
+        I: IntoIterator<Item = T> + 'static,
+        I::IntoIter: Send,
+        T: AsRef<[u8]> + 'static,
+    {
+        let capacity = NonZeroUsize::new(3).unwrap();
+        UnorderedReceiver::with_window(
+            Box::pin(iter(it)),
+            capacity,
+            Some(NonZeroUsize::new(window).unwrap()),
+        )
+    }
+
     #[cfg(not(feature = "shuttle"))]
     fn run<F, Fut>(f: F)
     where
@@ -581,4 +690,83 @@ mod test {
             }
         });
     }
+
+    /// Reads that are too far ahead are rejected, but the same records can be read
+    /// once the cursor catches up.
+    #[test]
+    #[cfg(not(feature = "shuttle"))]
+    fn outside_window() {
+        use futures::FutureExt;
+
+        use crate::helpers::buffers::unordered_receiver::Error;
+
+        const DATA: &[u8] = &[1, 2, 3, 5, 7, 11];
+        let recv = windowed_receiver(DATA.chunks(2).map(<[u8]>::to_vec).collect::<Vec<_>>(), 4);
+        let Err(Error::OutsideWindow(e)) = recv.recv::<Fp31, _>(4_usize).now_or_never().unwrap()
+        else {
+            panic!("expected the read to be rejected");
+        };
+        assert_eq!(4, usize::from(e.record_id));
+        assert_eq!(0, usize::from(e.next));
+        assert_eq!(4, e.window.get());
+
+        // Inside the window, the read waits for earlier records.
+        assert!(recv.recv::<Fp31, _>(3_usize).now_or_never().is_none());
+        for (i, &v) in DATA.iter().enumerate() {
+            let f: Fp31 = recv.recv(i).now_or_never().unwrap().unwrap();
+            assert_eq!(f, Fp31::try_from(u128::from(v)).unwrap());
+        }
+    }
+
+    /// Chunks that carry records outside of the window are rejected, rather than buffered.
+    #[test]
+    #[cfg(not(feature = "shuttle"))]
+    fn incoming_outside_window() {
+        use futures::FutureExt;
+
+        use crate::helpers::buffers::unordered_receiver::Error;
+
+        const DATA: &[u8] = &[1, 2, 3, 5, 7, 11];
+        let recv = windowed_receiver(vec![DATA[..2].to_vec(), DATA[2..].to_vec()], 4);
+        for (i, &v) in DATA[..2].iter().enumerate() {
+            let f: Fp31 = recv.recv(i).now_or_never().unwrap().unwrap();
+            assert_eq!(f, Fp31::try_from(u128::from(v)).unwrap());
+        }
+        // The second chunk has records 2 to 5, which fit in the window from record 2.
+        assert!(recv
+            .recv::<Fp31, _>(2_usize)
+            .now_or_never()
+            .unwrap()
+            .is_ok());
+
+        let recv = windowed_receiver(vec![DATA.to_vec()], 4);
+        let Err(Error::OutsideWindow(e)) = recv.recv::<Fp31, _>(0_usize).now_or_never().unwrap()
+        else {
+            panic!("expected the chunk to be rejected");
+        };
+        assert_eq!(4, usize::from(e.record_id));
+        assert_eq!(0, usize::from(e.next));
+    }
+
+    /// Reads that are ahead of the cursor, but within the window, are delivered in order.
+    #[test]
+    fn within_window() {
+        const DATA: &[u8] = &[1, 2, 3, 5, 7, 11, 13, 17, 23, 29];
+        run(|| {
+            let recv = windowed_receiver(vec![DATA.to_vec()], DATA.len());
+            async move {
+                try_join_all(DATA.iter().enumerate().rev().map(|(i, &v)| {
+                    spawn({
+                        let recv = recv.clone();
+                        async move {
+                            let f: Fp31 = recv.recv(i).await.unwrap();
+                            assert_eq!(f, Fp31::try_from(u128::from(v)).unwrap());
+                        }
+                    })
+                }))
+                .await
+                .unwrap();
+            }
+        });
+    }
 }
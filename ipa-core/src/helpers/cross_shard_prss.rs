@@ -32,7 +32,7 @@ pub async fn gen_and_distribute<R: SharedRandomness, C: ShardConfiguration>(
         shard_config
             .peer_shards()
             .map(|shard| {
-                let channel = ChannelId::new(shard, gate.clone());
+                let channel = ChannelId::new(gateway.query_id(), shard, gate.clone());
                 let sender = gateway.get_shard_sender(&channel, TotalRecords::ONE);
                 let (l_seed, r_seed) = (setup.left_seed().clone(), setup.right_seed().clone());
                 async move { sender.send(RecordId::FIRST, (l_seed, r_seed)).await }
@@ -45,7 +45,7 @@ pub async fn gen_and_distribute<R: SharedRandomness, C: ShardConfiguration>(
         setup.setup()
     } else {
         // Receive seeds from the leader.
-        let channel_id = ChannelId::new(shard_config.leader(), gate.clone());
+        let channel_id = ChannelId::new(gateway.query_id(), shard_config.leader(), gate.clone());
         let (l_seed, r_seed): (_, Seed) = gateway
             .get_shard_receiver(&channel_id)
             .try_next()
@@ -1,11 +1,13 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 use crate::{
     helpers::{
-        buffers::{DeserializeError, EndOfStreamError},
+        buffers::{DeserializeError, EndOfStreamError, OutsideWindowError},
         ChannelId, TotalRecords, TransportIdentity,
     },
-    protocol::RecordId,
+    protocol::{Gate, RecordId},
 };
 
 /// An error raised by the IPA supporting infrastructure.
@@ -21,10 +23,41 @@ pub enum Error<I: TransportIdentity> {
         channel_id: ChannelId<I>,
         inner: DeserializeError,
     },
+    #[error("Receive window exceeded for {channel_id:?}: {inner}")]
+    OutsideWindow {
+        channel_id: ChannelId<I>,
+        inner: OutsideWindowError,
+    },
     #[error("record ID {record_id:?} is out of range for {channel_id:?} (expected {total_records:?} records)")]
     TooManyRecords {
         record_id: RecordId,
         channel_id: ChannelId<I>,
         total_records: TotalRecords,
     },
+    #[error("deadline set by step {step} exceeded while waiting on {channel_id:?}")]
+    StepDeadlineExceeded {
+        step: Gate,
+        channel_id: ChannelId<I>,
+    },
+    #[error("nothing received on {channel_id:?} for {timeout:?}, the peer is unavailable")]
+    PeerUnavailable {
+        channel_id: ChannelId<I>,
+        timeout: Duration,
+    },
+}
+
+impl<I: TransportIdentity> Error<I> {
+    /// The peer this error blames for not responding. Helpers can't tell a peer that went
+    /// down from one that stopped sending, so both deadlines and peer timeouts point to it.
+    #[must_use]
+    pub fn unavailable_peer(&self) -> Option<I> {
+        match self {
+            Self::StepDeadlineExceeded { channel_id, .. }
+            | Self::PeerUnavailable { channel_id, .. } => Some(channel_id.peer),
+            Self::EndOfStream { .. }
+            | Self::DeserializeFailed { .. }
+            | Self::OutsideWindow { .. }
+            | Self::TooManyRecords { .. } => None,
+        }
+    }
 }
@@ -0,0 +1,48 @@
+use std::borrow::Borrow;
+
+use futures::future::{join, try_join};
+
+use super::send::SerializedMessage;
+use crate::{
+    helpers::{Error, MpcMessage, Role, SendingEnd},
+    protocol::RecordId,
+};
+
+/// Sending ends of the channels to both MPC peers, for protocols that send the same message to
+/// each of them. Messages are serialized once and then written to both channels.
+pub struct BroadcastingEnd<M: MpcMessage> {
+    left: SendingEnd<Role, M>,
+    right: SendingEnd<Role, M>,
+}
+
+impl<M: MpcMessage> BroadcastingEnd<M> {
+    #[must_use]
+    pub fn new(left: SendingEnd<Role, M>, right: SendingEnd<Role, M>) -> Self {
+        Self { left, right }
+    }
+
+    /// Sends `msg` to both peers. Like [`SendingEnd::send`], this blocks until both channels
+    /// have accepted the message.
+    ///
+    /// ## Errors
+    /// If sending to either peer fails.
+    pub async fn broadcast<B: Borrow<M>>(
+        &self,
+        record_id: RecordId,
+        msg: B,
+    ) -> Result<(), Error<Role>> {
+        let msg = SerializedMessage::new(msg.borrow());
+        try_join(
+            self.left.send_serialized(record_id, &msg),
+            self.right.send_serialized(record_id, &msg),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Closes both channels at the specified record, see [`SendingEnd::close`].
+    pub async fn close(&self, at: RecordId) {
+        join(self.left.close(at), self.right.close(at)).await;
+    }
+}
@@ -0,0 +1,78 @@
+use std::{future::Future, time::Instant};
+
+use crate::{
+    helpers::{ChannelId, Error, TransportIdentity},
+    protocol::Gate,
+    sync::Arc,
+};
+
+/// Point in time by which all sends and receives of a step, and of the steps below it, must
+/// complete. Set with [`Context::with_deadline`].
+///
+/// [`Context::with_deadline`]: crate::protocol::context::Context::with_deadline
+#[derive(Clone, Debug)]
+pub struct StepDeadline(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    at: Instant,
+    /// The step that set this deadline.
+    step: Gate,
+}
+
+impl StepDeadline {
+    #[must_use]
+    pub fn new(at: Instant, step: Gate) -> Self {
+        Self(Arc::new(Inner { at, step }))
+    }
+
+    #[must_use]
+    pub fn at(&self) -> Instant {
+        self.0.at
+    }
+
+    #[must_use]
+    pub fn step(&self) -> &Gate {
+        &self.0.step
+    }
+
+    /// Returns the deadline that expires first. Steps below a step with a deadline can only
+    /// tighten it.
+    #[must_use]
+    pub fn earliest(this: Option<&Self>, other: Self) -> Self {
+        match this {
+            Some(this) if this.at() <= other.at() => this.clone(),
+            _ => other,
+        }
+    }
+
+    /// Completes `f`, unless this deadline expires first. `f` is dropped on expiry, which
+    /// cancels the pending send or receive on `channel_id`.
+    pub(super) async fn bound<I: TransportIdentity, T, F>(
+        deadline: Option<&Self>,
+        channel_id: &ChannelId<I>,
+        f: F,
+    ) -> Result<T, Error<I>>
+    where
+        F: Future<Output = Result<T, Error<I>>>,
+    {
+        // Shuttle does not provide timers, deadlines never expire there.
+        #[cfg(feature = "shuttle")]
+        let deadline: Option<&Self> = {
+            let _ = deadline;
+            None
+        };
+
+        let Some(deadline) = deadline else {
+            return f.await;
+        };
+
+        match ::tokio::time::timeout_at(deadline.at().into(), f).await {
+            Ok(r) => r,
+            Err(_) => Err(Error::StepDeadlineExceeded {
+                step: deadline.step().clone(),
+                channel_id: channel_id.clone(),
+            }),
+        }
+    }
+}
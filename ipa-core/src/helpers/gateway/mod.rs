@@ -1,3 +1,6 @@
+mod broadcast;
+mod deadline;
+mod priority;
 mod receive;
 mod send;
 #[cfg(feature = "stall-detection")]
@@ -6,15 +9,24 @@ mod transport;
 
 use std::{
     cmp::{max, min},
+    collections::HashMap,
     num::NonZeroUsize,
+    time::Duration,
 };
 
+pub use broadcast::BroadcastingEnd;
+pub use deadline::StepDeadline;
+pub use priority::MessagePriority;
 pub(super) use receive::{MpcReceivingEnd, ShardReceivingEnd};
 pub(super) use send::SendingEnd;
 #[cfg(feature = "stall-detection")]
 pub(super) use stall_detection::InstrumentedGateway;
 pub use transport::RoleResolvingTransport;
 
+use ipa_metrics::counter;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
 use crate::{
     helpers::{
         buffers::UnorderedReceiver,
@@ -23,13 +35,20 @@ use crate::{
             send::GatewaySenders,
             transport::Transports,
         },
-        query::QueryConfig,
-        HelperChannelId, LogErrors, Message, MpcMessage, RecordsStream, Role, RoleAssignment,
-        ShardChannelId, TotalRecords, Transport,
+        query::{PeerUnavailable, QueryConfig},
+        Direction, HelperChannelId, HelperIdentity, LogErrors, Message, MpcMessage, RecordsStream,
+        Role, RoleAssignment, ShardChannelId, TotalRecords, Transport,
     },
-    protocol::QueryId,
+    protocol::{Gate, ProtocolVersion, QueryId},
     sharding::{ShardConfiguration, ShardIndex},
     sync::{Arc, Mutex},
+    telemetry::{
+        labels::STEP,
+        metrics::SEND_BUFFERS_STUCK,
+        rounds::{RoundCounter, StageRounds},
+        send_buffers::SendBufferStatus,
+        tuning::{StageStats, TuningReport},
+    },
     utils::NonZeroU32PowerOfTwo,
 };
 
@@ -49,6 +68,11 @@ pub type MpcTransportImpl = crate::net::MpcHttpTransport;
 #[cfg(feature = "real-world-infra")]
 pub type ShardTransportImpl = crate::net::ShardHttpTransport;
 
+#[cfg(feature = "dyn-infra")]
+pub type MpcTransportImpl = super::transport::BoxedTransport<crate::helpers::HelperIdentity>;
+#[cfg(feature = "dyn-infra")]
+pub type ShardTransportImpl = super::transport::BoxedTransport<ShardIndex>;
+
 pub type MpcTransportError = <MpcTransportImpl as Transport>::Error;
 pub type ShardTransportError = <ShardTransportImpl as Transport>::Error;
 
@@ -69,6 +93,9 @@ pub struct State {
     mpc_receivers: GatewayReceivers<Role, UR>,
     shard_senders: GatewaySenders<ShardIndex>,
     shard_receivers: GatewayReceivers<ShardIndex, ShardReceiveStream>,
+    rounds: RoundCounter,
+    /// Notified when a channel may have drained, see [`Gateway::barrier`].
+    drained: Arc<Notify>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -99,6 +126,37 @@ pub struct GatewayConfig {
     /// the best way to slice this data before sending it to a peer.
     pub read_size: NonZeroUsize,
 
+    /// Send buffers that have not been flushed to the network for this long are reported as
+    /// stuck by [`Gateway::watch_send_buffers`]. This is also how often the buffers are checked.
+    pub stuck_send_buffer_age: Duration,
+
+    /// How far ahead of the next expected record a receiver can be asked to read. Reads that
+    /// are further ahead fail with [`Error::OutsideWindow`] instead of being held until the
+    /// records before them arrive. Batches from peers that carry records that far ahead fail
+    /// the same way instead of being buffered. Together this bounds the state and the data kept
+    /// for every receiving channel. If not set, there is no limit. This should not be smaller
+    /// than [`Self::active_work`].
+    ///
+    /// [`Error::OutsideWindow`]: crate::helpers::Error::OutsideWindow
+    pub receive_window: Option<NonZeroUsize>,
+
+    /// Protocol version negotiated for the query. All records this gateway sends are tagged
+    /// with it, so peers running a different version reject them.
+    pub protocol_version: ProtocolVersion,
+
+    /// Limits on the active work of individual stages of IPA. Active work of a stage is never
+    /// higher than [`Self::active`].
+    pub stage_concurrency: StageConcurrency,
+
+    /// Receives that get nothing from the peer for this long fail with
+    /// [`Error::PeerUnavailable`], so that a helper that went down does not stall the query
+    /// forever. Every receive is bounded on its own, so the timeout must be longer than any
+    /// stretch in which the peer legitimately sends nothing. If not set, receives wait for as
+    /// long as it takes.
+    ///
+    /// [`Error::PeerUnavailable`]: crate::helpers::Error::PeerUnavailable
+    pub peer_timeout: Option<Duration>,
+
     /// Time to wait before checking gateway progress. If no progress has been made between
     /// checks, the gateway is considered to be stalled and will create a report with outstanding
     /// send/receive requests
@@ -106,6 +164,73 @@ pub struct GatewayConfig {
     pub progress_check_interval: std::time::Duration,
 }
 
+/// Stages of IPA that can run with less active work than the rest of the query, see
+/// [`StageConcurrency`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConcurrencyStage {
+    /// Conversion of match keys to curve points. Every record holds [`CONV_CHUNK`] match keys.
+    ///
+    /// [`CONV_CHUNK`]: crate::protocol::ipa_prf::CONV_CHUNK
+    Conversion,
+    /// PRF evaluation. Every record holds [`PRF_CHUNK`] curve points.
+    ///
+    /// [`PRF_CHUNK`]: crate::protocol::ipa_prf::PRF_CHUNK
+    Prf,
+    /// Aggregation of the attributed values into the histogram.
+    Aggregation,
+}
+
+/// Number of records stages of IPA keep in flight, if it should be lower than the active work
+/// of the query. Stages that process wide records, like aggregation, hold much more memory per
+/// record than others, so limiting them lets memory-constrained helpers run with a high active
+/// work for the rest of the query.
+///
+/// Helpers must run every stage with the same active work, or they wait on each other forever.
+/// The leader of a query sends its limits to the other helpers with [`PrepareQuery`].
+///
+/// [`PrepareQuery`]: crate::helpers::query::PrepareQuery
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageConcurrency {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<NonZeroU32PowerOfTwo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prf: Option<NonZeroU32PowerOfTwo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<NonZeroU32PowerOfTwo>,
+}
+
+impl StageConcurrency {
+    /// Returns `true` if no stage is limited.
+    #[must_use]
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the active work of `stage` in a query that runs with `active_work`. Limits can
+    /// only lower active work, and never below 2, which is the least receivers can work with.
+    ///
+    /// ## Panics
+    /// If 2 is not a power of two.
+    #[must_use]
+    pub fn active_work(
+        &self,
+        stage: ConcurrencyStage,
+        active_work: NonZeroU32PowerOfTwo,
+    ) -> NonZeroU32PowerOfTwo {
+        let limit = match stage {
+            ConcurrencyStage::Conversion => self.conversion,
+            ConcurrencyStage::Prf => self.prf,
+            ConcurrencyStage::Aggregation => self.aggregation,
+        };
+        limit.map_or(active_work, |limit| {
+            min(
+                active_work,
+                max(limit, NonZeroU32PowerOfTwo::try_from(2).unwrap()),
+            )
+        })
+    }
+}
+
 impl ShardConfiguration for Gateway {
     fn shard_id(&self) -> ShardIndex {
         ShardConfiguration::shard_id(&self)
@@ -162,6 +287,182 @@ impl Gateway {
         &self.config
     }
 
+    /// The helper that created this query and coordinates it with the report collector.
+    #[must_use]
+    pub fn leader(&self) -> HelperIdentity {
+        self.transports.mpc.roles.identity(Role::H1)
+    }
+
+    #[must_use]
+    pub fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
+    #[must_use]
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.config.protocol_version
+    }
+
+    /// Summarizes how the send buffers of this gateway were used by every step, for a query
+    /// that took `duration` to run.
+    #[must_use]
+    pub fn tuning_report(&self, duration: Duration) -> TuningReport {
+        let mut stages = HashMap::new();
+        for (gate, stats) in self
+            .inner
+            .mpc_senders
+            .stats()
+            .into_iter()
+            .chain(self.inner.shard_senders.stats())
+        {
+            let (stage, span) = stages
+                .entry(gate)
+                .or_insert_with(|| (StageStats::default(), None));
+            stage.records += stats.writes;
+            stage.bytes += stats.bytes;
+            stage.flushes += stats.flushes;
+            stage.blocked_writes += stats.blocked_writes;
+            stage.record_size = max(stage.record_size, stats.write_size);
+            stage.capacity = max(stage.capacity, stats.capacity);
+            stage.peak_occupancy = max(stage.peak_occupancy, stats.peak_occupancy);
+            if let (Some(first), Some(last)) = (stats.first_write, stats.last_write) {
+                *span = Some(span.map_or((first, last), |(a, b)| (min(a, first), max(b, last))));
+            }
+        }
+
+        let stages = stages
+            .into_iter()
+            .map(|(gate, (stage, span))| StageStats {
+                step: gate.as_ref().to_string(),
+                duration_ms: span.map_or(0, |(first, last)| {
+                    u64::try_from((last - first).as_millis()).unwrap_or(u64::MAX)
+                }),
+                ..stage
+            })
+            .collect();
+
+        TuningReport::new(
+            duration,
+            self.config.active_work().get(),
+            self.config.read_size.get(),
+            stages,
+        )
+        .with_rounds(self.rounds())
+    }
+
+    /// Returns the number of sequential communication rounds of every top-level stage that
+    /// received data from other helpers so far.
+    #[must_use]
+    pub fn rounds(&self) -> Vec<StageRounds> {
+        self.inner.rounds.stages()
+    }
+
+    /// Returns the collector of communication rounds that contexts of this query report to.
+    #[must_use]
+    pub fn round_counter(&self) -> &RoundCounter {
+        &self.inner.rounds
+    }
+
+    /// Returns the current state of every send buffer that still has data to send.
+    #[must_use]
+    pub fn send_buffers(&self) -> Vec<SendBufferStatus> {
+        self.send_buffers_by_gate()
+            .into_iter()
+            .map(|(_, status)| status)
+            .collect()
+    }
+
+    fn send_buffers_by_gate(&self) -> Vec<(Gate, SendBufferStatus)> {
+        let mut buffers = self.inner.mpc_senders.status();
+        buffers.extend(self.inner.shard_senders.status());
+        buffers
+    }
+
+    /// Tells the remaining peer of this helper that the query can't finish, because the helper
+    /// with `unavailable` role stopped responding. Failing to tell it is only logged, the
+    /// remaining peer then fails the query on its own once it times out.
+    pub async fn notify_peer_unavailable(&self, unavailable: Role) {
+        let me = self.role();
+        let remaining = if me.peer(Direction::Left) == unavailable {
+            me.peer(Direction::Right)
+        } else {
+            me.peer(Direction::Left)
+        };
+        let req = PeerUnavailable {
+            query_id: self.query_id,
+            role: unavailable,
+        };
+        if let Err(e) = self
+            .transports
+            .mpc
+            .send(remaining, req, futures::stream::empty())
+            .await
+        {
+            tracing::warn!("failed to tell {remaining:?} that {unavailable:?} is unavailable: {e}");
+        }
+    }
+
+    /// Resolves once every MPC and shard channel created for `gate`, or for any step under it,
+    /// has sent all of its records and every MPC channel has received all the records it
+    /// expects. Channels created after this is called are also waited on.
+    ///
+    /// Records on shard channels are read in FIFO order by a single owner, so receiving them is
+    /// not tracked here.
+    pub async fn barrier(&self, gate: &Gate) {
+        let prefix = format!("{}/", gate.as_ref().trim_end_matches('/'));
+        let in_subtree = |g: &Gate| g == gate || g.as_ref().starts_with(&prefix);
+
+        loop {
+            // Must be created before the check, so a channel that drains right after it is not
+            // missed.
+            let drained = self.inner.drained.notified();
+            if self.inner.mpc_senders.is_drained(in_subtree)
+                && self.inner.shard_senders.is_drained(in_subtree)
+                && self.inner.mpc_receivers.is_drained(in_subtree)
+            {
+                break;
+            }
+            drained.await;
+        }
+    }
+
+    /// Checks the send buffers of this gateway every [`GatewayConfig::stuck_send_buffer_age`],
+    /// until the returned future is dropped. Buffers that hold data that has not been flushed
+    /// for that long are logged along with their steps and counted in [`SEND_BUFFERS_STUCK`]
+    /// metric. The state of all buffers seen by the last check is kept in `latest`.
+    ///
+    /// ## Panics
+    /// If `latest` mutex is poisoned.
+    pub async fn watch_send_buffers(&self, latest: &Mutex<Vec<SendBufferStatus>>) {
+        #[cfg(feature = "shuttle")]
+        {
+            let _ = latest;
+            futures::future::pending::<()>().await;
+        }
+
+        #[cfg(not(feature = "shuttle"))]
+        loop {
+            ::tokio::time::sleep(self.config.stuck_send_buffer_age).await;
+            let buffers = self.send_buffers_by_gate();
+            for (gate, buffer) in &buffers {
+                if buffer.is_stuck(self.config.stuck_send_buffer_age) {
+                    counter!(SEND_BUFFERS_STUCK, 1, STEP => gate);
+                    tracing::warn!(
+                        query_id = %self.query_id,
+                        step = %buffer.step,
+                        peer = %buffer.peer,
+                        fill = buffer.fill,
+                        capacity = buffer.capacity,
+                        pending_record = buffer.pending_record,
+                        idle_ms = ?buffer.idle_ms,
+                        "send buffer is stuck"
+                    );
+                }
+            }
+            *latest.lock().unwrap() = buffers.into_iter().map(|(_, status)| status).collect();
+        }
+    }
+
     /// Returns a sender suitable for sending data between MPC helpers. The data must be approved
     /// for sending by implementing [`MpcMessage`] trait.
     ///
@@ -193,8 +494,8 @@ impl Gateway {
             // we override the active work provided in config if caller
             // wants to use a different value.
             self.config.set_active_work(active_work),
-            self.query_id,
             total_records,
+            &self.inner.drained,
         );
 
         send::SendingEnd::new(channel, transport.identity())
@@ -217,29 +518,37 @@ impl Gateway {
             channel_id,
             transport,
             self.config,
-            self.query_id,
             total_records,
+            &self.inner.drained,
         );
 
         send::SendingEnd::new(channel, transport.identity())
     }
 
+    /// Returns a receiver for data sent by another MPC helper. `total_records` is the number of
+    /// records expected on this channel, [`Self::barrier`] waits for all of them to arrive.
     #[must_use]
     pub fn get_mpc_receiver<M: MpcMessage>(
         &self,
         channel_id: &HelperChannelId,
+        total_records: TotalRecords,
     ) -> receive::MpcReceivingEnd<M> {
+        self.inner.mpc_receivers.expect(channel_id, total_records);
         receive::MpcReceivingEnd::new(
             channel_id.clone(),
+            self.config.peer_timeout,
             self.inner.mpc_receivers.get_or_create(channel_id, || {
-                UnorderedReceiver::new(
+                UnorderedReceiver::with_window(
                     Box::pin(LogErrors::new(self.transports.mpc.receive(
                         channel_id.peer,
-                        (self.query_id, channel_id.gate.clone()),
+                        (channel_id.query_id, channel_id.gate.clone()),
                     ))),
                     self.config.active_work(),
+                    self.config.receive_window,
                 )
             }),
+            total_records,
+            Arc::clone(&self.inner.drained),
         )
     }
 
@@ -252,11 +561,10 @@ impl Gateway {
         let mut called_before = true;
         let rx = self.inner.shard_receivers.get_or_create(channel_id, || {
             called_before = false;
-            ShardReceiveStream(Arc::new(Mutex::new(
-                self.transports
-                    .shard
-                    .receive(channel_id.peer, (self.query_id, channel_id.gate.clone())),
-            )))
+            ShardReceiveStream(Arc::new(Mutex::new(self.transports.shard.receive(
+                channel_id.peer,
+                (channel_id.query_id, channel_id.gate.clone()),
+            ))))
         });
 
         assert!(
@@ -276,6 +584,11 @@ impl Default for GatewayConfig {
         Self {
             active: 32768.try_into().unwrap(),
             read_size: 2048.try_into().unwrap(),
+            stuck_send_buffer_age: Duration::from_secs(30),
+            receive_window: None,
+            protocol_version: ProtocolVersion::CURRENT,
+            stage_concurrency: StageConcurrency::default(),
+            peer_timeout: None,
             // In-memory tests are fast, so progress check intervals can be lower.
             // Real world scenarios currently over-report stalls because of inefficiencies inside
             // infrastructure and actual networking issues. This check is only valuable to report
@@ -338,7 +651,9 @@ impl GatewayConfig {
 mod tests {
     use std::{
         iter::{repeat, zip},
+        num::NonZeroUsize,
         sync::Arc,
+        time::Duration,
     };
 
     use futures::{
@@ -357,18 +672,19 @@ mod tests {
         helpers::{
             gateway::QueryConfig,
             query::{QuerySize, QueryType},
-            ChannelId, Direction, GatewayConfig, MpcMessage, MpcReceivingEnd, Role, SendingEnd,
-            TotalRecords,
+            ChannelId, ConcurrencyStage, Direction, Error, Gateway, GatewayConfig, HelperIdentity,
+            InMemoryMpcNetwork, InMemoryShardNetwork, MpcMessage, MpcReceivingEnd, Role,
+            RoleAssignment, SendingEnd, StageConcurrency, TotalRecords,
         },
         protocol::{
             context::{Context, ShardedContext},
-            Gate, RecordId,
+            Gate, QueryId, RecordId,
         },
         secret_sharing::{
             replicated::semi_honest::AdditiveShare, SharedValue, SharedValueArray, StdArray,
         },
         seq_join::seq_join,
-        sharding::ShardConfiguration,
+        sharding::{ShardConfiguration, ShardIndex},
         test_executor::run,
         test_fixture::{Reconstruct, Runner, TestWorld, TestWorldConfig, WithShards},
         utils::NonZeroU32PowerOfTwo,
@@ -415,6 +731,170 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn broadcast() {
+        let world = TestWorld::default();
+        let received = world
+            .semi_honest((), |ctx, ()| async move {
+                let ctx = ctx.narrow("broadcast").set_total_records(2);
+                let role = ctx.role();
+                let sender = ctx.broadcast_channel::<Fp31>();
+                for i in 0..2_u8 {
+                    let value =
+                        Fp31::truncate_from(10 * u128::from(role as u8) + 1 + u128::from(i));
+                    sender
+                        .broadcast(RecordId::from(usize::from(i)), value)
+                        .await
+                        .unwrap();
+                }
+
+                let mut received = Vec::new();
+                for direction in [Direction::Left, Direction::Right] {
+                    let recv_channel = ctx.recv_channel::<Fp31>(role.peer(direction));
+                    for i in 0..2_usize {
+                        received.push(recv_channel.receive(RecordId::from(i)).await.unwrap());
+                    }
+                }
+                received
+            })
+            .await;
+
+        let values = |roles: [u128; 2]| {
+            roles
+                .into_iter()
+                .flat_map(|role| [10 * role + 1, 10 * role + 2])
+                .map(Fp31::truncate_from)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values([2, 1]), received[0]);
+        assert_eq!(values([0, 2]), received[1]);
+        assert_eq!(values([1, 0]), received[2]);
+    }
+
+    #[tokio::test]
+    async fn rounds() {
+        let world = TestWorld::default();
+        world
+            .semi_honest((), |ctx, ()| async move {
+                let role = ctx.role();
+                let ctx = ctx.narrow("stage");
+                // Records are exchanged in parallel, but the second step waits on the first.
+                for step in ["first", "second"] {
+                    let ctx = ctx.narrow(step).set_total_records(2);
+                    let send_channel = ctx.send_channel::<Fp31>(role.peer(Direction::Right));
+                    let recv_channel = ctx.recv_channel::<Fp31>(role.peer(Direction::Left));
+                    try_join_all((0..2_usize).map(|i| {
+                        let (send_channel, recv_channel) = (&send_channel, &recv_channel);
+                        async move {
+                            send_channel.send(RecordId::from(i), Fp31::ZERO).await?;
+                            recv_channel.receive(RecordId::from(i)).await
+                        }
+                    }))
+                    .await
+                    .unwrap();
+                }
+            })
+            .await;
+
+        for role in Role::all() {
+            let rounds = world.gateway(*role).rounds();
+            assert_eq!(
+                vec![2],
+                rounds.iter().map(|stage| stage.rounds).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn receive_window() {
+        let config = TestWorldConfig {
+            gateway_config: GatewayConfig {
+                active: 2.try_into().unwrap(),
+                receive_window: NonZeroUsize::new(4),
+                ..Default::default()
+            },
+            ..TestWorldConfig::default()
+        };
+        let world = TestWorld::new_with(config);
+        let contexts = world.contexts();
+        let recv_ctx = contexts[1].narrow("receive-window").set_total_records(8);
+        let recv_channel = recv_ctx.recv_channel::<Fp31>(Role::H1);
+
+        let err = recv_channel.receive(RecordId::from(4)).await.unwrap_err();
+        assert!(
+            matches!(err, Error::OutsideWindow { ref inner, .. } if inner.window.get() == 4),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "shuttle"))]
+    async fn peer_timeout() {
+        let config = TestWorldConfig {
+            gateway_config: GatewayConfig {
+                peer_timeout: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+            ..TestWorldConfig::default()
+        };
+        let world = TestWorld::new_with(config);
+        let contexts = world.contexts();
+        // H1 never sends anything, as if it went down.
+        let recv_ctx = contexts[1].narrow("peer-timeout").set_total_records(1);
+        let recv_channel = recv_ctx.recv_channel::<Fp31>(Role::H1);
+
+        let err = recv_channel.receive(RecordId::FIRST).await.unwrap_err();
+        assert!(matches!(err, Error::PeerUnavailable { .. }), "{err:?}");
+        assert!(matches!(
+            crate::error::Error::from(err).blame_unavailable_helper(),
+            crate::error::Error::HelperUnavailable(Role::H1)
+        ));
+    }
+
+    #[tokio::test]
+    async fn barrier_waits_for_channels_to_drain() {
+        let world = TestWorld::default();
+        world
+            .semi_honest((), |ctx, ()| async move {
+                let stage = ctx.narrow("stage");
+                let exchange_ctx = stage.narrow("exchange").set_total_records(2);
+                let send_channel =
+                    exchange_ctx.send_channel::<Fp31>(ctx.role().peer(Direction::Right));
+                let recv_channel =
+                    exchange_ctx.recv_channel::<Fp31>(ctx.role().peer(Direction::Left));
+                // channels outside of the stage are not waited on, even if their names share
+                // a prefix with it.
+                let _idle = ctx
+                    .narrow("stage2")
+                    .set_total_records(1)
+                    .send_channel::<Fp31>(ctx.role().peer(Direction::Right));
+                let is_pending = || async move {
+                    tokio::time::timeout(Duration::from_millis(10), stage.barrier())
+                        .await
+                        .is_err()
+                };
+
+                assert!(is_pending().await);
+                for i in 0..2_usize {
+                    send_channel
+                        .send(RecordId::from(i), Fp31::ZERO)
+                        .await
+                        .unwrap();
+                }
+                // everything is sent, but not received yet
+                assert!(is_pending().await);
+
+                try_join(
+                    recv_channel.receive(RecordId::FIRST),
+                    recv_channel.receive(RecordId::from(1)),
+                )
+                .await
+                .unwrap();
+                stage.barrier().await;
+            })
+            .await;
+    }
+
     #[tokio::test]
     pub async fn handles_reordering() {
         let config = TestWorldConfig {
@@ -559,6 +1039,113 @@ mod tests {
         let _world = unsafe { Box::from_raw(world_ptr) };
     }
 
+    #[test]
+    fn stage_concurrency() {
+        let concurrency = StageConcurrency {
+            conversion: Some(1.try_into().unwrap()),
+            prf: None,
+            aggregation: Some(4.try_into().unwrap()),
+        };
+        let active_work = |stage, active: usize| {
+            u32::from(concurrency.active_work(stage, active.try_into().unwrap()))
+        };
+
+        assert_eq!(2, active_work(ConcurrencyStage::Conversion, 16));
+        assert_eq!(16, active_work(ConcurrencyStage::Prf, 16));
+        assert_eq!(4, active_work(ConcurrencyStage::Aggregation, 16));
+        assert_eq!(2, active_work(ConcurrencyStage::Aggregation, 2));
+    }
+
+    #[test]
+    fn send_buffers() {
+        run(|| async move {
+            let world = TestWorld::default();
+            let gateway = world.gateway(Role::H1);
+            let sender = gateway.get_mpc_sender::<BA3>(
+                &ChannelId::new(QueryId, Role::H2, Gate::default()),
+                TotalRecords::specified(2).unwrap(),
+                gateway.config().active_work_as_power_of_two(),
+            );
+            assert!(gateway.send_buffers().is_empty());
+
+            sender.send(RecordId::FIRST, BA3::ZERO).await.unwrap();
+            let buffers = gateway.send_buffers();
+            assert_eq!(1, buffers.len());
+            assert_eq!(
+                (1, 1, Some(2)),
+                (
+                    buffers[0].fill,
+                    buffers[0].pending_record,
+                    buffers[0].total_records
+                )
+            );
+            assert!(buffers[0].idle_ms.is_some());
+
+            sender.send(RecordId::from(1), BA3::ZERO).await.unwrap();
+            let recv = world.gateway(Role::H2).get_mpc_receiver::<BA3>(
+                &ChannelId::new(QueryId, Role::H1, Gate::default()),
+                TotalRecords::specified(2).unwrap(),
+            );
+            try_join(
+                recv.receive(RecordId::FIRST),
+                recv.receive(RecordId::from(1)),
+            )
+            .await
+            .unwrap();
+        });
+    }
+
+    /// Two queries running on the same transports use identical steps, but must not see each
+    /// other's messages.
+    #[test]
+    fn concurrent_queries() {
+        run(|| async move {
+            let network = InMemoryMpcNetwork::default();
+            let shard_network = InMemoryShardNetwork::with_shards(1);
+            let gateways = |query_id: QueryId| {
+                HelperIdentity::make_three().map(|id| {
+                    Gateway::new(
+                        query_id,
+                        GatewayConfig::default(),
+                        RoleAssignment::new(HelperIdentity::make_three()),
+                        network.transport(id),
+                        shard_network.transport(id, ShardIndex::FIRST),
+                    )
+                })
+            };
+            let queries = [QueryId::new(1), QueryId::new(2)];
+            let [first, second] = queries.map(gateways);
+
+            let exchange = |gateways: &[Gateway; 3], query_id: QueryId, value: u128| {
+                let sender = gateways[0].get_mpc_sender::<BA8>(
+                    &ChannelId::new(query_id, Role::H2, Gate::default()),
+                    TotalRecords::ONE,
+                    gateways[0].config().active_work_as_power_of_two(),
+                );
+                let receiver = gateways[1].get_mpc_receiver::<BA8>(
+                    &ChannelId::new(query_id, Role::H1, Gate::default()),
+                    TotalRecords::ONE,
+                );
+                async move {
+                    let ((), received) = try_join(
+                        sender.send(RecordId::FIRST, BA8::truncate_from(value)),
+                        receiver.receive(RecordId::FIRST),
+                    )
+                    .await
+                    .unwrap();
+                    received.as_u128()
+                }
+            };
+
+            let received = join(
+                exchange(&first, queries[0], 1),
+                exchange(&second, queries[1], 2),
+            )
+            .await;
+            assert_eq!((1, 2), received);
+        });
+    }
+
     #[test]
     fn shards() {
         run(|| async move {
@@ -569,7 +1156,7 @@ mod tests {
 
     #[test]
     #[should_panic(
-        expected = "Shard receiver channel[ShardIndex(1),\"protocol/iter000\"] can only be created once"
+        expected = "Shard receiver channel[0,ShardIndex(1),\"protocol/iter000\"] can only be created once"
     )]
     fn shards_receive_twice() {
         run(|| async move {
@@ -605,7 +1192,7 @@ mod tests {
                         .active_work_as_power_of_two()
             );
             let sender = world.gateway(Role::H1).get_mpc_sender::<BA3>(
-                &ChannelId::new(Role::H2, Gate::default()),
+                &ChannelId::new(QueryId, Role::H2, Gate::default()),
                 TotalRecords::specified(15).unwrap(),
                 new_active_work,
             );
@@ -615,10 +1202,14 @@ mod tests {
             )
             .await
             .unwrap();
-            let recv = world.gateway(Role::H2).get_mpc_receiver::<BA3>(&ChannelId {
-                peer: Role::H1,
-                gate: Gate::default(),
-            });
+            let recv = world.gateway(Role::H2).get_mpc_receiver::<BA3>(
+                &ChannelId {
+                    query_id: QueryId,
+                    peer: Role::H1,
+                    gate: Gate::default(),
+                },
+                TotalRecords::specified(15).unwrap(),
+            );
             // this will hang if the original active work is used
             try_join_all(
                 (0..new_active_work.get()).map(|record_id| recv.receive(record_id.into())),
@@ -762,13 +1353,14 @@ mod tests {
         ) -> (SendingEnd<Role, M>, MpcReceivingEnd<M>) {
             (
                 world.gateway(left).get_mpc_sender::<M>(
-                    &ChannelId::new(right, Gate::default()),
+                    &ChannelId::new(QueryId, right, Gate::default()),
                     TotalRecords::specified(total_records).unwrap(),
                     active_work.try_into().unwrap(),
                 ),
-                world
-                    .gateway(right)
-                    .get_mpc_receiver::<M>(&ChannelId::new(left, Gate::default())),
+                world.gateway(right).get_mpc_receiver::<M>(
+                    &ChannelId::new(QueryId, left, Gate::default()),
+                    TotalRecords::specified(total_records).unwrap(),
+                ),
             )
         }
 
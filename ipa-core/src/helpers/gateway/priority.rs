@@ -0,0 +1,112 @@
+use crate::protocol::Gate;
+
+/// Steps whose messages are on the critical path of the protocol: reveals and the checks that
+/// validate malicious security. Their messages are small compared to the record traffic of the
+/// steps that run alongside them, but everything after them waits for them.
+///
+/// These are the names that the step enums of the protocols give to those steps.
+const CONTROL_STEPS: &[&str] = &[
+    // `QuicksortPassStep::Reveal` and `AggregationStep::Reveal`
+    "reveal",
+    // `AggregationStep::PruneReveal`
+    "prune_reveal",
+    // `PrfStep::RevealR`, `ValidateStep::RevealR` and `CheckZeroStep::RevealR`
+    "reveal_r",
+    // `PrfStep::Revealz`
+    "revealz",
+    // `Fp25519ConversionStep::RevealY`
+    "reveal_y",
+    // `VerifyShuffleStep::RevealMACKey`
+    "reveal_mac_key",
+    // `ValidateStep`
+    "propagate_u_and_w",
+    "check_zero",
+    // `DzkpValidationProtocolStep`
+    "generate_proof",
+    "challenge",
+    "verify_proof",
+];
+
+/// Priority class of a channel. Data of [`Control`] channels is handed over to the transport
+/// before the data of [`Bulk`] channels.
+///
+/// [`Control`]: Self::Control
+/// [`Bulk`]: Self::Bulk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessagePriority {
+    /// Record traffic.
+    Bulk,
+    /// Reveals and validation checks.
+    Control,
+}
+
+impl MessagePriority {
+    /// Classifies the channel for `gate`. A channel carries control messages if any of the
+    /// steps of its gate is a reveal or a validation step.
+    #[must_use]
+    pub fn of(gate: &Gate) -> Self {
+        if is_control(gate.as_ref()) {
+            Self::Control
+        } else {
+            Self::Bulk
+        }
+    }
+}
+
+fn is_control(path: &str) -> bool {
+    path.split('/').any(|step| CONTROL_STEPS.contains(&step))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use ipa_step::StepNarrow;
+
+    use super::MessagePriority;
+    use crate::protocol::{
+        context::step::{
+            DzkpProofVerifyStep, DzkpValidationProtocolStep, MaliciousProtocolStep, ValidateStep,
+        },
+        ipa_prf::step::{IpaPrfStep, PrfStep, QuicksortPassStep, QuicksortStep},
+        step::ProtocolStep,
+        Gate,
+    };
+
+    #[test]
+    fn classify() {
+        let ipa = Gate::default().narrow(&ProtocolStep::IpaPrf);
+        let prf = ipa.narrow(&IpaPrfStep::EvalPrf);
+        let sort = ipa.narrow(&IpaPrfStep::SortByTimestamp);
+        let pass = sort.narrow(&QuicksortStep::QuicksortPass(1));
+
+        for (gate, priority) in [
+            (Gate::default(), MessagePriority::Bulk),
+            (ipa.narrow(&IpaPrfStep::Shuffle), MessagePriority::Bulk),
+            (
+                pass.narrow(&QuicksortPassStep::Compare),
+                MessagePriority::Bulk,
+            ),
+            (
+                pass.narrow(&QuicksortPassStep::Reveal),
+                MessagePriority::Control,
+            ),
+            (
+                prf.narrow(&MaliciousProtocolStep::MaliciousProtocol)
+                    .narrow(&PrfStep::Revealz),
+                MessagePriority::Control,
+            ),
+            (
+                prf.narrow(&MaliciousProtocolStep::Validate)
+                    .narrow(&ValidateStep::PropagateUAndW),
+                MessagePriority::Control,
+            ),
+            (
+                sort.narrow(&QuicksortStep::QuicksortPassValidate(1))
+                    .narrow(&DzkpValidationProtocolStep::VerifyProof)
+                    .narrow(&DzkpProofVerifyStep::PTimesQ),
+                MessagePriority::Control,
+            ),
+        ] {
+            assert_eq!(priority, MessagePriority::of(&gate), "{}", gate.as_ref());
+        }
+    }
+}
@@ -1,25 +1,29 @@
 use std::{
+    future::Future,
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use bytes::Bytes;
 use dashmap::{mapref::entry::Entry, DashMap};
 use futures::Stream;
 use pin_project::pin_project;
+use tokio::sync::Notify;
 
 use crate::{
     error::BoxError,
     helpers::{
         buffers::{UnorderedReceiver, UnorderedReceiverError},
-        gateway::transport::RoleResolvingTransport,
+        gateway::{transport::RoleResolvingTransport, StepDeadline},
         transport::SingleRecordStream,
         ChannelId, Error, HelperChannelId, LogErrors, Message, MpcMessage, Role, ShardChannelId,
-        ShardTransportImpl, Transport, TransportIdentity,
+        ShardTransportImpl, TotalRecords, Transport, TransportIdentity,
     },
-    protocol::RecordId,
+    protocol::{Gate, RecordId},
     sync::{Arc, Mutex},
+    telemetry::rounds::Depth,
 };
 
 /// Receiving end of the MPC gateway channel.
@@ -31,6 +35,12 @@ use crate::{
 pub struct MpcReceivingEnd<M> {
     channel_id: HelperChannelId,
     unordered_rx: UR,
+    deadline: Option<StepDeadline>,
+    peer_timeout: Option<Duration>,
+    depth: Depth,
+    total_records: TotalRecords,
+    /// Notified once all `total_records` have been received.
+    drained: Arc<Notify>,
     _phantom: PhantomData<fn() -> M>,
 }
 
@@ -44,6 +54,8 @@ pub struct ShardReceivingEnd<M: Message> {
 /// Receiving channels, indexed by (role, step).
 pub(super) struct GatewayReceivers<I, S> {
     pub(super) inner: DashMap<ChannelId<I>, S>,
+    /// Number of records expected on each channel, for those channels that declared it.
+    expected: DashMap<ChannelId<I>, usize>,
 }
 
 pub type UR = UnorderedReceiver<
@@ -62,41 +74,114 @@ pub struct ShardReceiveStream(
 );
 
 impl<M: MpcMessage> MpcReceivingEnd<M> {
-    pub(super) fn new(channel_id: HelperChannelId, rx: UR) -> Self {
+    pub(super) fn new(
+        channel_id: HelperChannelId,
+        peer_timeout: Option<Duration>,
+        rx: UR,
+        total_records: TotalRecords,
+        drained: Arc<Notify>,
+    ) -> Self {
         Self {
             channel_id,
             unordered_rx: rx,
+            deadline: None,
+            peer_timeout,
+            depth: Depth::default(),
+            total_records,
+            drained,
             _phantom: PhantomData,
         }
     }
 
+    /// Bounds the receives through this end by `deadline`.
+    #[must_use]
+    pub fn with_deadline(self, deadline: Option<StepDeadline>) -> Self {
+        Self { deadline, ..self }
+    }
+
+    /// Counts the receives through this end as communication rounds of `depth`.
+    #[must_use]
+    pub fn with_depth(self, depth: Depth) -> Self {
+        Self { depth, ..self }
+    }
+
     /// Receive message associated with the given record id. This method does not return until
     /// message is actually received and deserialized.
     ///
     /// ## Errors
-    /// Returns an error if receiving fails
+    /// Returns an error if receiving fails, the deadline of the step expires before the
+    /// message arrives or the peer does not send it within the peer timeout.
     ///
     /// ## Panics
     /// This will panic if message size does not fit into 8 bytes and it somehow got serialized
     /// and sent to this helper.
     #[tracing::instrument(level = "trace", "receive", skip_all, fields(i = %record_id, from = ?self.channel_id.peer, gate = ?self.channel_id.gate.as_ref()))]
     pub async fn receive(&self, record_id: RecordId) -> Result<M, Error<Role>> {
-        self.unordered_rx
-            .recv::<M, _>(record_id)
-            .await
-            .map_err(|e| match e {
-                UnorderedReceiverError::DeserializeFailed(inner) => Error::DeserializeFailed {
-                    channel_id: self.channel_id.clone(),
-                    inner,
-                },
-                UnorderedReceiverError::EndOfStream(inner) => Error::EndOfStream {
-                    channel_id: self.channel_id.clone(),
-                    inner,
-                },
-            })
+        let start = self.depth.current();
+        let recv = async {
+            self.unordered_rx
+                .recv::<M, _>(record_id)
+                .await
+                .map_err(|e| match e {
+                    UnorderedReceiverError::DeserializeFailed(inner) => Error::DeserializeFailed {
+                        channel_id: self.channel_id.clone(),
+                        inner,
+                    },
+                    UnorderedReceiverError::EndOfStream(inner) => Error::EndOfStream {
+                        channel_id: self.channel_id.clone(),
+                        inner,
+                    },
+                    UnorderedReceiverError::OutsideWindow(inner) => Error::OutsideWindow {
+                        channel_id: self.channel_id.clone(),
+                        inner,
+                    },
+                })
+        };
+
+        let recv = StepDeadline::bound(self.deadline.as_ref(), &self.channel_id, recv);
+        let msg = bound_by_peer_timeout(self.peer_timeout, &self.channel_id, recv).await?;
+        self.depth.received(&self.channel_id.gate, start);
+        if self
+            .total_records
+            .count()
+            .is_some_and(|count| self.unordered_rx.received() >= count)
+        {
+            self.drained.notify_waiters();
+        }
+        Ok(msg)
     }
 }
 
+/// Completes `f`, unless the peer on `channel_id` does not let it finish within `timeout`.
+async fn bound_by_peer_timeout<T, F>(
+    timeout: Option<Duration>,
+    channel_id: &HelperChannelId,
+    f: F,
+) -> Result<T, Error<Role>>
+where
+    F: Future<Output = Result<T, Error<Role>>>,
+{
+    // Shuttle does not provide timers, peers never time out there.
+    #[cfg(feature = "shuttle")]
+    let timeout: Option<Duration> = {
+        let _ = timeout;
+        None
+    };
+
+    let Some(timeout) = timeout else {
+        return f.await;
+    };
+
+    ::tokio::time::timeout(timeout, f)
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::PeerUnavailable {
+                channel_id: channel_id.clone(),
+                timeout,
+            })
+        })
+}
+
 impl<M: Message> Stream for ShardReceivingEnd<M> {
     type Item = Result<M, crate::error::Error>;
 
@@ -109,6 +194,7 @@ impl<I: TransportIdentity, S> Default for GatewayReceivers<I, S> {
     fn default() -> Self {
         Self {
             inner: DashMap::default(),
+            expected: DashMap::default(),
         }
     }
 }
@@ -128,6 +214,29 @@ impl<I: TransportIdentity, S: Clone> GatewayReceivers<I, S> {
     }
 }
 
+impl GatewayReceivers<Role, UR> {
+    /// Records that `total_records` are expected to arrive on `channel_id`. Channels that don't
+    /// know how many records they will get are never waited on by [`Self::is_drained`].
+    pub fn expect(&self, channel_id: &HelperChannelId, total_records: TotalRecords) {
+        if let Some(count) = total_records.count() {
+            self.expected.insert(channel_id.clone(), count);
+        }
+    }
+
+    /// Returns `true` if every channel whose gate matches `filter` has received all the
+    /// records expected on it.
+    pub fn is_drained<F: Fn(&Gate) -> bool>(&self, filter: F) -> bool {
+        self.inner
+            .iter()
+            .filter(|entry| filter(&entry.key().gate))
+            .all(|entry| {
+                self.expected
+                    .get(entry.key())
+                    .is_none_or(|count| entry.value().received() >= *count)
+            })
+    }
+}
+
 impl Stream for ShardReceiveStream {
     type Item = <<ShardTransportImpl as Transport>::RecordsStream as Stream>::Item;
 
@@ -1,29 +1,37 @@
 use std::{
     borrow::Borrow,
+    convert::Infallible,
     fmt::Debug,
     marker::PhantomData,
     num::NonZeroUsize,
     pin::Pin,
     task::{Context, Poll},
+    time::Instant,
 };
 
+use ::tokio::sync::Notify;
 use dashmap::{mapref::entry::Entry, DashMap};
 use futures::Stream;
+use generic_array::GenericArray;
 use ipa_metrics::counter;
 #[cfg(all(test, feature = "shuttle"))]
 use shuttle::future as tokio;
 use typenum::Unsigned;
 
 use crate::{
+    ff::Serializable,
     helpers::{
-        buffers::OrderingSender, routing::RouteId, ChannelId, Error, GatewayConfig, Message,
-        TotalRecords, Transport, TransportIdentity,
+        buffers::{OrderingSender, SendStats},
+        gateway::{priority::MessagePriority, StepDeadline},
+        routing::RouteId,
+        ChannelId, Error, GatewayConfig, Message, TotalRecords, Transport, TransportIdentity,
     },
-    protocol::{QueryId, RecordId},
-    sync::Arc,
+    protocol::{Gate, RecordId},
+    sync::{Arc, Mutex, Weak},
     telemetry::{
         labels::{ROLE, STEP},
-        metrics::{BYTES_SENT, RECORDS_SENT},
+        metrics::{BYTES_SENT, RECORDS_SENT, SEND_BUFFER_FLUSHES, SEND_PREEMPTIONS},
+        send_buffers::SendBufferStatus,
     },
     utils::non_zero_prev_power_of_two,
 };
@@ -32,13 +40,51 @@ use crate::{
 pub struct SendingEnd<I: TransportIdentity, M> {
     sender_id: I,
     inner: Arc<GatewaySender<I>>,
+    deadline: Option<StepDeadline>,
     /// This makes this struct [`Send`] even if [`M`] is not [`Sync`].
     _phantom: PhantomData<fn() -> M>,
 }
 
+/// A message that was serialized once, so that it can be sent to several peers without
+/// serializing it again for each of them.
+#[derive(Debug)]
+pub struct SerializedMessage<M: Message> {
+    bytes: GenericArray<u8, M::Size>,
+    _phantom: PhantomData<fn() -> M>,
+}
+
+impl<M: Message> SerializedMessage<M> {
+    #[must_use]
+    pub fn new(msg: &M) -> Self {
+        let mut bytes = GenericArray::default();
+        msg.serialize(&mut bytes);
+        Self {
+            bytes,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<M: Message> Serializable for SerializedMessage<M> {
+    type Size = M::Size;
+    type DeserializationError = Infallible;
+
+    fn serialize(&self, buf: &mut GenericArray<u8, Self::Size>) {
+        buf.copy_from_slice(&self.bytes);
+    }
+
+    fn deserialize(buf: &GenericArray<u8, Self::Size>) -> Result<Self, Self::DeserializationError> {
+        Ok(Self {
+            bytes: buf.clone(),
+            _phantom: PhantomData,
+        })
+    }
+}
+
 /// Sending channels, indexed by identity and gate.
 pub(super) struct GatewaySenders<I> {
     pub(super) inner: DashMap<ChannelId<I>, Arc<GatewaySender<I>>>,
+    scheduler: Arc<SendScheduler<I>>,
 }
 
 pub(super) struct GatewaySender<I> {
@@ -49,6 +95,25 @@ pub(super) struct GatewaySender<I> {
 
 struct GatewaySendStream<I> {
     inner: Arc<GatewaySender<I>>,
+    priority: MessagePriority,
+    scheduler: Arc<SendScheduler<I>>,
+    /// Notified once the channel is closed and hands its remaining data to the transport.
+    drained: Arc<Notify>,
+    /// Set when this stream stepped aside for control channels, so it takes the next chunk
+    /// when it is polled again.
+    yielded: bool,
+}
+
+/// Keeps track of the control channels of a gateway, so that bulk channels can step aside
+/// while control channels have data ready to be sent.
+///
+/// Bulk channels are never blocked on control channels: a control channel may have data
+/// that the transport can't take yet, because the peer is not reading it, while the peer
+/// waits for bulk data from this helper. Instead, a bulk channel yields once before taking
+/// each chunk while control data is ready, which lets the transport pick up the control data
+/// first.
+struct SendScheduler<I> {
+    control: Mutex<Vec<Weak<GatewaySender<I>>>>,
 }
 
 /// Configuration for each [`GatewaySender`]. All values stored here
@@ -72,10 +137,35 @@ impl<I: TransportIdentity> Default for GatewaySenders<I> {
     fn default() -> Self {
         Self {
             inner: DashMap::default(),
+            scheduler: Arc::new(SendScheduler {
+                control: Mutex::new(Vec::new()),
+            }),
         }
     }
 }
 
+impl<I> SendScheduler<I> {
+    fn register_control(&self, sender: &Arc<GatewaySender<I>>) {
+        self.control.lock().unwrap().push(Arc::downgrade(sender));
+    }
+
+    /// Returns `true` if a control channel has data ready to be sent. Forgets the control
+    /// channels that are done.
+    fn control_ready(&self) -> bool {
+        let mut ready = false;
+        self.control.lock().unwrap().retain(|sender| {
+            let Some(sender) = sender.upgrade() else {
+                return false;
+            };
+            let has_data = sender.ordering_tx.has_data();
+            ready |= has_data;
+            has_data || !sender.ordering_tx.is_closed()
+        });
+
+        ready
+    }
+}
+
 impl<I: TransportIdentity> GatewaySender<I> {
     fn new(channel_id: ChannelId<I>, tx: OrderingSender, total_records: TotalRecords) -> Self {
         Self {
@@ -129,6 +219,31 @@ impl<I: TransportIdentity> GatewaySender<I> {
         self.ordering_tx.is_closed()
     }
 
+    pub fn stats(&self) -> SendStats {
+        self.ordering_tx.stats()
+    }
+
+    /// Returns the current state of the send buffer, or `None` if this channel is closed and
+    /// everything it had has been sent.
+    pub fn status(&self, now: Instant) -> Option<SendBufferStatus> {
+        let stats = self.stats();
+        if self.is_closed() && stats.occupancy == 0 {
+            return None;
+        }
+
+        Some(SendBufferStatus {
+            step: self.channel_id.gate.as_ref().to_string(),
+            peer: format!("{:?}", self.channel_id.peer),
+            fill: stats.occupancy,
+            capacity: stats.capacity,
+            pending_record: stats.next,
+            total_records: self.total_records.count(),
+            idle_ms: stats.last_flush.or(stats.first_write).map(|since| {
+                u64::try_from(now.saturating_duration_since(since).as_millis()).unwrap_or(u64::MAX)
+            }),
+        })
+    }
+
     pub async fn close(&self, at: RecordId) {
         self.ordering_tx.close(at.into()).await;
     }
@@ -139,17 +254,24 @@ impl<I: TransportIdentity, M: Message> SendingEnd<I, M> {
         Self {
             sender_id: id,
             inner: sender,
+            deadline: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Bounds the sends through this end by `deadline`.
+    #[must_use]
+    pub fn with_deadline(self, deadline: Option<StepDeadline>) -> Self {
+        Self { deadline, ..self }
+    }
+
     /// Sends the given message to the recipient. This method will block if there is no enough
     /// capacity to hold the message and will return only after message has been confirmed
     /// for sending.
     ///
     /// ## Errors
-    /// If send operation fails or `record_id` exceeds the channel limit set by [`set_total_records`]
-    /// call.
+    /// If send operation fails, `record_id` exceeds the channel limit set by [`set_total_records`]
+    /// call, or the deadline of the step expires before the message is accepted.
     ///
     /// [`set_total_records`]: crate::protocol::context::Context::set_total_records
     #[tracing::instrument(level = "trace", "send", skip_all, fields(
@@ -159,7 +281,34 @@ impl<I: TransportIdentity, M: Message> SendingEnd<I, M> {
         gate = ?self.inner.channel_id.gate.as_ref()
     ))]
     pub async fn send<B: Borrow<M>>(&self, record_id: RecordId, msg: B) -> Result<(), Error<I>> {
-        let r = self.inner.send(record_id, msg).await;
+        self.send_as::<M, B>(record_id, msg).await
+    }
+
+    /// Same as [`Self::send`], for a message that was serialized already.
+    ///
+    /// ## Errors
+    /// See [`Self::send`].
+    pub async fn send_serialized(
+        &self,
+        record_id: RecordId,
+        msg: &SerializedMessage<M>,
+    ) -> Result<(), Error<I>> {
+        self.send_as::<SerializedMessage<M>, _>(record_id, msg)
+            .await
+    }
+
+    /// Sends `msg` through this channel. `N` must be serialized the same way as `M`.
+    async fn send_as<N, B>(&self, record_id: RecordId, msg: B) -> Result<(), Error<I>>
+    where
+        N: Message<Size = M::Size>,
+        B: Borrow<N>,
+    {
+        let r = StepDeadline::bound(
+            self.deadline.as_ref(),
+            &self.inner.channel_id,
+            self.inner.send::<N, B>(record_id, msg),
+        )
+        .await;
         counter!(RECORDS_SENT, 1,
             STEP => &self.inner.channel_id.gate,
             ROLE => &self.sender_id
@@ -187,14 +336,15 @@ impl<I: TransportIdentity, M: Message> SendingEnd<I, M> {
 
 impl<I: TransportIdentity> GatewaySenders<I> {
     /// Returns a communication channel for the given [`ChannelId`]. If it does not exist, it will
-    /// be created using the provided [`Transport`] implementation.
+    /// be created using the provided [`Transport`] implementation. `drained` is notified once
+    /// the channel is closed and has handed all of its data to the transport.
     pub fn get<M: Message, T: Transport<Identity = I>>(
         &self,
         channel_id: &ChannelId<I>,
         transport: &T,
         config: GatewayConfig,
-        query_id: QueryId,
         total_records: TotalRecords, // TODO track children for indeterminate senders
+        drained: &Arc<Notify>,
     ) -> Arc<GatewaySender<I>> {
         assert!(
             total_records.is_specified(),
@@ -205,21 +355,38 @@ impl<I: TransportIdentity> GatewaySenders<I> {
         match self.inner.entry(channel_id.clone()) {
             Entry::Occupied(entry) => Arc::clone(entry.get()),
             Entry::Vacant(entry) => {
+                let protocol_version = config.protocol_version;
                 let config = SendChannelConfig::new::<M>(config, total_records);
                 tracing::trace!("send configuration for {channel_id:?}: {config:?}");
                 let sender = Self::new_sender(&config, channel_id.clone());
+                let priority = MessagePriority::of(&channel_id.gate);
+                if priority == MessagePriority::Control {
+                    self.scheduler.register_control(&sender);
+                }
                 entry.insert(Arc::clone(&sender));
 
                 tokio::spawn({
-                    let ChannelId { peer, gate } = channel_id.clone();
+                    let ChannelId {
+                        query_id,
+                        peer,
+                        gate,
+                    } = channel_id.clone();
                     let transport = transport.clone();
                     let stream = GatewaySendStream {
                         inner: Arc::clone(&sender),
+                        priority,
+                        scheduler: Arc::clone(&self.scheduler),
+                        drained: Arc::clone(drained),
+                        yielded: false,
                     };
                     async move {
                         // TODO(651): In the HTTP case we probably need more robust error handling here.
                         transport
-                            .send(peer, (RouteId::Records, query_id, gate), stream)
+                            .send(
+                                peer,
+                                (RouteId::Records, query_id, protocol_version, gate),
+                                stream,
+                            )
                             .await
                             .expect("{channel_id:?} receiving end should be accepted by transport");
                     }
@@ -230,6 +397,35 @@ impl<I: TransportIdentity> GatewaySenders<I> {
         }
     }
 
+    /// Returns the current state of every channel that still has data to send.
+    pub fn status(&self) -> Vec<(Gate, SendBufferStatus)> {
+        let now = Instant::now();
+        self.inner
+            .iter()
+            .filter_map(|entry| {
+                let status = entry.value().status(now)?;
+                Some((entry.key().gate.clone(), status))
+            })
+            .collect()
+    }
+
+    /// Returns `true` if every channel whose gate matches `filter` is closed and has handed all
+    /// of its data to the transport.
+    pub fn is_drained<F: Fn(&Gate) -> bool>(&self, filter: F) -> bool {
+        self.inner
+            .iter()
+            .filter(|entry| filter(&entry.key().gate))
+            .all(|entry| entry.value().is_closed() && entry.value().stats().occupancy == 0)
+    }
+
+    /// Returns send buffer measurements for every channel created so far.
+    pub fn stats(&self) -> Vec<(Gate, SendStats)> {
+        self.inner
+            .iter()
+            .map(|entry| (entry.key().gate.clone(), entry.value().stats()))
+            .collect()
+    }
+
     fn new_sender(config: &SendChannelConfig, channel_id: ChannelId<I>) -> Arc<GatewaySender<I>> {
         Arc::new(GatewaySender::new(
             channel_id,
@@ -244,7 +440,29 @@ impl<I: Debug> Stream for GatewaySendStream<I> {
 
     #[tracing::instrument(level = "trace", name = "send_stream", skip_all, fields(to = ?self.inner.channel_id.peer, gate = ?self.inner.channel_id.gate))]
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::get_mut(self).inner.ordering_tx.take_next(cx)
+        let this = Pin::get_mut(self);
+        let inner = &this.inner;
+        if this.priority == MessagePriority::Bulk
+            && !this.yielded
+            && inner.ordering_tx.has_data()
+            && this.scheduler.control_ready()
+        {
+            counter!(SEND_PREEMPTIONS, 1, STEP => &inner.channel_id.gate);
+            this.yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        let next = inner.ordering_tx.take_next(cx);
+        if let Poll::Ready(Some(_)) = next {
+            counter!(SEND_BUFFER_FLUSHES, 1, STEP => &inner.channel_id.gate);
+            this.yielded = false;
+        }
+        if next.is_ready() && inner.is_closed() {
+            this.drained.notify_waiters();
+        }
+
+        next
     }
 }
 
@@ -68,18 +68,26 @@ impl<T: ObserveState> Observed<T> {
 
 mod gateway {
 
+    use std::time::Duration;
+
     use delegate::delegate;
 
     use super::{receive, send, AtomicUsize, Debug, Formatter, ObserveState, Observed, Weak};
     use crate::{
         helpers::{
             gateway::{Gateway, ShardTransportImpl, State},
-            GatewayConfig, HelperChannelId, Message, MpcMessage, MpcReceivingEnd, MpcTransportImpl,
-            Role, RoleAssignment, SendingEnd, ShardChannelId, ShardReceivingEnd, TotalRecords,
+            GatewayConfig, HelperChannelId, HelperIdentity, Message, MpcMessage, MpcReceivingEnd,
+            MpcTransportImpl, Role, RoleAssignment, SendingEnd, ShardChannelId, ShardReceivingEnd,
+            TotalRecords,
         },
-        protocol::QueryId,
+        protocol::{Gate, ProtocolVersion, QueryId},
         sharding::{ShardConfiguration, ShardIndex},
-        sync::Arc,
+        sync::{Arc, Mutex},
+        telemetry::{
+            rounds::{RoundCounter, StageRounds},
+            send_buffers::SendBufferStatus,
+            tuning::TuningReport,
+        },
         utils::NonZeroU32PowerOfTwo,
     };
 
@@ -99,9 +107,41 @@ mod gateway {
 
                 #[inline]
                 pub fn config(&self) -> &GatewayConfig;
+
+                #[inline]
+                pub fn leader(&self) -> HelperIdentity;
+
+                #[inline]
+                pub fn query_id(&self) -> QueryId;
+
+                #[inline]
+                pub fn protocol_version(&self) -> ProtocolVersion;
+
+                pub fn tuning_report(&self, duration: Duration) -> TuningReport;
+
+                pub fn rounds(&self) -> Vec<StageRounds>;
+
+                pub fn round_counter(&self) -> &RoundCounter;
+
+                pub fn send_buffers(&self) -> Vec<SendBufferStatus>;
             }
         }
 
+        pub async fn watch_send_buffers(&self, latest: &Mutex<Vec<SendBufferStatus>>) {
+            self.inner().gateway.watch_send_buffers(latest).await;
+        }
+
+        pub async fn barrier(&self, gate: &Gate) {
+            self.inner().gateway.barrier(gate).await;
+        }
+
+        pub async fn notify_peer_unavailable(&self, unavailable: Role) {
+            self.inner()
+                .gateway
+                .notify_peer_unavailable(unavailable)
+                .await;
+        }
+
         #[allow(clippy::let_and_return)]
         pub fn new(
             query_id: QueryId,
@@ -181,10 +221,13 @@ mod gateway {
         pub fn get_mpc_receiver<M: MpcMessage>(
             &self,
             channel_id: &HelperChannelId,
+            total_records: TotalRecords,
         ) -> MpcReceivingEnd<M> {
             Observed::wrap(
                 Weak::clone(self.get_sn()),
-                self.inner().gateway.get_mpc_receiver(channel_id),
+                self.inner()
+                    .gateway
+                    .get_mpc_receiver(channel_id, total_records),
             )
         }
 
@@ -288,7 +331,7 @@ mod receive {
             error::Error,
             gateway::{
                 receive::{GatewayReceivers, ShardReceiveStream, ShardReceivingEnd, UR},
-                MpcReceivingEnd,
+                MpcReceivingEnd, StepDeadline,
             },
             ChannelId, Message, MpcMessage, Role, TransportIdentity,
         },
@@ -303,6 +346,11 @@ mod receive {
                 pub async fn receive(&self, record_id: RecordId) -> Result<M, Error<Role>>;
             }
         }
+
+        #[must_use]
+        pub fn with_deadline(self, deadline: Option<StepDeadline>) -> Self {
+            Self::wrap(self.sn, self.inner.with_deadline(deadline))
+        }
     }
 
     impl<M: Message> Stream for Observed<ShardReceivingEnd<M>> {
@@ -375,7 +423,10 @@ mod send {
     use crate::{
         helpers::{
             error::Error,
-            gateway::send::{GatewaySender, GatewaySenders},
+            gateway::{
+                send::{GatewaySender, GatewaySenders, SerializedMessage},
+                StepDeadline,
+            },
             ChannelId, Message, TotalRecords, TransportIdentity,
         },
         protocol::RecordId,
@@ -387,9 +438,16 @@ mod send {
                 #[inline]
                 pub async fn send<B: Borrow<M>>(&self, record_id: RecordId, msg: B) -> Result<(), Error<I>>;
                 #[inline]
+                pub async fn send_serialized(&self, record_id: RecordId, msg: &SerializedMessage<M>) -> Result<(), Error<I>>;
+                #[inline]
                 pub async fn close(&self, at: RecordId);
             }
         }
+
+        #[must_use]
+        pub fn with_deadline(self, deadline: Option<StepDeadline>) -> Self {
+            Self::wrap(self.sn, self.inner.with_deadline(deadline))
+        }
     }
 
     pub struct WaitingTasks<I>(BTreeMap<ChannelId<I>, (TotalRecords, Vec<String>)>);
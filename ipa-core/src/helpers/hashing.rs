@@ -110,6 +110,12 @@ where
     hash
 }
 
+/// Computes Hash of a byte string.
+#[must_use]
+pub fn compute_bytes_hash(bytes: &[u8]) -> Hash {
+    Hash(Sha256::digest(bytes))
+}
+
 /// This function takes two hashes, combines them together and returns a single field element.
 ///
 /// Its use is tailored to malicious security requirements where the random challenge point `r`
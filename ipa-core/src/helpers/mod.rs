@@ -57,7 +57,10 @@ mod gateway_exports {
 }
 
 pub use cross_shard_prss::gen_and_distribute as setup_cross_shard_prss;
-pub use gateway::GatewayConfig;
+pub use gateway::{
+    BroadcastingEnd, ConcurrencyStage, GatewayConfig, MessagePriority, StageConcurrency,
+    StepDeadline,
+};
 // TODO: this type should only be available within infra. Right now several infra modules
 // are exposed at the root level. That makes it impossible to have a proper hierarchy here.
 pub use gateway::{
@@ -75,11 +78,14 @@ pub use transport::{
     InMemoryTransportError,
 };
 pub use transport::{
-    make_owned_handler, query, routing, ApiError, BodyStream, BroadcastError, BytesStream,
-    HandlerBox, HandlerRef, HelperResponse, Identity as TransportIdentity, LengthDelimitedStream,
-    LogErrors, NoQueryId, NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords,
-    RecordsStream, RequestHandler, RouteParams, SingleRecordStream, StepBinding, StreamCollection,
-    StreamKey, Transport, WrappedBoxBodyStream,
+    frame, make_owned_handler, query, read_paired_input, read_site_input, read_tagged_input,
+    routing, ApiError, Arm, BodyStream, BoxedTransport, BroadcastError, BytesStream, DynTransport,
+    DynTransportError, HandlerBox, HandlerRef, HelperResponse, Identity as TransportIdentity,
+    InputIntegrityError, InputManifest, LengthDelimitedStream, LogErrors, ManifestCheck,
+    ManifestStream, NoQueryId, NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords,
+    RecordCounter, RecordFraming, RecordParseError, RecordPrefixes, RecordsStream, RequestHandler,
+    RouteParams, SingleRecordStream, StepBinding, StepTransfer, StreamCollection, StreamKey,
+    Transport, WrappedBoxBodyStream,
 };
 use typenum::{Const, ToUInt, Unsigned, U8};
 use x25519_dalek::PublicKey;
@@ -91,7 +97,7 @@ use crate::{
         Direction::{Left, Right},
         Role::{H1, H2, H3},
     },
-    protocol::{Gate, RecordId},
+    protocol::{Gate, QueryId, RecordId},
     secret_sharing::Sendable,
     sharding::ShardIndex,
 };
@@ -495,10 +501,13 @@ impl TryFrom<[Role; 3]> for RoleAssignment {
     }
 }
 
-/// Combination of helper role and step that uniquely identifies a single channel of communication
-/// between two helpers.
+/// Combination of query, helper role and step that uniquely identifies a single channel of
+/// communication between two helpers.
 #[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct ChannelId<I> {
+    /// Query this channel belongs to. Queries that share the same transport may use identical
+    /// steps, so channels must be kept apart by query as well.
+    pub query_id: QueryId,
     /// Entity we are talking to through this channel. It can be a source or a destination.
     pub peer: I,
     // TODO: step could be either reference or owned value. references are convenient to use inside
@@ -511,14 +520,24 @@ pub type ShardChannelId = ChannelId<ShardIndex>;
 
 impl<I: transport::Identity> ChannelId<I> {
     #[must_use]
-    pub fn new(peer: I, gate: Gate) -> Self {
-        Self { peer, gate }
+    pub fn new(query_id: QueryId, peer: I, gate: Gate) -> Self {
+        Self {
+            query_id,
+            peer,
+            gate,
+        }
     }
 }
 
 impl<I: transport::Identity> Debug for ChannelId<I> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "channel[{:?},{:?}]", self.peer, self.gate.as_ref())
+        write!(
+            f,
+            "channel[{},{:?},{:?}]",
+            self.query_id,
+            self.peer,
+            self.gate.as_ref()
+        )
     }
 }
 
@@ -18,8 +18,13 @@ pub async fn negotiate<R: RngCore + CryptoRng>(
     // setup protocol to exchange PRSS public keys. This protocol sends one message per peer.
     // Each message contains this helper's public key. At the end of this protocol, all helpers
     // have completed key exchange and each of them have established a shared secret with each peer.
-    let left_channel = ChannelId::new(gateway.role().peer(Direction::Left), gate.clone());
-    let right_channel = ChannelId::new(gateway.role().peer(Direction::Right), gate.clone());
+    let query_id = gateway.query_id();
+    let left_channel = ChannelId::new(query_id, gateway.role().peer(Direction::Left), gate.clone());
+    let right_channel = ChannelId::new(
+        query_id,
+        gateway.role().peer(Direction::Right),
+        gate.clone(),
+    );
 
     let left_sender = gateway.get_mpc_sender::<PublicKey>(
         &left_channel,
@@ -31,8 +36,8 @@ pub async fn negotiate<R: RngCore + CryptoRng>(
         TotalRecords::ONE,
         gateway.config().active_work_as_power_of_two(),
     );
-    let left_receiver = gateway.get_mpc_receiver::<PublicKey>(&left_channel);
-    let right_receiver = gateway.get_mpc_receiver::<PublicKey>(&right_channel);
+    let left_receiver = gateway.get_mpc_receiver::<PublicKey>(&left_channel, TotalRecords::ONE);
+    let right_receiver = gateway.get_mpc_receiver::<PublicKey>(&right_channel, TotalRecords::ONE);
 
     // setup local prss endpoint
     let ep_setup = prss::Endpoint::prepare(rng);
@@ -0,0 +1,226 @@
+//! Transport implemented outside of this crate.
+//!
+//! [`Transport`] is generic over the requests it sends, which makes it impossible to use as a
+//! trait object. [`DynTransport`] is the object-safe subset of it that an embedder needs to
+//! implement to run helpers over its own RPC stack, and [`BoxedTransport`] turns any
+//! implementation of it into a [`Transport`]. When this crate is built with the `dyn-infra`
+//! feature, [`BoxedTransport`] is the transport used by the gateway and the query processor,
+//! so all protocol code runs on top of it.
+//!
+//! Requests go both ways:
+//! * [`DynTransport::send`] delivers outgoing requests, [`Addr`] describes them. Requests that
+//!   carry step data ([`RouteId::Records`]) are expected to be delivered to the peer as a
+//!   stream that the peer later hands out from [`DynTransport::receive_stream`].
+//! * Every other incoming request is delivered by calling the [`RequestHandler`] that the
+//!   helper [`Setup`] returns. That handler is the only entry point for events coming from the
+//!   network.
+//!
+//! [`RequestHandler`]: crate::helpers::RequestHandler
+//! [`Setup`]: crate::AppSetup
+
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    error::BoxError,
+    helpers::{
+        transport::routing::{Addr, RouteId},
+        BodyStream, NoResourceIdentifier, QueryIdBinding, RouteParams, StepBinding, Transport,
+        TransportIdentity,
+    },
+    protocol::{Gate, ProtocolVersion, QueryId},
+};
+
+/// Object-safe transport between helpers, or between shards of the same helper, that can be
+/// implemented outside of this crate. See the [module documentation](self) for details.
+#[async_trait]
+pub trait DynTransport<I: TransportIdentity>: Send + Sync + 'static {
+    /// Identity of this party.
+    fn identity(&self) -> I;
+
+    /// All the other parties in the network.
+    fn peers(&self) -> Vec<I>;
+
+    /// Sends the request described by `addr`, with `data` as its body, to `dest`. Returns once
+    /// `dest` accepted the request.
+    ///
+    /// ## Errors
+    /// If the request could not be delivered or if `dest` rejected it.
+    async fn send(&self, dest: I, addr: Addr<I>, data: BodyStream) -> Result<(), BoxError>;
+
+    /// Returns the step data `from` sends over `gate` in the query `query_id`. The stream may be
+    /// requested before `from` starts sending.
+    fn receive_stream(&self, from: I, query_id: QueryId, gate: Gate) -> BodyStream;
+
+    /// See [`Transport::bind_protocol_version`].
+    fn bind_protocol_version(&self, _query_id: QueryId, _version: ProtocolVersion) {}
+}
+
+/// Error returned by a [`DynTransport`].
+#[derive(thiserror::Error, Debug)]
+#[error("request to {dest:?} failed: {inner}")]
+pub struct DynTransportError<I: Debug> {
+    pub dest: I,
+    #[source]
+    pub inner: BoxError,
+}
+
+/// [`Transport`] that forwards to a [`DynTransport`].
+pub struct BoxedTransport<I> {
+    inner: Arc<dyn DynTransport<I>>,
+}
+
+impl<I> Clone for BoxedTransport<I> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<I: TransportIdentity> BoxedTransport<I> {
+    #[must_use]
+    pub fn new<T: DynTransport<I>>(transport: T) -> Self {
+        Self {
+            inner: Arc::new(transport),
+        }
+    }
+}
+
+impl<I: TransportIdentity> Debug for BoxedTransport<I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BoxedTransport[{:?}]", self.inner.identity())
+    }
+}
+
+#[async_trait]
+impl<I: TransportIdentity> Transport for BoxedTransport<I> {
+    type Identity = I;
+    type RecordsStream = BodyStream;
+    type Error = DynTransportError<I>;
+
+    fn identity(&self) -> I {
+        self.inner.identity()
+    }
+
+    fn peers(&self) -> impl Iterator<Item = I> {
+        self.inner.peers().into_iter()
+    }
+
+    async fn send<D, Q, S, R>(&self, dest: I, route: R, data: D) -> Result<(), Self::Error>
+    where
+        Option<QueryId>: From<Q>,
+        Option<Gate>: From<S>,
+        Q: QueryIdBinding,
+        S: StepBinding,
+        R: RouteParams<RouteId, Q, S>,
+        D: Stream<Item = Vec<u8>> + Send + 'static,
+    {
+        let addr = Addr::from_route(Some(self.inner.identity()), route);
+        let data = BodyStream::from_bytes_stream(data.map(|chunk| Ok(Bytes::from(chunk))));
+        self.inner
+            .send(dest, addr, data)
+            .await
+            .map_err(|inner| DynTransportError { dest, inner })
+    }
+
+    fn receive<R: RouteParams<NoResourceIdentifier, QueryId, Gate>>(
+        &self,
+        from: I,
+        route: R,
+    ) -> Self::RecordsStream {
+        self.inner
+            .receive_stream(from, route.query_id(), route.gate())
+    }
+
+    fn bind_protocol_version(&self, query_id: QueryId, version: ProtocolVersion) {
+        self.inner.bind_protocol_version(query_id, version);
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use async_trait::async_trait;
+    use futures::{stream, StreamExt};
+
+    use super::{BoxedTransport, DynTransport};
+    use crate::{
+        error::BoxError,
+        helpers::{
+            routing::{Addr, RouteId},
+            BodyStream, HelperIdentity, Transport,
+        },
+        protocol::{Gate, QueryId},
+    };
+
+    /// Keeps everything sent to it, and hands it back to itself as the step data of the sender.
+    #[derive(Default)]
+    struct Loopback {
+        sent: Mutex<HashMap<(HelperIdentity, Gate), Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl DynTransport<HelperIdentity> for Loopback {
+        fn identity(&self) -> HelperIdentity {
+            HelperIdentity::ONE
+        }
+
+        fn peers(&self) -> Vec<HelperIdentity> {
+            vec![HelperIdentity::TWO, HelperIdentity::THREE]
+        }
+
+        async fn send(
+            &self,
+            _dest: HelperIdentity,
+            addr: Addr<HelperIdentity>,
+            data: BodyStream,
+        ) -> Result<(), BoxError> {
+            assert_eq!(RouteId::Records, addr.route);
+            let bytes = data.map(|chunk| chunk.unwrap().to_vec()).concat().await;
+            self.sent
+                .lock()
+                .unwrap()
+                .insert((addr.origin.unwrap(), addr.gate.unwrap()), bytes);
+            Ok(())
+        }
+
+        fn receive_stream(
+            &self,
+            from: HelperIdentity,
+            _query_id: QueryId,
+            gate: Gate,
+        ) -> BodyStream {
+            BodyStream::from(self.sent.lock().unwrap().remove(&(from, gate)).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_to_inner() {
+        let transport = BoxedTransport::new(Loopback::default());
+        assert_eq!(HelperIdentity::ONE, transport.identity());
+        assert_eq!(
+            vec![HelperIdentity::TWO, HelperIdentity::THREE],
+            transport.peers().collect::<Vec<_>>()
+        );
+
+        let gate = Gate::default();
+        transport
+            .send(
+                HelperIdentity::TWO,
+                (RouteId::Records, QueryId, gate.clone()),
+                stream::iter([vec![1, 2], vec![3]]),
+            )
+            .await
+            .unwrap();
+        let received = transport
+            .receive(HelperIdentity::ONE, (QueryId, gate))
+            .to_vec()
+            .await;
+        assert_eq!(vec![1, 2, 3], received);
+    }
+}
@@ -0,0 +1,374 @@
+//! Framing of step data.
+//!
+//! Step data is sent as a sequence of frames. Every frame carries a payload preceded by its
+//! length and its CRC-32 checksum, both 4 byte little-endian integers, so a payload damaged on
+//! the way is caught before its bytes reach the receive buffers. Without it, corrupted shares
+//! only show up much later as failed MAC checks, which look exactly like a malicious peer.
+//!
+//! Only the payload is sent. The channel a frame belongs to is known from the route it is sent
+//! over, and its offset is the number of payload bytes sent on the channel before it. [`decode`]
+//! restores both, so errors point at the exact place a channel went wrong.
+//!
+//! Every transport frames step data with this module: HTTP inside the request body, and the
+//! in-memory transport on the streams it hands to the receiver. Tests that run helpers in
+//! memory therefore exercise the same encoder and decoder as production helpers do.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+
+use crate::{error::BoxError, protocol::Gate};
+
+/// Size of the length and checksum that precede every payload.
+pub const HEADER_LEN: usize = 2 * size_of::<u32>();
+
+/// Largest payload a frame can carry. Batches sent by gateways are several orders of magnitude
+/// smaller. A frame that claims a longer payload is rejected as soon as its header is read,
+/// instead of buffering up to 4Gb of data waiting for the rest of it.
+pub const MAX_PAYLOAD_LEN: usize = 1 << 28;
+
+/// Payload of step data sent on `channel`, starting `offset` bytes from the beginning of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub channel: Gate,
+    /// Offset of the payload from the beginning of the channel, not counting frame headers.
+    pub offset: u64,
+    pub payload: Bytes,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "frame at offset {offset} of {channel} is corrupted: expected checksum {expected:#010x}, \
+    got {actual:#010x}"
+)]
+pub struct ChecksumMismatch {
+    pub channel: Gate,
+    pub offset: u64,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Checksum(#[from] ChecksumMismatch),
+    #[error(
+        "frame at offset {offset} of {channel} claims {len} bytes of payload, more than \
+        {MAX_PAYLOAD_LEN} allowed"
+    )]
+    TooLarge {
+        channel: Gate,
+        offset: u64,
+        len: usize,
+    },
+    #[error("{channel} ended in the middle of the frame at offset {offset}")]
+    Truncated { channel: Gate, offset: u64 },
+}
+
+#[allow(clippy::cast_possible_truncation)] // `i` is less than 256
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < table.len() {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3) of `data`.
+#[must_use]
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Prepends the length and checksum to `payload`.
+///
+/// ## Panics
+/// If `payload` is longer than [`MAX_PAYLOAD_LEN`].
+#[must_use]
+pub fn encode(payload: &[u8]) -> Bytes {
+    assert!(
+        payload.len() <= MAX_PAYLOAD_LEN,
+        "payload of {} bytes does not fit in a frame",
+        payload.len()
+    );
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    buf.put_u32_le(u32::try_from(payload.len()).unwrap());
+    buf.put_u32_le(crc32(payload));
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+impl Frame {
+    /// Same as [`encode`] for the payload of this frame.
+    ///
+    /// ## Panics
+    /// If the payload is longer than [`MAX_PAYLOAD_LEN`].
+    #[must_use]
+    pub fn encode(&self) -> Bytes {
+        encode(&self.payload)
+    }
+
+    /// Checks that the payload matches the `expected` checksum.
+    ///
+    /// ## Errors
+    /// If it does not.
+    pub fn verify(&self, expected: u32) -> Result<(), ChecksumMismatch> {
+        let actual = crc32(&self.payload);
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(ChecksumMismatch {
+                channel: self.channel.clone(),
+                offset: self.offset,
+                expected,
+                actual,
+            })
+        }
+    }
+}
+
+/// Reassembles frames of one channel from chunks of bytes that split them arbitrarily.
+pub struct Decoder {
+    channel: Gate,
+    offset: u64,
+    buf: BytesMut,
+}
+
+impl Decoder {
+    #[must_use]
+    pub fn new(channel: Gate) -> Self {
+        Self {
+            channel,
+            offset: 0,
+            buf: BytesMut::new(),
+        }
+    }
+
+    pub fn extend(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Takes the next complete frame, if there is one, after checking its checksum.
+    ///
+    /// ## Errors
+    /// If the frame is corrupted or claims a payload longer than [`MAX_PAYLOAD_LEN`].
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = usize::try_from(u32::from_le_bytes(self.buf[..4].try_into().unwrap())).unwrap();
+        if len > MAX_PAYLOAD_LEN {
+            return Err(Error::TooLarge {
+                channel: self.channel.clone(),
+                offset: self.offset,
+                len,
+            });
+        }
+        if self.buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        let mut header = self.buf.split_to(HEADER_LEN);
+        header.advance(size_of::<u32>());
+        let expected = header.get_u32_le();
+        let frame = Frame {
+            channel: self.channel.clone(),
+            offset: self.offset,
+            payload: self.buf.split_to(len).freeze(),
+        };
+        frame.verify(expected)?;
+        self.offset += u64::try_from(len).unwrap();
+
+        Ok(Some(frame))
+    }
+
+    /// Checks that the input ended on a frame boundary.
+    ///
+    /// ## Errors
+    /// If there is a partial frame left.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Truncated {
+                channel: self.channel.clone(),
+                offset: self.offset,
+            })
+        }
+    }
+}
+
+/// Reverses [`encode`] for a stream of frames sent on `channel`, yielding the frames after their
+/// checksum was verified. The frames can be split into chunks arbitrarily.
+pub fn decode<S>(channel: Gate, input: S) -> impl Stream<Item = Result<Frame, BoxError>> + Send
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Send + Unpin,
+{
+    stream::try_unfold(
+        (input, Decoder::new(channel)),
+        |(mut input, mut decoder)| async move {
+            loop {
+                if let Some(frame) = decoder.next_frame()? {
+                    return Ok::<_, BoxError>(Some((frame, (input, decoder))));
+                }
+                match input.next().await {
+                    Some(chunk) => decoder.extend(&chunk?),
+                    None => {
+                        decoder.finish()?;
+                        return Ok(None);
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Same as [`decode`], yielding just the payloads.
+pub fn payloads<S>(channel: Gate, input: S) -> impl Stream<Item = Result<Bytes, BoxError>> + Send
+where
+    S: Stream<Item = Result<Bytes, BoxError>> + Send + Unpin,
+{
+    decode(channel, input).map_ok(|frame| frame.payload)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use bytes::Bytes;
+    use futures::{executor::block_on, stream, TryStreamExt};
+    use proptest::{collection::vec, prelude::*};
+
+    use super::{crc32, decode, encode, Error, Frame, HEADER_LEN, MAX_PAYLOAD_LEN};
+    use crate::{error::BoxError, protocol::Gate};
+
+    fn chunks(data: &[u8], size: usize) -> Vec<Result<Bytes, BoxError>> {
+        data.chunks(size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect()
+    }
+
+    fn decode_all(data: &[u8], chunk_size: usize) -> Result<Vec<Frame>, BoxError> {
+        block_on(decode(Gate::from("/a"), stream::iter(chunks(data, chunk_size))).try_collect())
+    }
+
+    fn frame_error(err: BoxError) -> Error {
+        *err.downcast::<Error>().unwrap()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0, crc32(b""));
+    }
+
+    #[test]
+    fn round_trip() {
+        let payloads = [vec![1_u8; 10], vec![], vec![2; 3]];
+        let encoded = payloads.iter().flat_map(|p| encode(p)).collect::<Vec<_>>();
+        for size in [1, 3, 8, encoded.len()] {
+            let frames = decode_all(&encoded, size).unwrap();
+            assert_eq!(
+                payloads.to_vec(),
+                frames
+                    .iter()
+                    .map(|f| f.payload.to_vec())
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![0, 10, 10],
+                frames.iter().map(|f| f.offset).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut encoded = [encode(&[1, 2, 3]), encode(&[4, 5, 6])].concat();
+        // flip a bit in the payload of the second frame
+        *encoded.last_mut().unwrap() ^= 1;
+        let Error::Checksum(err) = frame_error(decode_all(&encoded, 4).unwrap_err()) else {
+            panic!("expected a checksum mismatch");
+        };
+        assert_eq!(Gate::from("/a"), err.channel);
+        assert_eq!(3, err.offset);
+        assert_eq!(crc32(&[4, 5, 6]), err.expected);
+    }
+
+    #[test]
+    fn detects_truncation() {
+        let encoded = [encode(&[1, 2, 3]), encode(&[4, 5, 6])].concat();
+        let err = frame_error(decode_all(&encoded[..encoded.len() - 1], 2).unwrap_err());
+        assert!(matches!(err, Error::Truncated { offset: 3, .. }));
+    }
+
+    #[test]
+    fn rejects_oversized_frame() {
+        let mut header = u32::try_from(MAX_PAYLOAD_LEN + 1)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec();
+        header.extend_from_slice(&[0; 4]);
+        // rejected from the header alone, without waiting for the payload
+        let err = frame_error(decode_all(&header, HEADER_LEN).unwrap_err());
+        assert!(matches!(
+            err,
+            Error::TooLarge { offset: 0, len, .. } if len == MAX_PAYLOAD_LEN + 1
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_chunks(
+            payloads in vec(vec(any::<u8>(), 0..64), 0..16),
+            chunk_size in 1..128_usize,
+        ) {
+            let encoded = payloads.iter().flat_map(|p| encode(p)).collect::<Vec<_>>();
+            let frames = decode_all(&encoded, chunk_size).unwrap();
+            let mut offset = 0;
+            for (frame, payload) in frames.iter().zip(&payloads) {
+                prop_assert_eq!(offset, frame.offset);
+                prop_assert_eq!(payload.as_slice(), frame.payload.as_ref());
+                prop_assert_eq!(frame.encode(), encode(payload));
+                offset += u64::try_from(payload.len()).unwrap();
+            }
+            prop_assert_eq!(payloads.len(), frames.len());
+        }
+
+        #[test]
+        fn any_bit_flip_is_caught(
+            payloads in vec(vec(any::<u8>(), 1..64), 1..8),
+            position in any::<prop::sample::Index>(),
+            bit in 0..8_u8,
+        ) {
+            let mut encoded = payloads.iter().flat_map(|p| encode(p)).collect::<Vec<_>>();
+            let position = position.index(encoded.len());
+            encoded[position] ^= 1 << bit;
+            // A flipped length may still describe a valid frame, but then the rest of the
+            // input does not line up with it.
+            let decoded = decode_all(&encoded, 7);
+            prop_assert!(decoded.is_err(), "{position}:{bit} was not detected");
+        }
+
+        #[test]
+        fn arbitrary_input(data in vec(any::<u8>(), 0..256), chunk_size in 1..64_usize) {
+            // Must either decode or fail, never panic. Whatever decodes encodes back to the
+            // same bytes.
+            if let Ok(frames) = decode_all(&data, chunk_size) {
+                let encoded = frames.iter().flat_map(Frame::encode).collect::<Vec<_>>();
+                prop_assert_eq!(data, encoded);
+            }
+        }
+    }
+}
@@ -7,12 +7,14 @@ use serde_json::json;
 use crate::{
     error::BoxError,
     helpers::{
-        query::PrepareQuery, transport::routing::Addr, BodyStream, HelperIdentity,
-        TransportIdentity,
+        query::{PrepareQuery, TemplateError, TemplateList, ValidationReport},
+        transport::routing::Addr,
+        BodyStream, HelperIdentity, TransportIdentity,
     },
     query::{
-        NewQueryError, PrepareQueryError, ProtocolResult, QueryCompletionError, QueryInputError,
-        QueryKillStatus, QueryKilled, QueryStatus, QueryStatusError,
+        DeleteInputError, InputDeleted, NewQueryError, PrepareQueryError, ProtocolResult,
+        PruneQueryError, QueryCompletionError, QueryInputError, QueryKillStatus, QueryKilled,
+        QueryList, QueryPruned, QueryStatus, QueryStatusError, QueryStatusReport, Readiness,
     },
     sync::{Arc, Mutex, Weak},
 };
@@ -122,6 +124,22 @@ impl From<PrepareQuery> for HelperResponse {
     }
 }
 
+impl From<ValidationReport> for HelperResponse {
+    fn from(value: ValidationReport) -> Self {
+        Self {
+            body: serde_json::to_vec(&value).unwrap(),
+        }
+    }
+}
+
+impl From<TemplateList> for HelperResponse {
+    fn from(value: TemplateList) -> Self {
+        Self {
+            body: serde_json::to_vec(&value).unwrap(),
+        }
+    }
+}
+
 impl From<()> for HelperResponse {
     fn from(_value: ()) -> Self {
         Self::ok()
@@ -135,6 +153,14 @@ impl From<QueryStatus> for HelperResponse {
     }
 }
 
+impl From<QueryStatusReport> for HelperResponse {
+    fn from(value: QueryStatusReport) -> Self {
+        Self {
+            body: serde_json::to_vec(&value).unwrap(),
+        }
+    }
+}
+
 impl From<QueryKilled> for HelperResponse {
     fn from(value: QueryKilled) -> Self {
         let v = serde_json::to_vec(&json!({"query_id": value.0, "status": "killed"})).unwrap();
@@ -142,6 +168,34 @@ impl From<QueryKilled> for HelperResponse {
     }
 }
 
+impl From<InputDeleted> for HelperResponse {
+    fn from(value: InputDeleted) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
+impl From<QueryList> for HelperResponse {
+    fn from(value: QueryList) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
+impl From<Readiness> for HelperResponse {
+    fn from(value: Readiness) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
+impl From<QueryPruned> for HelperResponse {
+    fn from(value: QueryPruned) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
 impl<R: AsRef<dyn ProtocolResult>> From<R> for HelperResponse {
     fn from(value: R) -> Self {
         let v = value.as_ref().to_bytes();
@@ -171,6 +225,12 @@ pub enum Error {
     #[error(transparent)]
     QueryKill(#[from] QueryKillStatus),
     #[error(transparent)]
+    DeleteInput(#[from] DeleteInputError),
+    #[error(transparent)]
+    PruneQuery(#[from] PruneQueryError),
+    #[error(transparent)]
+    QueryTemplate(#[from] TemplateError),
+    #[error(transparent)]
     DeserializationFailure(#[from] serde_json::Error),
     #[error("MalformedRequest: {0}")]
     BadRequest(BoxError),
@@ -1,3 +1,5 @@
+use std::num::NonZeroUsize;
+
 use crate::{
     helpers::{HelperIdentity, Role, RoleAssignment},
     protocol::Gate,
@@ -100,6 +102,23 @@ pub enum InspectContext {
     },
 }
 
+/// Controls how chunks sent over the in-memory transport are delivered to the receiver.
+///
+/// The HTTP transport does not preserve the boundaries of chunks produced by the sender:
+/// the receiver may see a chunk split across several frames, or several chunks merged into
+/// one. Receivers must not depend on chunk boundaries, and [`Framing::Strict`] can be used to
+/// catch the ones that do without spinning up the HTTP stack.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Every chunk is delivered exactly as it was sent.
+    #[default]
+    Preserve,
+    /// Chunks that are ready at the same time are merged and then cut into frames of at most
+    /// this many bytes. Data is never held back waiting for more to arrive, so this does not
+    /// change when the receiver sees it.
+    Strict(NonZeroUsize),
+}
+
 /// The no-op stream peeker, which does nothing.
 /// This is used as a default value for stream
 /// peekers that don't do anything.
@@ -8,7 +8,8 @@ pub use transport::{Error as InMemoryTransportError, Setup};
 
 use crate::{
     helpers::{
-        in_memory_config::DynStreamInterceptor, transport::in_memory::config::passthrough,
+        in_memory_config::{DynStreamInterceptor, Framing},
+        transport::in_memory::config::passthrough,
         HandlerRef, HelperIdentity,
     },
     sharding::ShardIndex,
@@ -38,10 +39,11 @@ impl InMemoryMpcNetwork {
     /// Construct an unsharded `InMemoryMpcNetwork` with no stream interceptor.
     #[must_use]
     pub fn new(handlers: [Option<HandlerRef>; 3]) -> Self {
-        Self::with_stream_interceptor(handlers, &passthrough(), None)
+        Self::with_stream_interceptor(handlers, &passthrough(), Framing::default(), None)
     }
 
-    /// Construct an `InMemoryMpcNetwork` with a stream interceptor.
+    /// Construct an `InMemoryMpcNetwork` with a stream interceptor. Streams are delivered
+    /// according to `framing`, see [`Framing`].
     ///
     /// For sharded environments, the `shard_index` must be provided so that the
     /// interceptor can distinguish helper-to-helper streams for different shards.
@@ -50,11 +52,14 @@ impl InMemoryMpcNetwork {
     pub fn with_stream_interceptor(
         handlers: [Option<HandlerRef>; 3],
         interceptor: &DynStreamInterceptor,
+        framing: Framing,
         shard: Option<ShardIndex>,
     ) -> Self {
         let [mut first, mut second, mut third]: [_; 3] = HelperIdentity::make_three().map(|i| {
             let mut config_builder = TransportConfigBuilder::for_helper(i);
-            config_builder.with_interceptor(interceptor);
+            config_builder
+                .with_interceptor(interceptor)
+                .with_framing(framing);
 
             Setup::with_config(i, config_builder.with_sharding(shard))
         });
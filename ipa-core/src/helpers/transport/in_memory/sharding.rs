@@ -1,6 +1,6 @@
 use crate::{
     helpers::{
-        in_memory_config::{passthrough, DynStreamInterceptor},
+        in_memory_config::{passthrough, DynStreamInterceptor, Framing},
         transport::in_memory::transport::{InMemoryTransport, Setup, TransportConfigBuilder},
         HandlerBox, HelperIdentity, RequestHandler,
     },
@@ -23,14 +23,15 @@ pub struct InMemoryShardNetwork {
 
 impl InMemoryShardNetwork {
     pub fn with_shards<I: Into<ShardIndex>>(shard_count: I) -> Self {
-        Self::with_stream_interceptor(shard_count, &passthrough())
+        Self::with_stream_interceptor(shard_count, &passthrough(), Framing::default())
     }
 
     pub fn with_stream_interceptor<I: Into<ShardIndex>>(
         shard_count: I,
         interceptor: &DynStreamInterceptor,
+        framing: Framing,
     ) -> Self {
-        let shard_network = Self::create_shard_connections(shard_count, interceptor).map(
+        let shard_network = Self::create_shard_connections(shard_count, interceptor, framing).map(
             |(shard_connections, h)| {
                 shard_connections
                     .into_iter()
@@ -45,11 +46,14 @@ impl InMemoryShardNetwork {
     pub fn create_shard_connections<I: Into<ShardIndex>>(
         shard_count: I,
         interceptor: &DynStreamInterceptor,
+        framing: Framing,
     ) -> [(Vec<Setup<ShardIndex>>, HelperIdentity); 3] {
         let shard_count = shard_count.into();
         HelperIdentity::make_three().map(|h| {
             let mut config_builder = TransportConfigBuilder::for_helper(h);
-            config_builder.with_interceptor(interceptor);
+            config_builder
+                .with_interceptor(interceptor)
+                .with_framing(framing);
 
             let mut shard_connections = shard_count
                 .iter()
@@ -76,7 +80,8 @@ impl InMemoryShardNetwork {
         I: Into<ShardIndex>,
         F: Fn(ShardIndex) -> Arc<dyn RequestHandler<ShardIndex>>,
     {
-        let connections = Self::create_shard_connections(shard_count, &passthrough());
+        let connections =
+            Self::create_shard_connections(shard_count, &passthrough(), Framing::default());
         let shard_count = connections[0].0.len();
         let mut handlers = Vec::with_capacity(3 * shard_count);
         let shard_network = connections.map(|(shard_connections, h)| {
@@ -2,6 +2,7 @@ use std::{
     collections::HashMap,
     fmt::{Debug, Formatter},
     io,
+    num::NonZeroUsize,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -11,8 +12,8 @@ use ::tokio::sync::{
     oneshot,
 };
 use async_trait::async_trait;
-use bytes::Bytes;
-use futures::{Stream, StreamExt};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt, TryStreamExt};
 #[cfg(all(feature = "shuttle", test))]
 use shuttle::future as tokio;
 use tokio_stream::wrappers::ReceiverStream;
@@ -21,7 +22,8 @@ use tracing::Instrument;
 use crate::{
     error::BoxError,
     helpers::{
-        in_memory_config::{self, DynStreamInterceptor},
+        frame,
+        in_memory_config::{self, DynStreamInterceptor, Framing},
         transport::routing::{Addr, RouteId},
         ApiError, BodyStream, HandlerRef, HelperIdentity, HelperResponse, NoResourceIdentifier,
         QueryIdBinding, ReceiveRecords, RequestHandler, RouteParams, StepBinding, StreamCollection,
@@ -108,16 +110,28 @@ impl<I: TransportIdentity> InMemoryTransport<I> {
                                 let query_id = addr.query_id.unwrap();
                                 let gate = addr.gate.unwrap();
                                 let from = addr.origin.unwrap();
+                                let stream =
+                                    InMemoryStream::wrap(frame::payloads(gate.clone(), stream));
                                 streams.add_stream((query_id, from, gate), stream);
                                 Ok(HelperResponse::ok())
                             }
                             RouteId::ReceiveQuery
+                            | RouteId::ReceiveQueryFromTemplate
+                            | RouteId::QueryTemplates
+                            | RouteId::ValidateQuery
                             | RouteId::PrepareQuery
                             | RouteId::QueryInput
+                            | RouteId::AppendQueryInput
+                            | RouteId::SealQueryInput
                             | RouteId::QueryStatus
                             | RouteId::CompleteQuery
                             | RouteId::KillQuery
-                            | RouteId::Metrics => {
+                            | RouteId::PeerUnavailable
+                            | RouteId::DeleteQueryInput
+                            | RouteId::ListQueries
+                            | RouteId::PruneQuery
+                            | RouteId::Metrics
+                            | RouteId::Readiness => {
                                 handler
                                     .as_ref()
                                     .expect("Handler is set")
@@ -191,28 +205,35 @@ impl<I: TransportIdentity> Transport for Weak<InMemoryTransport<I>> {
         let channel = this.get_channel(dest);
         let addr = Addr::from_route(Some(this.identity), route);
         let gate = addr.gate.clone();
+        let records = addr.route == RouteId::Records;
 
         let (ack_tx, ack_rx) = oneshot::channel();
         let context =
             gate.map(|gate| dest.inspect_context(this.config.shard, this.config.identity, gate));
+        let framing = this.config.framing;
 
-        channel
-            .send((
-                addr,
-                InMemoryStream::wrap(data.map({
-                    move |mut chunk| {
-                        if let Some(ref context) = context {
-                            this.config.stream_interceptor.peek(context, &mut chunk);
-                        }
-                        Ok(Bytes::from(chunk))
-                    }
-                })),
-                ack_tx,
-            ))
-            .await
-            .map_err(|_e| {
-                io::Error::new::<String>(io::ErrorKind::ConnectionAborted, "channel closed".into())
-            })?;
+        let stream = InMemoryStream::wrap(data.map({
+            move |mut chunk| {
+                if let Some(ref context) = context {
+                    this.config.stream_interceptor.peek(context, &mut chunk);
+                }
+                Ok(Bytes::from(chunk))
+            }
+        }));
+        let stream = match framing {
+            Framing::Preserve => stream,
+            Framing::Strict(max_frame) => InMemoryStream::wrap(Reframe::new(stream, max_frame)),
+        };
+        // Step data is framed exactly like HTTP helpers frame it.
+        let stream = if records {
+            InMemoryStream::wrap(stream.map_ok(|chunk| frame::encode(&chunk)))
+        } else {
+            stream
+        };
+
+        channel.send((addr, stream, ack_tx)).await.map_err(|_e| {
+            io::Error::new::<String>(io::ErrorKind::ConnectionAborted, "channel closed".into())
+        })?;
 
         ack_rx
             .await
@@ -277,6 +298,50 @@ impl Debug for InMemoryStream {
     }
 }
 
+/// Stream adapter implementing [`Framing::Strict`]. It merges all chunks that are ready and
+/// cuts them into frames of at most `max_frame` bytes. Whatever is buffered is released as soon
+/// as the inner stream has nothing more to give, so the receiver never waits on the framing.
+struct Reframe<S> {
+    inner: S,
+    buf: BytesMut,
+    max_frame: usize,
+    done: bool,
+}
+
+impl<S> Reframe<S> {
+    fn new(inner: S, max_frame: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            buf: BytesMut::new(),
+            max_frame: max_frame.get(),
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = StreamItem> + Unpin> Stream for Reframe<S> {
+    type Item = StreamItem;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::get_mut(self);
+        loop {
+            if this.buf.len() >= this.max_frame {
+                return Poll::Ready(Some(Ok(this.buf.split_to(this.max_frame).freeze())));
+            }
+            if this.done {
+                return Poll::Ready((!this.buf.is_empty()).then(|| Ok(this.buf.split().freeze())));
+            }
+            match this.inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.buf.extend_from_slice(&chunk),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending if this.buf.is_empty() => return Poll::Pending,
+                Poll::Pending => return Poll::Ready(Some(Ok(this.buf.split().freeze()))),
+            }
+        }
+    }
+}
+
 pub struct Setup<I> {
     pub identity: I,
     tx: ConnectionTx<I>,
@@ -365,19 +430,24 @@ mod tests {
     use crate::{
         ff::{FieldType, Fp31, Serializable},
         helpers::{
+            frame,
+            in_memory_config::Framing,
             make_owned_handler,
             query::{PrepareQuery, QueryConfig, QueryType::TestMultiply},
             transport::{
                 in_memory::{
-                    transport::{Addr, ConnectionTx, Error, InMemoryStream, InMemoryTransport},
+                    transport::{
+                        Addr, ConnectionTx, Error, InMemoryStream, InMemoryTransport,
+                        TransportConfigBuilder,
+                    },
                     InMemoryMpcNetwork, Setup,
                 },
                 routing::RouteId,
             },
             HandlerBox, HelperIdentity, HelperResponse, InMemoryShardNetwork, OrderingSender, Role,
-            RoleAssignment, Transport, TransportIdentity,
+            RoleAssignment, StageConcurrency, Transport, TransportIdentity,
         },
-        protocol::{Gate, QueryId},
+        protocol::{Gate, ProtocolVersion, QueryId},
         sharding::ShardIndex,
         sync::Arc,
     };
@@ -389,7 +459,14 @@ mod tests {
         addr: Addr<I>,
         data: S,
     ) {
-        let data = InMemoryStream::wrap(data.map(Bytes::from).map(Ok));
+        let records = addr.route == RouteId::Records;
+        let data = InMemoryStream::wrap(data.map(move |chunk| {
+            Ok(if records {
+                frame::encode(&chunk)
+            } else {
+                Bytes::from(chunk)
+            })
+        }));
         let (tx, rx) = oneshot::channel();
         sender.send((addr, data, tx)).await.unwrap();
         let _ = rx
@@ -425,6 +502,9 @@ mod tests {
                     query_id: QueryId,
                     config: query_config,
                     roles: RoleAssignment::try_from([Role::H1, Role::H2, Role::H3]).unwrap(),
+                    protocol_version: ProtocolVersion::CURRENT,
+                    pull_receivers: Vec::new(),
+                    stage_concurrency: StageConcurrency::default(),
                 }))
             }
         });
@@ -486,6 +566,29 @@ mod tests {
         assert_eq!(expected, stream.collect::<Vec<_>>().await);
     }
 
+    #[tokio::test]
+    async fn streams_are_keyed_by_query() {
+        let (tx, transport) = Setup::new(HelperIdentity::ONE).into_active_conn(None);
+        let transport = Arc::downgrade(&transport);
+        let queries = [QueryId::new(1), QueryId::new(2)];
+
+        for (i, query_id) in (0..).zip(queries) {
+            send_and_ack(
+                &tx,
+                Addr::records(HelperIdentity::TWO, query_id, Gate::from(STEP)),
+                stream::iter(vec![vec![i]]),
+            )
+            .await;
+        }
+
+        for (i, query_id) in (0..).zip(queries).rev() {
+            let stream = transport
+                .receive(HelperIdentity::TWO, (query_id, Gate::from(STEP)))
+                .into_bytes_stream();
+            assert_eq!(vec![vec![i]], stream.collect::<Vec<_>>().await);
+        }
+    }
+
     #[tokio::test]
     async fn two_helpers() {
         async fn send_and_verify(
@@ -546,6 +649,80 @@ mod tests {
         send_and_verify(HelperIdentity::TWO, HelperIdentity::ONE, &transports).await;
     }
 
+    #[tokio::test]
+    async fn strict_framing() {
+        let (stream_tx, stream_rx) = channel(1);
+        let mut setup1 = Setup::with_config(
+            HelperIdentity::ONE,
+            TransportConfigBuilder::for_helper(HelperIdentity::ONE)
+                .with_framing(Framing::Strict(NonZeroUsize::new(2).unwrap()))
+                .not_sharded(),
+        );
+        let mut setup2 = Setup::new(HelperIdentity::TWO);
+        setup1.connect(&mut setup2);
+        let owned1 = setup1.start(None);
+        let owned2 = setup2.start(None);
+        let transport1 = Arc::downgrade(&owned1);
+        let transport2 = Arc::downgrade(&owned2);
+        let gate = Gate::from(STEP);
+
+        let mut recv = transport2
+            .receive(HelperIdentity::ONE, (QueryId, gate.clone()))
+            .into_bytes_stream();
+        transport1
+            .send(
+                HelperIdentity::TWO,
+                (RouteId::Records, QueryId, gate),
+                ReceiverStream::new(stream_rx),
+            )
+            .await
+            .unwrap();
+
+        // Chunks are split into frames of at most two bytes...
+        stream_tx.send(vec![1, 2, 3, 4, 5]).await.unwrap();
+        assert_eq!(vec![1, 2], recv.next().await.unwrap());
+        assert_eq!(vec![3, 4], recv.next().await.unwrap());
+        // ...but a partial frame is delivered instead of waiting for more data.
+        assert_eq!(vec![5], recv.next().await.unwrap());
+
+        stream_tx.send(vec![6]).await.unwrap();
+        assert_eq!(vec![6], recv.next().await.unwrap());
+        drop(stream_tx);
+        assert!(recv.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn strict_framing_merges_ready_chunks() {
+        let mut setup1 = Setup::with_config(
+            HelperIdentity::ONE,
+            TransportConfigBuilder::for_helper(HelperIdentity::ONE)
+                .with_framing(Framing::Strict(NonZeroUsize::new(2).unwrap()))
+                .not_sharded(),
+        );
+        let mut setup2 = Setup::new(HelperIdentity::TWO);
+        setup1.connect(&mut setup2);
+        let transport1 = setup1.start(None);
+        let transport2 = setup2.start(None);
+
+        Arc::downgrade(&transport1)
+            .send(
+                HelperIdentity::TWO,
+                (RouteId::Records, QueryId, Gate::from(STEP)),
+                stream::iter(vec![vec![1, 2, 3], vec![4], vec![5]]),
+            )
+            .await
+            .unwrap();
+
+        let stream = Arc::downgrade(&transport2)
+            .receive(HelperIdentity::ONE, (QueryId, Gate::from(STEP)))
+            .into_bytes_stream();
+
+        assert_eq!(
+            vec![vec![1, 2], vec![3, 4], vec![5]],
+            stream.collect::<Vec<_>>().await
+        );
+    }
+
     #[tokio::test]
     async fn panic_if_stream_received_twice() {
         let (tx, owned_transport) = Setup::new(HelperIdentity::ONE).into_active_conn(None);
@@ -654,11 +831,13 @@ pub struct TransportConfig {
     pub shard: Option<ShardIndex>,
     pub identity: HelperIdentity,
     pub stream_interceptor: DynStreamInterceptor,
+    pub framing: Framing,
 }
 
 pub struct TransportConfigBuilder {
     identity: HelperIdentity,
     stream_interceptor: DynStreamInterceptor,
+    framing: Framing,
 }
 
 impl TransportConfigBuilder {
@@ -666,6 +845,7 @@ impl TransportConfigBuilder {
         Self {
             identity,
             stream_interceptor: in_memory_config::passthrough(),
+            framing: Framing::default(),
         }
     }
 
@@ -675,19 +855,22 @@ impl TransportConfigBuilder {
         self
     }
 
+    pub fn with_framing(&mut self, framing: Framing) -> &mut Self {
+        self.framing = framing;
+
+        self
+    }
+
     pub fn with_sharding(&self, shard: Option<ShardIndex>) -> TransportConfig {
         TransportConfig {
             shard,
             identity: self.identity,
             stream_interceptor: Arc::clone(&self.stream_interceptor),
+            framing: self.framing,
         }
     }
 
     pub fn not_sharded(&self) -> TransportConfig {
-        TransportConfig {
-            shard: None,
-            identity: self.identity,
-            stream_interceptor: Arc::clone(&self.stream_interceptor),
-        }
+        self.with_sharding(None)
     }
 }
@@ -6,15 +6,18 @@ use std::{
 
 use async_trait::async_trait;
 use futures::{stream::FuturesUnordered, FutureExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "in-memory-infra")]
 use crate::helpers::in_memory_config::InspectContext;
 use crate::{
     helpers::{transport::routing::RouteId, HelperIdentity, Role, TransportIdentity},
-    protocol::{Gate, QueryId},
+    protocol::{Gate, ProtocolVersion, QueryId},
     sharding::ShardIndex,
 };
 
+mod dynamic;
+pub mod frame;
 mod handler;
 #[cfg(feature = "in-memory-infra")]
 mod in_memory;
@@ -23,6 +26,7 @@ mod receive;
 pub mod routing;
 mod stream;
 
+pub use dynamic::{BoxedTransport, DynTransport, DynTransportError};
 pub use handler::{
     make_owned_handler, Error as ApiError, HandlerBox, HandlerRef, HelperResponse, RequestHandler,
 };
@@ -35,8 +39,10 @@ pub use receive::{LogErrors, ReceiveRecords};
 #[cfg(feature = "web-app")]
 pub use stream::WrappedAxumBodyStream;
 pub use stream::{
-    BodyStream, BytesStream, LengthDelimitedStream, RecordsStream, SingleRecordStream,
-    StreamCollection, StreamKey, WrappedBoxBodyStream,
+    read_paired_input, read_site_input, read_tagged_input, Arm, BodyStream, BytesStream,
+    InputIntegrityError, InputManifest, LengthDelimitedStream, ManifestCheck, ManifestStream,
+    RecordCounter, RecordFraming, RecordParseError, RecordPrefixes, RecordsStream,
+    SingleRecordStream, StreamCollection, StreamKey, WrappedBoxBodyStream,
 };
 
 /// An identity of a peer that can be communicated with using [`Transport`]. There are currently two
@@ -269,8 +275,9 @@ impl RouteParams<NoResourceIdentifier, QueryId, Gate> for (QueryId, Gate) {
     }
 }
 
+/// Records sent without an explicit protocol version belong to [`ProtocolVersion::CURRENT`].
 impl RouteParams<RouteId, QueryId, Gate> for (RouteId, QueryId, Gate) {
-    type Params = &'static str;
+    type Params = String;
 
     fn resource_identifier(&self) -> RouteId {
         self.0
@@ -285,7 +292,29 @@ impl RouteParams<RouteId, QueryId, Gate> for (RouteId, QueryId, Gate) {
     }
 
     fn extra(&self) -> Self::Params {
-        ""
+        ProtocolVersion::CURRENT.to_string()
+    }
+}
+
+/// Records route whose step is rooted in the namespace of the given protocol version. The
+/// version travels inside [`RouteParams::extra`].
+impl RouteParams<RouteId, QueryId, Gate> for (RouteId, QueryId, ProtocolVersion, Gate) {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        self.0
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.1
+    }
+
+    fn gate(&self) -> Gate {
+        self.3.clone()
+    }
+
+    fn extra(&self) -> Self::Params {
+        self.2.to_string()
     }
 }
 
@@ -321,6 +350,19 @@ impl<I: TransportIdentity, E: Debug> From<Vec<(I, E)>> for BroadcastError<I, E>
     }
 }
 
+/// How a helper wants to receive step data from its peers.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepTransfer {
+    /// Peers send step data as soon as it is ready.
+    #[default]
+    Push,
+    /// The receiving helper asks the sending peer for the next range of every channel once it
+    /// is ready to accept it, and the peer serves that range from its send buffer. This keeps
+    /// a helper on a slow link from being overwhelmed by faster peers.
+    Pull,
+}
+
 /// Transport that supports per-query,per-step channels
 #[async_trait]
 pub trait Transport: Clone + Send + Sync + 'static {
@@ -366,6 +408,28 @@ pub trait Transport: Clone + Send + Sync + 'static {
         route: R,
     ) -> Self::RecordsStream;
 
+    /// Binds the query to the protocol version negotiated for it at prepare time. Transports
+    /// that accept record streams from the network use it to reject streams produced by a
+    /// different protocol version. Calling it again for the same query replaces the binding.
+    ///
+    /// The default implementation does nothing, which is fine for transports where all parties
+    /// share the same binary.
+    fn bind_protocol_version(&self, _query_id: QueryId, _version: ProtocolVersion) {}
+
+    /// Peers that asked to receive step data in [`StepTransfer::Pull`] mode. The leader
+    /// proposes this set to everyone else when it prepares a query.
+    fn pull_receivers(&self) -> Vec<Self::Identity> {
+        Vec::new()
+    }
+
+    /// Makes step data exchanged in this query with any of the `receivers` flow in
+    /// [`StepTransfer::Pull`] mode. It must be called with the same set on every party before
+    /// the query starts sending data.
+    ///
+    /// The default implementation does nothing, so transports that don't support pull mode
+    /// keep pushing data to everyone.
+    fn set_pull_receivers(&self, _query_id: QueryId, _receivers: &[Self::Identity]) {}
+
     /// Broadcasts a message to all peers, excluding this instance, collecting all failures and
     /// successes. This method waits for all responses and returns only when all peers responded.
     async fn broadcast<Q, S, R>(
@@ -1,4 +1,6 @@
 mod hybrid;
+mod template;
+mod validation;
 
 use std::{
     fmt::{Debug, Display, Formatter},
@@ -7,15 +9,33 @@ use std::{
 
 pub use hybrid::HybridQueryParams;
 use serde::{Deserialize, Deserializer, Serialize};
+pub use template::{
+    CreateFromTemplate, QueryTemplate, QueryTemplates, TemplateCommand, TemplateError,
+    TemplateList, TemplateOverrides, MAX_TEMPLATE_ID_LEN,
+};
+pub use validation::{
+    QueryPolicy, TooManyChannels, UnsupportedCap, ValidateQuery, ValidationProblem,
+    ValidationReport,
+};
 
 use crate::{
     ff::FieldType,
     helpers::{
         transport::{routing::RouteId, BodyStream, NoQueryId, NoStep},
-        RoleAssignment, RouteParams,
+        HelperIdentity, Role, RoleAssignment, RouteParams, StageConcurrency,
+    },
+    protocol::{
+        dp::NoiseReport,
+        ipa_prf::{
+            prf_sharding::{
+                credit_capping::CappingStrategy, time_to_conversion::TimeToConversionBuckets,
+            },
+            AggregationMethod, HistogramOverflow, TimestampBounds,
+        },
+        ProtocolVersion, QueryId,
     },
-    protocol::QueryId,
-    query::QueryStatus,
+    query::{DecryptionFailurePolicy, QueryStatus},
+    report::SiteDomainHash,
 };
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize)]
@@ -81,8 +101,8 @@ impl From<QuerySize> for usize {
     }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, Eq))]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Eq))]
 pub struct QueryConfig {
     pub size: QuerySize,
     pub field_type: FieldType,
@@ -101,6 +121,18 @@ pub struct PrepareQuery {
     pub query_id: QueryId,
     pub config: QueryConfig,
     pub roles: RoleAssignment,
+    /// Protocol version chosen by the leader for this query.
+    #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+    /// Helpers that receive step data in [`StepTransfer::Pull`] mode in this query.
+    ///
+    /// [`StepTransfer::Pull`]: crate::helpers::StepTransfer::Pull
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pull_receivers: Vec<HelperIdentity>,
+    /// Limits on the active work of IPA stages chosen by the leader for this query. Every helper
+    /// must use the same limits.
+    #[serde(default, skip_serializing_if = "StageConcurrency::is_unlimited")]
+    pub stage_concurrency: StageConcurrency,
 }
 
 impl RouteParams<RouteId, QueryId, NoStep> for PrepareQuery {
@@ -187,11 +219,123 @@ impl RouteParams<RouteId, QueryId, NoStep> for &PrepareQuery {
 pub struct QueryInput {
     pub query_id: QueryId,
     pub input_stream: BodyStream,
+    /// Set if the input of this query is split between several report collectors and this
+    /// upload is one of the slices.
+    pub contribution: Option<InputContribution>,
 }
 
 impl Debug for QueryInput {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "query_inputs[{:?}]", self.query_id)
+        write!(f, "query_inputs[{:?}]", self.query_id)?;
+        if let Some(contribution) = &self.contribution {
+            write!(f, "[contributor={}]", contribution.contributor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Declares one slice of the query input, uploaded by one of several report collectors that
+/// hold reports for the same query.
+///
+/// Helpers wait until every declared contributor uploaded its slice, then concatenate the slices
+/// and run the query on all of them. Every contributor must declare the same number of
+/// contributors and shuffle seed, and their counts must add up to the query size. The query
+/// fails if a slice does not hold as many reports as its contributor declared.
+///
+/// Only queries that take encrypted reports, without arms, sites, tags or a manifest, accept
+/// their input in slices.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputContribution {
+    /// Identifies the report collector that uploads this slice. Must be unique within a query.
+    pub contributor: u32,
+    /// Number of report collectors that contribute to the query.
+    pub contributors: NonZeroU32,
+    /// Number of reports in this slice.
+    pub count: u32,
+    /// If set, slices are concatenated in an order derived from this seed. Otherwise they
+    /// are concatenated in the order of contributor ids. Either way, this only permutes whole
+    /// slices; reports are not shuffled within or across them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shuffle_seed: Option<u64>,
+}
+
+impl RouteParams<RouteId, QueryId, NoStep> for (QueryId, InputContribution) {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::QueryInput
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.0
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(&self.1).unwrap()
+    }
+}
+
+/// Declares one batch of a query input that is sealed later with [`SealInput`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppendInput {
+    /// Number of reports in this batch.
+    pub reports: u32,
+}
+
+impl RouteParams<RouteId, QueryId, NoStep> for (QueryId, AppendInput) {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::AppendQueryInput
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.0
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(&self.1).unwrap()
+    }
+}
+
+/// Ends the upload of a query input that was sent in batches, for report collectors that keep
+/// receiving reports after they started uploading them.
+///
+/// Until the input is sealed, every upload appends a batch of reports to it. Sealing fixes the
+/// size of the query to the total number of reports in all batches and starts running it. The
+/// sealed count must match the number of reports declared by the batches, and helpers confirm
+/// with each other that they were sealed with the same count before the query starts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealInput {
+    /// Total number of reports in all batches.
+    pub count: QuerySize,
+}
+
+impl RouteParams<RouteId, QueryId, NoStep> for (QueryId, SealInput) {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::SealQueryInput
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.0
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(&self.1).unwrap()
     }
 }
 
@@ -222,8 +366,37 @@ impl RouteParams<RouteId, QueryId, NoStep> for CompareStatusRequest {
     }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+/// Tells a helper that a query can't finish, because the helper that plays `role` in it
+/// stopped responding.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct PeerUnavailable {
+    pub query_id: QueryId,
+    pub role: Role,
+}
+
+impl RouteParams<RouteId, QueryId, NoStep> for PeerUnavailable {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::PeerUnavailable
+    }
+
+    fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Eq))]
 pub enum QueryType {
     #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
     TestMultiply,
@@ -231,6 +404,8 @@ pub enum QueryType {
     TestAddInPrimeField,
     #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
     TestShardedShuffle,
+    #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+    TestShareConversion(ShareConversionParams),
     SemiHonestOprfIpa(IpaQueryConfig),
     MaliciousOprfIpa(IpaQueryConfig),
     MaliciousHybrid(HybridQueryParams),
@@ -241,6 +416,7 @@ impl QueryType {
     pub const TEST_MULTIPLY_STR: &'static str = "test-multiply";
     pub const TEST_ADD_STR: &'static str = "test-add";
     pub const TEST_SHARDED_SHUFFLE_STR: &'static str = "test-sharded-shuffle";
+    pub const TEST_SHARE_CONVERSION_STR: &'static str = "test-share-conversion";
     pub const SEMI_HONEST_OPRF_IPA_STR: &'static str = "semi-honest-oprf-ipa";
     pub const MALICIOUS_OPRF_IPA_STR: &'static str = "malicious-oprf-ipa";
     pub const MALICIOUS_HYBRID_STR: &'static str = "malicious-hybrid";
@@ -256,6 +432,8 @@ impl AsRef<str> for QueryType {
             QueryType::TestAddInPrimeField => Self::TEST_ADD_STR,
             #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
             QueryType::TestShardedShuffle => Self::TEST_SHARDED_SHUFFLE_STR,
+            #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+            QueryType::TestShareConversion(_) => Self::TEST_SHARE_CONVERSION_STR,
             QueryType::SemiHonestOprfIpa(_) => Self::SEMI_HONEST_OPRF_IPA_STR,
             QueryType::MaliciousOprfIpa(_) => Self::MALICIOUS_OPRF_IPA_STR,
             QueryType::MaliciousHybrid(_) => Self::MALICIOUS_HYBRID_STR,
@@ -263,6 +441,33 @@ impl AsRef<str> for QueryType {
     }
 }
 
+/// Parameters of [`QueryType::TestShareConversion`], which benchmarks the conversion of Boolean
+/// shares into Fp25519 shares on its own. Helpers generate the input from PRSS, so the query
+/// needs no input from the client.
+#[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct ShareConversionParams {
+    /// Width of the converted values, in bits.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "64"))]
+    pub bits: u32,
+}
+
+#[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+impl ShareConversionParams {
+    /// Widest value the conversion supports. The masks it uses are 256 bits wide and must
+    /// leave at least 128 bits of statistical security.
+    pub const MAX_BITS: u32 = 127;
+}
+
+#[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+impl Default for ShareConversionParams {
+    fn default() -> Self {
+        // The width of match keys, which is what OPRF IPA converts.
+        Self { bits: 64 }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum DpMechanism {
     NoDp,
@@ -270,12 +475,33 @@ pub enum DpMechanism {
     DiscreteLaplace { epsilon: f64 },
 }
 
+impl DpMechanism {
+    /// Privacy parameters for one of `parts` releases that together must not exceed the budget
+    /// of `self`. By sequential composition, the epsilon is divided evenly between them.
+    #[must_use]
+    pub fn split_budget(self, parts: u32) -> Self {
+        let parts = f64::from(parts);
+        match self {
+            Self::NoDp => Self::NoDp,
+            Self::Binomial { epsilon } => Self::Binomial {
+                epsilon: epsilon / parts,
+            },
+            Self::DiscreteLaplace { epsilon } => Self::DiscreteLaplace {
+                epsilon: epsilon / parts,
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 impl Eq for IpaQueryConfig {}
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
 #[cfg_attr(feature = "clap", derive(clap::Args))]
 pub struct IpaQueryConfig {
+    /// Maximum total value a single user can contribute to the output. `0` disables capping,
+    /// which is only allowed for queries without DP noise. Caps that are not a power of two are
+    /// enforced exactly, but DP noise is calibrated to the next power of two.
     #[cfg_attr(feature = "clap", arg(long, default_value = "8"))]
     pub per_user_credit_cap: u32,
     #[cfg_attr(feature = "clap", arg(long, default_value = "5"))]
@@ -294,6 +520,270 @@ pub struct IpaQueryConfig {
     #[cfg_attr(feature = "clap", arg(long))]
     #[serde(default)]
     pub plaintext_match_keys: bool,
+
+    /// Number of bits used to represent trigger values in the input reports. Must be one of
+    /// 3, 8, or 16. Wider trigger values allow larger purchase values to be reported without
+    /// truncation, at the cost of more expensive attribution and aggregation circuits.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "3"))]
+    #[serde(default = "IpaQueryConfig::default_trigger_value_bits")]
+    pub trigger_value_bits: u32,
+
+    /// Number of bits used to represent breakdown keys in the input reports. Must be one of
+    /// [`IpaQueryConfig::SUPPORTED_BREAKDOWN_KEY_BITS`] and wide enough to hold
+    /// `max_breakdown_key` buckets. Queries with few breakdowns can use narrower keys to pay
+    /// less for sorting and aggregation.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "8"))]
+    #[serde(default = "IpaQueryConfig::default_breakdown_key_bits")]
+    pub breakdown_key_bits: u32,
+
+    /// Number of bits used to represent timestamps in the input reports. Must be one of
+    /// [`IpaQueryConfig::SUPPORTED_TIMESTAMP_BITS`].
+    #[cfg_attr(feature = "clap", arg(long, default_value = "20"))]
+    #[serde(default = "IpaQueryConfig::default_timestamp_bits")]
+    pub timestamp_bits: u32,
+
+    /// Length, in seconds, of one timestamp unit. Report timestamps are expressed in multiples
+    /// of this value, so coarser units let the same number of timestamp bits cover a longer
+    /// range. This value is public and is shared by all reports in a query.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "1"))]
+    #[serde(default = "IpaQueryConfig::default_timestamp_granularity_seconds")]
+    pub timestamp_granularity_seconds: NonZeroU32,
+
+    /// If set, trigger events with timestamps below this one, in timestamp units, get no
+    /// credit. Helpers compare timestamps against the bounds obliviously, and the output
+    /// contains another histogram, following all others, with the number of suppressed trigger
+    /// events in its first bucket. That count is only released with DP noise, so queries
+    /// without DP can't set bounds. Every user adds at most the per-user cap to it, and it takes
+    /// a share of the DP budget like any other histogram.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub min_timestamp: Option<u32>,
+
+    /// If set, trigger events with timestamps above this one, in timestamp units, get no
+    /// credit, see `min_timestamp`. Must fit into `timestamp_bits`.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub max_timestamp: Option<u32>,
+
+    /// If true, the report collector accepts a partial result when noise can't be added to
+    /// some of the histograms that follow the histogram of attributed values: helpers return
+    /// those histograms zeroed instead of an error. Nothing is ever returned without noise.
+    /// Results of such queries tell whether they are partial, see
+    /// [`crate::protocol::ipa_prf::Release`].
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub allow_partial_results: bool,
+
+    /// If true, the output contains a second histogram with the number of attributed
+    /// conversions per breakdown key, following the histogram of attributed values. Counts are
+    /// capped per user the same way values are, and the DP budget is split evenly between the
+    /// two histograms.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub attributed_counts: bool,
+
+    /// If set, the output contains another histogram, following all others, with the number of
+    /// attributed conversions per bucket of time between a conversion and the source event it
+    /// was attributed to. Buckets are this many seconds wide, rounded up to whole timestamp
+    /// units, except for the last one, which holds all longer times. The DP budget is split
+    /// evenly between all histograms of the output.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub time_to_conversion_bucket_seconds: Option<NonZeroU32>,
+
+    /// Number of buckets of the time-to-conversion histogram. Must be between 2 and
+    /// [`MAX_TIME_TO_CONVERSION_BUCKETS`].
+    ///
+    /// [`MAX_TIME_TO_CONVERSION_BUCKETS`]: crate::protocol::ipa_prf::prf_sharding::time_to_conversion::MAX_TIME_TO_CONVERSION_BUCKETS
+    #[cfg_attr(feature = "clap", arg(long, default_value = "8"))]
+    #[serde(default = "IpaQueryConfig::default_time_to_conversion_buckets")]
+    pub time_to_conversion_buckets: u32,
+
+    /// If true, trigger values are interpreted as two's complement signed integers, so that
+    /// refunds and other adjustments can be reported as negative values. The per-user cap then
+    /// bounds the sum of absolute trigger values, and the output histogram holds signed
+    /// values, also in two's complement.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub signed_trigger_values: bool,
+
+    /// If set, caps the credit any single source event can receive, before the per-user cap is
+    /// applied. Must be a power of two that does not exceed `per_user_credit_cap`.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub per_source_event_cap: Option<u32>,
+
+    /// If set, a source event can be credited by at most this many of the trigger events that
+    /// follow it. Later trigger events that would be attributed to it get no credit. Must be
+    /// between 1 and [`Self::MAX_PER_SOURCE_TRIGGER_LIMIT`].
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub per_source_trigger_limit: Option<u32>,
+
+    /// How the contributions of a user that exceed `per_user_credit_cap` are reduced. The
+    /// default hard cap drops contributions in timestamp order once the cap is reached, while
+    /// the proportional cap scales all of them down by the same factor.
+    #[cfg_attr(feature = "clap", arg(long, value_enum, default_value_t = CappingStrategy::Hard))]
+    #[serde(default)]
+    pub capping_strategy: CappingStrategy,
+
+    /// How attributed values are added up into the output histogram. The default reveals the
+    /// breakdown key of every shuffled and padded attribution output, while oblivious
+    /// aggregation reveals nothing at a higher compute cost.
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, value_enum, default_value_t = AggregationMethod::BreakdownReveal)
+    )]
+    #[serde(default)]
+    pub aggregation_method: AggregationMethod,
+
+    /// If true, breakdown reveal aggregation drops attribution outputs that carry no value
+    /// before revealing breakdown keys, which makes aggregation much cheaper when most outputs
+    /// are zero after capping. Helpers reveal, for every shuffled output, whether its value is
+    /// zero. Padding rows are kept, so the number of outputs per breakdown key is protected by
+    /// the same padding as without pruning, but that number then counts the outputs with
+    /// credit, i.e. conversions, instead of all of them. This count is protected by aggregation
+    /// padding only, not by the DP noise of the query. Only supported by semi-honest queries,
+    /// helpers reject malicious queries that set it.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub prune_zero_rows: bool,
+
+    /// Number of bits used to represent values in the output histogram. Must be one of
+    /// [`IpaQueryConfig::SUPPORTED_HISTOGRAM_VALUE_BITS`]. Narrower values make aggregation
+    /// cheaper, but high-volume queries may overflow them, see `histogram_overflow`. Set to
+    /// [`IpaQueryConfig::AUTO_HISTOGRAM_VALUE_BITS`] to let helpers pick the narrowest width
+    /// that can't overflow, see [`IpaQueryConfig::histogram_value_bits_for`].
+    #[cfg_attr(feature = "clap", arg(long, default_value = "32"))]
+    #[serde(default = "IpaQueryConfig::default_histogram_value_bits")]
+    pub histogram_value_bits: u32,
+
+    /// What a histogram bucket holds when the sum of its values does not fit into
+    /// `histogram_value_bits`. Saturated buckets hold the largest representable value, wrapped
+    /// buckets the sum modulo `2^histogram_value_bits`. Signed histograms always wrap.
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, value_enum, default_value_t = HistogramOverflow::Saturate)
+    )]
+    #[serde(default)]
+    pub histogram_overflow: HistogramOverflow,
+
+    /// If true, helpers check that their replicated shares of the output agree with those of
+    /// the other helpers before releasing them. A mismatch fails the query, instead of
+    /// producing a result that the report collector can't reconstruct.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub verify_output_shares: bool,
+
+    /// Registrable domain of the site the reports were collected for. If set, helpers reject
+    /// encrypted reports whose `site_domain` is not this domain or one of its subdomains. Only
+    /// the digest of the domain is sent to the helpers. This is not checked for plaintext
+    /// match keys, as those inputs carry no site.
+    #[cfg_attr(
+        feature = "clap",
+        arg(long = "site-domain", value_parser = SiteDomainHash::from_domain)
+    )]
+    #[serde(default)]
+    pub site_domain_hash: Option<SiteDomainHash>,
+
+    /// If set, only this share of users is attributed and aggregated, to reduce the cost of
+    /// queries over very large inputs. Users are sampled uniformly after PRF evaluation, see
+    /// [`UserSampling`]. The output is not rescaled by the helpers: report collectors need to
+    /// divide it by this rate. Must be in `(0, 1]`.
+    ///
+    /// [`UserSampling`]: crate::protocol::ipa_prf::UserSampling
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub user_sampling_rate: Option<f64>,
+
+    /// If true, the input sent to each helper ends with an [`InputManifest`] carrying the
+    /// number of records and the digest of the input. Helpers read the whole input and check it
+    /// against the manifest before starting to compute, so inputs that were truncated or
+    /// corrupted on upload fail the query instead of being silently processed.
+    ///
+    /// [`InputManifest`]: crate::helpers::InputManifest
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub input_manifest: bool,
+
+    /// What helpers do with reports that fail to decrypt, or that were collected for another
+    /// site. By default the query fails.
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, value_enum, default_value_t = DecryptionFailurePolicy::Abort)
+    )]
+    #[serde(default)]
+    pub decryption_failure_policy: DecryptionFailurePolicy,
+
+    /// Largest share of the input that may be dropped under the skip and quarantine
+    /// [`decryption_failure_policy`]. Helpers fail queries that drop more than that after all,
+    /// since it points to a problem with the whole input. Must be in `[0, 1]`.
+    ///
+    /// [`decryption_failure_policy`]: Self::decryption_failure_policy
+    #[cfg_attr(feature = "clap", arg(long, default_value = "0.01"))]
+    #[serde(default = "IpaQueryConfig::default_max_decryption_failure_rate")]
+    pub max_decryption_failure_rate: f64,
+
+    /// If true, helpers that have a PRF cache keep the padded, shuffled and PRF'd input of this
+    /// query, and queries over the same reports that set this flag too skip these stages.
+    /// Every such query is charged its epsilon against the input, and helpers refuse queries
+    /// that exceed the budget of their cache. See [`prf_cache`] for details.
+    ///
+    /// [`prf_cache`]: crate::query::prf_cache
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub cache_prf: bool,
+
+    /// If true, the input holds the reports of the two arms of an experiment, control and
+    /// treatment, and every record in it is preceded by a public [`Arm`] byte. Both arms are
+    /// padded, shuffled, PRF'd and sorted together. Each of them gets half of the breakdown
+    /// keys, so `max_breakdown_key` must fit into `breakdown_key_bits - 1` bits: the lower half
+    /// of every output histogram holds the control arm, the upper half the treatment arm.
+    /// Users are capped across both arms, so the two halves share the DP budget of the query
+    /// without splitting it. Can't be combined with time-to-conversion histograms or input
+    /// manifests.
+    ///
+    /// [`Arm`]: crate::helpers::Arm
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub paired_arms: bool,
+
+    /// If set, the input holds the reports of this many source sites, and every record in it is
+    /// preceded by one public byte with the index of its site. Each site gets the same share of
+    /// the breakdown keys: the site index takes the top [`Self::source_site_bits`] bits of the
+    /// breakdown key, so `max_breakdown_key` must fit into the remaining ones, and every output
+    /// histogram holds the breakdowns of all sites, one after the other. Conversions are
+    /// credited to the site of the source event they are attributed to. The reports of
+    /// different sites are disjoint, so helpers with a PRF cache charge the epsilon of the
+    /// query to each site separately. Must be between 2 and [`Self::MAX_SOURCE_SITES`], and
+    /// can't be combined with paired arms, time-to-conversion histograms or input manifests.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub source_sites: Option<u32>,
+
+    /// If set, the input holds the reports of this many sub-queries, and every record in it is
+    /// preceded by one public byte with its tag, something the report collector already knows
+    /// about the report, like the campaign it belongs to. Helpers run all sub-queries together,
+    /// as if the reports of every tag came from a separate set of users: the tag is mixed into
+    /// the match key before the PRF, so attribution and per-user capping never cross tags. Like
+    /// source sites, every tag gets the same share of the breakdown keys: the tag takes the top
+    /// [`Self::public_tag_bits`] bits of the breakdown key, and the output histogram holds the
+    /// breakdowns of all tags, one after the other. Every sub-query spends the full epsilon of
+    /// the query, so a user with reports under several tags is charged once per tag. Must be
+    /// between 2 and [`Self::MAX_PUBLIC_TAGS`], and can't be combined with paired arms, source
+    /// sites, time-to-conversion histograms, input manifests or PRF caching.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub public_tags: Option<u32>,
+
+    /// If true, every helper holds on to its output shares until both of its peers confirm
+    /// that they completed the same query, so the results of a query that one of the helpers
+    /// aborted are never released. See [`escrow`] for details.
+    ///
+    /// [`escrow`]: crate::query::escrow
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub escrow_results: bool,
 }
 
 impl Default for IpaQueryConfig {
@@ -305,11 +795,270 @@ impl Default for IpaQueryConfig {
             with_dp: 1,
             epsilon: 0.10,
             plaintext_match_keys: false,
+            trigger_value_bits: Self::DEFAULT_TRIGGER_VALUE_BITS,
+            breakdown_key_bits: Self::DEFAULT_BREAKDOWN_KEY_BITS,
+            timestamp_bits: Self::DEFAULT_TIMESTAMP_BITS,
+            timestamp_granularity_seconds: Self::default_timestamp_granularity_seconds(),
+            min_timestamp: None,
+            max_timestamp: None,
+            allow_partial_results: false,
+            attributed_counts: false,
+            time_to_conversion_bucket_seconds: None,
+            time_to_conversion_buckets: Self::DEFAULT_TIME_TO_CONVERSION_BUCKETS,
+            signed_trigger_values: false,
+            per_source_event_cap: None,
+            per_source_trigger_limit: None,
+            capping_strategy: CappingStrategy::Hard,
+            aggregation_method: AggregationMethod::BreakdownReveal,
+            prune_zero_rows: false,
+            histogram_value_bits: Self::DEFAULT_HISTOGRAM_VALUE_BITS,
+            histogram_overflow: HistogramOverflow::Saturate,
+            verify_output_shares: false,
+            site_domain_hash: None,
+            user_sampling_rate: None,
+            input_manifest: false,
+            decryption_failure_policy: DecryptionFailurePolicy::Abort,
+            max_decryption_failure_rate: Self::DEFAULT_MAX_DECRYPTION_FAILURE_RATE,
+            cache_prf: false,
+            paired_arms: false,
+            source_sites: None,
+            public_tags: None,
+            escrow_results: false,
         }
     }
 }
 
 impl IpaQueryConfig {
+    /// Largest per-user cap that OPRF IPA has instantiations for.
+    pub const MAX_PER_USER_CREDIT_CAP: u32 = 128;
+
+    /// Largest per-source trigger limit. Attribution counts the triggers of every source event
+    /// with an 8-bit counter.
+    pub const MAX_PER_SOURCE_TRIGGER_LIMIT: u32 = 128;
+
+    /// Trigger value width used by reports that do not specify one explicitly.
+    pub const DEFAULT_TRIGGER_VALUE_BITS: u32 = 3;
+
+    /// Trigger value widths that OPRF IPA has instantiations for.
+    pub const SUPPORTED_TRIGGER_VALUE_BITS: &'static [u32] = &[3, 8, 16];
+
+    /// Breakdown key width used by reports that do not specify one explicitly.
+    pub const DEFAULT_BREAKDOWN_KEY_BITS: u32 = 8;
+
+    /// Breakdown key widths that OPRF IPA has instantiations for.
+    pub const SUPPORTED_BREAKDOWN_KEY_BITS: &'static [u32] = &[5, 8, 10, 12];
+
+    /// Timestamp width used by reports that do not specify one explicitly.
+    pub const DEFAULT_TIMESTAMP_BITS: u32 = 20;
+
+    /// Timestamp widths that OPRF IPA has instantiations for. The upper bound comes from
+    /// the 32-bit sort key, which also holds a record counter and the trigger bit.
+    pub const SUPPORTED_TIMESTAMP_BITS: &'static [u32] = &[20, 24];
+
+    /// Histogram value width used by queries that do not specify one explicitly.
+    pub const DEFAULT_HISTOGRAM_VALUE_BITS: u32 = 32;
+
+    /// Histogram value widths that OPRF IPA has instantiations for. Aggregation adds values
+    /// with a 32-bit adder, so wider histograms would need another addition step.
+    pub const SUPPORTED_HISTOGRAM_VALUE_BITS: &'static [u32] = &[16, 32];
+
+    /// Histogram value width that asks helpers to pick one from the query size.
+    pub const AUTO_HISTOGRAM_VALUE_BITS: u32 = 0;
+
+    /// Share of reports that may fail to decrypt, unless the query sets its own limit.
+    pub const DEFAULT_MAX_DECRYPTION_FAILURE_RATE: f64 = 0.01;
+
+    /// Number of time-to-conversion buckets used by queries that do not specify one explicitly.
+    pub const DEFAULT_TIME_TO_CONVERSION_BUCKETS: u32 = 8;
+
+    /// Largest number of source sites a query can report per-site aggregates for.
+    pub const MAX_SOURCE_SITES: u32 = 16;
+
+    /// Largest number of tags, and so sub-queries, a query can split its input into.
+    pub const MAX_PUBLIC_TAGS: u32 = 8;
+
+    fn default_trigger_value_bits() -> u32 {
+        Self::DEFAULT_TRIGGER_VALUE_BITS
+    }
+
+    fn default_breakdown_key_bits() -> u32 {
+        Self::DEFAULT_BREAKDOWN_KEY_BITS
+    }
+
+    fn default_timestamp_bits() -> u32 {
+        Self::DEFAULT_TIMESTAMP_BITS
+    }
+
+    fn default_histogram_value_bits() -> u32 {
+        Self::DEFAULT_HISTOGRAM_VALUE_BITS
+    }
+
+    fn default_timestamp_granularity_seconds() -> NonZeroU32 {
+        NonZeroU32::MIN
+    }
+
+    fn default_max_decryption_failure_rate() -> f64 {
+        Self::DEFAULT_MAX_DECRYPTION_FAILURE_RATE
+    }
+
+    fn default_time_to_conversion_buckets() -> u32 {
+        Self::DEFAULT_TIME_TO_CONVERSION_BUCKETS
+    }
+
+    /// Returns the attribution window expressed in timestamp units, rounding up so that
+    /// events that are within the window in seconds are never excluded.
+    #[must_use]
+    pub fn attribution_window_units(&self) -> Option<NonZeroU32> {
+        self.attribution_window_seconds.map(|window| {
+            window
+                .get()
+                .div_ceil(self.timestamp_granularity_seconds.get())
+                .try_into()
+                .expect("ceiling division of a positive value is positive")
+        })
+    }
+
+    /// Returns the number of top breakdown key bits that hold the source site, if the query
+    /// reports per-site aggregates.
+    #[must_use]
+    pub fn source_site_bits(&self) -> Option<u32> {
+        self.source_sites
+            .map(|sites| sites.max(1).next_power_of_two().trailing_zeros())
+    }
+
+    /// Returns the number of top breakdown key bits that hold the public tag, if the input is
+    /// tagged.
+    #[must_use]
+    pub fn public_tag_bits(&self) -> Option<u32> {
+        self.public_tags
+            .map(|tags| tags.max(1).next_power_of_two().trailing_zeros())
+    }
+
+    /// Returns the range of timestamps the query covers, if it declares one. A bound that is not
+    /// set is the smallest or largest timestamp that fits into `timestamp_bits`. Queries with
+    /// empty bounds are rejected, this returns `None` for them.
+    #[must_use]
+    pub fn timestamp_bounds(&self) -> Option<TimestampBounds> {
+        if !self.has_timestamp_bounds() {
+            return None;
+        }
+        let largest = u32::try_from((1_u64 << self.timestamp_bits.min(u32::BITS)) - 1)
+            .expect("largest timestamp fits into 32 bits");
+        let min = self.min_timestamp.unwrap_or(0);
+        let max = self.max_timestamp.unwrap_or(largest);
+        (min <= max).then(|| TimestampBounds::new(min, max))
+    }
+
+    /// Returns `true` if the output contains the histogram of suppressed trigger events, see
+    /// `min_timestamp`.
+    #[must_use]
+    pub fn has_timestamp_bounds(&self) -> bool {
+        self.min_timestamp.is_some() || self.max_timestamp.is_some()
+    }
+
+    /// Number of histograms in the output of the query. The histogram of attributed values is
+    /// followed by attributed conversion counts, the time-to-conversion histogram and the
+    /// histogram of suppressed trigger events, if the query asks for them, in this order.
+    #[must_use]
+    pub fn output_histograms(&self) -> u32 {
+        1 + u32::from(self.attributed_counts)
+            + u32::from(self.time_to_conversion_bucket_seconds.is_some())
+            + u32::from(self.has_timestamp_bounds())
+    }
+
+    /// Returns the buckets of the time-to-conversion histogram, if the query asks for one, with
+    /// bucket widths expressed in timestamp units and rounded up like the attribution window.
+    ///
+    /// ## Panics
+    /// If the number of buckets is not supported.
+    #[must_use]
+    pub fn time_to_conversion(&self) -> Option<TimeToConversionBuckets> {
+        self.time_to_conversion_bucket_seconds.map(|width| {
+            TimeToConversionBuckets::linear(
+                width
+                    .get()
+                    .div_ceil(self.timestamp_granularity_seconds.get())
+                    .try_into()
+                    .expect("ceiling division of a positive value is positive"),
+                usize::try_from(self.time_to_conversion_buckets).unwrap(),
+            )
+        })
+    }
+
+    /// DP mechanism that adds noise to the output of the query.
+    #[must_use]
+    pub fn dp_mechanism(&self) -> DpMechanism {
+        match self.with_dp {
+            0 => DpMechanism::NoDp,
+            _ => DpMechanism::DiscreteLaplace {
+                epsilon: self.epsilon,
+            },
+        }
+    }
+
+    /// Width of the values in the output histogram of a query over `size` reports.
+    ///
+    /// Attribution runs over trigger values, which are narrow. Aggregation widens them to this
+    /// width, and adds DP noise at this width as well. If the query does not set
+    /// `histogram_value_bits`, this is the narrowest supported width that holds the largest
+    /// total a bucket can reach, noise included, so that the cheaper aggregation never
+    /// overflows. Helpers and report collectors must agree on it, so it only depends on the
+    /// query config and size.
+    #[must_use]
+    pub fn histogram_value_bits_for(&self, size: QuerySize) -> u32 {
+        let widest = *Self::SUPPORTED_HISTOGRAM_VALUE_BITS.last().unwrap();
+        if self.histogram_value_bits != Self::AUTO_HISTOGRAM_VALUE_BITS {
+            return self.histogram_value_bits;
+        }
+        // Widths and caps the protocols don't support overflow the bounds below. The query is
+        // rejected anyway.
+        if !Self::SUPPORTED_TRIGGER_VALUE_BITS.contains(&self.trigger_value_bits)
+            || !Self::SUPPORTED_BREAKDOWN_KEY_BITS.contains(&self.breakdown_key_bits)
+            || self.per_user_credit_cap > Self::MAX_PER_USER_CREDIT_CAP
+        {
+            return widest;
+        }
+        // Every user contributes at most the cap to a bucket, and there are no more users than
+        // reports. Without a cap, every report contributes at most the largest trigger value.
+        // Conversion counts are bounded the same way.
+        let max_trigger_value = (1_u64 << self.trigger_value_bits) - 1;
+        let per_report = match self.per_user_credit_cap {
+            0 => max_trigger_value,
+            cap => u64::from(cap).min(max_trigger_value),
+        };
+        let histograms = self.output_histograms();
+        let noise = match NoiseReport::with_sensitivity(
+            self.dp_mechanism().split_budget(histograms),
+            self.per_user_credit_cap.next_power_of_two().max(2),
+            1 << self.breakdown_key_bits,
+            histograms,
+        ) {
+            Ok(report) => report.map_or(0, |report| report.max_magnitude()),
+            // The query is rejected anyway.
+            Err(_) => return widest,
+        };
+        let max_total = u64::from(u32::from(size)) * per_report + noise;
+
+        Self::SUPPORTED_HISTOGRAM_VALUE_BITS
+            .iter()
+            .copied()
+            .find(|&bits| {
+                let magnitude_bits = bits - u32::from(self.signed_trigger_values);
+                max_total < 1 << magnitude_bits
+            })
+            .unwrap_or(widest)
+    }
+
+    /// Same config, with [`Self::AUTO_HISTOGRAM_VALUE_BITS`] replaced by the width picked for a
+    /// query over `size` reports.
+    #[must_use]
+    pub fn resolve_histogram_value_bits(self, size: QuerySize) -> Self {
+        Self {
+            histogram_value_bits: self.histogram_value_bits_for(size),
+            ..self
+        }
+    }
+
     /// ## Panics
     /// If attribution window is 0
     #[must_use]
@@ -331,6 +1080,35 @@ impl IpaQueryConfig {
             epsilon,
             // dp_params,
             plaintext_match_keys: false,
+            trigger_value_bits: Self::DEFAULT_TRIGGER_VALUE_BITS,
+            breakdown_key_bits: Self::DEFAULT_BREAKDOWN_KEY_BITS,
+            timestamp_bits: Self::DEFAULT_TIMESTAMP_BITS,
+            timestamp_granularity_seconds: Self::default_timestamp_granularity_seconds(),
+            min_timestamp: None,
+            max_timestamp: None,
+            allow_partial_results: false,
+            attributed_counts: false,
+            time_to_conversion_bucket_seconds: None,
+            time_to_conversion_buckets: Self::DEFAULT_TIME_TO_CONVERSION_BUCKETS,
+            signed_trigger_values: false,
+            per_source_event_cap: None,
+            per_source_trigger_limit: None,
+            capping_strategy: CappingStrategy::Hard,
+            aggregation_method: AggregationMethod::BreakdownReveal,
+            prune_zero_rows: false,
+            histogram_value_bits: Self::DEFAULT_HISTOGRAM_VALUE_BITS,
+            histogram_overflow: HistogramOverflow::Saturate,
+            verify_output_shares: false,
+            site_domain_hash: None,
+            user_sampling_rate: None,
+            input_manifest: false,
+            decryption_failure_policy: DecryptionFailurePolicy::Abort,
+            max_decryption_failure_rate: Self::DEFAULT_MAX_DECRYPTION_FAILURE_RATE,
+            cache_prf: false,
+            paired_arms: false,
+            source_sites: None,
+            public_tags: None,
+            escrow_results: false,
         }
     }
 
@@ -352,6 +1130,35 @@ impl IpaQueryConfig {
             with_dp,
             epsilon,
             plaintext_match_keys: false,
+            trigger_value_bits: Self::DEFAULT_TRIGGER_VALUE_BITS,
+            breakdown_key_bits: Self::DEFAULT_BREAKDOWN_KEY_BITS,
+            timestamp_bits: Self::DEFAULT_TIMESTAMP_BITS,
+            timestamp_granularity_seconds: Self::default_timestamp_granularity_seconds(),
+            min_timestamp: None,
+            max_timestamp: None,
+            allow_partial_results: false,
+            attributed_counts: false,
+            time_to_conversion_bucket_seconds: None,
+            time_to_conversion_buckets: Self::DEFAULT_TIME_TO_CONVERSION_BUCKETS,
+            signed_trigger_values: false,
+            per_source_event_cap: None,
+            per_source_trigger_limit: None,
+            capping_strategy: CappingStrategy::Hard,
+            aggregation_method: AggregationMethod::BreakdownReveal,
+            prune_zero_rows: false,
+            histogram_value_bits: Self::DEFAULT_HISTOGRAM_VALUE_BITS,
+            histogram_overflow: HistogramOverflow::Saturate,
+            verify_output_shares: false,
+            site_domain_hash: None,
+            user_sampling_rate: None,
+            input_manifest: false,
+            decryption_failure_policy: DecryptionFailurePolicy::Abort,
+            max_decryption_failure_rate: Self::DEFAULT_MAX_DECRYPTION_FAILURE_RATE,
+            cache_prf: false,
+            paired_arms: false,
+            source_sites: None,
+            public_tags: None,
+            escrow_results: false,
         }
     }
 }
@@ -385,3 +1192,89 @@ impl std::fmt::Display for ContributionBits {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{IpaQueryConfig, QuerySize};
+    use crate::protocol::dp::NoiseReport;
+
+    fn bits(config: IpaQueryConfig, size: u32) -> u32 {
+        config.histogram_value_bits_for(QuerySize::try_from(size).unwrap())
+    }
+
+    fn auto() -> IpaQueryConfig {
+        IpaQueryConfig {
+            histogram_value_bits: IpaQueryConfig::AUTO_HISTOGRAM_VALUE_BITS,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        }
+    }
+
+    #[test]
+    fn explicit_histogram_value_bits() {
+        let config = IpaQueryConfig {
+            histogram_value_bits: 16,
+            ..IpaQueryConfig::default()
+        };
+        assert_eq!(16, bits(config, QuerySize::MAX));
+    }
+
+    #[test]
+    fn histogram_value_bits_from_size() {
+        // Reports contribute at most 7, the largest 3 bit trigger value.
+        assert_eq!(16, bits(auto(), 65_535 / 7));
+        assert_eq!(32, bits(auto(), 65_535 / 7 + 1));
+
+        let capped = IpaQueryConfig {
+            per_user_credit_cap: 2,
+            ..auto()
+        };
+        assert_eq!(16, bits(capped, 65_535 / 2));
+        assert_eq!(32, bits(capped, 65_535 / 2 + 1));
+
+        let signed = IpaQueryConfig {
+            signed_trigger_values: true,
+            ..capped
+        };
+        assert_eq!(16, bits(signed, 32_767 / 2));
+        assert_eq!(32, bits(signed, 32_767 / 2 + 1));
+    }
+
+    #[test]
+    fn histogram_value_bits_leave_room_for_noise() {
+        let config = IpaQueryConfig {
+            with_dp: 1,
+            epsilon: 1.0,
+            attributed_counts: true,
+            ..auto()
+        };
+        let noise = NoiseReport::with_sensitivity(config.dp_mechanism().split_budget(2), 8, 256, 2)
+            .unwrap()
+            .unwrap()
+            .max_magnitude();
+        let size = u32::try_from((65_535 - noise) / 7).unwrap();
+        assert_eq!(16, bits(config, size));
+        assert_eq!(32, bits(config, size + 1));
+    }
+
+    #[test]
+    fn histogram_value_bits_for_unsupported_config() {
+        for config in [
+            IpaQueryConfig {
+                trigger_value_bits: 64,
+                ..auto()
+            },
+            IpaQueryConfig {
+                breakdown_key_bits: u32::MAX,
+                ..auto()
+            },
+            IpaQueryConfig {
+                per_user_credit_cap: u32::MAX,
+                with_dp: 1,
+                ..auto()
+            },
+        ] {
+            assert_eq!(32, bits(config, 1));
+        }
+    }
+}
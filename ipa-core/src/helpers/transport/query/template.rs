@@ -0,0 +1,673 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ff::FieldType,
+    helpers::{
+        query::{QueryConfig, QueryPolicy, QuerySize, QueryType, ValidationReport},
+        routing::RouteId,
+        NoQueryId, NoStep, RouteParams,
+    },
+    report::SiteDomainHash,
+    sync::Mutex,
+};
+
+/// Longest template id accepted by helpers. Ids are part of the URL used to create queries.
+pub const MAX_TEMPLATE_ID_LEN: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("Query template {0:?} does not exist")]
+    NotFound(String),
+    #[error(
+        "Invalid template id {0:?}: must be 1 to {MAX_TEMPLATE_ID_LEN} ASCII letters, digits, '-' or '_'"
+    )]
+    InvalidId(String),
+    #[error("Cannot override {parameter} of query template {template_id:?}: {reason}")]
+    Override {
+        template_id: String,
+        parameter: &'static str,
+        reason: String,
+    },
+    #[error("This helper only runs queries created from its query templates")]
+    Required,
+    #[error("Query template {id:?} is not allowed by this helper: {report}")]
+    Invalid {
+        id: String,
+        report: ValidationReport,
+    },
+    #[error("Failed to store query templates: {0}")]
+    Store(#[from] io::Error),
+}
+
+/// A vetted query configuration stored on the helper. Report collectors create queries from it
+/// by referring to it by id, and can only change the fields in [`TemplateOverrides`]. Everything
+/// that affects privacy (caps, attribution window, epsilon, value widths) is fixed by the
+/// template.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct QueryTemplate {
+    pub field_type: FieldType,
+    pub query_type: QueryType,
+}
+
+/// The fields of a query that may differ between queries created from the same template.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct TemplateOverrides {
+    pub size: QuerySize,
+    /// Breakdown domain of this query. It can be narrower than the template's, but not wider.
+    #[serde(default)]
+    pub max_breakdown_key: Option<u32>,
+    /// Site the reports were collected for. Only supported by IPA templates that don't fix
+    /// the site themselves.
+    #[serde(default)]
+    pub site_domain_hash: Option<SiteDomainHash>,
+}
+
+impl TemplateOverrides {
+    #[must_use]
+    pub fn new(size: QuerySize) -> Self {
+        Self {
+            size,
+            max_breakdown_key: None,
+            site_domain_hash: None,
+        }
+    }
+}
+
+impl QueryTemplate {
+    /// Builds the configuration of a query created from this template.
+    ///
+    /// ## Errors
+    /// If `overrides` change this template in a way that is not allowed.
+    pub fn instantiate(
+        &self,
+        template_id: &str,
+        overrides: TemplateOverrides,
+    ) -> Result<QueryConfig, TemplateError> {
+        let reject = |parameter, reason: String| TemplateError::Override {
+            template_id: template_id.to_string(),
+            parameter,
+            reason,
+        };
+        let mut query_type = self.query_type;
+        if let Some(max_breakdown_key) = overrides.max_breakdown_key {
+            let template_max = match &mut query_type {
+                QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config) => {
+                    &mut config.max_breakdown_key
+                }
+                QueryType::MaliciousHybrid(config) => &mut config.max_breakdown_key,
+                #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+                QueryType::TestMultiply
+                | QueryType::TestAddInPrimeField
+                | QueryType::TestShardedShuffle
+                | QueryType::TestShareConversion(_) => {
+                    return Err(reject(
+                        "max_breakdown_key",
+                        format!("{} queries have no breakdowns", self.query_type.as_ref()),
+                    ))
+                }
+            };
+            if max_breakdown_key == 0 || max_breakdown_key > *template_max {
+                return Err(reject(
+                    "max_breakdown_key",
+                    format!("must be between 1 and {template_max}, got {max_breakdown_key}"),
+                ));
+            }
+            *template_max = max_breakdown_key;
+        }
+        if let Some(site_domain_hash) = overrides.site_domain_hash {
+            match &mut query_type {
+                QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config)
+                    if config.site_domain_hash.is_none() =>
+                {
+                    config.site_domain_hash = Some(site_domain_hash);
+                }
+                QueryType::SemiHonestOprfIpa(_) | QueryType::MaliciousOprfIpa(_) => {
+                    return Err(reject(
+                        "site_domain_hash",
+                        "the template already sets the site".to_string(),
+                    ));
+                }
+                _ => {
+                    return Err(reject(
+                        "site_domain_hash",
+                        format!(
+                            "{} queries are not bound to a site",
+                            self.query_type.as_ref()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        Ok(QueryConfig {
+            size: overrides.size,
+            field_type: self.field_type,
+            query_type,
+        })
+    }
+
+    /// Returns `true` if a query with `config` can be created from this template.
+    #[must_use]
+    pub fn admits(&self, config: &QueryConfig) -> bool {
+        let (max_breakdown_key, site_domain_hash) = match &config.query_type {
+            QueryType::SemiHonestOprfIpa(ipa) | QueryType::MaliciousOprfIpa(ipa) => {
+                (Some(ipa.max_breakdown_key), ipa.site_domain_hash)
+            }
+            QueryType::MaliciousHybrid(hybrid) => (Some(hybrid.max_breakdown_key), None),
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestMultiply
+            | QueryType::TestAddInPrimeField
+            | QueryType::TestShardedShuffle
+            | QueryType::TestShareConversion(_) => (None, None),
+        };
+        // The site is an override only if the template leaves it open.
+        let site_domain_hash = match &self.query_type {
+            QueryType::SemiHonestOprfIpa(ipa) | QueryType::MaliciousOprfIpa(ipa)
+                if ipa.site_domain_hash.is_none() =>
+            {
+                site_domain_hash
+            }
+            _ => None,
+        };
+        let overrides = TemplateOverrides {
+            size: config.size,
+            max_breakdown_key,
+            site_domain_hash,
+        };
+
+        self.instantiate("", overrides)
+            .is_ok_and(|instance| instance == *config)
+    }
+
+    /// Checks this template against `policy`. Limits that depend on the query size are checked
+    /// again for every query created from it.
+    #[must_use]
+    pub fn validate(&self, policy: &QueryPolicy) -> ValidationReport {
+        QueryConfig {
+            size: QuerySize(1),
+            field_type: self.field_type,
+            query_type: self.query_type,
+        }
+        .validate(policy)
+    }
+}
+
+/// Request to create a query from one of the helper's templates.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateFromTemplate {
+    pub template_id: String,
+    pub overrides: TemplateOverrides,
+}
+
+impl RouteParams<RouteId, NoQueryId, NoStep> for CreateFromTemplate {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::ReceiveQueryFromTemplate
+    }
+
+    fn query_id(&self) -> NoQueryId {
+        NoQueryId
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Changes to the set of templates stored on a helper. These are only accepted from helper
+/// administrators.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TemplateCommand {
+    List,
+    Put { id: String, template: QueryTemplate },
+    Remove { id: String },
+}
+
+impl RouteParams<RouteId, NoQueryId, NoStep> for TemplateCommand {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::QueryTemplates
+    }
+
+    fn query_id(&self) -> NoQueryId {
+        NoQueryId
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Templates stored on a helper, keyed by id.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(test, derive(PartialEq))]
+#[serde(transparent)]
+pub struct TemplateList(pub BTreeMap<String, QueryTemplate>);
+
+/// Query templates known to this helper.
+#[derive(Debug, Default)]
+pub struct QueryTemplates {
+    templates: Mutex<BTreeMap<String, QueryTemplate>>,
+    required: bool,
+    /// File the templates are written to whenever they change, so that they outlive the helper.
+    file: Option<PathBuf>,
+}
+
+impl QueryTemplates {
+    /// Creates a store with the given templates. If `required` is set, queries can only be
+    /// created from templates.
+    ///
+    /// ## Errors
+    /// If any of the template ids is not valid.
+    pub fn new(templates: TemplateList, required: bool) -> Result<Self, TemplateError> {
+        for id in templates.0.keys() {
+            check_id(id)?;
+        }
+        Ok(Self {
+            templates: Mutex::new(templates.0),
+            required,
+            file: None,
+        })
+    }
+
+    /// Writes the templates to `file` whenever they change. Helpers load them from the same
+    /// file when they start.
+    #[must_use]
+    pub fn stored_in(mut self, file: PathBuf) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Whether queries can only be created from templates.
+    #[must_use]
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Checks that queries with `config` may run on this helper. If templates are required,
+    /// `config` must be one that a stored template admits.
+    ///
+    /// ## Errors
+    /// If templates are required and none of them admits `config`.
+    pub fn check(&self, config: &QueryConfig) -> Result<(), TemplateError> {
+        if !self.required
+            || self
+                .templates
+                .lock()
+                .unwrap()
+                .values()
+                .any(|template| template.admits(config))
+        {
+            Ok(())
+        } else {
+            Err(TemplateError::Required)
+        }
+    }
+
+    /// Checks every stored template against `policy`.
+    ///
+    /// ## Errors
+    /// If any of the templates allows queries that `policy` does not.
+    pub fn check_policy(&self, policy: &QueryPolicy) -> Result<(), TemplateError> {
+        for (id, template) in self.templates.lock().unwrap().iter() {
+            check_template(id, template, policy)?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the configuration of a query created from the template `id`.
+    ///
+    /// ## Errors
+    /// If the template does not exist or `overrides` are not allowed by it.
+    pub fn instantiate(
+        &self,
+        id: &str,
+        overrides: TemplateOverrides,
+    ) -> Result<QueryConfig, TemplateError> {
+        let template = self
+            .templates
+            .lock()
+            .unwrap()
+            .get(id)
+            .copied()
+            .ok_or_else(|| TemplateError::NotFound(id.to_string()))?;
+        template.instantiate(id, overrides)
+    }
+
+    /// Applies `command` and returns the resulting set of templates. New templates must be
+    /// allowed by `policy`.
+    ///
+    /// ## Errors
+    /// If the command refers to an invalid id or a template that does not exist, if `policy`
+    /// does not allow the template, or if the templates can't be stored.
+    pub fn execute(
+        &self,
+        command: TemplateCommand,
+        policy: &QueryPolicy,
+    ) -> Result<TemplateList, TemplateError> {
+        let mut templates = self.templates.lock().unwrap();
+        let mut changed = templates.clone();
+        match &command {
+            TemplateCommand::List => return Ok(TemplateList(changed)),
+            TemplateCommand::Put { id, template } => {
+                check_id(id)?;
+                check_template(id, template, policy)?;
+                changed.insert(id.clone(), *template);
+            }
+            TemplateCommand::Remove { id } => {
+                if changed.remove(id).is_none() {
+                    return Err(TemplateError::NotFound(id.clone()));
+                }
+            }
+        }
+        let changed = TemplateList(changed);
+        if let Some(file) = &self.file {
+            store(file, &changed)?;
+        }
+        match command {
+            TemplateCommand::Put { id, template } => {
+                tracing::info!("query template {id:?} set to {template:?}");
+            }
+            TemplateCommand::Remove { id } => tracing::info!("query template {id:?} removed"),
+            TemplateCommand::List => {}
+        }
+        templates.clone_from(&changed.0);
+
+        Ok(changed)
+    }
+}
+
+fn check_template(
+    id: &str,
+    template: &QueryTemplate,
+    policy: &QueryPolicy,
+) -> Result<(), TemplateError> {
+    let report = template.validate(policy);
+    if report.is_valid() {
+        Ok(())
+    } else {
+        Err(TemplateError::Invalid {
+            id: id.to_string(),
+            report,
+        })
+    }
+}
+
+/// Writes `templates` to `file`. Readers never see a partially written file.
+fn store(file: &PathBuf, templates: &TemplateList) -> io::Result<()> {
+    let tmp = file.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(templates)?)?;
+    fs::rename(tmp, file)
+}
+
+fn check_id(id: &str) -> Result<(), TemplateError> {
+    if !id.is_empty()
+        && id.len() <= MAX_TEMPLATE_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        Ok(())
+    } else {
+        Err(TemplateError::InvalidId(id.to_string()))
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{
+        QueryTemplate, QueryTemplates, TemplateCommand, TemplateError, TemplateList,
+        TemplateOverrides,
+    };
+    use crate::{
+        ff::FieldType,
+        helpers::query::{
+            HybridQueryParams, IpaQueryConfig, QueryConfig, QueryPolicy, QuerySize, QueryType,
+        },
+        report::SiteDomainHash,
+    };
+
+    fn ipa_template() -> QueryTemplate {
+        QueryTemplate {
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                per_user_credit_cap: 16,
+                max_breakdown_key: 64,
+                epsilon: 2.0,
+                ..IpaQueryConfig::default()
+            }),
+        }
+    }
+
+    fn store() -> QueryTemplates {
+        QueryTemplates::new(
+            TemplateList(BTreeMap::from([("ipa".to_string(), ipa_template())])),
+            false,
+        )
+        .unwrap()
+    }
+
+    fn size(v: u32) -> QuerySize {
+        QuerySize::try_from(v).unwrap()
+    }
+
+    #[test]
+    fn instantiate() {
+        let site = SiteDomainHash::from_domain("example.com").unwrap();
+        let config = store()
+            .instantiate(
+                "ipa",
+                TemplateOverrides {
+                    max_breakdown_key: Some(32),
+                    site_domain_hash: Some(site),
+                    ..TemplateOverrides::new(size(100))
+                },
+            )
+            .unwrap();
+        assert_eq!(size(100), config.size);
+        assert_eq!(FieldType::Fp32BitPrime, config.field_type);
+        let QueryType::MaliciousOprfIpa(ipa) = config.query_type else {
+            panic!("unexpected query type {:?}", config.query_type);
+        };
+        assert_eq!(16, ipa.per_user_credit_cap);
+        assert_eq!(32, ipa.max_breakdown_key);
+        assert!((ipa.epsilon - 2.0).abs() < f64::EPSILON);
+        assert_eq!(Some(site), ipa.site_domain_hash);
+    }
+
+    #[test]
+    fn cannot_widen_breakdowns() {
+        let err = store()
+            .instantiate(
+                "ipa",
+                TemplateOverrides {
+                    max_breakdown_key: Some(65),
+                    ..TemplateOverrides::new(size(1))
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TemplateError::Override {
+                parameter: "max_breakdown_key",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn site_only_for_ipa() {
+        let templates = store();
+        templates
+            .execute(
+                TemplateCommand::Put {
+                    id: "hybrid".to_string(),
+                    template: QueryTemplate {
+                        field_type: FieldType::Fp32BitPrime,
+                        query_type: QueryType::MaliciousHybrid(HybridQueryParams::default()),
+                    },
+                },
+                &QueryPolicy::default(),
+            )
+            .unwrap();
+        let err = templates
+            .instantiate(
+                "hybrid",
+                TemplateOverrides {
+                    site_domain_hash: Some(SiteDomainHash::from_domain("example.com").unwrap()),
+                    ..TemplateOverrides::new(size(1))
+                },
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TemplateError::Override {
+                parameter: "site_domain_hash",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn admits() {
+        let template = ipa_template();
+        let config = template
+            .instantiate(
+                "ipa",
+                TemplateOverrides {
+                    max_breakdown_key: Some(32),
+                    ..TemplateOverrides::new(size(100))
+                },
+            )
+            .unwrap();
+        assert!(template.admits(&config));
+
+        let QueryType::MaliciousOprfIpa(mut ipa) = config.query_type else {
+            panic!("unexpected query type {:?}", config.query_type);
+        };
+        ipa.epsilon = 10.0;
+        assert!(!template.admits(&QueryConfig {
+            query_type: QueryType::MaliciousOprfIpa(ipa),
+            ..config
+        }));
+        assert!(!template.admits(&QueryConfig {
+            query_type: QueryType::SemiHonestOprfIpa(ipa),
+            ..config
+        }));
+    }
+
+    #[test]
+    fn put_checks_policy() {
+        let policy = QueryPolicy {
+            max_breakdown_key: Some(32),
+            ..QueryPolicy::default()
+        };
+        assert!(matches!(
+            store().execute(
+                TemplateCommand::Put {
+                    id: "ipa-2".to_string(),
+                    template: ipa_template(),
+                },
+                &policy,
+            ),
+            Err(TemplateError::Invalid { .. })
+        ));
+        assert!(matches!(
+            store().check_policy(&policy),
+            Err(TemplateError::Invalid { id, .. }) if id == "ipa"
+        ));
+    }
+
+    #[test]
+    fn persists_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("templates.json");
+        let templates = store().stored_in(file.clone());
+        templates
+            .execute(
+                TemplateCommand::Remove {
+                    id: "ipa".to_string(),
+                },
+                &QueryPolicy::default(),
+            )
+            .unwrap();
+        templates
+            .execute(
+                TemplateCommand::Put {
+                    id: "ipa-2".to_string(),
+                    template: ipa_template(),
+                },
+                &QueryPolicy::default(),
+            )
+            .unwrap();
+
+        let stored: TemplateList = serde_json::from_slice(&std::fs::read(&file).unwrap()).unwrap();
+        assert_eq!(vec!["ipa-2"], stored.0.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn manage() {
+        let policy = QueryPolicy::default();
+        let templates = store();
+        let list = templates
+            .execute(
+                TemplateCommand::Put {
+                    id: "ipa-2".to_string(),
+                    template: ipa_template(),
+                },
+                &policy,
+            )
+            .unwrap();
+        assert_eq!(vec!["ipa", "ipa-2"], list.0.keys().collect::<Vec<_>>());
+
+        let list = templates
+            .execute(
+                TemplateCommand::Remove {
+                    id: "ipa".to_string(),
+                },
+                &policy,
+            )
+            .unwrap();
+        assert_eq!(vec!["ipa-2"], list.0.keys().collect::<Vec<_>>());
+        assert!(matches!(
+            templates.instantiate("ipa", TemplateOverrides::new(size(1))),
+            Err(TemplateError::NotFound(_))
+        ));
+        assert!(matches!(
+            templates.execute(
+                TemplateCommand::Remove {
+                    id: "ipa".to_string()
+                },
+                &policy,
+            ),
+            Err(TemplateError::NotFound(_))
+        ));
+        assert!(matches!(
+            templates.execute(
+                TemplateCommand::Put {
+                    id: "not/valid".to_string(),
+                    template: ipa_template(),
+                },
+                &policy,
+            ),
+            Err(TemplateError::InvalidId(_))
+        ));
+    }
+}
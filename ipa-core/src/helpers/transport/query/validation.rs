@@ -0,0 +1,1221 @@
+use std::fmt::{Display, Formatter};
+
+use ipa_step::CompactStep;
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+use crate::helpers::query::ShareConversionParams;
+use crate::{
+    ff::boolean_array::{BA112, BA3, BA32, BA8},
+    helpers::{
+        query::{HybridQueryParams, IpaQueryConfig, QueryConfig, QueryType},
+        routing::RouteId,
+        NoQueryId, NoStep, RouteParams,
+    },
+    protocol::{
+        hybrid::step::HybridStep,
+        ipa_prf::{
+            prf_sharding::time_to_conversion::MAX_TIME_TO_CONVERSION_BUCKETS, step::IpaPrfStep,
+            AggregationMethod, MatchKey, UserSampling,
+        },
+    },
+    secret_sharing::SharedValue,
+};
+
+/// Limits a helper places on the queries it agrees to run, on top of what the protocols
+/// support.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryPolicy {
+    /// Smallest privacy budget a query that adds DP noise may request.
+    pub min_epsilon: Option<f64>,
+    /// Largest breakdown domain a query may request.
+    pub max_breakdown_key: Option<u32>,
+    /// Largest number of channels a query may need, as estimated by
+    /// [`QueryConfig::estimated_channels`].
+    #[serde(default)]
+    pub max_channels: Option<u64>,
+}
+
+impl QueryPolicy {
+    /// Checks that `config` does not need more channels than this policy allows. Returns the
+    /// estimate.
+    ///
+    /// ## Errors
+    /// If the estimate exceeds [`Self::max_channels`].
+    pub fn check_channels(&self, config: &QueryConfig) -> Result<u64, TooManyChannels> {
+        let estimated = config.estimated_channels();
+        match self.max_channels {
+            Some(max) if estimated > max => Err(TooManyChannels { estimated, max }),
+            _ => Ok(estimated),
+        }
+    }
+}
+
+/// A query needs more channels than the helper [`QueryPolicy`] allows.
+#[derive(thiserror::Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[error("query needs an estimated {estimated} channels, more than the helper maximum of {max}")]
+pub struct TooManyChannels {
+    pub estimated: u64,
+    pub max: u64,
+}
+
+/// A query asks for a per-user cap that OPRF IPA is not instantiated for.
+#[derive(thiserror::Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[error(
+    "Unsupported per-user cap: {cap}. Must be at most {}, or 0 for no cap.",
+    IpaQueryConfig::MAX_PER_USER_CREDIT_CAP
+)]
+pub struct UnsupportedCap {
+    pub cap: u32,
+}
+
+// The two constants below are not derived from the step tree. They are a rough model of how
+// it grows with query parameters, tuned by hand. `estimated_channels_follow_step_tree` fails
+// if they drift too far from the compact gate.
+
+/// Steps every query runs regardless of its parameters, such as PRSS setup and input
+/// validation.
+const FIXED_STEPS: u64 = 64;
+
+/// Steps bit-level circuits, such as comparisons and additions, run for every bit of the
+/// values they operate on.
+const STEPS_PER_BIT: u64 = 8;
+
+/// Request to check a [`QueryConfig`] without creating a query.
+#[derive(Copy, Clone, Debug)]
+pub struct ValidateQuery(pub QueryConfig);
+
+impl RouteParams<RouteId, NoQueryId, NoStep> for ValidateQuery {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::ValidateQuery
+    }
+
+    fn query_id(&self) -> NoQueryId {
+        NoQueryId
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(&self.0).unwrap()
+    }
+}
+
+/// A single reason a query configuration can't be run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationProblem {
+    /// Name of the query parameter at fault.
+    pub parameter: String,
+    pub message: String,
+}
+
+/// Outcome of validating a [`QueryConfig`]. Unlike query creation, validation does not stop
+/// at the first problem, so all of them can be fixed at once.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub problems: Vec<ValidationProblem>,
+    /// Number of channels the query is estimated to need, see
+    /// [`QueryConfig::estimated_channels`].
+    #[serde(default)]
+    pub estimated_channels: u64,
+}
+
+/// Lists all problems, separated by semicolons.
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}: {}", problem.parameter, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn push<M: Into<String>>(&mut self, parameter: &str, message: M) {
+        self.problems.push(ValidationProblem {
+            parameter: parameter.to_string(),
+            message: message.into(),
+        });
+    }
+
+    fn check_dp(&mut self, policy: &QueryPolicy, with_dp: u32, epsilon: f64) {
+        if with_dp == 0 {
+            return;
+        }
+        if !(epsilon.is_finite() && epsilon > 0.0) {
+            self.push(
+                "epsilon",
+                format!("epsilon must be positive, got {epsilon}"),
+            );
+        } else if let Some(min_epsilon) = policy.min_epsilon {
+            if epsilon < min_epsilon {
+                self.push(
+                    "epsilon",
+                    format!("epsilon {epsilon} is below the helper minimum of {min_epsilon}"),
+                );
+            }
+        }
+    }
+
+    fn check_breakdowns(&mut self, policy: &QueryPolicy, max_breakdown_key: u32, bits: u32) {
+        if max_breakdown_key == 0 {
+            self.push("max_breakdown_key", "max_breakdown_key must be positive");
+        }
+        if u64::from(max_breakdown_key) > 1 << bits {
+            self.push(
+                "max_breakdown_key",
+                format!("max_breakdown_key {max_breakdown_key} does not fit into {bits} bits"),
+            );
+        }
+        if let Some(limit) = policy.max_breakdown_key {
+            if max_breakdown_key > limit {
+                self.push(
+                    "max_breakdown_key",
+                    format!(
+                        "max_breakdown_key {max_breakdown_key} exceeds the helper maximum of {limit}"
+                    ),
+                );
+            }
+        }
+    }
+}
+
+impl QueryConfig {
+    /// Estimates how many channels a helper needs to run this query. Every step of the
+    /// protocol gets a channel to each of the two peers, and the number of steps grows with
+    /// the width of the values the protocol operates on and with the breakdown domain, which
+    /// sets the depth of the aggregation tree.
+    ///
+    /// This is a heuristic. The compact gate holds the steps of every configuration of a
+    /// protocol at once, so it can't tell which of them this configuration uses. It only caps
+    /// the estimate: a query never needs more channels than all steps of its protocol give.
+    #[must_use]
+    pub fn estimated_channels(&self) -> u64 {
+        fn depth(max_breakdown_key: u32) -> u64 {
+            u64::from(u32::BITS - max_breakdown_key.saturating_sub(1).leading_zeros())
+        }
+
+        let bits = match &self.query_type {
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestMultiply
+            | QueryType::TestAddInPrimeField
+            | QueryType::TestShardedShuffle => 0,
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestShareConversion(params) => u64::from(params.bits),
+            QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config) => {
+                let histogram_bits = u64::from(config.histogram_value_bits_for(self.size));
+                let cap_bits = u32::BITS - config.per_user_credit_cap.leading_zeros();
+                u64::from(config.breakdown_key_bits)
+                    + u64::from(config.trigger_value_bits)
+                    + u64::from(config.timestamp_bits)
+                    + u64::from(cap_bits)
+                    + histogram_bits * (1 + depth(config.max_breakdown_key))
+            }
+            QueryType::MaliciousHybrid(params) => {
+                u64::from(BA8::BITS + BA3::BITS)
+                    + u64::from(BA32::BITS) * (1 + depth(params.max_breakdown_key))
+            }
+        };
+
+        let estimated = FIXED_STEPS + STEPS_PER_BIT * bits;
+        2 * self
+            .gate_steps()
+            .map_or(estimated, |steps| estimated.min(steps))
+    }
+
+    /// Number of steps the compact gate has under the root of the protocol that runs queries of
+    /// this type, if it has one.
+    fn gate_steps(&self) -> Option<u64> {
+        match &self.query_type {
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestMultiply
+            | QueryType::TestAddInPrimeField
+            | QueryType::TestShardedShuffle
+            | QueryType::TestShareConversion(_) => None,
+            QueryType::SemiHonestOprfIpa(_) | QueryType::MaliciousOprfIpa(_) => {
+                Some(IpaPrfStep::STEP_COUNT.into())
+            }
+            QueryType::MaliciousHybrid(_) => Some(HybridStep::STEP_COUNT.into()),
+        }
+    }
+
+    /// Checks this configuration against what the protocols support and against `policy`.
+    #[must_use]
+    pub fn validate(&self, policy: &QueryPolicy) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        match policy.check_channels(self) {
+            Ok(estimated) => report.estimated_channels = estimated,
+            Err(e) => {
+                report.estimated_channels = e.estimated;
+                report.push("query_type", e.to_string());
+            }
+        }
+        match &self.query_type {
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestMultiply
+            | QueryType::TestAddInPrimeField
+            | QueryType::TestShardedShuffle => {}
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestShareConversion(params) => params.validate(&mut report),
+            QueryType::SemiHonestOprfIpa(config) => config.validate(policy, &mut report),
+            QueryType::MaliciousOprfIpa(config) => {
+                config.validate(policy, &mut report);
+                if config.prune_zero_rows {
+                    report.push(
+                        "prune_zero_rows",
+                        "Zero-value rows can only be pruned in semi-honest queries",
+                    );
+                }
+            }
+            QueryType::MaliciousHybrid(config) => config.validate(policy, &mut report),
+        }
+
+        report
+    }
+}
+
+#[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+impl ShareConversionParams {
+    fn validate(&self, report: &mut ValidationReport) {
+        if !(1..=Self::MAX_BITS).contains(&self.bits) {
+            report.push(
+                "bits",
+                format!(
+                    "Unsupported share conversion width: {}. Must be between 1 and {}.",
+                    self.bits,
+                    Self::MAX_BITS
+                ),
+            );
+        }
+    }
+}
+
+impl IpaQueryConfig {
+    /// Checks this configuration against what the IPA protocol supports and against `policy`.
+    /// The query runner calls this as well, with the default policy, so there is a single
+    /// definition of a runnable IPA query.
+    pub(crate) fn validate(&self, policy: &QueryPolicy, report: &mut ValidationReport) {
+        if self.per_user_credit_cap > Self::MAX_PER_USER_CREDIT_CAP {
+            report.push(
+                "per_user_credit_cap",
+                UnsupportedCap {
+                    cap: self.per_user_credit_cap,
+                }
+                .to_string(),
+            );
+        } else if self.per_user_credit_cap == 0 && self.with_dp != 0 {
+            report.push(
+                "per_user_credit_cap",
+                "DP noise can't be added to the output of a query without a per-user cap",
+            );
+        }
+        if let Some(cap) = self.per_source_event_cap {
+            let max = match self.per_user_credit_cap {
+                0 => Self::MAX_PER_USER_CREDIT_CAP,
+                per_user_cap => per_user_cap,
+            };
+            if !cap.is_power_of_two() || cap > max {
+                report.push(
+                    "per_source_event_cap",
+                    format!(
+                        "Unsupported per-source event cap: {cap}. Must be a power of two that \
+                         does not exceed {max}.",
+                    ),
+                );
+            }
+        }
+        if let Some(limit) = self.per_source_trigger_limit {
+            if !(1..=Self::MAX_PER_SOURCE_TRIGGER_LIMIT).contains(&limit) {
+                report.push(
+                    "per_source_trigger_limit",
+                    format!(
+                        "Unsupported per-source trigger limit: {limit}. Must be between 1 and {}.",
+                        Self::MAX_PER_SOURCE_TRIGGER_LIMIT
+                    ),
+                );
+            }
+        }
+        if let Some(sites) = self.source_sites {
+            self.validate_source_sites(sites, report);
+        }
+        if let Some(tags) = self.public_tags {
+            self.validate_public_tags(tags, report);
+        }
+        // Per-site and tagged queries already report pairing as a conflict.
+        if self.paired_arms && self.source_sites.is_none() && self.public_tags.is_none() {
+            self.validate_paired_arms(report);
+        }
+        if let Some(rate) = self.user_sampling_rate {
+            if !UserSampling::is_valid_rate(rate) {
+                report.push(
+                    "user_sampling_rate",
+                    format!("User sampling rate must be in (0, 1], got {rate}"),
+                );
+            }
+        }
+        if self.time_to_conversion_bucket_seconds.is_some()
+            && !(2..=MAX_TIME_TO_CONVERSION_BUCKETS)
+                .contains(&usize::try_from(self.time_to_conversion_buckets).unwrap())
+        {
+            report.push(
+                "time_to_conversion_buckets",
+                format!(
+                    "Unsupported number of time-to-conversion buckets: {}. Must be between 2 \
+                     and {MAX_TIME_TO_CONVERSION_BUCKETS}.",
+                    self.time_to_conversion_buckets,
+                ),
+            );
+        }
+        self.validate_timestamp_bounds(report);
+        if self.prune_zero_rows && self.aggregation_method != AggregationMethod::BreakdownReveal {
+            report.push(
+                "prune_zero_rows",
+                "Zero-value rows can only be pruned by breakdown reveal aggregation",
+            );
+        }
+        if !(0.0..=1.0).contains(&self.max_decryption_failure_rate) {
+            report.push(
+                "max_decryption_failure_rate",
+                format!(
+                    "Decryption failure rate must be in [0, 1], got {}",
+                    self.max_decryption_failure_rate
+                ),
+            );
+        }
+        if !Self::SUPPORTED_TRIGGER_VALUE_BITS.contains(&self.trigger_value_bits) {
+            report.push(
+                "trigger_value_bits",
+                format!(
+                    "Unsupported trigger value width: {} bits. Must be one of {:?}.",
+                    self.trigger_value_bits,
+                    Self::SUPPORTED_TRIGGER_VALUE_BITS
+                ),
+            );
+        }
+        if Self::SUPPORTED_BREAKDOWN_KEY_BITS.contains(&self.breakdown_key_bits) {
+            report.check_breakdowns(policy, self.max_breakdown_key, self.breakdown_key_bits);
+        } else {
+            report.push(
+                "breakdown_key_bits",
+                format!(
+                    "Unsupported breakdown key width: {} bits. Must be one of {:?}.",
+                    self.breakdown_key_bits,
+                    Self::SUPPORTED_BREAKDOWN_KEY_BITS
+                ),
+            );
+        }
+        if Self::SUPPORTED_TIMESTAMP_BITS.contains(&self.timestamp_bits) {
+            if let Some(window) = self.attribution_window_units() {
+                if u64::from(window.get()) >= 1 << self.timestamp_bits {
+                    report.push(
+                        "attribution_window_seconds",
+                        format!(
+                            "Attribution window of {window} timestamp units does not fit into {} bits",
+                            self.timestamp_bits
+                        ),
+                    );
+                }
+            }
+        } else {
+            report.push(
+                "timestamp_bits",
+                format!(
+                    "Unsupported timestamp width: {} bits. Must be one of {:?}.",
+                    self.timestamp_bits,
+                    Self::SUPPORTED_TIMESTAMP_BITS
+                ),
+            );
+        }
+        if self.histogram_value_bits != Self::AUTO_HISTOGRAM_VALUE_BITS
+            && !Self::SUPPORTED_HISTOGRAM_VALUE_BITS.contains(&self.histogram_value_bits)
+        {
+            report.push(
+                "histogram_value_bits",
+                format!(
+                    "Unsupported histogram value width: {} bits. Must be one of {:?}, or {} to \
+                     pick one from the query size.",
+                    self.histogram_value_bits,
+                    Self::SUPPORTED_HISTOGRAM_VALUE_BITS,
+                    Self::AUTO_HISTOGRAM_VALUE_BITS,
+                ),
+            );
+        }
+        // All input fields are packed into a single share for the shuffle.
+        let row_bits = u64::from(MatchKey::BITS)
+            + 1
+            + u64::from(self.breakdown_key_bits)
+            + u64::from(self.trigger_value_bits)
+            + u64::from(self.timestamp_bits);
+        if row_bits > u64::from(BA112::BITS) {
+            report.push(
+                "trigger_value_bits",
+                format!(
+                    "Input rows with {} bit breakdown keys, {} bit trigger values and {} bit \
+                     timestamps do not fit into {} bits",
+                    self.breakdown_key_bits,
+                    self.trigger_value_bits,
+                    self.timestamp_bits,
+                    BA112::BITS
+                ),
+            );
+        }
+        report.check_dp(policy, self.with_dp, self.epsilon);
+    }
+
+    fn validate_paired_arms(&self, report: &mut ValidationReport) {
+        if Self::SUPPORTED_BREAKDOWN_KEY_BITS.contains(&self.breakdown_key_bits)
+            && u64::from(self.max_breakdown_key) > 1 << (self.breakdown_key_bits - 1)
+        {
+            report.push(
+                "max_breakdown_key",
+                format!(
+                    "Paired queries give each arm half of the breakdown keys, so \
+                     max_breakdown_key {} must fit into {} bits",
+                    self.max_breakdown_key,
+                    self.breakdown_key_bits - 1
+                ),
+            );
+        }
+        for (conflict, name) in [
+            (
+                self.time_to_conversion_bucket_seconds.is_some(),
+                "time_to_conversion_bucket_seconds",
+            ),
+            (self.input_manifest, "input_manifest"),
+        ] {
+            if conflict {
+                report.push(name, "Can't be combined with paired arms");
+            }
+        }
+    }
+
+    fn validate_source_sites(&self, sites: u32, report: &mut ValidationReport) {
+        if !(2..=Self::MAX_SOURCE_SITES).contains(&sites) {
+            report.push(
+                "source_sites",
+                format!(
+                    "Unsupported number of source sites: {sites}. Must be between 2 and {}.",
+                    Self::MAX_SOURCE_SITES
+                ),
+            );
+            return;
+        }
+        let site_bits = self.source_site_bits().unwrap();
+        if Self::SUPPORTED_BREAKDOWN_KEY_BITS.contains(&self.breakdown_key_bits)
+            && u64::from(self.max_breakdown_key) > 1 << (self.breakdown_key_bits - site_bits)
+        {
+            report.push(
+                "max_breakdown_key",
+                format!(
+                    "Queries over {sites} source sites give each site {} bits of the breakdown \
+                     key, max_breakdown_key {} does not fit into them",
+                    self.breakdown_key_bits - site_bits,
+                    self.max_breakdown_key
+                ),
+            );
+        }
+        for (conflict, name) in [
+            (self.paired_arms, "paired_arms"),
+            (
+                self.time_to_conversion_bucket_seconds.is_some(),
+                "time_to_conversion_bucket_seconds",
+            ),
+            (self.input_manifest, "input_manifest"),
+        ] {
+            if conflict {
+                report.push(name, "Can't be combined with per-site aggregates");
+            }
+        }
+    }
+
+    /// Checks `min_timestamp` and `max_timestamp`. Query runners call this as well, so that
+    /// bounds are validated the same way everywhere.
+    pub(crate) fn validate_timestamp_bounds(&self, report: &mut ValidationReport) {
+        if self.has_timestamp_bounds() && self.with_dp == 0 {
+            report.push(
+                "with_dp",
+                "The number of trigger events outside of the timestamp bounds is only released \
+                 with DP noise",
+            );
+        }
+        let bits = self.timestamp_bits.min(u32::BITS);
+        for (bound, name) in [
+            (self.min_timestamp, "min_timestamp"),
+            (self.max_timestamp, "max_timestamp"),
+        ] {
+            match bound {
+                Some(timestamp) if u64::from(timestamp) >= 1 << bits => report.push(
+                    name,
+                    format!("{name} {timestamp} does not fit into {bits} bits"),
+                ),
+                _ => {}
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_timestamp, self.max_timestamp) {
+            if min > max {
+                report.push(
+                    "max_timestamp",
+                    format!("max_timestamp {max} is below min_timestamp {min}"),
+                );
+            }
+        }
+    }
+
+    fn validate_public_tags(&self, tags: u32, report: &mut ValidationReport) {
+        if !(2..=Self::MAX_PUBLIC_TAGS).contains(&tags) {
+            report.push(
+                "public_tags",
+                format!(
+                    "Unsupported number of public tags: {tags}. Must be between 2 and {}.",
+                    Self::MAX_PUBLIC_TAGS
+                ),
+            );
+            return;
+        }
+        let tag_bits = self.public_tag_bits().unwrap();
+        if Self::SUPPORTED_BREAKDOWN_KEY_BITS.contains(&self.breakdown_key_bits)
+            && u64::from(self.max_breakdown_key) > 1 << (self.breakdown_key_bits - tag_bits)
+        {
+            report.push(
+                "max_breakdown_key",
+                format!(
+                    "Queries over {tags} public tags give each tag {} bits of the breakdown key, \
+                     max_breakdown_key {} does not fit into them",
+                    self.breakdown_key_bits - tag_bits,
+                    self.max_breakdown_key
+                ),
+            );
+        }
+        for (conflict, name) in [
+            (self.paired_arms, "paired_arms"),
+            (self.source_sites.is_some(), "source_sites"),
+            (
+                self.time_to_conversion_bucket_seconds.is_some(),
+                "time_to_conversion_bucket_seconds",
+            ),
+            (self.input_manifest, "input_manifest"),
+            (self.cache_prf, "cache_prf"),
+        ] {
+            if conflict {
+                report.push(name, "Can't be combined with public tags");
+            }
+        }
+    }
+}
+
+impl HybridQueryParams {
+    fn validate(&self, policy: &QueryPolicy, report: &mut ValidationReport) {
+        if self.plaintext_match_keys {
+            report.push(
+                "plaintext_match_keys",
+                "Hybrid queries do not currently support plaintext match keys",
+            );
+        }
+        report.check_breakdowns(policy, self.max_breakdown_key, BA8::BITS);
+        report.check_dp(policy, self.with_dp, self.epsilon);
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::{QueryPolicy, TooManyChannels, ValidationReport};
+    use crate::{
+        ff::FieldType,
+        helpers::query::{
+            HybridQueryParams, IpaQueryConfig, QueryConfig, QueryType, ShareConversionParams,
+        },
+        protocol::ipa_prf::AggregationMethod,
+    };
+
+    fn validate(query_type: QueryType, policy: &QueryPolicy) -> ValidationReport {
+        QueryConfig::new(query_type, FieldType::Fp32BitPrime, 100)
+            .unwrap()
+            .validate(policy)
+    }
+
+    fn parameters(report: &ValidationReport) -> Vec<&str> {
+        report
+            .problems
+            .iter()
+            .map(|p| p.parameter.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn default_configs_are_valid() {
+        let policy = QueryPolicy::default();
+        for query_type in [
+            QueryType::TestMultiply,
+            QueryType::TestShareConversion(ShareConversionParams::default()),
+            QueryType::SemiHonestOprfIpa(IpaQueryConfig::default()),
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+            QueryType::MaliciousHybrid(HybridQueryParams::default()),
+        ] {
+            assert!(validate(query_type, &policy).is_valid(), "{query_type:?}");
+        }
+    }
+
+    #[test]
+    fn share_conversion_width() {
+        let policy = QueryPolicy::default();
+        for bits in [0, ShareConversionParams::MAX_BITS + 1] {
+            let report = validate(
+                QueryType::TestShareConversion(ShareConversionParams { bits }),
+                &policy,
+            );
+            assert_eq!(vec!["bits"], parameters(&report), "{bits}");
+        }
+        for bits in [1, ShareConversionParams::MAX_BITS] {
+            let report = validate(
+                QueryType::TestShareConversion(ShareConversionParams { bits }),
+                &policy,
+            );
+            assert!(report.is_valid(), "{bits}: {report:?}");
+        }
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                per_user_credit_cap: 200,
+                trigger_value_bits: 5,
+                max_breakdown_key: 300,
+                attribution_window_seconds: NonZeroU32::new(1 << 20),
+                histogram_value_bits: 64,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(
+            vec![
+                "per_user_credit_cap",
+                "trigger_value_bits",
+                "max_breakdown_key",
+                "attribution_window_seconds",
+                "histogram_value_bits",
+            ],
+            parameters(&report)
+        );
+    }
+
+    #[test]
+    fn widths_out_of_range() {
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                trigger_value_bits: u32::MAX,
+                breakdown_key_bits: u32::MAX,
+                timestamp_bits: u32::MAX,
+                histogram_value_bits: IpaQueryConfig::AUTO_HISTOGRAM_VALUE_BITS,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(
+            vec![
+                "trigger_value_bits",
+                "breakdown_key_bits",
+                "timestamp_bits",
+                "trigger_value_bits",
+            ],
+            parameters(&report)
+        );
+    }
+
+    #[test]
+    fn auto_histogram_value_bits() {
+        let config = IpaQueryConfig {
+            histogram_value_bits: IpaQueryConfig::AUTO_HISTOGRAM_VALUE_BITS,
+            ..IpaQueryConfig::default()
+        };
+        assert!(validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid());
+    }
+
+    #[test]
+    fn time_to_conversion_buckets() {
+        let config = |buckets| IpaQueryConfig {
+            time_to_conversion_bucket_seconds: NonZeroU32::new(86_400),
+            time_to_conversion_buckets: buckets,
+            ..IpaQueryConfig::default()
+        };
+        for buckets in [2, 8, 16] {
+            assert!(
+                validate(
+                    QueryType::MaliciousOprfIpa(config(buckets)),
+                    &QueryPolicy::default()
+                )
+                .is_valid(),
+                "{buckets}"
+            );
+        }
+        for buckets in [0, 1, 17] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(config(buckets)),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(vec!["time_to_conversion_buckets"], parameters(&report));
+        }
+
+        // The number of buckets does not matter if the histogram is not requested.
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                time_to_conversion_buckets: 0,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert!(report.is_valid());
+    }
+
+    #[test]
+    fn per_user_credit_cap() {
+        for cap in [1, 3, 6, 100, 128] {
+            let config = IpaQueryConfig {
+                per_user_credit_cap: cap,
+                ..IpaQueryConfig::default()
+            };
+            assert!(
+                validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid(),
+                "{cap}"
+            );
+        }
+
+        let uncapped = IpaQueryConfig {
+            per_user_credit_cap: 0,
+            ..IpaQueryConfig::default()
+        };
+        let report = validate(
+            QueryType::MaliciousOprfIpa(uncapped),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["per_user_credit_cap"], parameters(&report));
+        assert!(validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                with_dp: 0,
+                ..uncapped
+            }),
+            &QueryPolicy::default()
+        )
+        .is_valid());
+    }
+
+    #[test]
+    fn per_source_event_cap() {
+        let config = IpaQueryConfig {
+            per_user_credit_cap: 16,
+            per_source_event_cap: Some(8),
+            ..IpaQueryConfig::default()
+        };
+        assert!(validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid());
+
+        for cap in [0, 6, 32] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    per_source_event_cap: Some(cap),
+                    ..config
+                }),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(vec!["per_source_event_cap"], parameters(&report), "{cap}");
+        }
+    }
+
+    #[test]
+    fn timestamp_bounds() {
+        let bounds = |min_timestamp, max_timestamp| {
+            validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    min_timestamp,
+                    max_timestamp,
+                    ..IpaQueryConfig::default()
+                }),
+                &QueryPolicy::default(),
+            )
+        };
+        let largest = (1 << IpaQueryConfig::DEFAULT_TIMESTAMP_BITS) - 1;
+
+        assert!(bounds(Some(0), Some(largest)).is_valid());
+        assert!(bounds(Some(7), Some(7)).is_valid());
+        assert!(bounds(None, Some(7)).is_valid());
+        assert!(bounds(Some(largest), None).is_valid());
+        assert_eq!(
+            vec!["min_timestamp"],
+            parameters(&bounds(Some(largest + 1), None))
+        );
+        assert_eq!(vec!["max_timestamp"], parameters(&bounds(Some(8), Some(7))));
+        assert_eq!(
+            vec!["max_timestamp", "max_timestamp"],
+            parameters(&bounds(Some(8), Some(largest + 1)))
+        );
+
+        // The number of suppressed trigger events is only released with noise.
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                with_dp: 0,
+                max_timestamp: Some(7),
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["with_dp"], parameters(&report));
+    }
+
+    #[test]
+    fn per_source_trigger_limit() {
+        for limit in [1, 3, IpaQueryConfig::MAX_PER_SOURCE_TRIGGER_LIMIT] {
+            let config = IpaQueryConfig {
+                per_source_trigger_limit: Some(limit),
+                ..IpaQueryConfig::default()
+            };
+            assert!(
+                validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid(),
+                "{limit}"
+            );
+        }
+
+        for limit in [0, IpaQueryConfig::MAX_PER_SOURCE_TRIGGER_LIMIT + 1] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    per_source_trigger_limit: Some(limit),
+                    ..IpaQueryConfig::default()
+                }),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(
+                vec!["per_source_trigger_limit"],
+                parameters(&report),
+                "{limit}"
+            );
+        }
+    }
+
+    #[test]
+    fn source_sites() {
+        for sites in [2, 3, IpaQueryConfig::MAX_SOURCE_SITES] {
+            let config = IpaQueryConfig {
+                max_breakdown_key: 8,
+                source_sites: Some(sites),
+                ..IpaQueryConfig::default()
+            };
+            assert!(
+                validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid(),
+                "{sites}"
+            );
+        }
+
+        for sites in [0, 1, IpaQueryConfig::MAX_SOURCE_SITES + 1] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    source_sites: Some(sites),
+                    ..IpaQueryConfig::default()
+                }),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(vec!["source_sites"], parameters(&report), "{sites}");
+        }
+
+        // Five sites take three of the eight bits of the breakdown key.
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                max_breakdown_key: 33,
+                source_sites: Some(5),
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["max_breakdown_key"], parameters(&report));
+
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                source_sites: Some(2),
+                paired_arms: true,
+                input_manifest: true,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["paired_arms", "input_manifest"], parameters(&report));
+    }
+
+    #[test]
+    fn paired_arms() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 128,
+            paired_arms: true,
+            ..IpaQueryConfig::default()
+        };
+        assert!(validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid());
+
+        // Each arm gets seven of the eight bits of the breakdown key.
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                max_breakdown_key: 129,
+                paired_arms: true,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["max_breakdown_key"], parameters(&report));
+
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                paired_arms: true,
+                time_to_conversion_bucket_seconds: Some(NonZeroU32::new(3600).unwrap()),
+                input_manifest: true,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(
+            vec!["time_to_conversion_bucket_seconds", "input_manifest"],
+            parameters(&report)
+        );
+    }
+
+    #[test]
+    fn public_tags() {
+        for tags in [2, 3, IpaQueryConfig::MAX_PUBLIC_TAGS] {
+            let config = IpaQueryConfig {
+                max_breakdown_key: 8,
+                public_tags: Some(tags),
+                ..IpaQueryConfig::default()
+            };
+            assert!(
+                validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid(),
+                "{tags}"
+            );
+        }
+
+        for tags in [0, 1, IpaQueryConfig::MAX_PUBLIC_TAGS + 1] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    public_tags: Some(tags),
+                    ..IpaQueryConfig::default()
+                }),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(vec!["public_tags"], parameters(&report), "{tags}");
+        }
+
+        // Eight tags take three of the eight bits of the breakdown key.
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                max_breakdown_key: 33,
+                public_tags: Some(8),
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["max_breakdown_key"], parameters(&report));
+
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                public_tags: Some(2),
+                input_manifest: true,
+                cache_prf: true,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["input_manifest", "cache_prf"], parameters(&report));
+    }
+
+    #[test]
+    fn user_sampling_rate() {
+        for rate in [0.01, 0.5, 1.0] {
+            let config = IpaQueryConfig {
+                user_sampling_rate: Some(rate),
+                ..IpaQueryConfig::default()
+            };
+            assert!(
+                validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default()).is_valid(),
+                "{rate}"
+            );
+        }
+
+        for rate in [0.0, -0.5, 1.5, f64::NAN] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    user_sampling_rate: Some(rate),
+                    ..IpaQueryConfig::default()
+                }),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(vec!["user_sampling_rate"], parameters(&report), "{rate}");
+        }
+    }
+
+    #[test]
+    fn prune_zero_rows() {
+        let config = IpaQueryConfig {
+            prune_zero_rows: true,
+            ..IpaQueryConfig::default()
+        };
+        assert!(validate(
+            QueryType::SemiHonestOprfIpa(config),
+            &QueryPolicy::default()
+        )
+        .is_valid());
+
+        let report = validate(QueryType::MaliciousOprfIpa(config), &QueryPolicy::default());
+        assert_eq!(vec!["prune_zero_rows"], parameters(&report));
+
+        let report = validate(
+            QueryType::SemiHonestOprfIpa(IpaQueryConfig {
+                aggregation_method: AggregationMethod::Oblivious,
+                ..config
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["prune_zero_rows"], parameters(&report));
+    }
+
+    #[test]
+    fn max_decryption_failure_rate() {
+        for rate in [-0.1, 1.5, f64::NAN] {
+            let report = validate(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                    max_decryption_failure_rate: rate,
+                    ..IpaQueryConfig::default()
+                }),
+                &QueryPolicy::default(),
+            );
+            assert_eq!(
+                vec!["max_decryption_failure_rate"],
+                parameters(&report),
+                "{rate}"
+            );
+        }
+    }
+
+    #[test]
+    fn row_does_not_fit() {
+        let report = validate(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                trigger_value_bits: 16,
+                timestamp_bits: 24,
+                ..IpaQueryConfig::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["trigger_value_bits"], parameters(&report));
+    }
+
+    #[test]
+    fn policy() {
+        let policy = QueryPolicy {
+            min_epsilon: Some(1.0),
+            max_breakdown_key: Some(16),
+            max_channels: None,
+        };
+        let ipa = IpaQueryConfig {
+            epsilon: 0.5,
+            max_breakdown_key: 20,
+            ..IpaQueryConfig::default()
+        };
+        assert_eq!(
+            vec!["max_breakdown_key", "epsilon"],
+            parameters(&validate(QueryType::MaliciousOprfIpa(ipa), &policy))
+        );
+
+        // The epsilon floor does not apply to queries without noise.
+        let ipa = IpaQueryConfig {
+            with_dp: 0,
+            max_breakdown_key: 16,
+            ..ipa
+        };
+        assert!(validate(QueryType::MaliciousOprfIpa(ipa), &policy).is_valid());
+    }
+
+    #[test]
+    fn hybrid() {
+        let report = validate(
+            QueryType::MaliciousHybrid(HybridQueryParams {
+                plaintext_match_keys: true,
+                epsilon: 0.0,
+                ..HybridQueryParams::default()
+            }),
+            &QueryPolicy::default(),
+        );
+        assert_eq!(vec!["plaintext_match_keys", "epsilon"], parameters(&report));
+    }
+
+    #[test]
+    fn estimated_channels() {
+        let config = |config| {
+            QueryConfig::new(
+                QueryType::MaliciousOprfIpa(config),
+                FieldType::Fp32BitPrime,
+                100,
+            )
+            .unwrap()
+        };
+        let small = config(IpaQueryConfig {
+            breakdown_key_bits: 5,
+            max_breakdown_key: 20,
+            ..IpaQueryConfig::default()
+        });
+        let large = config(IpaQueryConfig::default());
+        assert!(small.estimated_channels() < large.estimated_channels());
+        assert_eq!(
+            large.estimated_channels(),
+            large.validate(&QueryPolicy::default()).estimated_channels
+        );
+
+        let policy = QueryPolicy {
+            max_channels: Some(small.estimated_channels()),
+            ..QueryPolicy::default()
+        };
+        assert_eq!(
+            Ok(small.estimated_channels()),
+            policy.check_channels(&small)
+        );
+        assert_eq!(
+            Err(TooManyChannels {
+                estimated: large.estimated_channels(),
+                max: small.estimated_channels(),
+            }),
+            policy.check_channels(&large)
+        );
+        let report = large.validate(&policy);
+        assert_eq!(vec!["query_type"], parameters(&report));
+        assert_eq!(large.estimated_channels(), report.estimated_channels);
+    }
+
+    #[test]
+    fn estimated_channels_follow_step_tree() {
+        // `FIXED_STEPS` and `STEPS_PER_BIT` are tuned by hand. If the step tree of a protocol
+        // grows this much larger than the estimate for a typical query, they need another look.
+        const MAX_DRIFT: u64 = 64;
+
+        for query_type in [
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+            QueryType::MaliciousHybrid(HybridQueryParams::default()),
+        ] {
+            let config = QueryConfig::new(query_type, FieldType::Fp32BitPrime, 100).unwrap();
+            let gate_channels = 2 * config.gate_steps().unwrap();
+            let estimated = config.estimated_channels();
+            assert!(estimated <= gate_channels);
+            assert!(
+                gate_channels <= MAX_DRIFT * estimated,
+                "{}: the step tree has grown to {gate_channels} channels, but only {estimated} \
+                 are estimated",
+                config.query_type.as_ref()
+            );
+        }
+    }
+}
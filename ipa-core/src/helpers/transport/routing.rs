@@ -12,8 +12,18 @@ use crate::{
 pub enum RouteId {
     Records,
     ReceiveQuery,
+    /// Creates a query from one of the templates stored on the helper.
+    ReceiveQueryFromTemplate,
+    /// Lists or changes the query templates stored on the helper.
+    QueryTemplates,
+    /// Checks a query configuration without creating a query.
+    ValidateQuery,
     PrepareQuery,
     QueryInput,
+    /// Adds a batch of input to a query that runs once its input is sealed.
+    AppendQueryInput,
+    /// Fixes the size of a query that received its input in batches and starts running it.
+    SealQueryInput,
     /// To accelerate delivery, we made some compromise here and as a result this API
     /// has double-meaning depending on the context.
     /// In the context of a shard, it is used to check whether other shards have the
@@ -24,7 +34,17 @@ pub enum RouteId {
     QueryStatus,
     CompleteQuery,
     KillQuery,
+    /// Fails a query on a helper, because another helper in it is unavailable.
+    PeerUnavailable,
+    /// Deletes the data kept for the input of a query.
+    DeleteQueryInput,
+    /// Lists the queries a helper keeps track of.
+    ListQueries,
+    /// Removes a query that is no longer running, along with its results and input data.
+    PruneQuery,
     Metrics,
+    /// Runs the readiness checks of the query processor.
+    Readiness,
 }
 
 /// The header/metadata of the incoming request.
@@ -0,0 +1,139 @@
+//! Inputs of paired queries, which hold the reports of both arms of an experiment.
+//!
+//! Every record of such an input is preceded by one byte that tells which [`Arm`] it belongs
+//! to. Arms are public: helpers read them before the records are parsed and use them to keep
+//! the aggregates of the two arms apart.
+
+#[cfg(all(test, unit_test))]
+use bytes::Bytes;
+
+#[cfg(all(test, unit_test))]
+use super::prefixed::split_prefixed;
+use super::prefixed::{strip_prefixes, RecordPrefixes};
+use crate::{
+    error::Error,
+    helpers::{BytesStream, RecordFraming},
+};
+
+/// Experiment arm of a record in the input of a paired query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arm {
+    Control,
+    Treatment,
+}
+
+impl Arm {
+    /// Encoding of the arm in the input.
+    #[must_use]
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Control => 0,
+            Self::Treatment => 1,
+        }
+    }
+
+    /// Parses the encoding produced by [`Self::to_byte`].
+    #[must_use]
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Control),
+            1 => Some(Self::Treatment),
+            _ => None,
+        }
+    }
+}
+
+/// Strips the arms from the `input` of a paired query, in which every record laid out as
+/// described by `framing` is preceded by its arm, while the input is read. Returns the arms of
+/// the records, in input order, and the input without them.
+///
+/// The input is never held in memory as a whole. The arm of a record can be taken from the
+/// returned arms once the record has been read from the returned input.
+///
+/// The returned input fails if reading `input` fails, if an arm is not valid or if the input
+/// ends in the middle of a record.
+pub fn read_paired_input<S: BytesStream>(
+    input: S,
+    framing: RecordFraming,
+) -> (RecordPrefixes<Arm>, impl BytesStream) {
+    strip_prefixes(input, framing, parse_arm)
+}
+
+fn parse_arm(record: usize, byte: u8) -> Result<Arm, Error> {
+    Arm::from_byte(byte)
+        .ok_or_else(|| Error::ParseError(format!("record {record} has unknown arm {byte}").into()))
+}
+
+#[cfg(all(test, unit_test))]
+fn split_arms(input: &[u8], framing: RecordFraming) -> Result<(Vec<Arm>, Bytes), Error> {
+    split_prefixed(input, framing, parse_arm)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use bytes::Bytes;
+    use futures::{stream, TryStreamExt};
+
+    use super::{read_paired_input, split_arms, Arm};
+    use crate::{error::BoxError, helpers::RecordFraming, test_executor::run};
+
+    #[test]
+    fn fixed_size_records() {
+        let framing = RecordFraming::Fixed(NonZeroUsize::new(2).unwrap());
+        let (arms, records) = split_arms(&[1, 7, 8, 0, 9, 10], framing).unwrap();
+
+        assert_eq!(vec![Arm::Treatment, Arm::Control], arms);
+        assert_eq!(&[7, 8, 9, 10], records.as_ref());
+    }
+
+    #[test]
+    fn length_delimited_records() {
+        let (arms, records) =
+            split_arms(&[0, 1, 0, 7, 1, 2, 0, 8, 9], RecordFraming::LengthDelimited).unwrap();
+
+        assert_eq!(vec![Arm::Control, Arm::Treatment], arms);
+        assert_eq!(&[1, 0, 7, 2, 0, 8, 9], records.as_ref());
+    }
+
+    #[test]
+    fn rejects_unknown_arm() {
+        let framing = RecordFraming::Fixed(NonZeroUsize::new(1).unwrap());
+        assert!(split_arms(&[0, 7, 2, 8], framing).is_err());
+    }
+
+    #[test]
+    fn strips_arms_while_reading() {
+        run(|| async {
+            // records are split across chunks in every possible way
+            let input = [0, 1, 0, 7, 1, 2, 0, 8, 9, 0, 0, 0];
+            for split in 0..input.len() {
+                let chunks = vec![
+                    Ok::<_, BoxError>(Bytes::copy_from_slice(&input[..split])),
+                    Ok(Bytes::copy_from_slice(&input[split..])),
+                ];
+                let (arms, records) =
+                    read_paired_input(stream::iter(chunks), RecordFraming::LengthDelimited);
+                let records = records.try_collect::<Vec<_>>().await.unwrap().concat();
+                assert_eq!(vec![1, 0, 7, 2, 0, 8, 9, 0, 0], records);
+                assert_eq!(
+                    vec![Arm::Control, Arm::Treatment, Arm::Control],
+                    arms.collect::<Vec<_>>()
+                );
+            }
+
+            let (_, records) = read_paired_input(
+                stream::iter(vec![Ok::<_, BoxError>(Bytes::from_static(&[0, 1, 0]))]),
+                RecordFraming::LengthDelimited,
+            );
+            assert!(records.try_collect::<Vec<_>>().await.is_err());
+        });
+    }
+
+    #[test]
+    fn rejects_partial_record() {
+        assert!(split_arms(&[0, 1, 0, 7, 1, 2, 0, 8], RecordFraming::LengthDelimited).is_err());
+        assert!(split_arms(&[0, 1], RecordFraming::LengthDelimited).is_err());
+    }
+}
@@ -47,7 +47,10 @@ impl<Buf: Into<bytes::Bytes>> From<Buf> for WrappedBoxBodyStream {
     }
 }
 
-#[cfg(all(feature = "in-memory-infra", feature = "web-app"))]
+#[cfg(all(
+    any(feature = "in-memory-infra", feature = "dyn-infra"),
+    feature = "web-app"
+))]
 #[async_trait::async_trait]
 impl<S> axum::extract::FromRequest<S> for WrappedBoxBodyStream
 where
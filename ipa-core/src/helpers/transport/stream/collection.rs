@@ -115,6 +115,15 @@ impl<I: TransportIdentity, S: Stream> StreamCollection<I, S> {
         streams.clear();
     }
 
+    /// Removes the streams of `query_id`, leaving those of other queries in place.
+    ///
+    /// ## Panics
+    /// if mutex is poisoned.
+    pub fn clear_query(&self, query_id: QueryId) {
+        let mut streams = self.inner.lock().unwrap();
+        streams.retain(|(id, _, _), _| *id != query_id);
+    }
+
     /// Returns the number of streams inside this collection.
     ///
     /// ## Panics
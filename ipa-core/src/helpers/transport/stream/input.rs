@@ -6,6 +6,7 @@ use std::{
     future::Ready,
     io,
     marker::PhantomData,
+    num::NonZeroUsize,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -21,6 +22,13 @@ use typenum::{Unsigned, U2};
 
 use crate::{error::BoxError, ff::Serializable, helpers::BytesStream};
 
+/// Most records [`RecordsStream`] yields in one batch, unless set otherwise with
+/// [`RecordsStream::with_max_batch_size`].
+pub const DEFAULT_MAX_BATCH_SIZE: NonZeroUsize = match NonZeroUsize::new(4096) {
+    Some(v) => v,
+    None => unreachable!(),
+};
+
 #[derive(Debug)]
 pub struct BufDeque {
     buffered_size: usize,
@@ -93,18 +101,40 @@ impl BufDeque {
 
     /// Deserialize fixed-length items from the buffer.
     ///
-    /// Deserializes `count` items of fixed-length-[`Serializable`] type `T` from the stream.
-    /// Returns `None` if there are less than `count` items available, or if `count` is zero.
+    /// Deserializes up to `count` items of fixed-length-[`Serializable`] type `T` from the
+    /// stream. Returns `None` if there are less than `count` items available, or if `count` is
+    /// zero. Items before the first one that fails to deserialize are returned on their own,
+    /// and that item is left in the buffer, so that the next call returns its error along with
+    /// its index, which is always 0. The items after it are left in the buffer as well.
     fn read_multi<T: Serializable>(
         &mut self,
         count: usize,
-    ) -> Option<Result<Vec<T>, T::DeserializationError>> {
-        self.read_bytes(count * T::Size::USIZE).map(|bytes| {
-            bytes
-                .chunks(T::Size::USIZE)
-                .map(|bytes| T::deserialize(GenericArray::from_slice(bytes)))
-                .collect::<Result<_, _>>()
-        })
+    ) -> Option<Result<Vec<T>, (usize, T::DeserializationError)>> {
+        let bytes = self.read_bytes(count * T::Size::USIZE)?;
+        let mut items = Vec::with_capacity(count);
+        for (i, chunk) in bytes.chunks(T::Size::USIZE).enumerate() {
+            match T::deserialize(GenericArray::from_slice(chunk)) {
+                Ok(item) => items.push(item),
+                Err(_) if i > 0 => {
+                    self.unread(bytes.slice(i * T::Size::USIZE..));
+                    return Some(Ok(items));
+                }
+                Err(e) => {
+                    self.unread(bytes.slice(T::Size::USIZE..));
+                    return Some(Err((0, e)));
+                }
+            }
+        }
+
+        Some(Ok(items))
+    }
+
+    /// Puts `bytes` back in front of the buffer, to be read again.
+    fn unread(&mut self, bytes: Bytes) {
+        if !bytes.is_empty() {
+            self.buffered_size += bytes.len();
+            self.buffered.push_front(bytes);
+        }
     }
 
     /// Deserialize a single instance of `T` from the buffer with the guarantee that deserialization
@@ -164,9 +194,13 @@ enum ExtendResult {
 pub trait Mode {
     type Output<T: Serializable>;
 
+    /// Reads at most `limit` records from the buffer. If a record can't be deserialized, the
+    /// records before it are returned first, then the error along with the index of that
+    /// record among the ones read.
     fn read_from<T: Serializable>(
         buf: &mut BufDeque,
-    ) -> Option<Result<Self::Output<T>, T::DeserializationError>>;
+        limit: NonZeroUsize,
+    ) -> Option<Result<Self::Output<T>, (usize, T::DeserializationError)>>;
 }
 
 /// Makes [`RecordsStream`] return one record per poll.
@@ -180,8 +214,9 @@ impl Mode for Single {
 
     fn read_from<T: Serializable>(
         buf: &mut BufDeque,
-    ) -> Option<Result<Self::Output<T>, T::DeserializationError>> {
-        buf.try_read()
+        _limit: NonZeroUsize,
+    ) -> Option<Result<Self::Output<T>, (usize, T::DeserializationError)>> {
+        buf.try_read().map(|r| r.map_err(|e| (0, e)))
     }
 }
 impl Mode for Batch {
@@ -189,17 +224,36 @@ impl Mode for Batch {
 
     fn read_from<T: Serializable>(
         buf: &mut BufDeque,
-    ) -> Option<Result<Self::Output<T>, T::DeserializationError>> {
-        let count = max(1, buf.contiguous_len() / T::Size::USIZE);
+        limit: NonZeroUsize,
+    ) -> Option<Result<Self::Output<T>, (usize, T::DeserializationError)>> {
+        let count = max(1, buf.contiguous_len() / T::Size::USIZE).min(limit.get());
         buf.read_multi(count)
     }
 }
 
+/// A record in the input of [`RecordsStream`] could not be deserialized.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to parse record at byte offset {offset}: {source}")]
+pub struct RecordParseError {
+    /// Offset of the first byte of the record within the input.
+    pub offset: usize,
+    source: BoxError,
+}
+
 /// Parse a [`Stream`] of bytes into a stream of records of some
 /// fixed-length-[`Serializable`] type `T`.
 ///
 /// Depending on `M`, the provided stream can yield a single record `T` or multiples of `T`. See
 /// [`Single`], [`Batch`] and [`Mode`]
+///
+/// Records are yielded as soon as enough bytes for them are received. In [`Batch`] mode, the
+/// number of records yielded at once is bounded by [`DEFAULT_MAX_BATCH_SIZE`], or by
+/// [`Self::with_max_batch_size`], so the amount of data parsed ahead of the consumer does not
+/// depend on how the input is chunked.
+///
+/// If a record fails to deserialize, the stream yields the records before it, then a
+/// [`RecordParseError`] with its byte offset, and continues with the next record. By default, input that ends in the middle of a
+/// record is an error; see [`Self::allow_truncated_tail`].
 #[pin_project]
 pub struct RecordsStream<T, S, M = Batch>
 where
@@ -214,6 +268,9 @@ where
     #[pin]
     stream: Fuse<S>,
     buffer: BufDeque,
+    offset: usize,
+    max_batch_size: NonZeroUsize,
+    allow_truncated_tail: bool,
     phantom_data: PhantomData<(T, M)>,
 }
 
@@ -230,9 +287,34 @@ where
         Self {
             stream: stream.fuse(),
             buffer: BufDeque::new(),
+            offset: 0,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            allow_truncated_tail: false,
             phantom_data: PhantomData,
         }
     }
+
+    /// Limits the number of records yielded in one batch. Has no effect in [`Single`] mode.
+    #[must_use]
+    pub fn with_max_batch_size(mut self, max_batch_size: NonZeroUsize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// If set, input that ends in the middle of a record is not an error: the records before
+    /// it are yielded and the incomplete one is dropped.
+    #[must_use]
+    pub fn allow_truncated_tail(mut self, allow: bool) -> Self {
+        self.allow_truncated_tail = allow;
+        self
+    }
+
+    /// Returns the number of input bytes consumed so far, including bytes of records that
+    /// failed to deserialize.
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
 }
 
 impl<T, S, M> Stream for RecordsStream<T, S, M>
@@ -246,10 +328,18 @@ where
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
         loop {
-            if let Some(v) = M::read_from(this.buffer) {
-                return Poll::Ready(Some(v.map_err(|e: T::DeserializationError| {
-                    crate::error::Error::ParseError(e.into())
-                })));
+            let start = *this.offset;
+            let buffered = this.buffer.buffered_size;
+            if let Some(v) = M::read_from(this.buffer, *this.max_batch_size) {
+                *this.offset += buffered - this.buffer.buffered_size;
+                return Poll::Ready(Some(v.map_err(
+                    |(i, e): (usize, T::DeserializationError)| {
+                        crate::error::Error::ParseError(Box::new(RecordParseError {
+                            offset: start + i * T::Size::USIZE,
+                            source: e.into(),
+                        }))
+                    },
+                )));
             }
 
             // We need more data, poll the stream
@@ -257,6 +347,25 @@ where
                 return Poll::Pending;
             };
 
+            if polled_item.is_none() && this.buffer.buffered_size > 0 {
+                let truncated = this.buffer.buffered_size;
+                if *this.allow_truncated_tail {
+                    tracing::warn!(
+                        "dropping truncated record of {truncated} bytes at byte offset {start}"
+                    );
+                    this.buffer.read_bytes(truncated);
+                    *this.offset += truncated;
+                    return Poll::Ready(None);
+                }
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!(
+                        "stream terminated with {truncated} extra bytes at byte offset {start}"
+                    ),
+                )
+                .into())));
+            }
+
             match this.buffer.extend(polled_item) {
                 ExtendResult::Finished => return Poll::Ready(None),
                 ExtendResult::Error(err) => return Poll::Ready(Some(Err(err.into()))),
@@ -511,16 +620,17 @@ mod test {
     use super::*;
 
     mod unit_test {
-        use std::io;
+        use std::{io, num::NonZeroUsize};
 
         use futures::{StreamExt, TryStreamExt};
         use generic_array::GenericArray;
         use typenum::Unsigned;
 
+        use super::super::DEFAULT_MAX_BATCH_SIZE;
         use crate::{
             error::Error,
             ff::{Fp31, Fp32BitPrime, Serializable},
-            helpers::RecordsStream,
+            helpers::{RecordParseError, RecordsStream},
             secret_sharing::replicated::semi_honest::AdditiveShare,
         };
 
@@ -647,6 +757,86 @@ mod test {
             assert_eq!(seen_count, ARR_SIZE);
             assert!(more_than_one);
         }
+
+        #[tokio::test]
+        async fn max_batch_size() {
+            let stream = RecordsStream::<Fp31, _>::from(vec![3; 10])
+                .with_max_batch_size(NonZeroUsize::new(4).unwrap());
+            let batches = stream.try_collect::<Vec<Vec<Fp31>>>().await.unwrap();
+
+            assert_eq!(
+                vec![4, 4, 2],
+                batches.iter().map(Vec::len).collect::<Vec<_>>()
+            );
+        }
+
+        #[tokio::test]
+        async fn reports_parse_error_offset() {
+            // 31 is not a valid Fp31 value
+            let mut stream = RecordsStream::<Fp31, _>::from(vec![vec![1, 2], vec![3, 31, 5]])
+                .with_max_batch_size(NonZeroUsize::new(2).unwrap());
+
+            assert_eq!(2, stream.next().await.unwrap().unwrap().len());
+            // records parsed before the invalid one are not lost
+            assert_eq!(
+                vec![Fp31::try_from(3).unwrap()],
+                stream.next().await.unwrap().unwrap()
+            );
+            let err = stream.next().await.unwrap().unwrap_err();
+            let Error::ParseError(err) = err else {
+                panic!("unexpected error: {err}")
+            };
+            assert_eq!(3, err.downcast_ref::<RecordParseError>().unwrap().offset);
+
+            // parsing continues after the invalid record
+            assert_eq!(
+                vec![Fp31::try_from(5).unwrap()],
+                stream.next().await.unwrap().unwrap()
+            );
+            assert!(stream.next().await.is_none());
+            assert_eq!(5, stream.offset());
+        }
+
+        #[tokio::test]
+        async fn bounded_batches_by_default() {
+            let len = DEFAULT_MAX_BATCH_SIZE.get() + 1;
+            let stream = RecordsStream::<Fp31, _>::from(vec![3; len]);
+            let batches = stream.try_collect::<Vec<Vec<Fp31>>>().await.unwrap();
+
+            assert_eq!(
+                vec![DEFAULT_MAX_BATCH_SIZE.get(), 1],
+                batches.iter().map(Vec::len).collect::<Vec<_>>()
+            );
+        }
+
+        #[tokio::test]
+        async fn reports_truncation_offset() {
+            let vec = vec![4u8; 2 * <Fp32BitPrime as Serializable>::Size::USIZE + 3];
+            let err = RecordsStream::<Fp32BitPrime, _>::from(vec)
+                .try_concat()
+                .await
+                .unwrap_err();
+
+            assert!(
+                err.to_string().contains("3 extra bytes at byte offset 8"),
+                "{err}"
+            );
+        }
+
+        #[tokio::test]
+        async fn allow_truncated_tail() {
+            let vec = vec![4u8; 2 * <Fp32BitPrime as Serializable>::Size::USIZE + 3];
+            let chunks = vec.chunks(3).map(ToOwned::to_owned).collect::<Vec<_>>();
+            let mut stream =
+                RecordsStream::<Fp32BitPrime, _>::from(chunks).allow_truncated_tail(true);
+            let mut records = Vec::new();
+            while let Some(batch) = stream.next().await {
+                records.extend(batch.unwrap());
+            }
+
+            assert_eq!(records, vec![0x0404_0404; 2]);
+            assert_eq!(vec.len(), stream.offset());
+        }
     }
 
     mod single_record {
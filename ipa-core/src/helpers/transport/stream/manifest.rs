@@ -0,0 +1,439 @@
+//! End-of-input manifests, to detect inputs that were truncated or corrupted on upload.
+//!
+//! Report collectors that opt in append an [`InputManifest`] to the input they send to each
+//! helper. It carries the number of records in the input and the SHA-256 digest of everything
+//! before it. Helpers strip the manifest from the stream while they read it, and check it once
+//! the whole input has been read, before any computation starts.
+
+use std::{
+    num::NonZeroUsize,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use pin_project::pin_project;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::BoxError,
+    helpers::BytesStream,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InputIntegrityError {
+    #[error("input does not end with a manifest")]
+    MissingManifest,
+    #[error("input digest does not match the manifest")]
+    DigestMismatch,
+    #[error("manifest declares {expected} records, but the input has {actual}")]
+    RecordCount { expected: u32, actual: u64 },
+    #[error("input ends in the middle of a record")]
+    PartialRecord,
+    #[error("input was not read to the end")]
+    Incomplete,
+}
+
+/// Trailer appended to query inputs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputManifest {
+    pub records: u32,
+    pub digest: [u8; 32],
+}
+
+impl InputManifest {
+    const MAGIC: [u8; 8] = *b"IPAINEND";
+
+    /// Size of the serialized manifest.
+    pub const SIZE: usize = Self::MAGIC.len() + 4 + 32;
+
+    /// Builds the manifest for `input`, which holds `records` records.
+    #[must_use]
+    pub fn for_input(input: &[u8], records: u32) -> Self {
+        Self {
+            records,
+            digest: Sha256::digest(input).into(),
+        }
+    }
+
+    /// Appends the manifest for the `records` records in `input` to it.
+    ///
+    /// ## Panics
+    /// If there are more than `u32::MAX` records.
+    pub fn append_to(input: &mut Vec<u8>, records: usize) {
+        let manifest = Self::for_input(input, records.try_into().unwrap());
+        input.extend_from_slice(&manifest.to_bytes());
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0; Self::SIZE];
+        let (magic, rest) = buf.split_at_mut(Self::MAGIC.len());
+        let (records, digest) = rest.split_at_mut(4);
+        magic.copy_from_slice(&Self::MAGIC);
+        records.copy_from_slice(&self.records.to_le_bytes());
+        digest.copy_from_slice(&self.digest);
+        buf
+    }
+
+    /// Returns `None` if `buf` is not a manifest.
+    #[must_use]
+    pub fn from_bytes(buf: &[u8; Self::SIZE]) -> Option<Self> {
+        let (magic, rest) = buf.split_first_chunk::<8>()?;
+        let (records, digest) = rest.split_first_chunk::<4>()?;
+        let digest = <[u8; 32]>::try_from(digest).ok()?;
+        (*magic == Self::MAGIC).then(|| Self {
+            records: u32::from_le_bytes(*records),
+            digest,
+        })
+    }
+}
+
+/// How records are laid out in the input, to count them.
+#[derive(Clone, Copy, Debug)]
+pub enum RecordFraming {
+    /// Each record is prefixed with its length, as read by [`LengthDelimitedStream`].
+    ///
+    /// [`LengthDelimitedStream`]: crate::helpers::LengthDelimitedStream
+    LengthDelimited,
+    /// All records have this size.
+    Fixed(NonZeroUsize),
+}
+
+/// Counts the records of an input as it is read, chunk by chunk.
+#[derive(Debug)]
+pub struct RecordCounter {
+    framing: RecordFraming,
+    records: u64,
+    /// Bytes left in the current record, for fixed size records, or in the current
+    /// length-delimited record, once its length has been read.
+    remaining: usize,
+    /// First byte of a length prefix that was split between two chunks.
+    partial_length: Option<u8>,
+}
+
+impl RecordCounter {
+    #[must_use]
+    pub fn new(framing: RecordFraming) -> Self {
+        Self {
+            framing,
+            records: 0,
+            remaining: 0,
+            partial_length: None,
+        }
+    }
+
+    /// Number of records that started in the chunks consumed so far.
+    #[must_use]
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Counts the records that start in `buf`, the next chunk of the input.
+    pub fn consume(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(buf.len());
+                self.remaining -= n;
+                buf = &buf[n..];
+                continue;
+            }
+            self.records += 1;
+            match self.framing {
+                RecordFraming::Fixed(size) => self.remaining = size.get(),
+                RecordFraming::LengthDelimited => {
+                    let len = match (self.partial_length.take(), buf) {
+                        (Some(lo), [hi, rest @ ..]) => {
+                            buf = rest;
+                            u16::from_le_bytes([lo, *hi])
+                        }
+                        (None, [lo, hi, rest @ ..]) => {
+                            buf = rest;
+                            u16::from_le_bytes([*lo, *hi])
+                        }
+                        (None, [lo]) => {
+                            // The rest of the prefix is in the next chunk. Don't count this
+                            // record twice.
+                            self.records -= 1;
+                            self.partial_length = Some(*lo);
+                            buf = &[];
+                            continue;
+                        }
+                        (_, []) => unreachable!(),
+                    };
+                    self.remaining = usize::from(len);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the last record consumed so far is whole.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0 && self.partial_length.is_none()
+    }
+}
+
+/// Number of records declared by the manifest, if the input matches it.
+type Outcome = Arc<Mutex<Option<Result<u32, InputIntegrityError>>>>;
+
+/// Strips the manifest from the end of an input stream and checks the input against it.
+/// The outcome of the check is available from the [`ManifestCheck`] returned by
+/// [`Self::new`] once the stream is exhausted.
+#[pin_project]
+pub struct ManifestStream<S> {
+    #[pin]
+    inner: S,
+    hasher: Sha256,
+    counter: RecordCounter,
+    /// The last [`InputManifest::SIZE`] bytes seen. They are only released once it is known
+    /// that they are not part of the manifest.
+    tail: Vec<u8>,
+    outcome: Outcome,
+}
+
+/// Result of checking an input against its manifest.
+pub struct ManifestCheck(Outcome);
+
+impl ManifestCheck {
+    /// ## Errors
+    /// If the input does not match its manifest, or was not read to the end.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn verify(&self) -> Result<(), InputIntegrityError> {
+        self.outcome().map(|_| ())
+    }
+
+    /// Same as [`Self::verify`], and also checks that the reader of the input got as many
+    /// `records` out of it as the manifest declares.
+    ///
+    /// ## Errors
+    /// If the input does not match its manifest, was not read to the end or has a different
+    /// number of records.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn verify_records(&self, records: usize) -> Result<(), InputIntegrityError> {
+        let expected = self.outcome()?;
+        let actual = u64::try_from(records).unwrap();
+        if actual != u64::from(expected) {
+            return Err(InputIntegrityError::RecordCount { expected, actual });
+        }
+
+        Ok(())
+    }
+
+    fn outcome(&self) -> Result<u32, InputIntegrityError> {
+        self.0
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or(Err(InputIntegrityError::Incomplete))
+    }
+}
+
+impl<S: BytesStream> ManifestStream<S> {
+    #[must_use]
+    pub fn new(inner: S, framing: RecordFraming) -> (Self, ManifestCheck) {
+        let outcome = Arc::new(Mutex::new(None));
+        (
+            Self {
+                inner,
+                hasher: Sha256::new(),
+                counter: RecordCounter::new(framing),
+                tail: Vec::with_capacity(2 * InputManifest::SIZE),
+                outcome: Arc::clone(&outcome),
+            },
+            ManifestCheck(outcome),
+        )
+    }
+}
+
+impl<S: BytesStream> Stream for ManifestStream<S> {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let held = this.tail.len() + chunk.len();
+                    if held <= InputManifest::SIZE {
+                        this.tail.extend_from_slice(&chunk);
+                        continue;
+                    }
+                    let release_len = held - InputManifest::SIZE;
+                    let release = if release_len <= this.tail.len() {
+                        let release = this.tail.drain(..release_len).collect::<Vec<_>>();
+                        this.tail.extend_from_slice(&chunk);
+                        Bytes::from(release)
+                    } else {
+                        let from_chunk = release_len - this.tail.len();
+                        let mut release = std::mem::take(this.tail);
+                        release.extend_from_slice(&chunk[..from_chunk]);
+                        this.tail.extend_from_slice(&chunk[from_chunk..]);
+                        Bytes::from(release)
+                    };
+                    this.hasher.update(&release);
+                    this.counter.consume(&release);
+                    return Poll::Ready(Some(Ok(release)));
+                }
+                Poll::Ready(None) => {
+                    let outcome = check(this.tail, this.hasher.clone(), this.counter);
+                    if let Err(e) = &outcome {
+                        tracing::error!("input integrity check failed: {e}");
+                    }
+                    *this.outcome.lock().unwrap() = Some(outcome);
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+}
+
+fn check(tail: &[u8], hasher: Sha256, counter: &RecordCounter) -> Result<u32, InputIntegrityError> {
+    let manifest = <&[u8; InputManifest::SIZE]>::try_from(tail)
+        .ok()
+        .and_then(InputManifest::from_bytes)
+        .ok_or(InputIntegrityError::MissingManifest)?;
+    if <[u8; 32]>::from(hasher.finalize()) != manifest.digest {
+        return Err(InputIntegrityError::DigestMismatch);
+    }
+    if !counter.is_complete() {
+        return Err(InputIntegrityError::PartialRecord);
+    }
+    if counter.records != u64::from(manifest.records) {
+        return Err(InputIntegrityError::RecordCount {
+            expected: manifest.records,
+            actual: counter.records,
+        });
+    }
+
+    Ok(manifest.records)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use bytes::Bytes;
+    use futures::{stream::iter, StreamExt};
+
+    use super::{InputIntegrityError, InputManifest, ManifestStream, RecordFraming};
+    use crate::{error::BoxError, test_executor::run};
+
+    fn length_delimited(records: &[&[u8]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for record in records {
+            buf.extend_from_slice(&u16::try_from(record.len()).unwrap().to_le_bytes());
+            buf.extend_from_slice(record);
+        }
+        buf
+    }
+
+    /// Sends `input` in chunks of `chunk_size` bytes and returns the input read by the helper
+    /// together with the outcome of the check.
+    async fn read(
+        input: &[u8],
+        chunk_size: usize,
+        framing: RecordFraming,
+    ) -> (Vec<u8>, Result<(), InputIntegrityError>) {
+        let chunks = input
+            .chunks(chunk_size)
+            .map(|c| Ok::<_, BoxError>(Bytes::copy_from_slice(c)))
+            .collect::<Vec<_>>();
+        let (stream, check) = ManifestStream::new(iter(chunks), framing);
+        let read = stream.map(|chunk| chunk.unwrap().to_vec()).concat().await;
+        (read, check.verify())
+    }
+
+    #[test]
+    fn round_trip() {
+        run(|| async {
+            let records: [&[u8]; 4] = [b"a", &[0xff; 300], b"", b"report"];
+            let input = length_delimited(&records);
+            let mut with_manifest = input.clone();
+            InputManifest::append_to(&mut with_manifest, records.len());
+            for chunk_size in [1, 2, 3, 7, 44, 45, 1000] {
+                let (read, outcome) =
+                    read(&with_manifest, chunk_size, RecordFraming::LengthDelimited).await;
+                assert_eq!(input, read, "chunk size {chunk_size}");
+                assert_eq!(Ok(()), outcome, "chunk size {chunk_size}");
+            }
+        });
+    }
+
+    #[test]
+    fn truncated() {
+        run(|| async {
+            let input = length_delimited(&[b"first", b"second"]);
+            let manifest = InputManifest::for_input(&input, 2);
+            let mut truncated = input[..input.len() - 3].to_vec();
+            truncated.extend_from_slice(&manifest.to_bytes());
+            let (_, outcome) = read(&truncated, 4, RecordFraming::LengthDelimited).await;
+            assert_eq!(Err(InputIntegrityError::DigestMismatch), outcome);
+
+            let (_, outcome) = read(&input, 4, RecordFraming::LengthDelimited).await;
+            assert_eq!(Err(InputIntegrityError::MissingManifest), outcome);
+        });
+    }
+
+    #[test]
+    fn record_count() {
+        run(|| async {
+            let size = NonZeroUsize::new(4).unwrap();
+            let input = vec![7; 12];
+            let mut with_manifest = input.clone();
+            InputManifest::append_to(&mut with_manifest, 2);
+            let (_, outcome) = read(&with_manifest, 5, RecordFraming::Fixed(size)).await;
+            assert_eq!(
+                Err(InputIntegrityError::RecordCount {
+                    expected: 2,
+                    actual: 3
+                }),
+                outcome
+            );
+
+            let input = vec![7; 10];
+            let mut with_manifest = input.clone();
+            InputManifest::append_to(&mut with_manifest, 3);
+            let (_, outcome) = read(&with_manifest, 5, RecordFraming::Fixed(size)).await;
+            assert_eq!(Err(InputIntegrityError::PartialRecord), outcome);
+        });
+    }
+
+    #[test]
+    fn records_read() {
+        run(|| async {
+            let input = length_delimited(&[b"first", b"second"]);
+            let mut with_manifest = input.clone();
+            InputManifest::append_to(&mut with_manifest, 2);
+            let chunks = vec![Ok::<_, BoxError>(Bytes::from(with_manifest))];
+            let (stream, check) = ManifestStream::new(iter(chunks), RecordFraming::LengthDelimited);
+            stream.map(|chunk| chunk.unwrap()).collect::<Vec<_>>().await;
+
+            assert_eq!(Ok(()), check.verify_records(2));
+            assert_eq!(
+                Err(InputIntegrityError::RecordCount {
+                    expected: 2,
+                    actual: 1
+                }),
+                check.verify_records(1)
+            );
+        });
+    }
+
+    #[test]
+    fn not_read_to_the_end() {
+        let (_, check) = ManifestStream::new(
+            iter(Vec::<Result<Bytes, BoxError>>::new()),
+            RecordFraming::LengthDelimited,
+        );
+        assert_eq!(Err(InputIntegrityError::Incomplete), check.verify());
+    }
+}
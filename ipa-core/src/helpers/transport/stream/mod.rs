@@ -1,3 +1,4 @@
+mod arms;
 #[cfg(feature = "web-app")]
 mod axum_body;
 mod box_body;
@@ -5,12 +6,17 @@ mod box_body;
 mod buffered;
 mod collection;
 mod input;
+mod manifest;
+mod prefixed;
+mod sites;
+mod tags;
 
 use std::{
     pin::Pin,
     task::{Context, Poll},
 };
 
+pub use arms::{read_paired_input, Arm};
 #[cfg(feature = "web-app")]
 pub use axum_body::WrappedAxumBodyStream;
 pub use box_body::WrappedBoxBodyStream;
@@ -19,7 +25,13 @@ pub use collection::{StreamCollection, StreamKey};
 use futures::{stream::iter, Stream};
 use futures_util::StreamExt;
 use generic_array::GenericArray;
-pub use input::{LengthDelimitedStream, RecordsStream, SingleRecordStream};
+pub use input::{LengthDelimitedStream, RecordParseError, RecordsStream, SingleRecordStream};
+pub use manifest::{
+    InputIntegrityError, InputManifest, ManifestCheck, ManifestStream, RecordCounter, RecordFraming,
+};
+pub use prefixed::RecordPrefixes;
+pub use sites::read_site_input;
+pub use tags::read_tagged_input;
 
 use crate::{const_assert, error::BoxError, ff::Serializable};
 
@@ -48,7 +60,7 @@ pub type BoxBytesStream = Pin<Box<dyn BytesStream>>;
 //  * Reducing the number of places we depend on axum types.
 //  * Avoiding an extra level of boxing in the production configuration using axum, since
 //    the axum body stream type is already a `Pin<Box<dyn HttpBody>>`.
-#[cfg(feature = "in-memory-infra")]
+#[cfg(any(feature = "in-memory-infra", feature = "dyn-infra"))]
 type BodyStreamInner = WrappedBoxBodyStream;
 #[cfg(feature = "real-world-infra")]
 type BodyStreamInner = WrappedAxumBodyStream;
@@ -0,0 +1,202 @@
+//! Inputs in which every record is preceded by one public byte, such as the [`Arm`] of the
+//! records of paired queries, the source site of the records of per-site queries or the tag of
+//! the records of tagged queries.
+//!
+//! [`Arm`]: super::Arm
+
+use std::collections::VecDeque;
+
+use bytes::{Bytes, BytesMut};
+use futures::{future::ready, stream, StreamExt, TryStreamExt};
+
+use crate::{
+    error::{BoxError, Error},
+    helpers::{BytesStream, RecordFraming},
+    sync::{Arc, Mutex},
+};
+
+/// Prefixes stripped from an input by [`strip_prefixes`], in input order. The prefix of a
+/// record is available as soon as any byte of that record has been read from the stripped
+/// input, so parsers that read records from it always find the prefixes of these records here.
+pub struct RecordPrefixes<T>(Arc<Mutex<VecDeque<T>>>);
+
+impl<T> Iterator for RecordPrefixes<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.lock().unwrap().pop_front()
+    }
+}
+
+/// Part of a record that the next byte of the input belongs to.
+#[derive(Clone, Copy)]
+enum Next {
+    Prefix,
+    /// Length of a length-delimited record, with its first byte if that has been read already.
+    Length(Option<u8>),
+    /// Remaining bytes of the record.
+    Record(usize),
+}
+
+/// Strips the prefixes from an input, one chunk at a time.
+struct Splitter<F> {
+    framing: RecordFraming,
+    parse: F,
+    records: usize,
+    next: Next,
+}
+
+impl<T, F: FnMut(usize, u8) -> Result<T, Error>> Splitter<F> {
+    fn new(framing: RecordFraming, parse: F) -> Self {
+        Self {
+            framing,
+            parse,
+            records: 0,
+            next: Next::Prefix,
+        }
+    }
+
+    /// Returns `chunk` without the prefixes in it, which are parsed and added to `prefixes`.
+    fn split(
+        &mut self,
+        mut chunk: &[u8],
+        prefixes: &mut impl Extend<T>,
+    ) -> Result<BytesMut, Error> {
+        let mut records = BytesMut::with_capacity(chunk.len());
+        while let Some((&byte, tail)) = chunk.split_first() {
+            self.next = match self.next {
+                Next::Prefix => {
+                    prefixes.extend(Some((self.parse)(self.records, byte)?));
+                    self.records += 1;
+                    chunk = tail;
+                    match self.framing {
+                        RecordFraming::Fixed(size) => Next::Record(size.get()),
+                        RecordFraming::LengthDelimited => Next::Length(None),
+                    }
+                }
+                Next::Length(None) => {
+                    records.extend_from_slice(&[byte]);
+                    chunk = tail;
+                    Next::Length(Some(byte))
+                }
+                Next::Length(Some(lo)) => {
+                    records.extend_from_slice(&[byte]);
+                    chunk = tail;
+                    match u16::from_le_bytes([lo, byte]) {
+                        0 => Next::Prefix,
+                        len => Next::Record(usize::from(len)),
+                    }
+                }
+                Next::Record(remaining) => {
+                    let (record, tail) = chunk.split_at(remaining.min(chunk.len()));
+                    records.extend_from_slice(record);
+                    chunk = tail;
+                    match remaining - record.len() {
+                        0 => Next::Prefix,
+                        remaining => Next::Record(remaining),
+                    }
+                }
+            };
+        }
+
+        Ok(records)
+    }
+
+    /// ## Errors
+    /// If the input ended in the middle of a record.
+    fn finish(&self) -> Result<(), Error> {
+        match self.next {
+            Next::Prefix => Ok(()),
+            Next::Length(_) | Next::Record(_) => Err(Error::ParseError(
+                format!("input ends in the middle of record {}", self.records - 1).into(),
+            )),
+        }
+    }
+}
+
+/// Strips the byte that precedes every record laid out as described by `framing` from `input`,
+/// while it is read. `parse` gets the index of the record and its byte. Returns the parsed bytes
+/// and the input without them. Unlike [`read_prefixed_input`], this does not hold on to the
+/// input.
+///
+/// The returned input fails if `input` fails, if `parse` fails or if it ends in the middle of a
+/// record.
+pub(super) fn strip_prefixes<S, T, F>(
+    input: S,
+    framing: RecordFraming,
+    parse: F,
+) -> (RecordPrefixes<T>, impl BytesStream)
+where
+    S: BytesStream,
+    T: Send,
+    F: FnMut(usize, u8) -> Result<T, Error> + Send,
+{
+    let prefixes = Arc::new(Mutex::new(VecDeque::new()));
+    let stripped = stream::unfold(
+        Some((
+            Box::pin(input),
+            Splitter::new(framing, parse),
+            Arc::clone(&prefixes),
+        )),
+        |state| async move {
+            let (mut input, mut splitter, prefixes) = state?;
+            let next = match input.next().await {
+                Some(Ok(chunk)) => splitter
+                    .split(&chunk, &mut *prefixes.lock().unwrap())
+                    .map(|records| Some(records.freeze())),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => splitter.finish().map(|()| None),
+            };
+            match next {
+                Ok(Some(records)) => Some((Ok(records), Some((input, splitter, prefixes)))),
+                Ok(None) => None,
+                Err(e) => Some((Err(BoxError::from(e)), None)),
+            }
+        },
+    );
+
+    (RecordPrefixes(prefixes), stripped)
+}
+
+/// Reads the whole `input`, in which every record laid out as described by `framing` is
+/// preceded by one byte. `parse` gets the index of the record and its byte. Returns the parsed
+/// bytes of all records, in input order, and the input without them.
+///
+/// ## Errors
+/// If reading `input` fails, if `parse` fails or if the input ends in the middle of a record.
+pub(super) async fn read_prefixed_input<S, T, F>(
+    input: S,
+    framing: RecordFraming,
+    parse: F,
+) -> Result<(Vec<T>, Bytes), Error>
+where
+    S: BytesStream,
+    F: FnMut(usize, u8) -> Result<T, Error>,
+{
+    let input = input
+        .try_fold(BytesMut::new(), |mut buf, chunk| {
+            buf.extend_from_slice(&chunk);
+            ready(Ok(buf))
+        })
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, e))?;
+
+    split_prefixed(&input, framing, parse)
+}
+
+/// Same as [`read_prefixed_input`], for an input that was read already.
+pub(super) fn split_prefixed<T, F>(
+    input: &[u8],
+    framing: RecordFraming,
+    parse: F,
+) -> Result<(Vec<T>, Bytes), Error>
+where
+    F: FnMut(usize, u8) -> Result<T, Error>,
+{
+    let mut splitter = Splitter::new(framing, parse);
+    let mut prefixes = Vec::new();
+    let records = splitter.split(input, &mut prefixes)?;
+    splitter.finish()?;
+
+    Ok((prefixes, records.freeze()))
+}
@@ -0,0 +1,96 @@
+//! Inputs of queries that report aggregates per source site.
+//!
+//! Every record of such an input is preceded by one byte with the index of the site that
+//! produced it. Sites are public: helpers read them before the records are parsed and use them
+//! to keep the aggregates of different sites apart.
+
+use bytes::Bytes;
+
+use super::prefixed::read_prefixed_input;
+#[cfg(all(test, unit_test))]
+use super::prefixed::split_prefixed;
+use crate::{
+    error::Error,
+    helpers::{BytesStream, RecordFraming},
+};
+
+/// Reads the whole `input` of a per-site query, in which every record laid out as described by
+/// `framing` is preceded by the index of its source site, below `sites`. Returns the sites of
+/// all records, in input order, and the input without them.
+///
+/// ## Errors
+/// If reading `input` fails, if a site is not below `sites` or if the input ends in the middle
+/// of a record.
+pub async fn read_site_input<S: BytesStream>(
+    input: S,
+    framing: RecordFraming,
+    sites: u32,
+) -> Result<(Vec<u8>, Bytes), Error> {
+    read_prefixed_input(input, framing, |record, site| {
+        parse_site(record, site, sites)
+    })
+    .await
+}
+
+fn parse_site(record: usize, site: u8, sites: u32) -> Result<u8, Error> {
+    if u32::from(site) < sites {
+        Ok(site)
+    } else {
+        Err(Error::ParseError(
+            format!("record {record} has site {site}, but the query has {sites} sites").into(),
+        ))
+    }
+}
+
+#[cfg(all(test, unit_test))]
+fn split_sites(
+    input: &[u8],
+    framing: RecordFraming,
+    sites: u32,
+) -> Result<(Vec<u8>, Bytes), Error> {
+    split_prefixed(input, framing, |record, site| {
+        parse_site(record, site, sites)
+    })
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::split_sites;
+    use crate::helpers::RecordFraming;
+
+    #[test]
+    fn fixed_size_records() {
+        let framing = RecordFraming::Fixed(NonZeroUsize::new(2).unwrap());
+        let (sites, records) = split_sites(&[2, 7, 8, 0, 9, 10], framing, 3).unwrap();
+
+        assert_eq!(vec![2, 0], sites);
+        assert_eq!(&[7, 8, 9, 10], records.as_ref());
+    }
+
+    #[test]
+    fn length_delimited_records() {
+        let (sites, records) = split_sites(
+            &[1, 1, 0, 7, 3, 2, 0, 8, 9],
+            RecordFraming::LengthDelimited,
+            4,
+        )
+        .unwrap();
+
+        assert_eq!(vec![1, 3], sites);
+        assert_eq!(&[1, 0, 7, 2, 0, 8, 9], records.as_ref());
+    }
+
+    #[test]
+    fn rejects_unknown_site() {
+        let framing = RecordFraming::Fixed(NonZeroUsize::new(1).unwrap());
+        assert!(split_sites(&[0, 7, 2, 8], framing, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_partial_record() {
+        let framing = RecordFraming::Fixed(NonZeroUsize::new(2).unwrap());
+        assert!(split_sites(&[0, 7, 8, 1, 9], framing, 2).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Inputs of queries that run a sub-query per public tag.
+//!
+//! Every record of such an input is preceded by one byte with its tag, something the report
+//! collector knows about the report already, such as the campaign it belongs to. Tags are
+//! public: helpers read them before the records are parsed, and use them to keep the records of
+//! different sub-queries apart.
+
+use bytes::Bytes;
+
+use super::prefixed::read_prefixed_input;
+#[cfg(all(test, unit_test))]
+use super::prefixed::split_prefixed;
+use crate::{
+    error::Error,
+    helpers::{BytesStream, RecordFraming},
+};
+
+/// Reads the whole `input` of a tagged query, in which every record laid out as described by
+/// `framing` is preceded by its tag, below `tags`. Returns the tags of all records, in input
+/// order, and the input without them.
+///
+/// ## Errors
+/// If reading `input` fails, if a tag is not below `tags` or if the input ends in the middle
+/// of a record.
+pub async fn read_tagged_input<S: BytesStream>(
+    input: S,
+    framing: RecordFraming,
+    tags: u32,
+) -> Result<(Vec<u8>, Bytes), Error> {
+    read_prefixed_input(input, framing, |record, tag| parse_tag(record, tag, tags)).await
+}
+
+fn parse_tag(record: usize, tag: u8, tags: u32) -> Result<u8, Error> {
+    if u32::from(tag) < tags {
+        Ok(tag)
+    } else {
+        Err(Error::ParseError(
+            format!("record {record} has tag {tag}, but the query has {tags} tags").into(),
+        ))
+    }
+}
+
+#[cfg(all(test, unit_test))]
+fn split_tags(input: &[u8], framing: RecordFraming, tags: u32) -> Result<(Vec<u8>, Bytes), Error> {
+    split_prefixed(input, framing, |record, tag| parse_tag(record, tag, tags))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::split_tags;
+    use crate::helpers::RecordFraming;
+
+    #[test]
+    fn splits_tags() {
+        let (tags, records) = split_tags(
+            &[1, 1, 0, 7, 0, 2, 0, 8, 9],
+            RecordFraming::LengthDelimited,
+            2,
+        )
+        .unwrap();
+
+        assert_eq!(vec![1, 0], tags);
+        assert_eq!(&[1, 0, 7, 2, 0, 8, 9], records.as_ref());
+    }
+
+    #[test]
+    fn rejects_unknown_tag() {
+        let framing = RecordFraming::Fixed(NonZeroUsize::new(1).unwrap());
+        assert!(split_tags(&[0, 7, 3, 8], framing, 3).is_err());
+    }
+}
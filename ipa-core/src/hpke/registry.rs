@@ -1,11 +1,14 @@
 use std::ops::Deref;
 
 use hpke::Serializable;
+use zeroize::{Zeroize, Zeroizing};
 
 use super::{IpaPrivateKey, IpaPublicKey, KeyIdentifier};
 
 /// A pair of secret key and public key. Public keys used by UA to encrypt the data towards helpers
 /// secret keys used by helpers to open the ciphertexts. Each helper needs access to both
+///
+/// The secret key is wiped from memory by `hpke` when the pair is dropped.
 pub struct KeyPair {
     pk: IpaPublicKey,
     sk: IpaPrivateKey,
@@ -34,13 +37,16 @@ impl KeyPair {
     }
 
     /// Returns the secret key bytes, for the same reason as [`pk_bytes`] it returns an owned slice,
-    /// instead of borrow.
+    /// instead of borrow. The bytes are wiped from memory when the returned value is dropped.
     ///
     /// [`pk_bytes`]: Self::pk_bytes
     #[must_use]
-    pub fn sk_bytes(&self) -> Box<[u8]> {
-        let sk_bytes: [u8; 32] = self.sk.to_bytes().into();
-        Box::new(sk_bytes)
+    pub fn sk_bytes(&self) -> Zeroizing<Box<[u8]>> {
+        let mut sk_bytes = self.sk.to_bytes();
+        let boxed = Zeroizing::new(Box::<[u8]>::from(sk_bytes.as_slice()));
+        sk_bytes.as_mut_slice().zeroize();
+
+        boxed
     }
 }
 
@@ -108,6 +114,12 @@ impl<K> KeyRegistry<K> {
         }
     }
 
+    /// Returns `true` if this registry holds no keys.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
     fn key(&self, key_id: KeyIdentifier) -> Option<&K> {
         match key_id as usize {
             key_id if key_id < self.keys.len() => Some(&self.keys[key_id]),
@@ -226,4 +238,18 @@ mod tests {
             decrypt(private_registry.private_key(0).unwrap(), &ct_payload).unwrap_err()
         );
     }
+
+    #[test]
+    fn sk_bytes() {
+        let keypair = KeyPair::gen(&mut StdRng::seed_from_u64(42));
+        let mut sk_bytes = keypair.sk_bytes();
+        assert_eq!(
+            keypair.sk.to_bytes().as_slice(),
+            &**sk_bytes,
+            "secret key bytes do not match the key"
+        );
+
+        sk_bytes.zeroize();
+        assert!(sk_bytes.iter().all(|&b| b == 0));
+    }
 }
@@ -11,16 +11,25 @@
 pub mod cli;
 #[cfg(feature = "web-app")]
 pub mod config;
+#[cfg(all(feature = "embedded", feature = "in-memory-infra"))]
+pub mod embedded;
 pub mod error;
 pub mod ff;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 pub mod helpers;
 pub mod hpke;
 
 #[cfg(feature = "web-app")]
 pub mod net;
 pub mod protocol;
+#[cfg(feature = "python")]
+pub mod python;
 pub mod query;
 pub mod report;
+pub mod results;
 pub mod secret_sharing;
 pub mod telemetry;
 
@@ -382,6 +391,9 @@ macro_rules! mutually_incompatible {
 }
 
 mutually_incompatible!("in-memory-infra", "real-world-infra");
+mutually_incompatible!("in-memory-infra", "dyn-infra");
+mutually_incompatible!("real-world-infra", "dyn-infra");
+mutually_incompatible!("dhat-heap", "alloc-profiling");
 #[cfg(not(any(compact_gate, descriptive_gate)))]
 compile_error!("At least one of `compact_gate` or `descriptive_gate` features must be enabled");
 
@@ -0,0 +1,25 @@
+//! Integrity checks for step data.
+//!
+//! Starting with [`ProtocolVersion::V6`], every batch of step data a helper sends is protected
+//! by a CRC-32 checksum. Batches that are pushed to the peer are sent as [`frame`]s inside the
+//! request body, and a corrupted frame fails the stream. Batches that are pulled by the peer
+//! carry the checksum in the [`BATCH_CHECKSUM_HEADER`] of the response, and a corrupted batch
+//! is simply requested again.
+//!
+//! Pushed frames are not retransmitted. A step channel is pushed as a single request body that
+//! the sender does not keep once it is written, and there is no way to resume a channel from
+//! an offset. So one corrupted frame loses the rest of the channel, and the query fails with
+//! the checksum error instead of a MAC failure later on. Peers behind links that corrupt data
+//! should receive step data in pull mode, where every batch can be requested again.
+//!
+//! [`ProtocolVersion::V6`]: crate::protocol::ProtocolVersion::V6
+//! [`frame`]: crate::helpers::frame
+
+use hyper::header::HeaderName;
+
+/// Response header with the checksum of a pulled batch.
+pub static BATCH_CHECKSUM_HEADER: HeaderName = HeaderName::from_static("x-ipa-batch-crc32");
+
+/// Number of times a pulled batch is requested again if it fails the checksum, before giving
+/// up on the channel.
+pub const MAX_RETRANSMITS: usize = 3;
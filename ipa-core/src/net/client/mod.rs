@@ -16,7 +16,9 @@ use bytes::Bytes;
 use futures::{stream::StreamExt, Stream};
 use http_body_util::BodyExt;
 use hyper::{header::HeaderName, http::HeaderValue, Request, Response, StatusCode, Uri};
-use hyper_rustls::{ConfigBuilderExt, HttpsConnector, HttpsConnectorBuilder};
+use hyper_rustls::{
+    ConfigBuilderExt, FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder,
+};
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::TokioTimer,
@@ -33,11 +35,23 @@ use crate::{
     },
     executor::IpaRuntime,
     helpers::{
-        query::{CompareStatusRequest, PrepareQuery, QueryConfig, QueryInput},
-        TransportIdentity,
+        frame::{self, Frame},
+        query::{
+            CompareStatusRequest, PeerUnavailable, PrepareQuery, QueryConfig, QueryInput,
+            QuerySize, TemplateCommand, TemplateList, TemplateOverrides, ValidationReport,
+        },
+        BodyStream, TransportIdentity,
+    },
+    net::{
+        checksum,
+        error::ShardQueryStatusMismatchError,
+        http_serde,
+        open_files::OpenFilesConnector,
+        pinning::{native_roots, PinnedServerVerifier},
+        Error, PeerSigningKey, CRYPTO_PROVIDER,
     },
-    net::{error::ShardQueryStatusMismatchError, http_serde, Error, CRYPTO_PROVIDER},
-    protocol::{Gate, QueryId},
+    protocol::{Gate, ProtocolVersion, QueryId},
+    query::InputDeleted,
 };
 
 #[derive(Default)]
@@ -179,10 +193,11 @@ async fn response_to_bytes(resp: ResponseFromEndpoint) -> Result<Bytes, Error> {
 ///       client can be configured to talk to all three helpers.
 #[derive(Debug, Clone)]
 pub struct IpaHttpClient<F: ConnectionFlavor> {
-    client: Client<HttpsConnector<HttpConnector>, Body>,
+    client: Client<HttpsConnector<OpenFilesConnector<HttpConnector>>, Body>,
     scheme: uri::Scheme,
     authority: uri::Authority,
     auth_header: Option<(HeaderName, HeaderValue)>,
+    signing_key: Option<PeerSigningKey>,
     _restriction: PhantomData<F>,
 }
 
@@ -201,6 +216,7 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
         peer_config: PeerConfig,
         identity: ClientIdentity<F>,
     ) -> Self {
+        let open_files = client_config.open_files.clone();
         let (connector, auth_header) = if peer_config.url.scheme() == Some(&Scheme::HTTP) {
             // This connector works for both http and https. A regular HttpConnector would suffice,
             // but would make the type of `self.client` variable.
@@ -221,23 +237,40 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
                     .expect("Error creating client with Rustls, native roots should be available.")
                     .https_or_http()
                     .enable_http2()
-                    .wrap_connector(make_http_connector()),
+                    .wrap_connector(OpenFilesConnector::new(make_http_connector(), open_files)),
                 auth_header,
             )
         } else {
+            let tls = peer_config.tls;
             let builder = rustls::ClientConfig::builder_with_provider(Arc::clone(&CRYPTO_PROVIDER))
-                .with_safe_default_protocol_versions()
+                .with_protocol_versions(tls.min_version.protocol_versions())
                 .expect("Default crypto provider should be valid");
-            let client_config = if let Some(certificate) = peer_config.certificate {
+            let pins = peer_config.certificate_pins;
+            let trust_anchors = peer_config
+                .certificate
+                .into_iter()
+                .chain(tls.ca_bundle.into_iter().flatten())
+                .collect::<Vec<_>>();
+            let client_config = if !trust_anchors.is_empty() {
                 let cert_store = {
                     let mut store = RootCertStore::empty();
-                    store
-                        .add(certificate)
-                        .expect("Error adding Certificate, should be a valid Trust Anchor.");
+                    for certificate in trust_anchors {
+                        store
+                            .add(certificate)
+                            .expect("Error adding Certificate, should be a valid Trust Anchor.");
+                    }
                     store
                 };
 
-                let builder = builder.with_root_certificates(cert_store);
+                let builder = if let Some(pins) = pins {
+                    builder
+                        .dangerous()
+                        .with_custom_certificate_verifier(Arc::new(PinnedServerVerifier::new(
+                            cert_store, pins,
+                        )))
+                } else {
+                    builder.with_root_certificates(cert_store)
+                };
                 match identity {
                     ClientIdentity::Certificate((cert_chain, pk)) => builder
                         .with_client_auth_cert(cert_chain, pk)
@@ -248,6 +281,14 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
                     }
                     ClientIdentity::None => builder.with_no_client_auth(),
                 }
+            } else if let Some(pins) = pins {
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(PinnedServerVerifier::new(
+                        native_roots(),
+                        pins,
+                    )))
+                    .with_no_client_auth()
             } else {
                 builder.with_native_roots().unwrap().with_no_client_auth()
             };
@@ -255,29 +296,36 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
             // `HttpsConnector::new()`, but not by `HttpsConnector::from()`.
             let mut http = make_http_connector();
             http.enforce_http(false);
-            (
-                HttpsConnectorBuilder::new()
-                    .with_tls_config(client_config)
-                    .https_only()
-                    .enable_http2()
-                    .wrap_connector(http),
-                None,
-            )
+            let connector = HttpsConnectorBuilder::new()
+                .with_tls_config(client_config)
+                .https_only();
+            let connector = match tls.server_name {
+                Some(server_name) => {
+                    connector.with_server_name_resolver(FixedServerNameResolver::new(server_name))
+                }
+                None => connector,
+            };
+            let http = OpenFilesConnector::new(http, open_files);
+            (connector.enable_http2().wrap_connector(http), None)
         };
-        Self::new_internal(
+        // Signing only makes sense along with the claimed identity, which is sent in the clear.
+        let signing_key = auth_header.as_ref().and(peer_config.signing_key);
+        let mut client = Self::new_internal(
             runtime,
             peer_config.url,
             connector,
             auth_header,
             client_config,
-        )
+        );
+        client.signing_key = signing_key;
+        client
     }
 
     #[must_use]
     fn new_internal<C: HyperClientConfigurator>(
         runtime: IpaRuntime,
         addr: Uri,
-        connector: HttpsConnector<HttpConnector>,
+        connector: HttpsConnector<OpenFilesConnector<HttpConnector>>,
         auth_header: Option<(HeaderName, HeaderValue)>,
         conf: &C,
     ) -> Self {
@@ -300,12 +348,34 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
             scheme,
             authority,
             auth_header,
+            signing_key: None,
             _restriction: PhantomData,
         }
     }
 
-    pub fn request(&self, mut req: Request<Body>) -> ResponseFuture {
+    pub fn request(&self, req: Request<Body>) -> ResponseFuture {
+        self.request_with_payload(req, None)
+    }
+
+    /// Sends a request and includes `payload`, which must be the complete request body, in the
+    /// request signature, if requests to this peer are signed.
+    fn request_with_payload(
+        &self,
+        mut req: Request<Body>,
+        payload: Option<&[u8]>,
+    ) -> ResponseFuture {
         if let Some((k, v)) = self.auth_header.clone() {
+            if let Some(key) = &self.signing_key {
+                // Requests without a body sign the digest of the empty payload, so that they
+                // don't need to be sent in chunks.
+                let payload = payload.or_else(|| {
+                    (http_body::Body::size_hint(req.body()).exact() == Some(0)).then_some(&[][..])
+                });
+                if let Some(signer) = key.sign(v.to_str().unwrap(), &mut req, payload) {
+                    req = req
+                        .map(|body| Body::from_stream(signer.sign_stream(body.into_data_stream())));
+                }
+            }
             req.headers_mut().insert(k, v);
         }
         ResponseFuture {
@@ -351,16 +421,75 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
     pub fn step<S: Stream<Item = Vec<u8>> + Send + 'static>(
         &self,
         query_id: QueryId,
+        protocol_version: ProtocolVersion,
         gate: &Gate,
         data: S,
     ) -> Result<ResponseFuture, Error> {
-        let data = data.map(|v| Ok::<bytes::Bytes, Error>(Bytes::from(v)));
+        let checksums = protocol_version.has_batch_checksums();
+        let data = data.map(move |v| {
+            Ok::<bytes::Bytes, Error>(if checksums {
+                frame::encode(&v)
+            } else {
+                Bytes::from(v)
+            })
+        });
         let body = axum::body::Body::from_stream(data);
-        let req = http_serde::query::step::Request::new(query_id, gate.clone(), body);
+        let req =
+            http_serde::query::step::Request::new(query_id, protocol_version, gate.clone(), body);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
         Ok(self.request(req))
     }
 
+    /// Asks another helper for the next chunk of a step channel it is sending to this one,
+    /// starting at `from_offset` bytes. Returns `None` once the channel has been read to
+    /// the end.
+    /// # Errors
+    /// If the request has illegal arguments, fails to deliver to helper, the offset is not
+    /// the one helper expects, helper has nothing to serve before its pull timeout expires or
+    /// the chunk does not match its checksum.
+    pub async fn pull_step(
+        &self,
+        query_id: QueryId,
+        protocol_version: ProtocolVersion,
+        gate: &Gate,
+        from_offset: u64,
+    ) -> Result<Option<Bytes>, Error> {
+        let req = http_serde::query::step::PullRequest::new(
+            query_id,
+            protocol_version,
+            gate.clone(),
+            from_offset,
+        );
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        match resp.status() {
+            StatusCode::NO_CONTENT => Ok(None),
+            status if status.is_success() => {
+                let expected = if protocol_version.has_batch_checksums() {
+                    let header = resp
+                        .headers()
+                        .get(&checksum::BATCH_CHECKSUM_HEADER)
+                        .ok_or_else(|| {
+                            Error::MissingHeader(checksum::BATCH_CHECKSUM_HEADER.to_string())
+                        })?;
+                    Some(header.to_str()?.parse::<u32>()?)
+                } else {
+                    None
+                };
+                let batch = Frame {
+                    channel: gate.clone(),
+                    offset: from_offset,
+                    payload: response_to_bytes(resp).await?,
+                };
+                if let Some(expected) = expected {
+                    batch.verify(expected)?;
+                }
+                Ok(Some(batch.payload))
+            }
+            _ => Err(Error::from_failed_resp(resp).await),
+        }
+    }
+
     /// Used to communicate from one helper to another. Specifically, the helper that receives a
     /// "create query" from an external party must communicate the intent to start a query to the
     /// other helpers, which this prepare query does.
@@ -369,7 +498,16 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
     pub async fn prepare_query(&self, data: PrepareQuery) -> Result<(), Error> {
         let req = http_serde::query::prepare::Request::new(data);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
-        let resp = self.request(req).await?;
+        let resp = if self.signing_key.is_some() {
+            // Prepare requests are small, so their payload is buffered to be covered by the
+            // signature.
+            let (parts, body) = req.into_parts();
+            let payload = axum::body::to_bytes(body, usize::MAX).await?;
+            let req = Request::from_parts(parts, Body::from(payload.clone()));
+            self.request_with_payload(req, Some(&payload)).await?
+        } else {
+            self.request(req).await?
+        };
         resp_ok(resp).await
     }
 
@@ -409,6 +547,21 @@ impl<F: ConnectionFlavor> IpaHttpClient<F> {
             _ => Err(Error::from_failed_resp(resp).await),
         }
     }
+
+    /// Tells a peer helper that a query can't finish, because the third helper in it is
+    /// unavailable.
+    ///
+    /// # Errors
+    /// If the request has illegal arguments, or fails to be delivered
+    pub async fn peer_unavailable(&self, data: PeerUnavailable) -> Result<(), Error> {
+        let req = http_serde::query::peer_unavailable::try_into_http_request(
+            &data,
+            self.scheme.clone(),
+            self.authority.clone(),
+        )?;
+        let resp = self.request(req).await?;
+        resp_ok(resp).await
+    }
 }
 
 impl IpaHttpClient<Helper> {
@@ -455,6 +608,68 @@ impl IpaHttpClient<Helper> {
         }
     }
 
+    /// Intended to be called externally, by the report collector. Same as [`Self::create_query`],
+    /// but the query configuration comes from the template `template_id` stored on the helpers.
+    /// # Errors
+    /// If the template does not exist or does not allow `overrides`, or the request fails to
+    /// deliver to helper
+    pub async fn create_query_from_template(
+        &self,
+        template_id: &str,
+        overrides: TemplateOverrides,
+    ) -> Result<QueryId, Error> {
+        let req =
+            http_serde::query::create_from_template::Request::new(template_id.into(), overrides);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            let http_serde::query::create::ResponseBody { query_id } =
+                serde_json::from_slice(&bytes)?;
+            Ok(query_id)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Intended to be called by helper administrators. Lists or changes the query templates
+    /// stored on the helper, and returns the templates after the change.
+    /// # Errors
+    /// If `admin_token` is not accepted by the helper, the command refers to a template that does
+    /// not exist, or the request fails to deliver to helper
+    pub async fn query_templates(
+        &self,
+        admin_token: &str,
+        command: TemplateCommand,
+    ) -> Result<TemplateList, Error> {
+        let req = http_serde::templates::Request::new(admin_token.into(), command);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            Ok(serde_json::from_slice(&bytes)?)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Intended to be called externally, by the report collector. Asks the helper whether it
+    /// would accept a query with the given configuration, without creating one. Problems with
+    /// the configuration are returned in the report rather than as an error.
+    /// # Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    pub async fn validate_query(&self, data: QueryConfig) -> Result<ValidationReport, Error> {
+        let req = http_serde::query::validate::Request::new(data);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            Ok(serde_json::from_slice(&bytes)?)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
     /// Intended to be called externally, e.g. by the report collector. After the report collector
     /// calls "create query", it must then send the data for the query to each of the clients. This
     /// query input contains the data intended for a helper.
@@ -467,6 +682,59 @@ impl IpaHttpClient<Helper> {
         resp_ok(resp).await
     }
 
+    /// Intended to be called externally, by the report collector. Sends one batch of the input of
+    /// a query that keeps receiving reports after its upload started, holding `reports` reports.
+    /// The query does not start until [`Self::seal_query_input`] is called.
+    /// # Errors
+    /// If the query does not accept batches, or the request fails to deliver to helper
+    pub async fn append_query_input(
+        &self,
+        query_id: QueryId,
+        reports: u32,
+        input_stream: BodyStream,
+    ) -> Result<(), Error> {
+        let req = http_serde::query::input::Request::appending(
+            QueryInput {
+                query_id,
+                input_stream,
+                contribution: None,
+            },
+            reports,
+        );
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        resp_ok(resp).await
+    }
+
+    /// Intended to be called externally, by the report collector. Tells the helper that all
+    /// batches of the query input were sent and how many reports they hold, so it can start the
+    /// query.
+    /// # Errors
+    /// If the query did not receive its input in batches, `count` does not match the reports
+    /// declared by the batches, or the request fails to deliver to helper
+    pub async fn seal_query_input(&self, query_id: QueryId, count: QuerySize) -> Result<(), Error> {
+        let req = http_serde::query::seal_input::Request::new(query_id, count);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        resp_ok(resp).await
+    }
+
+    /// Intended to be called externally, by the report collector. Asks the helper to delete
+    /// the data it keeps for the input of a query, once the query is done.
+    /// # Errors
+    /// If the query is still running, or the request fails to deliver to helper
+    pub async fn delete_query_input(&self, query_id: QueryId) -> Result<InputDeleted, Error> {
+        let req = http_serde::query::delete_input::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            Ok(serde_json::from_slice(&bytes)?)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
     /// Retrieve the status of a query.
     ///
     /// ## Errors
@@ -482,7 +750,7 @@ impl IpaHttpClient<Helper> {
         let resp = self.request(req).await?;
         if resp.status().is_success() {
             let bytes = response_to_bytes(resp).await?;
-            let http_serde::query::status::ResponseBody { status } =
+            let http_serde::query::status::ResponseBody { status, .. } =
                 serde_json::from_slice(&bytes)?;
             Ok(status)
         } else {
@@ -490,6 +758,103 @@ impl IpaHttpClient<Helper> {
         }
     }
 
+    /// Retrieve the noise added to the output of a completed query. Returns `None` if the query
+    /// has not completed, its results were already collected, or its output carries no noise.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
+    pub async fn query_noise(
+        &self,
+        query_id: QueryId,
+    ) -> Result<Option<crate::protocol::dp::NoiseReport>, Error> {
+        let req = http_serde::query::status::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            let http_serde::query::status::ResponseBody { noise, .. } =
+                serde_json::from_slice(&bytes)?;
+            Ok(noise)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Retrieve the helper that leads a query. Any helper can create queries, and the one that
+    /// does leads it. Returns `None` if the helpers have not agreed on roles yet, or the query
+    /// has completed.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
+    pub async fn query_leader(
+        &self,
+        query_id: QueryId,
+    ) -> Result<Option<crate::helpers::HelperIdentity>, Error> {
+        let req = http_serde::query::status::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            let http_serde::query::status::ResponseBody { leader, .. } =
+                serde_json::from_slice(&bytes)?;
+            Ok(leader)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Retrieve the estimated completion time of a running query. Returns `None` if the query is
+    /// not running, or the protocol has not completed its first stage yet.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
+    pub async fn query_eta(
+        &self,
+        query_id: QueryId,
+    ) -> Result<Option<crate::telemetry::progress::QueryEta>, Error> {
+        let req = http_serde::query::status::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            let http_serde::query::status::ResponseBody { eta, .. } =
+                serde_json::from_slice(&bytes)?;
+            Ok(eta)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Retrieve the tuning report of a completed query. Returns `None` if the query has not
+    /// completed or its results were already collected.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
+    pub async fn query_tuning_report(
+        &self,
+        query_id: QueryId,
+    ) -> Result<Option<crate::telemetry::tuning::TuningReport>, Error> {
+        let req = http_serde::query::status::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = response_to_bytes(resp).await?;
+            let http_serde::query::status::ResponseBody { tuning_report, .. } =
+                serde_json::from_slice(&bytes)?;
+            Ok(tuning_report)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
     /// Wait for completion of the query and pull the results of this query. This is a blocking
     /// API so it is not supposed to be used outside of CLI context.
     ///
@@ -559,16 +924,28 @@ pub(crate) mod tests {
 
     use super::*;
     use crate::{
+        config::{PeerTlsConfig, TlsVersion},
         ff::{FieldType, Fp31},
         helpers::{
-            make_owned_handler, query::QueryType::TestMultiply, BytesStream, HelperIdentity,
-            HelperResponse, RequestHandler, RoleAssignment, MESSAGE_PAYLOAD_SIZE_BYTES,
+            make_owned_handler,
+            query::{CreateFromTemplate, QueryType::TestMultiply},
+            routing::RouteId,
+            BytesStream, HelperIdentity, HelperResponse, RequestHandler, RoleAssignment,
+            StageConcurrency, StepTransfer, MESSAGE_PAYLOAD_SIZE_BYTES,
+        },
+        net::{
+            test::{TestServer, TEST_CERTS_DER},
+            CertificatePin, CertificatePins,
+        },
+        protocol::{
+            dp::{NoiseMechanism, NoiseReport},
+            step::TestExecutionStep,
         },
-        net::test::TestServer,
-        protocol::step::TestExecutionStep,
-        query::ProtocolResult,
+        query::{ProtocolResult, QueryStatus, QueryStatusReport},
         secret_sharing::replicated::semi_honest::AdditiveShare as Replicated,
         sync::Arc,
+        telemetry::{progress::QueryEta, tuning::TuningReport},
+        utils::NonZeroU32PowerOfTwo,
     };
 
     #[tokio::test]
@@ -582,7 +959,11 @@ pub(crate) mod tests {
                 .parse()
                 .unwrap(),
             certificate: None,
+            certificate_pins: None,
             hpke_config: None,
+            signing_key: None,
+            step_transfer: StepTransfer::Push,
+            tls: PeerTlsConfig::default(),
         };
         let client = IpaHttpClient::new(
             IpaRuntime::current(),
@@ -597,6 +978,81 @@ pub(crate) mod tests {
         assert!(matches!(res, Err(Error::ConnectError { inner: e, .. }) if e.is_connect()));
     }
 
+    #[tokio::test]
+    async fn pinned_certificate() {
+        const ECHO_DATA: &str = "asdf";
+
+        let TestServer { addr, .. } = TestServer::default().await;
+
+        // The server presents the first test certificate, which is trusted in both cases.
+        let client = |pinned: usize| {
+            let peer_config = PeerConfig {
+                url: format!("https://localhost:{}", addr.port())
+                    .parse()
+                    .unwrap(),
+                certificate: Some(TEST_CERTS_DER[0].clone()),
+                certificate_pins: Some(CertificatePins {
+                    current: CertificatePin::of(&TEST_CERTS_DER[pinned]),
+                    previous: None,
+                }),
+                hpke_config: None,
+                signing_key: None,
+                step_transfer: StepTransfer::Push,
+                tls: PeerTlsConfig::default(),
+            };
+            IpaHttpClient::new(
+                IpaRuntime::current(),
+                &ClientConfig::default(),
+                peer_config,
+                ClientIdentity::<Helper>::None,
+            )
+        };
+
+        assert_eq!(ECHO_DATA, client(0).echo(ECHO_DATA).await.unwrap());
+        let res = client(1).echo(ECHO_DATA).await;
+        assert!(matches!(res, Err(Error::ConnectError { inner: e, .. }) if e.is_connect()));
+    }
+
+    #[tokio::test]
+    async fn peer_tls_config() {
+        const ECHO_DATA: &str = "asdf";
+
+        let TestServer { addr, .. } = TestServer::default().await;
+
+        // The server's certificate is only valid for `localhost`, so connecting to it by IP
+        // address requires the server name to be set.
+        let client = |server_name: Option<&str>| {
+            let peer_config = PeerConfig {
+                url: format!("https://127.0.0.1:{}", addr.port())
+                    .parse()
+                    .unwrap(),
+                certificate: None,
+                certificate_pins: None,
+                hpke_config: None,
+                signing_key: None,
+                step_transfer: StepTransfer::Push,
+                tls: PeerTlsConfig {
+                    ca_bundle: Some(vec![TEST_CERTS_DER[1].clone(), TEST_CERTS_DER[0].clone()]),
+                    server_name: server_name.map(|name| name.to_string().try_into().unwrap()),
+                    min_version: TlsVersion::Tls13,
+                },
+            };
+            IpaHttpClient::new(
+                IpaRuntime::current(),
+                &ClientConfig::default(),
+                peer_config,
+                ClientIdentity::<Helper>::None,
+            )
+        };
+
+        assert_eq!(
+            ECHO_DATA,
+            client(Some("localhost")).echo(ECHO_DATA).await.unwrap()
+        );
+        let res = client(None).echo(ECHO_DATA).await;
+        assert!(matches!(res, Err(Error::ConnectError { inner: e, .. }) if e.is_connect()));
+    }
+
     /// tests that a query command runs as expected. Since query commands require the server to
     /// actively respond to a client request, the test must handle both ends of the request
     /// simultaneously. That means taking the client behavior (`clientf`) and the server behavior
@@ -670,6 +1126,9 @@ pub(crate) mod tests {
                     query_id: expected_query_id,
                     config: query_config,
                     roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    protocol_version: ProtocolVersion::CURRENT,
+                    pull_receivers: Vec::new(),
+                    stage_concurrency: StageConcurrency::default(),
                 }))
             })
         };
@@ -681,15 +1140,246 @@ pub(crate) mod tests {
         assert_eq!(query_id, expected_query_id);
     }
 
+    #[tokio::test]
+    async fn create_from_template() {
+        let expected_query_id = QueryId;
+        let overrides = TemplateOverrides::new(1.try_into().unwrap());
+
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::ReceiveQueryFromTemplate = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                let req = addr.into::<CreateFromTemplate>().unwrap();
+                assert_eq!("multiply", req.template_id);
+                assert_eq!(overrides, req.overrides);
+
+                Ok(HelperResponse::from(PrepareQuery {
+                    query_id: expected_query_id,
+                    config: QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+                    roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    protocol_version: ProtocolVersion::CURRENT,
+                    pull_receivers: Vec::new(),
+                    stage_concurrency: StageConcurrency::default(),
+                }))
+            })
+        };
+        let query_id = test_query_command(
+            |client| async move {
+                client
+                    .create_query_from_template("multiply", overrides)
+                    .await
+                    .unwrap()
+            },
+            handler,
+        )
+        .await;
+        assert_eq!(query_id, expected_query_id);
+    }
+
+    #[tokio::test]
+    async fn validate() {
+        let expected_query_config = QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap();
+
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::ValidateQuery = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                let query_config = addr.into::<QueryConfig>().unwrap();
+                assert_eq!(query_config, expected_query_config);
+
+                Ok(HelperResponse::from(ValidationReport::default()))
+            })
+        };
+        let report = test_query_command(
+            |client| async move { client.validate_query(expected_query_config).await.unwrap() },
+            handler,
+        )
+        .await;
+        assert!(report.is_valid());
+    }
+
+    #[tokio::test]
+    async fn delete_input() {
+        let expected = InputDeleted {
+            query_id: QueryId,
+            stored: true,
+            quarantine: false,
+            prf_cache: true,
+        };
+
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::DeleteQueryInput = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(InputDeleted {
+                    query_id: QueryId,
+                    stored: true,
+                    quarantine: false,
+                    prf_cache: true,
+                }))
+            })
+        };
+        let deleted = test_query_command(
+            |client| async move { client.delete_query_input(QueryId).await.unwrap() },
+            handler,
+        )
+        .await;
+        assert_eq!(expected, deleted);
+    }
+
+    #[tokio::test]
+    async fn query_noise() {
+        fn noise() -> NoiseReport {
+            NoiseReport {
+                mechanism: NoiseMechanism::Binomial {
+                    num_bernoulli: 1000,
+                    success_prob: 0.5,
+                },
+                epsilon: 1.0,
+                delta: 1e-6,
+                sensitivity: 8,
+                histograms: 1,
+                buckets: 16,
+                mean: 500.0,
+                std_dev: 15.8,
+            }
+        }
+
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::QueryStatus = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(QueryStatusReport {
+                    noise: Some(noise()),
+                    ..QueryStatusReport::new(QueryStatus::Completed)
+                }))
+            })
+        };
+        // `NoiseReport` is not `Eq`, so the comparison happens on the client side.
+        let matches = test_query_command(
+            |client| async move { client.query_noise(QueryId).await.unwrap() == Some(noise()) },
+            handler,
+        )
+        .await;
+        assert!(matches);
+    }
+
+    #[tokio::test]
+    async fn query_tuning_report() {
+        fn report() -> TuningReport {
+            TuningReport {
+                duration_ms: 1500,
+                active_work: 32,
+                read_size: 2048,
+                ..TuningReport::default()
+            }
+        }
+
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::QueryStatus = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(QueryStatusReport {
+                    tuning_report: Some(report()),
+                    ..QueryStatusReport::new(QueryStatus::Completed)
+                }))
+            })
+        };
+        // `TuningReport` is not `Eq`, so the comparison happens on the client side.
+        let matches =
+            test_query_command(
+                |client| async move {
+                    client.query_tuning_report(QueryId).await.unwrap() == Some(report())
+                },
+                handler,
+            )
+            .await;
+        assert!(matches);
+    }
+
+    #[tokio::test]
+    async fn query_leader() {
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::QueryStatus = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(QueryStatusReport {
+                    leader: Some(HelperIdentity::TWO),
+                    ..QueryStatusReport::new(QueryStatus::Running)
+                }))
+            })
+        };
+        let leader = test_query_command(
+            |client| async move { client.query_leader(QueryId).await.unwrap() },
+            handler,
+        )
+        .await;
+        assert_eq!(Some(HelperIdentity::TWO), leader);
+    }
+
+    #[tokio::test]
+    async fn query_eta() {
+        fn eta() -> QueryEta {
+            QueryEta {
+                elapsed_ms: 4000,
+                remaining_ms: 12_000,
+                completed_stages: 2,
+                planned_stages: 5,
+            }
+        }
+
+        let handler = || {
+            make_owned_handler(move |addr, _| async move {
+                let RouteId::QueryStatus = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(QueryStatusReport {
+                    leader: Some(HelperIdentity::ONE),
+                    eta: Some(eta()),
+                    ..QueryStatusReport::new(QueryStatus::Running)
+                }))
+            })
+        };
+        let received = test_query_command(
+            |client| async move { client.query_eta(QueryId).await.unwrap() },
+            handler,
+        )
+        .await;
+        assert_eq!(Some(eta()), received);
+    }
+
     #[tokio::test]
     async fn prepare() {
         let config = QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap();
+        let stage_concurrency = StageConcurrency {
+            aggregation: Some(NonZeroU32PowerOfTwo::try_from(4).unwrap()),
+            ..StageConcurrency::default()
+        };
         let handler = move || {
             make_owned_handler(move |addr, _| async move {
                 let input = PrepareQuery {
                     query_id: QueryId,
                     config,
                     roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    protocol_version: ProtocolVersion::CURRENT,
+                    pull_receivers: Vec::new(),
+                    stage_concurrency,
                 };
                 let prepare_query = addr.into::<PrepareQuery>().unwrap();
                 assert_eq!(prepare_query, input);
@@ -704,6 +1394,9 @@ pub(crate) mod tests {
                     query_id: QueryId,
                     config,
                     roles: RoleAssignment::new(HelperIdentity::make_three()),
+                    protocol_version: ProtocolVersion::CURRENT,
+                    pull_receivers: Vec::new(),
+                    stage_concurrency,
                 };
                 async move { client.prepare_query(req).await.unwrap() }
             },
@@ -729,6 +1422,7 @@ pub(crate) mod tests {
                 let data = QueryInput {
                     query_id: expected_query_id,
                     input_stream: expected_input.to_vec().into(),
+                    contribution: None,
                 };
                 client.query_input(data).await.unwrap();
             },
@@ -749,6 +1443,7 @@ pub(crate) mod tests {
         let resp = client
             .step(
                 expected_query_id,
+                ProtocolVersion::CURRENT,
                 &expected_step,
                 once(ready(expected_payload.clone())),
             )
@@ -768,6 +1463,38 @@ pub(crate) mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn pull_step() {
+        let TestServer {
+            client, transport, ..
+        } = TestServer::builder().build().await;
+        let step = Gate::default().narrow(&TestExecutionStep::Iter(0));
+        let payload = vec![7u8; MESSAGE_PAYLOAD_SIZE_BYTES];
+        let end = u64::try_from(payload.len()).unwrap();
+
+        let serve = transport.pull_sources.serve(
+            (QueryId, HelperIdentity::ONE, step.clone()),
+            once(ready(payload.clone())),
+        );
+        let pull = async {
+            let chunk = client
+                .pull_step(QueryId, ProtocolVersion::CURRENT, &step, 0)
+                .await
+                .unwrap();
+            assert_eq!(Some(&payload[..]), chunk.as_deref());
+            assert!(client
+                .pull_step(QueryId, ProtocolVersion::CURRENT, &step, end + 1)
+                .await
+                .is_err());
+            let chunk = client
+                .pull_step(QueryId, ProtocolVersion::CURRENT, &step, end)
+                .await
+                .unwrap();
+            assert_eq!(None, chunk);
+        };
+        futures::join!(serve, pull);
+    }
+
     #[tokio::test]
     async fn results() {
         let expected_results = [
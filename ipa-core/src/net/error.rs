@@ -4,7 +4,11 @@ use axum::{
 };
 
 use crate::{
-    error::BoxError, net::client::ResponseFromEndpoint, protocol::QueryId, query::QueryStatus,
+    error::BoxError,
+    helpers::frame::ChecksumMismatch,
+    net::{client::ResponseFromEndpoint, pull::PullError},
+    protocol::{ProtocolVersion, QueryId},
+    query::QueryStatus,
     sharding::ShardIndex,
 };
 
@@ -16,6 +20,16 @@ pub enum Error {
     MissingHeader(String),
     #[error("invalid header: {0}")]
     InvalidHeader(BoxError),
+    #[error("request signature does not match")]
+    InvalidSignature,
+    #[error("request was signed at {signed_at}, more than {max_age}s away from now ({now})")]
+    SignatureExpired {
+        signed_at: u64,
+        now: u64,
+        max_age: u64,
+    },
+    #[error("request with the same signature nonce was received before")]
+    ReplayedRequest,
     #[error(
         "Request body length {body_len} is not aligned with size of the element {element_size}"
     )]
@@ -30,6 +44,24 @@ pub enum Error {
     MissingExtension(#[from] axum::extract::rejection::ExtensionRejection),
     #[error("query id not found: {}", .0.as_ref())]
     QueryIdNotFound(QueryId),
+    #[error("protocol version {0} is not supported")]
+    UnsupportedProtocolVersion(ProtocolVersion),
+    #[error("query {query_id} runs protocol {expected}, but received records for {actual}")]
+    ProtocolVersionMismatch {
+        query_id: QueryId,
+        expected: ProtocolVersion,
+        actual: ProtocolVersion,
+    },
+    #[error("query {0} receives records in pull mode, but they were pushed")]
+    UnexpectedPush(QueryId),
+    #[error(transparent)]
+    Pull(#[from] PullError),
+    #[error(transparent)]
+    ChecksumMismatch(#[from] ChecksumMismatch),
+    #[error("request target is {len} bytes long, the server accepts at most {limit}")]
+    UriTooLong { len: usize, limit: usize },
+    #[error("request headers take {size} bytes, the server accepts at most {limit}")]
+    HeadersTooLarge { size: usize, limit: usize },
     #[error(transparent)]
     HyperPassthrough(#[from] hyper::Error),
     #[error(transparent)]
@@ -168,14 +200,28 @@ impl IntoResponse for Error {
             | Self::InvalidJsonBody(_)
             | Self::InvalidBytesBody(_)
             | Self::QueryIdNotFound(_)
+            | Self::UnsupportedProtocolVersion(_)
+            | Self::ChecksumMismatch(_)
             | Self::ConnectError { .. } => StatusCode::BAD_REQUEST,
 
+            Self::ProtocolVersionMismatch { .. } | Self::UnexpectedPush(_) => StatusCode::CONFLICT,
+
+            Self::Pull(PullError::OffsetOutOfRange { .. }) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::Pull(PullError::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+
+            Self::UriTooLong { .. } => StatusCode::URI_TOO_LONG,
+            Self::HeadersTooLarge { .. } => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+
             Self::HyperPassthrough { .. }
             | Self::HyperHttpPassthrough(_)
             | Self::FailedHttpRequest { .. }
             | Self::InvalidUri(_)
             | Self::MissingExtension(_) => StatusCode::INTERNAL_SERVER_ERROR,
 
+            Self::InvalidSignature | Self::SignatureExpired { .. } | Self::ReplayedRequest => {
+                StatusCode::UNAUTHORIZED
+            }
+
             Self::Application { code, .. } => code,
             Self::ShardQueryStatusMismatch { error } => {
                 return (
@@ -78,25 +78,212 @@ pub mod metrics {
     pub const AXUM_PATH: &str = "/metrics";
 }
 
+/// Status of the certificate pins of the peers of a helper. Only helper administrators can call
+/// this API. They authenticate with a bearer token.
+///
+/// The response body is a JSON list of [`crate::net::PeerPinStatus`].
+pub mod pins {
+    pub const AXUM_PATH: &str = "/pins";
+}
+
+/// Probes for orchestrators. `/healthz` answers as long as the process serves requests.
+/// `/readyz` answers with HTTP 200 only if the helper is ready to run queries, and with HTTP 503
+/// otherwise. Its body is a [`crate::query::Readiness`] either way.
+pub mod health {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Health {
+        pub status: String,
+    }
+
+    impl Health {
+        #[must_use]
+        pub fn ok() -> Self {
+            Self {
+                status: "ok".to_string(),
+            }
+        }
+    }
+
+    pub const HEALTH_AXUM_PATH: &str = "/healthz";
+    pub const READY_AXUM_PATH: &str = "/readyz";
+}
+
+/// Listing of the queries a helper keeps track of. Only helper administrators can call this API.
+/// They authenticate with a bearer token.
+pub mod queries {
+    use axum::body::Body;
+    use hyper::{header::AUTHORIZATION, http::uri};
+
+    use crate::{helpers::HelperResponse, query::ListQueries};
+
+    #[derive(Debug, Clone)]
+    pub struct Request {
+        pub token: String,
+        pub params: ListQueries,
+    }
+
+    impl Request {
+        pub fn new(token: String, params: ListQueries) -> Self {
+            Self { token, params }
+        }
+
+        pub fn try_into_http_request(
+            self,
+            scheme: uri::Scheme,
+            authority: uri::Authority,
+        ) -> crate::net::http_serde::OutgoingRequest {
+            let ListQueries {
+                state,
+                after,
+                limit,
+            } = self.params;
+            let query = state
+                .map(|state| format!("state={state}"))
+                .into_iter()
+                .chain(after.map(|after| format!("after={after}")))
+                .chain(limit.map(|limit| format!("limit={limit}")))
+                .collect::<Vec<_>>();
+            let path = if query.is_empty() {
+                AXUM_PATH.to_string()
+            } else {
+                format!("{AXUM_PATH}?{}", query.join("&"))
+            };
+            let uri = uri::Uri::builder()
+                .scheme(scheme)
+                .authority(authority)
+                .path_and_query(path)
+                .build()?;
+            Ok(hyper::Request::get(uri)
+                .header(AUTHORIZATION, format!("Bearer {}", self.token))
+                .body(Body::empty())?)
+        }
+    }
+
+    /// A page of queries, see [`crate::query::QueryList::next`] to get the next one.
+    pub type ResponseBody = crate::query::QueryList;
+
+    impl From<HelperResponse> for ResponseBody {
+        fn from(value: HelperResponse) -> Self {
+            serde_json::from_slice(value.into_body().as_slice()).unwrap()
+        }
+    }
+
+    pub const AXUM_PATH: &str = "/queries";
+}
+
+/// Management of the query templates stored on a helper. Only helper administrators can call
+/// these APIs. They authenticate with a bearer token.
+pub mod templates {
+    use axum::body::Body;
+    use hyper::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        http::uri,
+    };
+
+    use crate::{helpers::query::TemplateCommand, net::APPLICATION_JSON};
+
+    #[derive(Debug, Clone)]
+    pub struct Request {
+        pub token: String,
+        pub command: TemplateCommand,
+    }
+
+    impl Request {
+        pub fn new(token: String, command: TemplateCommand) -> Self {
+            Self { token, command }
+        }
+
+        pub fn try_into_http_request(
+            self,
+            scheme: uri::Scheme,
+            authority: uri::Authority,
+        ) -> crate::net::http_serde::OutgoingRequest {
+            let path = match &self.command {
+                TemplateCommand::List => AXUM_PATH.to_string(),
+                TemplateCommand::Put { id, .. } | TemplateCommand::Remove { id } => {
+                    format!("{AXUM_PATH}/{id}")
+                }
+            };
+            let uri = uri::Uri::builder()
+                .scheme(scheme)
+                .authority(authority)
+                .path_and_query(path)
+                .build()?;
+            let auth = format!("Bearer {}", self.token);
+            Ok(match self.command {
+                TemplateCommand::List => hyper::Request::get(uri)
+                    .header(AUTHORIZATION, auth)
+                    .body(Body::empty())?,
+                TemplateCommand::Put { template, .. } => hyper::Request::put(uri)
+                    .header(AUTHORIZATION, auth)
+                    .header(CONTENT_TYPE, APPLICATION_JSON)
+                    .body(Body::from(serde_json::to_string(&template)?))?,
+                TemplateCommand::Remove { .. } => hyper::Request::delete(uri)
+                    .header(AUTHORIZATION, auth)
+                    .body(Body::empty())?,
+            })
+        }
+    }
+
+    /// The response body of all template APIs is the resulting
+    /// [`crate::helpers::query::TemplateList`].
+    pub const AXUM_PATH: &str = "/templates";
+    pub const ITEM_AXUM_PATH: &str = "/templates/:template_id";
+}
+
 pub mod query {
     use std::fmt::{Display, Formatter};
 
     use async_trait::async_trait;
-    use axum::{
-        extract::{FromRequestParts, Query},
-        http::request::Parts,
-        RequestPartsExt,
-    };
-    use serde::Deserialize;
+    use axum::{extract::FromRequestParts, http::request::Parts};
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
     use crate::{
         ff::FieldType,
-        helpers::query::{QueryConfig, QuerySize, QueryType},
+        helpers::query::{QueryConfig, QueryPolicy, QuerySize, QueryType},
         net::Error,
+        serde::fields,
     };
 
+    /// Parameters every query has. The parameters of its query type follow them in the same
+    /// query string.
+    #[derive(Serialize, Deserialize)]
+    struct QueryTypeParam {
+        size: QuerySize,
+        field_type: FieldType,
+        query_type: String,
+    }
+
+    /// Deserializes `T` from the query string, rejecting parameters that neither `T` nor
+    /// [`QueryTypeParam`] have, so that a misspelled parameter fails the request instead of
+    /// silently taking its default value.
+    fn from_query<T: DeserializeOwned>(query: &str) -> Result<T, Error> {
+        let known = [fields::of::<QueryTypeParam>(), fields::of::<T>()];
+        let params: Vec<(String, String)> =
+            serde_urlencoded::from_str(query).map_err(|e| Error::BadQueryString(e.into()))?;
+        if let Some((key, value)) = params
+            .iter()
+            .find(|(key, _)| !known.iter().any(|fields| fields.contains(&key.as_str())))
+        {
+            return Err(Error::bad_query_value(key, value));
+        }
+        serde_urlencoded::from_str(query).map_err(|e| Error::BadQueryString(e.into()))
+    }
+
+    /// Query types without parameters of their own.
+    #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+    #[derive(Deserialize)]
+    struct NoParams {}
+
     /// wrapper around [`QueryConfig`] to enable extraction from an `Axum` request. To be used with
     /// the `create` and `prepare` commands
+    ///
+    /// The query string holds the parameters of [`QueryTypeParam`] and those of the query type,
+    /// as [`serde`] serializes them, and is written by the [`Display`] implementation of this
+    /// type. Parameters left out of it take the serde default of their field, parameters the
+    /// query type does not have are rejected.
     pub struct QueryConfigQueryParams(pub QueryConfig);
 
     impl std::ops::Deref for QueryConfigQueryParams {
@@ -115,36 +302,41 @@ pub mod query {
         type Rejection = Error;
 
         async fn from_request_parts(req: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-            #[derive(Deserialize)]
-            struct QueryTypeParam {
-                size: QuerySize,
-                field_type: FieldType,
-                query_type: String,
-            }
-            let Query(QueryTypeParam {
+            let query = req.uri.query().unwrap_or_default();
+            let QueryTypeParam {
                 size,
                 field_type,
                 query_type,
-            }) = req.extract().await?;
+            } = serde_urlencoded::from_str(query).map_err(|e| Error::BadQueryString(e.into()))?;
 
             let query_type = match query_type.as_str() {
                 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-                QueryType::TEST_MULTIPLY_STR => Ok(QueryType::TestMultiply),
+                QueryType::TEST_MULTIPLY_STR => {
+                    from_query::<NoParams>(query)?;
+                    Ok(QueryType::TestMultiply)
+                }
+                #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+                QueryType::TEST_ADD_STR => {
+                    from_query::<NoParams>(query)?;
+                    Ok(QueryType::TestAddInPrimeField)
+                }
                 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-                QueryType::TEST_ADD_STR => Ok(QueryType::TestAddInPrimeField),
+                QueryType::TEST_SHARDED_SHUFFLE_STR => {
+                    from_query::<NoParams>(query)?;
+                    Ok(QueryType::TestShardedShuffle)
+                }
                 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-                QueryType::TEST_SHARDED_SHUFFLE_STR => Ok(QueryType::TestShardedShuffle),
+                QueryType::TEST_SHARE_CONVERSION_STR => {
+                    Ok(QueryType::TestShareConversion(from_query(query)?))
+                }
                 QueryType::SEMI_HONEST_OPRF_IPA_STR => {
-                    let Query(q) = req.extract().await?;
-                    Ok(QueryType::SemiHonestOprfIpa(q))
+                    Ok(QueryType::SemiHonestOprfIpa(from_query(query)?))
                 }
                 QueryType::MALICIOUS_OPRF_IPA_STR => {
-                    let Query(q) = req.extract().await?;
-                    Ok(QueryType::MaliciousOprfIpa(q))
+                    Ok(QueryType::MaliciousOprfIpa(from_query(query)?))
                 }
                 QueryType::MALICIOUS_HYBRID_STR => {
-                    let Query(q) = req.extract().await?;
-                    Ok(QueryType::MaliciousHybrid(q))
+                    Ok(QueryType::MaliciousHybrid(from_query(query)?))
                 }
                 other => Err(Error::bad_query_value("query_type", other)),
             }?;
@@ -158,51 +350,62 @@ pub mod query {
 
     impl Display for QueryConfigQueryParams {
         fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            write!(
+            fn write_params<T: Serialize>(f: &mut Formatter<'_>, params: &T) -> std::fmt::Result {
+                let params = serde_urlencoded::to_string(params).map_err(|_| std::fmt::Error)?;
+                f.write_str(&params)
+            }
+
+            write_params(
                 f,
-                "query_type={qt}&field_type={f:?}&size={size}",
-                qt = self.query_type.as_ref(),
-                f = self.field_type,
-                size = self.size
+                &QueryTypeParam {
+                    size: self.size,
+                    field_type: self.field_type,
+                    query_type: self.query_type.as_ref().to_string(),
+                },
             )?;
-            match self.query_type {
+            match &self.query_type {
                 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
-                QueryType::TestMultiply | QueryType::TestAddInPrimeField => Ok(()),
+                QueryType::TestMultiply
+                | QueryType::TestAddInPrimeField
+                | QueryType::TestShardedShuffle => Ok(()),
                 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
-                QueryType::TestShardedShuffle => Ok(()),
+                QueryType::TestShareConversion(params) => {
+                    f.write_str("&")?;
+                    write_params(f, params)
+                }
                 QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config) => {
-                    write!(
-                        f,
-                        "&per_user_credit_cap={}&max_breakdown_key={}&with_dp={}&epsilon={}",
-                        config.per_user_credit_cap,
-                        config.max_breakdown_key,
-                        config.with_dp,
-                        config.epsilon,
-                    )?;
-
-                    if config.plaintext_match_keys {
-                        write!(f, "&plaintext_match_keys=true")?;
-                    }
+                    f.write_str("&")?;
+                    write_params(f, config)
+                }
+                QueryType::MaliciousHybrid(params) => {
+                    f.write_str("&")?;
+                    write_params(f, params)
+                }
+            }
+        }
+    }
 
-                    if let Some(window) = config.attribution_window_seconds {
-                        write!(f, "&attribution_window_seconds={}", window.get())?;
-                    }
+    /// Same as [`QueryConfigQueryParams`], but also rejects configs the protocols can't run, see
+    /// [`QueryConfig::validate`]. The limits of the helper policy are checked when the query is
+    /// created. To be used by the `create` and `prepare` commands, while `validate` reports the
+    /// problems of a config instead of rejecting it.
+    pub struct ValidQueryConfigQueryParams(pub QueryConfig);
 
-                    Ok(())
-                }
-                QueryType::MaliciousHybrid(config) => {
-                    write!(
-                        f,
-                        "&max_breakdown_key={}&with_dp={}&epsilon={}",
-                        config.max_breakdown_key, config.with_dp, config.epsilon,
-                    )?;
-
-                    if config.plaintext_match_keys {
-                        write!(f, "&plaintext_match_keys=true")?;
-                    }
+    #[async_trait]
+    impl<S> FromRequestParts<S> for ValidQueryConfigQueryParams
+    where
+        S: Send + Sync,
+    {
+        type Rejection = Error;
 
-                    Ok(())
-                }
+        async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+            let QueryConfigQueryParams(config) =
+                QueryConfigQueryParams::from_request_parts(req, state).await?;
+            let report = config.validate(&QueryPolicy::default());
+            if report.is_valid() {
+                Ok(Self(config))
+            } else {
+                Err(Error::BadQueryString(report.to_string().into()))
             }
         }
     }
@@ -265,17 +468,109 @@ pub mod query {
         pub const AXUM_PATH: &str = "/";
     }
 
+    pub mod validate {
+        use axum::body::Body;
+        use hyper::http::uri;
+
+        use crate::{
+            helpers::query::QueryConfig,
+            net::http_serde::query::{QueryConfigQueryParams, BASE_AXUM_PATH},
+        };
+
+        #[derive(Debug, Clone)]
+        pub struct Request {
+            pub query_config: QueryConfig,
+        }
+
+        impl Request {
+            pub fn new(query_config: QueryConfig) -> Request {
+                Request { query_config }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = uri::Builder::new()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!(
+                        "{}/validate?{}",
+                        BASE_AXUM_PATH,
+                        QueryConfigQueryParams(self.query_config)
+                    ))
+                    .build()?;
+                Ok(hyper::Request::post(uri).body(Body::empty())?)
+            }
+        }
+
+        /// The response body is a [`crate::helpers::query::ValidationReport`].
+        pub const AXUM_PATH: &str = "/validate";
+    }
+
+    pub mod create_from_template {
+        use std::fmt::Write;
+
+        use axum::body::Body;
+        use hyper::http::uri;
+
+        use crate::{helpers::query::TemplateOverrides, net::http_serde::query::BASE_AXUM_PATH};
+
+        #[derive(Debug, Clone)]
+        pub struct Request {
+            pub template_id: String,
+            pub overrides: TemplateOverrides,
+        }
+
+        impl Request {
+            pub fn new(template_id: String, overrides: TemplateOverrides) -> Request {
+                Request {
+                    template_id,
+                    overrides,
+                }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let mut path = format!(
+                    "{}/template/{}?size={}",
+                    BASE_AXUM_PATH, self.template_id, self.overrides.size
+                );
+                if let Some(max_breakdown_key) = self.overrides.max_breakdown_key {
+                    write!(path, "&max_breakdown_key={max_breakdown_key}").unwrap();
+                }
+                if let Some(site) = self.overrides.site_domain_hash {
+                    write!(path, "&site_domain_hash={site}").unwrap();
+                }
+                let uri = uri::Builder::new()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(path)
+                    .build()?;
+                Ok(hyper::Request::post(uri).body(Body::empty())?)
+            }
+        }
+
+        /// The response body is the same as for [`super::create`].
+        pub const AXUM_PATH: &str = "/template/:template_id";
+    }
+
     pub mod prepare {
         use axum::{body::Body, http::uri};
         use hyper::header::CONTENT_TYPE;
         use serde::{Deserialize, Serialize};
 
         use crate::{
-            helpers::{query::PrepareQuery, RoleAssignment},
+            helpers::{query::PrepareQuery, HelperIdentity, RoleAssignment, StageConcurrency},
             net::{
                 http_serde::query::{QueryConfigQueryParams, BASE_AXUM_PATH},
                 APPLICATION_JSON,
             },
+            protocol::ProtocolVersion,
         };
 
         #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -298,12 +593,15 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}?{}",
                         BASE_AXUM_PATH,
-                        self.data.query_id.as_ref(),
+                        self.data.query_id,
                         QueryConfigQueryParams(self.data.config),
                     ))
                     .build()?;
                 let body = RequestBody {
                     roles: self.data.roles,
+                    protocol_version: self.data.protocol_version,
+                    pull_receivers: self.data.pull_receivers,
+                    stage_concurrency: self.data.stage_concurrency,
                 };
                 let body = serde_json::to_string(&body)?;
                 let body = Body::from(body);
@@ -316,28 +614,49 @@ pub mod query {
         #[derive(Serialize, Deserialize)]
         pub struct RequestBody {
             pub roles: RoleAssignment,
+            #[serde(default)]
+            pub protocol_version: ProtocolVersion,
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            pub pull_receivers: Vec<HelperIdentity>,
+            #[serde(default, skip_serializing_if = "StageConcurrency::is_unlimited")]
+            pub stage_concurrency: StageConcurrency,
         }
 
         pub const AXUM_PATH: &str = "/:query_id";
     }
 
     pub mod input {
+        use std::num::NonZeroU32;
+
         use axum::{body::Body, http::uri};
         use hyper::header::CONTENT_TYPE;
+        use serde::Deserialize;
 
         use crate::{
-            helpers::query::QueryInput,
-            net::{http_serde::query::BASE_AXUM_PATH, APPLICATION_OCTET_STREAM},
+            helpers::query::{AppendInput, InputContribution, QueryInput},
+            net::{http_serde::query::BASE_AXUM_PATH, Error, APPLICATION_OCTET_STREAM},
         };
 
         #[derive(Debug)]
         pub struct Request {
             pub query_input: QueryInput,
+            /// Set if the upload is one batch of an input that is sealed later.
+            pub append: Option<AppendInput>,
         }
 
         impl Request {
             pub fn new(query_input: QueryInput) -> Self {
-                Self { query_input }
+                Self {
+                    query_input,
+                    append: None,
+                }
+            }
+
+            pub fn appending(query_input: QueryInput, reports: u32) -> Self {
+                Self {
+                    query_input,
+                    append: Some(AppendInput { reports }),
+                }
             }
 
             pub fn try_into_http_request(
@@ -345,14 +664,23 @@ pub mod query {
                 scheme: uri::Scheme,
                 authority: uri::Authority,
             ) -> crate::net::http_serde::OutgoingRequest {
+                let mut path_and_query =
+                    format!("{}/{}/input", BASE_AXUM_PATH, self.query_input.query_id);
+                if let Some(contribution) = self.query_input.contribution {
+                    path_and_query.push_str(&format!(
+                        "?contributor={}&contributors={}&count={}",
+                        contribution.contributor, contribution.contributors, contribution.count,
+                    ));
+                    if let Some(seed) = contribution.shuffle_seed {
+                        path_and_query.push_str(&format!("&shuffle_seed={seed}"));
+                    }
+                } else if let Some(append) = self.append {
+                    path_and_query.push_str(&format!("?append=true&reports={}", append.reports));
+                }
                 let uri = uri::Uri::builder()
                     .scheme(scheme)
                     .authority(authority)
-                    .path_and_query(format!(
-                        "{}/{}/input",
-                        BASE_AXUM_PATH,
-                        self.query_input.query_id.as_ref(),
-                    ))
+                    .path_and_query(path_and_query)
                     .build()?;
                 let body = Body::from_stream(self.query_input.input_stream);
                 Ok(hyper::Request::post(uri)
@@ -361,30 +689,182 @@ pub mod query {
             }
         }
 
+        /// Query string of an input upload that is one slice of the query input. All parameters
+        /// are absent if the upload carries the whole input.
+        #[derive(Debug, Default, Deserialize)]
+        pub struct ContributionQueryParams {
+            contributor: Option<u32>,
+            contributors: Option<NonZeroU32>,
+            count: Option<u32>,
+            shuffle_seed: Option<u64>,
+        }
+
+        impl TryFrom<ContributionQueryParams> for Option<InputContribution> {
+            type Error = Error;
+
+            fn try_from(params: ContributionQueryParams) -> Result<Self, Self::Error> {
+                match params {
+                    ContributionQueryParams {
+                        contributor: Some(contributor),
+                        contributors: Some(contributors),
+                        count: Some(count),
+                        shuffle_seed,
+                    } => Ok(Some(InputContribution {
+                        contributor,
+                        contributors,
+                        count,
+                        shuffle_seed,
+                    })),
+                    ContributionQueryParams {
+                        contributor: None,
+                        contributors: None,
+                        count: None,
+                        shuffle_seed: None,
+                    } => Ok(None),
+                    _ => Err(Error::BadQueryString(
+                        "contributor, contributors and count must be set together".into(),
+                    )),
+                }
+            }
+        }
+
+        /// Query string of an input upload that adds a batch to an input that is sealed later.
+        /// The batch must declare the number of reports it holds.
+        #[derive(Debug, Default, Deserialize)]
+        pub struct AppendQueryParams {
+            #[serde(default)]
+            append: bool,
+            reports: Option<u32>,
+        }
+
+        impl TryFrom<AppendQueryParams> for Option<AppendInput> {
+            type Error = Error;
+
+            fn try_from(params: AppendQueryParams) -> Result<Self, Self::Error> {
+                match params {
+                    AppendQueryParams {
+                        append: true,
+                        reports: Some(reports),
+                    } => Ok(Some(AppendInput { reports })),
+                    AppendQueryParams {
+                        append: false,
+                        reports: None,
+                    } => Ok(None),
+                    _ => Err(Error::BadQueryString(
+                        "append and reports must be set together".into(),
+                    )),
+                }
+            }
+        }
+
         pub const AXUM_PATH: &str = "/:query_id/input";
     }
 
-    pub mod step {
+    pub mod seal_input {
         use axum::{body::Body, http::uri};
 
+        use crate::{
+            helpers::query::{QuerySize, SealInput},
+            net::http_serde::query::BASE_AXUM_PATH,
+            protocol::QueryId,
+        };
+
+        pub struct Request {
+            pub query_id: QueryId,
+            pub seal: SealInput,
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId, count: QuerySize) -> Self {
+                Self {
+                    query_id,
+                    seal: SealInput { count },
+                }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!(
+                        "{}/{}/input/seal?count={}",
+                        BASE_AXUM_PATH, self.query_id, self.seal.count
+                    ))
+                    .build()?;
+                Ok(hyper::Request::post(uri).body(Body::empty())?)
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/:query_id/input/seal";
+    }
+
+    pub mod step {
+        use axum::{
+            body::Body,
+            http::{header::HeaderName, request, uri},
+        };
+        use serde::Deserialize;
+
         use crate::{
             net::{http_serde::query::BASE_AXUM_PATH, Error},
-            protocol::{Gate, QueryId},
+            protocol::{Gate, ProtocolVersion, QueryId},
         };
 
+        /// Request header that names the step of the records a request carries, starting with
+        /// [`ProtocolVersion::V7`]. Names of deep steps are too long to be put in the request
+        /// path safely, proxies in front of helpers may refuse them.
+        pub static STEP_HEADER: HeaderName = HeaderName::from_static("x-ipa-step");
+
+        /// Path of the requests for the records of `gate`. It only ends with the step if the
+        /// protocol version does not name it in [`STEP_HEADER`].
+        fn path(query_id: QueryId, protocol_version: ProtocolVersion, gate: &Gate) -> String {
+            let path = format!(
+                "{BASE_AXUM_PATH}/{query_id}/step/{}",
+                protocol_version.namespace()
+            );
+            if protocol_version.has_step_header() {
+                path
+            } else {
+                format!("{path}/{}", gate.as_ref())
+            }
+        }
+
+        fn with_step_header(
+            builder: request::Builder,
+            protocol_version: ProtocolVersion,
+            gate: &Gate,
+        ) -> request::Builder {
+            if protocol_version.has_step_header() {
+                builder.header(STEP_HEADER.clone(), gate.as_ref())
+            } else {
+                builder
+            }
+        }
+
         // When this type is used on the client side, `B` is `hyper::Body`. When this type
         // is used on the server side, `B` can be any body type supported by axum.
         #[derive(Debug)]
         pub struct Request<B> {
             pub query_id: QueryId,
+            pub protocol_version: ProtocolVersion,
             pub gate: Gate,
             pub body: B,
         }
 
         impl<B> Request<B> {
-            pub fn new(query_id: QueryId, gate: Gate, body: B) -> Self {
+            pub fn new(
+                query_id: QueryId,
+                protocol_version: ProtocolVersion,
+                gate: Gate,
+                body: B,
+            ) -> Self {
                 Self {
                     query_id,
+                    protocol_version,
                     gate,
                     body,
                 }
@@ -393,6 +873,49 @@ pub mod query {
 
         /// Convert to hyper request. Used on client side.
         impl Request<Body> {
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> Result<hyper::Request<Body>, Error> {
+                let uri = uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(path(self.query_id, self.protocol_version, &self.gate))
+                    .build()?;
+                Ok(
+                    with_step_header(hyper::Request::post(uri), self.protocol_version, &self.gate)
+                        .body(self.body)?,
+                )
+            }
+        }
+
+        /// Asks the sending helper for the data of a step channel, starting at `from_offset`
+        /// bytes from the beginning of the channel. Used by helpers that receive step data in
+        /// pull mode.
+        #[derive(Debug)]
+        pub struct PullRequest {
+            pub query_id: QueryId,
+            pub protocol_version: ProtocolVersion,
+            pub gate: Gate,
+            pub from_offset: u64,
+        }
+
+        impl PullRequest {
+            pub fn new(
+                query_id: QueryId,
+                protocol_version: ProtocolVersion,
+                gate: Gate,
+                from_offset: u64,
+            ) -> Self {
+                Self {
+                    query_id,
+                    protocol_version,
+                    gate,
+                    from_offset,
+                }
+            }
+
             pub fn try_into_http_request(
                 self,
                 scheme: uri::Scheme,
@@ -402,26 +925,36 @@ pub mod query {
                     .scheme(scheme)
                     .authority(authority)
                     .path_and_query(format!(
-                        "{}/{}/step/{}",
-                        BASE_AXUM_PATH,
-                        self.query_id.as_ref(),
-                        self.gate.as_ref()
+                        "{}?from_offset={}",
+                        path(self.query_id, self.protocol_version, &self.gate),
+                        self.from_offset,
                     ))
                     .build()?;
-                Ok(hyper::Request::post(uri).body(self.body)?)
+                Ok(
+                    with_step_header(hyper::Request::get(uri), self.protocol_version, &self.gate)
+                        .body(Body::empty())?,
+                )
             }
         }
 
-        pub const AXUM_PATH: &str = "/:query_id/step/*step";
+        #[derive(Debug, Deserialize)]
+        pub struct PullQueryParams {
+            pub from_offset: u64,
+        }
+
+        /// Steps are rooted in the namespace of the protocol version the query runs with, i.e.
+        /// `ipa/v5/<gate>`.
+        pub const AXUM_PATH: &str = "/:query_id/step/ipa/:protocol_version/*step";
+
+        /// Path of the requests that name their step in [`STEP_HEADER`].
+        pub const HEADER_AXUM_PATH: &str = "/:query_id/step/ipa/:protocol_version";
     }
 
     pub mod status {
-        use serde::{Deserialize, Serialize};
-
         use crate::{
             helpers::{routing::RouteId, HelperResponse, NoStep, RouteParams},
             protocol::QueryId,
-            query::QueryStatus,
+            query::QueryStatusReport,
         };
 
         #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -467,17 +1000,15 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}",
                         crate::net::http_serde::query::BASE_AXUM_PATH,
-                        self.query_id.as_ref()
+                        self.query_id
                     ))
                     .build()?;
                 Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
             }
         }
 
-        #[derive(Clone, Debug, Serialize, Deserialize)]
-        pub struct ResponseBody {
-            pub status: QueryStatus,
-        }
+        /// Body of the response, see [`QueryStatusReport`].
+        pub type ResponseBody = QueryStatusReport;
 
         impl From<HelperResponse> for ResponseBody {
             fn from(value: HelperResponse) -> Self {
@@ -535,7 +1066,7 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}/complete",
                         crate::net::http_serde::query::BASE_AXUM_PATH,
-                        self.query_id.as_ref()
+                        self.query_id
                     ))
                     .build()?;
                 Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
@@ -601,7 +1132,7 @@ pub mod query {
                     .path_and_query(format!(
                         "{}/{}/kill",
                         crate::net::http_serde::query::BASE_AXUM_PATH,
-                        self.query_id.as_ref()
+                        self.query_id
                     ))
                     .build()?;
                 Ok(hyper::Request::post(uri).body(axum::body::Body::empty())?)
@@ -623,6 +1154,134 @@ pub mod query {
         pub const AXUM_PATH: &str = "/:query_id/kill";
     }
 
+    pub mod delete_input {
+        use axum::{body::Body, http::uri};
+
+        use crate::{
+            helpers::{routing::RouteId, HelperResponse, NoStep, RouteParams},
+            net::http_serde::query::BASE_AXUM_PATH,
+            protocol::QueryId,
+        };
+
+        pub struct Request {
+            pub query_id: QueryId,
+        }
+
+        impl RouteParams<RouteId, QueryId, NoStep> for Request {
+            type Params = String;
+
+            fn resource_identifier(&self) -> RouteId {
+                RouteId::DeleteQueryInput
+            }
+
+            fn query_id(&self) -> QueryId {
+                self.query_id
+            }
+
+            fn gate(&self) -> NoStep {
+                NoStep
+            }
+
+            fn extra(&self) -> Self::Params {
+                String::new()
+            }
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId) -> Self {
+                Self { query_id }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!("{}/{}/input", BASE_AXUM_PATH, self.query_id))
+                    .build()?;
+                Ok(hyper::Request::delete(uri).body(Body::empty())?)
+            }
+        }
+
+        /// What the helper deleted.
+        pub type ResponseBody = crate::query::InputDeleted;
+
+        impl From<HelperResponse> for ResponseBody {
+            fn from(value: HelperResponse) -> Self {
+                serde_json::from_slice(value.into_body().as_slice()).unwrap()
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/:query_id/input";
+    }
+
+    pub mod prune {
+        use axum::{body::Body, http::uri};
+
+        use crate::{
+            helpers::{routing::RouteId, HelperResponse, NoStep, RouteParams},
+            net::http_serde::query::BASE_AXUM_PATH,
+            protocol::QueryId,
+        };
+
+        pub struct Request {
+            pub query_id: QueryId,
+        }
+
+        impl RouteParams<RouteId, QueryId, NoStep> for Request {
+            type Params = String;
+
+            fn resource_identifier(&self) -> RouteId {
+                RouteId::PruneQuery
+            }
+
+            fn query_id(&self) -> QueryId {
+                self.query_id
+            }
+
+            fn gate(&self) -> NoStep {
+                NoStep
+            }
+
+            fn extra(&self) -> Self::Params {
+                String::new()
+            }
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId) -> Self {
+                Self { query_id }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: uri::Scheme,
+                authority: uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!("{}/{}", BASE_AXUM_PATH, self.query_id))
+                    .build()?;
+                Ok(hyper::Request::delete(uri).body(Body::empty())?)
+            }
+        }
+
+        /// What the helper removed.
+        pub type ResponseBody = crate::query::QueryPruned;
+
+        impl From<HelperResponse> for ResponseBody {
+            fn from(value: HelperResponse) -> Self {
+                serde_json::from_slice(value.into_body().as_slice()).unwrap()
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/:query_id";
+    }
+
     pub mod status_match {
         use serde::{Deserialize, Serialize};
 
@@ -657,7 +1316,7 @@ pub mod query {
                 .path_and_query(format!(
                     "{}/{}/status-match?{}",
                     crate::net::http_serde::query::BASE_AXUM_PATH,
-                    req.query_id.as_ref(),
+                    req.query_id,
                     StatusQueryString::from(req.status).url_encode(),
                 ))
                 .build()?;
@@ -666,4 +1325,35 @@ pub mod query {
 
         pub const AXUM_PATH: &str = "/:query_id/status-match";
     }
+
+    pub mod peer_unavailable {
+        use serde::{Deserialize, Serialize};
+
+        use crate::helpers::{query::PeerUnavailable, Role};
+
+        #[derive(Serialize, Deserialize)]
+        pub struct RoleQueryString {
+            pub role: Role,
+        }
+
+        pub fn try_into_http_request(
+            req: &PeerUnavailable,
+            scheme: axum::http::uri::Scheme,
+            authority: axum::http::uri::Authority,
+        ) -> crate::net::http_serde::OutgoingRequest {
+            let uri = axum::http::uri::Uri::builder()
+                .scheme(scheme)
+                .authority(authority)
+                .path_and_query(format!(
+                    "{}/{}/peer-unavailable?role={}",
+                    crate::net::http_serde::query::BASE_AXUM_PATH,
+                    req.query_id,
+                    req.role.as_static_str(),
+                ))
+                .build()?;
+            Ok(hyper::Request::post(uri).body(axum::body::Body::empty())?)
+        }
+
+        pub const AXUM_PATH: &str = "/:query_id/peer-unavailable";
+    }
 }
@@ -15,17 +15,33 @@ use crate::{
     sharding::ShardIndex,
 };
 
+mod checksum;
 mod client;
 mod error;
 mod http_serde;
+mod open_files;
+mod pinning;
+mod pull;
+mod request_limits;
 mod server;
+mod signing;
 #[cfg(all(test, not(feature = "shuttle")))]
 pub mod test;
 mod transport;
 
 pub use client::{ClientIdentity, IpaHttpClient};
 pub use error::{Error, ShardError};
+#[cfg(feature = "fuzzing")]
+pub(crate) use http_serde::query::QueryConfigQueryParams;
+pub use open_files::{
+    os_limit, raise_os_limit, OpenFile, OpenFiles, OsLimit, OsLimitError, ResourceLimitError,
+};
+pub use pinning::{CertificatePin, CertificatePins, PeerPinStatus, PinStatus, PreviousPin};
+pub use request_limits::{
+    header_list_size, RequestLimits, DEFAULT_MAX_HEADER_BYTES, DEFAULT_MAX_URI_LEN,
+};
 pub use server::{IpaHttpServer, TracingSpanMaker};
+pub use signing::PeerSigningKey;
 pub use transport::{HttpTransport, MpcHttpTransport, ShardHttpTransport};
 
 const APPLICATION_JSON: &str = "application/json";
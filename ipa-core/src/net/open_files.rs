@@ -0,0 +1,433 @@
+//! Awareness of the limit the OS puts on open file descriptors.
+//!
+//! Every connection to or from a peer helper, a shard or a report collector takes a file
+//! descriptor, and so do the files helpers write while running queries. Once the process runs
+//! out of them, accepting connections and opening files fail with errors that do not point at
+//! the cause, and peers see their connections reset.
+//!
+//! Helpers raise the soft limit to the hard one at startup and refuse to start if it is lower
+//! than the number of sockets they are configured to keep open. At runtime, [`OpenFiles`] keeps
+//! count of the connections the servers accept and the clients open. New connections past the
+//! limit wait for others to close. Servers close incoming connections that find no room in time,
+//! and clients fail to connect with a resource limit error.
+//!
+//! Helpers don't spill query data to disk. The only files they write are the quarantine and PRF
+//! cache files of a query, which are open only while they are written, one at a time per query.
+//! These are covered by [`RESERVED_FILES`] rather than counted.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use hyper::{
+    rt::{Read, ReadBufCursor, Write},
+    Uri,
+};
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use pin_project::pin_project;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tower::Service;
+
+use crate::error::BoxError;
+
+/// File descriptors left to the helper for files, the runtime and its logs, on top of the
+/// sockets counted by [`OpenFiles`].
+pub const RESERVED_FILES: u64 = 256;
+
+/// Number of sockets helpers keep open on platforms that don't report a limit.
+pub const DEFAULT_OPEN_FILES: usize = 1024;
+
+/// How long a new connection waits for another one to close once the limit is reached.
+pub const DEFAULT_ACQUIRE_WAIT: Duration = Duration::from_secs(5);
+
+/// Highest soft limit helpers raise to. Linux refuses limits above `fs.nr_open`, which defaults
+/// to this value, and macOS refuses limits above `OPEN_MAX`, even if the hard limit is higher.
+#[cfg(all(unix, not(target_os = "macos")))]
+const MAX_RAISED_LIMIT: u64 = 1 << 20;
+#[cfg(target_os = "macos")]
+const MAX_RAISED_LIMIT: u64 = 10240;
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("resource limit: {in_use} of {limit} sockets and files are open")]
+pub struct ResourceLimitError {
+    pub in_use: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OsLimitError {
+    #[error("failed to read or raise the limit on open files: {0}")]
+    Io(#[from] io::Error),
+    #[error(
+        "resource limit: the OS allows {available} open files, but the helper needs {required}. \
+        Raise it with `ulimit -n` or lower --max-open-files"
+    )]
+    TooLow { available: u64, required: u64 },
+}
+
+/// Soft and hard limit on open files of this process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OsLimit {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// Reads the limit on open files of this process. Returns `None` on platforms without one.
+///
+/// # Errors
+/// If the OS fails to report the limit.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // `rlim_t` is not `u64` on every platform
+pub fn os_limit() -> io::Result<Option<OsLimit>> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `getrlimit` only writes to the struct it is given.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Some(OsLimit {
+        soft: limit.rlim_cur as u64,
+        hard: limit.rlim_max as u64,
+    }))
+}
+
+/// Reads the limit on open files of this process. Returns `None` on platforms without one.
+///
+/// # Errors
+/// Never, on this platform.
+#[cfg(not(unix))]
+pub fn os_limit() -> io::Result<Option<OsLimit>> {
+    Ok(None)
+}
+
+/// Raises the soft limit on open files of this process to its hard limit, and returns the new
+/// soft limit. Returns `None` on platforms without one.
+///
+/// # Errors
+/// If the OS fails to report or change the limit.
+#[cfg(unix)]
+pub fn raise_os_limit() -> io::Result<Option<u64>> {
+    let Some(OsLimit { soft, hard }) = os_limit()? else {
+        return Ok(None);
+    };
+    let raised = hard.min(MAX_RAISED_LIMIT);
+    if soft >= raised {
+        return Ok(Some(soft));
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    let limit = libc::rlimit {
+        rlim_cur: raised as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    // SAFETY: `setrlimit` only reads the struct it is given.
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Some(raised))
+}
+
+/// Raises the soft limit on open files of this process to its hard limit, and returns the new
+/// soft limit. Returns `None` on platforms without one.
+///
+/// # Errors
+/// Never, on this platform.
+#[cfg(not(unix))]
+pub fn raise_os_limit() -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Counts the sockets the helper servers and clients keep open, up to a soft limit that stays
+/// below the one the OS enforces. Clones share the count.
+#[derive(Debug, Clone)]
+pub struct OpenFiles {
+    limit: usize,
+    wait: Duration,
+    permits: Arc<Semaphore>,
+}
+
+/// A socket counted by [`OpenFiles`], until it is dropped.
+#[derive(Debug)]
+pub struct OpenFile {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl OpenFiles {
+    #[must_use]
+    pub fn new(limit: usize) -> Self {
+        // Semaphores don't take more permits than this.
+        let limit = limit.min(Semaphore::MAX_PERMITS);
+        Self {
+            limit,
+            wait: DEFAULT_ACQUIRE_WAIT,
+            permits: Arc::new(Semaphore::new(limit)),
+        }
+    }
+
+    /// Raises the OS limit on open files and checks that it leaves room for `max_open_files`
+    /// sockets, along with [`RESERVED_FILES`]. Without `max_open_files`, the helper keeps as
+    /// many sockets open as the OS limit allows.
+    ///
+    /// # Errors
+    /// If the OS limit can't be read or raised, or is too low.
+    pub fn from_os_limit(max_open_files: Option<usize>) -> Result<Self, OsLimitError> {
+        let Some(available) = raise_os_limit()? else {
+            return Ok(Self::new(max_open_files.unwrap_or(DEFAULT_OPEN_FILES)));
+        };
+        let limit = match max_open_files {
+            Some(limit) => {
+                let required = u64::try_from(limit)
+                    .unwrap_or(u64::MAX)
+                    .saturating_add(RESERVED_FILES);
+                if available < required {
+                    return Err(OsLimitError::TooLow {
+                        available,
+                        required,
+                    });
+                }
+                limit
+            }
+            None => {
+                if available <= RESERVED_FILES {
+                    return Err(OsLimitError::TooLow {
+                        available,
+                        required: RESERVED_FILES + 1,
+                    });
+                }
+                usize::try_from(available - RESERVED_FILES).unwrap_or(usize::MAX)
+            }
+        };
+
+        Ok(Self::new(limit))
+    }
+
+    /// Sets how long [`Self::acquire`] waits for a socket to close once the limit is reached.
+    #[must_use]
+    pub fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    #[must_use]
+    pub fn in_use(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+
+    /// Counts one more open socket, if there is room for it.
+    ///
+    /// # Errors
+    /// If the limit is reached.
+    pub fn try_acquire(&self) -> Result<OpenFile, ResourceLimitError> {
+        match Arc::clone(&self.permits).try_acquire_owned() {
+            Ok(permit) => Ok(OpenFile { _permit: permit }),
+            Err(TryAcquireError::NoPermits | TryAcquireError::Closed) => Err(self.exhausted()),
+        }
+    }
+
+    /// Counts one more open socket, waiting for another one to close if the limit is reached.
+    ///
+    /// # Errors
+    /// If none closes in time.
+    pub async fn acquire(&self) -> Result<OpenFile, ResourceLimitError> {
+        match ::tokio::time::timeout(self.wait, Arc::clone(&self.permits).acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(OpenFile { _permit: permit }),
+            Ok(Err(_)) | Err(_) => Err(self.exhausted()),
+        }
+    }
+
+    fn exhausted(&self) -> ResourceLimitError {
+        ResourceLimitError {
+            in_use: self.in_use(),
+            limit: self.limit,
+        }
+    }
+}
+
+/// Connector that counts the sockets it opens against [`OpenFiles`]. Connecting past the limit
+/// waits for another socket to close, and fails with [`ResourceLimitError`] if none does in time.
+#[derive(Debug, Clone)]
+pub struct OpenFilesConnector<C> {
+    inner: C,
+    open_files: Option<OpenFiles>,
+}
+
+impl<C> OpenFilesConnector<C> {
+    /// Counts the sockets `inner` opens against `open_files`, if set.
+    #[must_use]
+    pub fn new(inner: C, open_files: Option<OpenFiles>) -> Self {
+        Self { inner, open_files }
+    }
+}
+
+impl<C> Service<Uri> for OpenFilesConnector<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+    C::Error: Into<BoxError>,
+{
+    type Response = CountedIo<C::Response>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let open_files = self.open_files.clone();
+        let connecting = self.inner.call(uri);
+        Box::pin(async move {
+            let open_file = match open_files {
+                Some(open_files) => Some(open_files.acquire().await?),
+                None => None,
+            };
+            let io = connecting.await.map_err(Into::into)?;
+            Ok(CountedIo {
+                inner: io,
+                _open_file: open_file,
+            })
+        })
+    }
+}
+
+/// Connection opened by [`OpenFilesConnector`], counted until it is dropped.
+#[pin_project]
+pub struct CountedIo<T> {
+    #[pin]
+    inner: T,
+    _open_file: Option<OpenFile>,
+}
+
+impl<T: Read> Read for CountedIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl<T: Write> Write for CountedIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+}
+
+impl<T: Connection> Connection for CountedIo<T> {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use tower::Service;
+
+    use super::{os_limit, OpenFiles, OpenFilesConnector, OsLimitError};
+
+    #[tokio::test]
+    async fn counts_until_dropped() {
+        let open_files = OpenFiles::new(2).with_wait(Duration::from_millis(10));
+        let first = open_files.try_acquire().unwrap();
+        let _second = open_files.acquire().await.unwrap();
+        assert_eq!(2, open_files.in_use());
+
+        let err = open_files.try_acquire().unwrap_err();
+        assert_eq!((2, 2), (err.in_use, err.limit));
+        open_files.acquire().await.unwrap_err();
+
+        drop(first);
+        assert_eq!(1, open_files.in_use());
+        open_files.try_acquire().unwrap();
+    }
+
+    #[tokio::test]
+    async fn waits_for_close() {
+        let open_files = OpenFiles::new(1);
+        let first = open_files.try_acquire().unwrap();
+        let waiting = tokio::spawn({
+            let open_files = open_files.clone();
+            async move { open_files.acquire().await.map(drop) }
+        });
+        drop(first);
+
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn counts_outgoing_connections() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse::<hyper::Uri>()
+            .unwrap();
+        let open_files = OpenFiles::new(1).with_wait(Duration::from_millis(10));
+        let mut connector = OpenFilesConnector::new(HttpConnector::new(), Some(open_files.clone()));
+
+        let first = connector.call(uri.clone()).await.unwrap();
+        assert_eq!(1, open_files.in_use());
+        let err = connector.call(uri.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("resource limit"), "{err}");
+
+        drop(first);
+        assert_eq!(0, open_files.in_use());
+        connector.call(uri).await.unwrap();
+    }
+
+    #[test]
+    fn rejects_more_than_os_allows() {
+        let Some(limit) = os_limit().unwrap() else {
+            return;
+        };
+        match OpenFiles::from_os_limit(Some(usize::try_from(limit.hard).unwrap_or(usize::MAX))) {
+            Err(OsLimitError::TooLow {
+                available,
+                required,
+            }) => {
+                assert!(available < required);
+            }
+            other => panic!("expected a resource limit error, got {other:?}"),
+        }
+    }
+}
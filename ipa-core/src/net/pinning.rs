@@ -0,0 +1,303 @@
+//! Certificate pinning for peer helpers.
+//!
+//! Verifying a peer's TLS certificate against a root of trust does not protect against a CA that
+//! issues a certificate for the peer's name to someone else. A peer can be pinned in the network
+//! discovery file, in which case its end-entity certificate must also match the SHA-256 digest of
+//! one of the pinned certificates.
+//!
+//! To rotate a pinned certificate without downtime, the pin of the new certificate becomes the
+//! current pin, and the pin of the old certificate is kept as the previous pin until the end of a
+//! transition window. The peer can switch to the new certificate at any time within the window.
+
+use std::{
+    fmt::{Debug, Display, Formatter},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::net::CRYPTO_PROVIDER;
+
+/// SHA-256 digest of a DER-encoded certificate.
+///
+/// In `network.toml`, the digest is specified as 64 hex characters.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CertificatePin([u8; Self::LEN]);
+
+impl CertificatePin {
+    pub const LEN: usize = 32;
+
+    #[must_use]
+    pub fn of(certificate: &CertificateDer) -> Self {
+        Self(Sha256::digest(certificate).into())
+    }
+}
+
+impl Display for CertificatePin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl Debug for CertificatePin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CertificatePin({self})")
+    }
+}
+
+impl<'de> Deserialize<'de> for CertificatePin {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        let mut buf = [0_u8; Self::LEN];
+        hex::decode_to_slice(s.trim(), &mut buf).map_err(serde::de::Error::custom)?;
+        Ok(Self(buf))
+    }
+}
+
+impl Serialize for CertificatePin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Pin of the certificate a peer is rotating away from.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PreviousPin {
+    pub pin: CertificatePin,
+
+    /// End of the transition window, in seconds since the Unix epoch. The previous certificate
+    /// is not accepted anymore after that.
+    pub accepted_until: u64,
+}
+
+/// Certificates a peer is pinned to.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CertificatePins {
+    /// Pin of the certificate the peer is expected to present.
+    pub current: CertificatePin,
+
+    /// Pin that is still accepted while the peer rotates its certificate.
+    #[serde(default)]
+    pub previous: Option<PreviousPin>,
+}
+
+impl CertificatePins {
+    /// Returns `true` if `certificate` matches one of the pins that are accepted at time `now`,
+    /// given in seconds since the Unix epoch.
+    #[must_use]
+    pub fn accepts(&self, certificate: &CertificateDer, now: u64) -> bool {
+        let pin = CertificatePin::of(certificate);
+        pin == self.current
+            || self
+                .previous
+                .as_ref()
+                .is_some_and(|previous| pin == previous.pin && now <= previous.accepted_until)
+    }
+
+    #[must_use]
+    pub fn status(&self, now: u64) -> PinStatus {
+        PinStatus {
+            current: self.current,
+            previous: self.previous.as_ref().map(|previous| previous.pin),
+            previous_accepted_until: self
+                .previous
+                .as_ref()
+                .map(|previous| previous.accepted_until),
+            in_transition: self
+                .previous
+                .as_ref()
+                .is_some_and(|previous| now <= previous.accepted_until),
+        }
+    }
+}
+
+/// Pins of a peer, as reported by the admin API.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PinStatus {
+    pub current: CertificatePin,
+    pub previous: Option<CertificatePin>,
+    pub previous_accepted_until: Option<u64>,
+    /// Whether the previous pin is still accepted.
+    pub in_transition: bool,
+}
+
+/// Pin status of one peer, as reported by the admin API. `pins` is `None` if the peer is not
+/// pinned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerPinStatus<I> {
+    pub identity: I,
+    pub pins: Option<PinStatus>,
+}
+
+/// Current time in seconds since the Unix epoch.
+pub(crate) fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time should be after the Unix epoch")
+        .as_secs()
+}
+
+/// Server certificate verifier that verifies the certificate against `roots` as usual, and then
+/// checks it against the pins of the peer.
+#[derive(Debug)]
+pub(crate) struct PinnedServerVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: CertificatePins,
+}
+
+impl PinnedServerVerifier {
+    /// ## Panics
+    /// If `roots` is empty.
+    pub fn new(roots: RootCertStore, pins: CertificatePins) -> Self {
+        let inner =
+            WebPkiServerVerifier::builder_with_provider(roots.into(), Arc::clone(&CRYPTO_PROVIDER))
+                .build()
+                .expect("Error building server verifier, should specify valid Trust Anchors");
+        Self { inner, pins }
+    }
+}
+
+impl ServerCertVerifier for PinnedServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        if self.pins.accepts(end_entity, now.as_secs()) {
+            Ok(verified)
+        } else {
+            tracing::error!(
+                "Certificate presented by {server_name:?} does not match its pins. Pin of the certificate: {}",
+                CertificatePin::of(end_entity),
+            );
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Loads the root certificates of the system truststore.
+pub(crate) fn native_roots() -> RootCertStore {
+    let result = rustls_native_certs::load_native_certs();
+    if !result.errors.is_empty() {
+        tracing::warn!(
+            "Errors loading native root certificates: {:?}",
+            result.errors
+        );
+    }
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(result.certs);
+    roots
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rustls_pki_types::CertificateDer;
+
+    use super::{CertificatePin, CertificatePins, PinStatus, PreviousPin};
+
+    const OLD: &[u8] = b"old certificate";
+    const NEW: &[u8] = b"new certificate";
+
+    fn pins() -> CertificatePins {
+        CertificatePins {
+            current: CertificatePin::of(&CertificateDer::from(NEW)),
+            previous: Some(PreviousPin {
+                pin: CertificatePin::of(&CertificateDer::from(OLD)),
+                accepted_until: 100,
+            }),
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let pins = pins();
+        let (old, new) = (CertificateDer::from(OLD), CertificateDer::from(NEW));
+
+        assert!(pins.accepts(&new, 0));
+        assert!(pins.accepts(&old, 100));
+        assert!(pins.accepts(&new, 101));
+        assert!(!pins.accepts(&old, 101));
+        assert!(!pins.accepts(&CertificateDer::from(&b"other"[..]), 0));
+    }
+
+    #[test]
+    fn status() {
+        let pins = pins();
+        assert_eq!(
+            PinStatus {
+                current: pins.current,
+                previous: Some(pins.previous.as_ref().unwrap().pin),
+                previous_accepted_until: Some(100),
+                in_transition: true,
+            },
+            pins.status(100)
+        );
+        assert!(!pins.status(101).in_transition);
+    }
+
+    #[test]
+    fn parse() {
+        let pin = CertificatePin::of(&CertificateDer::from(NEW));
+        let pins: CertificatePins = toml::from_str(&format!(
+            r#"
+            current = "{pin}"
+
+            [previous]
+            pin = "{pin}"
+            accepted_until = 1767225600
+            "#
+        ))
+        .unwrap();
+        assert_eq!(pin, pins.current);
+        assert_eq!(1_767_225_600, pins.previous.unwrap().accepted_until);
+
+        assert!(toml::from_str::<CertificatePins>(r#"current = "abcd""#).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, ops::Range, pin::Pin, time::Duration};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::sync::{oneshot, Notify};
+
+use crate::{
+    helpers::{StreamKey, TransportIdentity},
+    protocol::QueryId,
+    sync::{Arc, Mutex},
+};
+
+type DataStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+/// How long [`PullSources::pull`] waits for the channel to be served and for its next chunk,
+/// unless configured otherwise with [`PullSources::with_timeout`]. Like the peer timeout of the
+/// gateway, this must be longer than any stretch in which the sender legitimately has nothing
+/// to send on the channel.
+pub const PULL_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, thiserror::Error)]
+pub enum PullError {
+    #[error("offset {requested} cannot be served, expected one of {expected:?}")]
+    OffsetOutOfRange {
+        requested: u64,
+        expected: Range<u64>,
+    },
+    #[error("no step data to serve after waiting for {0:?}")]
+    Timeout(Duration),
+}
+
+/// Step data that this helper sends to peers receiving it in pull mode. Instead of pushing it to
+/// the peer, every channel is kept here until the peer asks for the next chunk of it.
+///
+/// Channels are indexed by [`StreamKey`], where identity is the peer receiving the data. Like
+/// [`StreamCollection`], a channel is never removed once it has been served to the end, so
+/// a late request for its end is still answered.
+///
+/// [`StreamCollection`]: crate::helpers::StreamCollection
+pub struct PullSources<I> {
+    channels: Mutex<HashMap<StreamKey<I>, Arc<tokio::sync::Mutex<PullSource>>>>,
+    added: Notify,
+    timeout: Duration,
+}
+
+impl<I> Default for PullSources<I> {
+    fn default() -> Self {
+        Self::with_timeout(PULL_TIMEOUT)
+    }
+}
+
+impl<I> PullSources<I> {
+    /// Pulls that have nothing to serve after waiting for `timeout` fail with
+    /// [`PullError::Timeout`].
+    #[must_use]
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            channels: Mutex::new(HashMap::new()),
+            added: Notify::new(),
+            timeout,
+        }
+    }
+}
+
+struct PullSource {
+    data: DataStream,
+    /// Offset of the first byte that has not been handed out yet.
+    next_offset: u64,
+    /// The last chunk handed out and the offset it starts at. If the response carrying it got
+    /// lost, the peer asks for the same offset again and gets it from here.
+    last: Option<(u64, Bytes)>,
+    /// Fired once the peer asked past the end of the channel.
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl<I: TransportIdentity> PullSources<I> {
+    /// Makes `data` available to the peer and waits until the peer has read all of it.
+    ///
+    /// ## Panics
+    /// If there was another channel with the same key before or if mutex is poisoned.
+    pub async fn serve<D: Stream<Item = Vec<u8>> + Send + 'static>(
+        &self,
+        key: StreamKey<I>,
+        data: D,
+    ) {
+        let (tx, rx) = oneshot::channel();
+        let source = PullSource {
+            data: Box::pin(data),
+            next_offset: 0,
+            last: None,
+            done: Some(tx),
+        };
+        {
+            let mut channels = self.channels.lock().unwrap();
+            assert!(
+                !channels.contains_key(&key),
+                "{key:?} is already served to the peer"
+            );
+            channels.insert(key, Arc::new(tokio::sync::Mutex::new(source)));
+        }
+        self.added.notify_waiters();
+
+        // The channel may also be dropped before it was read to the end, see
+        // [`Self::clear_query`].
+        let _ = rx.await;
+    }
+
+    /// Drops the channels of `query_id`, so the senders waiting for them to be read are
+    /// released.
+    ///
+    /// ## Panics
+    /// If mutex is poisoned.
+    pub fn clear_query(&self, query_id: QueryId) {
+        self.channels
+            .lock()
+            .unwrap()
+            .retain(|(id, _, _), _| *id != query_id);
+    }
+
+    /// Returns the chunk of the channel that starts at `from_offset`, or `None` if `from_offset`
+    /// is the end of it. If the channel is not served yet, or its next chunk is not ready, waits
+    /// until it is, but not longer than the timeout of these sources. A sender that failed or
+    /// dropped the query without serving the channel would otherwise hold the pull forever.
+    ///
+    /// The peer must ask for the offset right after the last chunk it received. It may also ask
+    /// for the last chunk again, if the response carrying it got lost.
+    ///
+    /// ## Errors
+    /// If `from_offset` is neither of these, or if the timeout expires.
+    ///
+    /// ## Panics
+    /// If mutex is poisoned.
+    pub async fn pull(
+        &self,
+        key: &StreamKey<I>,
+        from_offset: u64,
+    ) -> Result<Option<Bytes>, PullError> {
+        tokio::time::timeout(self.timeout, self.pull_inner(key, from_offset))
+            .await
+            .map_err(|_| PullError::Timeout(self.timeout))?
+    }
+
+    async fn pull_inner(
+        &self,
+        key: &StreamKey<I>,
+        from_offset: u64,
+    ) -> Result<Option<Bytes>, PullError> {
+        let source = loop {
+            // Must be created before the lookup, so a channel added right after it is not missed.
+            let added = self.added.notified();
+            if let Some(source) = self.channels.lock().unwrap().get(key) {
+                break Arc::clone(source);
+            }
+            added.await;
+        };
+
+        let mut source = source.lock().await;
+        match &source.last {
+            Some((offset, chunk)) if *offset == from_offset => return Ok(Some(chunk.clone())),
+            _ => {}
+        }
+        if from_offset != source.next_offset {
+            let start = source.last.as_ref().map_or(0, |(offset, _)| *offset);
+            return Err(PullError::OffsetOutOfRange {
+                requested: from_offset,
+                expected: start..source.next_offset + 1,
+            });
+        }
+
+        if let Some(chunk) = source.data.next().await {
+            let chunk = Bytes::from(chunk);
+            source.next_offset += u64::try_from(chunk.len()).unwrap();
+            source.last = Some((from_offset, chunk.clone()));
+            Ok(Some(chunk))
+        } else {
+            if let Some(done) = source.done.take() {
+                let _ = done.send(());
+            }
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{task::Poll, time::Duration};
+
+    use futures::{future::poll_immediate, stream, StreamExt};
+
+    use super::{PullError, PullSources};
+    use crate::{
+        helpers::HelperIdentity,
+        protocol::{Gate, QueryId},
+        test_executor::run,
+    };
+
+    fn key() -> (QueryId, HelperIdentity, Gate) {
+        (QueryId, HelperIdentity::TWO, Gate::default())
+    }
+
+    #[test]
+    fn serves_in_order() {
+        run(|| async {
+            let sources = PullSources::default();
+            let serve = sources.serve(key(), stream::iter(vec![vec![1, 2], vec![3]]));
+            let pull = async {
+                assert_eq!(
+                    &[1, 2],
+                    &sources.pull(&key(), 0).await.unwrap().unwrap()[..]
+                );
+                assert_eq!(&[3], &sources.pull(&key(), 2).await.unwrap().unwrap()[..]);
+                assert_eq!(None, sources.pull(&key(), 3).await.unwrap());
+                // end can be requested again
+                assert_eq!(None, sources.pull(&key(), 3).await.unwrap());
+            };
+            futures::join!(serve, pull);
+        });
+    }
+
+    #[test]
+    fn repeats_last_chunk() {
+        run(|| async {
+            let sources = PullSources::default();
+            let serve = sources.serve(key(), stream::iter(vec![vec![1, 2], vec![3]]));
+            let pull = async {
+                assert_eq!(
+                    &[1, 2],
+                    &sources.pull(&key(), 0).await.unwrap().unwrap()[..]
+                );
+                assert_eq!(
+                    &[1, 2],
+                    &sources.pull(&key(), 0).await.unwrap().unwrap()[..]
+                );
+                assert_eq!(&[3], &sources.pull(&key(), 2).await.unwrap().unwrap()[..]);
+                assert_eq!(None, sources.pull(&key(), 3).await.unwrap());
+            };
+            futures::join!(serve, pull);
+        });
+    }
+
+    #[test]
+    fn rejects_unexpected_offset() {
+        run(|| async {
+            let sources = PullSources::default();
+            let serve = sources.serve(key(), stream::iter(vec![vec![1, 2], vec![3], vec![4]]));
+            let pull = async {
+                sources.pull(&key(), 0).await.unwrap();
+                sources.pull(&key(), 2).await.unwrap();
+                let err = sources.pull(&key(), 0).await.unwrap_err();
+                assert!(
+                    matches!(err, PullError::OffsetOutOfRange { expected, .. } if expected == (2..4))
+                );
+                sources.pull(&key(), 3).await.unwrap();
+                sources.pull(&key(), 4).await.unwrap();
+            };
+            futures::join!(serve, pull);
+        });
+    }
+
+    #[test]
+    fn waits_for_channel() {
+        run(|| async {
+            let sources = PullSources::default();
+            let mut first = Box::pin(sources.pull(&key(), 0));
+            assert!(poll_immediate(&mut first).await.is_none());
+
+            let serve = sources.serve(key(), stream::iter(vec![vec![5]]));
+            let pull = async {
+                assert_eq!(&[5], &first.await.unwrap().unwrap()[..]);
+                assert_eq!(None, sources.pull(&key(), 1).await.unwrap());
+            };
+            futures::join!(serve, pull);
+        });
+    }
+
+    #[test]
+    fn serve_completes_after_end_is_read() {
+        run(|| async {
+            let sources = PullSources::default();
+            let mut serve = Box::pin(sources.serve(key(), stream::iter(vec![vec![1]])));
+            assert_eq!(Poll::Pending, futures::poll!(serve.as_mut()));
+            sources.pull(&key(), 0).await.unwrap();
+            assert_eq!(Poll::Pending, futures::poll!(serve.as_mut()));
+            sources.pull(&key(), 1).await.unwrap();
+            serve.await;
+        });
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let sources = PullSources::with_timeout(Duration::from_millis(10));
+        // the channel is never served
+        assert!(matches!(
+            sources.pull(&key(), 0).await,
+            Err(PullError::Timeout(_))
+        ));
+
+        // or it never gets its next chunk
+        let serve = sources.serve(key(), stream::iter(vec![vec![1]]).chain(stream::pending()));
+        let pull = async {
+            sources.pull(&key(), 0).await.unwrap();
+            assert!(matches!(
+                sources.pull(&key(), 1).await,
+                Err(PullError::Timeout(_))
+            ));
+            sources.clear_query(QueryId);
+        };
+        futures::join!(serve, pull);
+    }
+}
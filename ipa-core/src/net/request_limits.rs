@@ -0,0 +1,127 @@
+//! Limits on the size of the request targets and headers the server accepts.
+//!
+//! Proxies and load balancers in front of helpers refuse requests whose target or headers are
+//! larger than they are configured to accept, often with errors that don't tell which limit
+//! was hit. Helpers enforce limits of their own, so that oversized requests fail the same way
+//! no matter what is deployed in front of them: with `414 URI Too Long` or `431 Request Header
+//! Fields Too Large`, and a message naming the size of the request and the limit it exceeds.
+//!
+//! Step names grow with the depth of the step tree. Starting with protocol version 7, they are
+//! sent in a request header instead of the request path, see [`ProtocolVersion::has_step_header`].
+//!
+//! [`ProtocolVersion::has_step_header`]: crate::protocol::ProtocolVersion::has_step_header
+
+use hyper::{HeaderMap, Request};
+
+use crate::net::Error;
+
+/// Longest request target, path and query string, helpers accept by default.
+pub const DEFAULT_MAX_URI_LEN: usize = 8 * 1024;
+
+/// Largest header list helpers accept by default, as counted by [`header_list_size`].
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// Size limits the server puts on every request before routing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    pub max_uri_len: usize,
+    pub max_header_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_uri_len: DEFAULT_MAX_URI_LEN,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Checks that the target and the headers of `req` are within these limits.
+    ///
+    /// ## Errors
+    /// [`Error::UriTooLong`] or [`Error::HeadersTooLarge`] if `req` exceeds one of the limits.
+    pub fn check<B>(self, req: &Request<B>) -> Result<(), Error> {
+        let uri_len = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path().len(), |pq| pq.as_str().len());
+        if uri_len > self.max_uri_len {
+            return Err(Error::UriTooLong {
+                len: uri_len,
+                limit: self.max_uri_len,
+            });
+        }
+
+        let header_bytes = header_list_size(req.headers());
+        if header_bytes > self.max_header_bytes {
+            return Err(Error::HeadersTooLarge {
+                size: header_bytes,
+                limit: self.max_header_bytes,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of `headers` the way HTTP/2 counts it for `SETTINGS_MAX_HEADER_LIST_SIZE`: the length
+/// of every name and value, plus 32 bytes of overhead per header.
+#[must_use]
+pub fn header_list_size(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 32)
+        .sum()
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use hyper::Request;
+
+    use super::RequestLimits;
+    use crate::net::Error;
+
+    const LIMITS: RequestLimits = RequestLimits {
+        max_uri_len: 32,
+        max_header_bytes: 64,
+    };
+
+    #[test]
+    fn within_limits() {
+        let req = Request::get("http://localhost/query/1?a=1")
+            .header("x-foo", "bar")
+            .body(())
+            .unwrap();
+        LIMITS.check(&req).unwrap();
+    }
+
+    #[test]
+    fn uri_too_long() {
+        // The scheme and authority don't count, the query string does.
+        let req = Request::get(format!("http://localhost/{}?a=1", "a".repeat(28)))
+            .body(())
+            .unwrap();
+        assert!(matches!(
+            LIMITS.check(&req),
+            Err(Error::UriTooLong { len: 33, limit: 32 })
+        ));
+    }
+
+    #[test]
+    fn headers_too_large() {
+        let req = Request::get("http://localhost/")
+            .header("x-foo", "bar")
+            .header("x-bar", "a".repeat(24))
+            .body(())
+            .unwrap();
+        assert!(matches!(
+            LIMITS.check(&req),
+            Err(Error::HeadersTooLarge {
+                size: 101,
+                limit: 64
+            })
+        ));
+    }
+}
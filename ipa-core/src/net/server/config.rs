@@ -1,12 +1,27 @@
-use hyper_util::server::conn::auto::Http2Builder;
+use hyper_util::server::conn::auto::Builder;
 
-use crate::net::{MAX_HTTP2_CONCURRENT_STREAMS, MAX_HTTP2_WINDOW_SIZE};
+use crate::net::{RequestLimits, MAX_HTTP2_CONCURRENT_STREAMS, MAX_HTTP2_WINDOW_SIZE};
+
+/// Smallest read buffer hyper accepts for HTTP/1 connections.
+const MIN_HTTP1_BUF_SIZE: usize = 8192;
 
 pub(super) struct HttpServerConfig;
 
 impl HttpServerConfig {
-    pub fn apply<'a, E>(http_builder: &'a mut Http2Builder<'a, E>) -> &'a mut Http2Builder<'a, E> {
+    pub fn apply<E>(http_builder: &mut Builder<E>, limits: RequestLimits) {
+        // `EnforceRequestLimits` only sees requests once hyper has read and parsed their heads,
+        // so hyper is told not to buffer more than the limits allow in the first place. An
+        // HTTP/1 request head holds the request target and the headers.
+        http_builder.http1().max_buf_size(
+            limits
+                .max_uri_len
+                .saturating_add(limits.max_header_bytes)
+                .max(MIN_HTTP1_BUF_SIZE),
+        );
         http_builder
+            .http2()
+            // Counted the same way as `RequestLimits::max_header_bytes`.
+            .max_header_list_size(u32::try_from(limits.max_header_bytes).unwrap_or(u32::MAX))
             // This turns off the flow control at the connection level. We ran into issues
             // (see #1085) while having it in place. IPA protocol tend to open many concurrent
             // streams and push data simultaneously through them without having any explicit
@@ -28,6 +43,6 @@ impl HttpServerConfig {
             //
             // See:
             // [`hyper_util::server::conn::auto::Http2Builder::max_concurrent_streams`]
-            .max_concurrent_streams(MAX_HTTP2_CONCURRENT_STREAMS)
+            .max_concurrent_streams(MAX_HTTP2_CONCURRENT_STREAMS);
     }
 }
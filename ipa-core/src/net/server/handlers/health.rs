@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use futures::future::join_all;
+use hyper::StatusCode;
+
+use crate::{
+    helpers::{routing::RouteId, BodyStream, HelperIdentity},
+    net::{
+        http_serde::health::{Health, HEALTH_AXUM_PATH, READY_AXUM_PATH},
+        transport::MpcHttpTransport,
+    },
+    query::{Readiness, ReadinessCheck},
+    sync::Arc,
+};
+
+/// A peer that answered a request this recently is not asked again.
+const PEER_FRESHNESS: Duration = Duration::from_secs(30);
+
+/// How long a peer has to answer the echo request of a readiness check.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When each peer last answered a readiness echo request.
+#[derive(Clone, Default)]
+struct PeerContacts(Arc<Mutex<HashMap<HelperIdentity, Instant>>>);
+
+#[allow(clippy::unused_async)] // needs to be async for axum handler
+async fn health() -> Json<Health> {
+    Json(Health::ok())
+}
+
+async fn ready(
+    transport: Extension<MpcHttpTransport>,
+    contacts: Extension<PeerContacts>,
+) -> Response {
+    let mut checks = match transport
+        .dispatch(RouteId::Readiness, BodyStream::empty())
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|resp| {
+            resp.try_into_owned::<Readiness>()
+                .map_err(|e| e.to_string())
+        }) {
+        Ok(readiness) => readiness.checks,
+        Err(e) => vec![ReadinessCheck::fail("query_processor", e)],
+    };
+    let identity = transport.inner_transport.identity;
+    #[allow(clippy::disallowed_methods)] // It's just 2 peers.
+    checks.extend(
+        join_all(
+            identity
+                .others()
+                .map(|peer| check_peer(&transport, &contacts, peer)),
+        )
+        .await,
+    );
+
+    let readiness = Readiness::new(checks);
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
+
+/// Checks that `peer` answered recently, and sends it an echo request if it did not.
+async fn check_peer(
+    transport: &MpcHttpTransport,
+    contacts: &PeerContacts,
+    peer: HelperIdentity,
+) -> ReadinessCheck {
+    let name = format!("peer_{peer}");
+    let last_contact = contacts.0.lock().unwrap().get(&peer).copied();
+    if last_contact.is_some_and(|at| at.elapsed() < PEER_FRESHNESS) {
+        return ReadinessCheck::pass(&name);
+    }
+
+    let client = &transport.inner_transport.clients[peer];
+    match tokio::time::timeout(PEER_TIMEOUT, client.echo("ready")).await {
+        Ok(Ok(_)) => {
+            contacts.0.lock().unwrap().insert(peer, Instant::now());
+            ReadinessCheck::pass(&name)
+        }
+        Ok(Err(e)) => ReadinessCheck::fail(&name, e),
+        Err(_) => ReadinessCheck::fail(&name, format!("no answer within {PEER_TIMEOUT:?}")),
+    }
+}
+
+/// Construct router for the health and readiness probes. They don't require authentication, so
+/// that orchestrators can call them.
+pub fn router(transport: MpcHttpTransport) -> Router {
+    Router::new()
+        .route(HEALTH_AXUM_PATH, get(health))
+        .route(READY_AXUM_PATH, get(ready))
+        .layer(Extension(transport))
+        .layer(Extension(PeerContacts::default()))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::{Request, StatusCode};
+
+    use crate::{
+        helpers::{make_owned_handler, routing::RouteId, HelperResponse},
+        net::{http_serde::health::Health, test::TestServer},
+        query::{Readiness, ReadinessCheck},
+    };
+
+    async fn get(path: &str, response: Readiness) -> (StatusCode, bytes::Bytes) {
+        let handler = make_owned_handler(move |addr, _| {
+            let response = response.clone();
+            async move {
+                let RouteId::Readiness = addr.route else {
+                    panic!("unexpected call: {addr:?}");
+                };
+                Ok(HelperResponse::from(response))
+            }
+        });
+        let test_server = TestServer::builder()
+            .with_request_handler(handler)
+            .build()
+            .await;
+        let req = Request::get(format!("http://localhost{path}"))
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_server.server.handle_req(req).await;
+        let status = resp.status();
+        (status, resp.into_body().collect().await.unwrap().to_bytes())
+    }
+
+    #[tokio::test]
+    async fn healthz() {
+        let (status, body) = get("/healthz", Readiness::new(Vec::new())).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(Health::ok(), serde_json::from_slice(&body).unwrap());
+    }
+
+    #[tokio::test]
+    async fn readyz() {
+        let processor = Readiness::new(vec![
+            ReadinessCheck::pass("key_registry"),
+            ReadinessCheck::fail("workspace", "disk full"),
+        ]);
+        let (status, body) = get("/readyz", processor.clone()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+
+        let readiness: Readiness = serde_json::from_slice(&body).unwrap();
+        assert!(!readiness.ready);
+        assert_eq!(processor.checks, readiness.checks[..2]);
+        assert_eq!(
+            vec!["peer_B", "peer_C"],
+            readiness.checks[2..]
+                .iter()
+                .map(|check| check.name.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,17 +1,30 @@
 mod echo;
+mod health;
 mod metrics;
+mod pins;
+mod queries;
 mod query;
+mod templates;
 
 use axum::Router;
 
 use crate::{
-    net::{http_serde, transport::MpcHttpTransport, HttpTransport, Shard},
+    config::{AdminToken, NetworkConfig},
+    net::{http_serde, transport::MpcHttpTransport, Helper, HttpTransport, Shard},
     sync::Arc,
 };
 
-pub fn mpc_router(transport: MpcHttpTransport) -> Router {
+pub fn mpc_router(
+    transport: MpcHttpTransport,
+    network_config: NetworkConfig<Helper>,
+    admin_token: Option<AdminToken>,
+) -> Router {
     echo::router()
+        .merge(health::router(transport.clone()))
         .merge(metrics::router(transport.clone()))
+        .merge(pins::router(Arc::new(network_config), admin_token.clone()))
+        .merge(queries::router(transport.clone(), admin_token.clone()))
+        .merge(templates::router(transport.clone(), admin_token))
         .nest(
             http_serde::query::BASE_AXUM_PATH,
             Router::new()
@@ -0,0 +1,93 @@
+use axum::{routing::get, Extension, Json, Router};
+use tower::layer::layer_fn;
+
+use crate::{
+    config::{AdminToken, NetworkConfig},
+    helpers::HelperIdentity,
+    net::{
+        http_serde, pinning::unix_time_now, server::handlers::templates::AdminAuthentication,
+        Helper, PeerPinStatus,
+    },
+    sync::Arc,
+};
+
+async fn handler(
+    network_config: Extension<Arc<NetworkConfig<Helper>>>,
+) -> Json<Vec<PeerPinStatus<HelperIdentity>>> {
+    Json(network_config.pin_status(unix_time_now()))
+}
+
+/// Construct router for the certificate pin status of the peers. It requires the caller to
+/// present `admin_token`.
+pub fn router(
+    network_config: Arc<NetworkConfig<Helper>>,
+    admin_token: Option<AdminToken>,
+) -> Router {
+    Router::new()
+        .route(http_serde::pins::AXUM_PATH, get(handler))
+        .layer(Extension(network_config))
+        .layer(layer_fn(move |inner| {
+            AdminAuthentication::new(inner, admin_token.clone())
+        }))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::{header::AUTHORIZATION, Request, StatusCode};
+    use rustls_pki_types::CertificateDer;
+    use tower::ServiceExt;
+
+    use crate::{
+        config::{AdminToken, ClientConfig, NetworkConfig, PeerConfig},
+        net::{CertificatePin, CertificatePins, PreviousPin},
+        sync::Arc,
+    };
+
+    #[tokio::test]
+    async fn pin_status() {
+        let pin = CertificatePin::of(&CertificateDer::from(&b"certificate"[..]));
+        let mut peers = (0..3)
+            .map(|_| PeerConfig::new("https://localhost".parse().unwrap(), None))
+            .collect::<Vec<_>>();
+        peers[1].certificate_pins = Some(CertificatePins {
+            current: pin,
+            previous: Some(PreviousPin {
+                pin,
+                accepted_until: 0,
+            }),
+        });
+        let network_config = Arc::new(NetworkConfig::new_mpc(peers, ClientConfig::default()));
+        let router = super::router(network_config, Some(AdminToken::new("secret".to_string())));
+
+        let resp = router
+            .oneshot(
+                Request::get("/pins")
+                    .header(AUTHORIZATION, "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                { "identity": 1, "pins": null },
+                {
+                    "identity": 2,
+                    "pins": {
+                        "current": pin.to_string(),
+                        "previous": pin.to_string(),
+                        "previous_accepted_until": 0,
+                        "in_transition": false,
+                    },
+                },
+                { "identity": 3, "pins": null },
+            ]),
+            status
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use axum::{extract::Query, routing::get, Extension, Json, Router};
+use hyper::StatusCode;
+use tower::layer::layer_fn;
+
+use crate::{
+    config::AdminToken,
+    helpers::BodyStream,
+    net::{
+        http_serde::{self, queries::ResponseBody},
+        server::handlers::templates::AdminAuthentication,
+        transport::MpcHttpTransport,
+        Error,
+    },
+    query::ListQueries,
+};
+
+async fn handler(
+    transport: Extension<MpcHttpTransport>,
+    Query(params): Query<ListQueries>,
+) -> Result<Json<ResponseBody>, Error> {
+    match transport.dispatch(params, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(ResponseBody::from(resp))),
+        Err(e) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+/// Construct router for listing the queries of this helper. It requires the caller to present
+/// `admin_token`.
+pub fn router(transport: MpcHttpTransport, admin_token: Option<AdminToken>) -> Router {
+    Router::new()
+        .route(http_serde::queries::AXUM_PATH, get(handler))
+        .layer(Extension(transport))
+        .layer(layer_fn(move |inner| {
+            AdminAuthentication::new(inner, admin_token.clone())
+        }))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use hyper::{
+        http::uri::{Authority, Scheme},
+        StatusCode,
+    };
+
+    use crate::{
+        helpers::{make_owned_handler, routing::RouteId, HelperResponse},
+        net::{http_serde, server::handlers::query::test_helpers::assert_fails_with_handler},
+        query::{ListQueries, QueryList},
+    };
+
+    /// Test servers have no admin token, so the request never reaches the handler.
+    #[tokio::test]
+    async fn disabled_without_token() {
+        let req = http_serde::queries::Request::new("secret".to_string(), ListQueries::default())
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        let handler = make_owned_handler(move |addr, _| async move {
+            let RouteId::ListQueries = addr.route else {
+                panic!("unexpected call");
+            };
+            Ok(HelperResponse::from(QueryList::default()))
+        });
+        assert_fails_with_handler(req, handler, StatusCode::FORBIDDEN).await;
+    }
+}
@@ -4,7 +4,8 @@ use hyper::StatusCode;
 use crate::{
     helpers::{ApiError, BodyStream},
     net::{
-        http_serde::{self, query::QueryConfigQueryParams},
+        http_serde::{self, query::ValidQueryConfigQueryParams},
+        server::handlers::templates::template_error_status,
         transport::MpcHttpTransport,
         Error,
     },
@@ -15,13 +16,24 @@ use crate::{
 /// to the [`HttpTransport`].
 async fn handler(
     transport: Extension<MpcHttpTransport>,
-    QueryConfigQueryParams(query_config): QueryConfigQueryParams,
+    ValidQueryConfigQueryParams(query_config): ValidQueryConfigQueryParams,
 ) -> Result<Json<http_serde::query::create::ResponseBody>, Error> {
     match transport.dispatch(query_config, BodyStream::empty()).await {
         Ok(resp) => Ok(Json(resp.try_into()?)),
         Err(err @ ApiError::NewQuery(NewQueryError::State { .. })) => {
             Err(Error::application(StatusCode::CONFLICT, err))
         }
+        Err(err @ ApiError::NewQuery(NewQueryError::HelperUnavailable(_))) => {
+            Err(Error::application(StatusCode::SERVICE_UNAVAILABLE, err))
+        }
+        Err(
+            err @ ApiError::NewQuery(
+                NewQueryError::UnsupportedQueryType(_) | NewQueryError::Invalid(_),
+            ),
+        ) => Err(Error::application(StatusCode::BAD_REQUEST, err)),
+        Err(ApiError::NewQuery(NewQueryError::Template(err))) => {
+            Err(Error::application(template_error_status(&err), err))
+        }
         Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
     }
 }
@@ -46,15 +58,21 @@ mod tests {
         ff::FieldType,
         helpers::{
             make_owned_handler,
-            query::{IpaQueryConfig, PrepareQuery, QueryConfig, QueryType},
+            query::{IpaQueryConfig, PrepareQuery, QueryConfig, QueryType, ShareConversionParams},
             routing::RouteId,
-            HelperResponse, Role, RoleAssignment,
+            HelperResponse, Role, RoleAssignment, StageConcurrency,
         },
         net::{
             http_serde,
             server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
         },
-        protocol::QueryId,
+        protocol::{
+            ipa_prf::{
+                prf_sharding::credit_capping::CappingStrategy, AggregationMethod, HistogramOverflow,
+            },
+            ProtocolVersion, QueryId,
+        },
+        report::SiteDomainHash,
     };
 
     async fn create_test(expected_query_config: QueryConfig) {
@@ -72,6 +90,9 @@ mod tests {
                 query_id: QueryId,
                 config: query_config,
                 roles: RoleAssignment::try_from([Role::H1, Role::H2, Role::H3]).unwrap(),
+                protocol_version: ProtocolVersion::CURRENT,
+                pull_receivers: Vec::new(),
+                stage_concurrency: StageConcurrency::default(),
             }))
         });
         let resp = assert_success_with(req, handler).await;
@@ -85,6 +106,19 @@ mod tests {
         create_test(QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1).unwrap()).await;
     }
 
+    #[tokio::test]
+    async fn create_test_share_conversion() {
+        create_test(
+            QueryConfig::new(
+                QueryType::TestShareConversion(ShareConversionParams { bits: 40 }),
+                FieldType::Fp32BitPrime,
+                1000,
+            )
+            .unwrap(),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn create_test_ipa_no_attr_window() {
         create_test(
@@ -92,10 +126,11 @@ mod tests {
                 QueryType::SemiHonestOprfIpa(IpaQueryConfig {
                     per_user_credit_cap: 1,
                     max_breakdown_key: 1,
-                    attribution_window_seconds: None,
                     with_dp: 0,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    breakdown_key_bits: 5,
+                    ..IpaQueryConfig::default()
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -110,12 +145,9 @@ mod tests {
         create_test(
             QueryConfig::new(
                 QueryType::SemiHonestOprfIpa(IpaQueryConfig {
-                    per_user_credit_cap: 8,
-                    max_breakdown_key: 20,
-                    attribution_window_seconds: None,
-                    with_dp: 1,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    ..IpaQueryConfig::default()
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -130,12 +162,10 @@ mod tests {
         create_test(
             QueryConfig::new(
                 QueryType::MaliciousOprfIpa(IpaQueryConfig {
-                    per_user_credit_cap: 8,
-                    max_breakdown_key: 20,
-                    attribution_window_seconds: None,
-                    with_dp: 1,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    trigger_value_bits: 16,
+                    ..IpaQueryConfig::default()
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -157,6 +187,102 @@ mod tests {
                 with_dp: 0,
                 epsilon: 5.0,
                 plaintext_match_keys: true,
+                timestamp_bits: 24,
+                timestamp_granularity_seconds: NonZeroU32::new(60).unwrap(),
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_site_domain() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                site_domain_hash: Some(SiteDomainHash::of("example.com")),
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_paired_arms() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                paired_arms: true,
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_escrowed_results() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                escrow_results: true,
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_proportional_capping() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                capping_strategy: CappingStrategy::Proportional,
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_oblivious_aggregation() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                aggregation_method: AggregationMethod::Oblivious,
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_wrapping_histogram() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                histogram_value_bits: 16,
+                histogram_overflow: HistogramOverflow::Wrap,
+                ..IpaQueryConfig::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_with_time_to_conversion() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                time_to_conversion_bucket_seconds: NonZeroU32::new(3600),
+                time_to_conversion_buckets: 12,
+                ..IpaQueryConfig::default()
             }),
         })
         .await;
@@ -311,4 +437,84 @@ mod tests {
         };
         assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
     }
+
+    /// Request for a semi-honest IPA query with only the required parameters set, followed by
+    /// `extra`.
+    fn ipa_req(extra: &str) -> hyper::Request<Body> {
+        OverrideReq {
+            field_type: format!("{:?}", FieldType::Fp32BitPrime),
+            query_type_params: format!(
+                "query_type={}&per_user_credit_cap=1&max_breakdown_key=1&with_dp=1&epsilon=3.0{extra}",
+                QueryType::SEMI_HONEST_OPRF_IPA_STR
+            ),
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn omitted_ipa_params_take_defaults() {
+        let handler = make_owned_handler(move |addr, _| async move {
+            let query_config: QueryConfig = addr.into().unwrap();
+            assert_eq!(
+                query_config.query_type,
+                QueryType::SemiHonestOprfIpa(IpaQueryConfig {
+                    per_user_credit_cap: 1,
+                    max_breakdown_key: 1,
+                    with_dp: 1,
+                    epsilon: 3.0,
+                    ..IpaQueryConfig::default()
+                })
+            );
+            Ok(HelperResponse::from(PrepareQuery {
+                query_id: QueryId,
+                config: query_config,
+                roles: RoleAssignment::try_from([Role::H1, Role::H2, Role::H3]).unwrap(),
+                protocol_version: ProtocolVersion::CURRENT,
+                pull_receivers: Vec::new(),
+                stage_concurrency: StageConcurrency::default(),
+            }))
+        });
+        assert_success_with(ipa_req(""), handler).await;
+    }
+
+    #[tokio::test]
+    async fn unknown_param_ipa() {
+        assert_fails_with(
+            ipa_req("&per_user_credit_capp=2"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn unknown_param_mul() {
+        let req = OverrideMulReq {
+            query_type: format!("{}&bits=3", QueryType::TEST_MULTIPLY_STR),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
+    #[tokio::test]
+    async fn duplicate_param_ipa() {
+        assert_fails_with(ipa_req("&epsilon=1.0"), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
+    #[tokio::test]
+    async fn unsupported_trigger_value_bits_ipa() {
+        assert_fails_with(
+            ipa_req("&trigger_value_bits=5"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn breakdown_key_out_of_range_ipa() {
+        assert_fails_with(
+            ipa_req("&breakdown_key_bits=5&max_breakdown_key=64"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await;
+    }
 }
@@ -1,35 +1,131 @@
-use axum::{extract::Path, routing::post, Extension, Router};
+use axum::{
+    extract::{Path, Query},
+    routing::post,
+    Extension, Json, Router,
+};
 use hyper::StatusCode;
 
 use crate::{
-    helpers::{query::QueryInput, routing::RouteId, BodyStream},
-    net::{http_serde, transport::MpcHttpTransport, Error},
+    helpers::{
+        query::{AppendInput, QueryInput, SealInput},
+        routing::RouteId,
+        ApiError, BodyStream,
+    },
+    net::{
+        http_serde::{
+            self,
+            query::{
+                delete_input,
+                input::{AppendQueryParams, ContributionQueryParams},
+                seal_input,
+            },
+        },
+        transport::MpcHttpTransport,
+        Error,
+    },
     protocol::QueryId,
+    query::{DeleteInputError, QueryInputError},
 };
 
 async fn handler(
     transport: Extension<MpcHttpTransport>,
     Path(query_id): Path<QueryId>,
+    Query(contribution): Query<ContributionQueryParams>,
+    Query(append): Query<AppendQueryParams>,
     input_stream: BodyStream,
 ) -> Result<(), Error> {
     let query_input = QueryInput {
         query_id,
         input_stream,
+        contribution: contribution.try_into()?,
+    };
+    let append: Option<AppendInput> = append.try_into()?;
+    let resp = match (query_input.contribution, append) {
+        (Some(_), Some(_)) => {
+            return Err(Error::BadQueryString(
+                "slices of a query input can't be appended in batches".into(),
+            ))
+        }
+        (Some(contribution), None) => {
+            transport
+                .dispatch(
+                    (query_input.query_id, contribution),
+                    query_input.input_stream,
+                )
+                .await
+        }
+        (None, Some(append)) => {
+            transport
+                .dispatch((query_input.query_id, append), query_input.input_stream)
+                .await
+        }
+        (None, None) => {
+            transport
+                .dispatch(
+                    (RouteId::QueryInput, query_input.query_id),
+                    query_input.input_stream,
+                )
+                .await
+        }
     };
+    let _ = resp.map_err(|e| Error::application(input_error_status(&e), e))?;
+
+    Ok(())
+}
+
+async fn seal_handler(
+    transport: Extension<MpcHttpTransport>,
+    Path(query_id): Path<QueryId>,
+    Query(seal): Query<SealInput>,
+) -> Result<(), Error> {
     let _ = transport
-        .dispatch(
-            (RouteId::QueryInput, query_input.query_id),
-            query_input.input_stream,
-        )
+        .dispatch((query_id, seal), BodyStream::empty())
         .await
-        .map_err(|e| Error::application(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        .map_err(|e| Error::application(input_error_status(&e), e))?;
 
     Ok(())
 }
 
+/// Status of a failed upload or seal of a query input. Errors the report collector can fix are
+/// reported as client errors.
+fn input_error_status(e: &ApiError) -> StatusCode {
+    match e {
+        ApiError::QueryInput(QueryInputError::NoSuchQuery(_)) => StatusCode::NOT_FOUND,
+        ApiError::QueryInput(QueryInputError::StateError { .. }) => StatusCode::CONFLICT,
+        ApiError::QueryInput(
+            QueryInputError::UnsupportedQueryType(_)
+            | QueryInputError::Contribution(_)
+            | QueryInputError::Batch(_)
+            | QueryInputError::TooManyChannels(_),
+        )
+        | ApiError::BadRequest(_)
+        | ApiError::DeserializationFailure(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn delete_handler(
+    transport: Extension<MpcHttpTransport>,
+    Path(query_id): Path<QueryId>,
+) -> Result<Json<delete_input::ResponseBody>, Error> {
+    let req = delete_input::Request::new(query_id);
+    match transport.dispatch(req, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(delete_input::ResponseBody::from(resp))),
+        Err(e @ ApiError::DeleteInput(DeleteInputError::QueryInProgress { .. })) => {
+            Err(Error::application(StatusCode::CONFLICT, e))
+        }
+        Err(e) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
 pub fn router(transport: MpcHttpTransport) -> Router {
+    // Both routes share the path, so they must be in the same router.
     Router::new()
-        .route(http_serde::query::input::AXUM_PATH, post(handler))
+        .route(
+            http_serde::query::input::AXUM_PATH,
+            post(handler).delete(delete_handler),
+        )
+        .route(seal_input::AXUM_PATH, post(seal_handler))
         .layer(Extension(transport))
 }
 
@@ -44,13 +140,19 @@ mod tests {
 
     use crate::{
         helpers::{
-            make_owned_handler, query::QueryInput, routing::RouteId, BytesStream, HelperResponse,
+            make_owned_handler,
+            query::{AppendInput, QueryInput, QuerySize, SealInput},
+            routing::{Addr, RouteId},
+            BodyStream, BytesStream, HelperIdentity, HelperResponse,
         },
         net::{
             http_serde,
-            server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
+            server::handlers::query::test_helpers::{
+                assert_fails_with, assert_fails_with_handler, assert_success_with,
+            },
         },
         protocol::QueryId,
+        query::{BatchError, DeleteInputError, InputDeleted, QueryInputError, QueryStatus},
     };
 
     #[tokio::test(flavor = "multi_thread")]
@@ -60,6 +162,7 @@ mod tests {
         let req = http_serde::query::input::Request::new(QueryInput {
             query_id: expected_query_id,
             input_stream: expected_input.to_vec().into(),
+            contribution: None,
         });
         let req_handler = make_owned_handler(move |addr, data| async move {
             let RouteId::QueryInput = addr.route else {
@@ -82,6 +185,106 @@ mod tests {
         assert_success_with(req, req_handler).await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn append() {
+        let req = http_serde::query::input::Request::appending(
+            QueryInput {
+                query_id: QueryId,
+                input_stream: vec![4; 4].into(),
+                contribution: None,
+            },
+            2,
+        );
+        let req_handler = make_owned_handler(move |addr: Addr<HelperIdentity>, _| async move {
+            let RouteId::AppendQueryInput = addr.route else {
+                panic!("unexpected call: {addr:?}");
+            };
+            assert_eq!(addr.query_id, Some(QueryId));
+            assert_eq!(
+                AppendInput { reports: 2 },
+                addr.into::<AppendInput>().unwrap()
+            );
+            Ok(HelperResponse::ok())
+        });
+        let req = req
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_success_with(req, req_handler).await;
+    }
+
+    #[tokio::test]
+    async fn seal() {
+        let count = QuerySize::try_from(10_u32).unwrap();
+        let req_handler = make_owned_handler(move |addr: Addr<HelperIdentity>, _| async move {
+            let RouteId::SealQueryInput = addr.route else {
+                panic!("unexpected call: {addr:?}");
+            };
+            assert_eq!(addr.query_id, Some(QueryId));
+            assert_eq!(SealInput { count }, addr.into::<SealInput>().unwrap());
+            Ok(HelperResponse::ok())
+        });
+        let req = http_serde::query::seal_input::Request::new(QueryId, count)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_success_with(req, req_handler).await;
+    }
+
+    #[tokio::test]
+    async fn append_requires_reports() {
+        let uri = format!(
+            "http://localhost{}/{}/input?append=true",
+            http_serde::query::BASE_AXUM_PATH,
+            QueryId.as_ref()
+        );
+        let req = hyper::Request::post(uri)
+            .body(Body::from(vec![4; 4]))
+            .unwrap();
+        assert_fails_with(req, StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn seal_count_mismatch() {
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                Err(QueryInputError::from(BatchError::CountMismatch {
+                    declared: 2,
+                    sealed: 3,
+                })
+                .into())
+            },
+        );
+        let count = QuerySize::try_from(3_u32).unwrap();
+        let req = http_serde::query::seal_input::Request::new(QueryId, count)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_fails_with_handler(req, handler, StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn seal_unknown_query() {
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                Err(QueryInputError::NoSuchQuery(QueryId).into())
+            },
+        );
+        let count = QuerySize::try_from(3_u32).unwrap();
+        let req = http_serde::query::seal_input::Request::new(QueryId, count)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_fails_with_handler(req, handler, StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn seal_rejects_empty_input() {
+        let uri = format!(
+            "http://localhost{}/{}/input/seal?count=0",
+            http_serde::query::BASE_AXUM_PATH,
+            QueryId.as_ref()
+        );
+        let req = hyper::Request::post(uri).body(Body::empty()).unwrap();
+        assert_fails_with(req, StatusCode::BAD_REQUEST).await;
+    }
+
     struct OverrideReq {
         query_id: String,
         input_stream: Vec<u8>,
@@ -109,6 +312,56 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn calls_delete() {
+        let expected = InputDeleted {
+            query_id: QueryId,
+            stored: true,
+            quarantine: true,
+            prf_cache: false,
+        };
+        let handler = make_owned_handler({
+            let expected = expected.clone();
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| {
+                let expected = expected.clone();
+                async move {
+                    let RouteId::DeleteQueryInput = addr.route else {
+                        panic!("unexpected call: {addr:?}");
+                    };
+                    assert_eq!(addr.query_id, Some(QueryId));
+                    Ok(HelperResponse::from(expected))
+                }
+            }
+        });
+
+        let req = http_serde::query::delete_input::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        let body = assert_success_with(req, handler).await;
+        assert_eq!(
+            expected,
+            serde_json::from_slice::<InputDeleted>(&body).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_in_progress() {
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                Err(DeleteInputError::QueryInProgress {
+                    query_id: QueryId,
+                    status: QueryStatus::Running,
+                }
+                .into())
+            },
+        );
+
+        let req = http_serde::query::delete_input::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_fails_with_handler(req, handler, StatusCode::CONFLICT).await;
+    }
+
     #[tokio::test]
     async fn malformed_query_id() {
         let req = OverrideReq {
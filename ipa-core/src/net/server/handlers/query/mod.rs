@@ -1,11 +1,14 @@
 mod create;
 mod input;
 mod kill;
+mod peer_unavailable;
 mod prepare;
 mod results;
 mod status;
 mod status_match;
 mod step;
+mod template;
+mod validate;
 
 use std::marker::PhantomData;
 
@@ -36,6 +39,8 @@ use crate::{
 pub fn query_router(transport: MpcHttpTransport) -> Router {
     Router::new()
         .merge(create::router(transport.clone()))
+        .merge(template::router(transport.clone()))
+        .merge(validate::router(transport.clone()))
         .merge(input::router(transport.clone()))
         .merge(status::router(transport.clone()))
         .merge(kill::router(transport.clone()))
@@ -52,6 +57,7 @@ pub fn query_router(transport: MpcHttpTransport) -> Router {
 pub fn h2h_router(transport: Arc<HttpTransport<Helper>>) -> Router {
     Router::new()
         .merge(step::router(Arc::clone(&transport)))
+        .merge(peer_unavailable::router(Arc::clone(&transport)))
         .merge(prepare::router(transport))
         .layer(layer_fn(HelperAuthentication::<_, Helper>::new))
 }
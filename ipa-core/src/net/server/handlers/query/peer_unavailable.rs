@@ -0,0 +1,46 @@
+use axum::{
+    extract::{Path, Query},
+    routing::post,
+    Extension, Router,
+};
+use hyper::StatusCode;
+
+use crate::{
+    helpers::{query::PeerUnavailable, ApiError, BodyStream},
+    net::{
+        http_serde::query::peer_unavailable::{self, RoleQueryString},
+        server::{ClientIdentity, Error},
+        Error::QueryIdNotFound,
+        Helper, HttpTransport,
+    },
+    protocol::QueryId,
+    query::QueryKillStatus,
+    sync::Arc,
+};
+
+/// Called by a peer helper that can't reach the third helper of a query, so that this helper
+/// fails the query instead of waiting on it.
+async fn handler(
+    transport: Extension<Arc<HttpTransport<Helper>>>,
+    _: Extension<ClientIdentity<Helper>>, // require that client is an authenticated helper
+    Path(query_id): Path<QueryId>,
+    Query(RoleQueryString { role }): Query<RoleQueryString>,
+) -> Result<(), Error> {
+    let req = PeerUnavailable { query_id, role };
+    match Arc::clone(&transport)
+        .dispatch(req, BodyStream::empty())
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(ApiError::QueryKill(QueryKillStatus::NoSuchQuery(query_id))) => Err(
+            Error::application(StatusCode::NOT_FOUND, QueryIdNotFound(query_id)),
+        ),
+        Err(e) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+pub fn router(transport: Arc<HttpTransport<Helper>>) -> Router {
+    Router::new()
+        .route(peer_unavailable::AXUM_PATH, post(handler))
+        .layer(Extension(transport))
+}
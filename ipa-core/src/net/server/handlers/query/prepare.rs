@@ -8,7 +8,7 @@ use crate::{
     net::{
         http_serde::{
             self,
-            query::{prepare::RequestBody, QueryConfigQueryParams},
+            query::{prepare::RequestBody, ValidQueryConfigQueryParams},
         },
         server::ClientIdentity,
         transport::HttpTransport,
@@ -24,13 +24,21 @@ async fn handler<F: ConnectionFlavor>(
     transport: Extension<Arc<HttpTransport<F>>>,
     _: Extension<ClientIdentity<F::Identity>>, // require that client is an authenticated helper
     Path(query_id): Path<QueryId>,
-    QueryConfigQueryParams(config): QueryConfigQueryParams,
-    Json(RequestBody { roles }): Json<RequestBody>,
+    ValidQueryConfigQueryParams(config): ValidQueryConfigQueryParams,
+    Json(RequestBody {
+        roles,
+        protocol_version,
+        pull_receivers,
+        stage_concurrency,
+    }): Json<RequestBody>,
 ) -> Result<(), Error> {
     let data = PrepareQuery {
         query_id,
         config,
         roles,
+        protocol_version,
+        pull_receivers,
+        stage_concurrency,
     };
     let _ = Arc::clone(&transport)
         .dispatch(data, BodyStream::empty())
@@ -64,7 +72,7 @@ mod tests {
             make_owned_handler,
             query::{PrepareQuery, QueryConfig, QueryType::TestMultiply},
             routing::RouteId,
-            HelperIdentity, HelperResponse, RoleAssignment,
+            HelperIdentity, HelperResponse, RoleAssignment, StageConcurrency,
         },
         net::{
             http_serde,
@@ -76,7 +84,7 @@ mod tests {
             },
             APPLICATION_JSON,
         },
-        protocol::QueryId,
+        protocol::{ProtocolVersion, QueryId},
     };
 
     #[tokio::test]
@@ -90,6 +98,9 @@ mod tests {
                 query_id: QueryId,
                 config: QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
                 roles: RoleAssignment::new(HelperIdentity::make_three()),
+                protocol_version: ProtocolVersion::CURRENT,
+                pull_receivers: Vec::new(),
+                stage_concurrency: StageConcurrency::default(),
             };
             let actual_prepare_query = addr.into::<PrepareQuery>().unwrap();
             assert_eq!(actual_prepare_query, expected_prepare_query);
@@ -123,7 +134,7 @@ mod tests {
         fn default() -> Self {
             Self {
                 client_id: Some(ClientIdentity(HelperIdentity::TWO)),
-                query_id: QueryId.as_ref().to_string(),
+                query_id: QueryId.to_string(),
                 field_type: format!("{:?}", FieldType::Fp31),
                 size: Some(1),
                 roles: OverrideReqRoles {
@@ -2,13 +2,17 @@ use axum::{extract::Path, routing::get, Extension, Json, Router};
 use hyper::StatusCode;
 
 use crate::{
-    helpers::BodyStream,
+    helpers::{ApiError, BodyStream},
     net::{
-        http_serde::query::status::{self, Request},
+        http_serde::query::{
+            prune,
+            status::{self, Request},
+        },
         server::Error,
         transport::MpcHttpTransport,
     },
     protocol::QueryId,
+    query::PruneQueryError,
 };
 
 async fn handler(
@@ -22,9 +26,27 @@ async fn handler(
     }
 }
 
+async fn prune_handler(
+    transport: Extension<MpcHttpTransport>,
+    Path(query_id): Path<QueryId>,
+) -> Result<Json<prune::ResponseBody>, Error> {
+    let req = prune::Request::new(query_id);
+    match transport.dispatch(req, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(prune::ResponseBody::from(resp))),
+        Err(e @ ApiError::PruneQuery(PruneQueryError::NoSuchQuery(_))) => {
+            Err(Error::application(StatusCode::NOT_FOUND, e))
+        }
+        Err(e @ ApiError::PruneQuery(PruneQueryError::QueryInProgress { .. })) => {
+            Err(Error::application(StatusCode::CONFLICT, e))
+        }
+        Err(e) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
 pub fn router(transport: MpcHttpTransport) -> Router {
+    // Both routes share the path, so they must be in the same router.
     Router::new()
-        .route(status::AXUM_PATH, get(handler))
+        .route(status::AXUM_PATH, get(handler).delete(prune_handler))
         .layer(Extension(transport))
 }
 
@@ -44,10 +66,12 @@ mod tests {
         },
         net::{
             http_serde,
-            server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
+            server::handlers::query::test_helpers::{
+                assert_fails_with, assert_fails_with_handler, assert_success_with,
+            },
         },
         protocol::QueryId,
-        query::QueryStatus,
+        query::{InputDeleted, PruneQueryError, QueryPruned, QueryStatus},
     };
 
     #[tokio::test]
@@ -72,6 +96,72 @@ mod tests {
         assert_success_with(req, handler).await;
     }
 
+    #[tokio::test]
+    async fn prune() {
+        let expected = QueryPruned {
+            query_id: QueryId,
+            input: InputDeleted {
+                query_id: QueryId,
+                stored: true,
+                quarantine: false,
+                prf_cache: false,
+            },
+        };
+        let handler = make_owned_handler({
+            let expected = expected.clone();
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| {
+                let expected = expected.clone();
+                async move {
+                    let RouteId::PruneQuery = addr.route else {
+                        panic!("unexpected call: {addr:?}");
+                    };
+                    assert_eq!(addr.query_id, Some(QueryId));
+                    Ok(HelperResponse::from(expected))
+                }
+            }
+        });
+
+        let req = http_serde::query::prune::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        let body = assert_success_with(req, handler).await;
+        assert_eq!(
+            expected,
+            serde_json::from_slice::<QueryPruned>(&body).unwrap()
+        );
+    }
+
+    async fn prune_fails(err: fn() -> PruneQueryError, expected_status: StatusCode) {
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move { Err(err().into()) },
+        );
+        let req = http_serde::query::prune::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_fails_with_handler(req, handler, expected_status).await;
+    }
+
+    #[tokio::test]
+    async fn prune_missing() {
+        prune_fails(
+            || PruneQueryError::NoSuchQuery(QueryId),
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn prune_in_progress() {
+        prune_fails(
+            || PruneQueryError::QueryInProgress {
+                query_id: QueryId,
+                status: QueryStatus::Running,
+            },
+            StatusCode::CONFLICT,
+        )
+        .await;
+    }
+
     struct OverrideReq {
         query_id: String,
     }
@@ -1,31 +1,132 @@
-use axum::{extract::Path, routing::post, Extension, Router};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Path, Query},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Router,
+};
+use serde::{de::value::BorrowedStrDeserializer, Deserialize};
 
 use crate::{
-    helpers::BodyStream,
+    helpers::{frame, BodyStream},
     net::{
-        http_serde,
+        checksum, http_serde,
+        http_serde::query::step::STEP_HEADER,
         server::{ClientIdentity, Error},
         ConnectionFlavor, HttpTransport,
     },
-    protocol::{Gate, QueryId},
+    protocol::{Gate, ProtocolVersion, QueryId},
     sync::Arc,
 };
 
+/// Channel a step request is for. The step is taken from the end of the path if it is there,
+/// and from [`STEP_HEADER`] otherwise.
+struct StepTarget {
+    query_id: QueryId,
+    protocol_version: ProtocolVersion,
+    gate: Gate,
+}
+
+#[derive(Deserialize)]
+struct StepPathParams {
+    query_id: QueryId,
+    protocol_version: ProtocolVersion,
+    step: Option<Gate>,
+}
+
+impl StepTarget {
+    fn gate_from_header(req: &Parts) -> Result<Gate, Error> {
+        let header = req
+            .headers
+            .get(&STEP_HEADER)
+            .ok_or_else(|| Error::MissingHeader(STEP_HEADER.to_string()))?;
+        Gate::deserialize(BorrowedStrDeserializer::<serde::de::value::Error>::new(
+            header.to_str()?,
+        ))
+        .map_err(|e| Error::InvalidHeader(e.into()))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for StepTarget {
+    // Malformed paths are rejected the same way as by the `Path` extractor.
+    type Rejection = Response;
+
+    async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(StepPathParams {
+            query_id,
+            protocol_version,
+            step,
+        }) = Path::<StepPathParams>::from_request_parts(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let gate = match step {
+            Some(gate) => gate,
+            None => Self::gate_from_header(req).map_err(IntoResponse::into_response)?,
+        };
+        Ok(Self {
+            query_id,
+            protocol_version,
+            gate,
+        })
+    }
+}
+
 #[allow(clippy::unused_async)] // axum doesn't like synchronous handler
-#[tracing::instrument(level = "trace", "step", skip_all, fields(from = ?**from, gate = ?gate))]
+#[tracing::instrument(level = "trace", "step", skip_all, fields(from = ?**from, gate = ?target.gate))]
 async fn handler<F: ConnectionFlavor>(
     transport: Extension<Arc<HttpTransport<F>>>,
     from: Extension<ClientIdentity<F::Identity>>,
-    Path((query_id, gate)): Path<(QueryId, Gate)>,
+    target: StepTarget,
     body: BodyStream,
 ) -> Result<(), Error> {
-    transport.receive_stream(query_id, gate, **from, body);
-    Ok(())
+    transport.receive_stream(
+        target.query_id,
+        target.protocol_version,
+        target.gate,
+        **from,
+        body,
+    )
+}
+
+#[tracing::instrument(level = "trace", "pull", skip_all, fields(from = ?**from, gate = ?target.gate))]
+async fn pull_handler<F: ConnectionFlavor>(
+    transport: Extension<Arc<HttpTransport<F>>>,
+    from: Extension<ClientIdentity<F::Identity>>,
+    target: StepTarget,
+    Query(http_serde::query::step::PullQueryParams { from_offset }): Query<
+        http_serde::query::step::PullQueryParams,
+    >,
+) -> Result<Response, Error> {
+    let StepTarget {
+        query_id,
+        protocol_version,
+        gate,
+    } = target;
+    let chunk = transport
+        .pull_step(query_id, protocol_version, gate, **from, from_offset)
+        .await?;
+    Ok(match chunk {
+        Some(chunk) if protocol_version.has_batch_checksums() => {
+            let crc = frame::crc32(&chunk).to_string();
+            ([(checksum::BATCH_CHECKSUM_HEADER.clone(), crc)], chunk).into_response()
+        }
+        Some(chunk) => chunk.into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    })
 }
 
 pub fn router<F: ConnectionFlavor>(transport: Arc<HttpTransport<F>>) -> Router {
     Router::new()
-        .route(http_serde::query::step::AXUM_PATH, post(handler::<F>))
+        .route(
+            http_serde::query::step::AXUM_PATH,
+            post(handler::<F>).get(pull_handler::<F>),
+        )
+        .route(
+            http_serde::query::step::HEADER_AXUM_PATH,
+            post(handler::<F>).get(pull_handler::<F>),
+        )
         .layer(Extension(transport))
 }
 
@@ -45,7 +146,7 @@ mod tests {
             server::handlers::query::test_helpers::{assert_fails_with, MaybeExtensionExt},
             test::TestServer,
         },
-        protocol::{Gate, QueryId},
+        protocol::{Gate, ProtocolVersion, QueryId},
     };
 
     const DATA_LEN: usize = 3;
@@ -78,21 +179,28 @@ mod tests {
     struct OverrideReq {
         client_id: Option<ClientIdentity<HelperIdentity>>,
         query_id: String,
+        namespace: String,
+        /// Put in [`STEP_HEADER`] if set, at the end of the path otherwise.
+        step_header: Option<String>,
         gate: Gate,
         payload: Vec<u8>,
     }
 
     impl From<OverrideReq> for hyper::Request<Body> {
         fn from(val: OverrideReq) -> Self {
-            let uri = format!(
+            let path = format!(
                 "http://localhost{}/{}/step/{}",
                 http_serde::query::BASE_AXUM_PATH,
                 val.query_id,
-                val.gate.as_ref()
+                val.namespace,
             );
-            hyper::Request::post(uri)
-                .maybe_extension(val.client_id)
-                .body(Body::from(val.payload))
+            let req = match val.step_header {
+                Some(step) => hyper::Request::post(path).header(STEP_HEADER.clone(), step),
+                None => hyper::Request::post(format!("{path}/{}", val.gate.as_ref())),
+            };
+            // Step data of the current protocol version is framed with checksums.
+            req.maybe_extension(val.client_id)
+                .body(Body::from(frame::encode(&val.payload)))
                 .unwrap()
         }
     }
@@ -102,12 +210,53 @@ mod tests {
             Self {
                 client_id: Some(ClientIdentity(HelperIdentity::ONE)),
                 query_id: QueryId.as_ref().to_string(),
+                namespace: ProtocolVersion::CURRENT.namespace(),
+                step_header: None,
                 gate: Gate::default().narrow("test"),
                 payload: vec![1; DATA_LEN * MESSAGE_PAYLOAD_SIZE_BYTES],
             }
         }
     }
 
+    #[tokio::test]
+    async fn step_in_header() {
+        let gate = Gate::default().narrow("test");
+        let payload = vec![213; DATA_LEN * MESSAGE_PAYLOAD_SIZE_BYTES];
+        let req = OverrideReq {
+            step_header: Some(gate.as_ref().to_string()),
+            payload: payload.clone(),
+            ..Default::default()
+        };
+        let test_server = TestServer::builder().build().await;
+
+        test_server.server.handle_req(req.into()).await;
+
+        let mut stream = test_server
+            .transport
+            .receive(HelperIdentity::ONE, &(QueryId, gate))
+            .into_bytes_stream();
+
+        assert_eq!(
+            poll_immediate(&mut stream).next().await,
+            Some(Poll::Ready(payload))
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_step_header() {
+        let uri = format!(
+            "http://localhost{}/{}/step/{}",
+            http_serde::query::BASE_AXUM_PATH,
+            QueryId.as_ref(),
+            ProtocolVersion::CURRENT.namespace(),
+        );
+        let req = hyper::Request::post(uri)
+            .extension(ClientIdentity(HelperIdentity::ONE))
+            .body(Body::from(frame::encode(&[1; DATA_LEN])))
+            .unwrap();
+        assert_fails_with(req, StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
     #[tokio::test]
     async fn malformed_query_id_fails() {
         let req = OverrideReq {
@@ -117,6 +266,102 @@ mod tests {
         assert_fails_with(req.into(), StatusCode::BAD_REQUEST).await;
     }
 
+    #[tokio::test]
+    async fn unsupported_protocol_version() {
+        let req = OverrideReq {
+            namespace: ProtocolVersion::new(4).namespace(),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn malformed_protocol_version() {
+        let req = OverrideReq {
+            namespace: "ipa/five".into(),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn unknown_namespace() {
+        let req = OverrideReq {
+            namespace: "other/v5".into(),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn version_mismatch() {
+        let test_server = TestServer::builder().build().await;
+        test_server
+            .transport
+            .bind_protocol_version(QueryId, ProtocolVersion::V5);
+
+        let resp = test_server
+            .server
+            .handle_req(OverrideReq::default().into())
+            .await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+    }
+
+    #[tokio::test]
+    async fn push_rejected_in_pull_mode() {
+        let test_server = TestServer::builder().build().await;
+        test_server
+            .transport
+            .set_pull_receivers(QueryId, &[test_server.transport.identity]);
+
+        let resp = test_server
+            .server
+            .handle_req(OverrideReq::default().into())
+            .await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+    }
+
+    #[tokio::test]
+    async fn pull_offset_out_of_range() {
+        let test_server = TestServer::builder().build().await;
+        let gate = Gate::default().narrow("test");
+        let serve = test_server.transport.pull_sources.serve(
+            (QueryId, HelperIdentity::ONE, gate.clone()),
+            futures::stream::iter([vec![1; DATA_LEN], vec![2; DATA_LEN]]),
+        );
+        let pull = |from_offset| {
+            let uri = format!(
+                "http://localhost{}/{}/step/{}/{}?from_offset={from_offset}",
+                http_serde::query::BASE_AXUM_PATH,
+                QueryId.as_ref(),
+                ProtocolVersion::CURRENT.namespace(),
+                gate.as_ref()
+            );
+            let req = hyper::Request::get(uri)
+                .extension(ClientIdentity(HelperIdentity::ONE))
+                .body(Body::empty())
+                .unwrap();
+            test_server.server.handle_req(req)
+        };
+        let requests = async {
+            let resp = pull(0).await;
+            assert_eq!(StatusCode::OK, resp.status());
+            assert_eq!(
+                frame::crc32(&[1; DATA_LEN]).to_string(),
+                resp.headers()[&checksum::BATCH_CHECKSUM_HEADER]
+                    .to_str()
+                    .unwrap()
+            );
+            assert_eq!(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                pull(DATA_LEN + 1).await.status()
+            );
+            assert_eq!(StatusCode::OK, pull(DATA_LEN).await.status());
+            assert_eq!(StatusCode::NO_CONTENT, pull(2 * DATA_LEN).await.status());
+        };
+        futures::join!(serve, requests);
+    }
+
     #[tokio::test]
     async fn auth_required() {
         let req = OverrideReq {
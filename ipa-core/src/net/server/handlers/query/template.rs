@@ -0,0 +1,153 @@
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query},
+    routing::post,
+    Extension, Json, Router,
+};
+use hyper::StatusCode;
+
+use crate::{
+    helpers::{
+        query::{CreateFromTemplate, TemplateOverrides},
+        ApiError, BodyStream,
+    },
+    net::{
+        http_serde, server::handlers::templates::template_error_status,
+        transport::MpcHttpTransport, Error,
+    },
+    query::NewQueryError,
+};
+
+/// Creates a query from one of the templates stored on this helper. Only the fields in
+/// [`TemplateOverrides`] are taken from the request.
+async fn handler(
+    transport: Extension<MpcHttpTransport>,
+    Path(template_id): Path<String>,
+    overrides: Result<Query<TemplateOverrides>, QueryRejection>,
+) -> Result<Json<http_serde::query::create::ResponseBody>, Error> {
+    let Query(overrides) = overrides?;
+    let req = CreateFromTemplate {
+        template_id,
+        overrides,
+    };
+    match transport.dispatch(req, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(resp.try_into()?)),
+        Err(err @ ApiError::NewQuery(NewQueryError::State { .. })) => {
+            Err(Error::application(StatusCode::CONFLICT, err))
+        }
+        Err(err @ ApiError::NewQuery(NewQueryError::HelperUnavailable(_))) => {
+            Err(Error::application(StatusCode::SERVICE_UNAVAILABLE, err))
+        }
+        Err(
+            err @ ApiError::NewQuery(
+                NewQueryError::UnsupportedQueryType(_) | NewQueryError::Invalid(_),
+            ),
+        ) => Err(Error::application(StatusCode::BAD_REQUEST, err)),
+        Err(ApiError::NewQuery(NewQueryError::Template(err))) => {
+            Err(Error::application(template_error_status(&err), err))
+        }
+        Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+pub fn router(transport: MpcHttpTransport) -> Router {
+    Router::new()
+        .route(
+            http_serde::query::create_from_template::AXUM_PATH,
+            post(handler),
+        )
+        .layer(Extension(transport))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::body::Body;
+    use hyper::{
+        http::uri::{Authority, Scheme},
+        StatusCode,
+    };
+
+    use crate::{
+        ff::FieldType,
+        helpers::{
+            make_owned_handler,
+            query::{
+                CreateFromTemplate, PrepareQuery, QueryConfig, QuerySize, QueryType, TemplateError,
+                TemplateOverrides,
+            },
+            routing::RouteId,
+            ApiError, HelperIdentity, HelperResponse, RoleAssignment, StageConcurrency,
+        },
+        net::{
+            http_serde,
+            server::handlers::query::test_helpers::{
+                assert_fails_with, assert_fails_with_handler, assert_success_with,
+            },
+        },
+        protocol::{ProtocolVersion, QueryId},
+        query::NewQueryError,
+        report::SiteDomainHash,
+    };
+
+    fn overrides() -> TemplateOverrides {
+        TemplateOverrides {
+            max_breakdown_key: Some(16),
+            site_domain_hash: Some(SiteDomainHash::from_domain("example.com").unwrap()),
+            ..TemplateOverrides::new(QuerySize::try_from(10).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn create_from_template() {
+        let expected = overrides();
+        let req =
+            http_serde::query::create_from_template::Request::new("ipa".to_string(), expected)
+                .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+                .unwrap();
+        let handler = make_owned_handler(move |addr, _| async move {
+            let RouteId::ReceiveQueryFromTemplate = addr.route else {
+                panic!("unexpected call");
+            };
+
+            let req: CreateFromTemplate = addr.into().unwrap();
+            assert_eq!("ipa", req.template_id);
+            assert_eq!(expected, req.overrides);
+            Ok(HelperResponse::from(PrepareQuery {
+                query_id: QueryId,
+                config: QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 10).unwrap(),
+                roles: RoleAssignment::new(HelperIdentity::make_three()),
+                protocol_version: ProtocolVersion::CURRENT,
+                pull_receivers: Vec::new(),
+                stage_concurrency: StageConcurrency::default(),
+            }))
+        });
+        let resp = assert_success_with(req, handler).await;
+        let resp: http_serde::query::create::ResponseBody = serde_json::from_slice(&resp).unwrap();
+        assert_eq!(QueryId, resp.query_id);
+    }
+
+    #[tokio::test]
+    async fn unknown_template() {
+        let req = http_serde::query::create_from_template::Request::new(
+            "missing".to_string(),
+            overrides(),
+        )
+        .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+        .unwrap();
+        let handler = make_owned_handler(move |_, _| async move {
+            Err(ApiError::NewQuery(NewQueryError::Template(
+                TemplateError::NotFound("missing".to_string()),
+            )))
+        });
+        assert_fails_with_handler(req, handler, StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn missing_size() {
+        let uri = format!(
+            "http://localhost{}/template/ipa?max_breakdown_key=4",
+            http_serde::query::BASE_AXUM_PATH,
+        );
+        let req = hyper::Request::post(uri).body(Body::empty()).unwrap();
+        assert_fails_with(req, StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+}
@@ -0,0 +1,102 @@
+use axum::{routing::post, Extension, Json, Router};
+use hyper::StatusCode;
+
+use crate::{
+    helpers::{
+        query::{ValidateQuery, ValidationReport},
+        BodyStream,
+    },
+    net::{
+        http_serde::{self, query::QueryConfigQueryParams},
+        transport::MpcHttpTransport,
+        Error,
+    },
+};
+
+/// Checks the query configuration carried by the request without creating a query. Problems
+/// with the configuration are reported in the response body, rather than as an error status.
+async fn handler(
+    transport: Extension<MpcHttpTransport>,
+    QueryConfigQueryParams(query_config): QueryConfigQueryParams,
+) -> Result<Json<ValidationReport>, Error> {
+    match transport
+        .dispatch(ValidateQuery(query_config), BodyStream::empty())
+        .await
+    {
+        Ok(resp) => Ok(Json(resp.try_into_owned()?)),
+        Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+pub fn router(transport: MpcHttpTransport) -> Router {
+    Router::new()
+        .route(http_serde::query::validate::AXUM_PATH, post(handler))
+        .layer(Extension(transport))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::body::Body;
+    use hyper::{
+        http::uri::{Authority, Scheme},
+        StatusCode,
+    };
+
+    use crate::{
+        ff::FieldType,
+        helpers::{
+            make_owned_handler,
+            query::{IpaQueryConfig, QueryConfig, QueryType, ValidationReport},
+            routing::RouteId,
+            HelperResponse,
+        },
+        net::{
+            http_serde,
+            server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
+        },
+    };
+
+    #[tokio::test]
+    async fn validate_test() {
+        let expected_config = QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+            FieldType::Fp32BitPrime,
+            10,
+        )
+        .unwrap();
+        let req = http_serde::query::validate::Request::new(expected_config)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        let handler = make_owned_handler(move |addr, _| async move {
+            let RouteId::ValidateQuery = addr.route else {
+                panic!("unexpected call");
+            };
+
+            let query_config: QueryConfig = addr.into().unwrap();
+            assert_eq!(query_config, expected_config);
+            let mut report = ValidationReport::default();
+            report.push("epsilon", "too small");
+            Ok(HelperResponse::from(report))
+        });
+        let resp = assert_success_with(req, handler).await;
+        let report: ValidationReport = serde_json::from_slice(&resp).unwrap();
+        assert_eq!(
+            vec!["epsilon"],
+            report
+                .problems
+                .iter()
+                .map(|p| p.parameter.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_query_type() {
+        let uri = format!(
+            "http://localhost{}/validate?size=1&field_type=Fp31&query_type=not-a-type",
+            http_serde::query::BASE_AXUM_PATH,
+        );
+        let req = hyper::Request::post(uri).body(Body::empty()).unwrap();
+        assert_fails_with(req, StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+}
@@ -0,0 +1,228 @@
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Extension, Json, Router,
+};
+use futures_util::{
+    future::{ready, Either, Ready},
+    FutureExt,
+};
+use hyper::{header::AUTHORIZATION, Request, StatusCode};
+use tower::{layer::layer_fn, Service};
+
+use crate::{
+    config::AdminToken,
+    helpers::{
+        query::{QueryTemplate, TemplateCommand, TemplateError, TemplateList},
+        ApiError, BodyStream,
+    },
+    net::{http_serde, transport::MpcHttpTransport, Error},
+};
+
+/// Status code reported to clients for template errors.
+pub fn template_error_status(err: &TemplateError) -> StatusCode {
+    match err {
+        TemplateError::NotFound(_) => StatusCode::NOT_FOUND,
+        TemplateError::InvalidId(_)
+        | TemplateError::Override { .. }
+        | TemplateError::Invalid { .. } => StatusCode::BAD_REQUEST,
+        TemplateError::Required => StatusCode::FORBIDDEN,
+        TemplateError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn execute(
+    transport: &MpcHttpTransport,
+    command: TemplateCommand,
+) -> Result<Json<TemplateList>, Error> {
+    match transport.dispatch(command, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(resp.try_into_owned()?)),
+        Err(ApiError::QueryTemplate(err)) => {
+            Err(Error::application(template_error_status(&err), err))
+        }
+        Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+async fn list(transport: Extension<MpcHttpTransport>) -> Result<Json<TemplateList>, Error> {
+    execute(&transport, TemplateCommand::List).await
+}
+
+async fn put_template(
+    transport: Extension<MpcHttpTransport>,
+    Path(id): Path<String>,
+    Json(template): Json<QueryTemplate>,
+) -> Result<Json<TemplateList>, Error> {
+    execute(&transport, TemplateCommand::Put { id, template }).await
+}
+
+async fn remove(
+    transport: Extension<MpcHttpTransport>,
+    Path(id): Path<String>,
+) -> Result<Json<TemplateList>, Error> {
+    execute(&transport, TemplateCommand::Remove { id }).await
+}
+
+/// Construct router for query template management. All these APIs require the caller to present
+/// `admin_token`. If it is not set, template management is disabled.
+pub fn router(transport: MpcHttpTransport, admin_token: Option<AdminToken>) -> Router {
+    Router::new()
+        .route(http_serde::templates::AXUM_PATH, get(list))
+        .route(
+            http_serde::templates::ITEM_AXUM_PATH,
+            put(put_template).delete(remove),
+        )
+        .layer(Extension(transport))
+        .layer(layer_fn(move |inner| {
+            AdminAuthentication::new(inner, admin_token.clone())
+        }))
+}
+
+/// Rejects requests that don't carry the admin token in the `Authorization` header, as
+/// `Bearer <token>`. Returns HTTP 401 Unauthorized if the token is missing or wrong, and HTTP 403
+/// Forbidden if this helper has no admin token configured.
+#[derive(Clone)]
+pub struct AdminAuthentication<S> {
+    inner: S,
+    token: Option<AdminToken>,
+}
+
+impl<S> AdminAuthentication<S> {
+    pub fn new(inner: S, token: Option<AdminToken>) -> Self {
+        Self { inner, token }
+    }
+}
+
+impl<B, S> Service<Request<B>> for AdminAuthentication<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<Response, S::Error>>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let Some(token) = &self.token else {
+            return ready(Ok((
+                StatusCode::FORBIDDEN,
+                "Admin APIs are disabled on this helper",
+            )
+                .into_response()))
+            .right_future();
+        };
+        let authorized = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|presented| token.matches(presented));
+        if authorized {
+            self.inner.call(req).left_future()
+        } else {
+            ready(Ok((
+                StatusCode::UNAUTHORIZED,
+                "This API requires a helper administrator token",
+            )
+                .into_response()))
+            .right_future()
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use axum::{body::Body, routing::get, Router};
+    use hyper::{
+        header::AUTHORIZATION,
+        http::uri::{Authority, Scheme},
+        Request, StatusCode,
+    };
+    use tower::{layer::layer_fn, ServiceExt};
+
+    use super::AdminAuthentication;
+    use crate::{
+        config::AdminToken,
+        ff::FieldType,
+        helpers::{
+            make_owned_handler,
+            query::{QueryTemplate, QueryType, TemplateCommand, TemplateList},
+            routing::RouteId,
+            HelperResponse,
+        },
+        net::{http_serde, server::handlers::query::test_helpers::assert_fails_with_handler},
+    };
+
+    async fn call(token: Option<&str>, authorization: Option<&str>) -> StatusCode {
+        let token = token.map(|t| AdminToken::new(t.to_string()));
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(layer_fn(move |inner| AdminAuthentication {
+                inner,
+                token: token.clone(),
+            }));
+        let mut req = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            req = req.header(AUTHORIZATION, authorization);
+        }
+        router
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn admin_authentication() {
+        assert_eq!(
+            StatusCode::OK,
+            call(Some("secret"), Some("Bearer secret")).await
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            call(Some("secret"), Some("Bearer wrong")).await
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            call(Some("secret"), Some("secret")).await
+        );
+        assert_eq!(StatusCode::UNAUTHORIZED, call(Some("secret"), None).await);
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            call(None, Some("Bearer secret")).await
+        );
+    }
+
+    /// Test servers have no admin token, so the request never reaches the handler.
+    #[tokio::test]
+    async fn disabled_without_token() {
+        let req = http_serde::templates::Request::new(
+            "secret".to_string(),
+            TemplateCommand::Put {
+                id: "multiply".to_string(),
+                template: QueryTemplate {
+                    field_type: FieldType::Fp31,
+                    query_type: QueryType::TestMultiply,
+                },
+            },
+        )
+        .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+        .unwrap();
+        let handler = make_owned_handler(move |addr, _| async move {
+            let RouteId::QueryTemplates = addr.route else {
+                panic!("unexpected call");
+            };
+            Ok(HelperResponse::from(TemplateList(BTreeMap::new())))
+        });
+        assert_fails_with_handler(req, handler, StatusCode::FORBIDDEN).await;
+    }
+}
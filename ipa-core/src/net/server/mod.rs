@@ -16,7 +16,8 @@ use ::tokio::{
     net::TcpStream,
 };
 use axum::{
-    http::HeaderValue,
+    body::Body,
+    http::{HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     routing::IntoMakeService,
     Router,
@@ -48,11 +49,13 @@ use crate::{
     executor::{IpaJoinHandle, IpaRuntime},
     helpers::TransportIdentity,
     net::{
-        parse_certificate_and_private_key_bytes, server::config::HttpServerConfig,
-        ConnectionFlavor, Error, Helper, CRYPTO_PROVIDER,
+        parse_certificate_and_private_key_bytes,
+        server::config::HttpServerConfig,
+        signing::{content_digest, SeenNonces, SignedPayload},
+        ConnectionFlavor, Error, Helper, OpenFile, OpenFiles, RequestLimits, CRYPTO_PROVIDER,
     },
     sync::Arc,
-    telemetry::metrics::{web::RequestProtocolVersion, REQUESTS_RECEIVED},
+    telemetry::metrics::{web::RequestProtocolVersion, CONNECTIONS_REFUSED, REQUESTS_RECEIVED},
 };
 
 pub trait TracingSpanMaker: Send + Sync + Clone + 'static {
@@ -96,9 +99,13 @@ impl IpaHttpServer<Helper> {
         config: ServerConfig,
         network_config: NetworkConfig<Helper>,
     ) -> Self {
-        let router = handlers::mpc_router(MpcHttpTransport {
-            inner_transport: transport,
-        });
+        let router = handlers::mpc_router(
+            MpcHttpTransport {
+                inner_transport: transport,
+            },
+            network_config.clone(),
+            config.admin_token.clone(),
+        );
         IpaHttpServer {
             config,
             network_config,
@@ -160,35 +167,46 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
         #[cfg(not(test))]
         const BIND_ADDRESS: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
 
-        let svc = self.router.clone().layer(
-            TraceLayer::new_for_http()
-                .make_span_with(move |_request: &hyper::Request<_>| tracing.make_span())
-                .on_request(|request: &hyper::Request<_>, _: &Span| {
-                    counter!(RequestProtocolVersion::from(request.version()).as_str(), 1);
-                    counter!(REQUESTS_RECEIVED, 1);
-                }),
-        );
+        let limits = self.config.request_limits;
+        let svc = self
+            .router
+            .clone()
+            .layer(layer_fn(move |inner| {
+                EnforceRequestLimits::new(inner, limits)
+            }))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(move |_request: &hyper::Request<_>| tracing.make_span())
+                    .on_request(|request: &hyper::Request<_>, _: &Span| {
+                        counter!(RequestProtocolVersion::from(request.version()).as_str(), 1);
+                        counter!(REQUESTS_RECEIVED, 1);
+                    }),
+            );
         let handle = Handle::new();
 
         let task_handle = match (self.config.disable_https, listener) {
             (true, Some(listener)) => {
-                let svc = svc
-                    .layer(layer_fn(SetClientIdentityFromHeader::<_, F>::new))
-                    .into_make_service();
+                let svc = self.insecure_http_service(svc);
                 spawn_server(
                     runtime,
-                    axum_server::from_tcp(listener),
+                    axum_server::from_tcp(listener).map(|a| self.limit_open_files(a)),
                     handle.clone(),
+                    limits,
                     svc,
                 )
                 .await
             }
             (true, None) => {
                 let addr = SocketAddr::new(BIND_ADDRESS.into(), self.config.port.unwrap_or(0));
-                let svc = svc
-                    .layer(layer_fn(SetClientIdentityFromHeader::<_, F>::new))
-                    .into_make_service();
-                spawn_server(runtime, axum_server::bind(addr), handle.clone(), svc).await
+                let svc = self.insecure_http_service(svc);
+                spawn_server(
+                    runtime,
+                    axum_server::bind(addr).map(|a| self.limit_open_files(a)),
+                    handle.clone(),
+                    limits,
+                    svc,
+                )
+                .await
             }
             (false, Some(listener)) => {
                 let rustls_config = rustls_config(&self.config, self.network_config.vec_peers())
@@ -196,10 +214,11 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
                     .expect("invalid TLS configuration");
                 spawn_server(
                     runtime,
-                    axum_server::from_tcp_rustls(listener, rustls_config).map(|a| {
-                        ClientCertRecognizingAcceptor::new(a, self.network_config.clone())
-                    }),
+                    axum_server::from_tcp_rustls(listener, rustls_config)
+                        .map(|a| ClientCertRecognizingAcceptor::new(a, self.network_config.clone()))
+                        .map(|a| self.limit_open_files(a)),
                     handle.clone(),
+                    limits,
                     svc.into_make_service(),
                 )
                 .await
@@ -211,10 +230,11 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
                     .expect("invalid TLS configuration");
                 spawn_server(
                     runtime,
-                    axum_server::bind_rustls(addr, rustls_config).map(|a| {
-                        ClientCertRecognizingAcceptor::new(a, self.network_config.clone())
-                    }),
+                    axum_server::bind_rustls(addr, rustls_config)
+                        .map(|a| ClientCertRecognizingAcceptor::new(a, self.network_config.clone()))
+                        .map(|a| self.limit_open_files(a)),
                     handle.clone(),
+                    limits,
                     svc.into_make_service(),
                 )
                 .await
@@ -237,6 +257,35 @@ impl<F: ConnectionFlavor> IpaHttpServer<F> {
         );
         (bound_addr, task_handle)
     }
+
+    /// Wraps `acceptor` to count the connections of this server against the open files limit
+    /// of the server configuration, if there is one.
+    fn limit_open_files<A>(&self, acceptor: A) -> OpenFilesAcceptor<A> {
+        OpenFilesAcceptor {
+            inner: acceptor,
+            open_files: self.config.open_files.clone(),
+        }
+    }
+
+    /// Wraps `svc` to identify clients when HTTPS is disabled. If any of the peers share a signing
+    /// key with this helper, the claimed identity must be backed by a valid request signature.
+    fn insecure_http_service(&self, svc: Router) -> IntoMakeService<Router> {
+        if self.network_config.has_signing_keys() {
+            let network_config = Arc::new(self.network_config.clone());
+            let nonces = Arc::new(SeenNonces::default());
+            svc.layer(layer_fn(move |inner| {
+                SetClientIdentityFromSignature::new(
+                    inner,
+                    Arc::clone(&network_config),
+                    Arc::clone(&nonces),
+                )
+            }))
+            .into_make_service()
+        } else {
+            svc.layer(layer_fn(SetClientIdentityFromHeader::<_, F>::new))
+                .into_make_service()
+        }
+    }
 }
 
 /// Spawns a new server with the given configuration.
@@ -246,6 +295,7 @@ async fn spawn_server<A>(
     runtime: &IpaRuntime,
     mut server: Server<A>,
     handle: Handle,
+    limits: RequestLimits,
     svc: IntoMakeService<Router>,
 ) -> IpaJoinHandle<()>
 where
@@ -257,7 +307,7 @@ where
     runtime.spawn({
         async move {
             // Apply configuration
-            HttpServerConfig::apply(&mut server.http_builder().http2());
+            HttpServerConfig::apply(server.http_builder(), limits);
             // Start serving
             server
                 .handle(handle)
@@ -422,6 +472,83 @@ where
     }
 }
 
+/// `Accept`or that counts the connections of the server against [`OpenFiles`]. New connections
+/// past the limit wait for others to close. Those that still find no room are closed right away,
+/// so that they give their file descriptor back, rather than being reset once the OS runs out of
+/// them. Clients see the connection close and can retry.
+#[derive(Clone)]
+struct OpenFilesAcceptor<A> {
+    inner: A,
+    open_files: Option<OpenFiles>,
+}
+
+impl<A, I, S> Accept<I, S> for OpenFilesAcceptor<A>
+where
+    A: Accept<I, S> + Clone + Send + 'static,
+    A::Future: Send,
+    I: Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = A::Stream;
+    type Service = HoldOpenFile<A::Service>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        let open_files = self.open_files.clone();
+
+        Box::pin(async move {
+            let open_file = match open_files {
+                Some(open_files) => match open_files.acquire().await {
+                    Ok(open_file) => Some(Arc::new(open_file)),
+                    Err(e) => {
+                        counter!(CONNECTIONS_REFUSED, 1);
+                        error!("[OpenFilesAcceptor] closing a new connection: {e}");
+                        // Dropping `stream` closes the connection.
+                        return Err(io::Error::other(e));
+                    }
+                },
+                None => None,
+            };
+
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            Ok((
+                stream,
+                HoldOpenFile {
+                    inner: service,
+                    _open_file: open_file,
+                },
+            ))
+        })
+    }
+}
+
+/// Service wrapper that holds the open file counted for its connection, for as long as the
+/// connection is served.
+#[derive(Clone)]
+struct HoldOpenFile<S> {
+    inner: S,
+    _open_file: Option<Arc<OpenFile>>,
+}
+
+impl<B, S> Service<Request<B>> for HoldOpenFile<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
 #[derive(Clone)]
 struct SetClientIdentityFromCertificate<S, F: ConnectionFlavor> {
     inner: S,
@@ -450,6 +577,42 @@ where
     }
 }
 
+/// Service wrapper that refuses requests exceeding the [`RequestLimits`] of the server, before
+/// they are routed. Hyper is set up with the same limits, so that it does not buffer request
+/// heads much larger than them before they get here.
+#[derive(Clone)]
+struct EnforceRequestLimits<S> {
+    inner: S,
+    limits: RequestLimits,
+}
+
+impl<S> EnforceRequestLimits<S> {
+    fn new(inner: S, limits: RequestLimits) -> Self {
+        Self { inner, limits }
+    }
+}
+
+impl<B, S> Service<Request<B>> for EnforceRequestLimits<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<Response, S::Error>>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        match self.limits.check(&req) {
+            Ok(()) => self.inner.call(req).left_future(),
+            Err(err) => ready(Ok(err.into_response())).right_future(),
+        }
+    }
+}
+
 /// Service wrapper that gets a client helper identity from a header.
 ///
 /// Since this allows a client to claim any identity, it is completely
@@ -495,11 +658,216 @@ where
     }
 }
 
+/// Service wrapper that gets a client identity from a header, like
+/// [`SetClientIdentityFromHeader`], but only accepts it if the request is signed with the key
+/// shared with that peer.
+///
+/// Requests that don't claim an identity are passed on without one. If the signature covers a
+/// digest of the request payload, the payload is buffered and checked against it before the
+/// request is passed on. Streamed payloads are checked chunk by chunk as they are read, and
+/// reading them fails at the first chunk that does not match the signature.
+#[derive(Clone)]
+struct SetClientIdentityFromSignature<S, F: ConnectionFlavor> {
+    inner: S,
+    network_config: Arc<NetworkConfig<F>>,
+    nonces: Arc<SeenNonces>,
+}
+
+impl<S, F: ConnectionFlavor> SetClientIdentityFromSignature<S, F> {
+    /// Upper bound on the size of a payload covered by the signature. Only small control requests
+    /// are signed that way; MPC step data is streamed and is not buffered.
+    const MAX_SIGNED_PAYLOAD_LEN: usize = 1 << 20;
+
+    fn new(inner: S, network_config: Arc<NetworkConfig<F>>, nonces: Arc<SeenNonces>) -> Self {
+        Self {
+            inner,
+            network_config,
+            nonces,
+        }
+    }
+
+    async fn authenticate(
+        network_config: &NetworkConfig<F>,
+        nonces: &SeenNonces,
+        req: Request<Body>,
+    ) -> Result<Request<Body>, Error> {
+        let Some(header_value) = req.headers().get(F::identity_header()) else {
+            return Ok(req);
+        };
+        let id = ClientIdentity::<F::Identity>::try_from(header_value)?;
+        let key = network_config.signing_key(*id).ok_or_else(|| {
+            Error::application(
+                StatusCode::UNAUTHORIZED,
+                format!("no signing key is configured for {}", id.as_str()),
+            )
+        })?;
+        let signed_payload = key
+            .verify(&id.as_str(), &req, nonces)
+            .map_err(|e| Error::application(StatusCode::UNAUTHORIZED, e))?;
+        let (parts, body) = req.into_parts();
+        let body = match signed_payload {
+            SignedPayload::Digest(expected_digest) => {
+                let payload = axum::body::to_bytes(body, Self::MAX_SIGNED_PAYLOAD_LEN).await?;
+                if content_digest(&payload) != expected_digest {
+                    return Err(Error::InvalidSignature);
+                }
+                Body::from(payload)
+            }
+            SignedPayload::Chunks(verifier) => {
+                Body::from_stream(verifier.verify_stream(body.into_data_stream()))
+            }
+        };
+        let mut req = Request::from_parts(parts, body);
+        req.extensions_mut().insert(id);
+        Ok(req)
+    }
+}
+
+impl<S, F> Service<Request<Body>> for SetClientIdentityFromSignature<S, F>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    F: ConnectionFlavor,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // `self.inner` is the instance that was polled ready, so it must serve this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let network_config = Arc::clone(&self.network_config);
+        let nonces = Arc::clone(&self.nonces);
+        Box::pin(async move {
+            match Self::authenticate(&network_config, &nonces, req).await {
+                Ok(req) => inner.call(req).await,
+                Err(err) => Ok(err.into_response()),
+            }
+        })
+    }
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
-    use axum::http::HeaderValue;
+    use axum::{
+        body::Body,
+        http::{HeaderValue, StatusCode},
+        routing::post,
+        Extension, Router,
+    };
+    use bytes::Bytes;
+    use hyper::Request;
+    use tower::{layer::layer_fn, ServiceExt};
+
+    use super::SetClientIdentityFromSignature;
+    use crate::{
+        config::{ClientConfig, NetworkConfig, PeerConfig},
+        helpers::{HelperIdentity, TransportIdentity},
+        net::{server::ClientIdentity, ConnectionFlavor, Helper, PeerSigningKey},
+        sync::Arc,
+    };
+
+    /// Router that requires an authenticated client and where the key shared with helper `i`
+    /// is filled with byte `i`.
+    fn signed_router() -> Router {
+        let peers = (0..3_u8)
+            .map(|i| {
+                let mut peer = PeerConfig::new("http://localhost".parse().unwrap(), None);
+                peer.signing_key = Some(PeerSigningKey::new([i; PeerSigningKey::LEN]));
+                peer
+            })
+            .collect();
+        let network_config = Arc::new(NetworkConfig::new_mpc(peers, ClientConfig::default()));
+        Router::new()
+            .route(
+                "/",
+                post(
+                    |id: Extension<ClientIdentity<HelperIdentity>>, body: Bytes| async move {
+                        format!("{}:{}", id.as_str(), body.len())
+                    },
+                ),
+            )
+            .layer(layer_fn(move |inner| {
+                SetClientIdentityFromSignature::new(
+                    inner,
+                    Arc::clone(&network_config),
+                    Arc::default(),
+                )
+            }))
+    }
+
+    /// Request with `payload` signed with key `key`. `signed` is the payload covered by the
+    /// signature digest, or `None` if the payload is sent in signed chunks.
+    fn signed_request(key: u8, payload: &'static [u8], signed: Option<&[u8]>) -> Request<Body> {
+        let mut req = Request::post("/")
+            .header(Helper::identity_header(), "B")
+            .body(Body::from(payload))
+            .unwrap();
+        let signer = PeerSigningKey::new([key; PeerSigningKey::LEN]).sign("B", &mut req, signed);
+        match signer {
+            Some(signer) => {
+                req.map(|body| Body::from_stream(signer.sign_stream(body.into_data_stream())))
+            }
+            None => req,
+        }
+    }
+
+    async fn send(req: Request<Body>) -> StatusCode {
+        signed_router().oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn identify_from_signature() {
+        assert_eq!(
+            StatusCode::OK,
+            send(signed_request(1, b"foo", Some(b"foo"))).await
+        );
+        assert_eq!(StatusCode::OK, send(signed_request(1, b"foo", None)).await);
+    }
+
+    #[tokio::test]
+    async fn identify_from_signature_wrong_key() {
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            send(signed_request(2, b"foo", None)).await
+        );
+    }
 
-    use crate::{helpers::HelperIdentity, net::server::ClientIdentity};
+    #[tokio::test]
+    async fn identify_from_signature_tampered_payload() {
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            send(signed_request(1, b"bar", Some(b"foo"))).await
+        );
+    }
+
+    #[tokio::test]
+    async fn identify_from_signature_tampered_chunk() {
+        let (parts, body) = signed_request(1, b"foo", None).into_parts();
+        let mut payload = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .unwrap()
+            .to_vec();
+        payload[4] ^= 1;
+        let req = Request::from_parts(parts, Body::from(payload));
+        // The identity is accepted, reading the payload fails.
+        assert_ne!(StatusCode::OK, send(req).await);
+    }
+
+    #[tokio::test]
+    async fn identify_from_signature_unsigned() {
+        let req = Request::post("/")
+            .header(Helper::identity_header(), "B")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, send(req).await);
+    }
 
     #[test]
     fn identify_from_header_happy_case() {
@@ -519,7 +887,7 @@ mod tests {
 
 #[cfg(all(test, unit_test))]
 mod e2e_tests {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, time::Duration};
 
     use bytes::Buf;
     use http_body_util::BodyExt;
@@ -541,7 +909,7 @@ mod e2e_tests {
 
     use super::*;
     use crate::{
-        net::{http_serde, test::TestServer},
+        net::{http_serde, test::TestServer, DEFAULT_MAX_HEADER_BYTES, DEFAULT_MAX_URI_LEN},
         test_fixture::metrics::MetricsHandle,
     };
 
@@ -597,6 +965,47 @@ mod e2e_tests {
         assert_eq!(expected, resp_body);
     }
 
+    #[tokio::test]
+    async fn refuses_requests_past_limits() {
+        let TestServer { addr, .. } = TestServer::builder().disable_https().build().await;
+        let client = create_client();
+
+        let long_uri = format!("http://{addr}/{}", "a".repeat(DEFAULT_MAX_URI_LEN));
+        let req = hyper::Request::get(long_uri).body(Body::empty()).unwrap();
+        let resp = client.request(req).await.unwrap();
+        assert_eq!(StatusCode::URI_TOO_LONG, resp.status());
+
+        let req = hyper::Request::get(format!("http://{addr}/"))
+            .header("x-large", "a".repeat(DEFAULT_MAX_HEADER_BYTES))
+            .body(Body::empty())
+            .unwrap();
+        let resp = client.request(req).await.unwrap();
+        assert_eq!(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, resp.status());
+    }
+
+    #[tokio::test]
+    async fn closes_connections_past_open_files_limit() {
+        let open_files = OpenFiles::new(1).with_wait(Duration::from_millis(10));
+        let TestServer { addr, .. } = TestServer::builder()
+            .disable_https()
+            .with_open_files(open_files.clone())
+            .build()
+            .await;
+        let expected = expected_req(addr.to_string());
+
+        let first = create_client();
+        let req = http_req(&expected, uri::Scheme::HTTP, addr.to_string());
+        let resp = first.request(req).await.unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(1, open_files.in_use());
+
+        // a second client opens another connection, while the first one keeps its own open
+        let second = create_client();
+        let req = http_req(&expected, uri::Scheme::HTTP, addr.to_string());
+        second.request(req).await.unwrap_err();
+        assert_eq!(1, open_files.in_use());
+    }
+
     #[derive(Debug)]
     struct NoVerify;
 
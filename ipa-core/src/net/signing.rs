@@ -0,0 +1,632 @@
+//! Application-layer authentication for peer-to-peer traffic.
+//!
+//! Helpers normally authenticate each other with TLS client certificates. When TLS is terminated
+//! in front of the helper (e.g. at a load balancer), the certificate never reaches the helper. For
+//! such deployments, every pair of peers may share a secret key, configured in the network
+//! discovery file, and each request is signed with HMAC-SHA256 over the claimed identity, the
+//! HTTP method, the request target (which carries the query id and the step), the step header of
+//! protocol versions that don't put the step in the target, a SHA-256 digest of the payload, the
+//! time the request was signed and a random nonce.
+//!
+//! Requests signed more than [`MAX_SIGNATURE_AGE`] away from the clock of the receiving helper
+//! are rejected, and so are requests whose nonce was seen before. Signed requests therefore
+//! can't be replayed.
+//!
+//! Step payloads are streamed, so their digest is not known when the request is sent. For those
+//! requests, [`STREAMING_PAYLOAD`] is signed in place of the digest, and the payload is sent as
+//! a sequence of chunks that each carry a MAC, see [`ChunkSigner`]. The MAC of every chunk covers
+//! the request signature and the position of the chunk, so chunks can't be moved between requests
+//! or reordered, and the payload ends with an empty chunk, so it can't be cut short either.
+
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use hyper::{header::HeaderName, http::HeaderValue, Request};
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    helpers::frame::MAX_PAYLOAD_LEN,
+    net::{http_serde::query::step::STEP_HEADER, Error},
+    rand::{thread_rng, RngCore},
+    sync::Mutex,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub(crate) static HTTP_SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-ipa-signature");
+pub(crate) static HTTP_CONTENT_DIGEST_HEADER: HeaderName =
+    HeaderName::from_static("x-ipa-content-sha256");
+pub(crate) static HTTP_SIGNATURE_TIME_HEADER: HeaderName =
+    HeaderName::from_static("x-ipa-signature-time");
+pub(crate) static HTTP_SIGNATURE_NONCE_HEADER: HeaderName =
+    HeaderName::from_static("x-ipa-signature-nonce");
+
+/// Value of the content digest header for requests whose payload is sent in signed chunks.
+pub(crate) const STREAMING_PAYLOAD: &str = "STREAMING-HMAC-SHA256";
+
+/// Longest time, in seconds, between signing a request and receiving it, in either direction to
+/// allow for clock skew between helpers.
+pub const MAX_SIGNATURE_AGE: u64 = 300;
+
+const NONCE_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+/// Secret key shared between this helper and one of its peers. It is used both to sign requests
+/// sent to that peer and to verify requests received from it.
+///
+/// In `network.toml`, the key is specified as 64 hex characters.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PeerSigningKey([u8; Self::LEN]);
+
+impl Debug for PeerSigningKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PeerSigningKey(<redacted>)")
+    }
+}
+
+impl<'de> Deserialize<'de> for PeerSigningKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        let mut buf = [0_u8; Self::LEN];
+        hex::decode_to_slice(s.trim(), &mut buf).map_err(serde::de::Error::custom)?;
+        Ok(Self(buf))
+    }
+}
+
+/// Payload of a signed request, as seen by [`PeerSigningKey::verify`].
+#[derive(Debug)]
+pub(crate) enum SignedPayload {
+    /// The payload must match this hex-encoded SHA-256 digest.
+    Digest(String),
+    /// The payload is sent in chunks that must be checked with this verifier.
+    Chunks(ChunkVerifier),
+}
+
+impl PeerSigningKey {
+    pub const LEN: usize = 32;
+
+    #[must_use]
+    pub fn new(bytes: [u8; Self::LEN]) -> Self {
+        Self(bytes)
+    }
+
+    fn hmac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any size")
+    }
+
+    fn mac<B>(
+        &self,
+        identity: &str,
+        req: &Request<B>,
+        content_digest: &str,
+        signed_at: &str,
+        nonce: &str,
+    ) -> HmacSha256 {
+        let target = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path(), |pq| pq.as_str());
+        // Only requests that carry the step header sign it, so that signatures of peers that
+        // never send it stay the same.
+        let step = req.headers().get(&STEP_HEADER).map(HeaderValue::as_bytes);
+        let mut mac = self.hmac();
+        for field in [identity, req.method().as_str(), target]
+            .into_iter()
+            .map(str::as_bytes)
+            .chain(step)
+            .chain([content_digest, signed_at, nonce].map(str::as_bytes))
+        {
+            // Prefix every field with its length so that the boundaries between them can't be
+            // moved around without invalidating the signature.
+            mac.update(&u64::try_from(field.len()).unwrap().to_be_bytes());
+            mac.update(field);
+        }
+        mac
+    }
+
+    /// Adds the signature headers to `req`. `body` is the complete request payload, or `None` if
+    /// the payload is streamed. Streamed payloads must be sent through the returned
+    /// [`ChunkSigner`].
+    pub(crate) fn sign<B>(
+        &self,
+        identity: &str,
+        req: &mut Request<B>,
+        body: Option<&[u8]>,
+    ) -> Option<ChunkSigner> {
+        self.sign_at(identity, req, body, unix_time())
+    }
+
+    fn sign_at<B>(
+        &self,
+        identity: &str,
+        req: &mut Request<B>,
+        body: Option<&[u8]>,
+        now: u64,
+    ) -> Option<ChunkSigner> {
+        let content_digest = body.map_or_else(|| STREAMING_PAYLOAD.to_string(), content_digest);
+        let signed_at = now.to_string();
+        let mut nonce = [0; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex::encode(nonce);
+        let signature: [u8; MAC_LEN] = self
+            .mac(identity, req, &content_digest, &signed_at, &nonce)
+            .finalize()
+            .into_bytes()
+            .into();
+        let headers = req.headers_mut();
+        for (name, value) in [
+            (&HTTP_CONTENT_DIGEST_HEADER, content_digest),
+            (&HTTP_SIGNATURE_TIME_HEADER, signed_at),
+            (&HTTP_SIGNATURE_NONCE_HEADER, nonce),
+            (&HTTP_SIGNATURE_HEADER, hex::encode(signature)),
+        ] {
+            headers.insert(name.clone(), HeaderValue::from_str(&value).unwrap());
+        }
+
+        body.is_none().then(|| ChunkSigner {
+            key: self.clone(),
+            signature,
+            index: 0,
+        })
+    }
+
+    /// Checks the signature carried by `req` and returns how the payload is covered by it. It is
+    /// the caller's responsibility to check the payload against the returned [`SignedPayload`].
+    /// Nonces of valid requests are remembered in `nonces`.
+    ///
+    /// ## Errors
+    /// If the signature headers are missing or malformed, if the signature does not match, if
+    /// the request was signed too long ago or if it was received before.
+    pub(crate) fn verify<B>(
+        &self,
+        identity: &str,
+        req: &Request<B>,
+        nonces: &SeenNonces,
+    ) -> Result<SignedPayload, Error> {
+        self.verify_at(identity, req, nonces, unix_time())
+    }
+
+    fn verify_at<B>(
+        &self,
+        identity: &str,
+        req: &Request<B>,
+        nonces: &SeenNonces,
+        now: u64,
+    ) -> Result<SignedPayload, Error> {
+        let header = |name: &HeaderName| {
+            req.headers()
+                .get(name)
+                .ok_or_else(|| Error::MissingHeader(name.to_string()))
+                .and_then(|v| Ok(v.to_str()?))
+        };
+        let content_digest = header(&HTTP_CONTENT_DIGEST_HEADER)?;
+        let signed_at = header(&HTTP_SIGNATURE_TIME_HEADER)?;
+        let nonce = header(&HTTP_SIGNATURE_NONCE_HEADER)?;
+        let signature = hex::decode(header(&HTTP_SIGNATURE_HEADER)?)
+            .map_err(|e| Error::InvalidHeader(e.into()))?;
+        self.mac(identity, req, content_digest, signed_at, nonce)
+            .verify_slice(&signature)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        let signed_at = signed_at.parse::<u64>()?;
+        if signed_at.abs_diff(now) > MAX_SIGNATURE_AGE {
+            return Err(Error::SignatureExpired {
+                signed_at,
+                now,
+                max_age: MAX_SIGNATURE_AGE,
+            });
+        }
+        // The nonce is only remembered once the signature is known to be valid, so that
+        // unauthenticated requests can't fill up the set.
+        if !nonces.insert(nonce, signed_at, now) {
+            return Err(Error::ReplayedRequest);
+        }
+
+        Ok(if content_digest == STREAMING_PAYLOAD {
+            SignedPayload::Chunks(ChunkVerifier {
+                key: self.clone(),
+                signature: signature.try_into().map_err(|_| Error::InvalidSignature)?,
+                index: 0,
+                buf: BytesMut::new(),
+                finished: false,
+            })
+        } else {
+            SignedPayload::Digest(content_digest.to_string())
+        })
+    }
+
+    /// MAC of the chunk at `index` of the payload of the request signed with `signature`.
+    fn chunk_mac(&self, signature: &[u8; MAC_LEN], index: u64, payload: &[u8]) -> HmacSha256 {
+        let mut mac = self.hmac();
+        mac.update(signature);
+        mac.update(&index.to_be_bytes());
+        mac.update(&u64::try_from(payload.len()).unwrap().to_be_bytes());
+        mac.update(payload);
+        mac
+    }
+}
+
+/// Splits the payload of a signed request into chunks that each carry a MAC. Every chunk is the
+/// length of its payload as a 4 byte little-endian integer, the payload and its MAC. The last
+/// chunk has no payload.
+pub(crate) struct ChunkSigner {
+    key: PeerSigningKey,
+    signature: [u8; MAC_LEN],
+    index: u64,
+}
+
+impl ChunkSigner {
+    fn seal(&mut self, payload: &[u8]) -> Bytes {
+        let mac = self
+            .key
+            .chunk_mac(&self.signature, self.index, payload)
+            .finalize()
+            .into_bytes();
+        self.index += 1;
+        let mut buf = BytesMut::with_capacity(size_of::<u32>() + payload.len() + MAC_LEN);
+        buf.put_u32_le(u32::try_from(payload.len()).unwrap());
+        buf.put_slice(payload);
+        buf.put_slice(&mac);
+        buf.freeze()
+    }
+
+    /// Signs every chunk of `payload` and ends it with the empty chunk.
+    pub(crate) fn sign_stream<S, E>(self, payload: S) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+    {
+        stream::unfold(Some((self, payload)), |state| async move {
+            let (mut signer, mut payload) = state?;
+            loop {
+                match payload.next().await {
+                    // The empty chunk marks the end of the payload.
+                    Some(Ok(chunk)) if chunk.is_empty() => {}
+                    Some(Ok(chunk)) => {
+                        let chunk = signer.seal(&chunk);
+                        return Some((Ok(chunk), Some((signer, payload))));
+                    }
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => return Some((Ok(signer.seal(&[])), None)),
+                }
+            }
+        })
+    }
+}
+
+/// Checks and strips the chunks made by [`ChunkSigner`].
+#[derive(Debug)]
+pub(crate) struct ChunkVerifier {
+    key: PeerSigningKey,
+    signature: [u8; MAC_LEN],
+    index: u64,
+    buf: BytesMut,
+    finished: bool,
+}
+
+impl ChunkVerifier {
+    fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Takes the payload of the next complete chunk, if there is one.
+    fn next_payload(&mut self) -> Result<Option<Bytes>, Error> {
+        const LEN: usize = size_of::<u32>();
+        if self.finished {
+            return if self.buf.is_empty() {
+                Ok(None)
+            } else {
+                Err(Error::InvalidSignature)
+            };
+        }
+        if self.buf.len() < LEN {
+            return Ok(None);
+        }
+        let len = usize::try_from(u32::from_le_bytes(self.buf[..LEN].try_into().unwrap())).unwrap();
+        if len > MAX_PAYLOAD_LEN {
+            return Err(Error::InvalidSignature);
+        }
+        if self.buf.len() < LEN + len + MAC_LEN {
+            return Ok(None);
+        }
+        self.buf.advance(LEN);
+        let payload = self.buf.split_to(len).freeze();
+        let mac = self.buf.split_to(MAC_LEN);
+        self.key
+            .chunk_mac(&self.signature, self.index, &payload)
+            .verify_slice(&mac)
+            .map_err(|_| Error::InvalidSignature)?;
+        self.index += 1;
+        self.finished = payload.is_empty();
+
+        Ok(Some(payload))
+    }
+
+    /// Checks every chunk of `payload` and yields their payloads. Fails if a chunk does not match
+    /// its MAC or if `payload` does not end with the empty chunk.
+    pub(crate) fn verify_stream<S, E>(self, payload: S) -> impl Stream<Item = Result<Bytes, Error>>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        Error: From<E>,
+    {
+        stream::unfold(Some((self, payload)), |state| async move {
+            let (mut verifier, mut payload) = state?;
+            loop {
+                match verifier.next_payload() {
+                    Ok(Some(chunk)) if chunk.is_empty() => {}
+                    Ok(Some(chunk)) => return Some((Ok(chunk), Some((verifier, payload)))),
+                    Ok(None) => match payload.next().await {
+                        Some(Ok(data)) => verifier.extend(&data),
+                        Some(Err(e)) => return Some((Err(Error::from(e)), None)),
+                        None if verifier.finished && verifier.buf.is_empty() => return None,
+                        None => return Some((Err(Error::InvalidSignature), None)),
+                    },
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+        })
+    }
+}
+
+/// Nonces of signed requests received within the last [`MAX_SIGNATURE_AGE`].
+#[derive(Default)]
+pub(crate) struct SeenNonces(Mutex<NonceSet>);
+
+#[derive(Default)]
+struct NonceSet {
+    /// Time every nonce was signed at.
+    nonces: HashMap<String, u64>,
+    pruned_at: u64,
+}
+
+impl SeenNonces {
+    /// Remembers `nonce` of a request signed at `signed_at`. Returns `false` if it was seen
+    /// before.
+    fn insert(&self, nonce: &str, signed_at: u64, now: u64) -> bool {
+        let mut set = self.0.lock().unwrap();
+        // Requests signed this long ago are rejected anyway, so their nonces can be forgotten.
+        // This is done at most once a second, not on every request.
+        if set.pruned_at != now {
+            set.nonces
+                .retain(|_, &mut t| t.abs_diff(now) <= MAX_SIGNATURE_AGE);
+            set.pruned_at = now;
+        }
+        set.nonces.insert(nonce.to_string(), signed_at).is_none()
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Hex-encoded SHA-256 digest of the request payload, as carried in the content digest header.
+pub(crate) fn content_digest(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use bytes::Bytes;
+    use futures::{stream, TryStreamExt};
+    use hyper::Request;
+
+    use super::{
+        content_digest, PeerSigningKey, SeenNonces, SignedPayload, HTTP_SIGNATURE_HEADER,
+        MAX_SIGNATURE_AGE, STEP_HEADER,
+    };
+    use crate::net::Error;
+
+    const KEY: PeerSigningKey = PeerSigningKey([7; PeerSigningKey::LEN]);
+    const BODY: &[u8] = br#"{"roles":["A","B","C"]}"#;
+
+    fn request(uri: &str) -> Request<()> {
+        Request::post(uri).body(()).unwrap()
+    }
+
+    fn verify(key: &PeerSigningKey, identity: &str, req: &Request<()>) -> Result<(), Error> {
+        key.verify(identity, req, &SeenNonces::default())
+            .map(|_| ())
+    }
+
+    /// Signs `chunks` as the payload of `req` and returns the bytes that are sent.
+    async fn signed_payload(req: &mut Request<()>, chunks: &[&'static [u8]]) -> Vec<u8> {
+        let signer = KEY.sign("A", req, None).unwrap();
+        let chunks = stream::iter(chunks.iter().map(|&c| Ok::<_, Error>(Bytes::from(c))));
+        signer
+            .sign_stream(chunks)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .concat()
+    }
+
+    /// Checks `payload` against the signature of `req`, receiving it in chunks of `split` bytes.
+    async fn verified_payload(
+        req: &Request<()>,
+        payload: &[u8],
+        split: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let SignedPayload::Chunks(verifier) = KEY.verify("A", req, &SeenNonces::default())? else {
+            panic!("payload is not streamed");
+        };
+        let chunks = payload
+            .chunks(split)
+            .map(|c| Ok::<_, Error>(Bytes::copy_from_slice(c)))
+            .collect::<Vec<_>>();
+        Ok(verifier
+            .verify_stream(stream::iter(chunks))
+            .try_collect::<Vec<_>>()
+            .await?
+            .concat())
+    }
+
+    #[test]
+    fn sign_verify() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        assert!(KEY.sign("A", &mut req, Some(BODY)).is_none());
+        assert!(matches!(
+            KEY.verify("A", &req, &SeenNonces::default()).unwrap(),
+            SignedPayload::Digest(digest) if digest == content_digest(BODY)
+        ));
+    }
+
+    #[tokio::test]
+    async fn streamed_payload() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        let payload = signed_payload(&mut req, &[b"foo", b"", b"barbaz"]).await;
+        for split in [1, 5, payload.len()] {
+            assert_eq!(
+                b"foobarbaz".to_vec(),
+                verified_payload(&req, &payload, split).await.unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tampered_chunk() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        let mut payload = signed_payload(&mut req, &[b"foo", b"bar"]).await;
+        payload[4] ^= 1;
+        assert!(matches!(
+            verified_payload(&req, &payload, payload.len()).await,
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn truncated_payload() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        let payload = signed_payload(&mut req, &[b"foo", b"bar"]).await;
+        // Drop the empty chunk that ends the payload.
+        let truncated = &payload[..payload.len() - 4 - 32];
+        assert!(matches!(
+            verified_payload(&req, truncated, truncated.len()).await,
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn chunks_bound_to_request() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        let _ = signed_payload(&mut req, &[b"foo"]).await;
+        let mut other = request("http://localhost/query/1/step/foo");
+        let payload = signed_payload(&mut other, &[b"foo"]).await;
+        assert!(matches!(
+            verified_payload(&req, &payload, payload.len()).await,
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn replayed() {
+        let nonces = SeenNonces::default();
+        let mut req = request("http://localhost/query/1/step/foo");
+        KEY.sign("A", &mut req, Some(BODY));
+        KEY.verify("A", &req, &nonces).unwrap();
+        assert!(matches!(
+            KEY.verify("A", &req, &nonces),
+            Err(Error::ReplayedRequest)
+        ));
+    }
+
+    #[test]
+    fn expired() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        KEY.sign_at("A", &mut req, Some(BODY), 1000);
+        KEY.verify_at("A", &req, &SeenNonces::default(), 1000 + MAX_SIGNATURE_AGE)
+            .unwrap();
+        assert!(matches!(
+            KEY.verify_at("A", &req, &SeenNonces::default(), 1001 + MAX_SIGNATURE_AGE),
+            Err(Error::SignatureExpired {
+                signed_at: 1000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn wrong_identity() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        KEY.sign("A", &mut req, Some(BODY));
+        assert!(matches!(
+            verify(&KEY, "B", &req),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn wrong_key() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        KEY.sign("A", &mut req, Some(BODY));
+        let other = PeerSigningKey::new([8; PeerSigningKey::LEN]);
+        assert!(matches!(
+            verify(&other, "A", &req),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn signature_bound_to_step() {
+        let mut signed = request("http://localhost/query/1/step/foo");
+        KEY.sign("A", &mut signed, Some(BODY));
+        let mut replayed = request("http://localhost/query/1/step/bar");
+        *replayed.headers_mut() = signed.headers().clone();
+        assert!(matches!(
+            verify(&KEY, "A", &replayed),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn signature_bound_to_step_header() {
+        let step_request = |step: &'static str| {
+            Request::post("http://localhost/query/1/step/ipa/v7")
+                .header(STEP_HEADER.clone(), step)
+                .body(())
+                .unwrap()
+        };
+        let mut signed = step_request("foo");
+        KEY.sign("A", &mut signed, Some(BODY));
+        verify(&KEY, "A", &signed).unwrap();
+
+        let mut replayed = step_request("bar");
+        *replayed.headers_mut() = signed.headers().clone();
+        replayed
+            .headers_mut()
+            .insert(STEP_HEADER.clone(), "bar".parse().unwrap());
+        assert!(matches!(
+            verify(&KEY, "A", &replayed),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn missing_signature() {
+        let mut req = request("http://localhost/query/1/step/foo");
+        KEY.sign("A", &mut req, Some(BODY));
+        req.headers_mut().remove(&HTTP_SIGNATURE_HEADER);
+        assert!(matches!(
+            verify(&KEY, "A", &req),
+            Err(Error::MissingHeader(_))
+        ));
+    }
+
+    #[test]
+    fn parse_key() {
+        let key: PeerSigningKey =
+            serde_json::from_str(&format!("\"{}\"", "07".repeat(32))).unwrap();
+        assert_eq!(KEY, key);
+        serde_json::from_str::<PeerSigningKey>("\"0707\"").unwrap_err();
+    }
+}
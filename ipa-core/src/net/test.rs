@@ -27,15 +27,21 @@ use super::{ConnectionFlavor, HttpTransport, Shard};
 use crate::cli::{install_collector, LoggingHandle};
 use crate::{
     config::{
-        ClientConfig, HpkeClientConfig, HpkeServerConfig, NetworkConfig, PeerConfig, ServerConfig,
-        TlsConfig,
+        ClientConfig, HpkeClientConfig, HpkeServerConfig, NetworkConfig, PeerConfig, PeerTlsConfig,
+        ServerConfig, TlsConfig,
     },
     executor::IpaRuntime,
-    helpers::{HandlerBox, HelperIdentity, RequestHandler, StreamCollection, TransportIdentity},
+    helpers::{
+        HandlerBox, HelperIdentity, RequestHandler, StepTransfer, StreamCollection,
+        TransportIdentity,
+    },
     hpke::{Deserializable as _, IpaPublicKey},
-    net::{ClientIdentity, Helper, IpaHttpClient, IpaHttpServer},
+    net::{
+        pull::PullSources, ClientIdentity, Helper, IpaHttpClient, IpaHttpServer, OpenFiles,
+        RequestLimits,
+    },
     sharding::{ShardIndex, ShardedHelperIdentity},
-    sync::Arc,
+    sync::{Arc, Mutex},
     test_fixture::metrics::MetricsHandle,
 };
 
@@ -129,7 +135,11 @@ impl<F: ConnectionFlavor> TestNetwork<F> {
                 PeerConfig {
                     url,
                     certificate,
+                    certificate_pins: None,
                     hpke_config,
+                    signing_key: None,
+                    step_transfer: StepTransfer::Push,
+                    tls: PeerTlsConfig::default(),
                 }
             })
             .collect()
@@ -192,6 +202,9 @@ fn server_config_insecure_http(port: u16, matchkey_encryption: bool) -> ServerCo
         disable_https: true,
         tls: None,
         hpke_config: get_dummy_matchkey_encryption_info(matchkey_encryption),
+        admin_token: None,
+        open_files: None,
+        request_limits: RequestLimits::default(),
     }
 }
 
@@ -210,6 +223,9 @@ fn server_config_https(
             private_key: String::from_utf8(private_key.to_owned()).unwrap(),
         }),
         hpke_config: get_dummy_matchkey_encryption_info(matchkey_encryption),
+        admin_token: None,
+        open_files: None,
+        request_limits: RequestLimits::default(),
     }
 }
 
@@ -565,6 +581,7 @@ pub struct TestServerBuilder<F: ConnectionFlavor = Helper> {
     disable_https: bool,
     use_http1: bool,
     disable_matchkey_encryption: bool,
+    open_files: Option<OpenFiles>,
 }
 
 impl<F: ConnectionFlavor> Default for TestServerBuilder<F> {
@@ -575,6 +592,7 @@ impl<F: ConnectionFlavor> Default for TestServerBuilder<F> {
             disable_https: false,
             use_http1: false,
             disable_matchkey_encryption: false,
+            open_files: None,
         }
     }
 }
@@ -593,6 +611,13 @@ impl<F: ConnectionFlavor> TestServerBuilder<F> {
         self
     }
 
+    #[cfg(all(test, unit_test))]
+    #[must_use]
+    pub fn with_open_files(mut self, open_files: OpenFiles) -> Self {
+        self.open_files = Some(open_files);
+        self
+    }
+
     #[must_use]
     pub fn disable_https(mut self) -> Self {
         self.disable_https = true;
@@ -656,6 +681,10 @@ trait TestTransportConfigurator {
             clients,
             record_streams: StreamCollection::default(),
             handler,
+            protocol_versions: Mutex::default(),
+            pull_config: Vec::new(),
+            pull_receivers: Mutex::default(),
+            pull_sources: PullSources::default(),
         };
 
         Arc::new(transport)
@@ -750,7 +779,8 @@ impl TestServerBuilder<Shard> {
 
 impl TestServerBuilder<Helper> {
     pub async fn build(self) -> TestServer<Helper> {
-        let test_config = self.test_config();
+        let mut test_config = self.test_config();
+        test_config.rings[0].servers[0].config.open_files = self.open_files.clone();
 
         let transport =
             self.make_transport(self.handler.clone(), test_config.rings.first().unwrap());
@@ -899,7 +929,7 @@ xYxSeDvd5vt4ROlqgvLMcOOUjbFF7YAT6g==
 ",
 ];
 
-static TEST_CERTS_DER: Lazy<[CertificateDer; 6]> = Lazy::new(|| {
+pub(crate) static TEST_CERTS_DER: Lazy<[CertificateDer; 6]> = Lazy::new(|| {
     TEST_CERTS.map(|mut pem| rustls_pemfile::certs(&mut pem).flatten().next().unwrap())
 });
 
@@ -1,29 +1,34 @@
 use std::{
     borrow::Borrow,
+    collections::HashMap,
     future::Future,
     pin::Pin,
+    str::FromStr,
     task::{Context, Poll},
 };
 
 use async_trait::async_trait;
-use futures::{Stream, TryFutureExt};
+use bytes::Bytes;
+use futures::{stream, Stream, TryFutureExt};
 use pin_project::{pin_project, pinned_drop};
 
 use super::{client::resp_ok, error::ShardError, ConnectionFlavor, Helper, Shard};
 use crate::{
     config::{NetworkConfig, ServerConfig},
+    error::BoxError,
     executor::IpaRuntime,
     helpers::{
+        frame,
         query::QueryConfig,
         routing::{Addr, RouteId},
         ApiError, BodyStream, HandlerRef, HelperIdentity, HelperResponse, NoQueryId,
         NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords, RequestHandler, RouteParams,
         StepBinding, StreamCollection, Transport, TransportIdentity,
     },
-    net::{client::IpaHttpClient, error::Error, IpaHttpServer},
-    protocol::{Gate, QueryId},
+    net::{checksum, client::IpaHttpClient, error::Error, pull::PullSources, IpaHttpServer},
+    protocol::{Gate, ProtocolVersion, QueryId},
     sharding::ShardIndex,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 /// Shared implementation used by [`MpcHttpTransport`] and [`ShardHttpTransport`]
@@ -33,6 +38,14 @@ pub struct HttpTransport<F: ConnectionFlavor> {
     pub(super) clients: Vec<IpaHttpClient<F>>,
     pub(super) record_streams: StreamCollection<F::Identity, BodyStream>,
     pub(super) handler: Option<HandlerRef<F::Identity>>,
+    /// Protocol version negotiated for each query this transport has seen.
+    pub(super) protocol_versions: Mutex<HashMap<QueryId, ProtocolVersion>>,
+    /// Peers configured to receive step data in pull mode. Used when this helper leads a query.
+    pub(super) pull_config: Vec<F::Identity>,
+    /// Peers that receive step data in pull mode, as negotiated for each query.
+    pub(super) pull_receivers: Mutex<HashMap<QueryId, Vec<F::Identity>>>,
+    /// Step data waiting to be pulled by peers.
+    pub(super) pull_sources: PullSources<F::Identity>,
 }
 
 /// HTTP transport for helper to helper traffic.
@@ -93,7 +106,14 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
                     .expect("query_id required when sending records");
                 let step =
                     <Option<Gate>>::from(route.gate()).expect("step required when sending records");
-                let resp_future = self.clients[client_ix].step(query_id, &step, data)?;
+                let protocol_version = ProtocolVersion::from_str(route.extra().borrow())
+                    .expect("protocol version required when sending records");
+                if self.pulls(query_id, dest) {
+                    self.pull_sources.serve((query_id, dest, step), data).await;
+                    return Ok(());
+                }
+                let resp_future =
+                    self.clients[client_ix].step(query_id, protocol_version, &step, data)?;
                 // Use a dedicated HTTP runtime to poll this future for several reasons:
                 // - avoid blocking this task, if the current runtime is overloaded
                 // - use the runtime that enables IO (current runtime may not).
@@ -115,10 +135,23 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
                 let req = serde_json::from_str(route.extra().borrow())?;
                 self.clients[client_ix].status_match(req).await
             }
+            RouteId::PeerUnavailable => {
+                let req = serde_json::from_str(route.extra().borrow())?;
+                self.clients[client_ix].peer_unavailable(req).await
+            }
             evt @ (RouteId::QueryInput
+            | RouteId::AppendQueryInput
+            | RouteId::SealQueryInput
             | RouteId::ReceiveQuery
+            | RouteId::ReceiveQueryFromTemplate
+            | RouteId::QueryTemplates
+            | RouteId::ValidateQuery
             | RouteId::KillQuery
-            | RouteId::Metrics) => {
+            | RouteId::DeleteQueryInput
+            | RouteId::ListQueries
+            | RouteId::PruneQuery
+            | RouteId::Metrics
+            | RouteId::Readiness) => {
                 unimplemented!(
                     "attempting to send client-specific request {evt:?} to another helper"
                 )
@@ -131,24 +164,177 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
         from: F::Identity,
         route: &R,
     ) -> ReceiveRecords<F::Identity, BodyStream> {
-        ReceiveRecords::new(
-            (route.query_id(), from, route.gate()),
-            self.record_streams.clone(),
-        )
+        let (query_id, gate) = (route.query_id(), route.gate());
+        if self.pulls(query_id, self.identity) {
+            self.record_streams.add_stream(
+                (query_id, from, gate.clone()),
+                self.pull_stream(query_id, from, gate.clone()),
+            );
+        }
+        ReceiveRecords::new((query_id, from, gate), self.record_streams.clone())
+    }
+
+    /// Returns `true` if `peer` receives step data for `query_id` by pulling it from the sender.
+    ///
+    /// ## Panics
+    /// If mutex is poisoned.
+    fn pulls(&self, query_id: QueryId, peer: F::Identity) -> bool {
+        self.pull_receivers
+            .lock()
+            .unwrap()
+            .get(&query_id)
+            .is_some_and(|receivers| receivers.contains(&peer))
+    }
+
+    /// Requests the data sent by `from` over `gate` chunk by chunk, until `from` reports there is
+    /// nothing left.
+    fn pull_stream(&self, query_id: QueryId, from: F::Identity, gate: Gate) -> BodyStream {
+        let client = self.clients[from.as_index()].clone();
+        let runtime = self.http_runtime.clone();
+        let protocol_version = self
+            .protocol_versions
+            .lock()
+            .unwrap()
+            .get(&query_id)
+            .copied()
+            .unwrap_or(ProtocolVersion::CURRENT);
+        let chunks = stream::try_unfold(0_u64, move |offset| {
+            let client = client.clone();
+            let gate = gate.clone();
+            let runtime = runtime.clone();
+            async move {
+                let mut retransmits = 0;
+                let chunk = loop {
+                    let client = client.clone();
+                    let gate = gate.clone();
+                    // See `send` for why requests are polled on the HTTP runtime.
+                    let next = runtime.spawn(async move {
+                        client
+                            .pull_step(query_id, protocol_version, &gate, offset)
+                            .await
+                    });
+                    match next.await {
+                        // The peer serves the last chunk again if asked for the same offset.
+                        Err(Error::ChecksumMismatch(e))
+                            if retransmits < checksum::MAX_RETRANSMITS =>
+                        {
+                            tracing::warn!("{e}, requesting it again");
+                            retransmits += 1;
+                        }
+                        next => break next.map_err(BoxError::from)?,
+                    }
+                };
+                Ok::<_, BoxError>(chunk.map(|chunk| {
+                    let next_offset = offset + u64::try_from(chunk.len()).unwrap();
+                    (chunk, next_offset)
+                }))
+            }
+        });
+        BodyStream::from_bytes_stream(chunks)
+    }
+
+    fn check_protocol_version(
+        &self,
+        query_id: QueryId,
+        protocol_version: ProtocolVersion,
+    ) -> Result<(), Error> {
+        if !protocol_version.is_supported() {
+            return Err(Error::UnsupportedProtocolVersion(protocol_version));
+        }
+        if let Some(&expected) = self.protocol_versions.lock().unwrap().get(&query_id) {
+            if expected != protocol_version {
+                return Err(Error::ProtocolVersionMismatch {
+                    query_id,
+                    expected,
+                    actual: protocol_version,
+                });
+            }
+        }
+
+        Ok(())
     }
 
     /// Connect an inbound stream of record data.
     ///
     /// This is called by peer entities (shards or helpers) via the HTTP server.
+    ///
+    /// With batch checksums, the first corrupted frame ends the stream with an error. Pushed
+    /// frames are never sent again, so the rest of the stream is lost.
+    ///
+    /// ## Errors
+    /// If `protocol_version` is not supported by this helper or if it differs from the version
+    /// negotiated for this query. Also if this helper pulls the data for this query instead.
+    ///
+    /// ## Panics
+    /// If mutex is poisoned.
     pub fn receive_stream(
         &self,
         query_id: QueryId,
+        protocol_version: ProtocolVersion,
         gate: Gate,
         from: F::Identity,
         stream: BodyStream,
-    ) {
+    ) -> Result<(), Error> {
+        self.check_protocol_version(query_id, protocol_version)?;
+        if self.pulls(query_id, self.identity) {
+            return Err(Error::UnexpectedPush(query_id));
+        }
+        let stream = if protocol_version.has_batch_checksums() {
+            BodyStream::from_bytes_stream(frame::payloads(gate.clone(), stream))
+        } else {
+            stream
+        };
         self.record_streams
             .add_stream((query_id, from, gate), stream);
+
+        Ok(())
+    }
+
+    /// Binds `query_id` to the protocol version negotiated for it. From now on, record streams
+    /// for this query are only accepted if they were produced by the same version.
+    ///
+    /// ## Panics
+    /// If mutex is poisoned.
+    pub fn bind_protocol_version(&self, query_id: QueryId, version: ProtocolVersion) {
+        self.protocol_versions
+            .lock()
+            .unwrap()
+            .insert(query_id, version);
+    }
+
+    /// Serves the chunk of step data sent to `from` that starts at `from_offset`. Returns `None`
+    /// once `from` has read all of it.
+    ///
+    /// This is called by peer entities that receive step data in pull mode via the HTTP server.
+    ///
+    /// ## Errors
+    /// If `protocol_version` is not the one negotiated for this query, if `from_offset` does
+    /// not follow the data `from` has already read or if there is nothing to serve before the
+    /// pull timeout expires.
+    pub async fn pull_step(
+        &self,
+        query_id: QueryId,
+        protocol_version: ProtocolVersion,
+        gate: Gate,
+        from: F::Identity,
+        from_offset: u64,
+    ) -> Result<Option<Bytes>, Error> {
+        self.check_protocol_version(query_id, protocol_version)?;
+        Ok(self
+            .pull_sources
+            .pull(&(query_id, from, gate), from_offset)
+            .await?)
+    }
+
+    /// Sets the peers that receive step data for `query_id` in pull mode.
+    ///
+    /// ## Panics
+    /// If mutex is poisoned.
+    pub fn set_pull_receivers(&self, query_id: QueryId, receivers: &[F::Identity]) {
+        self.pull_receivers
+            .lock()
+            .unwrap()
+            .insert(query_id, receivers.to_vec());
     }
 
     /// Dispatches the given request to the [`RequestHandler`] connected to this transport.
@@ -166,14 +352,12 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
     where
         Option<QueryId>: From<Q>,
     {
-        /// Cleans up the `records_stream` collection after drop to ensure this transport
-        /// can process the next query even in case of a panic.
-        ///
-        /// This implementation is a poor man's safety net and only works because we run
-        /// one query at a time and don't use query identifiers.
+        /// Cleans up the channels of the query in `records_stream` and `pull_sources` after drop,
+        /// even in case of a panic. Channels of other queries are left alone.
         #[pin_project(PinnedDrop)]
         struct ClearOnDrop<CF: ConnectionFlavor, F: Future> {
             transport: Arc<HttpTransport<CF>>,
+            query_id: QueryId,
             #[pin]
             inner: F,
         }
@@ -189,20 +373,24 @@ impl<F: ConnectionFlavor> HttpTransport<F> {
         #[pinned_drop]
         impl<CF: ConnectionFlavor, F: Future> PinnedDrop for ClearOnDrop<CF, F> {
             fn drop(self: Pin<&mut Self>) {
-                self.transport.record_streams.clear();
+                self.transport.record_streams.clear_query(self.query_id);
+                self.transport.pull_sources.clear_query(self.query_id);
             }
         }
 
         let route_id = req.resource_identifier();
+        let query_id = Option::<QueryId>::from(req.query_id());
         let r = self
             .handler
             .as_ref()
             .expect("A Handler should be set by now")
             .handle(Addr::from_route(None, req), body);
 
-        if let RouteId::CompleteQuery | RouteId::KillQuery = route_id {
+        if let (RouteId::CompleteQuery | RouteId::KillQuery, Some(query_id)) = (route_id, query_id)
+        {
             ClearOnDrop {
                 transport: Arc::clone(&self),
+                query_id,
                 inner: r,
             }
             .await
@@ -228,6 +416,10 @@ impl MpcHttpTransport {
             clients: clients.to_vec(),
             handler,
             record_streams: StreamCollection::default(),
+            protocol_versions: Mutex::default(),
+            pull_config: network_config.pull_receivers(),
+            pull_receivers: Mutex::default(),
+            pull_sources: PullSources::default(),
         });
 
         let server =
@@ -238,15 +430,19 @@ impl MpcHttpTransport {
     /// Connect an inbound stream of record data.
     ///
     /// This is called by peer helpers via the HTTP server.
+    ///
+    /// ## Errors
+    /// If the stream was produced by a protocol version other than the one this query runs.
     pub fn receive_stream(
         &self,
         query_id: QueryId,
+        protocol_version: ProtocolVersion,
         gate: Gate,
         from: HelperIdentity,
         stream: BodyStream,
-    ) {
+    ) -> Result<(), Error> {
         self.inner_transport
-            .receive_stream(query_id, gate, from, stream);
+            .receive_stream(query_id, protocol_version, gate, from, stream)
     }
 
     /// Dispatches the given request to the [`RequestHandler`] connected to this transport.
@@ -315,6 +511,19 @@ impl Transport for MpcHttpTransport {
     ) -> Self::RecordsStream {
         self.inner_transport.receive(from, &route)
     }
+
+    fn bind_protocol_version(&self, query_id: QueryId, version: ProtocolVersion) {
+        self.inner_transport
+            .bind_protocol_version(query_id, version);
+    }
+
+    fn pull_receivers(&self) -> Vec<Self::Identity> {
+        self.inner_transport.pull_config.clone()
+    }
+
+    fn set_pull_receivers(&self, query_id: QueryId, receivers: &[Self::Identity]) {
+        self.inner_transport.set_pull_receivers(query_id, receivers);
+    }
 }
 
 impl ShardHttpTransport {
@@ -335,6 +544,10 @@ impl ShardHttpTransport {
             clients,
             handler,
             record_streams: StreamCollection::default(),
+            protocol_versions: Mutex::default(),
+            pull_config: Vec::new(),
+            pull_receivers: Mutex::default(),
+            pull_sources: PullSources::default(),
         });
 
         let server =
@@ -398,6 +611,11 @@ impl Transport for ShardHttpTransport {
     ) -> Self::RecordsStream {
         self.inner_transport.receive(from, &route)
     }
+
+    fn bind_protocol_version(&self, query_id: QueryId, version: ProtocolVersion) {
+        self.inner_transport
+            .bind_protocol_version(query_id, version);
+    }
 }
 
 #[cfg(all(test, web_test, descriptive_gate))]
@@ -471,7 +689,15 @@ mod tests {
         let body = BodyStream::from_bytes_stream(ReceiverStream::new(rx));
 
         // Register the stream with the transport (normally called by step data HTTP API handler)
-        transport.receive_stream(QueryId, STEP.clone(), HelperIdentity::TWO, body);
+        transport
+            .receive_stream(
+                QueryId,
+                ProtocolVersion::CURRENT,
+                STEP.clone(),
+                HelperIdentity::TWO,
+                body,
+            )
+            .unwrap();
 
         // Request step data reception (normally called by protocol)
         let mut stream = transport
@@ -580,6 +806,7 @@ mod tests {
             let data = QueryInput {
                 query_id,
                 input_stream,
+                contribution: None,
             };
             handle_resps.push(leader_ring_clients[i].query_input(data));
         }
@@ -592,6 +819,7 @@ mod tests {
                 shard_client.query_input(QueryInput {
                     query_id,
                     input_stream: BodyStream::empty(),
+                    contribution: None,
                 })
             }))
         }))
@@ -644,6 +872,7 @@ mod tests {
                             clients[shard][helper].query_input(QueryInput {
                                 query_id,
                                 input_stream,
+                                contribution: None,
                             })
                         },
                     ))
@@ -707,6 +936,10 @@ mod tests {
                 clients: Vec::new(),
                 handler: None,
                 record_streams: StreamCollection::default(),
+                protocol_versions: Mutex::default(),
+                pull_config: Vec::new(),
+                pull_receivers: Mutex::default(),
+                pull_sources: PullSources::default(),
             })
         }
 
@@ -1,6 +1,7 @@
 pub mod check_zero;
 mod if_else;
 pub(crate) mod mul;
+pub mod paranoid;
 mod reshare;
 mod reveal;
 pub(crate) mod shard_fin;
@@ -161,4 +162,25 @@ const_assert_eq!(
     256,
     "Implementation for N = 256 required for num_breakdowns"
 );
+
+// 10 and 12 bit breakdown keys.
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedSemiHonestContext<'_, B>, 1024>
+    for AdditiveShare<Boolean, 1024>
+{
+}
+
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedMaliciousContext<'_, B>, 1024>
+    for AdditiveShare<Boolean, 1024>
+{
+}
+
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedSemiHonestContext<'_, B>, 4096>
+    for AdditiveShare<Boolean, 4096>
+{
+}
+
+impl<B: ShardBinding> BooleanProtocols<DZKPUpgradedMaliciousContext<'_, B>, 4096>
+    for AdditiveShare<Boolean, 4096>
+{
+}
 // End implementations for num_breakdowns
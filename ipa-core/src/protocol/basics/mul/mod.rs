@@ -9,7 +9,7 @@ use crate::{
     error::Error,
     ff::{
         boolean::Boolean,
-        boolean_array::{BA16, BA20, BA256, BA3, BA32, BA5, BA64, BA8},
+        boolean_array::{BA10, BA12, BA16, BA20, BA24, BA256, BA3, BA32, BA5, BA64, BA8},
         Expand,
     },
     protocol::{
@@ -126,8 +126,11 @@ macro_rules! boolean_array_mul {
 boolean_array_mul!(3, BA3);
 boolean_array_mul!(5, BA5);
 boolean_array_mul!(8, BA8);
+boolean_array_mul!(10, BA10);
+boolean_array_mul!(12, BA12);
 boolean_array_mul!(16, BA16);
 boolean_array_mul!(20, BA20);
+boolean_array_mul!(24, BA24);
 boolean_array_mul!(32, BA32);
 boolean_array_mul!(64, BA64);
 boolean_array_mul!(256, BA256);
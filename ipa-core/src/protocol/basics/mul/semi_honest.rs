@@ -178,11 +178,7 @@ where
 
 #[cfg(all(test, unit_test))]
 mod test {
-    use std::{
-        array,
-        iter::{repeat, zip},
-        time::Instant,
-    };
+    use std::{array, iter::zip, time::Instant};
 
     use rand::distributions::{Distribution, Standard};
 
@@ -193,7 +189,6 @@ mod test {
         protocol::{basics::SecureMul, context::Context, RecordId},
         rand::{thread_rng, Rng},
         secret_sharing::replicated::semi_honest::AdditiveShare,
-        seq_join::SeqJoin,
         test_fixture::{Reconstruct, ReconstructArr, Runner, TestWorld},
     };
 
@@ -246,15 +241,11 @@ mod test {
             .semi_honest(
                 (a.into_iter(), b.into_iter()),
                 |ctx, (a_shares, b_shares)| async move {
-                    ctx.try_join(
-                        zip(
-                            repeat(ctx.set_total_records(COUNT)),
-                            zip(a_shares, b_shares),
-                        )
-                        .enumerate()
-                        .map(|(i, (ctx, (a_share, b_share)))| async move {
-                            a_share.multiply(&b_share, ctx, RecordId::from(i)).await
-                        }),
+                    ctx.parallel_records(
+                        zip(a_shares, b_shares),
+                        |ctx, record_id, (a_share, b_share)| async move {
+                            a_share.multiply(&b_share, ctx, record_id).await
+                        },
                     )
                     .await
                     .unwrap()
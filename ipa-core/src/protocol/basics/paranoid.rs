@@ -0,0 +1,162 @@
+//! Share-consistency assertions for development builds.
+//!
+//! Nothing in a semi-honest protocol, or in a malicious one before its validation step, checks
+//! that helpers end up holding matching replicated shares. A bug in a new subprotocol that leaves
+//! `H1.x1 != H2.x1` only shows up later as garbage output or a failed validation, far away from
+//! where it was introduced. With the `paranoid` feature, [`assert_consistent`] calls placed at
+//! stage boundaries catch such bugs where they happen.
+//!
+//! Every helper sends its right share of each value to its right peer, masked with randomness
+//! shared with that peer, and the peer compares it against its left share masked the same way.
+//! This costs one message per checked value, which is much less than the full malicious
+//! machinery, but it only catches mistakes: a malicious helper can always send a value that
+//! passes.
+//!
+//! Without the `paranoid` feature, [`assert_consistent`] does nothing and costs nothing, so the
+//! calls can stay in protocol code.
+
+use futures::future::try_join;
+
+use crate::{
+    error::Error,
+    ff::U128Conversions,
+    helpers::{Direction, TotalRecords},
+    protocol::{context::Context, prss::SharedRandomness, RecordId},
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        SharedValue,
+    },
+};
+
+/// Checks that every helper holds the same shares of `shares` as its neighbours, if the
+/// `paranoid` feature is enabled.
+///
+/// ## Errors
+/// Propagates errors from send and receive.
+///
+/// ## Panics
+/// If the shares are inconsistent. Only the helper to the right of the inconsistent share
+/// panics, the other two see the check succeed.
+pub async fn assert_consistent<'a, C, I, S>(ctx: C, shares: I) -> Result<(), Error>
+where
+    C: Context,
+    I: IntoIterator<Item = &'a Replicated<S>>,
+    I::IntoIter: ExactSizeIterator + Send,
+    S: SharedValue + U128Conversions,
+{
+    if cfg!(feature = "paranoid") {
+        if let Some(record_id) = find_inconsistent(ctx.clone(), shares).await? {
+            panic!(
+                "{:?} holds a left share of record {record_id} that does not match the right \
+                share of {:?} at {:?}",
+                ctx.role(),
+                ctx.role().peer(Direction::Left),
+                ctx.gate(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the masked exchange and returns the first record whose left share does not match the
+/// right share of the left peer.
+async fn find_inconsistent<'a, C, I, S>(ctx: C, shares: I) -> Result<Option<RecordId>, Error>
+where
+    C: Context,
+    I: IntoIterator<Item = &'a Replicated<S>>,
+    I::IntoIter: ExactSizeIterator + Send,
+    S: SharedValue + U128Conversions,
+{
+    let shares = shares.into_iter();
+    let Ok(total_records) = TotalRecords::specified(shares.len()) else {
+        return Ok(None);
+    };
+    let ctx = ctx.set_total_records(total_records);
+    let send_channel = ctx.send_channel::<S>(ctx.role().peer(Direction::Right));
+    let recv_channel = ctx.recv_channel::<S>(ctx.role().peer(Direction::Left));
+
+    let mismatches = ctx
+        .parallel_join(shares.enumerate().map(|(i, share)| {
+            let (ctx, send_channel, recv_channel) = (&ctx, &send_channel, &recv_channel);
+            async move {
+                let record_id = RecordId::from(i);
+                // Masks shared with a peer are the same on both ends, so the right share of the
+                // left peer and our left share get the same mask.
+                let mask = |direction| {
+                    S::truncate_from(
+                        ctx.prss()
+                            .generate_one_side::<u128, _>(record_id, direction),
+                    )
+                };
+                let expected = share.left() + mask(Direction::Left);
+                let ((), received) = try_join(
+                    send_channel.send(record_id, share.right() + mask(Direction::Right)),
+                    recv_channel.receive(record_id),
+                )
+                .await?;
+
+                Ok::<_, Error>((received != expected).then_some(record_id))
+            }
+        }))
+        .await?;
+
+    Ok(mismatches.into_iter().flatten().next())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::find_inconsistent;
+    use crate::{
+        ff::{boolean_array::BA8, U128Conversions},
+        helpers::Role,
+        protocol::RecordId,
+        secret_sharing::replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+        test_executor::run,
+        test_fixture::{Runner, TestWorld},
+    };
+
+    fn values() -> Vec<BA8> {
+        (0_u128..10).map(BA8::truncate_from).collect()
+    }
+
+    #[test]
+    fn consistent() {
+        run(|| async {
+            let world = TestWorld::default();
+            let results = world
+                .semi_honest(
+                    values().into_iter(),
+                    |ctx, shares: Vec<AdditiveShare<BA8>>| async move {
+                        find_inconsistent(ctx, &shares).await.unwrap()
+                    },
+                )
+                .await;
+            assert_eq!([None, None, None], results);
+        });
+    }
+
+    #[test]
+    fn inconsistent() {
+        run(|| async {
+            let world = TestWorld::default();
+            let results = world
+                .semi_honest(
+                    values().into_iter(),
+                    |ctx, mut shares: Vec<AdditiveShare<BA8>>| async move {
+                        // H2 corrupts its right share of the fourth value, which H3 also holds.
+                        if ctx.role() == Role::H2 {
+                            let share = &mut shares[3];
+                            *share = AdditiveShare::new(
+                                share.left(),
+                                share.right() + BA8::truncate_from(1_u128),
+                            );
+                        }
+                        find_inconsistent(ctx, &shares).await.unwrap()
+                    },
+                )
+                .await;
+            assert_eq!([None, None, Some(RecordId::from(3_usize))], results);
+        });
+    }
+}
@@ -22,10 +22,7 @@
 // vectorization dimension for `CtxF` from context, so it's easier to make them the same
 // absent a need for them to be different.
 
-use std::{
-    future::Future,
-    iter::{repeat, zip},
-};
+use std::future::Future;
 
 use embed_doc_image::embed_doc_image;
 use futures::{FutureExt, TryFutureExt};
@@ -118,17 +115,16 @@ where
     where
         C: 'fut,
     {
-        ctx.parallel_join(zip(&**self, repeat(ctx.clone())).enumerate().map(
-            |(i, (bit, ctx))| async move {
-                generic_reveal(
-                    ctx.narrow(&TwoHundredFiftySixBitOpStep::from(i)),
-                    record_id,
-                    excluded,
-                    bit,
-                )
-                .await
-            },
-        ))
+        // Every bit is revealed in its own step, so they all share `record_id`.
+        let bit_ctx = ctx.clone();
+        ctx.parallel_join(self.iter().enumerate().map(move |(i, bit)| {
+            generic_reveal(
+                bit_ctx.narrow(&TwoHundredFiftySixBitOpStep::from(i)),
+                record_id,
+                excluded,
+                bit,
+            )
+        }))
         .map(move |res| {
             res.map(move |vec| {
                 match vec.first() {
@@ -1,14 +1,17 @@
-use futures_util::future::try_join;
+use futures_util::future::{try_join, try_join4};
 use subtle::ConstantTimeEq;
 
 use crate::{
     error::Error,
     helpers::{
-        hashing::{compute_hash, Hash},
+        hashing::{compute_hash, compute_possibly_empty_hash, Hash},
         Direction, TotalRecords,
     },
     protocol::{context::Context, RecordId},
-    secret_sharing::SharedValue,
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        SharedValue,
+    },
 };
 
 /// This function checks that a vector of shares are consistent across helpers
@@ -58,6 +61,57 @@ where
     }
 }
 
+/// Checks that every helper agrees with both of its neighbours on the replicated shares of
+/// `shares`, i.e. `H1` holds `(x0,x1)`, `H2` holds `(x1,x2)`, `H3` holds `(x2,x0)` for every
+/// element.
+///
+/// Unlike [`validate_replicated_shares`], hashes are exchanged in both directions, so both
+/// helpers that share an inconsistent value detect it. At most one helper can then pass the
+/// check, which is not enough to reconstruct any value. This makes the check suitable as a last
+/// step before shares are released to a party outside of the MPC.
+///
+/// # Errors
+/// [`Error::InconsistentShares`] if shares of either neighbour don't match, and propagates errors
+/// from send and receive.
+pub async fn cross_check_replicated_shares<'a, C, I, S>(ctx: C, shares: I) -> Result<(), Error>
+where
+    C: Context,
+    I: IntoIterator<Item = &'a Replicated<S>>,
+    S: SharedValue,
+{
+    let (left, right): (Vec<S>, Vec<S>) = shares
+        .into_iter()
+        .map(|share| (share.left(), share.right()))
+        .unzip();
+
+    let ctx = ctx.set_total_records(TotalRecords::ONE);
+    let left_peer = ctx.role().peer(Direction::Left);
+    let right_peer = ctx.role().peer(Direction::Right);
+
+    let hash_left = compute_possibly_empty_hash(&left);
+    let hash_right = compute_possibly_empty_hash(&right);
+
+    let ((), (), from_left, from_right) = try_join4(
+        ctx.send_channel::<Hash>(right_peer)
+            .send(RecordId::FIRST, hash_right.clone()),
+        ctx.send_channel::<Hash>(left_peer)
+            .send(RecordId::FIRST, hash_left.clone()),
+        ctx.recv_channel::<Hash>(left_peer).receive(RecordId::FIRST),
+        ctx.recv_channel::<Hash>(right_peer)
+            .receive(RecordId::FIRST),
+    )
+    .await?;
+
+    // The left peer sent the hash of its right shares, which are our left shares.
+    let matches_left = hash_left.ct_eq(&from_left);
+    let matches_right = hash_right.ct_eq(&from_right);
+    if (matches_left & matches_right).into() {
+        Ok(())
+    } else {
+        Err(Error::InconsistentShares)
+    }
+}
+
 /// This function is similar to validate the consistency of shares with the difference
 /// that it validates that tuple of shares sum to zero rather than being identical
 /// i.e. `H1` holds `(H1_x0,H1_x1)`, `H2` holds `(H2_x1,H2_x2)`, `H3` holds `(H3_x2,H3_x0)`
@@ -98,8 +152,12 @@ mod test {
     use crate::{
         error::Error,
         ff::{Field, Fp61BitPrime},
+        helpers::Role,
         protocol::{
-            basics::share_validation::validate_three_two_way_sharing_of_zero, context::Context,
+            basics::share_validation::{
+                cross_check_replicated_shares, validate_three_two_way_sharing_of_zero,
+            },
+            context::Context,
         },
         secret_sharing::replicated::ReplicatedSecretSharing,
         test_executor::run,
@@ -167,4 +225,35 @@ mod test {
                 .await;
         });
     }
+
+    #[test]
+    fn cross_check() {
+        run(|| async move {
+            let world = TestWorld::default();
+            let mut rng = thread_rng();
+            let values = (0..10)
+                .map(|_| rng.gen::<Fp61BitPrime>())
+                .collect::<Vec<_>>();
+
+            let results = world
+                .semi_honest(values.into_iter(), |ctx, mut shares| async move {
+                    cross_check_replicated_shares(ctx.narrow("consistent"), &shares)
+                        .await
+                        .unwrap();
+
+                    // H2 corrupts the half it shares with H3.
+                    if ctx.role() == Role::H2 {
+                        let left = shares[3].left();
+                        let right = shares[3].right() + Fp61BitPrime::ONE;
+                        shares[3] = ReplicatedSecretSharing::new(left, right);
+                    }
+                    cross_check_replicated_shares(ctx.narrow("corrupted"), &shares).await
+                })
+                .await;
+
+            assert!(results[0].is_ok());
+            assert!(matches!(results[1], Err(Error::InconsistentShares)));
+            assert!(matches!(results[2], Err(Error::InconsistentShares)));
+        });
+    }
 }
@@ -0,0 +1,534 @@
+//! Adders and subtractors over bit-decomposed unsigned integers.
+//!
+//! Operands are little-endian vectors of boolean shares, each holding the same bit of `N`
+//! records, so every circuit here processes `N` records at once. Any context that supports
+//! boolean multiplications works, including the DZKP-upgraded contexts of malicious protocols.
+//!
+//! Ripple-carry adders and subtractors need one multiplication per bit, and one round per bit.
+//! Each bit is computed under the step `S::from(i)`. Saturating circuits first narrow the context
+//! to the stages of `SaturatedAdditionStep` or `SaturatedSubtractionStep`.
+//!
+//! Unless stated otherwise, results have the length of `x`. Bits of `y` beyond the length of `x`
+//! are ignored, so they must be zero for results to be correct.
+
+use std::iter::{once, repeat, repeat_n};
+
+use ipa_step::StepNarrow;
+
+use crate::{
+    error::Error,
+    ff::{boolean::Boolean, Field},
+    protocol::{
+        basics::{BooleanProtocols, SecureMul},
+        boolean::{
+            or::bool_or,
+            step::{SaturatedAdditionStep, SaturatedSubtractionStep},
+            NBitStep,
+        },
+        context::Context,
+        Gate, RecordId,
+    },
+    secret_sharing::{replicated::semi_honest::AdditiveShare, BitDecomposed, FieldSimd},
+};
+
+/// Adds `y` to `x`. Returns the sum, which wraps around, and the carry out of its top bit.
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub async fn add<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+) -> Result<
+    (
+        BitDecomposed<AdditiveShare<Boolean, N>>,
+        AdditiveShare<Boolean, N>,
+    ),
+    Error,
+>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    let mut carry = AdditiveShare::ZERO;
+    let sum = ripple_carry_add::<_, S, N>(ctx, record_id, x, y, &mut carry).await?;
+    Ok((sum, carry))
+}
+
+/// Adds `y` to `x`, saturating at the largest value `x` can hold.
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub async fn saturating_add<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+) -> Result<BitDecomposed<AdditiveShare<Boolean, N>>, Error>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    let (sum, carry) =
+        add::<_, S, N>(ctx.narrow(&SaturatedAdditionStep::Add), record_id, x, y).await?;
+
+    // If the sum overflowed, all of its bits are set.
+    bool_or::<_, S, _, N>(
+        ctx.narrow(&SaturatedAdditionStep::Select),
+        record_id,
+        &sum,
+        repeat_n(&carry, sum.len()),
+    )
+    .await
+}
+
+/// Subtracts `y` from `x`. Returns the difference, which wraps around if `y > x`, and whether
+/// `x >= y`.
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub async fn subtract<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+) -> Result<
+    (
+        BitDecomposed<AdditiveShare<Boolean, N>>,
+        AdditiveShare<Boolean, N>,
+    ),
+    Error,
+>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    // The carry into the lowest bit of a subtraction is one, the carry out of its top bit is
+    // `x >= y`.
+    let mut carry = !AdditiveShare::<Boolean, N>::ZERO;
+    let difference = ripple_borrow_sub::<_, S, N>(ctx, record_id, x, y, &mut carry).await?;
+    Ok((difference, carry))
+}
+
+/// Subtracts `y` from `x`, saturating at zero.
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub async fn saturating_sub<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+) -> Result<BitDecomposed<AdditiveShare<Boolean, N>>, Error>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    let (difference, geq) = subtract::<_, S, N>(
+        ctx.narrow(&SaturatedSubtractionStep::Subtract),
+        record_id,
+        x,
+        y,
+    )
+    .await?;
+
+    let ctx = ctx.narrow(&SaturatedSubtractionStep::Select);
+    BitDecomposed::try_from(
+        ctx.parallel_join(difference.into_iter().enumerate().map(|(i, bit)| {
+            let ctx = ctx.narrow(&S::from(i));
+            let geq = &geq;
+            async move { bit.multiply(geq, ctx, record_id).await }
+        }))
+        .await?,
+    )
+}
+
+/// Reduces the sum of three operands to the sum of two, without propagating carries. Returns
+/// the bitwise sums and the carries out of all bits, shifted up by one bit, so that
+/// `x + y + z = sum + carries`. Both have the length of `x`, and the carry out of its top bit is
+/// dropped.
+///
+/// Unlike ripple-carry adders, all bits are computed in parallel, so adding many values with a
+/// tree of carry-save adders and a single ripple-carry adder at the end takes fewer rounds than
+/// adding them with ripple-carry adders alone.
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub async fn carry_save_add<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    z: &BitDecomposed<AdditiveShare<Boolean, N>>,
+) -> Result<
+    (
+        BitDecomposed<AdditiveShare<Boolean, N>>,
+        BitDecomposed<AdditiveShare<Boolean, N>>,
+    ),
+    Error,
+>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    let zero = AdditiveShare::<Boolean, N>::ZERO;
+    let bits = x
+        .iter()
+        .zip(y.iter().chain(repeat(&zero)))
+        .zip(z.iter().chain(repeat(&zero)))
+        .collect::<Vec<_>>();
+
+    let sum = BitDecomposed::new(bits.iter().map(|((x, y), z)| *x + *y + *z));
+    // The carry of a bit is the majority of its inputs: `x ⊕ ((x ⊕ y) ∧ (x ⊕ z))`.
+    let carries = BitDecomposed::try_from(
+        ctx.parallel_join(bits.into_iter().enumerate().map(|(i, ((x, y), z))| {
+            let ctx = ctx.narrow(&S::from(i));
+            async move {
+                (x + y)
+                    .multiply(&(x + z), ctx, record_id)
+                    .await
+                    .map(|product| product + x)
+            }
+        }))
+        .await?,
+    )?;
+
+    let carries = BitDecomposed::new(once(zero).chain(carries).take(x.len()));
+
+    Ok((sum, carries))
+}
+
+/// Ripple-carry adder. Adds `y` and the incoming `carry` to `x`, and updates `carry` to the
+/// carry out of the top bit. Implements the adder from Section 3.1 of
+/// "Improved Garbled Circuit Building Blocks and Applications to Auctions and Computing Minima"
+/// (`https://encrypto.de/papers/KSS09.pdf`).
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub(crate) async fn ripple_carry_add<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    carry: &mut AdditiveShare<Boolean, N>,
+) -> Result<BitDecomposed<AdditiveShare<Boolean, N>>, Error>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    let mut result = BitDecomposed::with_capacity(x.len());
+    for (i, (xb, yb)) in x
+        .iter()
+        .zip(y.iter().chain(repeat(&AdditiveShare::ZERO)))
+        .enumerate()
+    {
+        result.push(bit_adder(ctx.narrow(&S::from(i)), record_id, xb, yb, carry).await?);
+    }
+    Ok(result)
+}
+
+/// Ripple-borrow subtractor. Subtracts `y` from `x`, with `carry` set to one for a plain
+/// subtraction, and updates `carry` to the carry out of the top bit, which is set if the
+/// subtraction did not wrap around. Implements the subtractor from Sections 3.1 and 3.2 of the
+/// paper cited in [`ripple_carry_add`].
+///
+/// ## Errors
+/// Propagates errors from the multiplication protocol.
+pub(crate) async fn ripple_borrow_sub<C, S, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
+    carry: &mut AdditiveShare<Boolean, N>,
+) -> Result<BitDecomposed<AdditiveShare<Boolean, N>>, Error>
+where
+    C: Context,
+    S: NBitStep,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+    Gate: StepNarrow<S>,
+{
+    let mut result = BitDecomposed::with_capacity(x.len());
+    for (i, (xb, yb)) in x
+        .iter()
+        .zip(y.iter().chain(repeat(&AdditiveShare::ZERO)))
+        .enumerate()
+    {
+        result.push(bit_subtractor(ctx.narrow(&S::from(i)), record_id, xb, yb, carry).await?);
+    }
+    Ok(result)
+}
+
+/// One-bit adder with a single multiplication.
+///
+/// The sum bit is `s_i = x_i ⊕ y_i ⊕ c_i`, which is free. The carry out is
+/// `c_(i+1) = c_i ⊕ ((x_i ⊕ c_i) ∧ (y_i ⊕ c_i))`, which `carry` is updated to.
+async fn bit_adder<C, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &AdditiveShare<Boolean, N>,
+    y: &AdditiveShare<Boolean, N>,
+    carry: &mut AdditiveShare<Boolean, N>,
+) -> Result<AdditiveShare<Boolean, N>, Error>
+where
+    C: Context,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+{
+    let output = x + y + &*carry;
+
+    *carry = &*carry
+        + (x + &*carry)
+            .multiply(&(y + &*carry), ctx, record_id)
+            .await?;
+
+    Ok(output)
+}
+
+/// One-bit subtractor with a single multiplication.
+///
+/// The difference bit is `d_i = x_i ⊕ !y_i ⊕ c_i`, which is free. The carry out is
+/// `c_(i+1) = c_i ⊕ ((x_i ⊕ c_i) ∧ !(y_i ⊕ c_i))`, which `carry` is updated to.
+async fn bit_subtractor<C, const N: usize>(
+    ctx: C,
+    record_id: RecordId,
+    x: &AdditiveShare<Boolean, N>,
+    y: &AdditiveShare<Boolean, N>,
+    carry: &mut AdditiveShare<Boolean, N>,
+) -> Result<AdditiveShare<Boolean, N>, Error>
+where
+    C: Context,
+    Boolean: FieldSimd<N>,
+    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
+{
+    let output = x + !(y + &*carry);
+
+    *carry = &*carry
+        + (x + &*carry)
+            .multiply(&(!(y + &*carry)), ctx, record_id)
+            .await?;
+
+    Ok(output)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::Rng;
+
+    use super::{add, carry_save_add, saturating_add, saturating_sub, subtract};
+    use crate::{
+        ff::{
+            boolean::Boolean,
+            boolean_array::{BA16, BA8},
+            U128Conversions,
+        },
+        protocol::{boolean::step::DefaultBitStep, context::Context, RecordId},
+        rand::thread_rng,
+        secret_sharing::BitDecomposed,
+        test_executor::run,
+        test_fixture::{Reconstruct, ReconstructArr, Runner, TestWorld},
+    };
+
+    /// Number of records processed at once by the vectorized tests.
+    const N: usize = 8;
+
+    /// Decomposes `N` values of `bits` bits each into the vectorized layout the circuits take.
+    fn vectorized(bits: usize, values: [u128; N]) -> BitDecomposed<[Boolean; N]> {
+        BitDecomposed::decompose(bits, |i| values.map(|v| Boolean::from((v >> i) & 1 == 1)))
+    }
+
+    /// Recomposes the `N` values of a reconstructed vectorized result.
+    fn values(bits: &BitDecomposed<BA8>) -> [u128; N] {
+        std::array::from_fn(|record| {
+            bits.iter().enumerate().fold(0, |acc, (i, bit)| {
+                acc | ((bit.as_u128() >> record & 1) << i)
+            })
+        })
+    }
+
+    fn random_values(bits: u32) -> [u128; N] {
+        let mut rng = thread_rng();
+        std::array::from_fn(|_| rng.gen_range(0..1_u128 << bits))
+    }
+
+    #[test]
+    fn add_vectorized() {
+        run(|| async move {
+            const BITS: u32 = 12;
+            let (x, y) = (random_values(BITS), random_values(BITS));
+            let bits = usize::try_from(BITS).unwrap();
+
+            // The carry becomes the top bit of the sum.
+            let sum = TestWorld::default()
+                .dzkp_malicious(
+                    (vectorized(bits, x), vectorized(bits, y)),
+                    |ctx, (x, y)| async move {
+                        let (mut sum, carry) = add::<_, DefaultBitStep, N>(
+                            ctx.set_total_records(1),
+                            RecordId::FIRST,
+                            &x,
+                            &y,
+                        )
+                        .await
+                        .unwrap();
+                        sum.push(carry);
+                        sum
+                    },
+                )
+                .await
+                .reconstruct_arr();
+
+            let expected = std::array::from_fn(|i| x[i] + y[i]);
+            assert_eq!(expected, values(&sum), "{x:?} + {y:?}");
+        });
+    }
+
+    #[test]
+    fn saturating_add_vectorized() {
+        run(|| async move {
+            let x = [0, 1, 100, 200, 255, 128, 127, 0];
+            let y = [0, 254, 155, 56, 1, 127, 129, 255];
+
+            let sum = TestWorld::default()
+                .dzkp_malicious(
+                    (vectorized(8, x), vectorized(8, y)),
+                    |ctx, (x, y)| async move {
+                        saturating_add::<_, DefaultBitStep, N>(
+                            ctx.set_total_records(1),
+                            RecordId::FIRST,
+                            &x,
+                            &y,
+                        )
+                        .await
+                        .unwrap()
+                    },
+                )
+                .await
+                .reconstruct_arr();
+
+            let expected = std::array::from_fn(|i| (x[i] + y[i]).min(255));
+            assert_eq!(expected, values(&sum));
+        });
+    }
+
+    #[test]
+    fn subtract_vectorized() {
+        run(|| async move {
+            const BITS: u32 = 12;
+            let (x, y) = (random_values(BITS), random_values(BITS));
+            let bits = usize::try_from(BITS).unwrap();
+
+            // Whether `x >= y` becomes the top bit of the difference.
+            let difference = TestWorld::default()
+                .dzkp_malicious(
+                    (vectorized(bits, x), vectorized(bits, y)),
+                    |ctx, (x, y)| async move {
+                        let (mut difference, geq) = subtract::<_, DefaultBitStep, N>(
+                            ctx.set_total_records(1),
+                            RecordId::FIRST,
+                            &x,
+                            &y,
+                        )
+                        .await
+                        .unwrap();
+                        difference.push(geq);
+                        difference
+                    },
+                )
+                .await
+                .reconstruct_arr();
+
+            let expected = std::array::from_fn(|i| {
+                let (x, y) = (x[i], y[i]);
+                ((x + (1 << BITS) - y) % (1 << BITS)) | (u128::from(x >= y) << BITS)
+            });
+            assert_eq!(expected, values(&difference), "{x:?} - {y:?}");
+        });
+    }
+
+    #[test]
+    fn saturating_sub_vectorized() {
+        run(|| async move {
+            let x = [0, 1, 100, 200, 255, 128, 127, 0];
+            let y = [0, 2, 55, 200, 1, 129, 126, 255];
+
+            let difference = TestWorld::default()
+                .dzkp_malicious(
+                    (vectorized(8, x), vectorized(8, y)),
+                    |ctx, (x, y)| async move {
+                        saturating_sub::<_, DefaultBitStep, N>(
+                            ctx.set_total_records(1),
+                            RecordId::FIRST,
+                            &x,
+                            &y,
+                        )
+                        .await
+                        .unwrap()
+                    },
+                )
+                .await
+                .reconstruct_arr();
+
+            let expected = std::array::from_fn(|i| x[i].saturating_sub(y[i]));
+            assert_eq!(expected, values(&difference));
+        });
+    }
+
+    #[test]
+    fn carry_save_add_semi_honest() {
+        run(|| async move {
+            const BITS: usize = 16;
+            let mut rng = thread_rng();
+            // The top bits are left clear, so that the sum of three values fits into 16 bits.
+            let [x, y, z] = std::array::from_fn(|_| rng.gen_range(0..1_u128 << (BITS - 2)));
+            let [x_bits, y_bits, z_bits] =
+                [x, y, z].map(|v| BitDecomposed::new(BA16::truncate_from(v)));
+
+            // The carries follow the sums.
+            let bits = TestWorld::default()
+                .dzkp_semi_honest(((x_bits, y_bits), z_bits), |ctx, ((x, y), z)| async move {
+                    let (sum, carries) = carry_save_add::<_, DefaultBitStep, 1>(
+                        ctx.set_total_records(1),
+                        RecordId::FIRST,
+                        &x,
+                        &y,
+                        &z,
+                    )
+                    .await
+                    .unwrap();
+                    BitDecomposed::new(sum.into_iter().chain(carries))
+                })
+                .await
+                .reconstruct();
+
+            let recompose = |bits: &[Boolean]| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, b)| acc | (b.as_u128() << i))
+            };
+            assert_eq!(
+                x + y + z,
+                recompose(&bits[..BITS]) + recompose(&bits[BITS..])
+            );
+        });
+    }
+}
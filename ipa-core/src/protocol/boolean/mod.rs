@@ -4,6 +4,7 @@ use crate::protocol::boolean::step::{
     EightBitStep, SixteenBitStep, ThirtyTwoBitStep, TwoHundredFiftySixBitOpStep,
 };
 
+pub mod adder;
 pub mod and;
 pub mod or;
 pub(crate) mod step;
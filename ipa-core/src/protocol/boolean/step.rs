@@ -20,3 +20,25 @@ pub struct TwoHundredFiftySixBitOpStep(usize);
 #[derive(CompactStep)]
 #[step(count = 256, name = "bit")]
 pub struct DefaultBitStep(usize);
+
+/// FIXME: This step is not generic enough to be used in the `saturated_addition` protocol.
+/// It constrains the input to be at most 4 bytes and it will panic in runtime if it is greater
+/// than that. The issue is that compact gate requires concrete type to be put as child.
+/// If we ever see it being an issue, we should make a few implementations of this similar to what
+/// we've done for bit steps
+#[derive(CompactStep)]
+pub(crate) enum SaturatedAdditionStep {
+    #[step(child = ThirtyTwoBitStep)]
+    Add,
+    #[step(child = ThirtyTwoBitStep)]
+    Select,
+}
+
+/// Same limitation as [`SaturatedAdditionStep`].
+#[derive(CompactStep)]
+pub(crate) enum SaturatedSubtractionStep {
+    #[step(child = ThirtyTwoBitStep)]
+    Subtract,
+    #[step(child = ThirtyTwoBitStep)]
+    Select,
+}
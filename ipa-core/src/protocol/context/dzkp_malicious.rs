@@ -1,6 +1,8 @@
 use std::{
     fmt::{Debug, Formatter},
+    future::Future,
     num::NonZeroUsize,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -137,6 +139,17 @@ impl<B: ShardBinding> super::Context for DZKPUpgraded<'_, B> {
         self.base_ctx.total_records()
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        Self {
+            base_ctx: self.base_ctx.with_deadline(duration),
+            ..self.clone()
+        }
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.base_ctx.barrier()
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
         self.base_ctx.prss()
     }
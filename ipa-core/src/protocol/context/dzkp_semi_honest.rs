@@ -1,7 +1,9 @@
 use std::{
     any::type_name,
     fmt::{Debug, Formatter},
+    future::Future,
     num::NonZeroUsize,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -82,6 +84,14 @@ impl<B: ShardBinding> super::Context for DZKPUpgraded<'_, B> {
         self.inner.total_records()
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        Self::new(self.inner.with_deadline(duration))
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.inner.barrier()
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
         self.inner.prss()
     }
@@ -1,7 +1,9 @@
 use std::{
     any::type_name,
     fmt::{Debug, Formatter},
+    future::Future,
     num::NonZeroUsize,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -10,8 +12,8 @@ use ipa_step::{Step, StepNarrow};
 use crate::{
     error::Error,
     helpers::{
-        Gateway, Message, MpcMessage, MpcReceivingEnd, Role, SendingEnd, ShardReceivingEnd,
-        TotalRecords,
+        ConcurrencyStage, Gateway, Message, MpcMessage, MpcReceivingEnd, Role, SendingEnd,
+        ShardReceivingEnd, TotalRecords,
     },
     protocol::{
         basics::mul::{semi_honest_multiply, step::MaliciousMultiplyStep::RandomnessForValidation},
@@ -141,6 +143,16 @@ impl<B: ShardBinding> super::Context for Context<'_, B> {
         self.inner.total_records()
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        Self {
+            inner: self.inner.with_deadline(duration),
+        }
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.inner.barrier()
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
         self.inner.prss()
     }
@@ -183,6 +195,12 @@ impl<'a, B: ShardBinding> UpgradableContext for Context<'a, B> {
     {
         MaliciousDZKPValidator::new(self, steps, max_multiplications_per_gate)
     }
+
+    fn limit_active_work(self, stage: ConcurrencyStage) -> Self {
+        Self {
+            inner: self.inner.limit_active_work(stage),
+        }
+    }
 }
 
 impl<B: ShardBinding> SeqJoin for Context<'_, B> {
@@ -245,6 +263,29 @@ impl<'a, F: ExtendableField, B: ShardBinding> Upgraded<'a, F, B> {
         });
     }
 
+    /// Captures the state of the validation batch that `record_id` belongs to. See
+    /// [`MaliciousValidatorSnapshot`] for details.
+    ///
+    /// [`MaliciousValidatorSnapshot`]: validator::MaliciousValidatorSnapshot
+    #[must_use]
+    pub fn snapshot(&self, record_id: RecordId) -> validator::MaliciousValidatorSnapshot<F> {
+        self.with_batch(record_id, |v| v.snapshot())
+    }
+
+    /// Resumes MAC accumulation for the validation batch that `record_id` belongs to from
+    /// `snapshot`. This must be done before any record of that batch is processed by this
+    /// context.
+    ///
+    /// ## Panics
+    /// If `snapshot` was taken from a different batch.
+    pub fn restore(
+        &self,
+        record_id: RecordId,
+        snapshot: &validator::MaliciousValidatorSnapshot<F>,
+    ) {
+        self.with_batch(record_id, |v| v.restore(snapshot));
+    }
+
     /// `TestWorld` malicious methods require access to r share to perform validation.
     /// This method allows such access only in non-prod code.
     #[cfg(any(test, feature = "test-fixture"))]
@@ -319,6 +360,17 @@ impl<F: ExtendableField, B: ShardBinding> super::Context for Upgraded<'_, F, B>
         self.base_ctx.total_records()
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        Self {
+            base_ctx: self.base_ctx.with_deadline(duration),
+            ..self.clone()
+        }
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.base_ctx.barrier()
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
         self.base_ctx.prss()
     }
@@ -11,7 +11,13 @@ pub mod upgrade;
 mod batcher;
 pub mod validator;
 
-use std::{collections::HashMap, num::NonZeroUsize, pin::pin};
+use std::{
+    collections::HashMap,
+    future::Future,
+    num::NonZeroUsize,
+    pin::pin,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 pub use dzkp_malicious::DZKPUpgraded as DZKPUpgradedMaliciousContext;
@@ -36,8 +42,9 @@ pub(crate) use malicious::TEST_DZKP_STEPS;
 use crate::{
     error::Error,
     helpers::{
-        stream::ExactSizeStream, ChannelId, Direction, Gateway, Message, MpcMessage,
-        MpcReceivingEnd, Role, SendingEnd, ShardReceivingEnd, TotalRecords,
+        stream::ExactSizeStream, BroadcastingEnd, ChannelId, ConcurrencyStage, Direction, Gateway,
+        Message, MpcMessage, MpcReceivingEnd, Role, SendingEnd, ShardReceivingEnd, StepDeadline,
+        TotalRecords,
     },
     protocol::{
         context::dzkp_validator::DZKPValidator,
@@ -47,6 +54,7 @@ use crate::{
     secret_sharing::replicated::malicious::ExtendableField,
     seq_join::SeqJoin,
     sharding::{NotSharded, ShardBinding, ShardConfiguration, ShardIndex, Sharded},
+    telemetry::rounds::Depth,
     utils::NonZeroU32PowerOfTwo,
 };
 
@@ -76,6 +84,23 @@ pub trait Context: Clone + Send + Sync + SeqJoin {
     #[must_use]
     fn total_records(&self) -> TotalRecords;
 
+    /// Requires the sends and receives of this step, and of all steps below it, to complete
+    /// within `duration` from now. Once it expires, they fail with
+    /// [`StepDeadlineExceeded`] that names this step. Deadlines of the steps below can only
+    /// be shorter than this one.
+    ///
+    /// [`StepDeadlineExceeded`]: crate::helpers::Error::StepDeadlineExceeded
+    #[must_use]
+    fn with_deadline(&self, duration: Duration) -> Self;
+
+    /// Resolves once every channel of this step, and of all steps below it, has sent all of its
+    /// records and received all the records it expects. Stages that must not start before the
+    /// previous stage is fully processed, such as checkpoints or validation, can wait on this
+    /// instead of relying on the order in which futures complete.
+    ///
+    /// Channels created without a specified total number of records are not waited on.
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_;
+
     /// Get the indexed PRSS instance for this step.  It is safe to call this function
     /// multiple times.
     ///
@@ -103,9 +128,51 @@ pub trait Context: Clone + Send + Sync + SeqJoin {
     /// and this method is safe to use in multi-threaded environments.
     fn send_channel<M: MpcMessage>(&self, role: Role) -> SendingEnd<Role, M>;
 
+    /// Open communication channels to both MPC peers, for messages that are the same for both
+    /// of them. See [`BroadcastingEnd`].
+    fn broadcast_channel<M: MpcMessage>(&self) -> BroadcastingEnd<M> {
+        BroadcastingEnd::new(
+            self.send_channel(self.role().peer(Direction::Left)),
+            self.send_channel(self.role().peer(Direction::Right)),
+        )
+    }
+
     /// Requests data to be received from another MPC helper. Receive requests [`MpcReceivingEnd::receive`]
     /// can be issued from multiple threads.
     fn recv_channel<M: MpcMessage>(&self, role: Role) -> MpcReceivingEnd<M>;
+
+    /// Runs `f` for every item, each item being a separate record of this context.
+    ///
+    /// The total number of records is set to the number of items, and record ids are assigned in
+    /// the order of the items. Like [`try_join`], this keeps at most [`active_work`] records in
+    /// flight, and returns the outputs in the order of the items or the first error.
+    ///
+    /// [`try_join`]: SeqJoin::try_join
+    /// [`active_work`]: SeqJoin::active_work
+    fn parallel_records<'fut, I, F, Fut, O, E>(
+        &self,
+        items: I,
+        f: F,
+    ) -> impl Future<Output = Result<Vec<O>, E>> + Send + 'fut
+    where
+        Self: 'fut,
+        I: IntoIterator,
+        I::IntoIter: ExactSizeIterator + Send + 'fut,
+        F: Fn(Self, RecordId, I::Item) -> Fut + Send + 'fut,
+        Fut: Future<Output = Result<O, E>> + Send + 'fut,
+        O: Send + 'static,
+        E: Send + 'static,
+    {
+        let items = items.into_iter();
+        // Without items, no record is sent, so any total works.
+        let ctx = self
+            .set_total_records(TotalRecords::specified(items.len()).unwrap_or(TotalRecords::ONE));
+        self.try_join(
+            items
+                .enumerate()
+                .map(move |(i, item)| f(ctx.clone(), RecordId::from(i), item)),
+        )
+    }
 }
 
 pub trait UpgradableContext: Context {
@@ -123,6 +190,11 @@ pub trait UpgradableContext: Context {
     where
         Gate: StepNarrow<S>,
         S: Step + ?Sized;
+
+    /// Lowers active work of this context to the limit helper operators configured for
+    /// `stage`. Contexts and validators derived from it inherit the lower active work.
+    #[must_use]
+    fn limit_active_work(self, stage: ConcurrencyStage) -> Self;
 }
 
 pub type MacUpgraded<C, F> = <<C as UpgradableContext>::Validator<F> as Validator<F>>::Context;
@@ -167,6 +239,11 @@ pub struct Base<'a, B: ShardBinding = NotSharded> {
     gate: Gate,
     total_records: TotalRecords,
     active_work: NonZeroU32PowerOfTwo,
+    deadline: Option<StepDeadline>,
+    /// Communication rounds that led to this context, see [`rounds`].
+    ///
+    /// [`rounds`]: crate::telemetry::rounds
+    depth: Depth,
     /// This indicates whether the system uses sharding or no. It's not ideal that we keep it here
     /// because it gets cloned often, a potential solution to that, if this shows up on flame graph,
     /// would be to move it to [`Inner`] struct.
@@ -186,6 +263,8 @@ impl<'a, B: ShardBinding> Base<'a, B> {
             gate,
             total_records,
             active_work: gateway.config().active_work_as_power_of_two(),
+            deadline: None,
+            depth: Depth::default(),
             sharding,
         }
     }
@@ -197,20 +276,40 @@ impl<'a, B: ShardBinding> Base<'a, B> {
             ..self.clone()
         }
     }
+
+    /// Lowers active work of this context to the limit configured for `stage`, see
+    /// [`StageConcurrency`].
+    ///
+    /// [`StageConcurrency`]: crate::helpers::StageConcurrency
+    #[must_use]
+    pub fn limit_active_work(self, stage: ConcurrencyStage) -> Self {
+        let active_work = self
+            .inner
+            .gateway
+            .config()
+            .stage_concurrency
+            .active_work(stage, self.active_work);
+        self.set_active_work(active_work)
+    }
 }
 
 impl ShardedContext for Base<'_, Sharded> {
     fn shard_send_channel<M: Message>(&self, dest_shard: ShardIndex) -> SendingEnd<ShardIndex, M> {
-        self.inner.gateway.get_shard_sender(
-            &ChannelId::new(dest_shard, self.gate.clone()),
-            self.total_records,
-        )
+        self.inner
+            .gateway
+            .get_shard_sender(
+                &ChannelId::new(self.inner.gateway.query_id(), dest_shard, self.gate.clone()),
+                self.total_records,
+            )
+            .with_deadline(self.deadline.clone())
     }
 
     fn shard_recv_channel<M: Message>(&self, origin: ShardIndex) -> ShardReceivingEnd<M> {
-        self.inner
-            .gateway
-            .get_shard_receiver(&ChannelId::new(origin, self.gate.clone()))
+        self.inner.gateway.get_shard_receiver(&ChannelId::new(
+            self.inner.gateway.query_id(),
+            origin,
+            self.gate.clone(),
+        ))
     }
 
     fn cross_shard_prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
@@ -235,11 +334,14 @@ impl<B: ShardBinding> Context for Base<'_, B> {
     where
         Gate: StepNarrow<S>,
     {
+        let gate = self.gate.narrow(step);
         Self {
             inner: self.inner.clone(),
-            gate: self.gate.narrow(step),
+            depth: self.depth.narrow(&gate, self.inner.gateway.round_counter()),
+            gate,
             total_records: self.total_records,
             active_work: self.active_work,
+            deadline: self.deadline.clone(),
             sharding: self.sharding.clone(),
         }
     }
@@ -250,6 +352,8 @@ impl<B: ShardBinding> Context for Base<'_, B> {
             gate: self.gate.clone(),
             total_records: self.total_records.overwrite(total_records),
             active_work: self.active_work,
+            deadline: self.deadline.clone(),
+            depth: self.depth.clone(),
             sharding: self.sharding.clone(),
         }
     }
@@ -258,6 +362,18 @@ impl<B: ShardBinding> Context for Base<'_, B> {
         self.total_records
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        let deadline = StepDeadline::new(Instant::now() + duration, self.gate.clone());
+        Self {
+            deadline: Some(StepDeadline::earliest(self.deadline.as_ref(), deadline)),
+            ..self.clone()
+        }
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.inner.gateway.barrier(&self.gate)
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness {
         let prss = self.inner.prss.indexed(self.gate());
 
@@ -278,17 +394,25 @@ impl<B: ShardBinding> Context for Base<'_, B> {
     }
 
     fn send_channel<M: MpcMessage>(&self, role: Role) -> SendingEnd<Role, M> {
-        self.inner.gateway.get_mpc_sender(
-            &ChannelId::new(role, self.gate.clone()),
-            self.total_records,
-            self.active_work,
-        )
+        self.inner
+            .gateway
+            .get_mpc_sender(
+                &ChannelId::new(self.inner.gateway.query_id(), role, self.gate.clone()),
+                self.total_records,
+                self.active_work,
+            )
+            .with_deadline(self.deadline.clone())
     }
 
     fn recv_channel<M: MpcMessage>(&self, role: Role) -> MpcReceivingEnd<M> {
         self.inner
             .gateway
-            .get_mpc_receiver(&ChannelId::new(role, self.gate.clone()))
+            .get_mpc_receiver(
+                &ChannelId::new(self.inner.gateway.query_id(), role, self.gate.clone()),
+                self.total_records,
+            )
+            .with_deadline(self.deadline.clone())
+            .with_depth(self.depth.share())
     }
 }
 
@@ -618,7 +742,7 @@ pub trait DZKPContext: Context {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::{iter, iter::repeat, pin::Pin, task::Poll};
+    use std::{iter, iter::repeat, pin::Pin, task::Poll, time::Duration};
 
     use futures::{future::join_all, ready, stream, stream::StreamExt, try_join, Stream};
     use ipa_step::StepNarrow;
@@ -634,7 +758,7 @@ mod tests {
             boolean_array::{BA3, BA64, BA8},
             Field, Fp31, Serializable, U128Conversions,
         },
-        helpers::{Direction, Role},
+        helpers::{Direction, Error as InfraError, Role},
         protocol::{
             basics::ShareKnownValue,
             context::{
@@ -1190,4 +1314,33 @@ mod tests {
                 .reconstruct();
         });
     }
+
+    #[test]
+    #[cfg(not(feature = "shuttle"))]
+    fn deadline_exceeded() {
+        run(|| async {
+            let world = TestWorld::default();
+
+            world
+                .semi_honest((), |ctx, ()| async move {
+                    let ctx = ctx
+                        .narrow("deadline")
+                        .with_deadline(Duration::from_millis(10));
+                    let child = ctx.narrow("child").set_total_records(1);
+                    // H2 never sends anything, so H1 can't finish before the deadline.
+                    if ctx.role() == Role::H1 {
+                        let err = child
+                            .recv_channel::<Fp31>(Role::H2)
+                            .receive(RecordId::FIRST)
+                            .await
+                            .unwrap_err();
+                        assert!(matches!(
+                            err,
+                            InfraError::StepDeadlineExceeded { step, .. } if &step == ctx.gate()
+                        ));
+                    }
+                })
+                .await;
+        });
+    }
 }
@@ -1,8 +1,10 @@
 use std::{
     any::type_name,
     fmt::{Debug, Formatter},
+    future::Future,
     marker::PhantomData,
     num::NonZeroUsize,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -11,8 +13,8 @@ use ipa_step::{Step, StepNarrow};
 use crate::{
     error::Error,
     helpers::{
-        Gateway, Message, MpcMessage, MpcReceivingEnd, Role, SendingEnd, ShardReceivingEnd,
-        TotalRecords,
+        ConcurrencyStage, Gateway, Message, MpcMessage, MpcReceivingEnd, Role, SendingEnd,
+        ShardReceivingEnd, TotalRecords,
     },
     protocol::{
         context::{
@@ -132,6 +134,16 @@ impl<B: ShardBinding> super::Context for Context<'_, B> {
         self.inner.total_records()
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        Self {
+            inner: self.inner.with_deadline(duration),
+        }
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.inner.barrier()
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
         self.inner.prss()
     }
@@ -174,6 +186,12 @@ impl<'a, B: ShardBinding> UpgradableContext for Context<'a, B> {
     {
         Self::DZKPValidator::new(self.inner.narrow(steps.protocol))
     }
+
+    fn limit_active_work(self, stage: ConcurrencyStage) -> Self {
+        Self {
+            inner: self.inner.limit_active_work(stage),
+        }
+    }
 }
 
 impl<B: ShardBinding> SeqJoin for Context<'_, B> {
@@ -253,6 +271,14 @@ impl<B: ShardBinding, F: ExtendableField> super::Context for Upgraded<'_, B, F>
         self.inner.total_records()
     }
 
+    fn with_deadline(&self, duration: Duration) -> Self {
+        Self::new(self.inner.with_deadline(duration))
+    }
+
+    fn barrier(&self) -> impl Future<Output = ()> + Send + '_ {
+        self.inner.barrier()
+    }
+
     fn prss(&self) -> InstrumentedIndexedSharedRandomness<'_> {
         self.inner.prss()
     }
@@ -4,9 +4,13 @@ use std::{
     marker::PhantomData,
 };
 
+use generic_array::GenericArray;
+use typenum::Unsigned;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
 use crate::{
     error::Error,
-    ff::Field,
+    ff::{Field, Serializable},
     helpers::{Direction, TotalRecords},
     protocol::{
         basics::{check_zero::malicious_check_zero, malicious_reveal},
@@ -113,7 +117,7 @@ impl<B: ShardBinding, F: ExtendableField> Debug for SemiHonest<'_, B, F> {
 /// This means that we can locally accumulate values along the way, and only perform a tiny amount of communication when the arithmetic circuit is complete
 /// and the parties wish to validate the circuit. This makes for a very memory efficient implementation.
 ///
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, Zeroize, ZeroizeOnDrop)]
 struct AccumulatorState<T: Field> {
     u: T,
     w: T,
@@ -125,6 +129,104 @@ impl<T: Field> AccumulatorState<T> {
     }
 }
 
+/// State of a [`Malicious`] validator batch that is needed to resume MAC accumulation after
+/// the helper restarts: the local partial sums `u` and `w` and this helper's share of `r`.
+///
+/// A snapshot is as sensitive as the validator itself. Anyone holding the `r` shares of two
+/// helpers can launch an additive attack that goes undetected, so snapshots must be stored
+/// accordingly. The values are zeroed when the snapshot is dropped.
+pub struct MaliciousValidatorSnapshot<F: ExtendableField> {
+    offset: usize,
+    r_share: Replicated<F::ExtendedField>,
+    state: AccumulatorState<F::ExtendedField>,
+}
+
+impl<F: ExtendableField> MaliciousValidatorSnapshot<F> {
+    const OFFSET_LEN: usize = size_of::<u64>();
+    const VALUE_LEN: usize = <F::ExtendedField as Serializable>::Size::USIZE;
+
+    /// Size of a serialized snapshot, in bytes.
+    pub const SIZE: usize = Self::OFFSET_LEN + 4 * Self::VALUE_LEN;
+
+    /// Index of the validation batch this snapshot was taken from.
+    #[must_use]
+    pub fn batch_index(&self) -> usize {
+        self.offset
+    }
+
+    /// Serializes this snapshot into [`Self::SIZE`] bytes, which are zeroed when dropped.
+    #[must_use]
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut buf = Zeroizing::new(vec![0_u8; Self::SIZE]);
+        let (offset, mut chunks) = buf.split_at_mut(Self::OFFSET_LEN);
+        offset.copy_from_slice(&u64::try_from(self.offset).unwrap().to_le_bytes());
+        let mut values = [
+            self.state.u,
+            self.state.w,
+            self.r_share.left(),
+            self.r_share.right(),
+        ];
+        for v in &values {
+            let (chunk, rest) = chunks.split_at_mut(Self::VALUE_LEN);
+            v.serialize(GenericArray::from_mut_slice(chunk));
+            chunks = rest;
+        }
+        values.zeroize();
+
+        buf
+    }
+
+    /// Reads a snapshot that was serialized with [`Self::to_bytes`].
+    ///
+    /// ## Errors
+    /// If `bytes` is not [`Self::SIZE`] bytes long or does not contain valid field values.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != Self::SIZE {
+            return Err(Error::ParseError(
+                format!(
+                    "validator snapshot must be {} bytes long, got {}",
+                    Self::SIZE,
+                    bytes.len()
+                )
+                .into(),
+            ));
+        }
+        let (offset, values) = bytes.split_at(Self::OFFSET_LEN);
+        let offset = usize::try_from(u64::from_le_bytes(offset.try_into().unwrap()))
+            .map_err(|e| Error::ParseError(e.into()))?;
+        let mut values = values
+            .chunks_exact(Self::VALUE_LEN)
+            .map(|chunk| F::ExtendedField::deserialize(GenericArray::from_slice(chunk)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::ParseError(e.into()))?;
+        let snapshot = Self {
+            offset,
+            r_share: Replicated::new(values[2], values[3]),
+            state: AccumulatorState::new(values[0], values[1]),
+        };
+        values.zeroize();
+
+        Ok(snapshot)
+    }
+}
+
+impl<F: ExtendableField> Drop for MaliciousValidatorSnapshot<F> {
+    fn drop(&mut self) {
+        self.r_share.zeroize();
+    }
+}
+
+impl<F: ExtendableField> Debug for MaliciousValidatorSnapshot<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "MaliciousValidatorSnapshot<{:?}>(batch {})",
+            type_name::<F>(),
+            self.offset
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MaliciousAccumulator<F: ExtendableField> {
     inner: AccumulatorState<F::ExtendedField>,
@@ -338,6 +440,33 @@ impl<'a, F: ExtendableField, B: ShardBinding> Malicious<'a, F, B> {
         &self.r_share
     }
 
+    /// Captures the state of this batch, so that MAC accumulation can be resumed with
+    /// [`Self::restore`] after a restart.
+    #[must_use]
+    pub fn snapshot(&self) -> MaliciousValidatorSnapshot<F> {
+        MaliciousValidatorSnapshot {
+            offset: self.offset,
+            r_share: self.r_share.clone(),
+            state: self.accumulator.inner.clone(),
+        }
+    }
+
+    /// Replaces the `r` share and the accumulated MACs of this batch with the ones captured in
+    /// `snapshot`. Values accumulated after that are added to the restored partial sums, and
+    /// shares upgraded before the snapshot was taken remain valid inputs for this batch.
+    ///
+    /// ## Panics
+    /// If `snapshot` was taken from a different batch.
+    pub fn restore(&mut self, snapshot: &MaliciousValidatorSnapshot<F>) {
+        assert_eq!(
+            self.offset, snapshot.offset,
+            "Snapshot of batch {} can't be restored into batch {}",
+            snapshot.offset, self.offset
+        );
+        self.r_share = snapshot.r_share.clone();
+        self.accumulator.inner = snapshot.state.clone();
+    }
+
     /// Turns out local values for `u` and `w` into proper replicated shares.
     async fn propagate_u_and_w(
         &self,
@@ -386,6 +515,12 @@ impl<'a, F: ExtendableField, B: ShardBinding> Malicious<'a, F, B> {
     }
 }
 
+impl<F: ExtendableField, B: ShardBinding> Drop for Malicious<'_, F, B> {
+    fn drop(&mut self) {
+        self.r_share.zeroize();
+    }
+}
+
 impl<F: ExtendableField, B: ShardBinding> Debug for Malicious<'_, F, B> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "MaliciousValidator<{:?}>", type_name::<F>())
@@ -396,6 +531,8 @@ impl<F: ExtendableField, B: ShardBinding> Debug for Malicious<'_, F, B> {
 mod tests {
     use std::iter::{repeat, zip};
 
+    use futures::future::try_join;
+
     use crate::{
         error::Error,
         ff::{Field, Fp31, Fp32BitPrime},
@@ -403,8 +540,9 @@ mod tests {
         protocol::{
             basics::SecureMul,
             context::{
-                upgrade::Upgradable, validator::Validator, Context, UpgradableContext,
-                UpgradedContext,
+                upgrade::Upgradable,
+                validator::{MaliciousValidatorSnapshot, Validator},
+                Context, UpgradableContext, UpgradedContext,
             },
             RecordId,
         },
@@ -531,6 +669,73 @@ mod tests {
         }
     }
 
+    /// Helpers take a snapshot of the validation batch in the middle of a circuit, restart and
+    /// resume it from the snapshot. The output of the first multiplication, computed before the
+    /// restart, is multiplied with an input that is upgraded after the restart, and the batch
+    /// still validates.
+    #[tokio::test]
+    async fn snapshot_restore() -> Result<(), Error> {
+        let world = TestWorld::default();
+        let context = world.malicious_contexts();
+        let mut rng = thread_rng();
+
+        let a = rng.gen::<Fp32BitPrime>();
+        let b = rng.gen::<Fp32BitPrime>();
+        let c = rng.gen::<Fp32BitPrime>();
+
+        let shares = zip(
+            a.share_with(&mut rng),
+            zip(b.share_with(&mut rng), c.share_with(&mut rng)),
+        );
+
+        let futures = zip(context, shares).map(|(ctx, (a_share, (b_share, c_share)))| async move {
+            let ctx = ctx.set_total_records(2);
+
+            let (ab, snapshot) = {
+                let v = ctx.narrow("before_restart").validator::<Fp32BitPrime>();
+                let m_ctx = v.context();
+                let (a_malicious, b_malicious) = (a_share, b_share)
+                    .upgrade(m_ctx.clone(), RecordId::FIRST)
+                    .await?;
+                let ab = a_malicious
+                    .multiply(&b_malicious, m_ctx.clone(), RecordId::FIRST)
+                    .await?;
+                (ab, m_ctx.snapshot(RecordId::FIRST).to_bytes())
+            };
+
+            let snapshot = MaliciousValidatorSnapshot::<Fp32BitPrime>::from_bytes(&snapshot)?;
+            assert_eq!(0, snapshot.batch_index());
+
+            let v = ctx.narrow("after_restart").validator::<Fp32BitPrime>();
+            let m_ctx = v.context();
+            m_ctx.restore(RecordId::FIRST, &snapshot);
+
+            let record_id = RecordId::from(1);
+            let c_malicious = c_share.upgrade(m_ctx.clone(), record_id).await?;
+            let abc = ab.multiply(&c_malicious, m_ctx.clone(), record_id).await?;
+            // The first record was processed before the restart, it only needs to be
+            // accounted for.
+            try_join(
+                m_ctx.validate_record(RecordId::FIRST),
+                m_ctx.validate_record(record_id),
+            )
+            .await?;
+
+            Ok::<_, Error>(abc.x().access_without_downgrade().clone())
+        });
+
+        let results = join3v(futures).await;
+        assert_eq!(a * b * c, results.reconstruct());
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_wrong_size() {
+        let err = MaliciousValidatorSnapshot::<Fp31>::from_bytes(&[0; 5]).unwrap_err();
+        assert!(matches!(err, Error::ParseError(_)));
+    }
+
     /// This is a big more complex arithmetic circuit that tests the validator a bit more thoroughly
     /// input1   -
     ///              input1 * input2
@@ -4,7 +4,9 @@ pub mod step;
 use std::{convert::Infallible, f64};
 
 use futures_util::{stream, StreamExt};
+use ipa_step::{Step, StepNarrow};
 use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{
@@ -27,7 +29,7 @@ use crate::{
             step::IpaPrfStep,
         },
         prss::{FromPrss, SharedRandomness},
-        BooleanProtocols, RecordId,
+        BooleanProtocols, Gate, RecordId,
     },
     secret_sharing::{
         replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
@@ -74,6 +76,9 @@ impl Default for NoiseParams {
 const MAX_PROBABILITY: f64 = 1.0;
 const MAX_EPSILON: f64 = 20.0;
 
+/// Every helper adds one sample of discrete Laplace noise, shared with the helper to its right.
+const LAPLACE_PASSES: u32 = 3;
+
 impl NoiseParams {
     /// # Errors
     /// Will return an error if you try to construct a `NoiseParams` struct with
@@ -225,7 +230,6 @@ where
 /// # Panics
 /// may panic from asserts down in  `gen_binomial_noise`
 ///
-#[allow(clippy::too_many_lines)]
 pub async fn dp_for_histogram<C, const B: usize, OV, const SS_BITS: usize>(
     ctx: C,
     histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
@@ -242,35 +246,55 @@ where
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<OV>; B], Error = Infallible>,
 {
-    let steps = MaliciousProtocolSteps {
-        protocol: &IpaPrfStep::DifferentialPrivacy,
-        validate: &IpaPrfStep::DifferentialPrivacyValidate,
-    };
+    dp_for_histogram_with_steps::<_, _, B, OV, SS_BITS>(
+        ctx,
+        MaliciousProtocolSteps {
+            protocol: &IpaPrfStep::DifferentialPrivacy,
+            validate: &IpaPrfStep::DifferentialPrivacyValidate,
+        },
+        histogram_bin_values,
+        dp_params,
+    )
+    .await
+}
+
+/// Same as [`dp_for_histogram`], but noise is generated and validated under the given `steps`.
+/// This allows noise to be added to more than one histogram within the same query.
+/// # Errors
+/// See [`dp_for_histogram`].
+/// # Panics
+/// See [`dp_for_histogram`].
+#[allow(clippy::too_many_lines)]
+pub async fn dp_for_histogram_with_steps<C, S, const B: usize, OV, const SS_BITS: usize>(
+    ctx: C,
+    steps: MaliciousProtocolSteps<'_, S>,
+    histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
+    dp_params: DpMechanism,
+) -> Result<Vec<Replicated<OV>>, Error>
+where
+    C: UpgradableContext,
+    S: Step + ?Sized,
+    Gate: StepNarrow<S>,
+    Boolean: Vectorizable<B> + FieldSimd<B>,
+    BitDecomposed<Replicated<Boolean, B>>: FromPrss<usize>,
+    OV: BooleanArray + U128Conversions,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    Vec<Replicated<OV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<OV>; B], Error = Infallible>,
+{
     match dp_params {
         DpMechanism::NoDp => Ok(Vec::transposed_from(&histogram_bin_values)?),
         DpMechanism::Binomial { epsilon } => {
-            if epsilon <= 0.0 || epsilon > MAX_EPSILON {
-                return Err(EpsilonOutOfBounds);
-            }
-
-            let per_user_credit_cap = 2_u32.pow(u32::try_from(SS_BITS).unwrap());
-
-            let dimensions = f64::from(u32::try_from(B).unwrap());
-
-            let noise_params = NoiseParams {
-                epsilon,
-                per_user_credit_cap,
-                ell_1_sensitivity: f64::from(per_user_credit_cap),
-                ell_2_sensitivity: f64::from(per_user_credit_cap),
-                ell_infty_sensitivity: f64::from(per_user_credit_cap),
-                dimensions,
-                ..Default::default()
-            };
+            let noise_params = binomial_noise_params(epsilon, sensitivity::<SS_BITS>(), B)?;
 
             let num_bernoulli =
                 usize::try_from(find_smallest_num_bernoulli(&noise_params)).unwrap();
             let epsilon = noise_params.epsilon;
             let delta = noise_params.delta;
+            let dimensions = noise_params.dimensions;
+            let per_user_credit_cap = noise_params.per_user_credit_cap;
             tracing::info!(
                 "In dp_for_histogram with Binomial noise: \
                 epsilon = {epsilon}, \
@@ -308,11 +332,7 @@ where
             Ok(noisy_histogram)
         }
         DpMechanism::DiscreteLaplace { epsilon } => {
-            let noise_params = NoiseParams {
-                epsilon,
-                per_user_credit_cap: 2_u32.pow(u32::try_from(SS_BITS).unwrap()),
-                ..Default::default()
-            };
+            let noise_params = laplace_noise_params(epsilon, sensitivity::<SS_BITS>());
 
             let truncated_discret_laplace = OPRFPaddingDp::new(
                 noise_params.epsilon,
@@ -368,6 +388,171 @@ where
     }
 }
 
+/// Contribution of a single user that noise is calibrated to, when contributions are capped to
+/// `SS_BITS` bits.
+fn sensitivity<const SS_BITS: usize>() -> u32 {
+    2_u32.pow(u32::try_from(SS_BITS).unwrap())
+}
+
+/// Parameters of the binomial noise [`dp_for_histogram`] adds to histograms of `buckets`
+/// buckets.
+fn binomial_noise_params(
+    epsilon: f64,
+    per_user_credit_cap: u32,
+    buckets: usize,
+) -> Result<NoiseParams, Error> {
+    if epsilon <= 0.0 || epsilon > MAX_EPSILON {
+        return Err(EpsilonOutOfBounds);
+    }
+
+    Ok(NoiseParams {
+        epsilon,
+        per_user_credit_cap,
+        ell_1_sensitivity: f64::from(per_user_credit_cap),
+        ell_2_sensitivity: f64::from(per_user_credit_cap),
+        ell_infty_sensitivity: f64::from(per_user_credit_cap),
+        dimensions: f64::from(u32::try_from(buckets).unwrap()),
+        ..Default::default()
+    })
+}
+
+/// Parameters of the discrete Laplace noise [`dp_for_histogram`] adds to histograms.
+fn laplace_noise_params(epsilon: f64, per_user_credit_cap: u32) -> NoiseParams {
+    NoiseParams {
+        epsilon,
+        per_user_credit_cap,
+        ..Default::default()
+    }
+}
+
+/// Distribution of the noise added to a histogram.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NoiseMechanism {
+    /// Sum of `num_bernoulli` Bernoulli samples with success probability `success_prob`.
+    Binomial {
+        num_bernoulli: u32,
+        success_prob: f64,
+    },
+    /// Sum of `passes` samples of a discrete Laplace distribution with scale `1 / epsilon`,
+    /// truncated to `[-bound, bound]`.
+    TruncatedDiscreteLaplace { passes: u32, bound: u32 },
+}
+
+/// Machine-readable description of the noise [`dp_for_histogram`] adds to query output, so that
+/// report collectors can compute confidence intervals. It is derived from the same parameters
+/// the noise is generated with.
+///
+/// Noise is independent and identically distributed across buckets and histograms, so a
+/// single mean and standard deviation describe every bucket. They describe the noise as added
+/// by helpers, before the report collector rescales the output of sampled queries.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoiseReport {
+    pub mechanism: NoiseMechanism,
+    /// Privacy budget of every histogram. The budget of the query is split evenly between them.
+    pub epsilon: f64,
+    pub delta: f64,
+    /// Contribution of a single user the noise is calibrated to. This is the per-user cap,
+    /// rounded up to a power of two.
+    pub sensitivity: u32,
+    /// Number of noisy histograms in the output.
+    pub histograms: u32,
+    /// Number of buckets in every histogram, including those past the largest breakdown key.
+    pub buckets: usize,
+    /// Expected value of the noise in every bucket. Binomial noise is not centered, so its
+    /// mean must be subtracted from noisy values to get unbiased estimates.
+    pub mean: f64,
+    /// Standard deviation of the noise in every bucket.
+    pub std_dev: f64,
+}
+
+impl NoiseReport {
+    /// Describes the noise [`dp_for_histogram`] adds with `dp_params` to each of `histograms`
+    /// histograms of `buckets` buckets. Returns `None` if `dp_params` do not add noise.
+    ///
+    /// ## Errors
+    /// If `dp_params` are out of range, same as [`dp_for_histogram`].
+    pub fn new<const SS_BITS: usize>(
+        dp_params: DpMechanism,
+        buckets: usize,
+        histograms: u32,
+    ) -> Result<Option<Self>, Error> {
+        Self::with_sensitivity(dp_params, sensitivity::<SS_BITS>(), buckets, histograms)
+    }
+
+    /// Same as [`Self::new`], for noise calibrated to a per-user contribution of `sensitivity`,
+    /// which must be a power of two.
+    ///
+    /// ## Errors
+    /// If `dp_params` are out of range, same as [`dp_for_histogram`].
+    pub fn with_sensitivity(
+        dp_params: DpMechanism,
+        sensitivity: u32,
+        buckets: usize,
+        histograms: u32,
+    ) -> Result<Option<Self>, Error> {
+        let (mechanism, noise_params, mean, std_dev) = match dp_params {
+            DpMechanism::NoDp => return Ok(None),
+            DpMechanism::Binomial { epsilon } => {
+                let noise_params = binomial_noise_params(epsilon, sensitivity, buckets)?;
+                let (mean, std_dev) = binomial_noise_mean_std(&noise_params);
+                (
+                    NoiseMechanism::Binomial {
+                        num_bernoulli: find_smallest_num_bernoulli(&noise_params),
+                        success_prob: noise_params.success_prob,
+                    },
+                    noise_params,
+                    mean,
+                    std_dev,
+                )
+            }
+            DpMechanism::DiscreteLaplace { epsilon } => {
+                let noise_params = laplace_noise_params(epsilon, sensitivity);
+                let distribution = OPRFPaddingDp::new(
+                    noise_params.epsilon,
+                    noise_params.delta,
+                    noise_params.per_user_credit_cap,
+                )?;
+                // Samples are shifted to be symmetric around zero before they are added.
+                let bound = distribution.get_shift();
+                let (mean, std_dev) = distribution.mean_and_std();
+                let passes = f64::from(LAPLACE_PASSES);
+                (
+                    NoiseMechanism::TruncatedDiscreteLaplace {
+                        passes: LAPLACE_PASSES,
+                        bound,
+                    },
+                    noise_params,
+                    passes * (mean - f64::from(bound)),
+                    passes.sqrt() * std_dev,
+                )
+            }
+        };
+
+        Ok(Some(Self {
+            mechanism,
+            epsilon: noise_params.epsilon,
+            delta: noise_params.delta,
+            sensitivity: noise_params.per_user_credit_cap,
+            histograms,
+            buckets,
+            mean,
+            std_dev,
+        }))
+    }
+
+    /// Largest absolute value the noise can take in any bucket.
+    #[must_use]
+    pub fn max_magnitude(&self) -> u64 {
+        match self.mechanism {
+            NoiseMechanism::Binomial { num_bernoulli, .. } => u64::from(num_bernoulli),
+            NoiseMechanism::TruncatedDiscreteLaplace { passes, bound } => {
+                u64::from(passes) * u64::from(bound)
+            }
+        }
+    }
+}
+
 struct ShiftedTruncatedDiscreteLaplace {
     truncated_discrete_laplace: OPRFPaddingDp,
     shift: u32,
@@ -609,8 +794,8 @@ mod test {
         protocol::{
             dp::{
                 apply_dp_noise, delta_constraint, dp_for_histogram, epsilon_constraint, error,
-                find_smallest_num_bernoulli, gen_binomial_noise, NoiseParams,
-                ShiftedTruncatedDiscreteLaplace,
+                find_smallest_num_bernoulli, gen_binomial_noise, NoiseMechanism, NoiseParams,
+                NoiseReport, ShiftedTruncatedDiscreteLaplace,
             },
             ipa_prf::oprf_padding::insecure::OPRFPaddingDp,
         },
@@ -1016,4 +1201,57 @@ mod test {
         // println!("num_bernoulli {num_bernoulli}");
         println!("bytes_sent {bytes_sent}");
     }
+
+    #[test]
+    fn noise_report() {
+        assert_eq!(
+            None,
+            NoiseReport::new::<3>(DpMechanism::NoDp, 8, 1).unwrap()
+        );
+        assert!(NoiseReport::new::<3>(DpMechanism::Binomial { epsilon: 0.0 }, 8, 1).is_err());
+
+        let binomial = NoiseReport::new::<3>(DpMechanism::Binomial { epsilon: 1.0 }, 8, 2)
+            .unwrap()
+            .unwrap();
+        let NoiseMechanism::Binomial {
+            num_bernoulli,
+            success_prob,
+        } = binomial.mechanism
+        else {
+            panic!("expected binomial noise, got {:?}", binomial.mechanism);
+        };
+        assert_eq!(
+            num_bernoulli,
+            find_smallest_num_bernoulli(&NoiseParams {
+                epsilon: 1.0,
+                per_user_credit_cap: 8,
+                ell_1_sensitivity: 8.0,
+                ell_2_sensitivity: 8.0,
+                ell_infty_sensitivity: 8.0,
+                dimensions: 8.0,
+                ..Default::default()
+            })
+        );
+        assert!((success_prob - 0.5).abs() < f64::EPSILON);
+        assert!((binomial.mean - f64::from(num_bernoulli) / 2.0).abs() < 1e-9);
+        assert!((binomial.std_dev - f64::from(num_bernoulli).sqrt() / 2.0).abs() < 1e-9);
+        assert_eq!(
+            (8, 2, 8),
+            (binomial.sensitivity, binomial.histograms, binomial.buckets)
+        );
+
+        let laplace = NoiseReport::new::<3>(DpMechanism::DiscreteLaplace { epsilon: 1.0 }, 8, 1)
+            .unwrap()
+            .unwrap();
+        let NoiseMechanism::TruncatedDiscreteLaplace { passes, bound } = laplace.mechanism else {
+            panic!("expected Laplace noise, got {:?}", laplace.mechanism);
+        };
+        let (_, std) = OPRFPaddingDp::new(1.0, 1e-6, 8).unwrap().mean_and_std();
+        assert_eq!(3, passes);
+        assert_eq!(OPRFPaddingDp::new(1.0, 1e-6, 8).unwrap().get_shift(), bound);
+        assert!(laplace.mean.abs() < 1e-6, "{}", laplace.mean);
+        assert!((laplace.std_dev - 3_f64.sqrt() * std).abs() < 1e-9);
+        assert_eq!(3 * u64::from(bound), laplace.max_magnitude());
+        assert_eq!(u64::from(num_bernoulli), binomial.max_magnitude());
+    }
 }
@@ -30,6 +30,7 @@ use crate::{
         TransposeFrom, Vectorizable,
     },
     seq_join::seq_join,
+    utils::cooperative,
 };
 
 /// Improved Aggregation a.k.a Aggregation revealing breakdown.
@@ -109,7 +110,7 @@ where
     );
     let grouped_tvs = reveal_breakdowns(&validator.context(), attributions).await?;
     validator.validate().await?;
-    let mut intermediate_results: Vec<BitDecomposed<Replicated<Boolean, B>>> = grouped_tvs.into();
+    let mut intermediate_results = grouped_tvs.into_transposed().await;
 
     // Any real-world aggregation should be able to complete in two layers (two
     // iterations of the `while` loop below). Tests with small `TARGET_PROOF_SIZE`
@@ -222,23 +223,24 @@ impl<V: BooleanArray, const B: usize> ValueHistogram<V, B> {
     }
 }
 
-impl<V: BooleanArray, const B: usize> From<ValueHistogram<V, B>>
-    for Vec<BitDecomposed<Replicated<Boolean, B>>>
+impl<V: BooleanArray, const B: usize> ValueHistogram<V, B>
 where
     Boolean: FieldSimd<B>,
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<V>; B], Error = Infallible>,
 {
-    fn from(mut grouped_tvs: ValueHistogram<V, B>) -> Vec<BitDecomposed<Replicated<Boolean, B>>> {
-        let iter = (0..grouped_tvs.max_len).map(move |_| {
-            let slice: [Replicated<V>; B] = grouped_tvs
+    /// Transposes the values into rows of `B` values, one per breakdown. This touches every
+    /// value, so it yields to the runtime periodically.
+    async fn into_transposed(mut self) -> Vec<BitDecomposed<Replicated<Boolean, B>>> {
+        cooperative::collect((0..self.max_len).map(move |_| {
+            let slice: [Replicated<V>; B] = self
                 .tvs
                 .each_mut()
                 .map(|tv| tv.pop().unwrap_or(Replicated::ZERO));
 
             BitDecomposed::transposed_from(&slice).unwrap_infallible()
-        });
-        iter.collect()
+        }))
+        .await
     }
 }
 
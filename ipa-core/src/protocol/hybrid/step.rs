@@ -35,7 +35,7 @@ pub(crate) enum AggregateReportsStep {
 
 #[derive(CompactStep)]
 pub(crate) enum FinalizeSteps {
-    #[step(child = crate::protocol::ipa_prf::boolean_ops::step::SaturatedAdditionStep)]
+    #[step(child = crate::protocol::boolean::step::SaturatedAdditionStep)]
     Add,
     #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     Validate,
@@ -1,40 +1,41 @@
-use std::{convert::Infallible, pin::pin};
+use std::{convert::Infallible, iter, num::NonZeroUsize, pin::pin};
 
 use futures::stream;
 use futures_util::{StreamExt, TryStreamExt};
 use tracing::{info_span, Instrument};
 
-use super::aggregate_values;
+use super::{aggregate_contributions, Summation};
 use crate::{
-    error::{Error, UnwrapInfallible},
+    error::Error,
     ff::{
         boolean::Boolean,
         boolean_array::{BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA32},
-        U128Conversions,
+        ArrayAccess, Field, U128Conversions,
     },
-    helpers::TotalRecords,
+    helpers::{Direction, TotalRecords},
     protocol::{
-        basics::{reveal, Reveal},
+        basics::{reveal, Reveal, SecureMul},
+        boolean::step::ThirtyTwoBitStep,
         context::{
-            dzkp_validator::DZKPValidator, Context, DZKPUpgraded, MaliciousProtocolSteps,
-            UpgradableContext,
+            dzkp_validator::DZKPValidator, prss::InstrumentedSequentialSharedRandomness, Context,
+            DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
         },
         ipa_prf::{
-            aggregation::{
-                aggregate_values_proof_chunk, step::AggregationStep as Step, AGGREGATE_DEPTH,
-            },
-            oprf_padding::{apply_dp_padding, PaddingParameters},
+            aggregation::step::{AggregationStep as Step, PruneStep},
+            oprf_padding::{apply_dp_padding, Paddable, PaddingParameters},
             prf_sharding::{AttributionOutputs, SecretSharedAttributionOutputs},
-            shuffle::{Shuffle, Shuffleable},
+            shuffle::{MaliciousShuffleable, Shuffle, Shuffleable},
             BreakdownKey,
         },
         BooleanProtocols, RecordId,
     },
     secret_sharing::{
         replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        vector::BlockedColumns,
         BitDecomposed, FieldSimd, SharedValue, TransposeFrom, Vectorizable,
     },
     seq_join::seq_join,
+    utils::cooperative,
 };
 
 impl<BK, TV> AttributionOutputs<Replicated<BK>, Replicated<TV>>
@@ -99,6 +100,140 @@ where
     }
 }
 
+/// An attribution output, along with a bit that is set for padding rows. Outputs are padded and
+/// shuffled in this form when zero-value rows are pruned, so that pruning keeps padding rows.
+#[derive(Clone, Debug, Default)]
+struct PrunableAttributionOutputs<BK: BooleanArray, TV: BooleanArray> {
+    row: SecretSharedAttributionOutputs<BK, TV>,
+    is_padding: Replicated<Boolean>,
+}
+
+impl<BK, TV> PrunableAttributionOutputs<BK, TV>
+where
+    BK: BooleanArray,
+    TV: BooleanArray,
+{
+    fn join_fields(breakdown_key: BK, trigger_value: TV, is_padding: Boolean) -> BA32 {
+        let mut share = BA32::ZERO;
+
+        BooleanArrayWriter::new(&mut share)
+            .write(&breakdown_key)
+            .write(&trigger_value)
+            .write_boolean(is_padding);
+
+        share
+    }
+
+    fn split_fields(share: &BA32) -> (BK, TV, Boolean) {
+        let bits = BooleanArrayReader::new(share);
+        let (breakdown_key, bits) = bits.read();
+        let (trigger_value, bits) = bits.read();
+        let (is_padding, _bits) = bits.read_boolean();
+        (breakdown_key, trigger_value, is_padding)
+    }
+}
+
+impl<BK, TV> From<SecretSharedAttributionOutputs<BK, TV>> for PrunableAttributionOutputs<BK, TV>
+where
+    BK: BooleanArray,
+    TV: BooleanArray,
+{
+    fn from(row: SecretSharedAttributionOutputs<BK, TV>) -> Self {
+        Self {
+            row,
+            is_padding: Replicated::ZERO,
+        }
+    }
+}
+
+impl<BK, TV> Shuffleable for PrunableAttributionOutputs<BK, TV>
+where
+    BK: BooleanArray,
+    TV: BooleanArray,
+{
+    type Share = BA32;
+
+    fn left(&self) -> Self::Share {
+        Self::join_fields(
+            ReplicatedSecretSharing::left(&self.row.attributed_breakdown_key_bits),
+            ReplicatedSecretSharing::left(&self.row.capped_attributed_trigger_value),
+            ReplicatedSecretSharing::left(&self.is_padding),
+        )
+    }
+
+    fn right(&self) -> Self::Share {
+        Self::join_fields(
+            ReplicatedSecretSharing::right(&self.row.attributed_breakdown_key_bits),
+            ReplicatedSecretSharing::right(&self.row.capped_attributed_trigger_value),
+            ReplicatedSecretSharing::right(&self.is_padding),
+        )
+    }
+
+    fn new(l: Self::Share, r: Self::Share) -> Self {
+        debug_assert!(
+            BK::BITS + TV::BITS < Self::Share::BITS,
+            "share type {} is too small",
+            std::any::type_name::<Self::Share>(),
+        );
+
+        let left = Self::split_fields(&l);
+        let right = Self::split_fields(&r);
+
+        Self {
+            row: AttributionOutputs {
+                attributed_breakdown_key_bits: ReplicatedSecretSharing::new(left.0, right.0),
+                capped_attributed_trigger_value: ReplicatedSecretSharing::new(left.1, right.1),
+            },
+            is_padding: ReplicatedSecretSharing::new(left.2, right.2),
+        }
+    }
+}
+
+impl<BK, TV> Paddable for PrunableAttributionOutputs<BK, TV>
+where
+    BK: BooleanArray + U128Conversions,
+    TV: BooleanArray,
+{
+    /// Adds the same rows as padding for [`AttributionOutputs`] does, with the padding bit set.
+    fn add_padding_items<V: Extend<Self>, const B: usize>(
+        direction_to_excluded_helper: Direction,
+        padding_input_rows: &mut V,
+        padding_params: &PaddingParameters,
+        rng: &mut InstrumentedSequentialSharedRandomness,
+    ) -> Result<u32, Error> {
+        let mut rows = Vec::new();
+        let total_number_of_fake_rows =
+            SecretSharedAttributionOutputs::<BK, TV>::add_padding_items::<_, B>(
+                direction_to_excluded_helper,
+                &mut rows,
+                padding_params,
+                rng,
+            )?;
+        let is_padding = match direction_to_excluded_helper {
+            Direction::Left => ReplicatedSecretSharing::new(Boolean::ZERO, Boolean::ONE),
+            Direction::Right => ReplicatedSecretSharing::new(Boolean::ONE, Boolean::ZERO),
+        };
+        padding_input_rows.extend(rows.into_iter().map(|row| Self {
+            row,
+            is_padding: is_padding.clone(),
+        }));
+
+        Ok(total_number_of_fake_rows)
+    }
+
+    fn add_zero_shares<V: Extend<Self>>(
+        padding_input_rows: &mut V,
+        total_number_of_fake_rows: u32,
+    ) {
+        let mut rows = Vec::new();
+        SecretSharedAttributionOutputs::<BK, TV>::add_zero_shares(
+            &mut rows,
+            total_number_of_fake_rows,
+        );
+        padding_input_rows.extend(rows.into_iter().map(Self::from));
+    }
+}
+
 /// Improved Aggregation a.k.a Aggregation revealing breakdown.
 ///
 /// Aggregation steps happen after attribution. the input for Aggregation is a
@@ -112,29 +247,25 @@ where
 /// 1. Shuffle the data to protect privacy (see [`shuffle_attributions`]).
 /// 2. Reveal breakdown keys. This is the key difference to the previous
 ///    aggregation (see [`reveal_breakdowns`]).
-/// 3. Add all values for each breakdown.
+/// 3. Add all values for each breakdown (see [`aggregate_contributions`]).
+///
+/// See [`oblivious_aggregation`](super::bucket::oblivious_aggregation) for a variant that does not reveal breakdown keys.
 ///
-/// This protocol explicitly manages proof batches for DZKP-based malicious security by
-/// processing chunks of values from `intermediate_results.chunks()`. Procession
-/// through record IDs is not uniform for all of the gates in the protocol. The first
-/// layer of the reduction adds N pairs of records, the second layer adds N/2 pairs of
-/// records, etc. This has a few consequences:
-///   * We must specify a batch size of `usize::MAX` when calling `dzkp_validator`.
-///   * We must track record IDs across chunks, so that subsequent chunks can
-///     start from the last record ID that was used in the previous chunk.
-///   * Because the first record ID in the proof batch is set implicitly, we must
-///     guarantee that it submits multiplication intermediates before any other
-///     record. This is currently ensured by the serial operation of the aggregation
-///     protocol (i.e. by not using `seq_join`).
+/// `sum` selects how values are added up, and whether trigger values and the output are in
+/// two's complement representation (see [`Summation`]). If `prune_zero_rows` is set, rows
+/// without value are dropped after the shuffle (see [`prune_zero_rows`]).
 #[tracing::instrument(name = "breakdown_reveal_aggregation", skip_all, fields(total = attributed_values.len()))]
 pub async fn breakdown_reveal_aggregation<C, BK, TV, HV, const B: usize>(
     ctx: C,
     attributed_values: Vec<SecretSharedAttributionOutputs<BK, TV>>,
     padding_params: &PaddingParameters,
+    sum: Summation,
+    prune_zero_rows: bool,
 ) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
 where
     C: UpgradableContext + Shuffle,
     Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
     Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
     BK: BreakdownKey<B>,
     Replicated<BK>: Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>,
@@ -143,20 +274,28 @@ where
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
 {
-    // Apply DP padding for Breakdown Reveal Aggregation
-    let attributed_values_padded =
-        apply_dp_padding::<_, AttributionOutputs<Replicated<BK>, Replicated<TV>>, B>(
-            ctx.narrow(&Step::PaddingDp),
-            attributed_values,
+    let attributions = if prune_zero_rows {
+        let attributions = pad_and_shuffle::<_, PrunableAttributionOutputs<BK, TV>, B>(
+            &ctx,
+            attributed_values.into_iter().map(Into::into).collect(),
             padding_params,
         )
         .await?;
-
-    let attributions = ctx
-        .narrow(&Step::Shuffle)
-        .shuffle(attributed_values_padded)
-        .instrument(info_span!("shuffle_attribution_outputs"))
-        .await?;
+        self::prune_zero_rows(ctx.clone(), attributions).await?
+    } else {
+        pad_and_shuffle::<_, SecretSharedAttributionOutputs<BK, TV>, B>(
+            &ctx,
+            attributed_values,
+            padding_params,
+        )
+        .await?
+    };
+    if attributions.is_empty() {
+        // Nothing to add up, e.g. because pruning dropped every row.
+        return Ok(BitDecomposed::new(
+            iter::repeat(Replicated::<Boolean, B>::ZERO).take(usize::try_from(HV::BITS).unwrap()),
+        ));
+    }
 
     // Revealing the breakdowns doesn't do any multiplies, so won't make it as far as
     // doing a proof, but we need the validator to obtain an upgraded malicious context.
@@ -169,53 +308,118 @@ where
     );
     let grouped_tvs = reveal_breakdowns(&validator.context(), attributions).await?;
     validator.validate().await?;
-    let mut intermediate_results: Vec<BitDecomposed<Replicated<Boolean, B>>> = grouped_tvs.into();
-
-    // Any real-world aggregation should be able to complete in two layers (two
-    // iterations of the `while` loop below). Tests with small `TARGET_PROOF_SIZE`
-    // may exceed that.
-    let mut depth = 0;
-    let agg_proof_chunk = aggregate_values_proof_chunk(B, usize::try_from(TV::BITS).unwrap());
-
-    while intermediate_results.len() > 1 {
-        let mut record_ids = [RecordId::FIRST; AGGREGATE_DEPTH];
-        let mut next_intermediate_results = Vec::new();
-        for (chunk_counter, chunk) in intermediate_results.chunks(agg_proof_chunk).enumerate() {
-            let chunk_len = chunk.len();
-            let validator = ctx.clone().dzkp_validator(
-                MaliciousProtocolSteps {
-                    protocol: &Step::aggregate(depth),
-                    validate: &Step::aggregate_validate(depth),
-                },
-                usize::MAX, // See note about batching above.
-            );
-            let result = aggregate_values::<_, HV, B>(
-                validator.context(),
-                stream::iter(chunk).map(|v| Ok(v.clone())).boxed(),
-                chunk_len,
-                Some(&mut record_ids),
-            )
-            .await?;
-            validator.validate_indexed(chunk_counter).await?;
-            next_intermediate_results.push(result);
-        }
-        depth += 1;
-        intermediate_results = next_intermediate_results;
+    let intermediate_results = grouped_tvs.into_transposed().await;
+
+    aggregate_contributions::<_, HV, B>(
+        ctx,
+        intermediate_results,
+        usize::try_from(TV::BITS).unwrap(),
+        sum,
+    )
+    .await
+}
+
+/// Applies DP padding for breakdown reveal aggregation and shuffles the padded rows.
+async fn pad_and_shuffle<C, T, const B: usize>(
+    ctx: &C,
+    rows: Vec<T>,
+    padding_params: &PaddingParameters,
+) -> Result<Vec<T>, Error>
+where
+    C: Context + Shuffle,
+    T: Paddable + MaliciousShuffleable,
+{
+    let padded =
+        apply_dp_padding::<_, T, B>(ctx.narrow(&Step::PaddingDp), rows, padding_params).await?;
+
+    ctx.narrow(&Step::Shuffle)
+        .shuffle(padded)
+        .instrument(info_span!("shuffle_attribution_outputs"))
+        .await
+}
+
+/// Drops the shuffled rows whose trigger value is zero, unless they are padding.
+///
+/// After capping, most attribution outputs of sparse conversion data carry no value, but they
+/// still need to be revealed and added up. This computes, for every row, whether its value is
+/// nonzero or it is a padding row, and reveals only that bit. Padding rows are kept, so the
+/// number of rows per breakdown key that is revealed next is protected by the same padding as
+/// without pruning. The padding bit is shuffled along with the row, so helpers can't tell
+/// padding rows from real ones.
+///
+/// The multiplications that compute the bits are validated before any bit is revealed.
+#[tracing::instrument(name = "prune_zero_rows", skip_all, fields(total = rows.len()))]
+async fn prune_zero_rows<C, BK, TV>(
+    ctx: C,
+    rows: Vec<PrunableAttributionOutputs<BK, TV>>,
+) -> Result<Vec<SecretSharedAttributionOutputs<BK, TV>>, Error>
+where
+    C: UpgradableContext,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    BK: BooleanArray,
+    TV: BooleanArray,
+{
+    if rows.is_empty() {
+        return Ok(Vec::new());
     }
+    let total_records = TotalRecords::specified(rows.len())?;
 
-    let mut result = intermediate_results
-        .into_iter()
-        .next()
-        .expect("aggregation input must not be empty");
+    let validator = ctx.clone().dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::Prune,
+            validate: &Step::PruneValidate,
+        },
+        usize::MAX,
+    );
+    let prune_ctx = validator.context().set_total_records(total_records);
+    let keep_work = stream::iter(rows.iter()).enumerate().map(|(i, row)| {
+        let record_id = RecordId::from(i);
+        let prune_ctx = prune_ctx.clone();
+        async move {
+            // keep = is_padding OR tv_0 OR tv_1 OR ...
+            let mut keep = row.is_padding.clone();
+            for (j, bit) in row.row.capped_attributed_trigger_value.iter().enumerate() {
+                let ctx = prune_ctx
+                    .narrow(&PruneStep::NonZero)
+                    .narrow(&ThirtyTwoBitStep::from(j));
+                let both = keep.multiply(&bit, ctx, record_id).await?;
+                keep = -both + &keep + &bit;
+            }
+            Ok::<_, Error>(keep)
+        }
+    });
+    let keep = seq_join(prune_ctx.active_work(), keep_work)
+        .try_collect::<Vec<_>>()
+        .await?;
+    validator.validate().await?;
 
-    // If there were less than 2^(|ov| - |tv|) inputs, then we didn't add enough carries to produce
-    // a full-length output, so pad the output now.
-    result.resize(
-        usize::try_from(HV::BITS).unwrap(),
-        Replicated::<Boolean, B>::ZERO,
+    // As for revealing breakdowns, this validator is only needed to obtain an upgraded context.
+    let validator = ctx.dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::PruneReveal,
+            validate: &Step::PruneRevealValidate,
+        },
+        usize::MAX,
     );
+    let reveal_ctx = validator.context().set_total_records(total_records);
+    let reveal_work = stream::iter(keep).enumerate().map(|(i, keep)| {
+        let reveal_ctx = reveal_ctx.clone();
+        async move {
+            let keep = reveal(reveal_ctx, RecordId::from(i), &keep).await?;
+            Ok::<_, Error>(bool::from(Boolean::from_array(&keep)))
+        }
+    });
+    let keep = seq_join(reveal_ctx.active_work(), reveal_work)
+        .try_collect::<Vec<_>>()
+        .await?;
+    validator.validate().await?;
+
+    let kept = iter::zip(rows, keep)
+        .filter_map(|(row, keep)| keep.then_some(row.row))
+        .collect::<Vec<_>>();
+    tracing::info!("Kept {} rows after pruning zero values", kept.len());
 
-    Ok(result)
+    Ok(kept)
 }
 
 /// Transforms the Breakdown key from a secret share into a revealed `usize`.
@@ -267,45 +471,39 @@ where
 /// Key. The main functionality is to turn into a stream that can be given to
 /// [`aggregate_values`].
 struct GroupedTriggerValues<TV: BooleanArray, const B: usize> {
-    tvs: [Vec<Replicated<TV>>; B],
-    max_len: usize,
+    tvs: BlockedColumns<Replicated<TV>, B>,
 }
 
+/// Number of rows of trigger values that are transposed at a time. Larger blocks mean fewer
+/// allocations, smaller ones less memory held in both layouts at once.
+const TRANSPOSE_BLOCK_ROWS: NonZeroUsize = match NonZeroUsize::new(1024) {
+    Some(value) => value,
+    None => unreachable!(),
+};
+
 impl<TV: BooleanArray, const B: usize> GroupedTriggerValues<TV, B> {
     fn new() -> Self {
         Self {
-            tvs: std::array::from_fn(|_| vec![]),
-            max_len: 0,
+            tvs: BlockedColumns::new(TRANSPOSE_BLOCK_ROWS),
         }
     }
 
     fn push(&mut self, bk: usize, value: Replicated<TV>) {
-        self.tvs[bk].push(value);
-        if self.tvs[bk].len() > self.max_len {
-            self.max_len = self.tvs[bk].len();
-        }
+        self.tvs.push(bk, value);
     }
 }
 
-impl<TV: BooleanArray, const B: usize> From<GroupedTriggerValues<TV, B>>
-    for Vec<BitDecomposed<Replicated<Boolean, B>>>
+impl<TV: BooleanArray, const B: usize> GroupedTriggerValues<TV, B>
 where
     Boolean: FieldSimd<B>,
     BitDecomposed<Replicated<Boolean, B>>:
         for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
 {
-    fn from(
-        mut grouped_tvs: GroupedTriggerValues<TV, B>,
-    ) -> Vec<BitDecomposed<Replicated<Boolean, B>>> {
-        let iter = (0..grouped_tvs.max_len).map(move |_| {
-            let slice: [Replicated<TV>; B] = grouped_tvs
-                .tvs
-                .each_mut()
-                .map(|tv| tv.pop().unwrap_or(Replicated::ZERO));
-
-            BitDecomposed::transposed_from(&slice).unwrap_infallible()
-        });
-        iter.collect()
+    /// Transposes the values into rows of `B` values, one per breakdown. This touches every
+    /// value, so it yields to the runtime periodically. Values are released block by block as
+    /// they are transposed, see [`BlockedColumns`].
+    async fn into_transposed(self) -> Vec<BitDecomposed<Replicated<Boolean, B>>> {
+        cooperative::collect(self.tvs.into_rows()).await
     }
 }
 
@@ -326,7 +524,7 @@ pub mod tests {
             U128Conversions,
         },
         protocol::ipa_prf::{
-            aggregation::breakdown_reveal::breakdown_reveal_aggregation,
+            aggregation::{breakdown_reveal::breakdown_reveal_aggregation, Summation},
             oprf_padding::PaddingParameters,
             prf_sharding::{
                 AttributionOutputs, AttributionOutputsTestInput, SecretSharedAttributionOutputs,
@@ -377,6 +575,8 @@ pub mod tests {
                             ctx,
                             aos,
                             &PaddingParameters::no_padding(),
+                            Summation::Saturating,
+                            false,
                         )
                         .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
                             Vec::transposed_from(&d).unwrap()
@@ -432,6 +632,8 @@ pub mod tests {
                             ctx,
                             aos,
                             &PaddingParameters::relaxed(),
+                            Summation::Saturating,
+                            false,
                         )
                         .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
                             Vec::transposed_from(&d).unwrap()
@@ -448,6 +650,92 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn semi_honest_prune_zero_rows() {
+        run_with::<_, _, 3>(|| async {
+            let world = TestWorld::default();
+            let mut rng = world.rng();
+            let mut expectation = vec![0; 32];
+            let mut inputs = Vec::new();
+            for _ in 0..200 {
+                let bk = rng.gen_range(0..32);
+                let tv = if rng.gen_bool(0.1) {
+                    rng.gen_range(1u128..8)
+                } else {
+                    0
+                };
+                expectation[bk] += tv;
+                inputs.push(input_row(bk, tv));
+            }
+            let expectation = expectation; // no more mutability for safety
+            let result: Vec<_> = world
+                .semi_honest(inputs.into_iter(), |ctx, input_rows| async move {
+                    let aos = input_rows
+                        .into_iter()
+                        .map(|ti| SecretSharedAttributionOutputs {
+                            attributed_breakdown_key_bits: ti.0,
+                            capped_attributed_trigger_value: ti.1,
+                        })
+                        .collect();
+                    let r: Vec<Replicated<BA8>> =
+                        breakdown_reveal_aggregation::<_, BA5, BA3, BA8, 32>(
+                            ctx,
+                            aos,
+                            &PaddingParameters::relaxed(),
+                            Summation::Saturating,
+                            true,
+                        )
+                        .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                            Vec::transposed_from(&d).unwrap()
+                        })
+                        .await
+                        .unwrap();
+                    r
+                })
+                .await
+                .reconstruct();
+            let result = result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>();
+            assert_eq!(result, expectation);
+        });
+    }
+
+    #[test]
+    fn semi_honest_prune_all_rows() {
+        // Without padding, nothing is left to aggregate after pruning.
+        run_with::<_, _, 3>(|| async {
+            let world = TestWorld::default();
+            let inputs = (0..32).map(|bk| input_row(bk, 0)).collect::<Vec<_>>();
+            let result: Vec<_> = world
+                .semi_honest(inputs.into_iter(), |ctx, input_rows| async move {
+                    let aos = input_rows
+                        .into_iter()
+                        .map(|ti| SecretSharedAttributionOutputs {
+                            attributed_breakdown_key_bits: ti.0,
+                            capped_attributed_trigger_value: ti.1,
+                        })
+                        .collect();
+                    let r: Vec<Replicated<BA8>> =
+                        breakdown_reveal_aggregation::<_, BA5, BA3, BA8, 32>(
+                            ctx,
+                            aos,
+                            &PaddingParameters::no_padding(),
+                            Summation::Saturating,
+                            true,
+                        )
+                        .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                            Vec::transposed_from(&d).unwrap()
+                        })
+                        .await
+                        .unwrap();
+                    r
+                })
+                .await
+                .reconstruct();
+            assert!(result.iter().all(|v| v.as_u128() == 0));
+            assert_eq!(32, result.len());
+        });
+    }
+
     #[test]
     #[cfg(not(feature = "shuttle"))] // too slow
     fn malicious_happy_path() {
@@ -490,6 +778,8 @@ pub mod tests {
                         ctx,
                         aos,
                         &PaddingParameters::relaxed(),
+                        Summation::Saturating,
+                        false,
                     )
                     .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
                         Vec::transposed_from(&d).unwrap()
@@ -505,6 +795,57 @@ pub mod tests {
         });
     }
 
+    #[test]
+    #[cfg(not(feature = "shuttle"))] // too slow
+    fn malicious_prune_zero_rows() {
+        use crate::{sharding::NotSharded, test_fixture::TestWorldConfig};
+
+        run(|| async {
+            let config = TestWorldConfig::default().with_timeout_secs(60);
+            let world = TestWorld::<NotSharded>::with_config(&config);
+            let mut rng = world.rng();
+            let mut expectation = vec![0; 32];
+            let mut inputs = Vec::new();
+            for _ in 0..200 {
+                let bk = rng.gen_range(0..32);
+                let tv = if rng.gen_bool(0.1) {
+                    rng.gen_range(1u128..8)
+                } else {
+                    0
+                };
+                expectation[bk] += tv;
+                inputs.push(input_row(bk, tv));
+            }
+            let expectation = expectation; // no more mutability for safety
+            let result: Vec<_> = world
+                .malicious(inputs.into_iter(), |ctx, input_rows| async move {
+                    let aos = input_rows
+                        .into_iter()
+                        .map(|ti| SecretSharedAttributionOutputs {
+                            attributed_breakdown_key_bits: ti.0,
+                            capped_attributed_trigger_value: ti.1,
+                        })
+                        .collect();
+                    breakdown_reveal_aggregation::<_, BA5, BA3, BA8, 32>(
+                        ctx,
+                        aos,
+                        &PaddingParameters::relaxed(),
+                        Summation::Saturating,
+                        true,
+                    )
+                    .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                        Vec::transposed_from(&d).unwrap()
+                    })
+                    .await
+                    .unwrap()
+                })
+                .await
+                .reconstruct();
+            let result = result.iter().map(|v: &BA8| v.as_u128()).collect::<Vec<_>>();
+            assert_eq!(result, expectation);
+        });
+    }
+
     type PropBreakdownKey = BA5;
     type PropTriggerValue = BA3;
     type PropHistogramValue = BA8;
@@ -618,6 +959,8 @@ pub mod tests {
                             ctx,
                             inputs,
                             &PaddingParameters::no_padding(),
+                            Summation::Saturating,
+                            false,
                         ).await
                     })
                     .await
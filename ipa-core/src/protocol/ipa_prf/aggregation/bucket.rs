@@ -0,0 +1,287 @@
+//! Aggregation that does not reveal breakdown keys.
+//!
+//! [`breakdown_reveal_aggregation`] reveals the breakdown key of every attribution output after
+//! shuffling and padding them, which tells the helpers how many (possibly dummy) values fall
+//! into each bucket. This module instead moves every value into its bucket obliviously: it
+//! computes a one-hot vector over all buckets from the secret-shared breakdown key, and
+//! multiplies it by the value. Every row then contributes a secret-shared value to every
+//! bucket, most of them zero, and the contributions are added up without any reveals.
+//!
+//! This costs `B * (|bk| - 1 + |tv|)` multiplications per attribution output, where `B` is the
+//! number of buckets, instead of a reveal, but it does not need shuffling or DP padding either.
+//!
+//! [`breakdown_reveal_aggregation`]: super::breakdown_reveal::breakdown_reveal_aggregation
+
+use std::iter::repeat_n;
+
+use futures::{stream, StreamExt, TryStreamExt};
+
+use super::{aggregate_contributions, Summation};
+use crate::{
+    error::Error,
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, U128Conversions},
+    helpers::TotalRecords,
+    protocol::{
+        basics::SecureMul,
+        boolean::{
+            step::{EightBitStep, SixteenBitStep},
+            NBitStep,
+        },
+        context::{
+            dzkp_validator::{validated_seq_join, DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
+        },
+        ipa_prf::{
+            aggregation::step::{AggregationStep as Step, BucketStep},
+            prf_sharding::SecretSharedAttributionOutputs,
+            BreakdownKey,
+        },
+        BooleanProtocols, RecordId,
+    },
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        BitDecomposed, FieldSimd, SharedValue,
+    },
+    utils::non_zero_prev_power_of_two,
+};
+
+/// Returns a suitable proof chunk size (in records) for [`move_to_bucket`].
+fn move_to_bucket_proof_chunk(buckets: usize, bk_bits: usize, tv_bits: usize) -> usize {
+    non_zero_prev_power_of_two(TARGET_PROOF_SIZE / (buckets * (bk_bits - 1 + tv_bits)))
+}
+
+/// Reveal-free aggregation.
+///
+/// Takes the same input as [`breakdown_reveal_aggregation`] and produces the same histogram, but
+/// no information about the breakdown keys is revealed to the helpers at any point. The
+/// output remains secret-shared, like it is for the reveal-based aggregation.
+///
+/// `sum` selects how values are added up, and whether trigger values and the output are in
+/// two's complement representation (see [`Summation`]).
+///
+/// [`breakdown_reveal_aggregation`]: super::breakdown_reveal::breakdown_reveal_aggregation
+///
+/// # Errors
+/// Propagates errors from multiplications and validation.
+#[tracing::instrument(name = "oblivious_aggregation", skip_all, fields(total = attributed_values.len()))]
+pub async fn oblivious_aggregation<C, BK, TV, HV, const B: usize>(
+    ctx: C,
+    attributed_values: Vec<SecretSharedAttributionOutputs<BK, TV>>,
+    sum: Summation,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    BK: BreakdownKey<B>,
+    TV: BooleanArray + U128Conversions,
+    HV: BooleanArray + U128Conversions,
+{
+    if attributed_values.is_empty() {
+        return Ok(BitDecomposed::new(repeat_n(
+            Replicated::<Boolean, B>::ZERO,
+            usize::try_from(HV::BITS).unwrap(),
+        )));
+    }
+
+    let bk_bits = usize::try_from(BK::BITS).unwrap();
+    let tv_bits = usize::try_from(TV::BITS).unwrap();
+    let validator = ctx
+        .clone()
+        .set_total_records(TotalRecords::specified(attributed_values.len())?)
+        .dzkp_validator(
+            MaliciousProtocolSteps {
+                protocol: &Step::MoveToBucket,
+                validate: &Step::MoveToBucketValidate,
+            },
+            move_to_bucket_proof_chunk(B, bk_bits, tv_bits),
+        );
+    let bucket_ctx = validator.context();
+    let contributions = validated_seq_join(
+        validator,
+        stream::iter(attributed_values)
+            .enumerate()
+            .map(move |(i, row)| {
+                move_to_bucket::<_, BK, TV, B>(bucket_ctx.clone(), RecordId::from(i), row)
+            }),
+    )
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    aggregate_contributions::<_, HV, B>(ctx, contributions, tv_bits, sum).await
+}
+
+/// Returns the contribution of `row` to every bucket: the trigger value for the bucket that
+/// matches the breakdown key, and zero for all other buckets.
+async fn move_to_bucket<C, BK, TV, const B: usize>(
+    ctx: C,
+    record_id: RecordId,
+    row: SecretSharedAttributionOutputs<BK, TV>,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: Context,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+    BK: BooleanArray,
+    TV: BooleanArray,
+{
+    assert!(
+        BK::BITS <= EightBitStep::BITS,
+        "EightBitStep not large enough to accommodate the breakdown key"
+    );
+    assert!(
+        TV::BITS <= SixteenBitStep::BITS,
+        "SixteenBitStep not large enough to accommodate the trigger value"
+    );
+
+    // Lane `b` of bit `i` is set if bit `i` of the breakdown key is equal to bit `i` of `b`.
+    // Adding a public constant to both halves of every helper's share adds it to the shared value.
+    let mut bit_matches = row
+        .attributed_breakdown_key_bits
+        .to_bits()
+        .into_iter()
+        .enumerate()
+        .map(|(i, bit)| {
+            let flip = |b: usize| Boolean::from((b >> i) & 1 == 0);
+            Replicated::<Boolean, B>::from_fns(|b| bit.left() + flip(b), |b| bit.right() + flip(b))
+        });
+
+    let indicator_ctx = ctx.narrow(&BucketStep::Indicator);
+    let mut indicator = bit_matches
+        .next()
+        .expect("breakdown keys have at least one bit");
+    for (i, bit_match) in bit_matches.enumerate() {
+        indicator = indicator
+            .multiply(
+                &bit_match,
+                indicator_ctx.narrow(&EightBitStep::from(i)),
+                record_id,
+            )
+            .await?;
+    }
+
+    let value_ctx = ctx.narrow(&BucketStep::Value);
+    let indicator = &indicator;
+    BitDecomposed::try_from(
+        ctx.parallel_join(
+            row.capped_attributed_trigger_value
+                .to_bits()
+                .into_iter()
+                .enumerate()
+                .map(|(i, bit)| {
+                    let ctx = value_ctx.narrow(&SixteenBitStep::from(i));
+                    async move { indicator.multiply(&bit.expand(), ctx, record_id).await }
+                }),
+        )
+        .await?,
+    )
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::TryFutureExt;
+    use rand::seq::SliceRandom;
+
+    use super::{oblivious_aggregation, Summation};
+    use crate::{
+        ff::{
+            boolean::Boolean,
+            boolean_array::{BA16, BA3, BA5, BA8},
+            U128Conversions,
+        },
+        protocol::ipa_prf::prf_sharding::{
+            AttributionOutputsTestInput, SecretSharedAttributionOutputs,
+        },
+        rand::Rng,
+        secret_sharing::{
+            replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, TransposeFrom,
+        },
+        test_executor::run,
+        test_fixture::{Reconstruct, Runner, TestWorld},
+    };
+
+    fn input_row(bk: usize, tv: u128) -> AttributionOutputsTestInput<BA5, BA3> {
+        let bk: u128 = bk.try_into().unwrap();
+        AttributionOutputsTestInput {
+            bk: BA5::truncate_from(bk),
+            tv: BA3::truncate_from(tv),
+        }
+    }
+
+    fn expected_and_inputs<R: Rng>(
+        rng: &mut R,
+        max_value: u128,
+    ) -> (Vec<u128>, Vec<AttributionOutputsTestInput<BA5, BA3>>) {
+        let expectation = (0..32)
+            .map(|_| rng.gen_range(0u128..max_value))
+            .collect::<Vec<_>>();
+        let mut inputs = Vec::new();
+        for (bk, expected_hv) in expectation.iter().enumerate() {
+            let mut remainder = *expected_hv;
+            while remainder > 7 {
+                let tv = rng.gen_range(0u128..8);
+                remainder -= tv;
+                inputs.push(input_row(bk, tv));
+            }
+            inputs.push(input_row(bk, remainder));
+        }
+        inputs.shuffle(rng);
+        (expectation, inputs)
+    }
+
+    #[test]
+    fn semi_honest() {
+        run(|| async {
+            let world = TestWorld::default();
+            let (expectation, inputs) = expected_and_inputs(&mut world.rng(), 256);
+            let result: Vec<BA8> = world
+                .semi_honest(inputs.into_iter(), |ctx, input_rows| async move {
+                    let aos = input_rows
+                        .into_iter()
+                        .map(|ti| SecretSharedAttributionOutputs {
+                            attributed_breakdown_key_bits: ti.0,
+                            capped_attributed_trigger_value: ti.1,
+                        })
+                        .collect();
+                    oblivious_aggregation::<_, BA5, BA3, BA8, 32>(ctx, aos, Summation::Saturating)
+                        .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                            Vec::<Replicated<BA8>>::transposed_from(&d).unwrap()
+                        })
+                        .await
+                        .unwrap()
+                })
+                .await
+                .reconstruct();
+            let result = result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>();
+            assert_eq!(result, expectation);
+        });
+    }
+
+    #[test]
+    fn malicious() {
+        run(|| async {
+            let world = TestWorld::default();
+            let (expectation, inputs) = expected_and_inputs(&mut world.rng(), 512);
+            let result: Vec<BA16> = world
+                .malicious(inputs.into_iter(), |ctx, input_rows| async move {
+                    let aos = input_rows
+                        .into_iter()
+                        .map(|ti| SecretSharedAttributionOutputs {
+                            attributed_breakdown_key_bits: ti.0,
+                            capped_attributed_trigger_value: ti.1,
+                        })
+                        .collect();
+                    oblivious_aggregation::<_, BA5, BA3, BA16, 32>(ctx, aos, Summation::Saturating)
+                        .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                            Vec::<Replicated<BA16>>::transposed_from(&d).unwrap()
+                        })
+                        .await
+                        .unwrap()
+                })
+                .await
+                .reconstruct();
+            let result = result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>();
+            assert_eq!(result, expectation);
+        });
+    }
+}
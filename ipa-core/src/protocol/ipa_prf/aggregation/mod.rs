@@ -1,6 +1,7 @@
 use std::{any::type_name, cmp::max, iter, pin::Pin};
 
-use futures::{Stream, StreamExt, TryStreamExt};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
 use tracing::Instrument;
 
 use crate::{
@@ -13,9 +14,12 @@ use crate::{
     protocol::{
         basics::BooleanProtocols,
         boolean::{step::ThirtyTwoBitStep, NBitStep},
-        context::{dzkp_validator::TARGET_PROOF_SIZE, Context},
+        context::{
+            dzkp_validator::{DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
+        },
         ipa_prf::{
-            aggregation::step::{AggregateChunkStep, AggregateValuesStep},
+            aggregation::step::{AggregateChunkStep, AggregateValuesStep, AggregationStep as Step},
             boolean_ops::addition_sequential::{integer_add, integer_sat_add},
             prf_sharding::AttributionOutputs,
         },
@@ -29,6 +33,7 @@ use crate::{
 };
 
 pub(crate) mod breakdown_reveal;
+pub(crate) mod bucket;
 pub(crate) mod step;
 
 type AttributionOutputsChunk<const N: usize> = AttributionOutputs<
@@ -84,6 +89,76 @@ where
     }
 }
 
+/// Selects how attributed trigger values are added up into the output histogram.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationMethod {
+    /// Shuffles the attribution outputs and reveals their breakdown keys, so that values can be
+    /// added up per bucket. See
+    /// [`breakdown_reveal_aggregation`](breakdown_reveal::breakdown_reveal_aggregation).
+    #[default]
+    BreakdownReveal,
+    /// Moves every value into its bucket obliviously, without revealing anything. This costs
+    /// one multiplication per bucket and bit of every value. See
+    /// [`oblivious_aggregation`](bucket::oblivious_aggregation).
+    Oblivious,
+}
+
+/// Selects what a histogram bucket holds when the sum of its values does not fit the histogram
+/// value type.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum HistogramOverflow {
+    /// The largest value the type can represent. Report collectors can tell that the bucket
+    /// overflowed, but not by how much.
+    #[default]
+    Saturate,
+    /// The sum modulo `2^|HV|`. This is cheaper to compute than saturation, and meant for
+    /// queries that are known not to overflow.
+    Wrap,
+}
+
+/// Per-query options that control how attributed values are aggregated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AggregationParameters {
+    pub method: AggregationMethod,
+    /// Applies to unsigned trigger values only. Signed histograms always wrap, see
+    /// [`aggregate_signed_values`].
+    pub overflow: HistogramOverflow,
+    /// Drops attribution outputs without value before breakdown reveal aggregation reveals
+    /// breakdown keys. Semi-honest only, see
+    /// [`IpaQueryConfig::prune_zero_rows`](crate::helpers::query::IpaQueryConfig::prune_zero_rows).
+    pub prune_zero_rows: bool,
+}
+
+/// How values are added up in the aggregation tree.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Summation {
+    /// Unsigned values; sums saturate at the maximum of the output type.
+    Saturating,
+    /// Unsigned values; sums wrap around.
+    Wrapping,
+    /// Values in two's complement representation; sums wrap around.
+    Signed,
+}
+
+impl Summation {
+    #[must_use]
+    pub fn new(signed: bool, overflow: HistogramOverflow) -> Self {
+        match (signed, overflow) {
+            (true, _) => Self::Signed,
+            (false, HistogramOverflow::Saturate) => Self::Saturating,
+            (false, HistogramOverflow::Wrap) => Self::Wrapping,
+        }
+    }
+
+    fn is_signed(self) -> bool {
+        self == Self::Signed
+    }
+}
+
 /// A vector of histogram contributions for each output bucket.
 ///
 /// Aggregation is vectorized over histogram buckets, so bit 0 for every histogram bucket is stored
@@ -125,14 +200,214 @@ pub const AGGREGATE_DEPTH: usize = 24;
 /// implementation saturates at the maximum value the type can represent. It is recommended
 /// that clients select a query configuration that avoids the possibility of overflow.
 ///
-/// It might be possible to save some cost by using naive wrapping arithmetic. Another
+/// See [`aggregate_wrapping_values`] for a cheaper variant that wraps around instead. Another
 /// possibility would be to combine all carries into a single "overflow detected" bit.
 #[tracing::instrument(name = "aggregate_values", skip_all, fields(num_rows = num_rows))]
 pub async fn aggregate_values<'ctx, 'fut, C, OV, const B: usize>(
+    ctx: C,
+    aggregated_stream: Pin<Box<dyn Stream<Item = AggResult<B>> + Send + 'fut>>,
+    num_rows: usize,
+    record_ids: Option<&mut [RecordId; AGGREGATE_DEPTH]>,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    'ctx: 'fut,
+    C: Context + 'ctx,
+    OV: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+{
+    aggregate::<_, OV, B>(
+        ctx,
+        aggregated_stream,
+        num_rows,
+        record_ids,
+        Summation::Saturating,
+    )
+    .await
+}
+
+/// Same as [`aggregate_values`], but sums that overflow the `OV` type wrap around instead of
+/// saturating.
+///
+/// Once sums reach the full width of `OV`, this adds them with a plain adder and drops the
+/// carry, which saves the multiplications that saturating the output costs.
+#[tracing::instrument(name = "aggregate_wrapping_values", skip_all, fields(num_rows = num_rows))]
+pub async fn aggregate_wrapping_values<'ctx, 'fut, C, OV, const B: usize>(
+    ctx: C,
+    aggregated_stream: Pin<Box<dyn Stream<Item = AggResult<B>> + Send + 'fut>>,
+    num_rows: usize,
+    record_ids: Option<&mut [RecordId; AGGREGATE_DEPTH]>,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    'ctx: 'fut,
+    C: Context + 'ctx,
+    OV: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+{
+    aggregate::<_, OV, B>(
+        ctx,
+        aggregated_stream,
+        num_rows,
+        record_ids,
+        Summation::Wrapping,
+    )
+    .await
+}
+
+/// Same as [`aggregate_values`], but for contributions in two's complement representation.
+///
+/// Sums wrap around instead of saturating when they overflow the `OV` type, because
+/// saturation is not meaningful when contributions can cancel each other. The result is in
+/// two's complement as well.
+#[tracing::instrument(name = "aggregate_signed_values", skip_all, fields(num_rows = num_rows))]
+pub async fn aggregate_signed_values<'ctx, 'fut, C, OV, const B: usize>(
+    ctx: C,
+    aggregated_stream: Pin<Box<dyn Stream<Item = AggResult<B>> + Send + 'fut>>,
+    num_rows: usize,
+    record_ids: Option<&mut [RecordId; AGGREGATE_DEPTH]>,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    'ctx: 'fut,
+    C: Context + 'ctx,
+    OV: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+{
+    aggregate::<_, OV, B>(
+        ctx,
+        aggregated_stream,
+        num_rows,
+        record_ids,
+        Summation::Signed,
+    )
+    .await
+}
+
+/// Pads `value` to `len` bits. Signed values are padded with copies of their sign bit.
+pub(crate) fn extend_bits<const B: usize>(
+    value: &mut BitDecomposed<Replicated<Boolean, B>>,
+    len: usize,
+    signed: bool,
+) where
+    Boolean: FieldSimd<B>,
+{
+    let padding = match value.last() {
+        Some(sign) if signed => sign.clone(),
+        _ => Replicated::<Boolean, B>::ZERO,
+    };
+    value.resize(len, padding);
+}
+
+/// Adds up `contributions`, each of which holds one value per histogram bucket, into a
+/// histogram with `OV` values.
+///
+/// This explicitly manages proof batches for DZKP-based malicious security by processing
+/// chunks of `contributions`. Procession through record IDs is not uniform for all of the
+/// gates in the protocol. The first layer of the reduction adds N pairs of records, the second
+/// layer adds N/2 pairs of records, etc. This has a few consequences:
+///   * We must specify a batch size of `usize::MAX` when calling `dzkp_validator`.
+///   * We must track record IDs across chunks, so that subsequent chunks can
+///     start from the last record ID that was used in the previous chunk.
+///   * Because the first record ID in the proof batch is set implicitly, we must
+///     guarantee that it submits multiplication intermediates before any other
+///     record. This is currently ensured by the serial operation of the aggregation
+///     protocol (i.e. by not using `seq_join`).
+///
+/// `sum` selects how contributions are added up, and whether they are in two's complement
+/// representation (see [`Summation`]).
+///
+/// ## Panics
+/// If `contributions` is empty.
+pub(crate) async fn aggregate_contributions<C, OV, const B: usize>(
+    ctx: C,
+    mut contributions: Vec<BitDecomposed<Replicated<Boolean, B>>>,
+    contribution_bits: usize,
+    sum: Summation,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext,
+    OV: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+{
+    // Any real-world aggregation should be able to complete in two layers (two
+    // iterations of the `while` loop below). Tests with small `TARGET_PROOF_SIZE`
+    // may exceed that.
+    let mut depth = 0;
+    let agg_proof_chunk = aggregate_values_proof_chunk(B, contribution_bits);
+
+    while contributions.len() > 1 {
+        let mut record_ids = [RecordId::FIRST; AGGREGATE_DEPTH];
+        let mut next_contributions = Vec::new();
+        for (chunk_counter, chunk) in contributions.chunks(agg_proof_chunk).enumerate() {
+            let chunk_len = chunk.len();
+            let validator = ctx.clone().dzkp_validator(
+                MaliciousProtocolSteps {
+                    protocol: &Step::aggregate(depth),
+                    validate: &Step::aggregate_validate(depth),
+                },
+                usize::MAX, // See note about batching above.
+            );
+            let chunk_stream = stream::iter(chunk).map(|v| Ok(v.clone())).boxed();
+            let result = match sum {
+                Summation::Saturating => {
+                    aggregate_values::<_, OV, B>(
+                        validator.context(),
+                        chunk_stream,
+                        chunk_len,
+                        Some(&mut record_ids),
+                    )
+                    .await?
+                }
+                Summation::Wrapping => {
+                    aggregate_wrapping_values::<_, OV, B>(
+                        validator.context(),
+                        chunk_stream,
+                        chunk_len,
+                        Some(&mut record_ids),
+                    )
+                    .await?
+                }
+                Summation::Signed => {
+                    aggregate_signed_values::<_, OV, B>(
+                        validator.context(),
+                        chunk_stream,
+                        chunk_len,
+                        Some(&mut record_ids),
+                    )
+                    .await?
+                }
+            };
+            validator.validate_indexed(chunk_counter).await?;
+            next_contributions.push(result);
+        }
+        depth += 1;
+        contributions = next_contributions;
+    }
+
+    let mut result = contributions
+        .into_iter()
+        .next()
+        .expect("aggregation input must not be empty");
+
+    // If there were less than 2^(|ov| - |tv|) inputs, then we didn't add enough carries to produce
+    // a full-length output, so pad the output now.
+    extend_bits(
+        &mut result,
+        usize::try_from(OV::BITS).unwrap(),
+        sum.is_signed(),
+    );
+
+    Ok(result)
+}
+
+async fn aggregate<'ctx, 'fut, C, OV, const B: usize>(
     ctx: C,
     mut aggregated_stream: Pin<Box<dyn Stream<Item = AggResult<B>> + Send + 'fut>>,
     mut num_rows: usize,
     record_ids: Option<&mut [RecordId; AGGREGATE_DEPTH]>,
+    sum: Summation,
 ) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
 where
     'ctx: 'fut,
@@ -150,6 +425,7 @@ where
         OV::BITS,
     );
 
+    let signed = sum.is_signed();
     let mut record_id_store = None;
     let record_ids =
         record_ids.unwrap_or_else(|| record_id_store.insert([RecordId::FIRST; AGGREGATE_DEPTH]));
@@ -187,8 +463,15 @@ where
                             }
                             Ok(mut chunk_pair) => {
                                 assert_eq!(chunk_pair.len(), 2);
-                                let b = chunk_pair.pop().unwrap();
-                                let a = chunk_pair.pop().unwrap();
+                                let mut b = chunk_pair.pop().unwrap();
+                                let mut a = chunk_pair.pop().unwrap();
+                                if signed {
+                                    // A record passed through from a previous layer may be
+                                    // shorter than its partner.
+                                    let len = max(a.len(), b.len());
+                                    extend_bits(&mut a, len, true);
+                                    extend_bits(&mut b, len, true);
+                                }
                                 if a.len() < usize::try_from(OV::BITS).unwrap() {
                                     // If we have enough output bits, add and keep the carry.
                                     let (mut sum, carry) = integer_add::<_, AdditionStep, B>(
@@ -198,8 +481,24 @@ where
                                         &b,
                                     )
                                     .await?;
-                                    sum.push(carry);
+                                    if signed {
+                                        // The top bit of the sum of sign-extended inputs. This
+                                        // is free, unlike sign-extending the inputs.
+                                        sum.push(carry + &a[a.len() - 1] + &b[b.len() - 1]);
+                                    } else {
+                                        sum.push(carry);
+                                    }
                                     Ok(sum)
+                                } else if sum != Summation::Saturating {
+                                    // Sums are at full width, wrap around by dropping the carry.
+                                    integer_add::<_, AdditionStep, B>(
+                                        ctx.narrow(&AggregateValuesStep::Add),
+                                        record_id,
+                                        &a,
+                                        &b,
+                                    )
+                                    .await
+                                    .map(|(sum, _)| sum)
                                 } else {
                                     integer_sat_add::<C, AdditionStep, B>(
                                         ctx.narrow(&AggregateValuesStep::SaturatingAdd),
@@ -234,10 +533,7 @@ where
     );
     // If there were less than 2^(|ov| - |tv|) inputs, then we didn't add enough carries to produce
     // a full-length output, so pad the output now.
-    result.resize(
-        usize::try_from(OV::BITS).unwrap(),
-        Replicated::<Boolean, B>::ZERO,
-    );
+    extend_bits(&mut result, usize::try_from(OV::BITS).unwrap(), signed);
     // Aggregation output to remain vectorized
     Ok(result)
 }
@@ -249,11 +545,16 @@ pub mod tests {
     use futures::{stream, StreamExt};
     use proptest::prelude::*;
 
-    use super::aggregate_values;
+    use super::{
+        aggregate, aggregate_signed_values, aggregate_values, aggregate_wrapping_values, Summation,
+    };
     use crate::{
         const_assert,
         error::Error,
-        ff::{boolean::Boolean, boolean_array::BA8},
+        ff::{
+            boolean::Boolean,
+            boolean_array::{BA32, BA8},
+        },
         helpers::Role,
         secret_sharing::{BitDecomposed, SharedValue},
         test_executor::run,
@@ -403,6 +704,130 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn aggregate_wrapping() {
+        // Same input as `aggregate_saturating`, but sums wrap around
+        run(|| async move {
+            let inputs = vec![
+                Ok(input_row(7, &[0x7f, 0x40, 0x7f, 0x7f, 0, 0, 0, 0])),
+                Ok(input_row(7, &[0x7f, 0x40, 0x7f, 1, 0, 0, 0, 0])),
+                Ok(input_row(7, &[1, 0x40, 0x7f, 0x7f, 0, 0, 0, 0])),
+                Ok(input_row(7, &[0, 0x40, 0x7f, 1, 0, 0, 0, 0])),
+            ];
+            let result = TestWorld::default()
+                .dzkp_semi_honest(inputs.into_iter(), |ctx, inputs| {
+                    let num_rows = inputs.len();
+                    aggregate_wrapping_values::<_, BA8, 8>(
+                        ctx,
+                        stream::iter(inputs).boxed(),
+                        num_rows,
+                        None,
+                    )
+                })
+                .await
+                .map(Result::unwrap)
+                .reconstruct_arr();
+
+            assert_eq!(
+                result,
+                input_row(8, &[0xff_u32, 0, 0xfc, 0, 0, 0, 0, 0])
+                    .map(|x: [Boolean; 8]| x.into_iter().collect::<BA8>())
+            );
+        });
+    }
+
+    #[test]
+    fn aggregate_32_bit_boundary() {
+        // The first layer keeps the carry of 31-bit values, the second one adds at full width
+        // and has to saturate or wrap sums that reach 2^32.
+        run(|| async move {
+            for (sum, expected) in [
+                (
+                    Summation::Saturating,
+                    [u32::MAX, u32::MAX, 0x8000_0000, 0x3_fffc, 0, 0, 0, 0],
+                ),
+                (
+                    Summation::Wrapping,
+                    [0xffff_fffc, 0, 0x8000_0000, 0x3_fffc, 0, 0, 0, 0],
+                ),
+            ] {
+                let inputs = vec![
+                    Ok(input_row(
+                        31,
+                        &[0x7fff_ffff, 0x4000_0000, 0x7fff_ffff, 0xffff, 0, 0, 0, 0],
+                    )),
+                    Ok(input_row(
+                        31,
+                        &[0x7fff_ffff, 0x4000_0000, 1, 0xffff, 0, 0, 0, 0],
+                    )),
+                    Ok(input_row(
+                        31,
+                        &[0x7fff_ffff, 0x4000_0000, 0, 0xffff, 0, 0, 0, 0],
+                    )),
+                    Ok(input_row(
+                        31,
+                        &[0x7fff_ffff, 0x4000_0000, 0, 0xffff, 0, 0, 0, 0],
+                    )),
+                ];
+                let result = TestWorld::default()
+                    .dzkp_semi_honest(inputs.into_iter(), |ctx, inputs| {
+                        let num_rows = inputs.len();
+                        aggregate::<_, BA32, 8>(
+                            ctx,
+                            stream::iter(inputs).boxed(),
+                            num_rows,
+                            None,
+                            sum,
+                        )
+                    })
+                    .await
+                    .map(Result::unwrap)
+                    .reconstruct_arr();
+
+                assert_eq!(
+                    result,
+                    input_row(32, &expected).map(|x: [Boolean; 8]| x.into_iter().collect::<BA8>()),
+                    "{sum:?}",
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn aggregate_signed() {
+        // Test that signed aggregation sign-extends and wraps. An odd number of rows makes one of
+        // them pass through a layer and get added to a longer partial sum.
+        run(|| async move {
+            let inputs = vec![
+                Ok(input_row(3, &[1, 7, 3, 4, 0, 2, 5, 6])),
+                Ok(input_row(3, &[1, 7, 3, 4, 0, 7, 5, 1])),
+                Ok(input_row(3, &[1, 7, 3, 4, 0, 0, 5, 1])),
+                Ok(input_row(3, &[0, 0, 3, 4, 0, 0, 5, 1])),
+                Ok(input_row(3, &[0, 7, 3, 4, 1, 0, 5, 1])),
+            ];
+            let result = TestWorld::default()
+                .dzkp_semi_honest(inputs.into_iter(), |ctx, inputs| {
+                    let num_rows = inputs.len();
+                    aggregate_signed_values::<_, BA8, 8>(
+                        ctx,
+                        stream::iter(inputs).boxed(),
+                        num_rows,
+                        None,
+                    )
+                })
+                .await
+                .map(Result::unwrap)
+                .reconstruct_arr();
+
+            // 3, -4, 15, -20, 1, 1, -15, 2
+            assert_eq!(
+                result,
+                input_row(8, &[3_u32, 252, 15, 236, 1, 1, 241, 2])
+                    .map(|x: [Boolean; 8]| x.into_iter().collect::<BA8>())
+            );
+        });
+    }
+
     #[test]
     fn aggregate_empty() {
         run(|| async move {
@@ -3,8 +3,6 @@ use ipa_step_derive::CompactStep;
 pub(crate) enum AggregationStep {
     /// Shuffle and reveal are used in the aggregation protocol based on revealing breakdown
     /// key. Aggregation based on move to bucket approach does not need them.
-    /// When reveal-based aggregation is the default, other steps (such as `MoveToBucket`)
-    /// should be deleted
     #[step(child = crate::protocol::ipa_prf::oprf_padding::step::PaddingDpStep, name="padding_dp")]
     PaddingDp,
     #[step(child = crate::protocol::ipa_prf::shuffle::step::OPRFShuffleStep)]
@@ -12,12 +10,38 @@ pub(crate) enum AggregationStep {
     Reveal,
     #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     RevealValidate, // only partly used -- see code
+    /// Only used when zero-value rows are pruned before breakdown keys are revealed.
+    #[step(child = PruneStep)]
+    Prune,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    PruneValidate,
+    PruneReveal,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    PruneRevealValidate, // only partly used -- see code
+    #[step(child = BucketStep)]
+    MoveToBucket,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    MoveToBucketValidate,
     #[step(count = 4, child = AggregateChunkStep, name = "chunks")]
     Aggregate(usize),
     #[step(count = 4, child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     AggregateValidate(usize),
 }
 
+#[derive(CompactStep)]
+pub(crate) enum PruneStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    NonZero,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum BucketStep {
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    Indicator,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    Value,
+}
+
 // The step count here is duplicated as the AGGREGATE_DEPTH constant in the code.
 #[derive(CompactStep)]
 #[step(count = 24, child = AggregateValuesStep, name = "fold")]
@@ -27,6 +51,6 @@ pub(crate) struct AggregateChunkStep(usize);
 pub(crate) enum AggregateValuesStep {
     #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
     Add,
-    #[step(child = crate::protocol::ipa_prf::boolean_ops::step::SaturatedAdditionStep)]
+    #[step(child = crate::protocol::boolean::step::SaturatedAdditionStep)]
     SaturatingAdd,
 }
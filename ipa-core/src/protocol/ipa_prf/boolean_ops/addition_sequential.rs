@@ -1,4 +1,4 @@
-use std::iter::{repeat, repeat_n};
+use std::iter;
 
 use ipa_step::StepNarrow;
 
@@ -6,8 +6,11 @@ use crate::{
     error::Error,
     ff::boolean::Boolean,
     protocol::{
-        basics::{BooleanProtocols, SecureMul},
-        boolean::{or::bool_or, NBitStep},
+        basics::BooleanProtocols,
+        boolean::{
+            adder::{add, ripple_carry_add, saturating_add},
+            NBitStep,
+        },
         context::Context,
         Gate, RecordId,
     },
@@ -41,13 +44,10 @@ where
     AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
     Gate: StepNarrow<S>,
 {
-    let mut carry = AdditiveShare::ZERO;
-    let sum = addition_circuit::<_, S, N>(ctx, record_id, x, y, &mut carry).await?;
-    Ok((sum, carry))
+    add::<_, S, N>(ctx, record_id, x, y).await
 }
 
 /// saturated unsigned integer addition
-/// currently not used, but it is tested
 /// adds y to x, Output has same length as x (we dont seem to need support for different length)
 /// # Errors
 /// propagates errors from multiply
@@ -64,37 +64,21 @@ where
     AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
     Gate: StepNarrow<S>,
 {
-    use super::step::SaturatedAdditionStep as Step;
-
-    let mut carry = AdditiveShare::<Boolean, N>::ZERO;
-    let result =
-        addition_circuit::<_, S, N>(ctx.narrow::<Step>(&Step::Add), record_id, x, y, &mut carry)
-            .await?;
-
-    // if carry==1 then {all ones} else {result}
-    bool_or::<_, S, _, N>(
-        ctx.narrow::<Step>(&Step::Select),
-        record_id,
-        &result,
-        repeat_n(&carry, x.len()),
-    )
-    .await
+    saturating_add::<_, S, N>(ctx, record_id, x, y).await
 }
 
-/// addition using bit adder
-/// adds y to x, Output has same length as x (carries and indices of y too large for x are ignored)
-/// implementing `https://encrypto.de/papers/KSS09.pdf` from Section 3.1
-/// for all i: output[i] = x[i] + (c[i-1] + y[i])
+/// conditional two's complement negation
+/// Negates x if `condition` is set, and leaves it unchanged otherwise.
+/// Output has same length as x. Negating the minimum value of the type returns it unchanged,
+/// which is also the correct unsigned magnitude of that value.
+/// Computes `(x ⊕ condition) + condition`, with `condition` as the carry into the lowest bit.
 /// # Errors
 /// propagates errors from multiply
-///
-///
-async fn addition_circuit<C, S, const N: usize>(
+pub async fn integer_cond_negate<C, S, const N: usize>(
     ctx: C,
     record_id: RecordId,
     x: &BitDecomposed<AdditiveShare<Boolean, N>>,
-    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
-    carry: &mut AdditiveShare<Boolean, N>,
+    condition: &AdditiveShare<Boolean, N>,
 ) -> Result<BitDecomposed<AdditiveShare<Boolean, N>>, Error>
 where
     C: Context,
@@ -103,55 +87,16 @@ where
     AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
     Gate: StepNarrow<S>,
 {
-    let x = x.iter();
-    let y = y.iter();
-
-    let mut result = BitDecomposed::with_capacity(x.len());
-    for (i, (xb, yb)) in x.zip(y.chain(repeat(&AdditiveShare::ZERO))).enumerate() {
-        result.push(bit_adder(ctx.narrow(&S::from(i)), record_id, xb, yb, carry).await?);
-    }
-    Ok(result)
-}
-
-///
-/// This one-bit adder that only requires a single multiplication was taken from:
-/// "Improved Garbled Circuit Building Blocks and Applications to Auctions and Computing Minima"
-/// `https://encrypto.de/papers/KSS09.pdf`
-///
-/// Section 3.1 Integer Addition, Subtraction and Multiplication
-///
-/// For each bit, the `sum_bit` can be efficiently computed as just `s_i = x_i ⊕ y_i ⊕ c_i`
-/// This can be computed "for free" in Gf2
-///
-/// The `carry_out` bit can be efficiently computed with just a single multiplication as:
-/// `c_(i+1) = c_i ⊕ ((x_i ⊕ c_i) & (y_i ⊕ c_i))`
-///
-/// Returns `sum_bit`
-///
-/// The mutable refernce to `carry` is mutated to take on the value of the `carry_out` bit
-///
-/// # Errors
-/// propagates errors from multiply
-async fn bit_adder<C, const N: usize>(
-    ctx: C,
-    record_id: RecordId,
-    x: &AdditiveShare<Boolean, N>,
-    y: &AdditiveShare<Boolean, N>,
-    carry: &mut AdditiveShare<Boolean, N>,
-) -> Result<AdditiveShare<Boolean, N>, Error>
-where
-    C: Context,
-    Boolean: FieldSimd<N>,
-    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
-{
-    let output = x + y + &*carry;
-
-    *carry = &*carry
-        + (x + &*carry)
-            .multiply(&(y + &*carry), ctx, record_id)
-            .await?;
-
-    Ok(output)
+    let flipped = BitDecomposed::new(x.iter().map(|b| b + condition));
+    let mut carry = condition.clone();
+    ripple_carry_add::<_, S, N>(
+        ctx,
+        record_id,
+        &flipped,
+        &BitDecomposed::new(iter::empty()),
+        &mut carry,
+    )
+    .await
 }
 
 #[cfg(all(test, unit_test))]
@@ -160,13 +105,16 @@ mod test {
 
     use crate::{
         ff::{
-            boolean_array::{BA16, BA32, BA64},
+            boolean::Boolean,
+            boolean_array::{BA16, BA32, BA64, BA8},
             ArrayAccess, U128Conversions,
         },
         protocol::{
             boolean::step::DefaultBitStep,
             context::Context,
-            ipa_prf::boolean_ops::addition_sequential::{integer_add, integer_sat_add},
+            ipa_prf::boolean_ops::addition_sequential::{
+                integer_add, integer_cond_negate, integer_sat_add,
+            },
             RecordId,
         },
         rand::thread_rng,
@@ -308,4 +256,40 @@ mod test {
             );
         });
     }
+
+    #[test]
+    fn semi_honest_cond_negate() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            for (x, negate) in [
+                (5_u128, true),
+                (5, false),
+                (0, true),
+                (0xfb, true),
+                (0x80, true),
+            ] {
+                let x_ba8 = BA8::truncate_from(x);
+                let expected = if negate { (256 - x) % 256 } else { x };
+
+                let result = world
+                    .dzkp_semi_honest(
+                        (x_ba8, Boolean::from(negate)),
+                        |ctx, (x, negate)| async move {
+                            integer_cond_negate::<_, DefaultBitStep, 1>(
+                                ctx.set_total_records(1),
+                                RecordId::FIRST,
+                                &x.to_bits(),
+                                &negate,
+                            )
+                            .await
+                            .unwrap()
+                        },
+                    )
+                    .await
+                    .reconstruct();
+                assert_eq!((x, negate, result.as_u128()), (x, negate, expected));
+            }
+        });
+    }
 }
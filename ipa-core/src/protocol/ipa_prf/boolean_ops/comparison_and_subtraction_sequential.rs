@@ -3,8 +3,8 @@
 //! Implementations in this module require that if the bit-width of the second (y) operand exceeds
 //! the bit-width of the first (x) operand, then the excess bits of y must be zero. This condition
 //! is abbreviated below as `length(x) >= log2(y)`.
-
-use std::iter::repeat;
+//!
+//! The circuits themselves are in [`crate::protocol::boolean::adder`].
 
 use ipa_step::StepNarrow;
 
@@ -12,8 +12,11 @@ use crate::{
     error::Error,
     ff::{boolean::Boolean, boolean_array::BooleanArray, Field},
     protocol::{
-        basics::{select, BooleanArrayMul, BooleanProtocols, SecureMul, ShareKnownValue},
-        boolean::NBitStep,
+        basics::{BooleanProtocols, ShareKnownValue},
+        boolean::{
+            adder::{ripple_borrow_sub, saturating_sub, subtract},
+            NBitStep,
+        },
         context::Context,
         Gate, RecordId,
     },
@@ -38,11 +41,9 @@ where
     AdditiveShare<Boolean>: BooleanProtocols<C>,
     Gate: StepNarrow<S>,
 {
-    // we need to initialize carry to 1 for x>=y,
-    let mut carry = AdditiveShare::<Boolean>::share_known_value(&ctx, Boolean::ONE);
     // We don't care about the subtraction, we just want the carry
-    subtraction_circuit::<_, S, 1>(ctx, record_id, x, y, &mut carry).await?;
-    Ok(carry)
+    let (_, geq) = subtract::<_, S, 1>(ctx, record_id, x, y).await?;
+    Ok(geq)
 }
 
 /// Comparison operation
@@ -64,7 +65,7 @@ where
 {
     // we need to initialize carry to 0 for x>y
     let mut carry = AdditiveShare::<Boolean, N>::ZERO;
-    subtraction_circuit::<_, S, N>(ctx, record_id, x, y, &mut carry).await?;
+    ripple_borrow_sub::<_, S, N>(ctx, record_id, x, y, &mut carry).await?;
     Ok(carry)
 }
 
@@ -88,7 +89,33 @@ where
 {
     // we need to initialize carry to 1 for a subtraction
     let mut carry = AdditiveShare::<Boolean>::share_known_value(&ctx, Boolean::ONE);
-    subtraction_circuit::<_, S, 1>(ctx, record_id, x, y, &mut carry).await
+    ripple_borrow_sub::<_, S, 1>(ctx, record_id, x, y, &mut carry).await
+}
+
+/// unsigned integer subtraction that also outputs x>=y
+/// subtracts y from x, Output has same length as x, together with a bit that is set when x>=y,
+/// i.e. when the subtraction did not wrap around. Only correct when length(x) >= log2(y).
+/// # Errors
+/// propagates errors from multiply
+pub async fn integer_sub_geq<C, S>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean>>,
+    y: &BitDecomposed<AdditiveShare<Boolean>>,
+) -> Result<
+    (
+        BitDecomposed<AdditiveShare<Boolean>>,
+        AdditiveShare<Boolean>,
+    ),
+    Error,
+>
+where
+    C: Context,
+    S: NBitStep,
+    AdditiveShare<Boolean>: BooleanProtocols<C>,
+    Gate: StepNarrow<S>,
+{
+    subtract::<_, S, 1>(ctx, record_id, x, y).await
 }
 
 /// saturated unsigned integer subtraction
@@ -107,105 +134,16 @@ where
     C: Context,
     S: BooleanArray,
     St: NBitStep,
-    AdditiveShare<S>: BooleanArrayMul<C>,
     AdditiveShare<Boolean>: BooleanProtocols<C>,
     Gate: StepNarrow<St>,
 {
-    use super::step::SaturatedSubtractionStep as Step;
     use crate::ff::ArrayAccess;
 
-    let mut carry = !AdditiveShare::<Boolean>::ZERO;
-    let result = subtraction_circuit::<_, St, 1>(
-        ctx.narrow::<Step>(&Step::Subtract),
-        record_id,
-        &x.to_bits(),
-        &y.to_bits(),
-        &mut carry,
-    )
-    .await?
-    .collect_bits();
-
-    // carry computes carry=(x>=y)
-    // if carry==0 then {zero} else {result}
-    select(
-        ctx.narrow::<Step>(&Step::Select),
-        record_id,
-        &carry,
-        &result,
-        &AdditiveShare::<S>::ZERO,
+    Ok(
+        saturating_sub::<_, St, 1>(ctx, record_id, &x.to_bits(), &y.to_bits())
+            .await?
+            .collect_bits(),
     )
-    .await
-}
-
-/// subtraction using bit subtractor
-/// subtracts y from x, Output has same length as x (carries and indices of y too large for x are ignored,
-/// so only correct when length(x) >= log2(y)).
-/// Implements `https://encrypto.de/papers/KSS09.pdf` from Section 3.1/3.2
-///
-/// # Errors
-/// propagates errors from multiply
-async fn subtraction_circuit<C, S, const N: usize>(
-    ctx: C,
-    record_id: RecordId,
-    x: &BitDecomposed<AdditiveShare<Boolean, N>>,
-    y: &BitDecomposed<AdditiveShare<Boolean, N>>,
-    carry: &mut AdditiveShare<Boolean, N>,
-) -> Result<BitDecomposed<AdditiveShare<Boolean, N>>, Error>
-where
-    C: Context,
-    S: NBitStep,
-    Boolean: FieldSimd<N>,
-    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
-    Gate: StepNarrow<S>,
-{
-    let x = x.iter();
-    let y = y.iter();
-
-    let mut result = BitDecomposed::with_capacity(x.len());
-
-    for (i, (xb, yb)) in x
-        .zip(y.chain(repeat(&AdditiveShare::<Boolean, N>::ZERO)))
-        .enumerate()
-    {
-        result.push(bit_subtractor(ctx.narrow(&S::from(i)), record_id, xb, yb, carry).await?);
-    }
-    Ok(result)
-}
-
-/// This improved one-bit subtractor that only requires a single multiplication was taken from:
-/// "Improved Garbled Circuit Building Blocks and Applications to Auctions and Computing Minima"
-/// `https://encrypto.de/papers/KSS09.pdf`
-/// Section 3.1 Integer Addition, Subtraction and Multiplication
-///
-/// For each bit, the `difference_bit` denoted with `result` can be efficiently computed as:
-/// `d_i = x_i ⊕ !y_i ⊕ c_i` i.e. `result = x + !(c + y)`
-///
-/// The `carry_out` bit can be efficiently computed with just a single multiplication as:
-/// `c_(i+1) = c_i ⊕ ((x_i ⊕ c_i) ∧ !(y_i ⊕ c_i))`
-/// i.e. update `carry` to `carry = ( x + carry)(!(y + carry)) + carry`
-///
-/// # Errors
-/// propagates errors from multiply
-async fn bit_subtractor<C, const N: usize>(
-    ctx: C,
-    record_id: RecordId,
-    x: &AdditiveShare<Boolean, N>,
-    y: &AdditiveShare<Boolean, N>,
-    carry: &mut AdditiveShare<Boolean, N>,
-) -> Result<AdditiveShare<Boolean, N>, Error>
-where
-    C: Context,
-    Boolean: FieldSimd<N>,
-    AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
-{
-    let output = x + !(y + &*carry);
-
-    *carry = &*carry
-        + (x + &*carry)
-            .multiply(&(!(y + &*carry)), ctx, record_id)
-            .await?;
-
-    Ok(output)
 }
 
 #[cfg(all(test, unit_test))]
@@ -231,7 +169,7 @@ mod test {
             boolean::step::DefaultBitStep,
             context::Context,
             ipa_prf::boolean_ops::comparison_and_subtraction_sequential::{
-                compare_geq, compare_gt, integer_sat_sub, integer_sub,
+                compare_geq, compare_gt, integer_sat_sub, integer_sub, integer_sub_geq,
             },
             RecordId,
         },
@@ -545,6 +483,37 @@ mod test {
         });
     }
 
+    #[test]
+    fn semi_honest_sub_geq() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let mut rng = thread_rng();
+
+            let records: Vec<BA64> = vec![rng.gen::<BA64>(), rng.gen::<BA64>()];
+            let x = records[0].as_u128();
+            let y = records[1].as_u128();
+            let z = 1_u128 << 64;
+
+            let [(d0, g0), (d1, g1), (d2, g2)] = world
+                .dzkp_semi_honest(records.into_iter(), |ctx, x_y| async move {
+                    integer_sub_geq::<_, DefaultBitStep>(
+                        ctx.set_total_records(1),
+                        protocol::RecordId(0),
+                        &x_y[0].to_bits(),
+                        &x_y[1].to_bits(),
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await;
+            let difference = [d0, d1, d2].reconstruct().as_u128();
+            let geq = [g0, g1, g2].reconstruct();
+            assert_eq!((x, y, difference), (x, y, ((x + z) - y) % z));
+            assert_eq!((x, y, geq), (x, y, Boolean::from(x >= y)));
+        });
+    }
+
     #[test]
     fn semi_honest_sat_sub() {
         run(|| async move {
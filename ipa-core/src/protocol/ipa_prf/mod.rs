@@ -1,6 +1,9 @@
 use std::{cmp::max, convert::Infallible, iter::zip, num::NonZeroU32, ops::Add};
 
-use futures::{stream, StreamExt, TryStreamExt};
+use futures::{
+    future::{try_join, try_join3},
+    stream, StreamExt, TryStreamExt,
+};
 use generic_array::{ArrayLength, GenericArray};
 use tracing::{info_span, Instrument};
 use typenum::{Const, Unsigned, U18};
@@ -11,30 +14,33 @@ use crate::{
     ff::{
         boolean::Boolean,
         boolean_array::{
-            BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA112, BA5, BA64, BA8,
+            BooleanArray, BooleanArrayReader, BooleanArrayWriter, BA10, BA112, BA12, BA5, BA64, BA8,
         },
         curve_points::RP25519,
         ec_prime_field::Fp25519,
-        Serializable, U128Conversions,
+        ArrayAccess, Serializable, U128Conversions,
     },
     helpers::{
         stream::{div_round_up, process_slice_by_chunks, Chunk, ChunkData, TryFlattenItersExt},
-        TotalRecords,
+        ConcurrencyStage, Direction, TotalRecords,
     },
     protocol::{
-        basics::{BooleanArrayMul, BooleanProtocols, Reveal},
+        basics::{paranoid, BooleanArrayMul, BooleanProtocols, Reveal},
         context::{
             dzkp_validator::{DZKPValidator, TARGET_PROOF_SIZE},
-            DZKPUpgraded, MacUpgraded, MaliciousProtocolSteps, UpgradableContext,
+            Context, DZKPUpgraded, MacUpgraded, MaliciousProtocolSteps, UpgradableContext,
         },
         ipa_prf::{
             boolean_ops::convert_to_fp25519,
             oprf_padding::apply_dp_padding,
             prf_eval::{eval_dy_prf, gen_prf_key},
             prf_sharding::{
-                attribute_cap_aggregate, histograms_ranges_sortkeys, PrfShardedIpaInputRow,
+                attribute_cap_aggregate, credit_capping::CappingParameters, group_by_key,
+                histograms_ranges_sortkeys, time_to_conversion::TimeToConversionBuckets,
+                PrfShardedIpaInputRow,
             },
-            step::IpaPrfStep,
+            step::{IpaPrfStep, ParanoidCheckStep},
+            timestamp_bounds::{count_suppressed, suppress_out_of_range},
         },
         prss::FromPrss,
         RecordId,
@@ -44,7 +50,8 @@ use crate::{
         BitDecomposed, FieldSimd, SharedValue, TransposeFrom, Vectorizable,
     },
     seq_join::seq_join,
-    utils::non_zero_prev_power_of_two,
+    telemetry::progress,
+    utils::{cooperative, non_zero_prev_power_of_two},
 };
 
 pub(crate) mod aggregation;
@@ -55,15 +62,20 @@ pub mod prf_sharding;
 
 mod malicious_security;
 mod quicksort;
+mod sampling;
 pub(crate) mod shuffle;
 pub(crate) mod step;
+pub mod timestamp_bounds;
 pub mod validation_protocol;
 
+pub use aggregation::{AggregationMethod, AggregationParameters, HistogramOverflow};
 pub use malicious_security::{
     CompressedProofGenerator, FirstProofGenerator, LagrangeTable, ProverTableIndices,
     VerifierTableIndices,
 };
+pub use sampling::UserSampling;
 pub use shuffle::Shuffle;
+pub use timestamp_bounds::TimestampBounds;
 
 /// Match key type
 pub type MatchKey = BA64;
@@ -86,6 +98,8 @@ pub const MK_BITS: usize = BA64::BITS as usize;
 pub trait BreakdownKey<const MAX_BREAKDOWNS: usize>: BooleanArray + U128Conversions {}
 impl BreakdownKey<32> for BA5 {}
 impl BreakdownKey<256> for BA8 {}
+impl BreakdownKey<1024> for BA10 {}
+impl BreakdownKey<4096> for BA12 {}
 
 /// Vectorization dimension for share conversion
 pub const CONV_CHUNK: usize = 256;
@@ -105,7 +119,7 @@ use crate::{
     helpers::query::DpMechanism,
     protocol::{
         context::Validator,
-        dp::dp_for_histogram,
+        dp::{dp_for_histogram, dp_for_histogram_with_steps, NoiseReport},
         ipa_prf::{oprf_padding::PaddingParameters, prf_eval::PrfSharing},
     },
     secret_sharing::replicated::semi_honest::AdditiveShare,
@@ -301,6 +315,69 @@ where
     }
 }
 
+/// Input of [`oprf_ipa_with_partial_results`].
+pub enum IpaInput<BK: SharedValue, TV: SharedValue, TS: SharedValue> {
+    /// Rows as submitted by the report collector. They are padded, shuffled and PRF'd before
+    /// attribution.
+    Reports(Vec<OPRFIPAInputRow<BK, TV, TS>>),
+    /// Rows that were already padded, shuffled and PRF'd by [`prf_input_rows`], possibly by an
+    /// earlier query over the same reports.
+    Prfd(Vec<PrfShardedIpaInputRow<BK, TV, TS>>),
+}
+
+impl<BK: SharedValue, TV: SharedValue, TS: SharedValue> IpaInput<BK, TV, TS> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Reports(rows) => rows.len(),
+            Self::Prfd(rows) => rows.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<BK: SharedValue, TV: SharedValue, TS: SharedValue> From<Vec<OPRFIPAInputRow<BK, TV, TS>>>
+    for IpaInput<BK, TV, TS>
+{
+    fn from(rows: Vec<OPRFIPAInputRow<BK, TV, TS>>) -> Self {
+        Self::Reports(rows)
+    }
+}
+
+/// Tells which stage produced the histogram returned by [`oprf_ipa_with_partial_results`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Release {
+    /// All stages completed. Every histogram carries the noise requested by the query.
+    Final,
+    /// Adding noise to some of the histograms that follow the histogram of attributed values
+    /// failed. Those histograms are all zeros, the others carry the noise requested by the
+    /// query. This is only ever produced when the report collector opted into partial results.
+    Partial,
+}
+
+impl Release {
+    /// Encoding used in the serialized query result.
+    #[must_use]
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Final => 0,
+            Self::Partial => 1,
+        }
+    }
+
+    /// Parses the encoding produced by [`Self::to_byte`].
+    #[must_use]
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Final),
+            1 => Some(Self::Partial),
+            _ => None,
+        }
+    }
+}
+
 /// IPA OPRF Protocol
 ///
 /// The output of this function is a vector of secret-shared totals, one per breakdown key
@@ -311,13 +388,16 @@ where
 ///    information leakage) (TBD)
 /// 3. Shuffles the input
 /// 4. Computes an OPRF of these elliptic curve points and reveals this "pseudonym"
-/// 5. Groups together rows with the same OPRF, and then obliviously sorts each group by the
-///    secret-shared timestamp
+/// 5. Groups together rows with the same OPRF, without sorting them by it, and then
+///    obliviously sorts each group by the secret-shared timestamp
 /// 6. Attributes trigger events to source events
 /// 7. Caps each user's total contribution to the final result
 /// 8. Aggregates the contributions of all users
 /// 9. Adds random noise to the total for each breakdown key (to provide a differential
 ///    privacy guarantee)
+///
+/// The attribution window is compared directly against differences of input timestamps, so it
+/// must be expressed in the same units as the timestamps are.
 /// # Errors
 /// Propagates errors from config issues or while running the protocol
 /// # Panics
@@ -364,53 +444,637 @@ where
     BitDecomposed<AdditiveShare<Boolean, B>>:
         for<'a> TransposeFrom<&'a [AdditiveShare<HV>; B], Error = Infallible>,
 {
-    if input_rows.is_empty() {
-        return Ok(vec![Replicated::ZERO; B]);
+    oprf_ipa_with_partial_results::<_, BK, TV, HV, TS, SS_BITS, B>(
+        ctx,
+        input_rows.into(),
+        attribution_window_seconds,
+        dp_params,
+        dp_padding_params,
+        false,
+        false,
+        CappingParameters::default(),
+        AggregationParameters::default(),
+        None,
+        None,
+        None,
+    )
+    .await
+    .map(|(histogram, _, _)| histogram)
+}
+
+/// Same as [`oprf_ipa`], but takes either reports or rows that were already PRF'd, see
+/// [`IpaInput`]. If `allow_partial_results` is set and noise can't be added to some of the
+/// histograms that follow the histogram of attributed values, returns those histograms zeroed
+/// and marked as [`Release::Partial`] instead of failing. Nothing is ever released without
+/// noise, failures to noise the histogram of attributed values and failures in any stage
+/// before are always reported as errors.
+/// The noise added to the output is described by the returned [`NoiseReport`], which is `None`
+/// if no noise was added.
+///
+/// If `attributed_counts` is set, the output has `2 * B` values: the histogram of attributed
+/// values, followed by the histogram of attributed conversion counts. Counts are obtained by
+/// running attribution a second time over the sorted rows, with every trigger value replaced by
+/// one, so they are capped per user the same way values are. Padding, shuffling, PRF evaluation
+/// and sorting are shared between the two. The DP budget is split evenly between the two
+/// histograms.
+///
+/// If `time_to_conversion` is set, another `B` values follow: the number of attributed
+/// conversions per bucket of the time between the conversion and the source event it was
+/// attributed to. Only the first `time_to_conversion.count()` of them can be non-zero. They are
+/// computed like conversion counts, but keyed by bucket instead of breakdown key, and take an
+/// even share of the DP budget as well.
+///
+/// The output layout only depends on the query parameters: within every histogram, value `i`
+/// belongs to breakdown key `i` (or time-to-conversion bucket `i`), and histograms follow each
+/// other in the order above. Apart from the noise, the same input therefore produces the same
+/// output on every run, regardless of the order of input rows or how the helpers were seeded.
+///
+/// `capping` selects how each user's contribution is capped, see [`CappingParameters`].
+/// `aggregation` selects the aggregation protocol and its overflow behavior, see
+/// [`AggregationParameters`].
+/// If `sampling` is set, only a sample of users is attributed and aggregated, see
+/// [`UserSampling`]. The output is not rescaled.
+/// If `signed_trigger_values` is set, trigger values are interpreted as two's complement numbers,
+/// so that refunds can be reported as negative values. The per-user cap then limits the sum of
+/// absolute values, and the output histogram is in two's complement as well. Conversion counts
+/// are not affected.
+///
+/// If `per_source_event_cap` is set, the credit attributed to any single source event is capped
+/// before the per-user cap is applied. The cap must be a power of two. Conversion counts are
+/// only capped per user, using the same capping strategy as values.
+///
+/// If `per_source_trigger_limit` is set, every source event is credited by at most that many of
+/// the trigger events attributed to it, and the ones after that are treated as unattributed. This
+/// applies to conversion counts as well.
+///
+/// If `timestamp_bounds` is set, trigger events with timestamps outside of them get no credit
+/// and are not counted as conversions, see [`timestamp_bounds`]. One more histogram then
+/// follows all others, with the number of suppressed trigger events in its first bucket. Every
+/// user counts towards it at most as often as the per-user cap allows, and it takes an even
+/// share of the DP budget like any other histogram.
+///
+/// # Errors
+/// Propagates errors from config issues or while running the protocol
+/// # Panics
+/// Propagates errors from config issues or while running the protocol
+#[allow(clippy::too_many_arguments)]
+pub async fn oprf_ipa_with_partial_results<
+    'ctx,
+    C,
+    BK,
+    TV,
+    HV,
+    TS,
+    const SS_BITS: usize,
+    const B: usize,
+>(
+    ctx: C,
+    input: IpaInput<BK, TV, TS>,
+    attribution_window_seconds: Option<NonZeroU32>,
+    dp_params: DpMechanism,
+    dp_padding_params: PaddingParameters,
+    allow_partial_results: bool,
+    attributed_counts: bool,
+    capping: CappingParameters,
+    aggregation: AggregationParameters,
+    sampling: Option<UserSampling>,
+    time_to_conversion: Option<TimeToConversionBuckets>,
+    timestamp_bounds: Option<TimestampBounds>,
+) -> Result<(Vec<Replicated<HV>>, Release, Option<NoiseReport>), Error>
+where
+    C: UpgradableContext + 'ctx + Shuffle,
+    BK: BreakdownKey<B>,
+    TV: BooleanArray + U128Conversions,
+    HV: BooleanArray + U128Conversions,
+    TS: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    Replicated<Boolean, AGG_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, AGG_CHUNK>,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+    Replicated<BK>: BooleanArrayMul<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>,
+    Replicated<TS>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>>,
+    BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
+        for<'a> TransposeFrom<&'a Vec<Replicated<BK>>, Error = LengthError>,
+    BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
+        for<'a> TransposeFrom<&'a Vec<Replicated<TV>>, Error = LengthError>,
+    Vec<BitDecomposed<Replicated<Boolean, B>>>: for<'a> TransposeFrom<
+        &'a [BitDecomposed<Replicated<Boolean, AGG_CHUNK>>],
+        Error = Infallible,
+    >,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    BitDecomposed<AdditiveShare<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<HV>; B], Error = Infallible>,
+{
+    let histograms = 1
+        + u32::from(attributed_counts)
+        + u32::from(time_to_conversion.is_some())
+        + u32::from(timestamp_bounds.is_some());
+    let output_len = usize::try_from(histograms).unwrap() * B;
+    if input.is_empty() {
+        return Ok((vec![Replicated::ZERO; output_len], Release::Final, None));
     }
 
-    // Apply DP padding for OPRF
-    let padded_input_rows = apply_dp_padding::<_, OPRFIPAInputRow<BK, TV, TS>, B>(
-        ctx.narrow(&Step::PaddingDp),
-        input_rows,
-        &dp_padding_params,
+    // Every stage processes all rows, so helpers can estimate how long the query takes once
+    // the first stage completes.
+    progress::plan(
+        [
+            matches!(input, IpaInput::Reports(_)).then_some("prf"),
+            Some(Step::SortByTimestamp.as_ref()),
+            timestamp_bounds
+                .is_some()
+                .then_some(Step::TimestampBounds.as_ref()),
+            Some(Step::Attribution.as_ref()),
+            attributed_counts.then_some(Step::AttributionCounts.as_ref()),
+            time_to_conversion
+                .is_some()
+                .then_some(Step::AttributionTimeToConversion.as_ref()),
+            Some("dp"),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|step| (step, input.len())),
+    );
+
+    let mut prfd_inputs = match input {
+        IpaInput::Reports(input_rows) => {
+            progress::stage(
+                "prf",
+                prf_input_rows::<_, BK, TV, TS, B>(ctx.clone(), input_rows, &dp_padding_params),
+            )
+            .await?
+        }
+        IpaInput::Prfd(rows) => rows,
+    };
+    if let Some(sampling) = sampling {
+        prfd_inputs = sampling.sample(prfd_inputs);
+    }
+
+    group_by_key(&mut prfd_inputs);
+
+    let (row_count_histogram, ranges) = histograms_ranges_sortkeys(&mut prfd_inputs);
+    if row_count_histogram.len() <= 1 {
+        // No user has more than one record, or no user was sampled.
+        return Ok((vec![Replicated::ZERO; output_len], Release::Final, None));
+    }
+    progress::stage(
+        Step::SortByTimestamp.as_ref(),
+        quicksort_ranges_by_key_insecure(
+            ctx.narrow(&Step::SortByTimestamp),
+            &mut prfd_inputs,
+            false,
+            |x| &x.sort_key,
+            ranges,
+        ),
+    )
+    .await?;
+    let paranoid_ctx = ctx.narrow(&Step::ParanoidCheck);
+    try_join3(
+        paranoid::assert_consistent(
+            paranoid_ctx.narrow(&ParanoidCheckStep::SortedBreakdownKeys),
+            prfd_inputs.iter().map(|row| &row.breakdown_key),
+        ),
+        paranoid::assert_consistent(
+            paranoid_ctx.narrow(&ParanoidCheckStep::SortedTriggerValues),
+            prfd_inputs.iter().map(|row| &row.trigger_value),
+        ),
+        paranoid::assert_consistent(
+            paranoid_ctx.narrow(&ParanoidCheckStep::SortedTimestamps),
+            prfd_inputs.iter().map(|row| &row.timestamp),
+        ),
     )
     .await?;
+    #[cfg(all(any(test, feature = "test-fixture"), feature = "in-memory-infra"))]
+    {
+        use crate::test_fixture::debug_reveal;
+        debug_reveal(
+            &ctx,
+            "sorted breakdown keys",
+            prfd_inputs.iter().map(|row| &row.breakdown_key),
+        );
+        debug_reveal(
+            &ctx,
+            "sorted trigger values",
+            prfd_inputs.iter().map(|row| &row.trigger_value),
+        );
+    }
 
-    let shuffled = ctx
-        .narrow(&Step::Shuffle)
-        .shuffle(padded_input_rows)
-        .instrument(info_span!("shuffle_inputs"))
+    let suppressed = match timestamp_bounds {
+        Some(bounds) => Some(
+            progress::stage(
+                Step::TimestampBounds.as_ref(),
+                suppress_out_of_range(ctx.narrow(&Step::TimestampBounds), &mut prfd_inputs, bounds),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    let counts_inputs = if attributed_counts {
+        Some(counting_rows(&prfd_inputs, suppressed.as_deref()).await)
+    } else {
+        None
+    };
+    let time_to_conversion_inputs = match time_to_conversion {
+        Some(buckets) => Some((
+            buckets,
+            counting_rows(&prfd_inputs, suppressed.as_deref()).await,
+        )),
+        None => None,
+    };
+    let suppressed_histogram = match suppressed {
+        Some(suppressed) => Some(
+            count_suppressed::<_, _, _, _, HV, B>(
+                ctx.narrow(&Step::TimestampBounds),
+                &prfd_inputs,
+                suppressed,
+                capping.per_user_cap.value(SS_BITS)?,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    // Every aggregation reveals padded breakdown keys of the same users, so they share the
+    // budget for aggregation padding.
+    let aggregations =
+        1 + u32::from(counts_inputs.is_some()) + u32::from(time_to_conversion_inputs.is_some());
+    let aggregation_padding_params = dp_padding_params.split_aggregation_budget(aggregations);
+    let counts_capping = CappingParameters {
+        per_source_trigger_limit: capping.per_source_trigger_limit,
+        per_user_cap: capping.per_user_cap,
+        strategy: capping.strategy,
+        ..CappingParameters::default()
+    };
+    let output_histogram = progress::stage(
+        Step::Attribution.as_ref(),
+        attribute_cap_aggregate::<_, _, _, _, _, SS_BITS, B>(
+            ctx.narrow(&Step::Attribution),
+            prfd_inputs,
+            attribution_window_seconds,
+            &row_count_histogram,
+            &aggregation_padding_params,
+            capping,
+            aggregation,
+            None,
+        ),
+    )
+    .await?;
+    #[cfg(all(any(test, feature = "test-fixture"), feature = "in-memory-infra"))]
+    let debug_reveal = crate::test_fixture::debug_reveal_enabled();
+    #[cfg(not(all(any(test, feature = "test-fixture"), feature = "in-memory-infra")))]
+    let debug_reveal = false;
+    // Only the checks below need the histogram as values, so don't transpose it without them.
+    if cfg!(feature = "paranoid") || debug_reveal {
+        let aggregated_values = Vec::<Replicated<HV>>::transposed_from(&output_histogram)?;
+        paranoid::assert_consistent(
+            paranoid_ctx.narrow(&ParanoidCheckStep::AggregatedHistogram),
+            &aggregated_values,
+        )
         .await?;
-    let mut prfd_inputs = compute_prf_for_inputs(ctx.clone(), &shuffled).await?;
+        #[cfg(all(any(test, feature = "test-fixture"), feature = "in-memory-infra"))]
+        crate::test_fixture::debug_reveal(&ctx, "aggregated histogram", &aggregated_values);
+    }
+    let counts_histogram = match counts_inputs {
+        Some(rows) => Some(
+            progress::stage(
+                Step::AttributionCounts.as_ref(),
+                attribute_cap_aggregate::<_, _, _, _, _, SS_BITS, B>(
+                    ctx.narrow(&Step::AttributionCounts),
+                    rows,
+                    attribution_window_seconds,
+                    &row_count_histogram,
+                    &aggregation_padding_params,
+                    counts_capping,
+                    aggregation,
+                    None,
+                ),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let time_to_conversion_histogram = match time_to_conversion_inputs {
+        Some((buckets, rows)) => Some(
+            progress::stage(
+                Step::AttributionTimeToConversion.as_ref(),
+                attribute_cap_aggregate::<_, _, _, _, _, SS_BITS, B>(
+                    ctx.narrow(&Step::AttributionTimeToConversion),
+                    rows,
+                    attribution_window_seconds,
+                    &row_count_histogram,
+                    &aggregation_padding_params,
+                    counts_capping,
+                    aggregation,
+                    Some(buckets),
+                ),
+            )
+            .await?,
+        ),
+        None => None,
+    };
 
-    prfd_inputs.sort_by(|a, b| a.prf_of_match_key.cmp(&b.prf_of_match_key));
+    // Every histogram is noised on its own. Histograms are never released without noise: if
+    // the report collector asked for partial results, those that could not be noised are
+    // zeroed, as long as the histogram of attributed values was noised.
+    let dp_params = dp_params.split_budget(histograms);
+    let partial = |noised: Result<Vec<Replicated<HV>>, Error>| {
+        if allow_partial_results {
+            Ok(noised)
+        } else {
+            noised.map(Ok)
+        }
+    };
+    let noised = progress::stage("dp", async {
+        let values =
+            dp_for_histogram::<_, B, HV, SS_BITS>(ctx.clone(), output_histogram, dp_params).await?;
+        let counts = match counts_histogram {
+            Some(counts_histogram) => Some(partial(
+                dp_for_histogram_with_steps::<_, _, B, HV, SS_BITS>(
+                    ctx.clone(),
+                    MaliciousProtocolSteps {
+                        protocol: &Step::CountsDifferentialPrivacy,
+                        validate: &Step::CountsDifferentialPrivacyValidate,
+                    },
+                    counts_histogram,
+                    dp_params,
+                )
+                .await,
+            )?),
+            None => None,
+        };
+        let time_to_conversion = match time_to_conversion_histogram {
+            Some(time_to_conversion_histogram) => Some(partial(
+                dp_for_histogram_with_steps::<_, _, B, HV, SS_BITS>(
+                    ctx.clone(),
+                    MaliciousProtocolSteps {
+                        protocol: &Step::TimeToConversionDifferentialPrivacy,
+                        validate: &Step::TimeToConversionDifferentialPrivacyValidate,
+                    },
+                    time_to_conversion_histogram,
+                    dp_params,
+                )
+                .await,
+            )?),
+            None => None,
+        };
+        let suppressed = match suppressed_histogram {
+            Some(suppressed_histogram) => Some(partial(
+                dp_for_histogram_with_steps::<_, _, B, HV, SS_BITS>(
+                    ctx.clone(),
+                    MaliciousProtocolSteps {
+                        protocol: &Step::SuppressedDifferentialPrivacy,
+                        validate: &Step::SuppressedDifferentialPrivacyValidate,
+                    },
+                    suppressed_histogram,
+                    dp_params,
+                )
+                .await,
+            )?),
+            None => None,
+        };
+        Ok::<_, Error>(OutputHistograms {
+            values: Ok(values),
+            counts,
+            time_to_conversion,
+            suppressed,
+        })
+    })
+    .await?;
+    let noise = NoiseReport::new::<SS_BITS>(dp_params, B, histograms)?;
 
-    let (row_count_histogram, ranges) = histograms_ranges_sortkeys(&mut prfd_inputs);
-    if row_count_histogram.len() == 1 {
-        // No user has more than one record.
-        return Ok(vec![Replicated::ZERO; B]);
+    if !allow_partial_results {
+        let output = noised.try_map(|histogram| histogram)?.concat::<B>();
+        return Ok((output, Release::Final, noise));
     }
-    quicksort_ranges_by_key_insecure(
-        ctx.narrow(&Step::SortByTimestamp),
-        &mut prfd_inputs,
-        false,
-        |x| &x.sort_key,
-        ranges,
+
+    let (output, release) =
+        release_partial::<_, HV, B>(ctx.narrow(&Step::PartialRelease), noised).await?;
+    debug_assert_eq!(output_len, output.len());
+
+    Ok((output, release, noise))
+}
+
+/// Lays out the histograms as the output of [`oprf_ipa_with_partial_results`] for queries that
+/// allow partial results. Histograms that could not be noised on any helper are zeroed on all
+/// of them, otherwise the report collector would reconstruct noised shares of some helpers
+/// with zeros of the others. The histogram of attributed values is never zeroed.
+///
+/// ## Errors
+/// If the histogram of attributed values could not be noised on any helper, or if helpers
+/// fail to agree on the histograms that were noised.
+async fn release_partial<C, HV, const B: usize>(
+    ctx: C,
+    noised: OutputHistograms<Result<Vec<Replicated<HV>>, Error>>,
+) -> Result<(Vec<Replicated<HV>>, Release), Error>
+where
+    C: Context,
+    HV: SharedValue,
+{
+    let agreed = agree_on_noised(ctx, noised.noised()).await?;
+    let OutputHistograms {
+        values,
+        counts,
+        time_to_conversion,
+        suppressed,
+    } = noised;
+    let values = match values {
+        Ok(values) if agreed & 1 != 0 => values,
+        Ok(_) => return Err(Error::NoiseFailed),
+        Err(e) => return Err(e),
+    };
+    let zero_unless_noised = |bit: u8, histogram: Option<Result<Vec<Replicated<HV>>, Error>>| {
+        histogram.map(|histogram| match histogram {
+            Ok(histogram) if agreed & bit != 0 => histogram,
+            histogram => {
+                if let Err(e) = histogram {
+                    tracing::warn!("Failed to add noise to a histogram, releasing it zeroed: {e}");
+                }
+                vec![Replicated::ZERO; B]
+            }
+        })
+    };
+    let output = OutputHistograms {
+        values,
+        counts: zero_unless_noised(1 << 1, counts),
+        time_to_conversion: zero_unless_noised(1 << 2, time_to_conversion),
+        suppressed: zero_unless_noised(1 << 3, suppressed),
+    }
+    .concat::<B>();
+    let release = if agreed == ALL_NOISED {
+        Release::Final
+    } else {
+        Release::Partial
+    };
+
+    Ok((output, release))
+}
+
+/// Value exchanged by [`agree_on_noised`] if every histogram was noised.
+const ALL_NOISED: u8 = 0b1111;
+
+/// Exchanges with the other helpers which histograms got their noise, see
+/// [`OutputHistograms::noised`]. Returns the histograms that got it on all helpers.
+async fn agree_on_noised<C: Context>(ctx: C, noised: u8) -> Result<u8, Error> {
+    let ctx = ctx.set_total_records(TotalRecords::ONE);
+    let recv_left = ctx.recv_channel::<BA8>(ctx.role().peer(Direction::Left));
+    let recv_right = ctx.recv_channel::<BA8>(ctx.role().peer(Direction::Right));
+    let ((), (left, right)) = try_join(
+        ctx.broadcast_channel::<BA8>()
+            .broadcast(RecordId::FIRST, BA8::truncate_from(noised)),
+        try_join(
+            recv_left.receive(RecordId::FIRST),
+            recv_right.receive(RecordId::FIRST),
+        ),
     )
     .await?;
 
-    let output_histogram = attribute_cap_aggregate::<_, _, _, _, _, SS_BITS, B>(
-        ctx.narrow(&Step::Attribution),
-        prfd_inputs,
-        attribution_window_seconds,
-        &row_count_histogram,
-        &dp_padding_params,
+    let received = |v: BA8| u8::try_from(v.as_u128()).unwrap();
+    Ok(noised & received(left) & received(right))
+}
+
+/// Histograms computed by [`oprf_ipa_with_partial_results`], before they are laid out as its
+/// output.
+struct OutputHistograms<H> {
+    values: H,
+    counts: Option<H>,
+    time_to_conversion: Option<H>,
+    suppressed: Option<H>,
+}
+
+impl<H> OutputHistograms<H> {
+    fn try_map<T, E, F: FnMut(H) -> Result<T, E>>(
+        self,
+        mut f: F,
+    ) -> Result<OutputHistograms<T>, E> {
+        Ok(OutputHistograms {
+            values: f(self.values)?,
+            counts: self.counts.map(&mut f).transpose()?,
+            time_to_conversion: self.time_to_conversion.map(&mut f).transpose()?,
+            suppressed: self.suppressed.map(&mut f).transpose()?,
+        })
+    }
+}
+
+impl<H> OutputHistograms<Result<H, Error>> {
+    /// Bit `i` is set unless noising the `i`-th histogram, in output order, failed. Bits of
+    /// histograms the query does not ask for are set as well.
+    fn noised(&self) -> u8 {
+        [
+            Some(&self.values),
+            self.counts.as_ref(),
+            self.time_to_conversion.as_ref(),
+            self.suppressed.as_ref(),
+        ]
+        .into_iter()
+        .enumerate()
+        .fold(0, |noised, (i, histogram)| match histogram {
+            Some(Err(_)) => noised,
+            _ => noised | 1 << i,
+        })
+    }
+}
+
+impl<HV: SharedValue> OutputHistograms<Vec<Replicated<HV>>> {
+    /// Concatenates the histograms in the order described by [`oprf_ipa_with_partial_results`].
+    /// This is the only place that decides the order of the output, so it doesn't depend on the
+    /// order in which stages ran, or on whether noise was added.
+    ///
+    /// ## Panics
+    /// If any of the histograms doesn't have exactly `B` values.
+    fn concat<const B: usize>(self) -> Vec<Replicated<HV>> {
+        let histograms = [
+            Some(self.values),
+            self.counts,
+            self.time_to_conversion,
+            self.suppressed,
+        ];
+        let mut output = Vec::with_capacity(histograms.iter().flatten().count() * B);
+        for histogram in histograms.into_iter().flatten() {
+            assert_eq!(B, histogram.len(), "histograms have one value per bucket");
+            output.extend(histogram);
+        }
+        output
+    }
+}
+
+/// Pads, shuffles and PRFs the input rows, which is all the work [`oprf_ipa_with_partial_results`]
+/// does before sorting that only depends on the input. `ctx` must be the context that is passed
+/// to [`oprf_ipa_with_partial_results`] later.
+///
+/// # Errors
+/// Propagates errors from padding, shuffling and PRF evaluation.
+pub async fn prf_input_rows<C, BK, TV, TS, const B: usize>(
+    ctx: C,
+    input_rows: Vec<OPRFIPAInputRow<BK, TV, TS>>,
+    dp_padding_params: &PaddingParameters,
+) -> Result<Vec<PrfShardedIpaInputRow<BK, TV, TS>>, Error>
+where
+    C: UpgradableContext + Shuffle,
+    BK: BreakdownKey<B>,
+    TV: BooleanArray,
+    TS: BooleanArray,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+{
+    // Apply DP padding for OPRF
+    let padded_input_rows = apply_dp_padding::<_, OPRFIPAInputRow<BK, TV, TS>, B>(
+        ctx.narrow(&Step::PaddingDp),
+        input_rows,
+        dp_padding_params,
     )
     .await?;
 
-    let noisy_output_histogram =
-        dp_for_histogram::<_, B, HV, SS_BITS>(ctx, output_histogram, dp_params).await?;
-    Ok(noisy_output_histogram)
+    let shuffled = ctx
+        .narrow(&Step::Shuffle)
+        .shuffle(padded_input_rows)
+        .instrument(info_span!("shuffle_inputs"))
+        .await?;
+    compute_prf_for_inputs(ctx, &shuffled).await
+}
+
+/// Copies of the sorted input rows in which the trigger value of every trigger event is one, so
+/// that attributing and aggregating them yields the number of attributed conversions. Trigger
+/// events marked in `suppressed` get a value of zero instead.
+async fn counting_rows<BK, TV, TS>(
+    rows: &[PrfShardedIpaInputRow<BK, TV, TS>],
+    suppressed: Option<&[Replicated<Boolean>]>,
+) -> Vec<PrfShardedIpaInputRow<BK, TV, TS>>
+where
+    BK: BooleanArray,
+    TV: BooleanArray,
+    TS: BooleanArray,
+{
+    cooperative::collect(rows.iter().enumerate().map(|(i, row)| {
+        let mut trigger_value = Replicated::<TV>::ZERO;
+        // Only trigger events are suppressed, so this clears the bit of suppressed ones.
+        let counted = match suppressed {
+            Some(suppressed) => row.is_trigger_bit.clone() + &suppressed[i],
+            None => row.is_trigger_bit.clone(),
+        };
+        trigger_value.set(0, counted);
+        PrfShardedIpaInputRow {
+            prf_of_match_key: row.prf_of_match_key,
+            is_trigger_bit: row.is_trigger_bit.clone(),
+            breakdown_key: row.breakdown_key.clone(),
+            trigger_value,
+            timestamp: row.timestamp.clone(),
+            sort_key: row.sort_key.clone(),
+        }
+    }))
+    .await
 }
 
 /// Returns a suitable proof chunk size (in records) for use with `convert_to_fp25519`.
@@ -421,7 +1085,7 @@ where
 /// `CONV_CHUNK`*512*len ≈ 50M for a reasonably-sized proof. There is also a constraint
 /// on proof chunks to be powers of two, and we don't want to compute a proof chunk
 /// of zero when `TARGET_PROOF_SIZE` is smaller for tests.
-fn conv_proof_chunk() -> usize {
+pub(crate) fn conv_proof_chunk() -> usize {
     non_zero_prev_power_of_two(max(2, TARGET_PROOF_SIZE / CONV_CHUNK / 512))
 }
 
@@ -444,7 +1108,11 @@ where
     let conv_records =
         TotalRecords::specified(div_round_up(input_rows.len(), Const::<CONV_CHUNK>))?;
     let eval_records = TotalRecords::specified(div_round_up(input_rows.len(), Const::<PRF_CHUNK>))?;
-    let convert_ctx = ctx.set_total_records(conv_records);
+    let convert_ctx = ctx
+        .clone()
+        .limit_active_work(ConcurrencyStage::Conversion)
+        .set_total_records(conv_records);
+    let convert_work = convert_ctx.active_work();
 
     let validator = convert_ctx.dzkp_validator(
         MaliciousProtocolSteps {
@@ -456,7 +1124,7 @@ where
     let m_ctx = validator.context();
 
     let curve_pts = seq_join(
-        ctx.active_work(),
+        convert_work,
         process_slice_by_chunks(input_rows, move |idx, records: ChunkData<_, CONV_CHUNK>| {
             let record_id = RecordId::from(idx);
             let input_match_keys: &dyn Fn(usize) -> Replicated<MatchKey> =
@@ -473,14 +1141,15 @@ where
     .await?;
 
     let prf_key = gen_prf_key(&ctx.narrow(&IpaPrfStep::PrfKeyGen));
-    let validator = ctx
+    let prf_ctx = ctx.limit_active_work(ConcurrencyStage::Prf);
+    let validator = prf_ctx
         .narrow(&Step::EvalPrf)
         .set_total_records(eval_records)
         .validator::<Fp25519>();
     let eval_ctx = validator.context();
 
     let prf_of_match_keys = seq_join(
-        ctx.active_work(),
+        prf_ctx.active_work(),
         stream::iter(curve_pts).enumerate().map(|(i, curve_pts)| {
             let record_id = RecordId::from(i);
             let eval_ctx = eval_ctx.clone();
@@ -492,26 +1161,29 @@ where
     .try_collect::<Vec<_>>()
     .await?;
 
-    Ok(zip(input_rows, prf_of_match_keys.into_iter().flatten())
-        .map(|(input, prf_of_match_key)| {
-            let OPRFIPAInputRow {
-                match_key: _,
-                is_trigger,
-                breakdown_key,
-                trigger_value,
-                timestamp,
-            } = &input;
-
-            PrfShardedIpaInputRow {
-                prf_of_match_key,
-                is_trigger_bit: is_trigger.clone(),
-                breakdown_key: breakdown_key.clone(),
-                trigger_value: trigger_value.clone(),
-                timestamp: timestamp.clone(),
-                sort_key: Replicated::ZERO,
-            }
-        })
-        .collect())
+    Ok(cooperative::collect(
+        zip(input_rows, prf_of_match_keys.into_iter().flatten()).map(
+            |(input, prf_of_match_key)| {
+                let OPRFIPAInputRow {
+                    match_key: _,
+                    is_trigger,
+                    breakdown_key,
+                    trigger_value,
+                    timestamp,
+                } = &input;
+
+                PrfShardedIpaInputRow {
+                    prf_of_match_key,
+                    is_trigger_bit: is_trigger.clone(),
+                    breakdown_key: breakdown_key.clone(),
+                    trigger_value: trigger_value.clone(),
+                    timestamp: timestamp.clone(),
+                    sort_key: Replicated::ZERO,
+                }
+            },
+        ),
+    )
+    .await)
 }
 
 #[cfg(all(test, any(unit_test, feature = "shuttle")))]
@@ -522,7 +1194,7 @@ pub mod tests {
             boolean_array::{BA16, BA20, BA3, BA5, BA8},
             U128Conversions,
         },
-        helpers::query::DpMechanism,
+        helpers::{query::DpMechanism, StageConcurrency},
         protocol::{
             dp::NoiseParams,
             ipa_prf::{oprf_ipa, oprf_padding::PaddingParameters},
@@ -635,6 +1307,91 @@ pub mod tests {
         });
     }
 
+    /// Runs the protocol with share-consistency checks at every stage boundary.
+    #[cfg(feature = "paranoid")]
+    #[test]
+    fn malicious_paranoid() {
+        const EXPECTED: &[u128] = &[0, 2, 5, 0, 0, 0, 0, 0];
+
+        run(|| async {
+            let world = TestWorld::default();
+
+            let records: Vec<TestRawDataRecord> = vec![
+                test_input(0, 12345, false, 1, 0),
+                test_input(5, 12345, false, 2, 0),
+                test_input(10, 12345, true, 0, 5),
+                test_input(0, 68362, false, 1, 0),
+                test_input(20, 68362, true, 0, 2),
+            ];
+
+            let mut result: Vec<_> = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    oprf_ipa::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                        ctx,
+                        input_rows,
+                        None,
+                        DpMechanism::NoDp,
+                        PaddingParameters::no_padding(),
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await
+                .reconstruct();
+            result.truncate(EXPECTED.len());
+            assert_eq!(
+                result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>(),
+                EXPECTED,
+            );
+        });
+    }
+
+    #[test]
+    fn malicious_with_stage_concurrency() {
+        const EXPECTED: &[u128] = &[0, 2, 5, 0, 0, 0, 0, 0];
+
+        run(|| async {
+            let two = Some(2.try_into().unwrap());
+            let mut config = TestWorldConfig::default();
+            config.gateway_config.stage_concurrency = StageConcurrency {
+                conversion: two,
+                prf: two,
+                aggregation: two,
+            };
+            let world = TestWorld::<NotSharded>::with_config(&config);
+
+            let records: Vec<TestRawDataRecord> = vec![
+                test_input(0, 12345, false, 1, 0),
+                test_input(5, 12345, false, 2, 0),
+                test_input(10, 12345, true, 0, 5),
+                test_input(0, 68362, false, 1, 0),
+                test_input(20, 68362, true, 0, 2),
+            ];
+            let dp_params = DpMechanism::NoDp;
+            let padding_params = PaddingParameters::no_padding();
+
+            let mut result: Vec<_> = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    oprf_ipa::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                        ctx,
+                        input_rows,
+                        None,
+                        dp_params,
+                        padding_params,
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await
+                .reconstruct();
+            result.truncate(EXPECTED.len());
+            assert_eq!(
+                result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>(),
+                EXPECTED,
+            );
+        });
+    }
+
     #[test]
     fn semi_honest_with_dp() {
         const SS_BITS: usize = 1;
@@ -843,6 +1600,232 @@ pub mod tests {
             );
         });
     }
+
+    #[test]
+    fn timestamp_bounds() {
+        use crate::protocol::ipa_prf::{
+            aggregation::AggregationParameters, oprf_ipa_with_partial_results,
+            prf_sharding::credit_capping::CappingParameters, TimestampBounds,
+        };
+
+        run(|| async {
+            let world = TestWorld::default();
+
+            // The conversions at 10 and 30 are outside of the bounds, so only the one at 12 is
+            // attributed, and two trigger events are suppressed. Source events are not affected.
+            let records: Vec<TestRawDataRecord> = vec![
+                test_input(0, 12345, false, 2, 0),
+                test_input(4, 68362, false, 1, 0),
+                test_input(10, 12345, true, 0, 5),
+                test_input(12, 68362, true, 0, 7),
+                test_input(20, 68362, false, 1, 0),
+                test_input(30, 68362, true, 1, 7),
+            ];
+            let result: Vec<BA16> = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    oprf_ipa_with_partial_results::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                        ctx,
+                        input_rows.into(),
+                        None,
+                        DpMechanism::NoDp,
+                        PaddingParameters::no_padding(),
+                        false,
+                        true,
+                        CappingParameters::default(),
+                        AggregationParameters::default(),
+                        None,
+                        None,
+                        Some(TimestampBounds::new(11, 20)),
+                    )
+                    .await
+                    .unwrap()
+                    .0
+                })
+                .await
+                .reconstruct();
+
+            let mut expected = vec![0; 3 * 32];
+            expected[1] = 7;
+            expected[32 + 1] = 1;
+            expected[2 * 32] = 2;
+            assert_eq!(
+                result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>(),
+                expected,
+            );
+        });
+    }
+
+    #[test]
+    fn partial_release_zeroes_histograms_on_all_helpers() {
+        use std::iter::zip;
+
+        use crate::{
+            error::Error,
+            protocol::{
+                context::Context,
+                ipa_prf::{release_partial, OutputHistograms, Release},
+            },
+            secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
+            test_fixture::join3v,
+        };
+
+        // Only H2 fails to add noise to the histogram of counts, yet all helpers zero it.
+        run(|| async {
+            let world = TestWorld::default();
+            let values: [Vec<Replicated<BA16>>; 3] = (1u128..=4).map(BA16::truncate_from).share();
+            let counts: [Vec<Replicated<BA16>>; 3] = (5u128..=8).map(BA16::truncate_from).share();
+            let results = join3v(zip(world.contexts(), zip(values, counts)).enumerate().map(
+                |(i, (ctx, (values, counts)))| {
+                    release_partial::<_, BA16, 4>(
+                        ctx.narrow("partial-release"),
+                        OutputHistograms {
+                            values: Ok(values),
+                            counts: Some(if i == 1 {
+                                Err(Error::NoiseFailed)
+                            } else {
+                                Ok(counts)
+                            }),
+                            time_to_conversion: None,
+                            suppressed: None,
+                        },
+                    )
+                },
+            ))
+            .await;
+
+            assert!(results
+                .iter()
+                .all(|(_, release)| *release == Release::Partial));
+            let output = results.map(|(output, _)| output).reconstruct();
+            assert_eq!(
+                vec![1, 2, 3, 4, 0, 0, 0, 0],
+                output
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>()
+            );
+        });
+    }
+
+    #[test]
+    fn partial_release_requires_noised_values() {
+        use std::iter::zip;
+
+        use futures::future::join_all;
+
+        use crate::{
+            error::Error,
+            protocol::{
+                context::Context,
+                ipa_prf::{release_partial, OutputHistograms},
+            },
+            secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
+        };
+
+        // Only H3 fails to add noise to the histogram of attributed values, and all helpers
+        // fail rather than releasing anything.
+        run(|| async {
+            let world = TestWorld::default();
+            let values: [Vec<Replicated<BA16>>; 3] = (1u128..=4).map(BA16::truncate_from).share();
+            let results = join_all(zip(world.contexts(), values).enumerate().map(
+                |(i, (ctx, values))| {
+                    release_partial::<_, BA16, 4>(
+                        ctx.narrow("partial-release"),
+                        OutputHistograms {
+                            values: if i == 2 {
+                                Err(Error::NoiseFailed)
+                            } else {
+                                Ok(values)
+                            },
+                            counts: None,
+                            time_to_conversion: None,
+                            suppressed: None,
+                        },
+                    )
+                },
+            ))
+            .await;
+
+            assert!(results
+                .iter()
+                .all(|result| matches!(result, Err(Error::NoiseFailed))));
+        });
+    }
+
+    // Report collectors compare the outputs of queries over the same input, so the output must
+    // only depend on the input, not on the order of input rows or the randomness of the helpers.
+    #[cfg(not(feature = "shuttle"))]
+    #[test]
+    fn deterministic_output() {
+        use std::num::NonZeroU32;
+
+        use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+        use crate::protocol::ipa_prf::{
+            oprf_ipa_with_partial_results,
+            prf_sharding::{
+                credit_capping::CappingParameters, time_to_conversion::TimeToConversionBuckets,
+            },
+            AggregationParameters,
+        };
+
+        const B: usize = 32;
+
+        run(|| async {
+            let records: Vec<TestRawDataRecord> = vec![
+                test_input(0, 12345, false, 1, 0),
+                test_input(5, 12345, false, 2, 0),
+                test_input(10, 12345, true, 0, 5),
+                test_input(0, 68362, false, 1, 0),
+                test_input(20, 68362, true, 0, 2),
+            ];
+            // Values and conversion counts by breakdown key, then conversion counts by
+            // time-to-conversion bucket.
+            let mut expected = vec![0_u128; 3 * B];
+            expected[1] = 2;
+            expected[2] = 5;
+            expected[B + 1] = 1;
+            expected[B + 2] = 1;
+            expected[2 * B] = 1;
+            expected[2 * B + 2] = 1;
+
+            for seed in 0..3 {
+                let mut records = records.clone();
+                records.shuffle(&mut StdRng::seed_from_u64(seed));
+                let world = TestWorld::with_seed(seed);
+                let result: Vec<BA16> = world
+                    .semi_honest(records.into_iter(), |ctx, input_rows| async move {
+                        oprf_ipa_with_partial_results::<_, BA5, BA3, BA16, BA20, 5, B>(
+                            ctx,
+                            input_rows.into(),
+                            None,
+                            DpMechanism::NoDp,
+                            PaddingParameters::no_padding(),
+                            false,
+                            true,
+                            CappingParameters::default(),
+                            AggregationParameters::default(),
+                            None,
+                            Some(TimeToConversionBuckets::linear(
+                                NonZeroU32::new(10).unwrap(),
+                                4,
+                            )),
+                            None,
+                        )
+                        .await
+                        .unwrap()
+                        .0
+                    })
+                    .await
+                    .reconstruct();
+                assert_eq!(
+                    expected,
+                    result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>(),
+                    "seed {seed}",
+                );
+            }
+        });
+    }
 }
 
 #[cfg(all(test, all(compact_gate, feature = "in-memory-infra")))]
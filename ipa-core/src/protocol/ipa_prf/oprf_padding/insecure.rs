@@ -192,7 +192,7 @@ impl OPRFPaddingDp {
     /// will return errors if invalid DP parameters are provided.
     pub fn new(new_epsilon: f64, new_delta: f64, new_sensitivity: u32) -> Result<Self, Error> {
         // make sure delta and epsilon are in range, i.e. >min and delta<1-min
-        if new_epsilon < f64::MIN_POSITIVE {
+        if new_epsilon.is_nan() || new_epsilon < f64::MIN_POSITIVE {
             return Err(Error::BadEpsilon(new_epsilon));
         }
 
@@ -526,6 +526,10 @@ mod test {
         let mut actual = OPRFPaddingDp::new(-1.0, 1e-6, 10); // (epsilon, delta, sensitivity)
         let mut expected = Err(Error::BadEpsilon(-1.0));
         assert_eq!(expected, actual);
+        assert!(matches!(
+            OPRFPaddingDp::new(f64::NAN, 1e-6, 10),
+            Err(Error::BadEpsilon(_))
+        ));
         actual = OPRFPaddingDp::new(1.0, -1e-6, 10); // (epsilon, delta, sensitivity)
         expected = Err(Error::BadDelta(-1e-6));
         assert_eq!(expected, actual);
@@ -7,6 +7,7 @@ use std::iter::{repeat, repeat_with};
 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
 pub use insecure::DiscreteDp as InsecureDiscreteDp;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::try_join;
 
 use crate::{
@@ -38,13 +39,14 @@ use crate::{
 };
 
 /// Parameter struct for padding parameters.
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PaddingParameters {
     pub aggregation_padding: AggregationPadding,
     pub oprf_padding: OPRFPadding,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum AggregationPadding {
     NoAggPadding,
     Parameters {
@@ -54,7 +56,8 @@ pub enum AggregationPadding {
     },
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OPRFPadding {
     NoOPRFPadding,
     Parameters {
@@ -88,6 +91,17 @@ impl Default for OPRFPadding {
 }
 
 impl PaddingParameters {
+    /// Padding that IPA queries run with. Builds with the `relaxed-dp` feature use
+    /// [`Self::relaxed`].
+    #[must_use]
+    pub fn for_queries() -> Self {
+        if cfg!(feature = "relaxed-dp") {
+            Self::relaxed()
+        } else {
+            Self::default()
+        }
+    }
+
     #[must_use]
     pub fn relaxed() -> Self {
         PaddingParameters {
@@ -112,6 +126,31 @@ impl PaddingParameters {
             oprf_padding: OPRFPadding::NoOPRFPadding,
         }
     }
+
+    /// Padding parameters for one of `parts` aggregations of the same input, each of which
+    /// reveals padded breakdown keys. By sequential composition, the epsilon and delta of
+    /// aggregation padding are divided evenly between them. OPRF padding is applied once before
+    /// any aggregation and is left as is.
+    #[must_use]
+    pub fn split_aggregation_budget(self, parts: u32) -> Self {
+        let parts = f64::from(parts);
+        let aggregation_padding = match self.aggregation_padding {
+            AggregationPadding::NoAggPadding => AggregationPadding::NoAggPadding,
+            AggregationPadding::Parameters {
+                aggregation_epsilon,
+                aggregation_delta,
+                aggregation_padding_sensitivity,
+            } => AggregationPadding::Parameters {
+                aggregation_epsilon: aggregation_epsilon / parts,
+                aggregation_delta: aggregation_delta / parts,
+                aggregation_padding_sensitivity,
+            },
+        };
+        PaddingParameters {
+            aggregation_padding,
+            ..self
+        }
+    }
 }
 
 /// Paddable trait to support generation of padding for both `OPRFIPAInputRow`s and `AttributionOutputs`
@@ -621,6 +660,24 @@ mod tests {
         Ok(input)
     }
 
+    #[test]
+    fn split_aggregation_budget() {
+        let params = PaddingParameters::relaxed().split_aggregation_budget(2);
+        assert!(matches!(
+            params.aggregation_padding,
+            AggregationPadding::Parameters {
+                aggregation_epsilon,
+                aggregation_delta,
+                aggregation_padding_sensitivity: 3,
+            } if (aggregation_epsilon - 5.0).abs() < f64::EPSILON
+                && (aggregation_delta - 5e-5).abs() < f64::EPSILON
+        ));
+        assert!(matches!(
+            params.oprf_padding,
+            OPRFPadding::Parameters { oprf_epsilon, .. } if (oprf_epsilon - 10.0).abs() < f64::EPSILON
+        ));
+    }
+
     #[tokio::test]
     pub async fn oprf_noise_in_dp_padding_pass() {
         type BK = BA8;
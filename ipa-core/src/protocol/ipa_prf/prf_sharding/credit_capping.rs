@@ -0,0 +1,1141 @@
+//! Per-user credit capping.
+//!
+//! To bound the contribution of any user to the output, the sum of the attributed trigger values
+//! of every user is capped. How the contributions of a user are reduced to fit under the cap is
+//! up to the [`CreditCapping`] strategy selected for the query:
+//! * [`HardCap`] passes contributions through in order until their sum reaches the cap. The
+//!   contribution that crosses the cap is truncated, and all contributions after it are dropped.
+//!   This biases totals towards the earliest conversions of heavy users.
+//! * [`UnitCap`] is what [`HardCap`] reduces to when the cap is one: it keeps the first
+//!   attributed conversion of a user and drops the rest, at a fraction of the cost.
+//! * [`ProportionalCap`] scales all contributions of a user by `cap / sum` when their sum exceeds
+//!   the cap. This costs a fixed-point division per user and a multiplication per contribution.
+//!
+//! The cap does not need to be a power of two, see [`PerUserCap`].
+
+use std::{
+    future::Future,
+    iter::{self, repeat_n, zip},
+    num::NonZeroU32,
+    ops::Not,
+};
+
+use futures::{future::try_join, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, Field, U128Conversions},
+    protocol::{
+        basics::{select, BooleanArrayMul, BooleanProtocols, SecureMul, ShareKnownValue},
+        boolean::{
+            adder::{add, subtract},
+            and::bool_and_8_bit,
+            step::{EightBitStep, SixteenBitStep, ThirtyTwoBitStep},
+            NBitStep,
+        },
+        context::Context,
+        ipa_prf::{
+            boolean_ops::comparison_and_subtraction_sequential::compare_gt,
+            prf_sharding::step::{
+                AttributionCapStep as CapStep, AttributionPerRowStep as PerRowStep,
+                AttributionTriggerLimitStep as TriggerLimitStep, DivisionStep,
+                ProportionalCapStep as ProportionalStep, ResetCapStep, ScaleBitStep,
+                ScaleDivisionStep, ScaleValueStep, UnitCapStep as UnitStep,
+            },
+        },
+        RecordId,
+    },
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        BitDecomposed, SharedValue,
+    },
+};
+
+/// Selects the [`CreditCapping`] strategy of a query.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum CappingStrategy {
+    /// See [`HardCap`].
+    #[default]
+    Hard,
+    /// See [`ProportionalCap`].
+    Proportional,
+}
+
+impl CappingStrategy {
+    pub(super) fn multiplications_per_row<TV>(self) -> u32
+    where
+        TV: BooleanArray + U128Conversions,
+    {
+        match self {
+            Self::Hard => <HardCap as CreditCapping<TV>>::multiplications_per_row(),
+            Self::Proportional => <ProportionalCap as CreditCapping<TV>>::multiplications_per_row(),
+        }
+    }
+}
+
+/// Bound on the sum of contributions of a single user.
+///
+/// Protocols that cap contributions take the number of bits of the saturating sum, `SS_BITS`,
+/// as a type parameter. The cap itself can be any value that fits into `SS_BITS` bits.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PerUserCap {
+    /// The cap is `2^SS_BITS`.
+    #[default]
+    PowerOfTwo,
+    /// The cap is the given value, which must not exceed `2^SS_BITS`.
+    Exact(NonZeroU32),
+    /// Contributions are not capped, and the capping stage is skipped. Without a cap, the
+    /// sensitivity of the output is unbounded, so no DP noise can be added to it.
+    Uncapped,
+}
+
+impl PerUserCap {
+    /// Returns the cap for a saturating sum of `ss_bits` bits, or `None` if contributions are
+    /// not capped.
+    ///
+    /// ## Errors
+    /// If an exact cap exceeds `2^ss_bits`.
+    pub fn value(self, ss_bits: usize) -> Result<Option<u32>, Error> {
+        let max = 1_u32 << ss_bits;
+        match self {
+            Self::PowerOfTwo => Ok(Some(max)),
+            Self::Exact(cap) if cap.get() > max => Err(Error::InvalidQueryParameter(
+                format!("per-user cap {cap} does not fit into {ss_bits} bits").into(),
+            )),
+            Self::Exact(cap) => Ok(Some(cap.get())),
+            Self::Uncapped => Ok(None),
+        }
+    }
+}
+
+/// Per-query options that control how attributed trigger values are capped.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CappingParameters {
+    /// If set, trigger values are two's complement numbers, and caps apply to their absolute
+    /// values.
+    pub signed_trigger_values: bool,
+    /// If set, the credit of every source event is capped at this value before the per-user cap
+    /// is applied. Must be a power of two.
+    pub per_source_event_cap: Option<u32>,
+    /// If set, every source event is only credited by this many of the trigger events attributed
+    /// to it. Later ones are treated as unattributed.
+    pub per_source_trigger_limit: Option<u32>,
+    pub per_user_cap: PerUserCap,
+    pub strategy: CappingStrategy,
+}
+
+impl CappingParameters {
+    /// Checks that the protocol can run with these parameters, for a saturating sum of
+    /// `ss_bits` bits. Query validation rejects bad parameters before the protocol starts, but
+    /// release builds of callers that skip it must not run with them either.
+    ///
+    /// ## Errors
+    /// If the per-user cap does not fit into `ss_bits` bits, if the per-source event cap is not
+    /// a power of two or exceeds the per-user cap, or if the per-source trigger limit is out of
+    /// range.
+    pub(super) fn check(&self, ss_bits: usize) -> Result<(), Error> {
+        let per_user_cap = self.per_user_cap.value(ss_bits)?;
+        if let Some(cap) = self.per_source_event_cap {
+            if !cap.is_power_of_two() || per_user_cap.is_some_and(|max| cap > max) {
+                return Err(Error::InvalidQueryParameter(
+                    format!(
+                        "Unsupported per-source event cap: {cap}. Must be a power of two that \
+                         does not exceed the per-user cap."
+                    )
+                    .into(),
+                ));
+            }
+        }
+        if let Some(limit) = self.per_source_trigger_limit {
+            if limit == 0 || TriggerCount::count_bits(limit) >= EightBitStep::BITS {
+                return Err(Error::InvalidQueryParameter(
+                    format!(
+                        "Unsupported per-source trigger limit: {limit}. Must be between 1 and {}.",
+                        1 << (EightBitStep::BITS - 1)
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A way to bring the contributions of a user under the per-user cap.
+pub trait CreditCapping<TV: BooleanArray> {
+    /// Caps the contributions of a single user. `attributed_trigger_values` holds the
+    /// contributions of the rows of that user, in order, and `ctx_for_row_number` the contexts of
+    /// those rows. Returns the capped contributions in the same order.
+    fn cap_user_contributions<C>(
+        &self,
+        ctx_for_row_number: &[C],
+        record_id: RecordId,
+        attributed_trigger_values: &[Replicated<TV>],
+    ) -> impl Future<Output = Result<Vec<Replicated<TV>>, Error>> + Send
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<TV>: BooleanArrayMul<C>;
+
+    /// Upper bound on the number of Boolean multiplications per row of a user, for use in
+    /// computing the number of records in each DZKP.
+    fn multiplications_per_row() -> u32;
+}
+
+/// Caps the running sum of contributions at `cap`, truncating the contribution that crosses the
+/// cap and dropping all contributions that follow it.
+pub struct HardCap {
+    cap: u32,
+}
+
+impl HardCap {
+    #[must_use]
+    pub fn new(cap: u32) -> Self {
+        Self { cap }
+    }
+}
+
+impl<TV> CreditCapping<TV> for HardCap
+where
+    TV: BooleanArray + U128Conversions,
+{
+    async fn cap_user_contributions<C>(
+        &self,
+        ctx_for_row_number: &[C],
+        record_id: RecordId,
+        attributed_trigger_values: &[Replicated<TV>],
+    ) -> Result<Vec<Replicated<TV>>, Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<TV>: BooleanArrayMul<C>,
+    {
+        let mut state = CappingState::<TV>::new(self.cap);
+        let mut capped = Vec::with_capacity(attributed_trigger_values.len());
+        for (ctx, value) in zip(ctx_for_row_number, attributed_trigger_values) {
+            capped.push(
+                state
+                    .cap(ctx.narrow(&PerRowStep::PerUserCap), record_id, value)
+                    .await?,
+            );
+        }
+
+        Ok(capped)
+    }
+
+    fn multiplications_per_row() -> u32 {
+        // cumulative trigger value sum
+        // difference to cap
+        // compute_capped_trigger_value (2x)
+        4 * TV::BITS +
+        // overflow_bit_and_prev_row_not_saturated
+        1
+    }
+}
+
+/// [`HardCap`] with a cap of one. Once the cap is one, the running sum saturates on the first
+/// non-zero contribution, which is truncated to one, and every contribution after it is dropped.
+/// So instead of a running sum, it is enough to track whether a non-zero contribution has been
+/// seen, which costs `TV::BITS` multiplications per row rather than `4 * TV::BITS + 1`.
+pub struct UnitCap;
+
+impl<TV> CreditCapping<TV> for UnitCap
+where
+    TV: BooleanArray + U128Conversions,
+{
+    async fn cap_user_contributions<C>(
+        &self,
+        ctx_for_row_number: &[C],
+        record_id: RecordId,
+        attributed_trigger_values: &[Replicated<TV>],
+    ) -> Result<Vec<Replicated<TV>>, Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<TV>: BooleanArrayMul<C>,
+    {
+        let tv_bits = usize::try_from(TV::BITS).unwrap();
+        check_width::<SixteenBitStep>("Trigger values", tv_bits)?;
+        let mut seen_non_zero = Replicated::<Boolean>::ZERO;
+        let mut capped = Vec::with_capacity(attributed_trigger_values.len());
+        for (ctx, value) in zip(ctx_for_row_number, attributed_trigger_values) {
+            let ctx = ctx.narrow(&PerRowStep::UnitPerUserCap);
+            let is_non_zero = is_non_zero(
+                ctx.narrow(&UnitStep::IsNonZero),
+                record_id,
+                &value.to_bits(),
+            )
+            .await?;
+            let is_first_non_zero = is_non_zero
+                .multiply(
+                    &seen_non_zero.clone().not(),
+                    ctx.narrow(&UnitStep::KeepFirst),
+                    record_id,
+                )
+                .await?;
+            // `is_first_non_zero` is only set when `seen_non_zero` is not, so adding it is the
+            // same as `seen_non_zero OR is_non_zero`.
+            seen_non_zero += &is_first_non_zero;
+            capped.push(
+                iter::once(is_first_non_zero)
+                    .chain(repeat_n(Replicated::ZERO, tv_bits - 1))
+                    .collect(),
+            );
+        }
+
+        Ok(capped)
+    }
+
+    fn multiplications_per_row() -> u32 {
+        // is_non_zero
+        TV::BITS - 1 +
+        // is_first_non_zero
+        1
+    }
+}
+
+/// Returns a share of one if any of `bits` is set, computed as a chain of `a OR b = !(!a & !b)`.
+async fn is_non_zero<C>(
+    ctx: C,
+    record_id: RecordId,
+    bits: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<Replicated<Boolean>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    let mut bits = bits.iter().enumerate();
+    let Some((_, first)) = bits.next() else {
+        return Ok(Replicated::ZERO);
+    };
+    let mut is_zero = first.clone().not();
+    for (i, bit) in bits {
+        is_zero = is_zero
+            .multiply(
+                &bit.clone().not(),
+                ctx.narrow(&SixteenBitStep::from(i)),
+                record_id,
+            )
+            .await?;
+    }
+
+    Ok(is_zero.not())
+}
+
+/// Number of fractional bits of the scale factor applied by [`ProportionalCap`].
+const SCALE_FRACTIONAL_BITS: usize = 12;
+
+/// Number of bits the sum of contributions of a user may need on top of the trigger value bits.
+/// Users have at most 64 rows, see `UserNthRowStep`.
+const USER_SUM_EXTRA_BITS: usize = 6;
+
+/// If the sum of contributions of a user exceeds `cap`, scales every contribution by
+/// `cap / sum`, rounding down. Contributions of users under the cap are not changed.
+///
+/// The scale factor is computed with [`SCALE_FRACTIONAL_BITS`] fractional bits, so the scaled
+/// contributions can be off by up to one from the exact fraction, on top of rounding. The sum of
+/// scaled contributions never exceeds the cap.
+pub struct ProportionalCap {
+    cap: u32,
+}
+
+impl ProportionalCap {
+    #[must_use]
+    pub fn new(cap: u32) -> Self {
+        Self { cap }
+    }
+}
+
+impl<TV> CreditCapping<TV> for ProportionalCap
+where
+    TV: BooleanArray + U128Conversions,
+{
+    async fn cap_user_contributions<C>(
+        &self,
+        ctx_for_row_number: &[C],
+        record_id: RecordId,
+        attributed_trigger_values: &[Replicated<TV>],
+    ) -> Result<Vec<Replicated<TV>>, Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<TV>: BooleanArrayMul<C>,
+    {
+        let Some(first_ctx) = ctx_for_row_number.first() else {
+            return Ok(Vec::new());
+        };
+        let sum_bits = usize::try_from(TV::BITS).unwrap() + USER_SUM_EXTRA_BITS;
+        let cap_bits = usize::try_from(u32::BITS - self.cap.leading_zeros()).unwrap();
+        if sum_bits >= usize::try_from(ThirtyTwoBitStep::BITS).unwrap() || cap_bits > sum_bits {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "Proportional capping at {} does not support {} bit trigger values",
+                    self.cap,
+                    TV::BITS
+                )
+                .into(),
+            ));
+        }
+
+        let mut sum = BitDecomposed::new(repeat_n(Replicated::ZERO, sum_bits));
+        for (ctx, value) in zip(ctx_for_row_number, attributed_trigger_values) {
+            (sum, _) = add::<_, ThirtyTwoBitStep, 1>(
+                ctx.narrow(&PerRowStep::ProportionalPerUserCap)
+                    .narrow(&ProportionalStep::UserSum),
+                record_id,
+                &sum,
+                &value.to_bits(),
+            )
+            .await?;
+        }
+
+        let ctx = first_ctx.narrow(&PerRowStep::ProportionalPerUserCap);
+        let cap = BitDecomposed::new((0..cap_bits).map(|i| {
+            if (self.cap >> i) & 1 == 1 {
+                Replicated::share_known_value(&ctx, Boolean::ONE)
+            } else {
+                Replicated::ZERO
+            }
+        }));
+        let (is_over_cap, quotient) = try_join(
+            compare_gt::<_, ThirtyTwoBitStep, 1>(
+                ctx.narrow(&ProportionalStep::IsOverCap),
+                record_id,
+                &sum,
+                &cap,
+            ),
+            cap_over_sum(
+                ctx.narrow(&ProportionalStep::ComputeScale),
+                record_id,
+                cap.clone(),
+                &sum,
+            ),
+        )
+        .await?;
+
+        // The quotient is only meaningful when the sum is over the cap. Otherwise, the scale
+        // factor is one.
+        let clamp_ctx = ctx.narrow(&ProportionalStep::ClampScale);
+        let mut scale = BitDecomposed::try_from(
+            ctx.parallel_join(quotient.iter().enumerate().map(|(i, q)| {
+                q.multiply(
+                    &is_over_cap,
+                    clamp_ctx.narrow(&ThirtyTwoBitStep::from(i)),
+                    record_id,
+                )
+            }))
+            .await?,
+        )?;
+        scale.push(is_over_cap.not());
+
+        let mut capped = Vec::with_capacity(attributed_trigger_values.len());
+        for (ctx, value) in zip(ctx_for_row_number, attributed_trigger_values) {
+            let product = multiply_by_scale(
+                ctx.narrow(&PerRowStep::ProportionalPerUserCap)
+                    .narrow(&ProportionalStep::ScaleValue),
+                record_id,
+                &value.to_bits(),
+                &scale,
+            )
+            .await?;
+            capped.push(product.into_iter().skip(SCALE_FRACTIONAL_BITS).collect());
+        }
+
+        Ok(capped)
+    }
+
+    fn multiplications_per_row() -> u32 {
+        let tv_bits = TV::BITS;
+        let sum_bits = tv_bits + u32::try_from(USER_SUM_EXTRA_BITS).unwrap();
+        let scale_bits = u32::try_from(SCALE_FRACTIONAL_BITS).unwrap();
+        // sum of contributions
+        sum_bits +
+        // partial products and their sum
+        tv_bits * (scale_bits + 1) + tv_bits * (tv_bits + scale_bits) +
+        // The following is done once per user, but counted for every row to keep the bound
+        // simple:
+        // is_over_cap
+        sum_bits +
+        // cap_over_sum
+        scale_bits * 2 * (sum_bits + 1) +
+        // clamp the scale factor
+        scale_bits
+    }
+}
+
+/// Computes `floor(cap * 2^SCALE_FRACTIONAL_BITS / sum)`, using restoring division. Only the
+/// [`SCALE_FRACTIONAL_BITS`] least significant bits of the quotient are computed, which is the
+/// complete quotient when `sum > cap`.
+async fn cap_over_sum<C>(
+    ctx: C,
+    record_id: RecordId,
+    cap: BitDecomposed<Replicated<Boolean>>,
+    sum: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<BitDecomposed<Replicated<Boolean>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    // When `sum > cap`, the leading bits of the numerator (i.e. `cap`) produce zeros in the
+    // quotient, and leave `cap` as the remainder. The remainder is always less than `2 * sum`,
+    // so it needs one bit more than `sum`.
+    let mut remainder = cap;
+    remainder.resize(sum.len() + 1, Replicated::ZERO);
+    let mut quotient = BitDecomposed::new(repeat_n(Replicated::ZERO, SCALE_FRACTIONAL_BITS));
+    for (step, bit) in (0..SCALE_FRACTIONAL_BITS).rev().enumerate() {
+        let ctx = ctx.narrow(&ScaleDivisionStep::from(step));
+        // Bring down the next bit of the numerator, which is always zero.
+        remainder = BitDecomposed::new(
+            iter::once(Replicated::ZERO).chain(remainder.into_iter().take(sum.len())),
+        );
+        let (difference, geq) = subtract::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&DivisionStep::Subtract),
+            record_id,
+            &remainder,
+            sum,
+        )
+        .await?;
+        if bit > 0 {
+            remainder = select_bits(
+                ctx.narrow(&DivisionStep::Select),
+                record_id,
+                &geq,
+                &difference,
+                &remainder,
+            )
+            .await?;
+        }
+        quotient[bit] = geq;
+    }
+
+    Ok(quotient)
+}
+
+/// Returns `a` if `condition` is set and `b` otherwise, bit by bit.
+async fn select_bits<C>(
+    ctx: C,
+    record_id: RecordId,
+    condition: &Replicated<Boolean>,
+    a: &BitDecomposed<Replicated<Boolean>>,
+    b: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<BitDecomposed<Replicated<Boolean>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    BitDecomposed::try_from(
+        ctx.parallel_join(zip(a.iter(), b.iter()).enumerate().map(|(i, (a, b))| {
+            let ctx = ctx.narrow(&ThirtyTwoBitStep::from(i));
+            async move {
+                let selected = condition.multiply(&(a + b), ctx, record_id).await?;
+                Ok::<_, Error>(selected + b)
+            }
+        }))
+        .await?,
+    )
+}
+
+/// Multiplies `value` by `scale`, using shift-and-add over the bits of `value`. The product has
+/// `value.len() + SCALE_FRACTIONAL_BITS` bits, which is enough when `scale` is at most
+/// `2^SCALE_FRACTIONAL_BITS`.
+async fn multiply_by_scale<C>(
+    ctx: C,
+    record_id: RecordId,
+    value: &BitDecomposed<Replicated<Boolean>>,
+    scale: &BitDecomposed<Replicated<Boolean>>,
+) -> Result<BitDecomposed<Replicated<Boolean>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    let partial_products = ctx
+        .parallel_join(value.iter().enumerate().map(|(i, v)| {
+            let ctx = ctx
+                .narrow(&ScaleBitStep::from(i))
+                .narrow(&ScaleValueStep::PartialProduct);
+            async move {
+                BitDecomposed::try_from(
+                    ctx.parallel_join(scale.iter().enumerate().map(|(j, s)| {
+                        s.multiply(v, ctx.narrow(&ThirtyTwoBitStep::from(j)), record_id)
+                    }))
+                    .await?,
+                )
+            }
+        }))
+        .await?;
+
+    let mut product = BitDecomposed::new(repeat_n(
+        Replicated::ZERO,
+        value.len() + SCALE_FRACTIONAL_BITS,
+    ));
+    for (i, partial_product) in partial_products.into_iter().enumerate() {
+        let shifted = BitDecomposed::new(repeat_n(Replicated::ZERO, i).chain(partial_product));
+        (product, _) = add::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&ScaleBitStep::from(i))
+                .narrow(&ScaleValueStep::Accumulate),
+            record_id,
+            &product,
+            &shifted,
+        )
+        .await?;
+    }
+
+    Ok(product)
+}
+
+/// State of a cap on the running sum of attributed trigger values.
+///
+/// The sum is kept in `n` bits, where `2^n` is the smallest power of two not below the cap, and
+/// it starts at `2^n - cap`. This way, the sum overflows exactly when the contributions reach
+/// the cap, and comparing against a cap that is not a power of two costs nothing extra.
+///
+/// The sum is never narrower than trigger values. Bits of a trigger value above the width of the
+/// sum would be lost when it is added, so a single trigger value larger than the cap could slip
+/// under it. With a sum at least as wide as trigger values, such a value overflows the sum and is
+/// truncated to the cap like any other contribution that crosses it.
+pub(super) struct CappingState<TV: SharedValue> {
+    saturating_sum: BitDecomposed<Replicated<Boolean>>,
+    is_saturated: Replicated<Boolean>,
+    difference_to_cap: Replicated<TV>,
+    /// `2^n - cap`, the value `saturating_sum` starts at.
+    offset: u32,
+}
+
+/// Fails with [`Error::InvalidQueryParameter`] if `bits` bit values are wider than the steps of
+/// `S` can index.
+fn check_width<S: NBitStep>(what: &str, bits: usize) -> Result<(), Error> {
+    if bits > usize::try_from(S::BITS).unwrap() {
+        return Err(Error::InvalidQueryParameter(
+            format!(
+                "{what} of {bits} bits are wider than the {} bits capping supports",
+                S::BITS
+            )
+            .into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Shares `value` without communication. Every helper holds `value` in both of its shares, which
+/// is a valid XOR sharing of `value`.
+fn known_bits(value: u32, bits: usize) -> BitDecomposed<Replicated<Boolean>> {
+    BitDecomposed::new((0..bits).map(|i| {
+        if (value >> i) & 1 == 1 {
+            Replicated::<Boolean>::ZERO.not()
+        } else {
+            Replicated::ZERO
+        }
+    }))
+}
+
+impl<TV> CappingState<TV>
+where
+    TV: BooleanArray + U128Conversions,
+{
+    /// ## Panics
+    /// If `cap` is zero.
+    pub(super) fn new(cap: u32) -> Self {
+        assert!(cap > 0, "cap must be positive");
+        let cap_bits = std::cmp::max(1, u32::BITS - (cap - 1).leading_zeros());
+        let sum_bits = std::cmp::max(cap_bits, TV::BITS);
+        let offset = u32::try_from((1_u64 << sum_bits) - u64::from(cap)).unwrap();
+        let sum_bits = usize::try_from(sum_bits).unwrap();
+        Self {
+            saturating_sum: known_bits(offset, sum_bits),
+            is_saturated: Replicated::<Boolean>::ZERO,
+            // The first row may exceed the cap on its own, in which case it is truncated to it.
+            difference_to_cap: known_bits(cap, usize::try_from(TV::BITS).unwrap()).collect_bits(),
+            offset,
+        }
+    }
+
+    /// Adds `attributed_trigger_value` to the running sum and returns the part of it that fits
+    /// under the cap. See [`compute_capped_trigger_value`] for details.
+    pub(super) async fn cap<C>(
+        &mut self,
+        ctx: C,
+        record_id: RecordId,
+        attributed_trigger_value: &Replicated<TV>,
+    ) -> Result<Replicated<TV>, Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<TV>: BooleanArrayMul<C>,
+    {
+        check_width::<SixteenBitStep>("Capped sums", self.saturating_sum.len())?;
+        let (updated_sum, overflow_bit) = add::<_, SixteenBitStep, 1>(
+            ctx.narrow(&CapStep::ComputeSaturatingSum),
+            record_id,
+            &self.saturating_sum,
+            &attributed_trigger_value.to_bits(),
+        )
+        .await?;
+
+        let (overflow_bit_and_prev_row_not_saturated, difference_to_cap) = try_join(
+            overflow_bit.multiply(
+                &self.is_saturated.clone().not(),
+                ctx.narrow(&CapStep::IsSaturatedAndPrevRowNotSaturated),
+                record_id,
+            ),
+            // It is okay that we are calling `subtract` with length(y) > length(x) here.
+            // `difference_to_cap` only needs to be accurate in the case where the next row will
+            // overflow. When that is the case, `updated_sum` must be within `2^TV::BITS` of
+            // `2^n`, and a `TV::BITS` subtraction of the `TV::BITS` least significant bits of
+            // `updated_sum` from `2^n` will correctly compute the difference to the cap. Since the
+            // sum is at least `TV::BITS` wide, `2^n` truncated to `TV::BITS` is zero.
+            subtract::<_, SixteenBitStep, 1>(
+                ctx.narrow(&CapStep::ComputeDifferenceToCap),
+                record_id,
+                &known_bits(
+                    1_u32
+                        .checked_shl(u32::try_from(self.saturating_sum.len()).unwrap())
+                        .unwrap_or(0),
+                    usize::try_from(TV::BITS).unwrap(),
+                ),
+                &updated_sum,
+            )
+            .map(|res| res.map(|(difference, _)| difference.collect_bits())),
+        )
+        .await?;
+
+        // Tricky way of expressing an `OR` condition, but with no additional multiplications:
+        //   Logically: "Did this row just become saturated OR was the previous row already saturated"
+        //   This works because these conditions cannot both be true
+        let is_saturated = &self.is_saturated + &overflow_bit_and_prev_row_not_saturated;
+
+        let capped_attributed_trigger_value = compute_capped_trigger_value(
+            ctx,
+            record_id,
+            &is_saturated,
+            &overflow_bit_and_prev_row_not_saturated,
+            &self.difference_to_cap,
+            attributed_trigger_value,
+        )
+        .await?;
+
+        self.saturating_sum = updated_sum;
+        self.is_saturated = is_saturated;
+        self.difference_to_cap = difference_to_cap;
+
+        Ok(capped_attributed_trigger_value)
+    }
+
+    /// Starts over with an empty sum, unless `keep` is set. Only the sum and the saturation
+    /// flag are reset, `difference_to_cap` is only used once the sum overflows.
+    pub(super) async fn reset_unless<C>(
+        &mut self,
+        ctx: C,
+        record_id: RecordId,
+        keep: &Replicated<Boolean>,
+    ) -> Result<(), Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+    {
+        check_width::<SixteenBitStep>("Capped sums", self.saturating_sum.len())?;
+        let sum_ctx = ctx.narrow(&ResetCapStep::Sum);
+        let (state, is_saturated) = try_join(
+            sum_ctx.parallel_join(self.saturating_sum.iter().enumerate().map(|(i, bit)| {
+                bit.multiply(keep, sum_ctx.narrow(&SixteenBitStep::from(i)), record_id)
+            })),
+            self.is_saturated
+                .multiply(keep, ctx.narrow(&ResetCapStep::IsSaturated), record_id),
+        )
+        .await?;
+        self.is_saturated = is_saturated;
+        // An empty sum starts at the offset. Where `keep` is not set, the bits are zero, so
+        // adding `!keep` sets them.
+        let reset = keep.clone().not();
+        let mut state = BitDecomposed::new(state);
+        for (i, bit) in state.iter_mut().enumerate() {
+            if (self.offset >> i) & 1 == 1 {
+                *bit += &reset;
+            }
+        }
+        self.saturating_sum = state;
+
+        Ok(())
+    }
+}
+
+/// Number of trigger events credited to the most recent source event, for a limit on the number
+/// of trigger events a single source event can be credited by.
+///
+/// Like the sum of [`CappingState`], the count is kept in `n` bits, where `2^n` is the smallest
+/// power of two not below the limit, and it starts at `2^n - limit`, so that it overflows exactly
+/// when the limit is reached.
+pub(super) struct TriggerCount {
+    count: BitDecomposed<Replicated<Boolean>>,
+    is_limit_reached: Replicated<Boolean>,
+    /// `2^n - limit`, the value `count` starts at.
+    offset: u32,
+}
+
+impl TriggerCount {
+    /// ## Panics
+    /// If `limit` is zero, or if it is too large for the count and the limit flag to be reset
+    /// together.
+    pub(super) fn new(limit: u32) -> Self {
+        let count_bits = Self::count_bits(limit);
+        assert!(
+            count_bits < EightBitStep::BITS,
+            "trigger limit {limit} does not fit into {} bits",
+            EightBitStep::BITS - 1
+        );
+        let offset = u32::try_from((1_u64 << count_bits) - u64::from(limit)).unwrap();
+        Self {
+            count: known_bits(offset, usize::try_from(count_bits).unwrap()),
+            is_limit_reached: Replicated::ZERO,
+            offset,
+        }
+    }
+
+    fn count_bits(limit: u32) -> u32 {
+        assert!(limit > 0, "trigger limit must be positive");
+        std::cmp::max(1, u32::BITS - (limit - 1).leading_zeros())
+    }
+
+    /// Number of Boolean multiplications per row of a user, for use in computing the number of
+    /// records in each DZKP.
+    pub(super) fn multiplications_per_row(limit: u32) -> u32 {
+        let count_bits = Self::count_bits(limit);
+        // reset on source events
+        count_bits + 1 +
+        // is_credited
+        1 +
+        // increment
+        count_bits
+    }
+
+    /// Counts a row that `is_attributed` to the most recent source event, and returns whether
+    /// the row is credited, which is only the case until the limit is reached. The count starts
+    /// over at every source event, which are the rows where `is_trigger` is not set.
+    pub(super) async fn count<C>(
+        &mut self,
+        ctx: C,
+        record_id: RecordId,
+        is_trigger: &Replicated<Boolean>,
+        is_attributed: &Replicated<Boolean>,
+    ) -> Result<Replicated<Boolean>, Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+    {
+        let state = BitDecomposed::new(
+            self.count
+                .iter()
+                .chain(iter::once(&self.is_limit_reached))
+                .cloned(),
+        );
+        let mut state = bool_and_8_bit(
+            ctx.narrow(&TriggerLimitStep::ResetCount),
+            record_id,
+            &state,
+            repeat_n(is_trigger, state.len()),
+        )
+        .await?;
+        let count_bits = state.len() - 1;
+        let is_limit_reached = state[count_bits].clone();
+        state.truncate(count_bits);
+        // See `CappingState::reset_unless`.
+        let reset = is_trigger.clone().not();
+        for (i, bit) in state.iter_mut().enumerate() {
+            if (self.offset >> i) & 1 == 1 {
+                *bit += &reset;
+            }
+        }
+
+        let is_credited = is_attributed
+            .multiply(
+                &is_limit_reached.clone().not(),
+                ctx.narrow(&TriggerLimitStep::IsCredited),
+                record_id,
+            )
+            .await?;
+        // Rows are only counted until the limit is reached, so the count overflows at most once
+        // between two source events.
+        let (count, overflow_bit) = add::<_, EightBitStep, 1>(
+            ctx.narrow(&TriggerLimitStep::IncrementCount),
+            record_id,
+            &state,
+            &BitDecomposed::new(iter::once(is_credited.clone())),
+        )
+        .await?;
+        self.count = count;
+        self.is_limit_reached = is_limit_reached + overflow_bit;
+
+        Ok(is_credited)
+    }
+}
+
+///
+/// To provide a differential privacy guarantee, we need to bound the maximum contribution from any given user to some cap.
+///
+/// The following values are computed for each row:
+/// (1) The uncapped "Attributed trigger value" (which is either the original `trigger_value` bits or zero if it was unattributed)
+/// (2) The cumulative sum of "Attributed trigger value" thus far (which "saturates" at a given power of two as indicated by the `is_saturated` flag)
+/// (3) The "delta to cap", which is the difference between the "cap" and the cumulative sum (this value is meaningless once the cumulative sum is saturated)
+///
+/// To perfectly cap each user's contributions at precisely the cap, the "attributed trigger value" will sometimes need to be lowered,
+/// such that the total cumulative sum adds up to exactly the cap.
+///
+/// This oblivious algorithm computes the "capped attributed trigger value" in the following way:
+/// IF the cumulative is NOT YET saturated:
+///     - just return the attributed trigger value
+/// ELSE IF the cumulative sum JUST became saturated (that is, it was NOT saturated on the preceding line but IS on this line):
+///     - return the "delta to cap" from the preceding line
+/// ELSE
+///     - return zero
+///
+async fn compute_capped_trigger_value<C, TV>(
+    ctx: C,
+    record_id: RecordId,
+    is_saturated: &Replicated<Boolean>,
+    is_saturated_and_prev_row_not_saturated: &Replicated<Boolean>,
+    prev_row_diff_to_cap: &Replicated<TV>,
+    attributed_trigger_value: &Replicated<TV>,
+) -> Result<Replicated<TV>, Error>
+where
+    C: Context,
+    TV: BooleanArray + U128Conversions,
+    Replicated<TV>: BooleanArrayMul<C>,
+{
+    let narrowed_ctx1 = ctx.narrow(&CapStep::ComputedCappedAttributedTriggerValueNotSaturatedCase);
+    let narrowed_ctx2 = ctx.narrow(&CapStep::ComputedCappedAttributedTriggerValueJustSaturatedCase);
+
+    let attributed_trigger_value_or_zero = select(
+        narrowed_ctx1,
+        record_id,
+        is_saturated,
+        &Replicated::new(<TV as SharedValue>::ZERO, <TV as SharedValue>::ZERO),
+        attributed_trigger_value,
+    )
+    .await?;
+
+    select(
+        narrowed_ctx2,
+        record_id,
+        is_saturated_and_prev_row_not_saturated,
+        prev_row_diff_to_cap,
+        &attributed_trigger_value_or_zero,
+    )
+    .await
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::{CappingParameters, CreditCapping, HardCap, PerUserCap, UnitCap};
+    use crate::{
+        error::Error,
+        ff::{
+            boolean_array::{BA16, BA3},
+            U128Conversions,
+        },
+        protocol::{context::Context, ipa_prf::prf_sharding::step::UserNthRowStep, RecordId},
+        rand::{thread_rng, Rng},
+        secret_sharing::replicated::semi_honest::AdditiveShare as Replicated,
+        test_executor::run,
+        test_fixture::{Reconstruct, Runner, TestWorld},
+    };
+
+    /// Caps `values` as the contributions of a single user with both [`UnitCap`] and
+    /// [`HardCap`] with a cap of one, returning the capped values in that order.
+    async fn cap_at_one(values: &[u128]) -> (Vec<u128>, Vec<u128>) {
+        let world = TestWorld::default();
+        let input = values
+            .iter()
+            .map(|&v| BA3::truncate_from(v))
+            .collect::<Vec<_>>();
+        let (unit, hard): (Vec<BA3>, Vec<BA3>) = world
+            .dzkp_semi_honest(
+                input.into_iter(),
+                |ctx, values: Vec<Replicated<BA3>>| async move {
+                    let ctx_for_row_number = (0..values.len())
+                        .map(|i| {
+                            ctx.narrow(&UserNthRowStep::from(i + 1))
+                                .set_total_records(1)
+                        })
+                        .collect::<Vec<_>>();
+                    let unit = UnitCap
+                        .cap_user_contributions(&ctx_for_row_number, RecordId::FIRST, &values)
+                        .await
+                        .unwrap();
+                    let hard = HardCap::new(1)
+                        .cap_user_contributions(&ctx_for_row_number, RecordId::FIRST, &values)
+                        .await
+                        .unwrap();
+                    (unit, hard)
+                },
+            )
+            .await
+            .reconstruct();
+
+        let as_u128 = |v: Vec<BA3>| v.iter().map(U128Conversions::as_u128).collect();
+        (as_u128(unit), as_u128(hard))
+    }
+
+    /// Caps `values` as the contributions of a single user with [`HardCap`].
+    async fn hard_cap(cap: u32, values: &[u128]) -> Vec<u128> {
+        let world = TestWorld::default();
+        let input = values
+            .iter()
+            .map(|&v| BA3::truncate_from(v))
+            .collect::<Vec<_>>();
+        let capped: Vec<BA3> = world
+            .dzkp_semi_honest(
+                input.into_iter(),
+                |ctx, values: Vec<Replicated<BA3>>| async move {
+                    let ctx_for_row_number = (0..values.len())
+                        .map(|i| {
+                            ctx.narrow(&UserNthRowStep::from(i + 1))
+                                .set_total_records(1)
+                        })
+                        .collect::<Vec<_>>();
+                    HardCap::new(cap)
+                        .cap_user_contributions(&ctx_for_row_number, RecordId::FIRST, &values)
+                        .await
+                        .unwrap()
+                },
+            )
+            .await
+            .reconstruct();
+
+        capped.iter().map(U128Conversions::as_u128).collect()
+    }
+
+    #[test]
+    fn keeps_first_conversion() {
+        run(|| async {
+            let (unit, hard) = cap_at_one(&[0, 0, 1, 1, 0, 1]).await;
+            assert_eq!(vec![0, 0, 1, 0, 0, 0], unit);
+            assert_eq!(hard, unit);
+        });
+    }
+
+    #[test]
+    fn matches_hard_cap() {
+        run(|| async {
+            let mut rng = thread_rng();
+            for _ in 0..10 {
+                let len = rng.gen_range(1..10);
+                let values = (0..len)
+                    .map(|_| u128::from(rng.gen_bool(0.3)))
+                    .collect::<Vec<_>>();
+                let (unit, hard) = cap_at_one(&values).await;
+                assert_eq!(hard, unit, "{values:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn truncates_to_one() {
+        run(|| async {
+            let (unit, hard) = cap_at_one(&[0, 6, 7, 2]).await;
+            assert_eq!(vec![0, 1, 0, 0], unit);
+            assert_eq!(hard, unit);
+
+            // A trigger value above the cap is truncated to it even if it's the first.
+            let (unit, hard) = cap_at_one(&[4, 1, 2]).await;
+            assert_eq!(vec![1, 0, 0], unit);
+            assert_eq!(hard, unit);
+        });
+    }
+
+    #[test]
+    fn caps_values_wider_than_cap() {
+        // Trigger values have bits above the width of the cap, which must not be lost.
+        run(|| async {
+            assert_eq!(vec![2, 0, 0], hard_cap(2, &[4, 4, 1]).await);
+            assert_eq!(vec![1, 2, 0], hard_cap(3, &[1, 5, 6]).await);
+            assert_eq!(vec![0, 3, 0], hard_cap(3, &[0, 7, 1]).await);
+        });
+    }
+
+    #[test]
+    fn per_source_event_cap() {
+        let capping = |cap| CappingParameters {
+            per_source_event_cap: Some(cap),
+            ..CappingParameters::default()
+        };
+        for cap in [1, 8, 16] {
+            assert!(capping(cap).check(4).is_ok(), "{cap}");
+        }
+        for cap in [0, 3, 32] {
+            assert!(
+                matches!(capping(cap).check(4), Err(Error::InvalidQueryParameter(_))),
+                "{cap}"
+            );
+        }
+    }
+
+    #[test]
+    fn per_user_cap_does_not_fit() {
+        let cap = PerUserCap::Exact(NonZeroU32::new(17).unwrap());
+        assert_eq!(Some(17), cap.value(5).unwrap());
+        assert!(matches!(cap.value(4), Err(Error::InvalidQueryParameter(_))));
+    }
+
+    #[test]
+    fn per_source_trigger_limit() {
+        let capping = |limit| CappingParameters {
+            per_source_trigger_limit: Some(limit),
+            ..CappingParameters::default()
+        };
+        assert!(capping(1).check(4).is_ok());
+        assert!(capping(128).check(4).is_ok());
+        for limit in [0, 129] {
+            assert!(
+                matches!(
+                    capping(limit).check(4),
+                    Err(Error::InvalidQueryParameter(_))
+                ),
+                "{limit}"
+            );
+        }
+    }
+
+    #[test]
+    fn caps_wide_trigger_values() {
+        // Sums of 16-bit trigger values are wider than eight bits.
+        run(|| async {
+            let world = TestWorld::default();
+            let input = [300_u128, 1000, 1]
+                .into_iter()
+                .map(BA16::truncate_from)
+                .collect::<Vec<_>>();
+            let capped: Vec<BA16> = world
+                .dzkp_semi_honest(
+                    input.into_iter(),
+                    |ctx, values: Vec<Replicated<BA16>>| async move {
+                        let ctx_for_row_number = (0..values.len())
+                            .map(|i| {
+                                ctx.narrow(&UserNthRowStep::from(i + 1))
+                                    .set_total_records(1)
+                            })
+                            .collect::<Vec<_>>();
+                        HardCap::new(1024)
+                            .cap_user_contributions(&ctx_for_row_number, RecordId::FIRST, &values)
+                            .await
+                            .unwrap()
+                    },
+                )
+                .await
+                .reconstruct();
+
+            assert_eq!(
+                vec![300, 724, 0],
+                capped
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>()
+            );
+        });
+    }
+}
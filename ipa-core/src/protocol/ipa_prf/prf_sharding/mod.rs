@@ -1,17 +1,20 @@
 use std::{
+    collections::HashMap,
     convert::Infallible,
-    iter::{self, repeat_n, zip},
+    iter::{self, zip},
     num::NonZeroU32,
     ops::{Not, Range},
 };
 
 use futures::{
     future::{try_join, try_join3},
-    stream::{self, unfold},
-    FutureExt, Stream, StreamExt, TryStreamExt,
+    stream, Stream, TryStreamExt,
 };
 
-use super::aggregation::breakdown_reveal::breakdown_reveal_aggregation;
+use super::aggregation::{
+    breakdown_reveal::breakdown_reveal_aggregation, bucket::oblivious_aggregation,
+    AggregationMethod, AggregationParameters, Summation,
+};
 use crate::{
     error::{Error, LengthError},
     ff::{
@@ -19,12 +22,12 @@ use crate::{
         boolean_array::{BooleanArray, BA32, BA7},
         ArrayAccess, Field, U128Conversions,
     },
-    helpers::{stream::TryFlattenItersExt, TotalRecords},
+    helpers::{stream::TryFlattenItersExt, ConcurrencyStage, TotalRecords},
     protocol::{
         basics::{select, BooleanArrayMul, BooleanProtocols, Reveal, SecureMul, ShareKnownValue},
         boolean::{
             or::or,
-            step::{EightBitStep, ThirtyTwoBitStep},
+            step::{SixteenBitStep, ThirtyTwoBitStep},
             NBitStep,
         },
         context::{
@@ -33,15 +36,22 @@ use crate::{
         },
         ipa_prf::{
             boolean_ops::{
-                addition_sequential::integer_add,
+                addition_sequential::integer_cond_negate,
                 comparison_and_subtraction_sequential::{compare_gt, integer_sub},
                 expand_shared_array_in_place,
             },
             oprf_padding::PaddingParameters,
-            prf_sharding::step::{
-                AttributionPerRowStep as PerRowStep, AttributionStep as Step,
-                AttributionWindowStep as WindowStep,
-                AttributionZeroOutTriggerStep as ZeroOutTriggerStep, UserNthRowStep,
+            prf_sharding::{
+                credit_capping::{
+                    CappingParameters, CappingState, CappingStrategy, CreditCapping, HardCap,
+                    ProportionalCap, TriggerCount, UnitCap,
+                },
+                step::{
+                    AttributionPerRowStep as PerRowStep, AttributionStep as Step,
+                    AttributionWindowStep as WindowStep,
+                    AttributionZeroOutTriggerStep as ZeroOutTriggerStep, UserNthRowStep,
+                },
+                time_to_conversion::TimeToConversionBuckets,
             },
             shuffle::Shuffle,
             BreakdownKey, AGG_CHUNK,
@@ -55,8 +65,10 @@ use crate::{
     utils::non_zero_prev_power_of_two,
 };
 
+pub mod credit_capping;
 pub mod feature_label_dot_product;
 pub(crate) mod step;
+pub mod time_to_conversion;
 
 #[derive(Debug)]
 pub struct PrfShardedIpaInputRow<BK: SharedValue, TV: SharedValue, TS: SharedValue> {
@@ -107,30 +119,43 @@ impl<BK: SharedValue, TS: SharedValue, TV: SharedValue> GroupingKey
 struct InputsRequiredFromPrevRow<BK: SharedValue, TV: SharedValue, TS: SharedValue> {
     ever_encountered_a_source_event: Replicated<Boolean>,
     attributed_breakdown_key_bits: Replicated<BK>,
-    saturating_sum: BitDecomposed<Replicated<Boolean>>,
-    is_saturated: Replicated<Boolean>,
-    difference_to_cap: Replicated<TV>,
+    per_source_event_cap: Option<CappingState<TV>>,
+    per_source_trigger_count: Option<TriggerCount>,
     source_event_timestamp: Replicated<TS>,
 }
 
+/// Output of the attribution circuit for a single row, before per-user capping.
+struct AttributedRow<BK: SharedValue, TV: SharedValue> {
+    attributed_breakdown_key_bits: Replicated<BK>,
+    /// Magnitude of the attributed trigger value, after the per source event cap.
+    attributed_trigger_value: Replicated<TV>,
+    /// Sign of the attributed trigger value, if trigger values are signed.
+    is_negative: Option<Replicated<Boolean>>,
+}
+
 /// Returns the number of Boolean multiplications per input record, for use in computing the number
 /// of records in each DZKP. These multiplications are in `compute_row_with_previous` and the
 /// functions it calls.
-fn multiplications_per_record<BK: SharedValue, TV: SharedValue, TS: SharedValue>(
+fn multiplications_per_record<BK, TV, TS>(
     attribution_window: Option<NonZeroU32>,
-) -> usize {
+    capping: &CappingParameters,
+    time_to_conversion: Option<TimeToConversionBuckets>,
+) -> usize
+where
+    BK: SharedValue,
+    TV: BooleanArray + U128Conversions,
+    TS: SharedValue,
+{
     let mut count =
         // breakdown_key_of_most_recent_source_event
         BK::BITS +
         // zero_out_trigger_value_unless_attributed
-        // cumulative trigger value sum
-        // difference to cap
-        // compute_capped_trigger_value (2x)
-        5 * TV::BITS +
+        TV::BITS +
         // ever_encountered_a_source_event
-        // overflow_bit_and_prev_row_not_saturated
         // did_trigger_get_attributed
-        3;
+        2 +
+        // per-user capping
+        capping.strategy.multiplications_per_row::<TV>();
 
     if attribution_window.is_some() {
         count +=
@@ -142,6 +167,39 @@ fn multiplications_per_record<BK: SharedValue, TV: SharedValue, TS: SharedValue>
             1;
     }
 
+    if let Some(buckets) = time_to_conversion {
+        if attribution_window.is_none() {
+            // timestamp_of_most_recent_source_event
+            count += TS::BITS;
+        }
+        // time delta, and its comparison against every bucket boundary
+        count += u32::try_from(buckets.count()).unwrap() * TS::BITS;
+    }
+
+    if capping.signed_trigger_values {
+        // magnitude of the attributed trigger value
+        // sign of the capped trigger value
+        count += 2 * TV::BITS;
+    }
+
+    if let Some(limit) = capping.per_source_trigger_limit {
+        count += TriggerCount::multiplications_per_row(limit);
+    }
+
+    if let Some(cap) = capping.per_source_event_cap {
+        let cap_bits = cap.trailing_zeros();
+        count +=
+            // reset on source events
+            cap_bits + 1 +
+            // saturating sum
+            cap_bits +
+            // difference to cap
+            // compute_capped_trigger_value (2x)
+            3 * TV::BITS +
+            // overflow_bit_and_prev_row_not_saturated
+            1;
+    }
+
     usize::try_from(count).unwrap()
 }
 
@@ -159,29 +217,46 @@ where
     /// - Last touch attribution
     ///     - Every trigger event which is preceded by a source event is attributed
     ///     - Trigger events are attributed to the `breakdown_key` of the most recent preceding source event
-    /// - Per user capping
-    ///     - A cumulative sum of "Attributed Trigger Value" is maintained
+    /// - Per source event capping (optional)
+    ///     - If `per_source_event_cap` is set, the credit attributed to each source event is capped
+    ///     - A cumulative sum of "Attributed Trigger Value" is maintained, which starts over at every source event
     ///     - Bitwise addition is used, and a single bit indicates if the sum is "saturated"
     ///     - The only available values for "cap" are powers of 2 (i.e. 1, 2, 4, 8, 16, 32, ...)
     ///     - Prior to the cumulative sum reaching saturation, attributed trigger values are passed along
     ///     - The row which puts the cumulative sum over the cap is "capped" to the delta between the cumulative sum of the last row and the cap
     ///     - All subsequent rows contribute zero
+    /// - Per source trigger limit (optional)
+    ///     - If `per_source_trigger_limit` is set, each source event is credited by at most that many trigger events
+    ///     - A count of attributed trigger events is maintained, which starts over at every source event
+    ///     - Like the per source event cap, the count is offset so that a single bit indicates if the limit is reached
+    ///     - Attributed trigger events after that are treated as unattributed
+    /// - Per user capping is not done here, see [`credit_capping`]
     /// - Outputs
     ///     - If a user has `N` input rows, they will generate `N-1` output rows. (The first row cannot possibly contribute any value to the output)
     ///     - Each output row has two main values:
-    ///         - `capped_attributed_trigger_value` - the value to contribute to the output (bitwise secret-shared),
+    ///         - `attributed_trigger_value` - the value to cap per user and contribute to the output (bitwise secret-shared),
     ///         - `attributed_breakdown_key` - the breakdown to which this contribution applies (bitwise secret-shared),
     ///     - Additional output:
     ///         - `did_trigger_get_attributed` - a secret-shared bit indicating if this row corresponds to a trigger event
     ///           which was attributed. Might be able to reveal this (after a shuffle and the addition of dummies) to minimize
     ///           the amount of processing work that must be done in the Aggregation stage.
+    /// - Signed trigger values
+    ///     - If `signed_trigger_values` is set, trigger values are in two's complement, so that refunds can be
+    ///       reported as negative values
+    ///     - The cap applies to the sum of absolute values, so that refunds can't be used to make room under the cap
+    ///     - Capping is done on the magnitude of the attributed trigger value, and the sign is restored after per user capping
+    /// - Time to conversion
+    ///     - If `time_to_conversion` is set, the output row is keyed by the bucket of the time between the trigger event
+    ///       and the most recent source event, instead of the `breakdown_key` of that source event
     pub async fn compute_row_with_previous<C>(
         &mut self,
         ctx: C,
         record_id: RecordId,
         input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
         attribution_window_seconds: Option<NonZeroU32>,
-    ) -> Result<AttributionOutputs<Replicated<BK>, Replicated<TV>>, Error>
+        signed_trigger_values: bool,
+        time_to_conversion: Option<TimeToConversionBuckets>,
+    ) -> Result<AttributedRow<BK, TV>, Error>
     where
         C: Context,
         Replicated<Boolean>: BooleanProtocols<C>,
@@ -212,7 +287,7 @@ where
             timestamp_of_most_recent_source_event(
                 ctx.narrow(&PerRowStep::SourceEventTimestamp),
                 record_id,
-                attribution_window_seconds,
+                attribution_window_seconds.is_some() || time_to_conversion.is_some(),
                 &input_row.is_trigger_bit,
                 &self.source_event_timestamp,
                 &input_row.timestamp,
@@ -229,76 +304,71 @@ where
             attribution_window_seconds,
             &input_row.timestamp,
             &source_event_timestamp,
+            self.per_source_trigger_count.as_mut(),
         )
         .await?;
 
-        assert!(
-            TV::BITS <= EightBitStep::BITS,
-            "EightBitStep not large enough to accomodate this sum"
-        );
-        let (updated_sum, overflow_bit) = integer_add::<_, EightBitStep, 1>(
-            ctx.narrow(&PerRowStep::ComputeSaturatingSum),
-            record_id,
-            &self.saturating_sum,
-            &attributed_trigger_value.to_bits(),
-        )
-        .await?;
-
-        assert!(
-            TV::BITS <= EightBitStep::BITS,
-            "EightBitStep not large enough to accomodate this subtraction"
-        );
-        let (overflow_bit_and_prev_row_not_saturated, difference_to_cap) = try_join(
-            overflow_bit.multiply(
-                &self.is_saturated.clone().not(),
-                ctx.narrow(&PerRowStep::IsSaturatedAndPrevRowNotSaturated),
+        let (attributed_trigger_value, is_negative) = if signed_trigger_values {
+            let bits = attributed_trigger_value.to_bits();
+            let is_negative = bits[bits.len() - 1].clone();
+            let magnitude = integer_cond_negate::<_, SixteenBitStep, 1>(
+                ctx.narrow(&PerRowStep::TriggerValueMagnitude),
                 record_id,
-            ),
-            // It is okay that we are calling `integer_sub` with length(y) > length(x) here.
-            // `difference_to_cap` only needs to be accurate in the case where the next row will
-            // overflow. When that is the case, `updated_sum` must be within `2^TV::BITS` of the
-            // cap, and a `TV::BITS` subtraction of the `TV::BITS` least significant bits of
-            // `updated_sum` from zero will correctly compute the difference to the cap.
-            integer_sub::<_, EightBitStep>(
-                ctx.narrow(&PerRowStep::ComputeDifferenceToCap),
-                record_id,
-                &BitDecomposed::new(repeat_n(
-                    Replicated::ZERO,
-                    usize::try_from(TV::BITS).unwrap(),
-                )),
-                &updated_sum,
+                &bits,
+                &is_negative,
             )
-            .map(|res| res.map(BitDecomposed::collect_bits)),
-        )
-        .await?;
-
-        // Tricky way of expressing an `OR` condition, but with no additional multiplications:
-        //   Logically: "Did this row just become saturated OR was the previous row already saturated"
-        //   This works because these conditions cannot both be true
-        let is_saturated = &self.is_saturated + &overflow_bit_and_prev_row_not_saturated;
+            .await?;
+            (magnitude.collect_bits(), Some(is_negative))
+        } else {
+            (attributed_trigger_value, None)
+        };
 
-        let capped_attributed_trigger_value = compute_capped_trigger_value(
-            ctx,
-            record_id,
-            &is_saturated,
-            &overflow_bit_and_prev_row_not_saturated,
-            &self.difference_to_cap,
-            &attributed_trigger_value,
-        )
-        .await?;
+        let attributed_trigger_value = match self.per_source_event_cap.as_mut() {
+            Some(per_source_event_cap) => {
+                // The sum of credit for a source event starts over at every source event.
+                per_source_event_cap
+                    .reset_unless(
+                        ctx.narrow(&PerRowStep::ResetPerSourceEventCap),
+                        record_id,
+                        &input_row.is_trigger_bit,
+                    )
+                    .await?;
+                per_source_event_cap
+                    .cap(
+                        ctx.narrow(&PerRowStep::PerSourceEventCap),
+                        record_id,
+                        &attributed_trigger_value,
+                    )
+                    .await?
+            }
+            None => attributed_trigger_value,
+        };
 
+        let attributed_breakdown_key_bits = match time_to_conversion {
+            Some(buckets) => {
+                self.attributed_breakdown_key_bits = attributed_breakdown_key_bits;
+                buckets
+                    .bucket_of(
+                        ctx.narrow(&PerRowStep::TimeToConversionBucket),
+                        record_id,
+                        &input_row.timestamp,
+                        &source_event_timestamp,
+                    )
+                    .await?
+            }
+            None => {
+                self.attributed_breakdown_key_bits = attributed_breakdown_key_bits.clone();
+                attributed_breakdown_key_bits
+            }
+        };
         self.ever_encountered_a_source_event = ever_encountered_a_source_event;
-        self.attributed_breakdown_key_bits = attributed_breakdown_key_bits.clone();
-        self.saturating_sum = updated_sum;
-        self.is_saturated = is_saturated;
-        self.difference_to_cap = difference_to_cap;
         self.source_event_timestamp = source_event_timestamp;
 
-        let outputs_for_aggregation = AttributionOutputs {
+        Ok(AttributedRow {
             attributed_breakdown_key_bits,
-            capped_attributed_trigger_value,
-        };
-        Ok(outputs_for_aggregation)
+            attributed_trigger_value,
+            is_negative,
+        })
     }
 }
 
@@ -346,6 +416,52 @@ pub trait GroupingKey {
     fn get_grouping_key(&self) -> u64;
 }
 
+/// Arranges `input` so that the rows of every user are adjacent, which is all that
+/// [`histograms_ranges_sortkeys`] and attribution need. Users are not ordered by their keys:
+/// they appear in the order of their first row, and the rows of a user keep their relative
+/// order. This takes linear time, and rows that are already grouped stay in place. Its timing
+/// depends on the revealed keys, see [`crate::ff::constant_time`].
+#[tracing::instrument(name = "group_by_key", skip_all)]
+pub fn group_by_key<R: GroupingKey>(input: &mut [R]) {
+    // Index of the group of every row, numbering groups in the order they first appear.
+    let mut groups = HashMap::new();
+    let group_of = input
+        .iter()
+        .map(|row| {
+            let next = groups.len();
+            *groups.entry(row.get_grouping_key()).or_insert(next)
+        })
+        .collect::<Vec<_>>();
+
+    // Counting sort by group index, which is stable.
+    let mut offsets = vec![0; groups.len()];
+    for &group in &group_of {
+        offsets[group] += 1;
+    }
+    let mut start = 0;
+    for offset in &mut offsets {
+        let count = *offset;
+        *offset = start;
+        start += count;
+    }
+    let mut destination = group_of
+        .into_iter()
+        .map(|group| {
+            offsets[group] += 1;
+            offsets[group] - 1
+        })
+        .collect::<Vec<_>>();
+
+    // Move every row to its destination by following the cycles of the permutation.
+    for i in 0..input.len() {
+        while destination[i] != i {
+            let j = destination[i];
+            input.swap(i, j);
+            destination.swap(i, j);
+        }
+    }
+}
+
 #[tracing::instrument(name = "histograms_ranges_sortkeys", skip_all)]
 /// This function does following computations per user
 /// 1. Compute histogram of users with row counts. `histogram[row number]` contains the count of
@@ -360,31 +476,30 @@ where
     TV: BooleanArray,
     TS: BooleanArray,
 {
-    let mut histogram = vec![];
-    let mut last_prf = 0;
-    let mut cur_count = 0;
-    let mut start = 0;
-    let mut ranges = vec![];
-    for (idx, row) in input.iter_mut().enumerate() {
-        if idx != 0 && row.get_grouping_key() == last_prf {
-            cur_count += 1;
-        } else {
-            if idx > 0 {
-                ranges.push(start..idx);
-            }
-            start = idx;
-            cur_count = 0;
-            last_prf = row.get_grouping_key();
+    let ranges = user_ranges(input);
+    // The histogram is public, so the number of rows of every user can be used to index it.
+    let mut histogram = vec![0; ranges.iter().map(ExactSizeIterator::len).max().unwrap_or(0)];
+    for range in &ranges {
+        for (count, row) in input[range.clone()].iter_mut().enumerate() {
+            row.compute_sort_key(count.try_into().unwrap());
+            histogram[count] += 1;
         }
+    }
+    (histogram, ranges)
+}
 
-        row.compute_sort_key(cur_count.try_into().unwrap());
-        if histogram.len() <= cur_count {
-            histogram.push(0);
+/// Returns the range of rows of every user in `input`, in which the rows of every user must be
+/// adjacent.
+fn user_ranges<R: GroupingKey>(input: &[R]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for idx in 1..=input.len() {
+        if idx == input.len() || input[idx].get_grouping_key() != input[start].get_grouping_key() {
+            ranges.push(start..idx);
+            start = idx;
         }
-        histogram[cur_count] += 1;
     }
-    ranges.push(start..input.len());
-    (histogram, ranges)
+    ranges
 }
 
 fn set_up_contexts<C>(ctx: &C, histogram: &[usize]) -> Result<Vec<C>, Error>
@@ -406,40 +521,6 @@ where
     Ok(context_per_row_depth)
 }
 
-///
-/// Takes an input stream of `PrfShardedIpaInputRecordRow` which is assumed to have all records with a given PRF adjacent
-/// and converts it into a stream of vectors of `PrfShardedIpaInputRecordRow` having the same PRF.
-///
-/// Filters out any users that only have a single row, since they will produce no attributed conversions.
-///
-fn chunk_rows_by_user<IS, BK, TV, TS>(
-    input_stream: IS,
-    first_row: PrfShardedIpaInputRow<BK, TV, TS>,
-) -> impl Stream<Item = Vec<PrfShardedIpaInputRow<BK, TV, TS>>>
-where
-    BK: SharedValue,
-    TV: SharedValue,
-    TS: SharedValue,
-    IS: Stream<Item = PrfShardedIpaInputRow<BK, TV, TS>> + Unpin,
-{
-    unfold(Some((input_stream, first_row)), |state| async move {
-        let (mut s, last_row) = state?;
-        let mut last_row_prf = last_row.prf_of_match_key;
-        let mut current_chunk = vec![last_row];
-        while let Some(row) = s.next().await {
-            if row.prf_of_match_key == last_row_prf {
-                current_chunk.push(row);
-            } else if current_chunk.len() > 1 {
-                return Some((current_chunk, Some((s, row))));
-            } else {
-                last_row_prf = row.prf_of_match_key;
-                current_chunk = vec![row];
-            }
-        }
-        (current_chunk.len() > 1).then_some((current_chunk, None))
-    })
-}
-
 /// Sub-protocol of the PRF-sharded IPA Protocol
 ///
 /// After the computation of the per-user PRF, addition of dummy records and shuffling,
@@ -449,13 +530,21 @@ where
 /// This circuit expects to receive records from multiple users,
 /// but with all of the records from a given user adjacent to one another, and in time order.
 ///
-/// This circuit will compute attribution, per-user capping and aggregation.
+/// This circuit will compute attribution, per-user capping and aggregation. Capping is
+/// configured by `capping`, and `aggregation` selects the aggregation protocol and what it does
+/// with sums that overflow `HV`.
+///
+/// If `time_to_conversion` is set, attributed values are aggregated by the bucket of the time
+/// between the trigger event and the source event it was attributed to, instead of by breakdown
+/// key. `BK` must be able to hold the index of every bucket.
 ///
 /// # Errors
-/// Propagates errors from multiplications
+/// If `capping` has parameters the protocol can't run with, and propagates errors from
+/// multiplications
 /// # Panics
 /// Propagates errors from multiplications
 #[tracing::instrument(name = "attribute_cap_aggregate", skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub async fn attribute_cap_aggregate<
     'ctx,
     C,
@@ -471,6 +560,9 @@ pub async fn attribute_cap_aggregate<
     attribution_window_seconds: Option<NonZeroU32>,
     histogram: &[usize],
     padding_parameters: &PaddingParameters,
+    capping: CappingParameters,
+    aggregation: AggregationParameters,
+    time_to_conversion: Option<TimeToConversionBuckets>,
 ) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
 where
     C: UpgradableContext + Shuffle + 'ctx,
@@ -499,13 +591,19 @@ where
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
 {
+    capping.check(SS_BITS)?;
+
     // Get the validator and context to use for Boolean multiplication operations.
     // Record IDs count users. The maximum number of multiplications per record (user) is:
     // (max_events - 1) * multiplictions_per_record, because the attribution circuit is
     // only evaluated for the second and subsequent records.
     let chunk_size = TARGET_PROOF_SIZE
         / ((histogram.len() - 1)
-            * multiplications_per_record::<BK, TV, TS>(attribution_window_seconds));
+            * multiplications_per_record::<BK, TV, TS>(
+                attribution_window_seconds,
+                &capping,
+                time_to_conversion,
+            ));
 
     // Tricky hacks to work around the limitations of our current infrastructure
     let mut dzkp_validator = sh_ctx.clone().dzkp_validator(
@@ -524,16 +622,20 @@ where
     dzkp_validator.set_total_records(TotalRecords::specified(histogram[1]).unwrap());
     let ctx_for_row_number = set_up_contexts(&dzkp_validator.context(), histogram)?;
 
-    // Chunk the incoming stream of records into stream of vectors of records with the same PRF
-    let mut input_stream = stream::iter(input_rows);
-    let Some(first_row) = input_stream.next().await else {
+    if input_rows.is_empty() {
         return Ok(BitDecomposed::new(
             iter::repeat(Replicated::<Boolean, B>::ZERO).take(B),
         ));
-    };
-    let rows_chunked_by_user = chunk_rows_by_user(input_stream, first_row);
-
-    let mut collected = rows_chunked_by_user.collect::<Vec<_>>().await;
+    }
+    // Split the records into the records of every user. Users with a single row produce no
+    // attributed conversions.
+    let ranges = user_ranges(&input_rows);
+    let mut rows = input_rows.into_iter();
+    let mut collected = ranges
+        .into_iter()
+        .map(|range| rows.by_ref().take(range.len()).collect::<Vec<_>>())
+        .filter(|rows_for_user| rows_for_user.len() > 1)
+        .collect::<Vec<_>>();
     collected.sort_by(|a, b| std::cmp::Ord::cmp(&b.len(), &a.len()));
 
     let flattened_user_results = attribute::<_, _, _, _, SS_BITS, B>(
@@ -541,15 +643,30 @@ where
         ctx_for_row_number,
         collected,
         attribution_window_seconds,
+        capping,
+        time_to_conversion,
     );
 
     let user_contributions = flattened_user_results.try_collect::<Vec<_>>().await?;
-    breakdown_reveal_aggregation::<_, BK, TV, HV, B>(
-        sh_ctx.narrow(&Step::Aggregate),
-        user_contributions,
-        padding_parameters,
-    )
-    .await
+    let sum = Summation::new(capping.signed_trigger_values, aggregation.overflow);
+    let aggregate_ctx = sh_ctx
+        .narrow(&Step::Aggregate)
+        .limit_active_work(ConcurrencyStage::Aggregation);
+    match aggregation.method {
+        AggregationMethod::BreakdownReveal => {
+            breakdown_reveal_aggregation::<_, BK, TV, HV, B>(
+                aggregate_ctx,
+                user_contributions,
+                padding_parameters,
+                sum,
+                aggregation.prune_zero_rows,
+            )
+            .await
+        }
+        AggregationMethod::Oblivious => {
+            oblivious_aggregation::<_, BK, TV, HV, B>(aggregate_ctx, user_contributions, sum).await
+        }
+    }
 }
 
 #[tracing::instrument(name = "attribute_cap", skip_all, fields(unique_match_keys = input.len()))]
@@ -558,6 +675,8 @@ fn attribute<'ctx, V, BK, TV, TS, const SS_BITS: usize, const B: usize>(
     contexts: Vec<V::Context>,
     input: Vec<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>,
     attribution_window_seconds: Option<NonZeroU32>,
+    capping: CappingParameters,
+    time_to_conversion: Option<TimeToConversionBuckets>,
 ) -> impl Stream<Item = Result<SecretSharedAttributionOutputs<BK, TV>, Error>> + Send + 'ctx
 where
     V: DZKPValidator + 'ctx,
@@ -582,6 +701,8 @@ where
                     RecordId::from(record_id),
                     rows_for_user,
                     attribution_window_seconds,
+                    capping,
+                    time_to_conversion,
                 )
             });
 
@@ -598,6 +719,8 @@ async fn evaluate_per_user_attribution_circuit<C, BK, TV, TS, const SS_BITS: usi
     record_id: RecordId,
     rows_for_user: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
     attribution_window_seconds: Option<NonZeroU32>,
+    capping: CappingParameters,
+    time_to_conversion: Option<TimeToConversionBuckets>,
 ) -> Result<Vec<SecretSharedAttributionOutputs<BK, TV>>, Error>
 where
     C: DZKPContext,
@@ -614,16 +737,71 @@ where
         return Ok(Vec::new());
     }
     let first_row = &rows_for_user[0];
-    let mut prev_row_inputs =
-        initialize_new_device_attribution_variables::<BK, TV, TS, SS_BITS>(first_row);
+    let mut prev_row_inputs = initialize_new_device_attribution_variables(
+        first_row,
+        capping.per_source_event_cap,
+        capping.per_source_trigger_limit,
+    );
 
-    let mut output = Vec::with_capacity(rows_for_user.len() - 1);
-    for (row, ctx) in zip(rows_for_user.iter().skip(1), ctx_for_row_number.into_iter()) {
-        let capped_attribution_outputs = prev_row_inputs
-            .compute_row_with_previous(ctx, record_id, row, attribution_window_seconds)
+    let mut attributed_rows = Vec::with_capacity(rows_for_user.len() - 1);
+    for (row, ctx) in zip(rows_for_user.iter().skip(1), ctx_for_row_number.iter()) {
+        let attributed_row = prev_row_inputs
+            .compute_row_with_previous(
+                ctx.clone(),
+                record_id,
+                row,
+                attribution_window_seconds,
+                capping.signed_trigger_values,
+                time_to_conversion,
+            )
             .await?;
 
-        output.push(capped_attribution_outputs);
+        attributed_rows.push(attributed_row);
+    }
+
+    let attributed_trigger_values = attributed_rows
+        .iter()
+        .map(|row| row.attributed_trigger_value.clone())
+        .collect::<Vec<_>>();
+    let capped_trigger_values = match (capping.strategy, capping.per_user_cap.value(SS_BITS)?) {
+        (_, None) => attributed_trigger_values,
+        (CappingStrategy::Hard, Some(1)) => {
+            UnitCap
+                .cap_user_contributions(&ctx_for_row_number, record_id, &attributed_trigger_values)
+                .await?
+        }
+        (CappingStrategy::Hard, Some(cap)) => {
+            HardCap::new(cap)
+                .cap_user_contributions(&ctx_for_row_number, record_id, &attributed_trigger_values)
+                .await?
+        }
+        (CappingStrategy::Proportional, Some(cap)) => {
+            ProportionalCap::new(cap)
+                .cap_user_contributions(&ctx_for_row_number, record_id, &attributed_trigger_values)
+                .await?
+        }
+    };
+
+    let mut output = Vec::with_capacity(attributed_rows.len());
+    for ((row, capped_attributed_trigger_value), ctx) in zip(
+        zip(attributed_rows, capped_trigger_values),
+        ctx_for_row_number,
+    ) {
+        let capped_attributed_trigger_value = match row.is_negative {
+            Some(is_negative) => integer_cond_negate::<_, SixteenBitStep, 1>(
+                ctx.narrow(&PerRowStep::SignedCappedTriggerValue),
+                record_id,
+                &capped_attributed_trigger_value.to_bits(),
+                &is_negative,
+            )
+            .await?
+            .collect_bits(),
+            None => capped_attributed_trigger_value,
+        };
+        output.push(AttributionOutputs {
+            attributed_breakdown_key_bits: row.attributed_breakdown_key_bits,
+            capped_attributed_trigger_value,
+        });
     }
     Ok(output)
 }
@@ -632,22 +810,21 @@ where
 /// Upon encountering the first row of data from a new user (as distinguished by a different OPRF of the match key)
 /// this function encapsulates the variables that must be initialized. No communication is required for this first row.
 ///
-fn initialize_new_device_attribution_variables<BK, TV, TS, const SS_BITS: usize>(
+fn initialize_new_device_attribution_variables<BK, TV, TS>(
     input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
+    per_source_event_cap: Option<u32>,
+    per_source_trigger_limit: Option<u32>,
 ) -> InputsRequiredFromPrevRow<BK, TV, TS>
 where
     BK: SharedValue,
-    TV: SharedValue,
+    TV: BooleanArray + U128Conversions,
     TS: SharedValue,
 {
     InputsRequiredFromPrevRow {
         ever_encountered_a_source_event: input_row.is_trigger_bit.clone().not(),
         attributed_breakdown_key_bits: input_row.breakdown_key.clone(),
-        saturating_sum: BitDecomposed::new(repeat_n(Replicated::ZERO, SS_BITS)),
-        is_saturated: Replicated::<Boolean>::ZERO,
-        // This is incorrect in the case that the CAP is less than the maximum value of "trigger value" for a single row
-        // Not a problem if you assume that's an invalid input
-        difference_to_cap: Replicated::<TV>::ZERO,
+        per_source_event_cap: per_source_event_cap.map(CappingState::new),
+        per_source_trigger_count: per_source_trigger_limit.map(TriggerCount::new),
         source_event_timestamp: input_row.timestamp.clone(),
     }
 }
@@ -681,12 +858,13 @@ where
     .await
 }
 
-/// Same as above but for timestamps. If `attribution_window_seconds` is `None`, just
-/// return the previous row's timestamp. The bits aren't used but saves some multiplications.
+/// Same as above but for timestamps. If `is_needed` is not set, because there is neither an
+/// attribution window nor a time-to-conversion histogram, just return the previous row's
+/// timestamp. The bits aren't used but saves some multiplications.
 async fn timestamp_of_most_recent_source_event<C, TS>(
     ctx: C,
     record_id: RecordId,
-    attribution_window_seconds: Option<NonZeroU32>,
+    is_needed: bool,
     is_trigger_bit: &Replicated<Boolean>,
     prev_row_timestamp_bits: &Replicated<TS>,
     cur_row_timestamp_bits: &Replicated<TS>,
@@ -696,18 +874,17 @@ where
     TS: BooleanArray + U128Conversions,
     Replicated<TS>: BooleanArrayMul<C>,
 {
-    match attribution_window_seconds {
-        None => Ok(prev_row_timestamp_bits.clone()),
-        Some(_) => {
-            select(
-                ctx,
-                record_id,
-                is_trigger_bit,
-                prev_row_timestamp_bits,
-                cur_row_timestamp_bits,
-            )
-            .await
-        }
+    if is_needed {
+        select(
+            ctx,
+            record_id,
+            is_trigger_bit,
+            prev_row_timestamp_bits,
+            cur_row_timestamp_bits,
+        )
+        .await
+    } else {
+        Ok(prev_row_timestamp_bits.clone())
     }
 }
 
@@ -719,6 +896,7 @@ where
 /// The logic here is extremely simple. There is a secret-shared bit indicating if a given row is an "attributed trigger event" and
 /// another secret-shared bit indicating if a given row is within the attribution window. We multiply these two bits together and
 /// multiply it with the bits of the `trigger_value` in order to zero out contributions from unattributed trigger events.
+/// If there is a per source trigger limit, attributed trigger events past it are zeroed out as well.
 ///
 #[allow(clippy::too_many_arguments)]
 async fn zero_out_trigger_value_unless_attributed<C, TV, TS>(
@@ -730,6 +908,7 @@ async fn zero_out_trigger_value_unless_attributed<C, TV, TS>(
     attribution_window_seconds: Option<NonZeroU32>,
     trigger_event_timestamp: &Replicated<TS>,
     source_event_timestamp: &Replicated<TS>,
+    per_source_trigger_count: Option<&mut TriggerCount>,
 ) -> Result<Replicated<TV>, Error>
 where
     C: Context,
@@ -764,6 +943,20 @@ where
         did_trigger_get_attributed.clone()
     };
 
+    let zero_out_flag = match per_source_trigger_count {
+        Some(count) => {
+            count
+                .count(
+                    ctx.narrow(&ZeroOutTriggerStep::PerSourceTriggerLimit),
+                    record_id,
+                    is_trigger_bit,
+                    &zero_out_flag,
+                )
+                .await?
+        }
+        None => zero_out_flag,
+    };
+
     select(
         ctx,
         record_id,
@@ -824,67 +1017,16 @@ where
     }
 }
 
-///
-/// To provide a differential privacy guarantee, we need to bound the maximum contribution from any given user to some cap.
-///
-/// The following values are computed for each row:
-/// (1) The uncapped "Attributed trigger value" (which is either the original `trigger_value` bits or zero if it was unattributed)
-/// (2) The cumulative sum of "Attributed trigger value" thus far (which "saturates" at a given power of two as indicated by the `is_saturated` flag)
-/// (3) The "delta to cap", which is the difference between the "cap" and the cumulative sum (this value is meaningless once the cumulative sum is saturated)
-///
-/// To perfectly cap each user's contributions at precisely the cap, the "attributed trigger value" will sometimes need to be lowered,
-/// such that the total cumulative sum adds up to exactly the cap.
-///
-/// This oblivious algorithm computes the "capped attributed trigger value" in the following way:
-/// IF the cumulative is NOT YET saturated:
-///     - just return the attributed trigger value
-/// ELSE IF the cumulative sum JUST became saturated (that is, it was NOT saturated on the preceding line but IS on this line):
-///     - return the "delta to cap" from the preceding line
-/// ELSE
-///     - return zero
-///
-async fn compute_capped_trigger_value<C, TV>(
-    ctx: C,
-    record_id: RecordId,
-    is_saturated: &Replicated<Boolean>,
-    is_saturated_and_prev_row_not_saturated: &Replicated<Boolean>,
-    prev_row_diff_to_cap: &Replicated<TV>,
-    attributed_trigger_value: &Replicated<TV>,
-) -> Result<Replicated<TV>, Error>
-where
-    C: Context,
-    TV: BooleanArray + U128Conversions,
-    Replicated<TV>: BooleanArrayMul<C>,
-{
-    let narrowed_ctx1 =
-        ctx.narrow(&PerRowStep::ComputedCappedAttributedTriggerValueNotSaturatedCase);
-    let narrowed_ctx2 =
-        ctx.narrow(&PerRowStep::ComputedCappedAttributedTriggerValueJustSaturatedCase);
-
-    let attributed_trigger_value_or_zero = select(
-        narrowed_ctx1,
-        record_id,
-        is_saturated,
-        &Replicated::new(<TV as SharedValue>::ZERO, <TV as SharedValue>::ZERO),
-        attributed_trigger_value,
-    )
-    .await?;
-
-    select(
-        narrowed_ctx2,
-        record_id,
-        is_saturated_and_prev_row_not_saturated,
-        prev_row_diff_to_cap,
-        &attributed_trigger_value_or_zero,
-    )
-    .await
-}
-
 #[cfg(all(test, unit_test))]
 pub mod tests {
     use std::{iter::repeat_n, num::NonZeroU32};
 
-    use super::{AttributionOutputs, PrfShardedIpaInputRow};
+    use super::{
+        credit_capping::{CappingParameters, CappingStrategy},
+        group_by_key, multiplications_per_record,
+        step::AttributionStep,
+        user_ranges, AggregationParameters, AttributionOutputs, GroupingKey, PrfShardedIpaInputRow,
+    };
     use crate::{
         ff::{
             boolean::Boolean,
@@ -899,8 +1041,9 @@ pub mod tests {
             replicated::semi_honest::AdditiveShare as Replicated, IntoShares, SharedValue,
             TransposeFrom,
         },
+        telemetry::metrics::RECORDS_SENT,
         test_executor::run,
-        test_fixture::{Reconstruct, Runner, TestWorld},
+        test_fixture::{Reconstruct, Runner, TestWorld, TestWorldConfig},
     };
 
     #[derive(Clone)]
@@ -1084,6 +1227,9 @@ pub mod tests {
                             None,
                             &histogram,
                             &PaddingParameters::relaxed(),
+                            CappingParameters::default(),
+                            AggregationParameters::default(),
+                            None,
                         )
                         .await
                         .unwrap(),
@@ -1145,6 +1291,206 @@ pub mod tests {
                             NonZeroU32::new(ATTRIBUTION_WINDOW_SECONDS),
                             &histogram,
                             &PaddingParameters::relaxed(),
+                            CappingParameters::default(),
+                            AggregationParameters::default(),
+                            None,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn semi_honest_signed_trigger_values() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            // Trigger values are three bit two's complement numbers, so 5 is -3 and 6 is -2.
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User: a refund partially cancels a purchase */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 3),
+                oprf_test_input(123, true, 0, 6),
+                /* Second User: refunds count towards the cap */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                oprf_test_input(234, true, 0, 5),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User */
+                oprf_test_input(345, false, 20, 0),
+                oprf_test_input(345, true, 0, 3),
+                oprf_test_input(345, true, 0, 3),
+                oprf_test_input(345, true, 0, 3),
+            ];
+
+            // The cap is 8.
+            let mut expected = [0_u128; 32];
+            expected[12] = (1 << 16) - 8;
+            expected[17] = 1;
+            expected[20] = 8;
+
+            let histogram = [3, 3, 3, 2];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 3, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            CappingParameters {
+                                signed_trigger_values: true,
+                                ..CappingParameters::default()
+                            },
+                            AggregationParameters::default(),
+                            None,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn semi_honest_per_source_event_cap() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, true, 0, 3), // source event sum = 10, capped to 1
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 5),
+                oprf_test_input(123, true, 0, 2),
+                oprf_test_input(123, true, 0, 4), // source event sum = 11, capped to 1
+                /* Second User */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 7),
+                oprf_test_input(234, true, 0, 7), // source event sum = 14, capped to 1
+                oprf_test_input(234, true, 0, 7), // source event is saturated
+                oprf_test_input(234, false, 18, 0),
+                oprf_test_input(234, true, 0, 7),
+                oprf_test_input(234, true, 0, 7), // user sum = 16, per-user cap is reached
+                oprf_test_input(234, false, 20, 0),
+                oprf_test_input(234, true, 0, 7), // user is saturated
+            ];
+
+            // The per-source event cap is 8, the per-user cap is 16.
+            let mut expected = [0_u128; 32];
+            expected[12] = 8;
+            expected[17] = 8;
+            expected[18] = 8;
+            expected[20] = 8;
+
+            let histogram = [2, 2, 2, 2, 2, 2, 2, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 4, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            CappingParameters {
+                                per_source_event_cap: Some(8),
+                                ..CappingParameters::default()
+                            },
+                            AggregationParameters::default(),
+                            None,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn semi_honest_per_source_trigger_limit() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 1),
+                oprf_test_input(123, true, 0, 2),
+                oprf_test_input(123, true, 0, 4),
+                oprf_test_input(123, true, 0, 7), // limit of 3 is reached, no credit
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 3), // count starts over
+                /* Second User */
+                oprf_test_input(234, true, 0, 5), // not attributed, does not count
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 1),
+                oprf_test_input(234, true, 0, 1),
+                oprf_test_input(234, true, 0, 1),
+                oprf_test_input(234, true, 0, 1), // limit of 3 is reached, no credit
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 4),
+            ];
+
+            let mut expected = [0_u128; 32];
+            expected[12] = 7;
+            expected[17] = 7;
+            expected[20] = 3;
+
+            let histogram = [2, 2, 2, 2, 2, 2, 2, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .semi_honest(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 4, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            CappingParameters {
+                                per_source_trigger_limit: Some(3),
+                                ..CappingParameters::default()
+                            },
+                            AggregationParameters::default(),
+                            None,
                         )
                         .await
                         .unwrap(),
@@ -1163,6 +1509,130 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn semi_honest_proportional_capping() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User: total of 10 is scaled by 8/10 */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 3),
+                /* Second User: under the cap */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User: exactly at the cap */
+                oprf_test_input(345, false, 20, 0),
+                oprf_test_input(345, true, 0, 4),
+                oprf_test_input(345, true, 0, 4),
+                /* Fourth User: total of 28 is scaled by 8/28 */
+                oprf_test_input(456, false, 18, 0),
+                oprf_test_input(456, true, 0, 7),
+                oprf_test_input(456, true, 0, 7),
+                oprf_test_input(456, true, 0, 7),
+                oprf_test_input(456, true, 0, 7),
+            ];
+
+            // The cap is 8. Scaled values are rounded down.
+            let mut expected = [0_u128; 32];
+            expected[12] = 5;
+            expected[17] = 5;
+            expected[18] = 4;
+            expected[20] = 2 + 8;
+
+            let histogram = [4, 4, 3, 2, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 3, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            CappingParameters {
+                                strategy: CappingStrategy::Proportional,
+                                ..CappingParameters::default()
+                            },
+                            AggregationParameters::default(),
+                            None,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn attribution_communication_budget() {
+        run(|| async move {
+            let world = TestWorld::new_with(TestWorldConfig::default().enable_metrics());
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 3),
+                /* Second User */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User */
+                oprf_test_input(345, false, 20, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+            ];
+            let histogram = [3, 3, 2, 1];
+
+            world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                        ctx,
+                        input_rows,
+                        None,
+                        &histogram,
+                        &PaddingParameters::relaxed(),
+                        CappingParameters::default(),
+                        AggregationParameters::default(),
+                        None,
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await;
+
+            // The attribution circuit runs for every row except the first row of each user.
+            // Each of its multiplications sends at most one record from every helper.
+            let attributed_rows = histogram[1..].iter().sum::<usize>();
+            let budget = 3
+                * attributed_rows
+                * multiplications_per_record::<BA5, BA3, BA20>(
+                    None,
+                    &CappingParameters::default(),
+                    None,
+                );
+
+            world
+                .metrics_snapshot()
+                .assert_metric(RECORDS_SENT)
+                .per_step_at_most(&world.gate().narrow(&AttributionStep::Attribute), budget);
+        });
+    }
+
     #[test]
     #[should_panic(expected = "Step index 64 out of bounds for UserNthRowStep with count 64.")]
     fn attribution_too_many_records_per_user() {
@@ -1183,6 +1653,9 @@ pub mod tests {
                         None,
                         histogram_ref,
                         &PaddingParameters::relaxed(),
+                        CappingParameters::default(),
+                        AggregationParameters::default(),
+                        None,
                     )
                     .await
                     .unwrap()
@@ -1275,6 +1748,9 @@ pub mod tests {
                             None,
                             &HISTOGRAM,
                             &PaddingParameters::relaxed(),
+                            CappingParameters::default(),
+                            AggregationParameters::default(),
+                            None,
                         )
                         .await
                         .unwrap(),
@@ -1292,4 +1768,62 @@ pub mod tests {
             );
         });
     }
+
+    struct Keyed(u64);
+
+    impl GroupingKey for Keyed {
+        fn get_grouping_key(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn keys(rows: &[Keyed]) -> Vec<u64> {
+        rows.iter().map(|r| r.0).collect()
+    }
+
+    #[test]
+    fn grouped_rows_stay_in_place() {
+        let mut rows = [3, 3, 1, 2, 2, 2].map(Keyed);
+        group_by_key(&mut rows);
+        assert_eq!(vec![3, 3, 1, 2, 2, 2], keys(&rows));
+
+        let mut rows: [Keyed; 0] = [];
+        group_by_key(&mut rows);
+    }
+
+    #[test]
+    fn ungrouped_rows_are_grouped() {
+        let mut rows = [3, 1, 3, 2, 1, 3].map(Keyed);
+        group_by_key(&mut rows);
+        assert_eq!(vec![3, 3, 3, 1, 1, 2], keys(&rows));
+    }
+
+    #[test]
+    fn grouping_is_stable() {
+        struct Row(u64, usize);
+        impl GroupingKey for Row {
+            fn get_grouping_key(&self) -> u64 {
+                self.0
+            }
+        }
+
+        let mut rows = [2, 1, 2, 1, 2]
+            .into_iter()
+            .enumerate()
+            .map(|(i, key)| Row(key, i))
+            .collect::<Vec<_>>();
+        group_by_key(&mut rows);
+        assert_eq!(
+            vec![(2, 0), (2, 2), (2, 4), (1, 1), (1, 3)],
+            rows.iter().map(|row| (row.0, row.1)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn ranges_of_users() {
+        let rows = [3, 3, 1, 2, 2, 2].map(Keyed);
+        assert_eq!(vec![0..2, 2..3, 3..6], user_ranges(&rows));
+        assert_eq!(vec![0..1], user_ranges(&[Keyed(0)]));
+        assert!(user_ranges::<Keyed>(&[]).is_empty());
+    }
 }
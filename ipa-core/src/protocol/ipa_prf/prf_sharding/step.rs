@@ -21,21 +21,104 @@ pub(crate) enum AttributionPerRowStep {
     #[step(child = AttributionZeroOutTriggerStep)]
     AttributedTriggerValue,
     SourceEventTimestamp,
-    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    TriggerValueMagnitude,
+    #[step(child = ResetCapStep)]
+    ResetPerSourceEventCap,
+    #[step(child = AttributionCapStep)]
+    PerSourceEventCap,
+    #[step(child = AttributionCapStep)]
+    PerUserCap,
+    #[step(child = ProportionalCapStep)]
+    ProportionalPerUserCap,
+    #[step(child = UnitCapStep)]
+    UnitPerUserCap,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    SignedCappedTriggerValue,
+    #[step(child = TimeToConversionStep)]
+    TimeToConversionBucket,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum AttributionCapStep {
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
     ComputeSaturatingSum,
     IsSaturatedAndPrevRowNotSaturated,
-    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
     ComputeDifferenceToCap,
     ComputedCappedAttributedTriggerValueNotSaturatedCase,
     ComputedCappedAttributedTriggerValueJustSaturatedCase,
 }
 
+#[derive(CompactStep)]
+pub(crate) enum ResetCapStep {
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    Sum,
+    IsSaturated,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum UnitCapStep {
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    IsNonZero,
+    KeepFirst,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum ProportionalCapStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    UserSum,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    IsOverCap,
+    #[step(child = ScaleDivisionStep)]
+    ComputeScale,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    ClampScale,
+    #[step(child = ScaleBitStep)]
+    ScaleValue,
+}
+
+#[derive(CompactStep)]
+#[step(count = 12, child = DivisionStep, name = "quotient_bit")]
+pub struct ScaleDivisionStep(usize);
+
+#[derive(CompactStep)]
+pub(crate) enum DivisionStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    Subtract,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    Select,
+}
+
+#[derive(CompactStep)]
+#[step(count = 16, child = ScaleValueStep, name = "value_bit")]
+pub struct ScaleBitStep(usize);
+
+#[derive(CompactStep)]
+pub(crate) enum ScaleValueStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    PartialProduct,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    Accumulate,
+}
+
 #[derive(CompactStep)]
 pub(crate) enum AttributionZeroOutTriggerStep {
     DidTriggerGetAttributed,
     #[step(child = AttributionWindowStep)]
     CheckAttributionWindow,
     AttributedEventCheckFlag,
+    #[step(child = AttributionTriggerLimitStep)]
+    PerSourceTriggerLimit,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum AttributionTriggerLimitStep {
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    ResetCount,
+    IsCredited,
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    IncrementCount,
 }
 
 #[derive(CompactStep)]
@@ -46,6 +129,19 @@ pub(crate) enum AttributionWindowStep {
     CompareTimeDeltaToAttributionWindow,
 }
 
+#[derive(CompactStep)]
+pub(crate) enum TimeToConversionStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    ComputeTimeDelta,
+    #[step(child = TimeToConversionBoundaryStep)]
+    CompareTimeDeltaToBoundary,
+}
+
+/// One step per boundary between two of the at most 16 time-to-conversion buckets.
+#[derive(CompactStep)]
+#[step(count = 15, child = crate::protocol::boolean::step::ThirtyTwoBitStep, name = "boundary")]
+pub struct TimeToConversionBoundaryStep(usize);
+
 #[derive(CompactStep)]
 pub(crate) enum FeatureLabelDotProductStep {
     BinaryValidator,
@@ -0,0 +1,258 @@
+//! Time-to-conversion histogram.
+//!
+//! Besides the totals per breakdown key, a query can ask for the distribution of the time between
+//! an attributed trigger event and the source event it was attributed to. Time deltas are
+//! bucketed by public boundaries, and the bucket index takes the place of the breakdown key, so
+//! that the distribution can be capped, aggregated and noised like any other histogram.
+
+use std::num::NonZeroU32;
+
+use crate::{
+    error::Error,
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, U128Conversions},
+    protocol::{
+        basics::{BooleanProtocols, ShareKnownValue},
+        boolean::{step::ThirtyTwoBitStep, NBitStep},
+        context::Context,
+        ipa_prf::{
+            boolean_ops::comparison_and_subtraction_sequential::{compare_gt, integer_sub},
+            prf_sharding::step::{
+                TimeToConversionBoundaryStep as BoundaryStep, TimeToConversionStep as Step,
+            },
+        },
+        RecordId,
+    },
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed},
+};
+
+/// Maximum number of time-to-conversion buckets. Every boundary between two buckets costs a
+/// comparison per row.
+pub const MAX_TIME_TO_CONVERSION_BUCKETS: usize = 16;
+
+/// Public boundaries of the time-to-conversion buckets, in the units of the input timestamps.
+///
+/// Bucket `0` holds time deltas below the first lower bound, and the last bucket holds all time
+/// deltas at or above the last lower bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeToConversionBuckets {
+    lower_bounds: [u32; MAX_TIME_TO_CONVERSION_BUCKETS - 1],
+    buckets: usize,
+}
+
+impl TimeToConversionBuckets {
+    /// Creates buckets from the lower bounds of all buckets but the first one.
+    ///
+    /// # Panics
+    /// If there are fewer than two or more than [`MAX_TIME_TO_CONVERSION_BUCKETS`] buckets, or
+    /// if the lower bounds are zero or decreasing.
+    #[must_use]
+    pub fn new(lower_bounds: &[u32]) -> Self {
+        assert!(
+            (1..MAX_TIME_TO_CONVERSION_BUCKETS).contains(&lower_bounds.len()),
+            "between 2 and {MAX_TIME_TO_CONVERSION_BUCKETS} time-to-conversion buckets are supported"
+        );
+        assert!(
+            lower_bounds.first() != Some(&0) && lower_bounds.is_sorted(),
+            "time-to-conversion bucket bounds must be positive and increasing"
+        );
+        let mut bounds = [0; MAX_TIME_TO_CONVERSION_BUCKETS - 1];
+        bounds[..lower_bounds.len()].copy_from_slice(lower_bounds);
+        Self {
+            lower_bounds: bounds,
+            buckets: lower_bounds.len() + 1,
+        }
+    }
+
+    /// Creates `buckets` buckets that are `width` wide, except for the last one.
+    ///
+    /// # Panics
+    /// If `buckets` is not supported, see [`Self::new`].
+    #[must_use]
+    pub fn linear(width: NonZeroU32, buckets: usize) -> Self {
+        let lower_bounds = (1..buckets)
+            .map(|i| width.get().saturating_mul(u32::try_from(i).unwrap()))
+            .collect::<Vec<_>>();
+        Self::new(&lower_bounds)
+    }
+
+    /// Number of buckets.
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.buckets
+    }
+
+    /// Lower bounds of all buckets but the first one.
+    #[must_use]
+    pub fn lower_bounds(&self) -> &[u32] {
+        &self.lower_bounds[..self.buckets - 1]
+    }
+
+    /// Returns the index of the bucket that the time between the source event and the trigger
+    /// event falls into. The trigger event must not precede the source event.
+    ///
+    /// The time delta is compared against every lower bound, using the same circuits as the
+    /// attribution window check. The index of the bucket is computed from the results of these
+    /// comparisons without further multiplications.
+    ///
+    /// # Errors
+    /// Propagates errors from multiplications.
+    /// # Panics
+    /// If `BK` can't hold the index of the last bucket, or if `TS` is wider than 32 bits.
+    pub(super) async fn bucket_of<C, BK, TS>(
+        &self,
+        ctx: C,
+        record_id: RecordId,
+        trigger_event_timestamp: &Replicated<TS>,
+        source_event_timestamp: &Replicated<TS>,
+    ) -> Result<Replicated<BK>, Error>
+    where
+        C: Context,
+        BK: BooleanArray,
+        TS: BooleanArray + U128Conversions,
+        Replicated<Boolean>: BooleanProtocols<C>,
+    {
+        assert!(
+            self.buckets <= 1 << BK::BITS,
+            "{} time-to-conversion buckets do not fit into {} bits",
+            self.buckets,
+            BK::BITS
+        );
+        assert!(
+            TS::BITS <= ThirtyTwoBitStep::BITS,
+            "ThirtyTwoBitStep is not large enough to accomodate this subtraction"
+        );
+        let time_delta_bits = integer_sub::<_, ThirtyTwoBitStep>(
+            ctx.narrow(&Step::ComputeTimeDelta),
+            record_id,
+            &trigger_event_timestamp.to_bits(),
+            &source_event_timestamp.to_bits(),
+        )
+        .await?;
+
+        // `reached[i]` is set if the time delta is at least the lower bound of bucket `i + 1`.
+        let max_time_delta = (1_u128 << TS::BITS) - 1;
+        let compare_ctx = ctx.narrow(&Step::CompareTimeDeltaToBoundary);
+        let reached = compare_ctx
+            .parallel_join(
+                self.lower_bounds()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &lower_bound)| {
+                        let ctx = compare_ctx.narrow(&BoundaryStep::from(i));
+                        let time_delta_bits = &time_delta_bits;
+                        async move {
+                            if u128::from(lower_bound) > max_time_delta {
+                                return Ok(Replicated::ZERO);
+                            }
+                            let threshold_bits = BitDecomposed::decompose(TS::BITS, |j| {
+                                Replicated::share_known_value(
+                                    &ctx,
+                                    Boolean::truncate_from(((lower_bound - 1) >> j) & 0x1),
+                                )
+                            });
+                            compare_gt::<_, ThirtyTwoBitStep, 1>(
+                                ctx,
+                                record_id,
+                                time_delta_bits,
+                                &threshold_bits,
+                            )
+                            .await
+                        }
+                    }),
+            )
+            .await?;
+
+        // Bounds are increasing, so once the time delta stays below a bound, it stays below all
+        // the following ones as well. The time delta is in bucket `i` exactly when `reached[i - 1]`
+        // is set and `reached[i]` is not, which is their XOR.
+        let mut bucket_bits = vec![Replicated::<Boolean>::ZERO; BK::BITS as usize];
+        for i in 1..self.buckets {
+            let mut in_bucket = reached[i - 1].clone();
+            if let Some(next) = reached.get(i) {
+                in_bucket += next;
+            }
+            for (j, bit) in bucket_bits.iter_mut().enumerate() {
+                if (i >> j) & 1 == 1 {
+                    *bit += &in_bucket;
+                }
+            }
+        }
+
+        Ok(BitDecomposed::new(bucket_bits).collect_bits())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::TimeToConversionBuckets;
+    use crate::{
+        ff::{
+            boolean_array::{BA20, BA5},
+            U128Conversions,
+        },
+        protocol::{context::Context, RecordId},
+        secret_sharing::replicated::semi_honest::AdditiveShare as Replicated,
+        test_executor::run,
+        test_fixture::{Reconstruct, Runner, TestWorld},
+    };
+
+    #[test]
+    fn linear_bounds() {
+        let buckets = TimeToConversionBuckets::linear(NonZeroU32::new(10).unwrap(), 4);
+        assert_eq!(buckets.count(), 4);
+        assert_eq!(buckets.lower_bounds(), &[10, 20, 30]);
+
+        let buckets = TimeToConversionBuckets::linear(NonZeroU32::new(u32::MAX).unwrap(), 3);
+        assert_eq!(buckets.lower_bounds(), &[u32::MAX, u32::MAX]);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive and increasing")]
+    fn decreasing_bounds() {
+        let _ = TimeToConversionBuckets::new(&[10, 5]);
+    }
+
+    #[test]
+    fn bucket_of() {
+        run(|| async move {
+            // The last bound does not fit into 20 bits, so the last bucket is always empty.
+            let buckets = TimeToConversionBuckets::new(&[1, 60, 60, 3600, 1 << 20]);
+
+            for (source, trigger, expected) in [
+                (100, 100, 0),
+                (100, 101, 1),
+                (100, 159, 1),
+                (100, 160, 3),
+                (0, 3599, 3),
+                (0, 3600, 4),
+                (0, (1 << 20) - 1, 4),
+            ] {
+                let result = TestWorld::default()
+                    .semi_honest(
+                        (BA20::truncate_from(trigger), BA20::truncate_from(source)),
+                        |ctx, (trigger, source): (Replicated<BA20>, Replicated<BA20>)| async move {
+                            buckets
+                                .bucket_of::<_, BA5, BA20>(
+                                    ctx.set_total_records(1),
+                                    RecordId::FIRST,
+                                    &trigger,
+                                    &source,
+                                )
+                                .await
+                                .unwrap()
+                        },
+                    )
+                    .await
+                    .reconstruct();
+                assert_eq!(
+                    result.as_u128(),
+                    expected,
+                    "time delta {} should be in bucket {expected}",
+                    trigger - source
+                );
+            }
+        });
+    }
+}
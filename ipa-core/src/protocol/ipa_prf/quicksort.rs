@@ -1,3 +1,14 @@
+//! Sorting of secret-shared rows by a secret-shared key.
+//!
+//! Keys are XOR-shared boolean arrays, compared bit by bit with the boolean comparison circuit
+//! of [`compare_gt`], so sorting needs no conversion of the keys to another sharing. Only the
+//! outcome of every comparison is revealed, and helpers partition rows by branching on it, so
+//! the partition is not constant time. Rows are grouped by their revealed PRF values before they
+//! reach the sort, so it only orders rows within the range of each user.
+//!
+//! This is the only sort of shares in the protocol. Grouping rows by user happens in the clear,
+//! once PRF values are revealed after the shuffle, see [`super::prf_sharding::group_by_key`].
+
 use std::{convert::Infallible, mem, ops::Range};
 
 use bitvec::prelude::{BitVec, Lsb0};
@@ -281,14 +292,22 @@ pub mod tests {
 
     use ipa_step_derive::CompactStep;
     use rand::Rng;
+    use typenum::Const;
 
     use crate::{
         ff::{boolean_array::BA32, U128Conversions},
-        protocol::{context::Context, ipa_prf::quicksort::quicksort_ranges_by_key_insecure},
+        helpers::stream::div_round_up,
+        protocol::{
+            context::Context,
+            ipa_prf::{
+                quicksort::quicksort_ranges_by_key_insecure, step::QuicksortStep, SORT_CHUNK,
+            },
+        },
         rand::thread_rng,
-        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares, SharedValue},
+        telemetry::metrics::RECORDS_SENT,
         test_executor::run,
-        test_fixture::{Reconstruct, Runner, TestWorld},
+        test_fixture::{Reconstruct, Runner, TestWorld, TestWorldConfig},
     };
 
     type TestSortKey = BA32;
@@ -625,6 +644,43 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn test_quicksort_communication_budget() {
+        const COUNT: usize = 300;
+
+        run(|| async move {
+            let world = TestWorld::new_with(TestWorldConfig::default().enable_metrics());
+            let mut rng = thread_rng();
+            let records: Vec<TestSortKey> = repeat_with(|| rng.gen()).take(COUNT).collect();
+
+            let _: Vec<_> = world
+                .semi_honest(records.into_iter(), |ctx, mut r| async move {
+                    #[allow(clippy::single_range_in_vec_init)]
+                    quicksort_ranges_by_key_insecure(ctx, &mut r, false, |x| x, vec![0..COUNT])
+                        .await
+                        .unwrap();
+                    r
+                })
+                .await
+                .reconstruct();
+
+            // Every pass compares at most `COUNT - 1` keys against pivots, in chunks of
+            // `SORT_CHUNK` comparisons. Each chunk costs one multiplication per key bit and one
+            // reveal, and every helper sends one record for each of those.
+            let chunks = div_round_up(COUNT - 1, Const::<SORT_CHUNK>);
+            let per_pass = 3 * chunks * (usize::try_from(TestSortKey::BITS).unwrap() + 1);
+
+            let snapshot = world.metrics_snapshot();
+            let records_sent = snapshot.assert_metric(RECORDS_SENT);
+            for pass in 1..30 {
+                records_sent.per_step_at_most(
+                    &world.gate().narrow(&QuicksortStep::QuicksortPass(pass)),
+                    per_pass,
+                );
+            }
+        });
+    }
+
     #[derive(Clone, Copy, Debug)]
     struct SillyStruct {
         timestamp: TestSortKey,
@@ -0,0 +1,124 @@
+//! Uniform sampling of users, to trade accuracy for cost on large inputs.
+//!
+//! Users are sampled by their PRF value, after it has been revealed. PRF values are uniformly
+//! distributed and independent from the contents of the reports, so comparing them against a
+//! public threshold keeps every user with probability equal to the sampling rate, and all the
+//! rows of a user are either kept or dropped together. Since PRF values are public at this
+//! point, rows of users that are not sampled are simply dropped before the per-user stages
+//! instead of being carried through them as zeros. This does not reveal anything that the
+//! PRF values don't already reveal.
+//!
+//! The output of a sampled query estimates the output of the full query multiplied by the
+//! sampling rate, so report collectors need to [`rescale`] it. DP noise is added to the sampled
+//! output, so it grows by the same factor.
+//!
+//! Sampling does not amplify privacy. Amplification requires that nobody knows which users
+//! were sampled, but every helper does, so the query spends the same privacy budget as it would
+//! without sampling.
+//!
+//! [`rescale`]: UserSampling::rescale
+
+use crate::protocol::ipa_prf::prf_sharding::GroupingKey;
+
+/// Keeps every user with a public probability `rate`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UserSampling {
+    rate: f64,
+    /// Users with PRF values below this are kept. This is `rate * 2^64`, so that a rate of `1`
+    /// keeps everyone.
+    threshold: u128,
+}
+
+impl UserSampling {
+    /// ## Panics
+    /// If `rate` is not in `(0, 1]`.
+    #[must_use]
+    pub fn new(rate: f64) -> Self {
+        assert!(
+            Self::is_valid_rate(rate),
+            "sampling rate must be in (0, 1], got {rate}"
+        );
+        // The rate is in (0, 1], so the threshold is a non-negative value that fits in 65 bits.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let threshold = (rate * 2_f64.powi(64)) as u128;
+        Self { rate, threshold }
+    }
+
+    #[must_use]
+    pub fn is_valid_rate(rate: f64) -> bool {
+        rate > 0.0 && rate <= 1.0
+    }
+
+    #[must_use]
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Returns `true` if the user with PRF value `prf` is part of the sample.
+    #[must_use]
+    pub fn keeps(&self, prf: u64) -> bool {
+        u128::from(prf) < self.threshold
+    }
+
+    /// Drops the rows of users that are not part of the sample.
+    #[must_use]
+    pub fn sample<R: GroupingKey>(&self, mut rows: Vec<R>) -> Vec<R> {
+        rows.retain(|row| self.keeps(row.get_grouping_key()));
+        rows
+    }
+
+    /// Scales a value computed over the sample up to an estimate for the whole input.
+    #[must_use]
+    pub fn rescale(&self, value: f64) -> f64 {
+        value / self.rate
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::UserSampling;
+    use crate::protocol::ipa_prf::prf_sharding::GroupingKey;
+
+    struct Keyed(u64);
+
+    impl GroupingKey for Keyed {
+        fn get_grouping_key(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn keeps_everyone_at_rate_one() {
+        let sampling = UserSampling::new(1.0);
+        assert!(sampling.keeps(0));
+        assert!(sampling.keeps(u64::MAX));
+    }
+
+    #[test]
+    fn keeps_users_together() {
+        let sampling = UserSampling::new(0.5);
+        let half = 1 << 63;
+        let rows = [1, 1, half, half - 1, u64::MAX, half]
+            .map(Keyed)
+            .into_iter()
+            .collect();
+        let sampled = sampling
+            .sample(rows)
+            .into_iter()
+            .map(|row| row.0)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 1, half - 1], sampled);
+    }
+
+    #[test]
+    fn rescale() {
+        let sampling = UserSampling::new(0.25);
+        assert!((sampling.rescale(10.0) - 40.0).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "sampling rate must be in (0, 1]")]
+    fn rejects_zero_rate() {
+        let _ = UserSampling::new(0.0);
+    }
+}
@@ -2,6 +2,8 @@ use ipa_step_derive::CompactStep;
 
 #[derive(CompactStep)]
 pub(crate) enum IpaPrfStep {
+    PrfCacheLookup,
+    DecryptionFailures,
     #[step(child = crate::protocol::ipa_prf::oprf_padding::step::PaddingDpStep, name="padding_dp")]
     PaddingDp,
     #[step(child = crate::protocol::ipa_prf::shuffle::step::OPRFShuffleStep)]
@@ -15,12 +17,67 @@ pub(crate) enum IpaPrfStep {
     EvalPrf,
     #[step(child = QuicksortStep)]
     SortByTimestamp,
+    #[step(child = TimestampBoundsStep)]
+    TimestampBounds,
     #[step(child = crate::protocol::ipa_prf::prf_sharding::step::AttributionStep)]
     Attribution,
     #[step(child = crate::protocol::dp::step::DPStep, name = "dp")]
     DifferentialPrivacy,
     #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
     DifferentialPrivacyValidate,
+    #[step(child = crate::protocol::ipa_prf::prf_sharding::step::AttributionStep)]
+    AttributionCounts,
+    #[step(child = crate::protocol::dp::step::DPStep, name = "counts_dp")]
+    CountsDifferentialPrivacy,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    CountsDifferentialPrivacyValidate,
+    #[step(child = crate::protocol::ipa_prf::prf_sharding::step::AttributionStep)]
+    AttributionTimeToConversion,
+    #[step(child = crate::protocol::dp::step::DPStep, name = "time_to_conversion_dp")]
+    TimeToConversionDifferentialPrivacy,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    TimeToConversionDifferentialPrivacyValidate,
+    #[step(child = crate::protocol::dp::step::DPStep, name = "suppressed_dp")]
+    SuppressedDifferentialPrivacy,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    SuppressedDifferentialPrivacyValidate,
+    PartialRelease,
+    VerifyOutputShares,
+    #[step(child = ParanoidCheckStep)]
+    ParanoidCheck,
+}
+
+/// Stage boundaries at which `paranoid` builds check that helpers hold consistent shares.
+#[derive(CompactStep)]
+pub(crate) enum ParanoidCheckStep {
+    SortedBreakdownKeys,
+    SortedTriggerValues,
+    SortedTimestamps,
+    AggregatedHistogram,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum TimestampBoundsStep {
+    #[step(child = TimestampBoundsRowStep)]
+    Suppress,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    SuppressValidate,
+    #[step(child = crate::protocol::ipa_prf::prf_sharding::step::UserNthRowStep)]
+    Cap,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    CapValidate,
+    #[step(child = crate::protocol::ipa_prf::aggregation::step::AggregationStep)]
+    Count,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum TimestampBoundsRowStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    BelowMin,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    AboveMax,
+    TriggerValue,
+    Suppressed,
 }
 
 #[derive(CompactStep)]
@@ -0,0 +1,562 @@
+//! Suppression of trigger events outside of the time range a query covers.
+//!
+//! Report collectors may declare the range of timestamps a query is about, for instance the
+//! epoch it reports on. Trigger events outside of that range must not contribute to the
+//! output, but their timestamps are secret-shared, so helpers can't simply drop them. Instead,
+//! the timestamp of every row is compared against the public bounds, and the trigger value of
+//! rows that fall outside of them is zeroed out before attribution. These rows stay in the
+//! input as trigger events without value, so helpers learn neither how many of them there are
+//! nor which users they belong to.
+//!
+//! The number of suppressed trigger events tells report collectors whether their reports and
+//! their bounds agree, but it depends on the input like any other output, so it is only ever
+//! released with DP noise, as one more histogram of the query output. Every user contributes
+//! at most as many suppressed trigger events to it as the per-user cap allows, so the noise
+//! that hides a single user in the other histograms hides them in this one as well. See
+//! [`oprf_ipa_with_partial_results`](super::oprf_ipa_with_partial_results).
+
+use std::{array, cmp::Reverse, convert::Infallible, ops::Range};
+
+use futures::{future::try_join, stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, UnwrapInfallible},
+    ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, U128Conversions},
+    helpers::TotalRecords,
+    protocol::{
+        basics::{select, BooleanArrayMul, BooleanProtocols, SecureMul, ShareKnownValue},
+        boolean::{step::ThirtyTwoBitStep, NBitStep},
+        context::{
+            dzkp_validator::{validated_seq_join, DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
+        },
+        ipa_prf::{
+            aggregation::{aggregate_contributions, Summation},
+            boolean_ops::comparison_and_subtraction_sequential::compare_gt,
+            prf_sharding::{
+                credit_capping::{CreditCapping, HardCap, UnitCap},
+                step::UserNthRowStep,
+                PrfShardedIpaInputRow,
+            },
+            step::{TimestampBoundsRowStep as RowStep, TimestampBoundsStep as Step},
+        },
+        RecordId,
+    },
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, FieldSimd,
+        SharedValue, TransposeFrom,
+    },
+    utils::non_zero_prev_power_of_two,
+};
+
+/// Inclusive range of timestamps a query covers, in timestamp units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampBounds {
+    min: u32,
+    max: u32,
+}
+
+impl TimestampBounds {
+    /// ## Panics
+    /// If `min` is greater than `max`.
+    #[must_use]
+    pub fn new(min: u32, max: u32) -> Self {
+        assert!(
+            min <= max,
+            "timestamp bounds must not be empty, got [{min}, {max}]"
+        );
+        Self { min, max }
+    }
+
+    #[must_use]
+    pub fn min(&self) -> u32 {
+        self.min
+    }
+
+    #[must_use]
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Returns `true` if a row with `timestamp` contributes to the output.
+    #[must_use]
+    pub fn contains(&self, timestamp: u32) -> bool {
+        (self.min..=self.max).contains(&timestamp)
+    }
+}
+
+/// Returns a suitable proof chunk size (in records) for [`suppress_out_of_range`]. Every row
+/// takes two comparisons of its timestamp, and two multiplications with the result.
+fn suppress_proof_chunk(ts_bits: usize, tv_bits: usize) -> usize {
+    non_zero_prev_power_of_two(TARGET_PROOF_SIZE / (2 * ts_bits + tv_bits + 1))
+}
+
+/// Zeroes out the trigger value of every row whose timestamp is outside of `bounds`. Returns,
+/// for every row, a secret-shared bit that is set if the row is a trigger event that was
+/// suppressed.
+///
+/// ## Errors
+/// Propagates errors from multiplications and validation.
+///
+/// ## Panics
+/// If `bounds` don't fit into `TS`.
+#[tracing::instrument(name = "suppress_out_of_range", skip_all, fields(rows = rows.len()))]
+pub(crate) async fn suppress_out_of_range<C, BK, TV, TS>(
+    ctx: C,
+    rows: &mut [PrfShardedIpaInputRow<BK, TV, TS>],
+    bounds: TimestampBounds,
+) -> Result<Vec<Replicated<Boolean>>, Error>
+where
+    C: UpgradableContext,
+    BK: BooleanArray,
+    TV: BooleanArray,
+    TS: BooleanArray,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>>,
+{
+    assert!(
+        TS::BITS <= ThirtyTwoBitStep::BITS,
+        "ThirtyTwoBitStep is not large enough to accommodate this comparison"
+    );
+    assert!(
+        u64::from(bounds.max) < 1 << TS::BITS,
+        "timestamp bounds must fit into {} bits",
+        TS::BITS
+    );
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let validator = ctx
+        .set_total_records(TotalRecords::specified(rows.len())?)
+        .dzkp_validator(
+            MaliciousProtocolSteps {
+                protocol: &Step::Suppress,
+                validate: &Step::SuppressValidate,
+            },
+            suppress_proof_chunk(
+                usize::try_from(TS::BITS).unwrap(),
+                usize::try_from(TV::BITS).unwrap(),
+            ),
+        );
+    let suppress_ctx = validator.context();
+    let suppressed = validated_seq_join(
+        validator,
+        stream::iter(rows.iter()).enumerate().map(move |(i, row)| {
+            suppress_row(suppress_ctx.clone(), RecordId::from(i), row, bounds)
+        }),
+    )
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(rows
+        .iter_mut()
+        .zip(suppressed)
+        .map(|(row, (trigger_value, suppressed))| {
+            row.trigger_value = trigger_value;
+            suppressed
+        })
+        .collect())
+}
+
+/// Returns the trigger value of `row`, or zero if its timestamp is outside of `bounds`, and
+/// whether a trigger event was suppressed.
+async fn suppress_row<C, BK, TV, TS>(
+    ctx: C,
+    record_id: RecordId,
+    row: &PrfShardedIpaInputRow<BK, TV, TS>,
+    bounds: TimestampBounds,
+) -> Result<(Replicated<TV>, Replicated<Boolean>), Error>
+where
+    C: Context,
+    BK: SharedValue,
+    TV: BooleanArray,
+    TS: BooleanArray,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<TV>: BooleanArrayMul<C>,
+{
+    let known_bits = |value: u32| {
+        BitDecomposed::decompose(TS::BITS, |i| {
+            Replicated::share_known_value(&ctx, Boolean::from((value >> i) & 1 == 1))
+        })
+    };
+    let timestamp = row.timestamp.to_bits();
+    let (below_min, above_max) = try_join(
+        compare_gt::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&RowStep::BelowMin),
+            record_id,
+            &known_bits(bounds.min),
+            &timestamp,
+        ),
+        compare_gt::<_, ThirtyTwoBitStep, 1>(
+            ctx.narrow(&RowStep::AboveMax),
+            record_id,
+            &timestamp,
+            &known_bits(bounds.max),
+        ),
+    )
+    .await?;
+    // Bounds are never empty, so a timestamp can't be on both sides of them, and the sum of
+    // the two bits is their disjunction.
+    let out_of_range = below_min + above_max;
+
+    try_join(
+        select(
+            ctx.narrow(&RowStep::TriggerValue),
+            record_id,
+            &out_of_range,
+            &Replicated::<TV>::ZERO,
+            &row.trigger_value,
+        ),
+        row.is_trigger_bit
+            .multiply(&out_of_range, ctx.narrow(&RowStep::Suppressed), record_id),
+    )
+    .await
+}
+
+/// Adds up the bits returned by [`suppress_out_of_range`] for `rows` into a histogram with `B`
+/// buckets, of which only the first one holds the count. This is the layout
+/// [`dp_for_histogram`] adds noise to.
+///
+/// If `per_user_cap` is set, every user adds at most that many suppressed trigger events to the
+/// count, see [`cap_per_user`].
+///
+/// [`dp_for_histogram`]: crate::protocol::dp::dp_for_histogram
+///
+/// ## Errors
+/// Propagates errors from capping and aggregation.
+pub(crate) async fn count_suppressed<C, BK, TV, TS, HV, const B: usize>(
+    ctx: C,
+    rows: &[PrfShardedIpaInputRow<BK, TV, TS>],
+    suppressed: Vec<Replicated<Boolean>>,
+    per_user_cap: Option<u32>,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext,
+    BK: SharedValue,
+    TV: BooleanArray + U128Conversions,
+    TS: SharedValue,
+    HV: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<HV>; B], Error = Infallible>,
+{
+    let mut histogram = array::from_fn::<_, B, _>(|_| Replicated::<HV>::ZERO);
+    if !suppressed.is_empty() {
+        let suppressed = match per_user_cap {
+            Some(cap) => cap_per_user(ctx.clone(), rows, suppressed, cap).await?,
+            None => suppressed,
+        };
+        let contributions = suppressed
+            .into_iter()
+            .map(|bit| BitDecomposed::new([bit]))
+            .collect();
+        let count = aggregate_contributions::<_, HV, 1>(
+            ctx.narrow(&Step::Count),
+            contributions,
+            1,
+            Summation::Saturating,
+        )
+        .await?;
+        for (i, bit) in count.into_iter().enumerate() {
+            histogram[0].set(i, bit);
+        }
+    }
+
+    Ok(BitDecomposed::transposed_from(&histogram).unwrap_infallible())
+}
+
+/// Keeps only the first `cap` suppressed trigger events of every user, with the same
+/// [`CreditCapping`] attribution uses for conversion counts. Rows of a user are adjacent. Users
+/// with no more rows than the cap can't exceed it, and the number of rows of a user is not
+/// secret at this point, so only the rows of users with more are capped.
+async fn cap_per_user<C, BK, TV, TS>(
+    ctx: C,
+    rows: &[PrfShardedIpaInputRow<BK, TV, TS>],
+    mut suppressed: Vec<Replicated<Boolean>>,
+    cap: u32,
+) -> Result<Vec<Replicated<Boolean>>, Error>
+where
+    C: UpgradableContext,
+    BK: SharedValue,
+    TV: BooleanArray + U128Conversions,
+    TS: SharedValue,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>>,
+{
+    let mut users = users_over_cap(rows, usize::try_from(cap).unwrap());
+    if users.is_empty() {
+        return Ok(suppressed);
+    }
+    // Like in attribution, record ids count users and every row number has a context of its
+    // own, so users with more rows come first.
+    users.sort_by_key(|user| Reverse(user.len()));
+    let max_rows = users[0].len();
+    let multiplications_per_row = if cap == 1 {
+        <UnitCap as CreditCapping<TV>>::multiplications_per_row()
+    } else {
+        <HardCap as CreditCapping<TV>>::multiplications_per_row()
+    };
+
+    let mut validator = ctx.dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::Cap,
+            validate: &Step::CapValidate,
+        },
+        non_zero_prev_power_of_two(
+            TARGET_PROOF_SIZE / (max_rows * usize::try_from(multiplications_per_row).unwrap()),
+        ),
+    );
+    validator.set_total_records(TotalRecords::specified(users.len())?);
+    let cap_ctx = validator.context();
+    let ctx_for_row_number = (0..max_rows)
+        .map(|row| {
+            let users_with_row = users.partition_point(|user| user.len() > row);
+            Ok(cap_ctx
+                .narrow(&UserNthRowStep::from(row))
+                .set_total_records(TotalRecords::specified(users_with_row)?))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let capped = validated_seq_join(
+        validator,
+        stream::iter(users.iter().enumerate()).map(|(i, user)| {
+            let ctx_for_row_number = &ctx_for_row_number[..user.len()];
+            let counted = suppressed[user.clone()]
+                .iter()
+                .map(|bit| {
+                    let mut value = Replicated::<TV>::ZERO;
+                    value.set(0, bit.clone());
+                    value
+                })
+                .collect::<Vec<_>>();
+            async move {
+                let record_id = RecordId::from(i);
+                if cap == 1 {
+                    UnitCap
+                        .cap_user_contributions(ctx_for_row_number, record_id, &counted)
+                        .await
+                } else {
+                    HardCap::new(cap)
+                        .cap_user_contributions(ctx_for_row_number, record_id, &counted)
+                        .await
+                }
+            }
+        }),
+    )
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    for (user, capped) in users.into_iter().zip(capped) {
+        for (bit, value) in suppressed[user].iter_mut().zip(capped) {
+            *bit = value.get(0).unwrap();
+        }
+    }
+
+    Ok(suppressed)
+}
+
+/// Returns the ranges of `rows` that belong to users with more than `cap` rows.
+fn users_over_cap<BK, TV, TS>(
+    rows: &[PrfShardedIpaInputRow<BK, TV, TS>],
+    cap: usize,
+) -> Vec<Range<usize>>
+where
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+{
+    let mut users = Vec::new();
+    let mut start = 0;
+    for end in 1..=rows.len() {
+        if end == rows.len() || rows[end].prf_of_match_key != rows[start].prf_of_match_key {
+            if end - start > cap {
+                users.push(start..end);
+            }
+            start = end;
+        }
+    }
+    users
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{count_suppressed, suppress_out_of_range, TimestampBounds};
+    use crate::{
+        ff::{
+            boolean::Boolean,
+            boolean_array::{BA16, BA20, BA3, BA5},
+            U128Conversions,
+        },
+        protocol::ipa_prf::prf_sharding::PrfShardedIpaInputRow,
+        secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, TransposeFrom},
+        test_executor::run,
+        test_fixture::{Reconstruct, Runner, TestWorld},
+    };
+
+    /// Trigger bit, trigger value and timestamp of every row.
+    fn rows() -> Vec<((Boolean, BA3), BA20)> {
+        [
+            (false, 0, 3),
+            (true, 5, 3),
+            (true, 6, 4),
+            (true, 7, 10),
+            (false, 0, 11),
+            (true, 2, 11),
+            (true, 1, 1_000_000),
+        ]
+        .into_iter()
+        .map(|(is_trigger, tv, ts)| {
+            (
+                (Boolean::from(is_trigger), BA3::truncate_from(tv)),
+                BA20::truncate_from(ts),
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn bounds() {
+        let bounds = TimestampBounds::new(4, 10);
+        assert!(!bounds.contains(3));
+        assert!(bounds.contains(4));
+        assert!(bounds.contains(10));
+        assert!(!bounds.contains(11));
+    }
+
+    #[test]
+    #[should_panic(expected = "timestamp bounds must not be empty")]
+    fn empty_bounds() {
+        let _ = TimestampBounds::new(5, 4);
+    }
+
+    #[test]
+    fn suppress() {
+        run(|| async {
+            let bounds = TimestampBounds::new(4, 10);
+            let expected_values = rows()
+                .into_iter()
+                .map(|((_, tv), ts)| {
+                    if bounds.contains(u32::try_from(ts.as_u128()).unwrap()) {
+                        tv.as_u128()
+                    } else {
+                        0
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let (values, count): (Vec<BA3>, Vec<BA16>) = TestWorld::default()
+                .malicious(rows().into_iter(), |ctx, rows| async move {
+                    let mut rows =
+                        rows.into_iter()
+                            .map(|((is_trigger_bit, trigger_value), timestamp)| {
+                                PrfShardedIpaInputRow::<BA5, _, _> {
+                                    prf_of_match_key: 0,
+                                    is_trigger_bit,
+                                    breakdown_key: Replicated::ZERO,
+                                    trigger_value,
+                                    timestamp,
+                                    sort_key: Replicated::ZERO,
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                    let suppressed = suppress_out_of_range(ctx.clone(), &mut rows, bounds)
+                        .await
+                        .unwrap();
+                    let count =
+                        count_suppressed::<_, _, _, _, BA16, 32>(ctx, &rows, suppressed, None)
+                            .await
+                            .unwrap();
+                    (
+                        rows.into_iter()
+                            .map(|row| row.trigger_value)
+                            .collect::<Vec<_>>(),
+                        Vec::<Replicated<BA16>>::transposed_from(&count).unwrap(),
+                    )
+                })
+                .await
+                .reconstruct();
+
+            assert_eq!(
+                expected_values,
+                values
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>()
+            );
+            // The trigger events at 3, 11 and 1,000,000 are suppressed, the source event at 11
+            // is not counted.
+            let mut expected_count = vec![0; 32];
+            expected_count[0] = 3;
+            assert_eq!(
+                expected_count,
+                count
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>()
+            );
+        });
+    }
+
+    #[test]
+    fn capped_per_user() {
+        // Every trigger event is out of range. The first user has four of them, the second one.
+        const ROWS: [(u64, bool); 6] = [
+            (1, true),
+            (1, false),
+            (1, true),
+            (1, true),
+            (1, true),
+            (2, true),
+        ];
+
+        run(|| async {
+            for (cap, expected) in [(1, 2), (2, 3), (4, 5)] {
+                let count: Vec<BA16> = TestWorld::default()
+                    .malicious(
+                        ROWS.into_iter()
+                            .map(|(_, is_trigger)| Boolean::from(is_trigger)),
+                        |ctx, is_trigger_bits| async move {
+                            let mut rows = is_trigger_bits
+                                .into_iter()
+                                .zip(ROWS)
+                                .map(|(is_trigger_bit, (prf_of_match_key, _))| {
+                                    PrfShardedIpaInputRow::<BA5, BA3, BA20> {
+                                        prf_of_match_key,
+                                        is_trigger_bit,
+                                        breakdown_key: Replicated::ZERO,
+                                        trigger_value: Replicated::ZERO,
+                                        timestamp: Replicated::ZERO,
+                                        sort_key: Replicated::ZERO,
+                                    }
+                                })
+                                .collect::<Vec<_>>();
+                            let suppressed = suppress_out_of_range(
+                                ctx.clone(),
+                                &mut rows,
+                                TimestampBounds::new(1, 10),
+                            )
+                            .await
+                            .unwrap();
+                            let count = count_suppressed::<_, _, _, _, BA16, 32>(
+                                ctx,
+                                &rows,
+                                suppressed,
+                                Some(cap),
+                            )
+                            .await
+                            .unwrap();
+                            Vec::<Replicated<BA16>>::transposed_from(&count).unwrap()
+                        },
+                    )
+                    .await
+                    .reconstruct();
+
+                assert_eq!(expected, count[0].as_u128(), "cap {cap}");
+            }
+        });
+    }
+}
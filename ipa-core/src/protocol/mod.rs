@@ -6,6 +6,7 @@ pub mod hybrid;
 pub mod ipa_prf;
 pub mod prss;
 pub mod step;
+mod version;
 
 use std::{
     fmt::{Debug, Display, Formatter},
@@ -15,6 +16,7 @@ use std::{
 
 pub use basics::{BasicProtocols, BooleanProtocols};
 use serde::{Deserialize, Serialize};
+pub use version::ProtocolVersion;
 
 use crate::error::Error;
 
@@ -35,38 +37,45 @@ impl ipa_metrics::LabelValue for step::ProtocolGate {
     }
 }
 
-/// Unique identifier of the MPC query requested by report collectors
-/// TODO(615): Generating this unique id may be tricky as it may involve communication between helpers and
-/// them collaborating on constructing this unique id. These details haven't been flushed out yet,
-/// so for now it is just an empty struct. Once we know more about it, we will make necessary
-/// amendments to it
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(into = "&'static str", try_from = "&str")]
-pub struct QueryId;
+/// Unique identifier of the MPC query requested by report collectors.
+///
+/// The helper that creates a query picks a random id for it and sends it to the other helpers
+/// with the prepare request. Helpers refuse to prepare a query with an id they already know, so
+/// every query a helper keeps track of has an id of its own. Channels of queries that run
+/// concurrently are kept apart by their ids.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "&str")]
+pub struct QueryId {
+    id: u32,
+}
 
-impl Display for QueryId {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        // fail when query id becomes meaningful and change the display implementation
-        let _: QueryId = QueryId;
-        write!(f, "{self:?}")
+/// Fixed id for tests that run a single query and don't go through query creation.
+#[cfg(any(test, feature = "test-fixture"))]
+#[allow(non_upper_case_globals)]
+pub const QueryId: QueryId = QueryId::new(0);
+
+impl QueryId {
+    #[must_use]
+    pub const fn new(id: u32) -> Self {
+        Self { id }
     }
 }
 
-impl QueryId {
-    fn repr() -> &'static str {
-        "0"
+impl Debug for QueryId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "QueryId({})", self.id)
     }
 }
 
-impl AsRef<str> for QueryId {
-    fn as_ref(&self) -> &str {
-        QueryId::repr()
+impl Display for QueryId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id)
     }
 }
 
-impl From<QueryId> for &'static str {
-    fn from(_: QueryId) -> Self {
-        QueryId::repr()
+impl From<QueryId> for String {
+    fn from(value: QueryId) -> Self {
+        value.to_string()
     }
 }
 
@@ -74,11 +83,10 @@ impl TryFrom<&str> for QueryId {
     type Error = Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if value == QueryId::repr() {
-            Ok(QueryId)
-        } else {
-            Err(Error::path_parse_error(value))
-        }
+        value
+            .parse()
+            .map(Self::new)
+            .map_err(|_| Error::path_parse_error(value))
     }
 }
 
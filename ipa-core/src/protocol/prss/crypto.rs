@@ -278,16 +278,27 @@ impl KeyExchange {
 
 /// This intermediate object exists so that multiple generators can be constructed,
 /// with each one dedicated to one purpose.
+///
+/// It keeps the pseudorandom key extracted from the shared secret rather than the HKDF state,
+/// because `hkdf` offers no way to wipe the latter. With `zeroize-secrets` enabled, the key is
+/// wiped when the factory is dropped, while the AES key schedules of the generators and the key
+/// exchange secrets are wiped by `aes` and `x25519-dalek`.
+#[cfg_attr(feature = "zeroize-secrets", derive(zeroize::ZeroizeOnDrop))]
 pub struct GeneratorFactory {
-    kdf: Hkdf<Sha256>,
+    prk: [u8; 32],
 }
 
 impl GeneratorFactory {
     /// Create a factory from provided secret. This is not public,
     /// as it is only intended use is within the PRSS module.
     pub(super) fn from_secret(secret: &[u8; 32]) -> Self {
-        let kdf = Hkdf::<Sha256>::new(None, secret);
-        GeneratorFactory { kdf }
+        #[cfg_attr(not(feature = "zeroize-secrets"), allow(unused_mut))]
+        let (mut prk, _) = Hkdf::<Sha256>::extract(None, secret);
+        let factory = GeneratorFactory { prk: prk.into() };
+        #[cfg(feature = "zeroize-secrets")]
+        zeroize::Zeroize::zeroize(prk.as_mut_slice());
+
+        factory
     }
 
     /// Create a new generator using the provided context string.
@@ -295,12 +306,19 @@ impl GeneratorFactory {
     #[must_use]
     pub fn generator(&self, context: &[u8]) -> Generator {
         let mut k = aes::cipher::generic_array::GenericArray::default();
-        self.kdf.expand(context, &mut k).unwrap();
-        Generator {
+        Hkdf::<Sha256>::from_prk(&self.prk)
+            .unwrap()
+            .expand(context, &mut k)
+            .unwrap();
+        let generator = Generator {
             cipher: Aes256::new(&k),
             #[cfg(debug_assertions)]
             used: UsedSet::new(context.to_vec()),
-        }
+        };
+        #[cfg(feature = "zeroize-secrets")]
+        zeroize::Zeroize::zeroize(k.as_mut_slice());
+
+        generator
     }
 }
 
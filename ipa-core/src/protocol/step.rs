@@ -6,6 +6,10 @@ use ipa_step_derive::{CompactGate, CompactStep};
 pub enum ProtocolStep {
     Prss,
     CrossShardPrss,
+    /// Completion confirmations of queries with escrowed results.
+    Escrow,
+    /// Confirmations that helpers sealed a query input uploaded in batches with the same count.
+    SealedInput,
     #[step(child = crate::protocol::ipa_prf::step::IpaPrfStep)]
     IpaPrf,
     #[step(child = crate::protocol::hybrid::step::HybridStep)]
@@ -14,6 +18,8 @@ pub enum ProtocolStep {
     PrimeFieldAddition,
     #[step(child = TestShardedShuffleStep)]
     ShardedShuffle,
+    #[step(child = TestShareConversionStep)]
+    ShareConversion,
     /// Steps used in unit tests are grouped under this one. Ideally it should be
     /// gated behind test configuration, but it does not work with build.rs that
     /// does not enable any features when creating protocol gate file
@@ -35,7 +41,7 @@ impl<'de> serde::Deserialize<'de> for ProtocolGate {
 
 #[derive(CompactStep)]
 pub enum DeadCodeStep {
-    #[step(child = crate::protocol::ipa_prf::boolean_ops::step::SaturatedSubtractionStep)]
+    #[step(child = crate::protocol::boolean::step::SaturatedSubtractionStep)]
     SaturatedSubtraction,
     #[step(child = crate::protocol::ipa_prf::prf_sharding::step::FeatureLabelDotProductStep)]
     FeatureLabelDotProduct,
@@ -52,6 +58,15 @@ pub enum TestShardedShuffleStep {
     FinalizeValidate,
 }
 
+#[derive(CompactStep)]
+pub enum TestShareConversionStep {
+    GenerateInput,
+    #[step(child = crate::protocol::ipa_prf::boolean_ops::step::Fp25519ConversionStep)]
+    Convert,
+    #[step(child = crate::protocol::context::step::DzkpValidationProtocolStep)]
+    ConvertValidate,
+}
+
 /// Provides a unique per-iteration context in tests.
 #[derive(CompactStep)]
 pub enum TestExecutionStep {
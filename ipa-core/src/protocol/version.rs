@@ -0,0 +1,171 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Version of the MPC protocol a query runs with. It is chosen by the leader when the query is
+/// created and sent to every helper and shard inside [`PrepareQuery`], so all parties agree on it
+/// before any records are exchanged.
+///
+/// Every step helpers send to each other is rooted in the `ipa/<version>` namespace. This lets a
+/// helper serve queries of two adjacent protocol versions while a new version is being rolled
+/// out, and reject traffic that was produced by a different version than the one negotiated for
+/// the query, instead of feeding it into the protocol.
+///
+/// [`PrepareQuery`]: crate::helpers::query::PrepareQuery
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "&str")]
+pub struct ProtocolVersion(u16);
+
+impl ProtocolVersion {
+    /// Root of the step namespace shared by all protocol versions.
+    pub const NAMESPACE: &'static str = "ipa";
+
+    pub const V5: Self = Self(5);
+
+    /// Step data is sent in batches protected by checksums.
+    pub const V6: Self = Self(6);
+
+    /// Steps are identified by a request header instead of the request path.
+    pub const V7: Self = Self(7);
+
+    /// Version used by this helper when it leads a query.
+    pub const CURRENT: Self = Self::V7;
+
+    /// All versions this helper is able to run. The leader always picks [`Self::CURRENT`],
+    /// followers accept any version from this list.
+    pub const SUPPORTED: &'static [Self] = &[Self::V5, Self::V6, Self::V7];
+
+    #[must_use]
+    pub const fn new(version: u16) -> Self {
+        Self(version)
+    }
+
+    #[must_use]
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+
+    #[must_use]
+    pub fn is_supported(self) -> bool {
+        Self::SUPPORTED.contains(&self)
+    }
+
+    /// Returns `true` if every batch of step data carries a checksum in this version.
+    #[must_use]
+    pub fn has_batch_checksums(self) -> bool {
+        self >= Self::V6
+    }
+
+    /// Returns `true` if requests carrying step data name their step in a header, which keeps
+    /// request paths short no matter how deep the step is.
+    #[must_use]
+    pub fn has_step_header(self) -> bool {
+        self >= Self::V7
+    }
+
+    /// Root of the step namespace for this version, i.e. `ipa/v5`.
+    #[must_use]
+    pub fn namespace(self) -> String {
+        format!("{}/{self}", Self::NAMESPACE)
+    }
+}
+
+/// Peers that predate version negotiation do not send it inside the prepare request. They
+/// speak the first versioned protocol.
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::V5
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// Only the form written by [`Display`] is accepted, so that every version has a single name in
+/// step paths.
+impl FromStr for ProtocolVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.strip_prefix('v')
+            .and_then(|v| v.parse().ok())
+            .map(Self)
+            .filter(|version| version.to_string() == s)
+            .ok_or_else(|| Error::path_parse_error(s))
+    }
+}
+
+impl From<ProtocolVersion> for String {
+    fn from(value: ProtocolVersion) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<&str> for ProtocolVersion {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::ProtocolVersion;
+
+    #[test]
+    fn display_and_parse() {
+        assert_eq!("v5", ProtocolVersion::V5.to_string());
+        assert_eq!("ipa/v5", ProtocolVersion::V5.namespace());
+        assert_eq!(ProtocolVersion::V5, "v5".parse().unwrap());
+        assert_eq!(ProtocolVersion::new(12), "v12".parse().unwrap());
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        for s in [
+            "", "v", "5", "ipa/v5", "v-1", "v70000", "V5", "v+5", "v05", " v5",
+        ] {
+            assert!(s.parse::<ProtocolVersion>().is_err(), "{s} must not parse");
+        }
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        assert_eq!(
+            "\"v5\"",
+            serde_json::to_string(&ProtocolVersion::V5).unwrap()
+        );
+        assert_eq!(
+            ProtocolVersion::new(6),
+            serde_json::from_str::<ProtocolVersion>("\"v6\"").unwrap()
+        );
+    }
+
+    #[test]
+    fn supported() {
+        assert!(ProtocolVersion::CURRENT.is_supported());
+        assert!(ProtocolVersion::V5.is_supported());
+        assert!(!ProtocolVersion::new(4).is_supported());
+    }
+
+    #[test]
+    fn batch_checksums() {
+        assert!(!ProtocolVersion::V5.has_batch_checksums());
+        assert!(ProtocolVersion::V6.has_batch_checksums());
+    }
+
+    #[test]
+    fn step_header() {
+        assert!(!ProtocolVersion::V6.has_step_header());
+        assert!(ProtocolVersion::V7.has_step_header());
+    }
+}
@@ -0,0 +1,517 @@
+//! Python bindings for the report collector side of IPA.
+//!
+//! This exposes the same building blocks that the `report_collector` binary uses: the synthetic
+//! data generator, report encryption, running a hybrid query against a helper deployment and
+//! reconstructing the results. The extension module is built with [maturin] from the
+//! `pyproject.toml` of this crate:
+//!
+//! ```sh
+//! maturin develop --release
+//! ```
+//!
+//! ```python
+//! import ipa_core
+//!
+//! records = ipa_core.generate_hybrid_records(1000, seed=1, max_breakdown_key=20)
+//! encrypted = ipa_core.encrypt_hybrid_records(records, "network.toml")
+//! result = ipa_core.run_hybrid_query("network.toml", encrypted, max_breakdown_key=20, with_dp=0)
+//! assert result.breakdowns == ipa_core.hybrid_in_the_clear(records, 20)
+//! ```
+//!
+//! [maturin]: https://www.maturin.rs
+
+use std::{
+    fs::read_to_string,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use hyper::http::uri::Scheme;
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+use rand::{rngs::StdRng, thread_rng};
+use rand_core::SeedableRng;
+
+use crate::{
+    cli::{
+        config_parse::HelperNetworkConfigParseExt,
+        playbook::{
+            make_clients, make_sharded_clients, run_hybrid_query_and_validate, BreakdownKey,
+            RoundRobinSubmission, StreamingSubmission, TriggerValue,
+        },
+        CsvSerializer,
+    },
+    config::{KeyRegistries, NetworkConfig},
+    ff::{
+        boolean_array::{BA16, BA32},
+        FieldType, Serializable, U128Conversions,
+    },
+    helpers::{
+        query::{HybridQueryParams, QueryConfig, QuerySize, QueryType},
+        BodyStream,
+    },
+    hpke::PublicKeyRegistry,
+    net::Helper,
+    report::hybrid::{HybridReport, InvalidHybridReportError, DEFAULT_KEY_ID},
+    results,
+    secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+    test_fixture::{
+        hybrid::{hybrid_in_the_clear as in_the_clear, TestHybridRecord},
+        hybrid_event_gen::ConversionDistribution,
+        HybridEventGenerator, HybridGeneratorConfig,
+    },
+};
+
+/// A report in the clear, see [`TestHybridRecord`].
+#[pyclass(name = "HybridRecord", module = "ipa_core", frozen)]
+#[derive(Clone)]
+pub struct PyHybridRecord(TestHybridRecord);
+
+#[pymethods]
+impl PyHybridRecord {
+    #[staticmethod]
+    fn impression(match_key: u64, breakdown_key: u32) -> Self {
+        Self(TestHybridRecord::TestImpression {
+            match_key,
+            breakdown_key,
+            key_id: DEFAULT_KEY_ID,
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (
+        match_key,
+        value,
+        timestamp,
+        conversion_site_domain = String::from("meta.com"),
+        epsilon = 0.0,
+        sensitivity = 0.0,
+    ))]
+    fn conversion(
+        match_key: u64,
+        value: u32,
+        timestamp: u64,
+        conversion_site_domain: String,
+        epsilon: f64,
+        sensitivity: f64,
+    ) -> PyResult<Self> {
+        if !conversion_site_domain.is_ascii() {
+            return Err(PyValueError::new_err(format!(
+                "conversion site domain must be ASCII: {conversion_site_domain}"
+            )));
+        }
+        Ok(Self(TestHybridRecord::TestConversion {
+            match_key,
+            value,
+            key_id: DEFAULT_KEY_ID,
+            conversion_site_domain,
+            timestamp,
+            epsilon,
+            sensitivity,
+        }))
+    }
+
+    #[getter]
+    fn is_conversion(&self) -> bool {
+        matches!(self.0, TestHybridRecord::TestConversion { .. })
+    }
+
+    #[getter]
+    fn match_key(&self) -> u64 {
+        match self.0 {
+            TestHybridRecord::TestImpression { match_key, .. }
+            | TestHybridRecord::TestConversion { match_key, .. } => match_key,
+        }
+    }
+
+    /// The record in the input format of `test_hybrid_encrypt`.
+    fn to_csv(&self) -> String {
+        let mut buf = Vec::new();
+        self.0.to_csv(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// Outcome of [`run_hybrid_query`].
+#[pyclass(name = "HybridQueryResult", module = "ipa_core", frozen, get_all)]
+pub struct PyHybridQueryResult {
+    input_size: usize,
+    /// Seconds from submitting the inputs until the results were reconstructed.
+    latency: f64,
+    breakdowns: Vec<u32>,
+}
+
+#[pymethods]
+impl PyHybridQueryResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "HybridQueryResult(input_size={}, latency={:.3}, breakdowns={:?})",
+            self.input_size, self.latency, self.breakdowns
+        )
+    }
+}
+
+/// Generates `count` synthetic records, the same way `report_collector gen-hybrid-inputs` does.
+#[pyfunction]
+#[pyo3(signature = (
+    count,
+    seed = None,
+    max_breakdown_key = 20,
+    max_conversion_value = 5,
+    conversion_distribution = "default",
+))]
+fn generate_hybrid_records(
+    count: usize,
+    seed: Option<u64>,
+    max_breakdown_key: u32,
+    max_conversion_value: u32,
+    conversion_distribution: &str,
+) -> PyResult<Vec<PyHybridRecord>> {
+    let conversion_distribution = match conversion_distribution {
+        "default" => ConversionDistribution::Default,
+        "only_impressions" => ConversionDistribution::OnlyImpressions,
+        "only_conversions" => ConversionDistribution::OnlyConversions,
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "unknown conversion distribution {other}, expected one of default, \
+                only_impressions or only_conversions"
+            )))
+        }
+    };
+    if max_breakdown_key == 0 || max_conversion_value == 0 {
+        return Err(PyValueError::new_err(
+            "max_breakdown_key and max_conversion_value must be positive",
+        ));
+    }
+    let config = HybridGeneratorConfig::new(
+        max_conversion_value,
+        max_breakdown_key,
+        conversion_distribution,
+    );
+    let rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+    Ok(HybridEventGenerator::with_config(rng, config)
+        .take(count)
+        .map(PyHybridRecord)
+        .collect())
+}
+
+/// Computes the expected query output without MPC and without DP noise.
+#[pyfunction]
+#[allow(clippy::needless_pass_by_value)]
+fn hybrid_in_the_clear(records: Vec<PyHybridRecord>, max_breakdown_key: usize) -> Vec<u32> {
+    in_the_clear(records.iter().map(|record| &record.0), max_breakdown_key)
+}
+
+/// Secret shares the records and encrypts the shares towards the helpers, using the public keys
+/// from the network configuration at `network`. Returns one list of encrypted reports for each
+/// helper.
+#[pyfunction]
+#[allow(clippy::needless_pass_by_value, clippy::type_complexity)]
+fn encrypt_hybrid_records(
+    py: Python<'_>,
+    records: Vec<PyHybridRecord>,
+    network: PathBuf,
+) -> PyResult<(Vec<Py<PyBytes>>, Vec<Py<PyBytes>>, Vec<Py<PyBytes>>)> {
+    let network = read_network(&network)?;
+    let key_registries = KeyRegistries::default()
+        .init_from(&network[0])
+        .ok_or_else(|| {
+            PyValueError::new_err("network configuration does not specify all helpers' keys")
+        })?;
+    let [h1, h2, h3] = py
+        .allow_threads(|| encrypt(records.into_iter().map(|record| record.0), &key_registries))
+        .map_err(|e| PyValueError::new_err(e.to_string()))?
+        .map(|reports| {
+            reports
+                .iter()
+                .map(|report| PyBytes::new_bound(py, report).unbind())
+                .collect()
+        });
+    Ok((h1, h2, h3))
+}
+
+/// Runs a malicious hybrid query with the encrypted reports for each helper, as returned by
+/// [`encrypt_hybrid_records`], and reconstructs the result.
+///
+/// The query is submitted to the helpers in the network configuration at `network`. Inputs are
+/// distributed round robin across `shard_count` shards.
+#[pyfunction]
+#[pyo3(signature = (
+    network,
+    inputs,
+    max_breakdown_key,
+    with_dp = 1,
+    epsilon = 5.0,
+    plaintext_match_keys = false,
+    shard_count = 1,
+    disable_https = false,
+    wait = 0,
+))]
+#[allow(clippy::too_many_arguments)]
+fn run_hybrid_query(
+    py: Python<'_>,
+    network: PathBuf,
+    inputs: [Vec<Bound<'_, PyBytes>>; 3],
+    max_breakdown_key: u32,
+    with_dp: u32,
+    epsilon: f64,
+    plaintext_match_keys: bool,
+    shard_count: usize,
+    disable_https: bool,
+    wait: usize,
+) -> PyResult<PyHybridQueryResult> {
+    let count = inputs[0].len();
+    if inputs.iter().any(|reports| reports.len() != count) {
+        return Err(PyValueError::new_err(
+            "every helper must receive the same number of reports",
+        ));
+    }
+    if shard_count == 0 {
+        return Err(PyValueError::new_err("shard_count must be positive"));
+    }
+    // Hex-encoded and delimited by newlines, as in the files written by `test_hybrid_encrypt`.
+    let inputs = inputs.map(|reports| {
+        let mut lines = Vec::new();
+        for report in reports {
+            lines.extend_from_slice(hex::encode(report.as_bytes()).as_bytes());
+            lines.push(b'\n');
+        }
+        lines
+    });
+    let query_config = HybridQueryParams {
+        max_breakdown_key,
+        with_dp,
+        epsilon,
+        plaintext_match_keys,
+    };
+    let scheme = if disable_https {
+        Scheme::HTTP
+    } else {
+        Scheme::HTTPS
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let result = py.allow_threads(|| {
+        runtime.block_on(async move {
+            let clients = if shard_count == 1 {
+                vec![make_clients(Some(network.as_path()), scheme, wait).await.0]
+            } else {
+                make_sharded_clients(&network, scheme, wait).await.0
+            };
+            if clients.len() != shard_count {
+                return Err(PyValueError::new_err(format!(
+                    "network configuration has {} shards, expected {shard_count}",
+                    clients.len()
+                )));
+            }
+
+            let [h1_streams, h2_streams, h3_streams] = inputs.map(|lines| {
+                RoundRobinSubmission::new(Cursor::new(lines)).into_byte_streams(shard_count)
+            });
+            let submissions = h1_streams
+                .into_iter()
+                .zip(h2_streams)
+                .zip(h3_streams)
+                .map(|((s1, s2), s3)| {
+                    [
+                        BodyStream::from_bytes_stream(s1),
+                        BodyStream::from_bytes_stream(s2),
+                        BodyStream::from_bytes_stream(s3),
+                    ]
+                })
+                .collect::<Vec<_>>();
+
+            let query_id = clients[0][0]
+                .create_query(QueryConfig {
+                    size: QuerySize::try_from(count)
+                        .map_err(|e| PyValueError::new_err(e.to_string()))?,
+                    field_type: FieldType::Fp32BitPrime,
+                    query_type: QueryType::MaliciousHybrid(query_config),
+                })
+                .await
+                .map_err(|e| PyRuntimeError::new_err(format!("failed to create query: {e}")))?;
+
+            // Histogram values are BA32 on the helpers, see `report_collector`.
+            Ok(run_hybrid_query_and_validate::<BA32>(
+                submissions,
+                count,
+                clients,
+                query_id,
+                query_config,
+                None,
+            )
+            .await)
+        })
+    })?;
+
+    Ok(PyHybridQueryResult {
+        input_size: usize::from(result.input_size),
+        latency: result.latency.as_secs_f64(),
+        breakdowns: result.breakdowns,
+    })
+}
+
+/// Reconstructs values from the outputs of the three helpers, in helper order. `value_bits` is
+/// the width of the output values: 16 or 32.
+#[pyfunction]
+#[pyo3(signature = (outputs, value_bits = 32))]
+#[allow(clippy::needless_pass_by_value)]
+fn reconstruct_results(outputs: [Bound<'_, PyBytes>; 3], value_bits: u32) -> PyResult<Vec<u128>> {
+    let outputs = outputs.each_ref().map(|output| output.as_bytes());
+    match value_bits {
+        16 => reconstruct::<BA16>(outputs),
+        32 => reconstruct::<BA32>(outputs),
+        _ => Err(PyValueError::new_err(format!(
+            "{value_bits} bit values are not supported, expected 16 or 32"
+        ))),
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "ipa_core")]
+fn module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyHybridRecord>()?;
+    m.add_class::<PyHybridQueryResult>()?;
+    m.add_function(wrap_pyfunction!(generate_hybrid_records, m)?)?;
+    m.add_function(wrap_pyfunction!(hybrid_in_the_clear, m)?)?;
+    m.add_function(wrap_pyfunction!(encrypt_hybrid_records, m)?)?;
+    m.add_function(wrap_pyfunction!(run_hybrid_query, m)?)?;
+    m.add_function(wrap_pyfunction!(reconstruct_results, m)?)?;
+    Ok(())
+}
+
+fn read_network(path: &Path) -> PyResult<Vec<NetworkConfig<Helper>>> {
+    let input = read_to_string(path)?;
+    NetworkConfig::from_toml_str_sharded(&input).map_err(|e| {
+        PyValueError::new_err(format!(
+            "failed to parse network configuration {}: {e}",
+            path.display()
+        ))
+    })
+}
+
+fn encrypt<I, K>(
+    records: I,
+    key_registries: &[K; 3],
+) -> Result<[Vec<Vec<u8>>; 3], InvalidHybridReportError>
+where
+    I: IntoIterator<Item = TestHybridRecord>,
+    K: PublicKeyRegistry,
+{
+    let mut rng = thread_rng();
+    let mut encrypted = [(); 3].map(|()| Vec::new());
+    for record in records {
+        let shares: [HybridReport<BreakdownKey, TriggerValue>; 3] = record.share_with(&mut rng);
+        for ((share, key_registry), reports) in
+            shares.into_iter().zip(key_registries).zip(&mut encrypted)
+        {
+            reports.push(share.encrypt(DEFAULT_KEY_ID, key_registry, &mut rng)?);
+        }
+    }
+    Ok(encrypted)
+}
+
+fn reconstruct<V>(outputs: [&[u8]; 3]) -> PyResult<Vec<u128>>
+where
+    V: results::ResultValue + U128Conversions,
+    AdditiveShare<V>: Serializable,
+{
+    results::reconstruct::<V>(outputs)
+        .map(|values| values.iter().map(U128Conversions::as_u128).collect())
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::iter::zip;
+
+    use bytes::Bytes;
+    use rand::thread_rng;
+
+    use super::{encrypt, generate_hybrid_records};
+    use crate::{
+        cli::playbook::{BreakdownKey, TriggerValue},
+        ff::U128Conversions,
+        hpke::{KeyPair, KeyRegistry},
+        report::hybrid::{
+            EncryptedHybridReport,
+            HybridReport::{Conversion, Impression},
+        },
+        test_fixture::{
+            hybrid::{build_hybrid_records_and_expectation, TestHybridRecord},
+            Reconstruct,
+        },
+    };
+
+    #[test]
+    fn encrypt_round_trip() {
+        let mut rng = thread_rng();
+        let key_registries = [(); 3].map(|()| KeyRegistry::<KeyPair>::random(1, &mut rng));
+        let (records, _) = build_hybrid_records_and_expectation();
+
+        let [h1, h2, h3] = encrypt(records.clone(), &key_registries).unwrap();
+        for (record, (r1, (r2, r3))) in zip(records, zip(h1, zip(h2, h3))) {
+            let shares = zip([r1, r2, r3], &key_registries)
+                .map(|(report, key_registry)| {
+                    EncryptedHybridReport::<BreakdownKey, TriggerValue>::from_bytes(Bytes::from(
+                        report,
+                    ))
+                    .unwrap()
+                    .decrypt(key_registry)
+                    .unwrap()
+                })
+                .collect::<Vec<_>>();
+            match (&record, shares.as_slice()) {
+                (
+                    TestHybridRecord::TestImpression {
+                        match_key,
+                        breakdown_key,
+                        ..
+                    },
+                    [Impression(r1), Impression(r2), Impression(r3)],
+                ) => {
+                    let mk = [&r1.match_key, &r2.match_key, &r3.match_key].reconstruct();
+                    let bk =
+                        [&r1.breakdown_key, &r2.breakdown_key, &r3.breakdown_key].reconstruct();
+                    assert_eq!(u128::from(*match_key), mk.as_u128());
+                    assert_eq!(u128::from(*breakdown_key), bk.as_u128());
+                }
+                (
+                    TestHybridRecord::TestConversion {
+                        match_key, value, ..
+                    },
+                    [Conversion(r1), Conversion(r2), Conversion(r3)],
+                ) => {
+                    let mk = [&r1.match_key, &r2.match_key, &r3.match_key].reconstruct();
+                    let v = [&r1.value, &r2.value, &r3.value].reconstruct();
+                    assert_eq!(u128::from(*match_key), mk.as_u128());
+                    assert_eq!(u128::from(*value), v.as_u128());
+                }
+                (record, shares) => panic!("{record:?} was encrypted as {shares:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_generator() {
+        let generate = || {
+            generate_hybrid_records(50, Some(42), 20, 5, "default")
+                .unwrap()
+                .into_iter()
+                .map(|record| record.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(generate(), generate());
+        assert!(generate_hybrid_records(1, None, 20, 5, "uniform").is_err());
+        assert!(generate_hybrid_records(1, None, 0, 5, "default").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use bytes::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+
+use crate::{
+    error::BoxError,
+    helpers::{query::AppendInput, BodyStream},
+};
+
+/// Most batches the input of a query can be uploaded in.
+pub const MAX_INPUT_BATCHES: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("the query input can't be uploaded in more than {MAX_INPUT_BATCHES} batches")]
+    TooManyBatches,
+    #[error("failed to read a batch of the query input: {0}")]
+    Read(BoxError),
+    #[error(
+        "batches declared {declared} reports in total, but the input was sealed with {sealed}"
+    )]
+    CountMismatch { declared: u64, sealed: u64 },
+}
+
+/// Batches of a query input that is sealed later, see [`AppendInput`].
+///
+/// Every batch is read in full when it arrives, so that uploads don't keep connections open
+/// until the input is sealed. The reports batches declare are added up and checked against the
+/// count the input is sealed with.
+#[derive(Default)]
+pub struct InputBatches {
+    batches: Vec<Bytes>,
+    declared: u64,
+}
+
+impl InputBatches {
+    /// Returns `true` if the input can take one more batch.
+    pub fn has_room(&self) -> bool {
+        self.batches.len() < MAX_INPUT_BATCHES
+    }
+
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// Adds one batch, read with [`read_batch`].
+    ///
+    /// ## Errors
+    /// If the input was uploaded in [`MAX_INPUT_BATCHES`] already.
+    pub fn add(&mut self, append: AppendInput, batch: Bytes) -> Result<(), BatchError> {
+        if !self.has_room() {
+            return Err(BatchError::TooManyBatches);
+        }
+        self.batches.push(batch);
+        self.declared += u64::from(append.reports);
+
+        Ok(())
+    }
+
+    /// Checks that the input can be sealed with `count` reports.
+    ///
+    /// ## Errors
+    /// If batches declared a different number of reports than `count`.
+    pub fn check_sealed(&self, count: u32) -> Result<(), BatchError> {
+        if self.declared == u64::from(count) {
+            Ok(())
+        } else {
+            Err(BatchError::CountMismatch {
+                declared: self.declared,
+                sealed: count.into(),
+            })
+        }
+    }
+
+    /// Concatenates all batches into a single query input, in the order they were uploaded.
+    pub fn into_stream(self) -> BodyStream {
+        BodyStream::from_bytes_stream(
+            stream::iter(self.batches.into_iter().map(BodyStream::new)).flatten(),
+        )
+    }
+}
+
+/// Reads one batch in full.
+///
+/// ## Errors
+/// If reading the upload fails.
+pub async fn read_batch(input_stream: BodyStream) -> Result<Bytes, BatchError> {
+    let chunks = input_stream
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(BatchError::Read)?;
+
+    Ok(Bytes::from(chunks.concat()))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    use super::{read_batch, BatchError, InputBatches, MAX_INPUT_BATCHES};
+    use crate::{
+        helpers::{query::AppendInput, BodyStream},
+        test_executor::run,
+    };
+
+    #[test]
+    fn concatenates_in_upload_order() {
+        run(|| async {
+            let mut batches = InputBatches::default();
+            for (reports, data) in [(2, vec![1, 1]), (1, vec![2])] {
+                let batch = read_batch(BodyStream::from(data)).await.unwrap();
+                batches.add(AppendInput { reports }, batch).unwrap();
+            }
+            batches.check_sealed(3).unwrap();
+            let input = batches
+                .into_stream()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .concat();
+            assert_eq!(vec![1, 1, 2], input);
+        });
+    }
+
+    #[test]
+    fn count_mismatch() {
+        let mut batches = InputBatches::default();
+        batches
+            .add(AppendInput { reports: 2 }, Bytes::from_static(&[1, 1]))
+            .unwrap();
+        assert!(matches!(
+            batches.check_sealed(3),
+            Err(BatchError::CountMismatch {
+                declared: 2,
+                sealed: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn too_many_batches() {
+        let mut batches = InputBatches::default();
+        for _ in 0..MAX_INPUT_BATCHES {
+            batches
+                .add(AppendInput { reports: 1 }, Bytes::new())
+                .unwrap();
+        }
+        assert!(!batches.has_room());
+        assert!(matches!(
+            batches.add(AppendInput { reports: 1 }, Bytes::new()),
+            Err(BatchError::TooManyBatches)
+        ));
+    }
+}
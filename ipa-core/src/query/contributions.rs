@@ -0,0 +1,408 @@
+use std::{collections::BTreeMap, num::NonZeroU32};
+
+use futures::{stream, StreamExt};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    error::BoxError,
+    helpers::{
+        query::{InputContribution, QueryConfig, QueryType},
+        BodyStream, RecordCounter, RecordFraming,
+    },
+    protocol::QueryId,
+    query::{Redaction, SensitiveField},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContributionError {
+    #[error("contributor {0} already uploaded its input")]
+    DuplicateContributor(u32),
+    #[error(
+        "contributor {contributor} declared {actual:?}, but the first contributor declared \
+        {expected:?}"
+    )]
+    DeclarationMismatch {
+        contributor: u32,
+        expected: (NonZeroU32, Option<u64>),
+        actual: (NonZeroU32, Option<u64>),
+    },
+    #[error("contributors declared {declared} reports in total, but the query size is {expected}")]
+    CountMismatch { declared: u64, expected: u64 },
+    #[error("reports of {0} queries can't be counted, so they can't be contributed in slices")]
+    UnsupportedQuery(String),
+    #[error("contributor {contributor} declared {declared} reports, but {read} were read")]
+    SliceSize {
+        contributor: u32,
+        declared: u32,
+        read: u64,
+    },
+}
+
+/// Checks that the input of queries configured with `config` can be contributed in slices.
+/// The reports of every slice are counted as it is read, which only works for encrypted
+/// reports that don't carry anything else, like arms, sites, tags or a manifest.
+///
+/// ## Errors
+/// If it can't.
+pub fn check_supported(config: &QueryConfig) -> Result<(), ContributionError> {
+    let supported = match &config.query_type {
+        QueryType::SemiHonestOprfIpa(ipa) | QueryType::MaliciousOprfIpa(ipa) => {
+            !ipa.plaintext_match_keys
+                && !ipa.input_manifest
+                && !ipa.paired_arms
+                && ipa.source_sites.is_none()
+                && ipa.public_tags.is_none()
+        }
+        QueryType::MaliciousHybrid(_) => true,
+        #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+        _ => false,
+    };
+    if supported {
+        Ok(())
+    } else {
+        Err(ContributionError::UnsupportedQuery(
+            config.query_type.as_ref().to_string(),
+        ))
+    }
+}
+
+/// Passes `slice` through, failing it as soon as it holds more reports than `declared`, or once
+/// it ends with fewer.
+fn counted(contributor: u32, declared: u32, slice: BodyStream) -> BodyStream {
+    let mismatch = move |counter: &RecordCounter| -> BoxError {
+        ContributionError::SliceSize {
+            contributor,
+            declared,
+            read: counter.records(),
+        }
+        .into()
+    };
+    let counter = RecordCounter::new(RecordFraming::LengthDelimited);
+    BodyStream::from_bytes_stream(stream::unfold(
+        Some((slice, counter)),
+        move |state| async move {
+            let (mut slice, mut counter) = state?;
+            match slice.next().await {
+                Some(Ok(chunk)) => {
+                    counter.consume(&chunk);
+                    if counter.records() > u64::from(declared) {
+                        Some((Err(mismatch(&counter)), None))
+                    } else {
+                        Some((Ok(chunk), Some((slice, counter))))
+                    }
+                }
+                Some(Err(e)) => Some((Err(e), None)),
+                None if counter.records() == u64::from(declared) => None,
+                None => Some((Err(mismatch(&counter)), None)),
+            }
+        },
+    ))
+}
+
+/// Query input slices received so far from report collectors that contribute to the same
+/// query. See [`InputContribution`].
+///
+/// Slices are only ever reordered as a whole. If contributors ask for it, the order of slices is
+/// permuted, but reports are never moved within or across slices.
+pub struct InputContributions {
+    contributors: NonZeroU32,
+    shuffle_seed: Option<u64>,
+    slices: BTreeMap<u32, (u32, BodyStream)>,
+}
+
+impl InputContributions {
+    /// Starts collecting slices, using the declaration of the first contributor as reference
+    /// for the ones that follow.
+    pub fn new(first: &InputContribution) -> Self {
+        Self {
+            contributors: first.contributors,
+            shuffle_seed: first.shuffle_seed,
+            slices: BTreeMap::new(),
+        }
+    }
+
+    /// Adds one slice.
+    ///
+    /// ## Errors
+    /// If this contributor has uploaded its slice already or if its declaration does not match
+    /// the one of the first contributor.
+    pub fn add(
+        &mut self,
+        contribution: &InputContribution,
+        stream: BodyStream,
+    ) -> Result<(), ContributionError> {
+        let expected = (self.contributors, self.shuffle_seed);
+        let actual = (contribution.contributors, contribution.shuffle_seed);
+        if expected != actual {
+            return Err(ContributionError::DeclarationMismatch {
+                contributor: contribution.contributor,
+                expected,
+                actual,
+            });
+        }
+        if self.slices.contains_key(&contribution.contributor) {
+            return Err(ContributionError::DuplicateContributor(
+                contribution.contributor,
+            ));
+        }
+        self.slices
+            .insert(contribution.contributor, (contribution.count, stream));
+
+        Ok(())
+    }
+
+    /// Returns `true` once every declared contributor has uploaded its slice.
+    pub fn is_complete(&self) -> bool {
+        u32::try_from(self.slices.len()).unwrap() >= self.contributors.get()
+    }
+
+    /// Contributor ids and their report counts, in the order slices are concatenated. With a
+    /// shuffle seed, this is a permutation of whole slices, not a shuffle of reports.
+    fn order(&self) -> Vec<(u32, u32)> {
+        let mut order = self
+            .slices
+            .iter()
+            .map(|(&contributor, &(count, _))| (contributor, count))
+            .collect::<Vec<_>>();
+        // Every helper receives the same set of slices, so they agree on this order.
+        if let Some(seed) = self.shuffle_seed {
+            order.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+
+        order
+    }
+
+    /// Concatenates all slices into a single query input and records the contribution of every
+    /// report collector in the audit log. Report counts are left out of the record if `redaction`
+    /// covers [`SensitiveField::QuerySize`].
+    ///
+    /// Reports of every slice are counted as it is read, and the returned stream fails if a slice
+    /// does not hold the number of reports its contributor declared. Without that, the query
+    /// would silently drop the reports that don't fit into its size.
+    ///
+    /// ## Errors
+    /// If contributors declared a different number of reports than `query_size`.
+    pub fn into_stream(
+        mut self,
+        query_id: QueryId,
+        query_size: u32,
+        redaction: &Redaction,
+    ) -> Result<BodyStream, ContributionError> {
+        #[derive(Serialize)]
+        struct Record {
+            contributor: u32,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            count: Option<u32>,
+        }
+
+        let order = self.order();
+        let declared = order.iter().map(|&(_, count)| u64::from(count)).sum();
+        if declared != u64::from(query_size) {
+            return Err(ContributionError::CountMismatch {
+                declared,
+                expected: query_size.into(),
+            });
+        }
+        let records = order
+            .iter()
+            .map(|&(contributor, count)| Record {
+                contributor,
+                count: redaction.apply(SensitiveField::QuerySize, count),
+            })
+            .collect::<Vec<_>>();
+        tracing::info!(
+            target: "ipa_core::query::privacy",
+            query_id = %query_id,
+            contributions = %serde_json::to_string(&records).unwrap(),
+            "query input collected"
+        );
+
+        let streams = order
+            .into_iter()
+            .map(|(contributor, count)| {
+                counted(
+                    contributor,
+                    count,
+                    self.slices.remove(&contributor).unwrap().1,
+                )
+            })
+            .collect::<Vec<_>>();
+        Ok(BodyStream::from_bytes_stream(
+            stream::iter(streams).flatten(),
+        ))
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use futures::TryStreamExt;
+
+    use super::{check_supported, ContributionError, InputContributions};
+    use crate::{
+        ff::FieldType,
+        helpers::{
+            query::{InputContribution, IpaQueryConfig, QueryConfig, QueryType},
+            BodyStream,
+        },
+        protocol::QueryId,
+        query::Redaction,
+        test_executor::run,
+    };
+
+    fn contribution(contributor: u32, count: u32) -> InputContribution {
+        InputContribution {
+            contributor,
+            contributors: NonZeroU32::new(3).unwrap(),
+            count,
+            shuffle_seed: None,
+        }
+    }
+
+    /// `count` length-delimited reports of one byte each, holding `value`.
+    fn reports(count: usize, value: u8) -> Vec<u8> {
+        [1, 0, value].repeat(count)
+    }
+
+    fn collect(slices: &[(InputContribution, Vec<u8>)]) -> InputContributions {
+        let mut contributions = InputContributions::new(&slices[0].0);
+        for (contribution, data) in slices {
+            contributions
+                .add(contribution, BodyStream::from(data.clone()))
+                .unwrap();
+        }
+        contributions
+    }
+
+    async fn to_vec(stream: BodyStream) -> Vec<u8> {
+        stream.try_collect::<Vec<_>>().await.unwrap().concat()
+    }
+
+    #[test]
+    fn concatenates_by_contributor_id() {
+        run(|| async {
+            let contributions = collect(&[
+                (contribution(7, 1), reports(1, 7)),
+                (contribution(2, 2), reports(2, 2)),
+                (contribution(5, 1), reports(1, 5)),
+            ]);
+            assert!(contributions.is_complete());
+            let stream = contributions
+                .into_stream(QueryId, 4, &Redaction::none())
+                .unwrap();
+            assert_eq!(
+                [reports(2, 2), reports(1, 5), reports(1, 7)].concat(),
+                to_vec(stream).await
+            );
+        });
+    }
+
+    #[test]
+    fn verifies_slice_sizes() {
+        run(|| async {
+            // the first slice holds one report more or less than it declares
+            for (declared, actual) in [(1, 2), (2, 1)] {
+                let contributions = collect(&[
+                    (contribution(0, declared), reports(actual, 0)),
+                    (contribution(1, 3 - declared), reports(3 - actual, 1)),
+                    (contribution(2, 1), reports(1, 2)),
+                ]);
+                let stream = contributions
+                    .into_stream(QueryId, 4, &Redaction::none())
+                    .unwrap();
+                let err = stream.try_collect::<Vec<_>>().await.unwrap_err();
+                assert!(
+                    err.to_string().contains(&format!(
+                        "contributor 0 declared {declared} reports, but {actual} were read"
+                    )),
+                    "{err}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn supports_encrypted_reports_only() {
+        let config = |ipa| QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(ipa),
+        };
+        check_supported(&config(IpaQueryConfig::default())).unwrap();
+        assert!(matches!(
+            check_supported(&config(IpaQueryConfig {
+                plaintext_match_keys: true,
+                ..IpaQueryConfig::default()
+            })),
+            Err(ContributionError::UnsupportedQuery(_))
+        ));
+        assert!(matches!(
+            check_supported(&QueryConfig {
+                query_type: QueryType::TestMultiply,
+                ..config(IpaQueryConfig::default())
+            }),
+            Err(ContributionError::UnsupportedQuery(_))
+        ));
+    }
+
+    #[test]
+    fn waits_for_all_contributors() {
+        let contributions = collect(&[(contribution(0, 1), vec![0])]);
+        assert!(!contributions.is_complete());
+    }
+
+    #[test]
+    fn shuffle_is_deterministic() {
+        let with_seed = |contributor| InputContribution {
+            shuffle_seed: Some(42),
+            ..contribution(contributor, 1)
+        };
+        let slices = (0..3)
+            .map(|i| (with_seed(i), vec![u8::try_from(i).unwrap()]))
+            .collect::<Vec<_>>();
+        let mut reversed = slices.clone();
+        reversed.reverse();
+
+        assert_eq!(collect(&slices).order(), collect(&reversed).order());
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let mut contributions = collect(&[(contribution(1, 1), vec![1])]);
+        assert!(matches!(
+            contributions.add(&contribution(1, 1), BodyStream::empty()),
+            Err(ContributionError::DuplicateContributor(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_mismatched_declaration() {
+        let mut contributions = collect(&[(contribution(1, 1), vec![1])]);
+        let other = InputContribution {
+            contributors: NonZeroU32::new(2).unwrap(),
+            ..contribution(2, 1)
+        };
+        assert!(matches!(
+            contributions.add(&other, BodyStream::empty()),
+            Err(ContributionError::DeclarationMismatch { contributor: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_count_mismatch() {
+        let contributions = collect(&[
+            (contribution(0, 1), vec![0]),
+            (contribution(1, 1), vec![1]),
+            (contribution(2, 1), vec![2]),
+        ]);
+        assert!(matches!(
+            contributions.into_stream(QueryId, 4, &Redaction::none()),
+            Err(ContributionError::CountMismatch {
+                declared: 3,
+                expected: 4
+            })
+        ));
+    }
+}
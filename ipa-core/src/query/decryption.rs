@@ -0,0 +1,353 @@
+//! Handling of input reports that helpers fail to decrypt.
+//!
+//! By default, a single report that can't be decrypted, or that was collected for a different
+//! site, fails the whole query. Report collectors can instead ask helpers to drop such reports,
+//! optionally keeping their ciphertexts in a quarantine file for offline analysis. Either way,
+//! helpers still abort the query if too large a share of the input is dropped, since that
+//! points to a problem with the input rather than a few bad reports.
+//!
+//! Every helper decrypts its own share of a report, so a report can fail to decrypt on one
+//! helper only. Helpers exchange which reports they failed to decrypt and all of them drop the
+//! reports that failed on any helper. Otherwise, the remaining shares of one helper would no
+//! longer line up with those of the others.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures::future::try_join;
+use ipa_metrics::counter;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::{
+    error::Error,
+    ff::{boolean_array::BA64, U128Conversions},
+    helpers::{Direction, TotalRecords},
+    protocol::{context::Context, QueryId, RecordId},
+    report::InvalidReportError,
+    telemetry::metrics::DECRYPTION_FAILURES,
+};
+
+/// What helpers do with reports that fail to decrypt.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum DecryptionFailurePolicy {
+    /// Fail the query.
+    #[default]
+    Abort,
+    /// Drop the report and count it.
+    Skip,
+    /// Drop the report and write its ciphertext to the quarantine file of the query, if the
+    /// helper has a quarantine directory configured.
+    Quarantine,
+}
+
+/// Directory where helpers keep the reports that failed to decrypt, one file per query. Files
+/// have one hex-encoded report per line, the format accepted by the report collector.
+#[derive(Clone, Debug)]
+pub struct Quarantine {
+    dir: PathBuf,
+}
+
+impl Quarantine {
+    #[must_use]
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Directory where the quarantine files are kept.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the quarantine file for `query_id`.
+    #[must_use]
+    pub fn path(&self, query_id: QueryId) -> PathBuf {
+        self.dir.join(format!("{query_id}.quarantine"))
+    }
+}
+
+/// Applies a [`DecryptionFailurePolicy`] to the reports of one query.
+pub(crate) struct DecryptionFailures {
+    policy: DecryptionFailurePolicy,
+    max_rate: f64,
+    /// Quarantine file for this query, if reports are quarantined.
+    quarantine: Option<PathBuf>,
+    quarantined: Vec<Vec<u8>>,
+    /// Reports this helper failed to decrypt.
+    failures: usize,
+    /// Reports dropped by all helpers, which includes those that failed on other helpers once
+    /// helpers agreed on them.
+    dropped: usize,
+    total: usize,
+}
+
+impl DecryptionFailures {
+    /// `quarantine` is the quarantine file of the query, if this helper has one.
+    pub fn new(
+        policy: DecryptionFailurePolicy,
+        max_rate: f64,
+        quarantine: Option<PathBuf>,
+    ) -> Self {
+        let quarantine = quarantine.filter(|_| policy == DecryptionFailurePolicy::Quarantine);
+        if policy == DecryptionFailurePolicy::Quarantine && quarantine.is_none() {
+            tracing::warn!(
+                "query asks to quarantine reports that fail to decrypt, but this helper has no \
+                 quarantine directory. They will be skipped."
+            );
+        }
+        Self {
+            policy,
+            max_rate,
+            quarantine,
+            quarantined: Vec::new(),
+            failures: 0,
+            dropped: 0,
+            total: 0,
+        }
+    }
+
+    /// Returns the decrypted report, or `None` if the report failed to decrypt and the policy
+    /// is to drop it. Reports that decrypted may still have to be dropped, see [`Self::agree`].
+    ///
+    /// ## Errors
+    /// If the report failed to decrypt and the policy is to abort.
+    pub fn handle<T>(
+        &mut self,
+        ciphertext: &[u8],
+        decrypted: Result<T, InvalidReportError>,
+    ) -> Result<Option<T>, Error> {
+        self.total += 1;
+        match decrypted {
+            Ok(report) => Ok(Some(report)),
+            Err(e) if self.policy == DecryptionFailurePolicy::Abort => Err(e.into()),
+            Err(e) => {
+                tracing::debug!("dropping report that failed to decrypt: {e}");
+                counter!(DECRYPTION_FAILURES, 1);
+                self.failures += 1;
+                self.dropped += 1;
+                if self.quarantine.is_some() {
+                    self.quarantined.push(ciphertext.to_vec());
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Exchanges with the other helpers which reports failed to decrypt. `failed` tells for
+    /// every report whether this helper failed to decrypt it, the returned vector whether any
+    /// helper did. All helpers must drop the same reports.
+    ///
+    /// Queries that abort on the first failure have nothing to agree on, so helpers don't
+    /// exchange anything for them.
+    ///
+    /// ## Errors
+    /// If the exchange fails.
+    pub async fn agree<C: Context>(&mut self, ctx: C, failed: &[bool]) -> Result<Vec<bool>, Error> {
+        const WORD_BITS: usize = 64;
+
+        if self.policy == DecryptionFailurePolicy::Abort {
+            return Ok(failed.to_vec());
+        }
+        let words = failed
+            .chunks(WORD_BITS)
+            .map(|chunk| {
+                BA64::truncate_from(
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0_u128, |word, (i, &f)| word | (u128::from(f) << i)),
+                )
+            })
+            .collect::<Vec<_>>();
+        let Ok(total_records) = TotalRecords::specified(words.len()) else {
+            return Ok(Vec::new());
+        };
+
+        let ctx = ctx.set_total_records(total_records);
+        let sender = ctx.broadcast_channel::<BA64>();
+        let recv_left = ctx.recv_channel::<BA64>(ctx.role().peer(Direction::Left));
+        let recv_right = ctx.recv_channel::<BA64>(ctx.role().peer(Direction::Right));
+        let words = ctx
+            .parallel_join(words.into_iter().enumerate().map(|(i, word)| {
+                let (sender, recv_left, recv_right) = (&sender, &recv_left, &recv_right);
+                async move {
+                    let record_id = RecordId::from(i);
+                    let ((), (left, right)) = try_join(
+                        sender.broadcast(record_id, word),
+                        try_join(recv_left.receive(record_id), recv_right.receive(record_id)),
+                    )
+                    .await?;
+                    Ok::<_, Error>(word.as_u128() | left.as_u128() | right.as_u128())
+                }
+            }))
+            .await?;
+
+        let agreed = (0..failed.len())
+            .map(|i| (words[i / WORD_BITS] >> (i % WORD_BITS)) & 1 == 1)
+            .collect::<Vec<_>>();
+        self.dropped = agreed.iter().filter(|&&failed| failed).count();
+        Ok(agreed)
+    }
+
+    /// Writes the quarantined reports, and checks that no more than the allowed share of
+    /// reports were dropped. Only reports this helper failed to decrypt are quarantined.
+    ///
+    /// ## Errors
+    /// If too many reports were dropped, or if writing the quarantine file fails.
+    pub async fn finish(self) -> Result<(), Error> {
+        if let Some(path) = &self.quarantine {
+            if !self.quarantined.is_empty() {
+                write_quarantine(path, &self.quarantined).await?;
+                tracing::info!(
+                    "quarantined {} reports that failed to decrypt in {}",
+                    self.quarantined.len(),
+                    path.display()
+                );
+            }
+        }
+        if self.dropped > 0 {
+            tracing::warn!(
+                "{} of {} reports failed to decrypt, {} of them on this helper",
+                self.dropped,
+                self.total,
+                self.failures,
+            );
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let rate = self.dropped as f64 / self.total.max(1) as f64;
+        if rate > self.max_rate {
+            return Err(Error::TooManyDecryptionFailures {
+                failures: self.dropped,
+                total: self.total,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+async fn write_quarantine(path: &Path, reports: &[Vec<u8>]) -> io::Result<()> {
+    let lines = reports
+        .iter()
+        .map(|report| format!("{}\n", hex::encode(report)))
+        .collect::<String>();
+    // Reports of different queries never end up in the same file.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    file.write_all(lines.as_bytes()).await?;
+    file.flush().await
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::fs;
+
+    use super::{DecryptionFailurePolicy, DecryptionFailures, Quarantine};
+    use crate::{
+        error::Error,
+        protocol::{context::Context, QueryId},
+        report::InvalidReportError,
+        test_fixture::{join3v, TestWorld},
+    };
+
+    fn bad() -> Result<u32, InvalidReportError> {
+        Err(InvalidReportError::SiteMismatch("example.com".to_string()))
+    }
+
+    #[test]
+    fn abort() {
+        let mut failures = DecryptionFailures::new(DecryptionFailurePolicy::Abort, 1.0, None);
+        assert_eq!(Some(1), failures.handle(b"good", Ok(1)).unwrap());
+        assert!(matches!(
+            failures.handle(b"bad", bad()),
+            Err(Error::InvalidReport(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn skip_up_to_rate() {
+        let mut failures = DecryptionFailures::new(DecryptionFailurePolicy::Skip, 0.5, None);
+        assert_eq!(None, failures.handle(b"bad", bad()).unwrap());
+        assert_eq!(Some(1), failures.handle(b"good", Ok(1)).unwrap());
+        failures.finish().await.unwrap();
+
+        let mut failures = DecryptionFailures::new(DecryptionFailurePolicy::Skip, 0.5, None);
+        for _ in 0..2 {
+            assert_eq!(None, failures.handle(b"bad", bad()).unwrap());
+        }
+        assert_eq!(Some(1), failures.handle(b"good", Ok(1)).unwrap());
+        assert!(matches!(
+            failures.finish().await,
+            Err(Error::TooManyDecryptionFailures {
+                failures: 2,
+                total: 3
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let quarantine = Quarantine::new(dir.path().to_path_buf());
+        let mut failures = DecryptionFailures::new(
+            DecryptionFailurePolicy::Quarantine,
+            1.0,
+            Some(quarantine.path(QueryId)),
+        );
+        assert_eq!(None, failures.handle(&[0xab, 0x01], bad()).unwrap());
+        assert_eq!(Some(1), failures.handle(b"good", Ok(1)).unwrap());
+        assert_eq!(None, failures.handle(&[0xff], bad()).unwrap());
+        failures.finish().await.unwrap();
+
+        assert_eq!(
+            "ab01\nff\n",
+            fs::read_to_string(quarantine.path(QueryId)).unwrap()
+        );
+    }
+
+    /// Only H2 fails to decrypt some reports, yet all helpers must drop them.
+    #[tokio::test]
+    async fn agree_on_failures_of_one_helper() {
+        const REPORTS: usize = 70;
+        const FAILED: [usize; 2] = [3, 65];
+
+        let world = TestWorld::default();
+        let results = join3v(world.contexts().into_iter().enumerate().map(
+            |(helper, ctx)| async move {
+                let mut failures =
+                    DecryptionFailures::new(DecryptionFailurePolicy::Skip, 0.05, None);
+                let failed = (0..REPORTS)
+                    .map(|i| {
+                        let decrypted = if helper == 1 && FAILED.contains(&i) {
+                            bad()
+                        } else {
+                            Ok(1)
+                        };
+                        failures.handle(b"report", decrypted).unwrap().is_none()
+                    })
+                    .collect::<Vec<_>>();
+                let agreed = failures
+                    .agree(ctx.narrow("decryption-failures"), &failed)
+                    .await?;
+                failures.finish().await?;
+                Ok::<_, Error>(agreed)
+            },
+        ))
+        .await;
+
+        let expected = (0..REPORTS)
+            .map(|i| FAILED.contains(&i))
+            .collect::<Vec<_>>();
+        for agreed in results {
+            assert_eq!(expected, agreed);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Escrow of query results.
+//!
+//! Helpers that run a query with [`IpaQueryConfig::escrow_results`] set hold on to their output
+//! shares until both peers confirm that they completed the same query. Each helper sends its
+//! peers a digest of the query configuration once its part of the query succeeds, so the
+//! confirmation of a helper that aborted, for example because a malicious security check
+//! failed, never arrives. That stops the report collector from combining the output shares of
+//! two helpers with whatever it can get from the third one.
+//!
+//! Confirmations travel over the same authenticated channels as the rest of the query, so a
+//! peer can't be impersonated. The same exchange, [`confirm_config`], lets helpers check that
+//! they agree on parameters that report collectors send to each of them separately, before a
+//! query starts.
+//!
+//! [`IpaQueryConfig::escrow_results`]: crate::helpers::query::IpaQueryConfig::escrow_results
+
+use futures::future::try_join3;
+
+use crate::{
+    error::Error,
+    helpers::{
+        hashing::{compute_bytes_hash, Hash},
+        query::{QueryConfig, QueryType},
+        BroadcastingEnd, ChannelId, Direction, Gateway, TotalRecords,
+    },
+    protocol::{Gate, RecordId},
+};
+
+/// Whether the results of a query with this configuration are escrowed.
+#[must_use]
+pub fn enabled(config: &QueryConfig) -> bool {
+    match &config.query_type {
+        QueryType::SemiHonestOprfIpa(ipa_config) | QueryType::MaliciousOprfIpa(ipa_config) => {
+            ipa_config.escrow_results
+        }
+        _ => false,
+    }
+}
+
+/// Digest of `config` that peers compare to make sure they ran the same query.
+fn digest(config: &QueryConfig) -> Hash {
+    compute_bytes_hash(&serde_json::to_vec(config).unwrap())
+}
+
+/// Confirms to both peers that this helper completed the query described by `config` and waits
+/// for their confirmations. The results of the query can be released once this returns.
+///
+/// ## Errors
+/// If communication with the peers fails, or if a peer ran the query with a different
+/// configuration.
+pub async fn confirm_completion(
+    gateway: &Gateway,
+    gate: &Gate,
+    config: &QueryConfig,
+) -> Result<(), Error> {
+    confirm_config(gateway, gate, config).await
+}
+
+/// Sends both peers the digest of `config` and checks that they send back the same one.
+///
+/// ## Errors
+/// If communication with the peers fails, or if a peer runs the query with a different
+/// configuration.
+pub async fn confirm_config(
+    gateway: &Gateway,
+    gate: &Gate,
+    config: &QueryConfig,
+) -> Result<(), Error> {
+    let query_id = gateway.query_id();
+    let [left, right] = [Direction::Left, Direction::Right]
+        .map(|direction| ChannelId::new(query_id, gateway.role().peer(direction), gate.clone()));
+    let active_work = gateway.config().active_work_as_power_of_two();
+    let sender = BroadcastingEnd::new(
+        gateway.get_mpc_sender::<Hash>(&left, TotalRecords::ONE, active_work),
+        gateway.get_mpc_sender::<Hash>(&right, TotalRecords::ONE, active_work),
+    );
+    let left_receiver = gateway.get_mpc_receiver::<Hash>(&left, TotalRecords::ONE);
+    let right_receiver = gateway.get_mpc_receiver::<Hash>(&right, TotalRecords::ONE);
+
+    let digest = digest(config);
+    let ((), from_left, from_right) = try_join3(
+        sender.broadcast(RecordId::FIRST, &digest),
+        left_receiver.receive(RecordId::FIRST),
+        right_receiver.receive(RecordId::FIRST),
+    )
+    .await?;
+
+    for (peer, confirmed) in [(left.peer, from_left), (right.peer, from_right)] {
+        if confirmed != digest {
+            return Err(Error::QueryConfigMismatch(peer));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::future::join3;
+    use ipa_step::StepNarrow;
+
+    use super::confirm_completion;
+    use crate::{
+        error::Error,
+        ff::FieldType,
+        helpers::{
+            query::{IpaQueryConfig, QueryConfig, QueryType},
+            Role,
+        },
+        protocol::Gate,
+        test_executor::run,
+        test_fixture::TestWorld,
+    };
+
+    fn config(epsilon: f64) -> QueryConfig {
+        QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                epsilon,
+                escrow_results: true,
+                ..IpaQueryConfig::default()
+            }),
+            FieldType::Fp32BitPrime,
+            100,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn confirms_same_query() {
+        run(|| async {
+            let world = TestWorld::default();
+            let gate = Gate::default().narrow("escrow");
+            let config = config(1.0);
+            let [h1, h2, h3] = Role::all().map(|role| world.gateway(role));
+            let results = join3(
+                confirm_completion(h1, &gate, &config),
+                confirm_completion(h2, &gate, &config),
+                confirm_completion(h3, &gate, &config),
+            )
+            .await;
+
+            assert!(matches!(results, (Ok(()), Ok(()), Ok(()))));
+        });
+    }
+
+    #[test]
+    fn rejects_different_query() {
+        run(|| async {
+            let world = TestWorld::default();
+            let gate = Gate::default().narrow("escrow");
+            let [h1, h2, h3] = Role::all().map(|role| world.gateway(role));
+            let (r1, r2, r3) = join3(
+                confirm_completion(h1, &gate, &config(1.0)),
+                confirm_completion(h2, &gate, &config(1.0)),
+                confirm_completion(h3, &gate, &config(2.0)),
+            )
+            .await;
+
+            // H3 disagrees with both of its peers, H1 and H2 only with H3.
+            assert!(matches!(r1, Err(Error::QueryConfigMismatch(Role::H3))));
+            assert!(matches!(r2, Err(Error::QueryConfigMismatch(Role::H3))));
+            assert!(matches!(r3, Err(Error::QueryConfigMismatch(_))));
+        });
+    }
+}
@@ -1,8 +1,10 @@
 use std::{
     borrow::Borrow,
-    fmt::Debug,
+    collections::HashMap,
+    fmt::{Debug, Formatter},
     future::{ready, Future},
-    pin::Pin,
+    pin::{pin, Pin},
+    time::Instant,
 };
 
 use ::tokio::{
@@ -10,23 +12,23 @@ use ::tokio::{
     sync::oneshot,
     task::block_in_place,
 };
-use futures::FutureExt;
+use futures::{
+    future::{select, Either},
+    FutureExt,
+};
 use generic_array::GenericArray;
 use ipa_step::StepNarrow;
-use rand::rngs::StdRng;
-use rand_core::SeedableRng;
 use typenum::Unsigned;
 
-#[cfg(any(
-    test,
-    feature = "cli",
-    feature = "test-fixture",
-    feature = "weak-field"
-))]
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 use crate::ff::FieldType;
 use crate::{
+    error::Error,
     executor::IpaRuntime,
-    ff::{boolean_array::BA32, Serializable},
+    ff::{
+        boolean_array::{BA16, BA32},
+        Serializable,
+    },
     helpers::{
         negotiate_prss,
         query::{QueryConfig, QueryType},
@@ -35,23 +37,34 @@ use crate::{
     hpke::PrivateKeyRegistry,
     protocol::{
         context::{MaliciousContext, SemiHonestContext},
+        dp::NoiseReport,
         prss::Endpoint as PrssEndpoint,
         Gate,
     },
     query::{
-        runner::{execute_hybrid_protocol, OprfIpaQuery, QueryResult},
+        escrow,
+        runner::{execute_hybrid_protocol, OprfIpaQuery},
         state::RunningQuery,
+        PrfCache, Quarantine,
     },
-    sync::Arc,
+    sync::{Arc, Mutex},
+    telemetry::{memory::MemoryProfile, progress::QueryProgress, send_buffers::SendBufferStatus},
+    utils::rng::CryptoRngProvider,
 };
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 use crate::{
-    ff::Fp32BitPrime, query::runner::execute_sharded_shuffle, query::runner::execute_test_multiply,
-    query::runner::test_add_in_prime_field,
+    ff::Fp32BitPrime, query::runner::execute_sharded_shuffle,
+    query::runner::execute_share_conversion, query::runner::execute_test_multiply,
+    query::runner::test_add_in_prime_field as execute_add_in_prime_field,
 };
 
 pub trait Result: Send + Debug {
     fn to_bytes(&self) -> Vec<u8>;
+
+    /// Noise added to the result by the protocol, if any.
+    fn noise(&self) -> Option<NoiseReport> {
+        None
+    }
 }
 
 impl<T> Result for Vec<T>
@@ -71,117 +84,371 @@ where
     }
 }
 
-/// Needless pass by value because IPA v3 does not make use of key registry yet.
-#[allow(clippy::too_many_lines, clippy::needless_pass_by_value)]
+/// Future that runs a query to completion and produces its serialized output.
+pub type QueryFuture<'a> =
+    Pin<Box<dyn Future<Output = std::result::Result<Box<dyn Result>, Error>> + Send + 'a>>;
+
+/// Runs queries of one type.
+///
+/// An executor owns everything that is specific to its query type: it parses the input stream
+/// sent by the report collector, runs the protocol and returns a [`Result`] that knows how to
+/// serialize the output. Executors are registered with [`QueryExecutors`] under the name of
+/// the query type they handle, so adding a query type does not require changes to the
+/// processor.
+pub trait QueryExecutor<R>: Send + Sync {
+    fn execute<'a>(
+        &self,
+        prss: &'a PrssEndpoint,
+        gateway: &'a Gateway,
+        config: &'a QueryConfig,
+        key_registry: Arc<R>,
+        input: BodyStream,
+    ) -> QueryFuture<'a>;
+}
+
+impl<R, F> QueryExecutor<R> for F
+where
+    F: for<'a> Fn(
+            &'a PrssEndpoint,
+            &'a Gateway,
+            &'a QueryConfig,
+            Arc<R>,
+            BodyStream,
+        ) -> QueryFuture<'a>
+        + Send
+        + Sync,
+{
+    fn execute<'a>(
+        &self,
+        prss: &'a PrssEndpoint,
+        gateway: &'a Gateway,
+        config: &'a QueryConfig,
+        key_registry: Arc<R>,
+        input: BodyStream,
+    ) -> QueryFuture<'a> {
+        self(prss, gateway, config, key_registry, input)
+    }
+}
+
+/// Runs queries whose input was uploaded in batches with `executor`. Every helper is sealed with
+/// the count of reports separately, so before the query starts, helpers confirm with each other
+/// that they run it with the same configuration, which includes the sealed count.
+pub struct SealedInput<R>(pub Arc<dyn QueryExecutor<R>>);
+
+impl<R> QueryExecutor<R> for SealedInput<R> {
+    fn execute<'a>(
+        &self,
+        prss: &'a PrssEndpoint,
+        gateway: &'a Gateway,
+        config: &'a QueryConfig,
+        key_registry: Arc<R>,
+        input: BodyStream,
+    ) -> QueryFuture<'a> {
+        let query = self.0.execute(prss, gateway, config, key_registry, input);
+        Box::pin(async move {
+            escrow::confirm_config(gateway, &sealed_input_gate(), config).await?;
+            query.await
+        })
+    }
+}
+
+/// Executors known to this helper, keyed by the name of the query type they handle (see
+/// [`QueryType::as_ref`]). [`Default`] registers the executors for all query types supported
+/// by this build.
+pub struct QueryExecutors<R> {
+    executors: HashMap<&'static str, Arc<dyn QueryExecutor<R>>>,
+}
+
+impl<R: PrivateKeyRegistry> QueryExecutors<R> {
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            executors: HashMap::new(),
+        }
+    }
+
+    /// Registers `executor` to run queries of type `query_type`.
+    ///
+    /// ## Panics
+    /// If an executor is already registered for `query_type`.
+    pub fn register<E: QueryExecutor<R> + 'static>(
+        &mut self,
+        query_type: &'static str,
+        executor: E,
+    ) -> &mut Self {
+        let prev = self.executors.insert(query_type, Arc::new(executor));
+        assert!(
+            prev.is_none(),
+            "executor for {query_type} is already registered"
+        );
+
+        self
+    }
+
+    /// Returns the executor for `query_type`, if one is registered.
+    #[must_use]
+    pub fn get(&self, query_type: &QueryType) -> Option<Arc<dyn QueryExecutor<R>>> {
+        self.executors.get(query_type.as_ref()).cloned()
+    }
+}
+
+impl<R: PrivateKeyRegistry> QueryExecutors<R> {
+    /// Executors for all query types supported by this build, like [`Default`]. OPRF IPA
+    /// queries that ask for it quarantine the reports that fail to decrypt in `quarantine`.
+    #[must_use]
+    pub fn with_quarantine(quarantine: Quarantine) -> Self {
+        Self::with_oprf_ipa_storage(Some(quarantine), None)
+    }
+
+    /// Executors for all query types supported by this build, like [`Default`]. OPRF IPA
+    /// queries that ask for it quarantine the reports that fail to decrypt in `quarantine`
+    /// and keep their PRF'd input in `prf_cache`, if this helper has them.
+    #[must_use]
+    pub fn with_oprf_ipa_storage(
+        quarantine: Option<Quarantine>,
+        prf_cache: Option<Arc<PrfCache>>,
+    ) -> Self {
+        Self::supported(OprfIpaExecutor {
+            quarantine,
+            prf_cache,
+        })
+    }
+
+    fn supported(oprf_ipa: OprfIpaExecutor) -> Self {
+        let mut this = Self::empty();
+        #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+        this.register(QueryType::TEST_MULTIPLY_STR, test_multiply::<R>)
+            .register(
+                QueryType::TEST_SHARDED_SHUFFLE_STR,
+                test_sharded_shuffle::<R>,
+            )
+            .register(
+                QueryType::TEST_SHARE_CONVERSION_STR,
+                test_share_conversion::<R>,
+            )
+            .register(QueryType::TEST_ADD_STR, test_add_in_prime_field::<R>);
+        this.register(QueryType::SEMI_HONEST_OPRF_IPA_STR, oprf_ipa.clone())
+            .register(QueryType::MALICIOUS_OPRF_IPA_STR, oprf_ipa)
+            .register(QueryType::MALICIOUS_HYBRID_STR, malicious_hybrid::<R>);
+
+        this
+    }
+}
+
+impl<R: PrivateKeyRegistry> Default for QueryExecutors<R> {
+    fn default() -> Self {
+        Self::supported(OprfIpaExecutor::default())
+    }
+}
+
+impl<R> Debug for QueryExecutors<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.executors.keys()).finish()
+    }
+}
+
+/// Error returned by an executor asked to run a query it does not support, like a query of
+/// a different type or over a field it can't work with.
+fn unsupported<'a>(config: &QueryConfig) -> QueryFuture<'a> {
+    Box::pin(ready(Err(Error::Unsupported(format!(
+        "{query_type} over {field_type:?}",
+        query_type = config.query_type.as_ref(),
+        field_type = config.field_type,
+    )))))
+}
+
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+#[allow(clippy::needless_pass_by_value)] // signature is fixed by `QueryExecutor`
+fn test_multiply<'a, R>(
+    prss: &'a PrssEndpoint,
+    gateway: &'a Gateway,
+    config: &'a QueryConfig,
+    _key_registry: Arc<R>,
+    input: BodyStream,
+) -> QueryFuture<'a> {
+    match config.field_type {
+        #[cfg(any(test, feature = "weak-field"))]
+        FieldType::Fp31 => Box::pin(execute_test_multiply::<crate::ff::Fp31>(
+            prss, gateway, input,
+        )),
+        FieldType::Fp32BitPrime => {
+            Box::pin(execute_test_multiply::<Fp32BitPrime>(prss, gateway, input))
+        }
+    }
+}
+
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+#[allow(clippy::needless_pass_by_value)] // signature is fixed by `QueryExecutor`
+fn test_sharded_shuffle<'a, R>(
+    prss: &'a PrssEndpoint,
+    gateway: &'a Gateway,
+    _config: &'a QueryConfig,
+    _key_registry: Arc<R>,
+    input: BodyStream,
+) -> QueryFuture<'a> {
+    Box::pin(execute_sharded_shuffle(prss, gateway, input))
+}
+
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+#[allow(clippy::needless_pass_by_value)] // signature is fixed by `QueryExecutor`
+fn test_share_conversion<'a, R>(
+    prss: &'a PrssEndpoint,
+    gateway: &'a Gateway,
+    config: &'a QueryConfig,
+    _key_registry: Arc<R>,
+    input: BodyStream,
+) -> QueryFuture<'a> {
+    let QueryType::TestShareConversion(params) = config.query_type else {
+        return unsupported(config);
+    };
+    Box::pin(execute_share_conversion(
+        prss,
+        gateway,
+        config.size.into(),
+        params,
+        input,
+    ))
+}
+
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+#[allow(clippy::needless_pass_by_value)] // signature is fixed by `QueryExecutor`
+fn test_add_in_prime_field<'a, R>(
+    prss: &'a PrssEndpoint,
+    gateway: &'a Gateway,
+    config: &'a QueryConfig,
+    _key_registry: Arc<R>,
+    input: BodyStream,
+) -> QueryFuture<'a> {
+    match config.field_type {
+        #[cfg(any(test, feature = "weak-field"))]
+        FieldType::Fp31 => Box::pin(execute_add_in_prime_field::<crate::ff::Fp31>(
+            prss, gateway, input,
+        )),
+        FieldType::Fp32BitPrime => Box::pin(execute_add_in_prime_field::<Fp32BitPrime>(
+            prss, gateway, input,
+        )),
+    }
+}
+
+/// Runs semi-honest and malicious OPRF IPA queries. Queries that ask for it quarantine the
+/// reports that fail to decrypt in `quarantine` and keep their PRF'd input in `prf_cache`, if
+/// this helper has them.
+#[derive(Clone, Default)]
+struct OprfIpaExecutor {
+    quarantine: Option<Quarantine>,
+    prf_cache: Option<Arc<PrfCache>>,
+}
+
+// TODO(953): This is really using BA16 or BA32, as selected by `histogram_value_bits` or picked
+// from the query size, not Fp32bitPrime. The `FieldType` mechanism needs to be reworked.
+impl<R: PrivateKeyRegistry> QueryExecutor<R> for OprfIpaExecutor {
+    fn execute<'a>(
+        &self,
+        prss: &'a PrssEndpoint,
+        gateway: &'a Gateway,
+        config: &'a QueryConfig,
+        key_registry: Arc<R>,
+        input: BodyStream,
+    ) -> QueryFuture<'a> {
+        let quarantine = self.quarantine.as_ref().map(|q| q.path(gateway.query_id()));
+        match config.query_type {
+            QueryType::SemiHonestOprfIpa(ipa_config) => {
+                let ipa_config = ipa_config.resolve_histogram_value_bits(config.size);
+                let ctx = SemiHonestContext::new(prss, gateway);
+                match ipa_config.histogram_value_bits {
+                    16 => Box::pin(
+                        OprfIpaQuery::<_, BA16, R>::new(ipa_config, key_registry)
+                            .with_quarantine(quarantine)
+                            .with_prf_cache(self.prf_cache.clone(), gateway.query_id())
+                            .with_request(config.field_type, gateway.protocol_version())
+                            .execute(ctx, config.size, input)
+                            .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                    ),
+                    // Other widths are rejected by the query runner.
+                    _ => Box::pin(
+                        OprfIpaQuery::<_, BA32, R>::new(ipa_config, key_registry)
+                            .with_quarantine(quarantine)
+                            .with_prf_cache(self.prf_cache.clone(), gateway.query_id())
+                            .with_request(config.field_type, gateway.protocol_version())
+                            .execute(ctx, config.size, input)
+                            .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                    ),
+                }
+            }
+            // Dry runs report this too, but queries must not depend on the client to check it.
+            QueryType::MaliciousOprfIpa(ipa_config) if ipa_config.prune_zero_rows => {
+                Box::pin(ready(Err(Error::InvalidQueryParameter(
+                    "zero-value rows can only be pruned in semi-honest queries".into(),
+                ))))
+            }
+            QueryType::MaliciousOprfIpa(ipa_config) => {
+                let ipa_config = ipa_config.resolve_histogram_value_bits(config.size);
+                let ctx = MaliciousContext::new(prss, gateway);
+                match ipa_config.histogram_value_bits {
+                    16 => Box::pin(
+                        OprfIpaQuery::<_, BA16, R>::new(ipa_config, key_registry)
+                            .with_quarantine(quarantine)
+                            .with_prf_cache(self.prf_cache.clone(), gateway.query_id())
+                            .with_request(config.field_type, gateway.protocol_version())
+                            .execute(ctx, config.size, input)
+                            .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                    ),
+                    _ => Box::pin(
+                        OprfIpaQuery::<_, BA32, R>::new(ipa_config, key_registry)
+                            .with_quarantine(quarantine)
+                            .with_prf_cache(self.prf_cache.clone(), gateway.query_id())
+                            .with_request(config.field_type, gateway.protocol_version())
+                            .execute(ctx, config.size, input)
+                            .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                    ),
+                }
+            }
+            _ => unsupported(config),
+        }
+    }
+}
+
+fn malicious_hybrid<'a, R: PrivateKeyRegistry>(
+    prss: &'a PrssEndpoint,
+    gateway: &'a Gateway,
+    config: &'a QueryConfig,
+    key_registry: Arc<R>,
+    input: BodyStream,
+) -> QueryFuture<'a> {
+    let QueryType::MaliciousHybrid(ipa_config) = config.query_type else {
+        return unsupported(config);
+    };
+    Box::pin(execute_hybrid_protocol(
+        prss,
+        gateway,
+        input,
+        ipa_config,
+        config,
+        key_registry,
+    ))
+}
+
+/// Starts running the query described by `config` with `executor` on `runtime`. PRSS for the
+/// query is set up with randomness from `rng_provider`.
 pub fn execute<R: PrivateKeyRegistry>(
     runtime: &IpaRuntime,
+    executor: Arc<dyn QueryExecutor<R>>,
     config: QueryConfig,
     key_registry: Arc<R>,
     gateway: Gateway,
     input: BodyStream,
+    rng_provider: &dyn CryptoRngProvider,
 ) -> RunningQuery {
-    match (config.query_type, config.field_type) {
-        #[cfg(any(test, feature = "weak-field"))]
-        (QueryType::TestMultiply, FieldType::Fp31) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            |prss, gateway, _config, input| {
-                Box::pin(execute_test_multiply::<crate::ff::Fp31>(
-                    prss, gateway, input,
-                ))
-            },
-        ),
-        #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-        (QueryType::TestMultiply, FieldType::Fp32BitPrime) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            |prss, gateway, _config, input| {
-                Box::pin(execute_test_multiply::<Fp32BitPrime>(prss, gateway, input))
-            },
-        ),
-        #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-        (QueryType::TestShardedShuffle, _) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            |prss, gateway, _config, input| Box::pin(execute_sharded_shuffle(prss, gateway, input)),
-        ),
-        #[cfg(any(test, feature = "weak-field"))]
-        (QueryType::TestAddInPrimeField, FieldType::Fp31) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            |prss, gateway, _config, input| {
-                Box::pin(test_add_in_prime_field::<crate::ff::Fp31>(
-                    prss, gateway, input,
-                ))
-            },
-        ),
-        #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-        (QueryType::TestAddInPrimeField, FieldType::Fp32BitPrime) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            |prss, gateway, _config, input| {
-                Box::pin(test_add_in_prime_field::<Fp32BitPrime>(
-                    prss, gateway, input,
-                ))
-            },
-        ),
-        // TODO(953): This is really using BA32, not Fp32bitPrime. The `FieldType` mechanism needs
-        // to be reworked.
-        (QueryType::SemiHonestOprfIpa(ipa_config), _) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            move |prss, gateway, config, input| {
-                let ctx = SemiHonestContext::new(prss, gateway);
-                Box::pin(
-                    OprfIpaQuery::<_, BA32, R>::new(ipa_config, key_registry)
-                        .execute(ctx, config.size, input)
-                        .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
-                )
-            },
-        ),
-        (QueryType::MaliciousOprfIpa(ipa_config), _) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            move |prss, gateway, config, input| {
-                let ctx = MaliciousContext::new(prss, gateway);
-                Box::pin(
-                    OprfIpaQuery::<_, BA32, R>::new(ipa_config, key_registry)
-                        .execute(ctx, config.size, input)
-                        .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
-                )
-            },
-        ),
-        (QueryType::MaliciousHybrid(ipa_config), _) => do_query(
-            runtime,
-            config,
-            gateway,
-            input,
-            move |prss, gateway, config, input| {
-                Box::pin(execute_hybrid_protocol(
-                    prss,
-                    gateway,
-                    input,
-                    ipa_config,
-                    config,
-                    key_registry,
-                ))
-            },
-        ),
-    }
+    do_query(
+        runtime,
+        config,
+        gateway,
+        input,
+        rng_provider,
+        move |prss, gateway, config, input| {
+            executor.execute(prss, gateway, config, key_registry, input)
+        },
+    )
 }
 
 pub fn do_query<B, F>(
@@ -189,6 +456,7 @@ pub fn do_query<B, F>(
     config: QueryConfig,
     gateway: B,
     input_stream: BodyStream,
+    rng_provider: &dyn CryptoRngProvider,
     query_impl: F,
 ) -> RunningQuery
 where
@@ -197,22 +465,39 @@ where
             &'a Gateway,
             &'a QueryConfig,
             BodyStream,
-        ) -> Pin<Box<dyn Future<Output = QueryResult> + Send + 'a>>
+        ) -> QueryFuture<'a>
         + Send
         + 'static,
     B: Borrow<Gateway> + Send + 'static,
 {
     let (tx, rx) = oneshot::channel();
+    let leader = gateway.borrow().leader();
+    let mut rng = rng_provider.rng();
+    let tuning_report = Arc::new(Mutex::new(None));
+    let report_slot = Arc::clone(&tuning_report);
+    let send_buffers = Arc::new(Mutex::new(Vec::new()));
+    let send_buffers_slot = Arc::clone(&send_buffers);
+    let progress = QueryProgress::default();
+    let query_progress = progress.clone();
 
     let join_handle = executor_handle.spawn(async move {
         let gateway = gateway.borrow();
-        // TODO: make it a generic argument for this function
-        let mut rng = StdRng::from_entropy();
+        let start = Instant::now();
         // Negotiate PRSS using the initial gate for the protocol (no narrowing).
         let prss = negotiate_prss(gateway, &prss_gate(), &mut rng)
             .await
             .unwrap();
 
+        let memory = MemoryProfile::default();
+        let query = watched(gateway, &send_buffers_slot, async {
+            let result = query_impl(&prss, gateway, &config, input_stream).await?;
+            if escrow::enabled(&config) {
+                escrow::confirm_completion(gateway, &escrow_gate(), &config).await?;
+            }
+            Ok::<_, Error>(result)
+        });
+        let query = memory.scope(query_progress.scope(query));
+
         // see private-attribution/ipa#1120
         let v = if !cfg!(feature = "shuttle")
             && Handle::current().runtime_flavor() == RuntimeFlavor::MultiThread
@@ -220,19 +505,50 @@ where
             block_in_place(|| {
                 // block_on runs on the current thread, so if it is also responsible for IO
                 // it's been handed off already by block_in_place.
-                Handle::current()
-                    .block_on(async { query_impl(&prss, gateway, &config, input_stream).await })
+                Handle::current().block_on(query)
             })
         } else {
-            query_impl(&prss, gateway, &config, input_stream).await
+            query.await
         };
 
+        // Queries can't run on two helpers, so the remaining peer learns right away that this
+        // one is over, instead of waiting on the unavailable helper.
+        let v = v.map_err(Error::blame_unavailable_helper);
+        if let Err(Error::HelperUnavailable(role)) = &v {
+            gateway.notify_peer_unavailable(*role).await;
+        }
+
+        // The report must be in place before the result is, so that whoever observes the
+        // result can also read the report.
+        let report = gateway
+            .tuning_report(start.elapsed())
+            .with_memory(memory.stages());
+        report.log(gateway.query_id());
+        *report_slot.lock().unwrap() = Some(report);
+
         tx.send(v).unwrap();
     });
 
     RunningQuery {
+        config,
+        leader,
         result: rx,
         join_handle,
+        tuning_report,
+        send_buffers,
+        progress,
+    }
+}
+
+/// Runs `query` while the watchdog keeps an eye on the send buffers of `gateway`.
+async fn watched<T>(
+    gateway: &Gateway,
+    send_buffers: &Mutex<Vec<SendBufferStatus>>,
+    query: impl Future<Output = T>,
+) -> T {
+    match select(pin!(query), pin!(gateway.watch_send_buffers(send_buffers))).await {
+        Either::Left((v, _)) => v,
+        Either::Right(((), _)) => unreachable!("send buffer watchdog never completes"),
     }
 }
 
@@ -248,6 +564,30 @@ fn prss_gate() -> Gate {
     ProtocolGate::default().narrow(&ProtocolStep::Prss)
 }
 
+#[cfg(descriptive_gate)]
+fn escrow_gate() -> Gate {
+    ipa_step::descriptive::Descriptive::default().narrow("escrow")
+}
+
+#[cfg(compact_gate)]
+fn escrow_gate() -> Gate {
+    use crate::protocol::step::{ProtocolGate, ProtocolStep};
+
+    ProtocolGate::default().narrow(&ProtocolStep::Escrow)
+}
+
+#[cfg(descriptive_gate)]
+fn sealed_input_gate() -> Gate {
+    ipa_step::descriptive::Descriptive::default().narrow("sealed_input")
+}
+
+#[cfg(compact_gate)]
+fn sealed_input_gate() -> Gate {
+    use crate::protocol::step::{ProtocolGate, ProtocolStep};
+
+    ProtocolGate::default().narrow(&ProtocolStep::SealedInput)
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
     use std::{array, future::Future, iter::zip, sync::Arc, time::Duration};
@@ -259,12 +599,20 @@ mod tests {
         executor::IpaRuntime,
         ff::{FieldType, Fp31, U128Conversions},
         helpers::{
-            query::{QueryConfig, QueryType},
+            query::{
+                HybridQueryParams, IpaQueryConfig, QueryConfig, QueryType, ShareConversionParams,
+            },
             BodyStream, Gateway, Role,
         },
-        query::{executor::do_query, state::RunningQuery, ProtocolResult},
+        hpke::{KeyRegistry, PrivateKeyOnly},
+        query::{
+            executor::{do_query, test_multiply},
+            state::RunningQuery,
+            ProtocolResult, QueryExecutors,
+        },
         secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
         test_fixture::TestWorld,
+        utils::rng::SystemRngProvider,
     };
 
     #[test]
@@ -280,6 +628,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn default_executors() {
+        let executors = QueryExecutors::<KeyRegistry<PrivateKeyOnly>>::default();
+        for query_type in [
+            QueryType::TestMultiply,
+            QueryType::TestAddInPrimeField,
+            QueryType::TestShardedShuffle,
+            QueryType::TestShareConversion(ShareConversionParams::default()),
+            QueryType::SemiHonestOprfIpa(IpaQueryConfig::default()),
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+            QueryType::MaliciousHybrid(HybridQueryParams::default()),
+        ] {
+            assert!(executors.get(&query_type).is_some(), "{query_type:?}");
+        }
+
+        assert!(QueryExecutors::<KeyRegistry<PrivateKeyOnly>>::empty()
+            .get(&QueryType::TestMultiply)
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "executor for test-multiply is already registered")]
+    fn duplicate_executor() {
+        QueryExecutors::<KeyRegistry<PrivateKeyOnly>>::default().register(
+            QueryType::TEST_MULTIPLY_STR,
+            test_multiply::<KeyRegistry<PrivateKeyOnly>>,
+        );
+    }
+
     #[tokio::test]
     async fn does_not_block_tokio_runtime() {
         let world = Box::leak(Box::<TestWorld>::default());
@@ -385,6 +762,7 @@ mod tests {
             },
             gateway,
             BodyStream::empty(),
+            &SystemRngProvider,
             move |_, _, _, _| {
                 Box::pin(async move {
                     f().await;
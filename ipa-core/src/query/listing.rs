@@ -0,0 +1,104 @@
+//! Listing and pruning the queries a helper keeps track of.
+//!
+//! Helpers keep the results of a query until the report collector collects them. Results that are
+//! never collected, and queries that failed, would otherwise stay around for as long as the helper
+//! runs. Operators can list them and prune the ones they don't need anymore, and helpers keep at
+//! most [`DEFAULT_MAX_COMPLETED_QUERIES`] completed queries unless configured otherwise.
+
+use std::num::NonZeroUsize;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    helpers::{routing::RouteId, NoQueryId, NoStep, RouteParams},
+    protocol::QueryId,
+    query::{InputDeleted, QueryStatus},
+};
+
+/// Number of completed queries a helper keeps by default. Once there are more, the ones with the
+/// lowest ids are pruned.
+pub const DEFAULT_MAX_COMPLETED_QUERIES: usize = 64;
+
+/// Number of queries listed in a single page, unless the request asks for fewer.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Request to list the queries of a helper, ordered by id.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListQueries {
+    /// Only list queries in this state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<QueryStatus>,
+    /// Only list queries with a larger id. Used to get the next page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<QueryId>,
+    /// Largest number of queries to list, capped at [`MAX_PAGE_SIZE`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<NonZeroUsize>,
+}
+
+impl ListQueries {
+    #[must_use]
+    pub fn page_size(&self) -> usize {
+        self.limit
+            .map_or(MAX_PAGE_SIZE, |limit| limit.get().min(MAX_PAGE_SIZE))
+    }
+}
+
+impl RouteParams<RouteId, NoQueryId, NoStep> for ListQueries {
+    type Params = String;
+
+    fn resource_identifier(&self) -> RouteId {
+        RouteId::ListQueries
+    }
+
+    fn query_id(&self) -> NoQueryId {
+        NoQueryId
+    }
+
+    fn gate(&self) -> NoStep {
+        NoStep
+    }
+
+    fn extra(&self) -> Self::Params {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// A query, as listed by [`ListQueries`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuerySummary {
+    pub query_id: QueryId,
+    pub status: QueryStatus,
+    /// Whether the query completed with an error.
+    pub failed: bool,
+}
+
+/// A page of queries.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryList {
+    pub queries: Vec<QuerySummary>,
+    /// Value of [`ListQueries::after`] that gets the next page, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<QueryId>,
+}
+
+/// What was removed when a query was pruned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryPruned {
+    pub query_id: QueryId,
+    /// The data kept on disk for the input of the query.
+    pub input: InputDeleted,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PruneQueryError {
+    #[error("failed to prune a query: {0} does not exist")]
+    NoSuchQuery(QueryId),
+    #[error("query {query_id} can't be pruned while it is {status}")]
+    QueryInProgress {
+        query_id: QueryId,
+        status: QueryStatus,
+    },
+    #[error("failed to delete the input of a query: {0}")]
+    Io(#[from] std::io::Error),
+}
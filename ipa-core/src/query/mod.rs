@@ -1,14 +1,38 @@
+mod batches;
 mod completion;
+mod contributions;
+mod decryption;
+pub mod escrow;
 mod executor;
+mod listing;
+pub mod placement;
+pub mod prf_cache;
+mod privacy;
 mod processor;
+mod readiness;
 mod runner;
 mod state;
+mod workspace;
 
+pub use batches::{BatchError, MAX_INPUT_BATCHES};
 use completion::Handle as CompletionHandle;
-pub use executor::Result as ProtocolResult;
+pub(crate) use decryption::DecryptionFailures;
+pub use decryption::{DecryptionFailurePolicy, Quarantine};
+pub use executor::{QueryExecutor, QueryExecutors, QueryFuture, Result as ProtocolResult};
+pub use listing::{
+    ListQueries, PruneQueryError, QueryList, QueryPruned, QuerySummary,
+    DEFAULT_MAX_COMPLETED_QUERIES, MAX_PAGE_SIZE,
+};
+pub use prf_cache::PrfCache;
+pub use privacy::{PrivacyParams, Redaction, SensitiveField};
 pub use processor::{
-    NewQueryError, PrepareQueryError, Processor as QueryProcessor, QueryCompletionError,
-    QueryInputError, QueryKillStatus, QueryKilled, QueryStatusError,
+    DeleteInputError, NewQueryError, PrepareQueryError, Processor as QueryProcessor,
+    QueryCompletionError, QueryInputError, QueryKillStatus, QueryKilled, QueryStatusError,
+    QueryStatusReport,
 };
+pub use readiness::{Readiness, ReadinessCheck};
+#[cfg(all(feature = "embedded", feature = "in-memory-infra"))]
+pub(crate) use runner::dispatch_input_widths;
 pub use runner::OprfIpaQuery;
 pub use state::{min_status, QueryStatus};
+pub use workspace::{InputDeleted, InputRetention, Workspace};
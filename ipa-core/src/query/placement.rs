@@ -0,0 +1,510 @@
+//! Recommends which helper takes which role in a query, given the network between helpers.
+//!
+//! Most stages of a query are symmetric: every helper sends its peers as much as it receives
+//! from them, so they take as long whichever helper takes which role. A few stages are not:
+//!
+//! * Shuffles. `H1` sends its masked rows to `H2` while `H2` sends its own to `H3`, then `H2`
+//!   and `H3` swap the reshuffled rows. `H2` and `H3` exchange three times as much data as `H1`
+//!   sends, and `H3` never hears from `H1`. Inputs are shuffled before PRF evaluation, and
+//!   breakdown reveal aggregation shuffles the attributed values again.
+//! * The reveal of the masked match keys that PRF evaluation starts from. It is hidden from
+//!   `H3`, so nothing is sent to it.
+//!
+//! [`asymmetric_stages`] describes how much these stages send from one role to another. Given
+//! [`NetworkMeasurements`] of the links between helpers, [`recommend`] estimates how long they
+//! take under every role assignment. Symmetric stages are left out of the estimate, as the
+//! measurements of a link hold for both of its directions.
+
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ff::{
+        boolean_array::{BA256, BA3, BA8},
+        Gf32Bit,
+    },
+    helpers::{
+        query::{QueryConfig, QueryType},
+        HelperIdentity, Role, RoleAssignment,
+    },
+    protocol::ipa_prf::{AggregationMethod, MatchKey},
+    secret_sharing::SharedValue,
+};
+
+/// Round trip time and bandwidth of the link between two helpers, as measured by their
+/// operators.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkMeasurement {
+    pub helpers: [HelperIdentity; 2],
+    /// Round trip time, in milliseconds.
+    pub rtt_ms: f64,
+    /// Bandwidth available to helpers in each direction of the link, in megabits per second.
+    pub bandwidth_mbps: f64,
+}
+
+impl LinkMeasurement {
+    fn transfer_time(&self, bytes: u64) -> Duration {
+        #[allow(clippy::cast_precision_loss)]
+        let bits = (bytes * 8) as f64;
+        Duration::from_secs_f64(self.rtt_ms / 2_000.0 + bits / (self.bandwidth_mbps * 1_000_000.0))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkMeasurementsError {
+    #[error("helper {} can't have a link to itself", u8::from(*.0))]
+    SameHelper(HelperIdentity),
+    #[error("link between helpers {} and {} is measured more than once", u8::from(*.0), u8::from(*.1))]
+    Duplicate(HelperIdentity, HelperIdentity),
+    #[error("link between helpers {} and {} is not measured", u8::from(*.0), u8::from(*.1))]
+    Missing(HelperIdentity, HelperIdentity),
+    #[error(
+        "link between helpers {} and {} needs a non-negative RTT and a positive bandwidth",
+        u8::from(*.0),
+        u8::from(*.1)
+    )]
+    InvalidValue(HelperIdentity, HelperIdentity),
+}
+
+/// Measurements of the three links between helpers. They are read from TOML files that list
+/// every link in a `[[link]]` table:
+///
+/// ```toml
+/// [[link]]
+/// helpers = [1, 2]
+/// rtt_ms = 80.0
+/// bandwidth_mbps = 1000.0
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "Links")]
+pub struct NetworkMeasurements {
+    // Indexed by both helpers of a link, in either order.
+    links: [[Option<LinkMeasurement>; 3]; 3],
+}
+
+#[derive(Deserialize)]
+struct Links {
+    link: Vec<LinkMeasurement>,
+}
+
+impl TryFrom<Links> for NetworkMeasurements {
+    type Error = NetworkMeasurementsError;
+
+    fn try_from(value: Links) -> Result<Self, Self::Error> {
+        Self::new(value.link)
+    }
+}
+
+impl NetworkMeasurements {
+    /// Creates measurements of the network from the measurements of its links.
+    ///
+    /// ## Errors
+    /// If a link is measured more than once or not at all, or its measurements are not valid.
+    pub fn new<I: IntoIterator<Item = LinkMeasurement>>(
+        links: I,
+    ) -> Result<Self, NetworkMeasurementsError> {
+        let mut network = Self {
+            links: [[None; 3]; 3],
+        };
+        for link in links {
+            let [a, b] = link.helpers;
+            if a == b {
+                return Err(NetworkMeasurementsError::SameHelper(a));
+            }
+            if network.links[a][b].is_some() {
+                return Err(NetworkMeasurementsError::Duplicate(a, b));
+            }
+            if !(link.rtt_ms.is_finite() && link.rtt_ms >= 0.0)
+                || !(link.bandwidth_mbps.is_finite() && link.bandwidth_mbps > 0.0)
+            {
+                return Err(NetworkMeasurementsError::InvalidValue(a, b));
+            }
+            network.links[a][b] = Some(link);
+            network.links[b][a] = Some(link);
+        }
+        for a in HelperIdentity::make_three() {
+            for b in a.others() {
+                if network.links[a][b].is_none() {
+                    return Err(NetworkMeasurementsError::Missing(a, b));
+                }
+            }
+        }
+
+        Ok(network)
+    }
+
+    fn link(&self, a: HelperIdentity, b: HelperIdentity) -> &LinkMeasurement {
+        self.links[a][b].as_ref().expect("all links are measured")
+    }
+}
+
+/// Bytes one role sends to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub from: Role,
+    pub to: Role,
+    pub bytes: u64,
+}
+
+/// A stage of a query that sends different amounts of data between different pairs of roles.
+/// Its rounds run one after another, while the transfers of a round run concurrently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stage {
+    pub name: &'static str,
+    pub rounds: Vec<Vec<Transfer>>,
+}
+
+impl Stage {
+    fn shuffle(name: &'static str, rows: u64, row_bytes: u64, malicious: bool) -> Self {
+        use Role::{H1, H2, H3};
+
+        let row_bytes = if malicious {
+            row_bytes + u64::from(Gf32Bit::BITS) / 8
+        } else {
+            row_bytes
+        };
+        let bytes = rows * row_bytes;
+        let mut rounds = vec![
+            transfers(&[(H1, H2), (H2, H3)], bytes),
+            transfers(&[(H2, H3), (H3, H2)], bytes),
+        ];
+        if malicious {
+            // H2 and H3 hash the tags they see, and H1 compares the hashes.
+            rounds.push(transfers(&[(H2, H1), (H3, H1), (H3, H2)], 32));
+        }
+
+        Self { name, rounds }
+    }
+
+    fn match_key_reveal(rows: u64, malicious: bool) -> Self {
+        use Role::{H1, H2, H3};
+
+        // Revealed PRF inputs are compressed curve points, whatever the widths of the query.
+        let bytes = rows * u64::from(BA256::BITS) / 8;
+        // Every helper sends its share to its right peer, and in malicious queries to its left
+        // peer too, unless that peer is H3.
+        let pairs: &[_] = if malicious {
+            &[(H1, H2), (H2, H1), (H3, H1), (H3, H2)]
+        } else {
+            &[(H1, H2), (H3, H1)]
+        };
+        Self {
+            name: "match key reveal",
+            rounds: vec![transfers(pairs, bytes)],
+        }
+    }
+
+    /// Time it takes for this stage to run if helpers take roles as in `roles`.
+    fn estimate(&self, roles: &RoleAssignment, network: &NetworkMeasurements) -> Duration {
+        self.rounds
+            .iter()
+            .map(|round| {
+                round
+                    .iter()
+                    .map(|transfer| {
+                        network
+                            .link(roles.identity(transfer.from), roles.identity(transfer.to))
+                            .transfer_time(transfer.bytes)
+                    })
+                    .max()
+                    .unwrap_or_default()
+            })
+            .sum()
+    }
+}
+
+fn transfers(pairs: &[(Role, Role)], bytes: u64) -> Vec<Transfer> {
+    pairs
+        .iter()
+        .map(|&(from, to)| Transfer { from, to, bytes })
+        .collect()
+}
+
+/// Stages of a query whose run time depends on which helper takes which role. The model counts
+/// the records of the query, but not the dummy records DP padding adds to them. Rows are as wide
+/// as the fields the query config asks for, rounded up to whole bytes.
+#[must_use]
+pub fn asymmetric_stages(config: &QueryConfig) -> Vec<Stage> {
+    let rows = u64::from(u32::from(config.size));
+    // Attributed rows are only shuffled by breakdown reveal aggregation.
+    let (malicious, input_row_bits, attributed_row_bits) = match &config.query_type {
+        QueryType::SemiHonestOprfIpa(ipa_config) | QueryType::MaliciousOprfIpa(ipa_config) => {
+            let attributed_row_bits = ipa_config.breakdown_key_bits
+                + ipa_config.trigger_value_bits
+                + u32::from(ipa_config.prune_zero_rows);
+            (
+                matches!(config.query_type, QueryType::MaliciousOprfIpa(_)),
+                MatchKey::BITS
+                    + 1
+                    + ipa_config.breakdown_key_bits
+                    + ipa_config.trigger_value_bits
+                    + ipa_config.timestamp_bits,
+                (ipa_config.aggregation_method == AggregationMethod::BreakdownReveal)
+                    .then_some(attributed_row_bits),
+            )
+        }
+        // Hybrid queries take 8 bit breakdown keys and 3 bit values, whatever their config.
+        QueryType::MaliciousHybrid(_) => (
+            true,
+            MatchKey::BITS + BA8::BITS + BA3::BITS,
+            Some(BA8::BITS + BA3::BITS),
+        ),
+        #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+        QueryType::TestMultiply
+        | QueryType::TestAddInPrimeField
+        | QueryType::TestShardedShuffle
+        | QueryType::TestShareConversion(_) => return Vec::new(),
+    };
+    let row_bytes = |bits: u32| u64::from(bits.div_ceil(8));
+
+    let mut stages = vec![
+        Stage::shuffle("input shuffle", rows, row_bytes(input_row_bits), malicious),
+        Stage::match_key_reveal(rows, malicious),
+    ];
+    if let Some(bits) = attributed_row_bits {
+        stages.push(Stage::shuffle(
+            "attribution output shuffle",
+            rows,
+            row_bytes(bits),
+            malicious,
+        ));
+    }
+
+    stages
+}
+
+/// Estimated time of the asymmetric stages of a query under one role assignment.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    pub roles: RoleAssignment,
+    pub stages: Vec<(&'static str, Duration)>,
+}
+
+impl Plan {
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, time)| *time).sum()
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for role in Role::all() {
+            write!(
+                f,
+                "{role}: helper {}, ",
+                u8::from(self.roles.identity(*role))
+            )?;
+        }
+        write!(f, "estimated {:.3?}", self.total())?;
+        for (i, (name, time)) in self.stages.iter().enumerate() {
+            let separator = if i == 0 { " (" } else { ", " };
+            write!(f, "{separator}{name} {time:.3?}")?;
+        }
+        if !self.stages.is_empty() {
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the asymmetric stages of a query under every role assignment. Returns the plans
+/// from the fastest to the slowest one. Among equally fast plans, the ones where every helper
+/// has the next one on its right come first, which is how helpers assign roles by default.
+#[must_use]
+pub fn recommend(config: &QueryConfig, network: &NetworkMeasurements) -> Vec<Plan> {
+    let stages = asymmetric_stages(config);
+    let mut plans = all_assignments()
+        .map(|roles| Plan {
+            stages: stages
+                .iter()
+                .map(|stage| (stage.name, stage.estimate(&roles, network)))
+                .collect(),
+            roles,
+        })
+        .collect::<Vec<_>>();
+    plans.sort_by_key(Plan::total);
+
+    plans
+}
+
+/// Same as [`recommend`], but only considers plans where `leader` takes [`Role::H1`], which is
+/// the role of the helper that creates a query. Returns the fastest of them.
+///
+/// ## Panics
+/// If there is no such plan, which can't happen.
+#[must_use]
+pub fn recommend_led_by(
+    leader: HelperIdentity,
+    config: &QueryConfig,
+    network: &NetworkMeasurements,
+) -> Plan {
+    recommend(config, network)
+        .into_iter()
+        .find(|plan| plan.roles.role(leader) == Role::H1)
+        .expect("every helper can take H1")
+}
+
+fn all_assignments() -> impl Iterator<Item = RoleAssignment> {
+    HelperIdentity::make_three().into_iter().flat_map(|h1| {
+        let [right, left] = h1.others();
+        [[h1, right, left], [h1, left, right]].map(RoleAssignment::new)
+    })
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::*;
+    use crate::helpers::query::IpaQueryConfig;
+
+    fn link(a: i32, b: i32, rtt_ms: f64, bandwidth_mbps: f64) -> LinkMeasurement {
+        LinkMeasurement {
+            helpers: [HelperIdentity::from(a), HelperIdentity::from(b)],
+            rtt_ms,
+            bandwidth_mbps,
+        }
+    }
+
+    fn ipa_config(size: u32) -> QueryConfig {
+        QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+            crate::ff::FieldType::Fp32BitPrime,
+            size,
+        )
+        .unwrap()
+    }
+
+    /// The link between helpers 1 and 2 is slow.
+    fn network() -> NetworkMeasurements {
+        NetworkMeasurements::new([
+            link(1, 2, 150.0, 100.0),
+            link(2, 3, 10.0, 1_000.0),
+            link(3, 1, 10.0, 1_000.0),
+        ])
+        .unwrap()
+    }
+
+    fn roles(plan: &Plan) -> [HelperIdentity; 3] {
+        Role::all().map(|role| plan.roles.identity(role))
+    }
+
+    #[test]
+    fn same_links_keep_default_roles() {
+        let network = NetworkMeasurements::new([
+            link(1, 2, 10.0, 1_000.0),
+            link(2, 3, 10.0, 1_000.0),
+            link(1, 3, 10.0, 1_000.0),
+        ])
+        .unwrap();
+        let plans = recommend(&ipa_config(1_000), &network);
+        assert_eq!(6, plans.len());
+        assert!(plans.iter().all(|plan| plan.total() == plans[0].total()));
+        assert_eq!(HelperIdentity::make_three(), roles(&plans[0]));
+
+        let plan = recommend_led_by(HelperIdentity::TWO, &ipa_config(1_000), &network);
+        assert_eq!(
+            [
+                HelperIdentity::TWO,
+                HelperIdentity::THREE,
+                HelperIdentity::ONE
+            ],
+            roles(&plan)
+        );
+    }
+
+    #[test]
+    fn slow_link_carries_least_traffic() {
+        let plans = recommend(&ipa_config(1_000_000), &network());
+        // Only the match key reveal goes between H3 and H1.
+        let [h1, _, h3] = roles(&plans[0]);
+        let mut pair = [h1, h3];
+        pair.sort();
+        assert_eq!([HelperIdentity::ONE, HelperIdentity::TWO], pair);
+        assert!(plans[0].total() < plans[5].total());
+    }
+
+    #[test]
+    fn leader_takes_h1() {
+        let plan = recommend_led_by(HelperIdentity::ONE, &ipa_config(1_000_000), &network());
+        assert_eq!(
+            [
+                HelperIdentity::ONE,
+                HelperIdentity::THREE,
+                HelperIdentity::TWO
+            ],
+            roles(&plan)
+        );
+    }
+
+    #[test]
+    fn row_sizes_follow_config() {
+        fn input_shuffle_bytes(config: IpaQueryConfig) -> u64 {
+            let config = QueryConfig::new(
+                QueryType::SemiHonestOprfIpa(config),
+                crate::ff::FieldType::Fp32BitPrime,
+                10,
+            )
+            .unwrap();
+            asymmetric_stages(&config)[0].rounds[0][0].bytes
+        }
+
+        // 64 bit match key, trigger bit, 8 bit breakdown key, 3 bit value and 20 bit timestamp
+        assert_eq!(10 * 12, input_shuffle_bytes(IpaQueryConfig::default()));
+        assert_eq!(
+            10 * 14,
+            input_shuffle_bytes(IpaQueryConfig {
+                trigger_value_bits: 16,
+                ..IpaQueryConfig::default()
+            })
+        );
+    }
+
+    #[test]
+    fn no_asymmetric_stages() {
+        let config =
+            QueryConfig::new(QueryType::TestMultiply, crate::ff::FieldType::Fp31, 1_000).unwrap();
+        assert!(asymmetric_stages(&config).is_empty());
+        let plan = recommend_led_by(HelperIdentity::THREE, &config, &network());
+        assert_eq!(Duration::ZERO, plan.total());
+        assert_eq!(
+            [
+                HelperIdentity::THREE,
+                HelperIdentity::ONE,
+                HelperIdentity::TWO
+            ],
+            roles(&plan)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_measurements() {
+        assert!(matches!(
+            NetworkMeasurements::new([link(1, 2, 10.0, 1.0), link(2, 3, 10.0, 1.0)]),
+            Err(NetworkMeasurementsError::Missing(..))
+        ));
+        assert!(matches!(
+            NetworkMeasurements::new([
+                link(1, 2, 10.0, 1.0),
+                link(2, 3, 10.0, 1.0),
+                link(2, 1, 10.0, 1.0),
+            ]),
+            Err(NetworkMeasurementsError::Duplicate(..))
+        ));
+        assert!(matches!(
+            NetworkMeasurements::new([link(1, 1, 10.0, 1.0)]),
+            Err(NetworkMeasurementsError::SameHelper(_))
+        ));
+        assert!(matches!(
+            NetworkMeasurements::new([link(1, 2, 10.0, 0.0)]),
+            Err(NetworkMeasurementsError::InvalidValue(..))
+        ));
+        assert!(matches!(
+            NetworkMeasurements::new([link(1, 2, f64::NAN, 1.0)]),
+            Err(NetworkMeasurementsError::InvalidValue(..))
+        ));
+    }
+}
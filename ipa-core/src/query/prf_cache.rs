@@ -0,0 +1,793 @@
+//! Cache of PRF'd query inputs.
+//!
+//! Report collectors often run several queries over the same reports, with different caps,
+//! attribution windows or DP parameters. Padding, shuffling and PRF evaluation only depend on the
+//! reports, yet they are the most expensive stages of OPRF IPA. Queries that set
+//! [`IpaQueryConfig::cache_prf`] let helpers keep the output of these stages in a cache, keyed by
+//! the digest of the decrypted input of each helper, so that the next query over the same reports
+//! resumes from there.
+//!
+//! Entries hold secret shares, so they are encrypted at rest with AES-256 in counter mode and
+//! authenticated with HMAC-SHA256, under keys derived from a secret of the helper. An entry is
+//! only used if all three helpers have one for the same query run, which they check by comparing
+//! digests of the revealed PRF values before anything else happens. Entries that fail
+//! authentication or can't be parsed are removed.
+//!
+//! Every query that asks for the cache is charged its epsilon against the input, whether the
+//! entry is used or not, and helpers refuse to run such queries once the total exceeds the
+//! budget configured for the cache. Queries without DP noise are not charged, helpers that
+//! want to refuse them should set a minimum epsilon in their [`QueryPolicy`]. The budget only
+//! covers queries that ask for the cache: the helper can't recognize the reports of queries
+//! that do not.
+//!
+//! Queries that report aggregates per source site are charged against the budget of each site
+//! present in their input instead, as the reports of different sites are disjoint. Queries over
+//! the whole input are charged against all sites at once, so they are checked against the site
+//! that spent the most.
+//!
+//! Entries are linked to the last query that used them, so that they are deleted with the rest
+//! of the data of that query, see [`Workspace`]. Their budget is kept.
+//!
+//! [`IpaQueryConfig::cache_prf`]: crate::helpers::query::IpaQueryConfig::cache_prf
+//! [`QueryPolicy`]: crate::helpers::query::QueryPolicy
+//! [`Workspace`]: crate::query::Workspace
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use aes::{
+    cipher::{BlockEncrypt, KeyInit},
+    Aes256,
+};
+use futures::future::try_join;
+use generic_array::GenericArray;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use typenum::Unsigned;
+
+use crate::{
+    error::Error,
+    ff::{boolean::Boolean, boolean_array::BA64, Serializable, U128Conversions},
+    helpers::{Direction, TotalRecords},
+    protocol::{
+        context::Context,
+        ipa_prf::{
+            oprf_padding::PaddingParameters, prf_sharding::PrfShardedIpaInputRow, OPRFIPAInputRow,
+        },
+        QueryId, RecordId,
+    },
+    query::workspace::remove_if_exists,
+    rand::{thread_rng, RngCore},
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LEN: usize = 8;
+const MAC_LEN: usize = 32;
+
+/// Digest of the decrypted input of a query on one helper, together with everything else the
+/// cached stages depend on. Entries are looked up by it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputDigest([u8; 32]);
+
+impl InputDigest {
+    #[must_use]
+    pub fn new<BK, TV, TS>(
+        rows: &[OPRFIPAInputRow<BK, TV, TS>],
+        padding_params: &PaddingParameters,
+    ) -> Self
+    where
+        BK: SharedValue,
+        TV: SharedValue,
+        TS: SharedValue,
+        OPRFIPAInputRow<BK, TV, TS>: Serializable,
+    {
+        let mut hasher = Sha256::new();
+        for bits in [BK::BITS, TV::BITS, TS::BITS] {
+            hasher.update(bits.to_le_bytes());
+        }
+        hasher.update(format!("{padding_params:?}"));
+        let mut buf = GenericArray::default();
+        for row in rows {
+            row.serialize(&mut buf);
+            hasher.update(&buf);
+        }
+
+        let mut digest = [0; 32];
+        digest.copy_from_slice(&hasher.finalize());
+        Self(digest)
+    }
+
+    fn file_name(&self, extension: &str) -> String {
+        format!("{}.{extension}", hex::encode(self.0))
+    }
+}
+
+/// Where a helper stands on the cache entry for a query, as exchanged with the other helpers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vote {
+    /// There is no usable entry.
+    Miss,
+    /// The query would exceed the budget of the input.
+    Exhausted,
+    /// There is an entry, identified by the digest of its PRF values.
+    Hit(u64),
+}
+
+impl Vote {
+    fn to_u64(self) -> u64 {
+        match self {
+            Self::Miss => 0,
+            Self::Exhausted => 1,
+            Self::Hit(tag) => tag,
+        }
+    }
+
+    fn from_u64(value: u64) -> Self {
+        match value {
+            0 => Self::Miss,
+            1 => Self::Exhausted,
+            tag => Self::Hit(tag),
+        }
+    }
+}
+
+/// Result of looking up an entry.
+pub enum Lookup<BK: SharedValue, TV: SharedValue, TS: SharedValue> {
+    Miss,
+    Exhausted,
+    Hit(Vec<PrfShardedIpaInputRow<BK, TV, TS>>),
+}
+
+impl<BK: SharedValue, TV: SharedValue, TS: SharedValue> Lookup<BK, TV, TS> {
+    #[must_use]
+    pub fn vote(&self) -> Vote {
+        match self {
+            Self::Miss => Vote::Miss,
+            Self::Exhausted => Vote::Exhausted,
+            Self::Hit(rows) => Vote::Hit(tag(rows)),
+        }
+    }
+}
+
+/// Part of an input a query spends its budget on.
+#[derive(Clone, Copy, Debug)]
+pub enum BudgetScope<'a> {
+    /// The whole input.
+    Input,
+    /// The reports of the given source sites, for queries that aggregate them separately.
+    Sites(&'a BTreeSet<u8>),
+}
+
+/// DP budget spent on one input.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Ledger {
+    /// Spent by queries over the whole input.
+    epsilon_spent: f64,
+    /// Spent by per-site queries, on the reports of each site.
+    #[serde(default)]
+    site_epsilon_spent: BTreeMap<u8, f64>,
+}
+
+impl Ledger {
+    /// Epsilon that the reports in `scope` spent so far.
+    fn spent(&self, scope: BudgetScope) -> f64 {
+        let site = |site: &u8| self.site_epsilon_spent.get(site).copied().unwrap_or(0.0);
+        let sites = match scope {
+            BudgetScope::Input => self
+                .site_epsilon_spent
+                .values()
+                .copied()
+                .fold(0.0, f64::max),
+            BudgetScope::Sites(sites) => sites.iter().map(site).fold(0.0, f64::max),
+        };
+        self.epsilon_spent + sites
+    }
+
+    fn charge(&mut self, scope: BudgetScope, epsilon: f64) {
+        match scope {
+            BudgetScope::Input => self.epsilon_spent += epsilon,
+            BudgetScope::Sites(sites) => {
+                for &site in sites {
+                    *self.site_epsilon_spent.entry(site).or_default() += epsilon;
+                }
+            }
+        }
+    }
+}
+
+/// Directory where a helper keeps PRF'd query inputs, see the [module documentation].
+///
+/// [module documentation]: self
+pub struct PrfCache {
+    dir: PathBuf,
+    cipher: Aes256,
+    mac_key: [u8; 32],
+    epsilon_budget: f64,
+}
+
+impl PrfCache {
+    /// Creates a cache in `dir`. Entries are protected with keys derived from `secret`, which
+    /// must stay the same across restarts for entries to survive them. Queries are refused once
+    /// the epsilon they spent on the same input exceeds `epsilon_budget`.
+    #[must_use]
+    pub fn new(dir: PathBuf, secret: &[u8; 32], epsilon_budget: f64) -> Self {
+        let kdf = Hkdf::<Sha256>::new(None, secret);
+        let mut cipher_key = aes::cipher::generic_array::GenericArray::default();
+        kdf.expand(b"prf-cache-encryption", &mut cipher_key)
+            .unwrap();
+        let mut mac_key = [0; 32];
+        kdf.expand(b"prf-cache-authentication", &mut mac_key)
+            .unwrap();
+
+        Self {
+            dir,
+            cipher: Aes256::new(&cipher_key),
+            mac_key,
+            epsilon_budget,
+        }
+    }
+
+    /// Directory where the cache entries are kept.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Looks up the entry for `digest`, for a query that spends `epsilon` on `scope`. Entries
+    /// that can't be read are removed.
+    #[must_use]
+    pub fn lookup<BK, TV, TS>(
+        &self,
+        digest: &InputDigest,
+        scope: BudgetScope,
+        epsilon: f64,
+    ) -> Lookup<BK, TV, TS>
+    where
+        BK: SharedValue,
+        TV: SharedValue,
+        TS: SharedValue,
+        Replicated<BK>: Serializable,
+        Replicated<TV>: Serializable,
+        Replicated<TS>: Serializable,
+    {
+        let spent = self.ledger(digest).spent(scope);
+        if spent + epsilon > self.epsilon_budget {
+            tracing::warn!(
+                "query would spend {epsilon} on an input that already spent {spent}, more than \
+                 the budget of {}",
+                self.epsilon_budget
+            );
+            return Lookup::Exhausted;
+        }
+
+        let name = digest.file_name("rows");
+        let path = self.dir.join(&name);
+        let Some(sealed) = read_if_exists(&path) else {
+            return Lookup::Miss;
+        };
+        match self
+            .open(&name, &sealed)
+            .and_then(|bytes| decode_rows(&bytes))
+        {
+            Some(rows) => Lookup::Hit(rows),
+            None => {
+                tracing::warn!("removing PRF cache entry {name} that failed to authenticate");
+                if let Err(e) = fs::remove_file(&path) {
+                    tracing::warn!("failed to remove {}: {e}", path.display());
+                }
+                Lookup::Miss
+            }
+        }
+    }
+
+    /// Charges `epsilon` against the budget of `scope` of the input.
+    ///
+    /// ## Errors
+    /// If the ledger can't be written.
+    pub fn charge(&self, digest: &InputDigest, scope: BudgetScope, epsilon: f64) -> io::Result<()> {
+        let mut ledger = self.ledger(digest);
+        ledger.charge(scope, epsilon);
+        let name = digest.file_name("budget");
+        let sealed = self.seal(&name, serde_json::to_vec(&ledger)?);
+        fs::write(self.dir.join(name), sealed)
+    }
+
+    /// Stores `rows` as the entry for `digest`, replacing the existing one.
+    ///
+    /// ## Errors
+    /// If the entry can't be written.
+    pub fn store<BK, TV, TS>(
+        &self,
+        digest: &InputDigest,
+        rows: &[PrfShardedIpaInputRow<BK, TV, TS>],
+    ) -> io::Result<()>
+    where
+        BK: SharedValue,
+        TV: SharedValue,
+        TS: SharedValue,
+        Replicated<BK>: Serializable,
+        Replicated<TV>: Serializable,
+        Replicated<TS>: Serializable,
+    {
+        let name = digest.file_name("rows");
+        let sealed = self.seal(&name, encode_rows(rows));
+        // Readers never see a partially written entry.
+        let tmp = self.dir.join(format!("{name}.tmp"));
+        fs::write(&tmp, sealed)?;
+        fs::rename(tmp, self.dir.join(name))
+    }
+
+    /// Records that `query_id` used the entry for `digest`, so that it can be removed with
+    /// the rest of the query data.
+    ///
+    /// ## Errors
+    /// If the link can't be written.
+    pub fn link(&self, query_id: QueryId, digest: &InputDigest) -> io::Result<()> {
+        fs::write(self.link_path(query_id), hex::encode(digest.0))
+    }
+
+    /// Returns `true` if an entry is linked to `query_id`.
+    #[must_use]
+    pub fn is_linked(&self, query_id: QueryId) -> bool {
+        self.link_path(query_id).exists()
+    }
+
+    /// Removes the entry linked to `query_id`, returning whether there was one. The budget spent
+    /// on the input is kept.
+    ///
+    /// ## Errors
+    /// If the entry exists but can't be removed.
+    pub fn remove_for_query(&self, query_id: QueryId) -> io::Result<bool> {
+        let link = self.link_path(query_id);
+        let Some(contents) = read_if_exists(&link) else {
+            return Ok(false);
+        };
+        let mut digest = [0; 32];
+        let removed = if hex::decode_to_slice(&contents, &mut digest).is_ok() {
+            let name = InputDigest(digest).file_name("rows");
+            remove_if_exists(&self.dir.join(format!("{name}.tmp")))?;
+            remove_if_exists(&self.dir.join(name))?
+        } else {
+            tracing::warn!("PRF cache link {} is malformed", link.display());
+            false
+        };
+        remove_if_exists(&link)?;
+
+        Ok(removed)
+    }
+
+    fn link_path(&self, query_id: QueryId) -> PathBuf {
+        self.dir.join(format!("{query_id}.query"))
+    }
+
+    /// Budget spent on the input so far. Ledgers that fail to authenticate count as exhausted,
+    /// so that tampering with them can't reset the budget.
+    fn ledger(&self, digest: &InputDigest) -> Ledger {
+        let name = digest.file_name("budget");
+        let Some(sealed) = read_if_exists(&self.dir.join(&name)) else {
+            return Ledger::default();
+        };
+        self.open(&name, &sealed)
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_else(|| {
+                tracing::warn!("PRF cache ledger {name} failed to authenticate");
+                Ledger {
+                    epsilon_spent: f64::INFINITY,
+                    ..Ledger::default()
+                }
+            })
+    }
+
+    /// Encrypts and authenticates `plaintext`. `name` is covered by the MAC, so that sealed
+    /// files can't be swapped.
+    fn seal(&self, name: &str, mut plaintext: Vec<u8>) -> Vec<u8> {
+        let mut nonce = [0; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        self.apply_keystream(&nonce, &mut plaintext);
+        let mac = self.mac(name, &nonce, &plaintext).finalize().into_bytes();
+
+        [&nonce[..], &plaintext, &mac].concat()
+    }
+
+    /// Reverses [`Self::seal`], returning `None` if `sealed` fails to authenticate.
+    fn open(&self, name: &str, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + MAC_LEN {
+            return None;
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, mac) = rest.split_at(rest.len() - MAC_LEN);
+        self.mac(name, nonce, ciphertext).verify_slice(mac).ok()?;
+        let mut plaintext = ciphertext.to_vec();
+        self.apply_keystream(nonce.try_into().unwrap(), &mut plaintext);
+
+        Some(plaintext)
+    }
+
+    fn mac(&self, name: &str, nonce: &[u8], ciphertext: &[u8]) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.mac_key)
+            .expect("HMAC accepts keys of any size");
+        mac.update(&u64::try_from(name.len()).unwrap().to_be_bytes());
+        mac.update(name.as_bytes());
+        mac.update(nonce);
+        mac.update(ciphertext);
+        mac
+    }
+
+    /// AES-256 in counter mode, with the nonce in the first half of the counter block.
+    fn apply_keystream(&self, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(16).enumerate() {
+            let mut block = aes::Block::default();
+            block[..NONCE_LEN].copy_from_slice(nonce);
+            block[NONCE_LEN..].copy_from_slice(&u64::try_from(i).unwrap().to_be_bytes());
+            self.cipher.encrypt_block(&mut block);
+            for (byte, key) in chunk.iter_mut().zip(block) {
+                *byte ^= key;
+            }
+        }
+    }
+}
+
+/// Exchanges votes with the other helpers. Returns `true` if all of them have the same entry, in
+/// which case it can be used.
+///
+/// ## Errors
+/// If any helper voted [`Vote::Exhausted`], or if the exchange fails.
+pub async fn agree_on_entry<C: Context>(ctx: C, vote: Vote) -> Result<bool, Error> {
+    let ctx = ctx.set_total_records(TotalRecords::ONE);
+    let message = BA64::truncate_from(vote.to_u64());
+    let recv_left = ctx.recv_channel::<BA64>(ctx.role().peer(Direction::Left));
+    let recv_right = ctx.recv_channel::<BA64>(ctx.role().peer(Direction::Right));
+    let ((), (left, right)) = try_join(
+        ctx.broadcast_channel::<BA64>()
+            .broadcast(RecordId::FIRST, message),
+        try_join(
+            recv_left.receive(RecordId::FIRST),
+            recv_right.receive(RecordId::FIRST),
+        ),
+    )
+    .await?;
+
+    let received = |v: BA64| Vote::from_u64(v.as_u128().try_into().unwrap());
+    let votes = [vote, received(left), received(right)];
+    if votes.contains(&Vote::Exhausted) {
+        return Err(Error::InputBudgetExhausted);
+    }
+
+    Ok(matches!(votes[0], Vote::Hit(_)) && votes.iter().all(|v| *v == votes[0]))
+}
+
+/// Digest of the revealed PRF values of `rows`. These are the same on all helpers, so helpers
+/// that cached the same query run agree on it.
+fn tag<BK: SharedValue, TV: SharedValue, TS: SharedValue>(
+    rows: &[PrfShardedIpaInputRow<BK, TV, TS>],
+) -> u64 {
+    let mut hasher = Sha256::new();
+    for row in rows {
+        hasher.update(row.prf_of_match_key.to_le_bytes());
+    }
+    let digest = hasher.finalize();
+    // 0 and 1 are taken by the other votes.
+    u64::from_le_bytes(digest[..8].try_into().unwrap()).max(2)
+}
+
+fn row_len<BK, TV, TS>() -> usize
+where
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+{
+    size_of::<u64>()
+        + <Replicated<Boolean> as Serializable>::Size::USIZE
+        + <Replicated<BK> as Serializable>::Size::USIZE
+        + <Replicated<TV> as Serializable>::Size::USIZE
+        + <Replicated<TS> as Serializable>::Size::USIZE
+}
+
+/// Serializes `rows`, except for the sort keys, which are computed after the cached stages.
+fn encode_rows<BK, TV, TS>(rows: &[PrfShardedIpaInputRow<BK, TV, TS>]) -> Vec<u8>
+where
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+{
+    fn put<S: Serializable>(buf: &mut Vec<u8>, value: &S) {
+        let mut bytes = GenericArray::default();
+        value.serialize(&mut bytes);
+        buf.extend_from_slice(&bytes);
+    }
+
+    let mut buf = Vec::with_capacity(rows.len() * row_len::<BK, TV, TS>());
+    for row in rows {
+        buf.extend_from_slice(&row.prf_of_match_key.to_le_bytes());
+        put(&mut buf, &row.is_trigger_bit);
+        put(&mut buf, &row.breakdown_key);
+        put(&mut buf, &row.trigger_value);
+        put(&mut buf, &row.timestamp);
+    }
+
+    buf
+}
+
+/// Reverses [`encode_rows`], returning `None` if `bytes` are not a sequence of rows.
+fn decode_rows<BK, TV, TS>(bytes: &[u8]) -> Option<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>
+where
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+{
+    fn take<S: Serializable>(bytes: &mut &[u8]) -> Option<S> {
+        let (value, rest) = bytes.split_at(S::Size::USIZE);
+        *bytes = rest;
+        S::deserialize(GenericArray::from_slice(value)).ok()
+    }
+
+    let row_len = row_len::<BK, TV, TS>();
+    if bytes.len() % row_len != 0 {
+        return None;
+    }
+    bytes
+        .chunks_exact(row_len)
+        .map(|mut row| {
+            let (prf, rest) = row.split_at(size_of::<u64>());
+            row = rest;
+            Some(PrfShardedIpaInputRow {
+                prf_of_match_key: u64::from_le_bytes(prf.try_into().unwrap()),
+                is_trigger_bit: take(&mut row)?,
+                breakdown_key: take(&mut row)?,
+                trigger_value: take(&mut row)?,
+                timestamp: take(&mut row)?,
+                sort_key: Replicated::ZERO,
+            })
+        })
+        .collect()
+}
+
+fn read_if_exists(path: &Path) -> Option<Vec<u8>> {
+    match fs::read(path) {
+        Ok(bytes) => Some(bytes),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            tracing::warn!("failed to read {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{collections::BTreeSet, fs, path::Path};
+
+    use super::{decode_rows, encode_rows, BudgetScope, InputDigest, Lookup, PrfCache, Vote};
+    use crate::{
+        ff::{
+            boolean::Boolean,
+            boolean_array::{BA20, BA3, BA5},
+            U128Conversions,
+        },
+        protocol::{
+            ipa_prf::{oprf_padding::PaddingParameters, prf_sharding::PrfShardedIpaInputRow},
+            QueryId,
+        },
+        secret_sharing::replicated::{
+            semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing,
+        },
+    };
+
+    type Row = PrfShardedIpaInputRow<BA5, BA3, BA20>;
+
+    fn rows() -> Vec<Row> {
+        (0_u64..5)
+            .map(|i| {
+                let i128 = u128::from(i);
+                Row {
+                    prf_of_match_key: i * 31,
+                    is_trigger_bit: Replicated::new(Boolean::from(i % 2 == 0), Boolean::ZERO),
+                    breakdown_key: Replicated::new(BA5::truncate_from(i128), BA5::ZERO),
+                    trigger_value: Replicated::new(BA3::ZERO, BA3::truncate_from(i128)),
+                    timestamp: Replicated::new(
+                        BA20::truncate_from(i128 * 7),
+                        BA20::truncate_from(i128),
+                    ),
+                    sort_key: Replicated::ZERO,
+                }
+            })
+            .collect()
+    }
+
+    fn digest() -> InputDigest {
+        InputDigest([7; 32])
+    }
+
+    fn cache(dir: &Path, budget: f64) -> PrfCache {
+        PrfCache::new(dir.to_path_buf(), &[1; 32], budget)
+    }
+
+    fn assert_rows_eq(expected: &[Row], actual: &[Row]) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert_eq!(e.prf_of_match_key, a.prf_of_match_key);
+            assert_eq!(e.is_trigger_bit, a.is_trigger_bit);
+            assert_eq!(e.breakdown_key, a.breakdown_key);
+            assert_eq!(e.trigger_value, a.trigger_value);
+            assert_eq!(e.timestamp, a.timestamp);
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let rows = rows();
+        let decoded = decode_rows::<BA5, BA3, BA20>(&encode_rows(&rows)).unwrap();
+        assert_rows_eq(&rows, &decoded);
+        assert!(decode_rows::<BA5, BA3, BA20>(&encode_rows(&rows)[1..]).is_none());
+    }
+
+    #[test]
+    fn store_and_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1.0);
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5),
+            Lookup::Miss
+        ));
+
+        cache.store(&digest(), &rows()).unwrap();
+        let Lookup::Hit(cached) =
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5)
+        else {
+            panic!("expected a hit");
+        };
+        assert_rows_eq(&rows(), &cached);
+        // The entry is encrypted.
+        let file = fs::read(dir.path().join(digest().file_name("rows"))).unwrap();
+        assert!(!file.windows(8).any(|w| w == (4_u64 * 31).to_le_bytes()));
+    }
+
+    #[test]
+    fn entries_depend_on_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        cache(dir.path(), 1.0).store(&digest(), &rows()).unwrap();
+        let other = PrfCache::new(dir.path().to_path_buf(), &[2; 32], 1.0);
+        assert!(matches!(
+            other.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5),
+            Lookup::Miss
+        ));
+    }
+
+    #[test]
+    fn removes_tampered_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1.0);
+        cache.store(&digest(), &rows()).unwrap();
+        let path = dir.path().join(digest().file_name("rows"));
+        let mut file = fs::read(&path).unwrap();
+        file[10] ^= 1;
+        fs::write(&path, file).unwrap();
+
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5),
+            Lookup::Miss
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn enforces_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1.0);
+        cache.store(&digest(), &rows()).unwrap();
+        cache.charge(&digest(), BudgetScope::Input, 0.75).unwrap();
+
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.25),
+            Lookup::Hit(_)
+        ));
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5),
+            Lookup::Exhausted
+        ));
+    }
+
+    #[test]
+    fn enforces_site_budgets() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1.0);
+        cache.store(&digest(), &rows()).unwrap();
+        let site_0 = BTreeSet::from([0]);
+        let site_1 = BTreeSet::from([1]);
+        let both = BTreeSet::from([0, 1]);
+        cache.charge(&digest(), BudgetScope::Input, 0.25).unwrap();
+        cache
+            .charge(&digest(), BudgetScope::Sites(&site_0), 0.5)
+            .unwrap();
+
+        // Sites spend their budgets independently.
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Sites(&site_1), 0.75),
+            Lookup::Hit(_)
+        ));
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Sites(&site_0), 0.5),
+            Lookup::Exhausted
+        ));
+        // Queries that cover site 0 are limited by it.
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Sites(&both), 0.5),
+            Lookup::Exhausted
+        ));
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5),
+            Lookup::Exhausted
+        ));
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.25),
+            Lookup::Hit(_)
+        ));
+    }
+
+    #[test]
+    fn tampered_ledger_exhausts_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1.0);
+        cache.charge(&digest(), BudgetScope::Input, 0.1).unwrap();
+        fs::write(dir.path().join(digest().file_name("budget")), b"{}").unwrap();
+
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.0),
+            Lookup::Exhausted
+        ));
+    }
+
+    #[test]
+    fn remove_for_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1.0);
+        assert!(!cache.remove_for_query(QueryId).unwrap());
+
+        cache.store(&digest(), &rows()).unwrap();
+        cache.charge(&digest(), BudgetScope::Input, 0.75).unwrap();
+        cache.link(QueryId, &digest()).unwrap();
+        assert!(cache.remove_for_query(QueryId).unwrap());
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.25),
+            Lookup::Miss
+        ));
+        // The budget outlives the entry.
+        assert!(matches!(
+            cache.lookup::<BA5, BA3, BA20>(&digest(), BudgetScope::Input, 0.5),
+            Lookup::Exhausted
+        ));
+        assert!(!cache.remove_for_query(QueryId).unwrap());
+    }
+
+    #[test]
+    fn digest_covers_layout() {
+        let padding = PaddingParameters::default();
+        assert_ne!(
+            InputDigest::new::<BA5, BA3, BA20>(&[], &padding),
+            InputDigest::new::<BA5, BA3, crate::ff::boolean_array::BA24>(&[], &padding),
+        );
+    }
+
+    #[test]
+    fn votes() {
+        for vote in [Vote::Miss, Vote::Exhausted, Vote::Hit(12345)] {
+            assert_eq!(vote, Vote::from_u64(vote.to_u64()));
+        }
+    }
+}
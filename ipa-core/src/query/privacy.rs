@@ -0,0 +1,351 @@
+//! Privacy-relevant parameters of a query, in a form suitable for auditing.
+//!
+//! Every helper logs these parameters when it starts running a query and reports them in the
+//! query status. All helpers derive them from the same [`QueryConfig`], so auditors can compare
+//! the records emitted by the three helpers and check that they agreed on the privacy
+//! guarantees of the query.
+//!
+//! Some parameters are not needed to reason about privacy, but reveal business information
+//! (like the number of reports a site submits). These are [`SensitiveField`]s and are
+//! redacted according to the helper's [`Redaction`] policy.
+
+use std::{collections::BTreeSet, num::NonZeroU32};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ff::FieldType,
+    helpers::query::{QueryConfig, QueryType},
+    protocol::{
+        ipa_prf::{
+            oprf_padding::PaddingParameters, prf_sharding::credit_capping::CappingStrategy,
+            AggregationMethod, TimestampBounds,
+        },
+        QueryId,
+    },
+    report::SiteDomainHash,
+};
+
+/// Parameters that are not relevant for privacy, but may be operationally sensitive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum SensitiveField {
+    /// Number of input reports.
+    QuerySize,
+    /// Digest of the site the reports were collected for.
+    SiteDomain,
+}
+
+impl SensitiveField {
+    pub const ALL: [Self; 2] = [Self::QuerySize, Self::SiteDomain];
+}
+
+/// Set of [`SensitiveField`]s to leave out of [`PrivacyParams`]. By default, all of them
+/// are redacted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redaction(BTreeSet<SensitiveField>);
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Redaction {
+    #[must_use]
+    pub fn all() -> Self {
+        Self::fields(SensitiveField::ALL)
+    }
+
+    #[must_use]
+    pub fn none() -> Self {
+        Self(BTreeSet::new())
+    }
+
+    #[must_use]
+    pub fn fields<I: IntoIterator<Item = SensitiveField>>(fields: I) -> Self {
+        Self(fields.into_iter().collect())
+    }
+
+    pub(super) fn apply<T>(&self, field: SensitiveField, value: T) -> Option<T> {
+        (!self.0.contains(&field)).then_some(value)
+    }
+}
+
+/// Privacy-relevant parameters of a query. Parameters that do not apply to the query type are
+/// `None`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PrivacyParams {
+    pub query_type: String,
+    pub field_type: FieldType,
+    /// Privacy budget spent by the query, or `None` if no DP noise is added to its output.
+    /// This is what a single user can lose: for queries split by public tags, it is the budget
+    /// of every sub-query times the number of tags. Sampling users does not reduce it, see
+    /// [`UserSampling`].
+    ///
+    /// [`UserSampling`]: crate::protocol::ipa_prf::UserSampling
+    pub epsilon: Option<f64>,
+    /// Share of users included in the output, if the query samples users.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_sampling_rate: Option<f64>,
+    /// If set, helpers zero the histograms they could not add noise to instead of failing the
+    /// query. Nothing is released without noise.
+    pub allow_partial_results: bool,
+    /// Per-user cap, or `None` if the query does not cap user contributions.
+    pub per_user_credit_cap: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_source_event_cap: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_source_trigger_limit: Option<u32>,
+    pub max_breakdown_key: Option<u32>,
+    pub attribution_window_seconds: Option<NonZeroU32>,
+    pub trigger_value_bits: Option<u32>,
+    pub attributed_counts: bool,
+    /// Number of buckets of the time-to-conversion histogram, if the query releases one. It
+    /// takes a share of the DP budget like any other histogram.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_conversion_buckets: Option<u32>,
+    /// Range of timestamps the query covers, if it declares one. The number of trigger events
+    /// outside of it is released as another histogram, which takes a share of the DP budget.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_bounds: Option<TimestampBounds>,
+    /// Number of source sites, if the query releases aggregates per site. Each site spends the
+    /// full budget of the query on its own reports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_sites: Option<u32>,
+    /// Number of public tags, if the input is split into sub-queries by tag. Each sub-query
+    /// spends the full budget of the query, including on users with reports under several tags,
+    /// which [`Self::epsilon`] accounts for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_tags: Option<u32>,
+    /// If set, trigger values and the output histogram are signed.
+    #[serde(default)]
+    pub signed_trigger_values: bool,
+    /// How contributions over the per-user cap are reduced.
+    #[serde(default)]
+    pub capping_strategy: CappingStrategy,
+    /// Oblivious aggregation does not reveal breakdown keys, even after shuffling and padding.
+    #[serde(default)]
+    pub aggregation_method: AggregationMethod,
+    /// If set, the number of attribution outputs with credit per breakdown key is revealed,
+    /// protected by aggregation padding only.
+    #[serde(default)]
+    pub prune_zero_rows: bool,
+    pub plaintext_match_keys: bool,
+    /// DP parameters of the dummy records added to the input before the PRF and to the
+    /// attribution outputs before aggregation. Padding hides how many reports users have and
+    /// how many outputs each breakdown gets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<PaddingParameters>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_size: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site_domain_hash: Option<SiteDomainHash>,
+    /// Fields that were left out by the helper's [`Redaction`] policy.
+    #[serde(default)]
+    pub redacted: BTreeSet<SensitiveField>,
+}
+
+impl PrivacyParams {
+    #[must_use]
+    pub fn new(config: &QueryConfig, redaction: &Redaction) -> Self {
+        let mut this = Self {
+            query_type: config.query_type.as_ref().to_string(),
+            field_type: config.field_type,
+            epsilon: None,
+            user_sampling_rate: None,
+            allow_partial_results: false,
+            per_user_credit_cap: None,
+            per_source_event_cap: None,
+            per_source_trigger_limit: None,
+            max_breakdown_key: None,
+            attribution_window_seconds: None,
+            trigger_value_bits: None,
+            attributed_counts: false,
+            time_to_conversion_buckets: None,
+            timestamp_bounds: None,
+            source_sites: None,
+            public_tags: None,
+            signed_trigger_values: false,
+            capping_strategy: CappingStrategy::default(),
+            aggregation_method: AggregationMethod::default(),
+            prune_zero_rows: false,
+            plaintext_match_keys: false,
+            padding: None,
+            query_size: redaction.apply(SensitiveField::QuerySize, config.size.into()),
+            site_domain_hash: None,
+            redacted: redaction.0.clone(),
+        };
+
+        match config.query_type {
+            #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
+            QueryType::TestMultiply
+            | QueryType::TestAddInPrimeField
+            | QueryType::TestShardedShuffle
+            | QueryType::TestShareConversion(_) => {}
+            QueryType::SemiHonestOprfIpa(ipa) | QueryType::MaliciousOprfIpa(ipa) => {
+                this.epsilon = (ipa.with_dp != 0)
+                    .then(|| ipa.epsilon * f64::from(ipa.public_tags.unwrap_or(1)));
+                this.user_sampling_rate = ipa.user_sampling_rate;
+                this.allow_partial_results = ipa.allow_partial_results;
+                this.per_user_credit_cap =
+                    (ipa.per_user_credit_cap != 0).then_some(ipa.per_user_credit_cap);
+                this.per_source_event_cap = ipa.per_source_event_cap;
+                this.per_source_trigger_limit = ipa.per_source_trigger_limit;
+                this.max_breakdown_key = Some(ipa.max_breakdown_key);
+                this.attribution_window_seconds = ipa.attribution_window_seconds;
+                this.trigger_value_bits = Some(ipa.trigger_value_bits);
+                this.attributed_counts = ipa.attributed_counts;
+                this.time_to_conversion_buckets = ipa
+                    .time_to_conversion_bucket_seconds
+                    .map(|_| ipa.time_to_conversion_buckets);
+                this.timestamp_bounds = ipa.timestamp_bounds();
+                this.source_sites = ipa.source_sites;
+                this.public_tags = ipa.public_tags;
+                this.signed_trigger_values = ipa.signed_trigger_values;
+                this.capping_strategy = ipa.capping_strategy;
+                this.aggregation_method = ipa.aggregation_method;
+                this.prune_zero_rows = ipa.prune_zero_rows;
+                this.plaintext_match_keys = ipa.plaintext_match_keys;
+                this.padding = Some(PaddingParameters::for_queries());
+                this.site_domain_hash = ipa
+                    .site_domain_hash
+                    .and_then(|hash| redaction.apply(SensitiveField::SiteDomain, hash));
+            }
+            QueryType::MaliciousHybrid(hybrid) => {
+                this.epsilon = (hybrid.with_dp != 0).then_some(hybrid.epsilon);
+                this.max_breakdown_key = Some(hybrid.max_breakdown_key);
+                this.plaintext_match_keys = hybrid.plaintext_match_keys;
+            }
+        }
+
+        this
+    }
+
+    /// Emits the query start record for these parameters. The record is a single JSON object,
+    /// so that records from different helpers can be compared as is.
+    pub fn log_query_start(&self, query_id: QueryId) {
+        tracing::info!(
+            target: "ipa_core::query::privacy",
+            query_id = %query_id,
+            privacy_params = %serde_json::to_string(self).unwrap(),
+            "query started"
+        );
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::{PrivacyParams, Redaction, SensitiveField};
+    use crate::{
+        ff::FieldType,
+        helpers::query::{IpaQueryConfig, QueryConfig, QueryType},
+        protocol::ipa_prf::oprf_padding::PaddingParameters,
+        report::SiteDomainHash,
+    };
+
+    fn ipa_config() -> QueryConfig {
+        QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                per_user_credit_cap: 16,
+                attribution_window_seconds: NonZeroU32::new(86_400),
+                epsilon: 3.0,
+                site_domain_hash: Some(SiteDomainHash::of("example.com")),
+                ..IpaQueryConfig::default()
+            }),
+            FieldType::Fp32BitPrime,
+            1000,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ipa_params() {
+        let params = PrivacyParams::new(&ipa_config(), &Redaction::none());
+        assert_eq!(Some(3.0), params.epsilon);
+        assert_eq!(Some(16), params.per_user_credit_cap);
+        assert_eq!(NonZeroU32::new(86_400), params.attribution_window_seconds);
+        assert_eq!(Some(1000), params.query_size);
+        assert_eq!(Some(PaddingParameters::for_queries()), params.padding);
+        assert_eq!(
+            Some(SiteDomainHash::of("example.com")),
+            params.site_domain_hash
+        );
+        assert!(params.redacted.is_empty());
+    }
+
+    #[test]
+    fn no_dp() {
+        let mut config = ipa_config();
+        let QueryType::MaliciousOprfIpa(ref mut ipa) = config.query_type else {
+            unreachable!()
+        };
+        ipa.with_dp = 0;
+        assert_eq!(
+            None,
+            PrivacyParams::new(&config, &Redaction::none()).epsilon
+        );
+    }
+
+    #[test]
+    fn user_sampling() {
+        let mut config = ipa_config();
+        let QueryType::MaliciousOprfIpa(ref mut ipa) = config.query_type else {
+            unreachable!()
+        };
+        ipa.user_sampling_rate = Some(0.1);
+        let params = PrivacyParams::new(&config, &Redaction::none());
+        assert_eq!(Some(0.1), params.user_sampling_rate);
+        // Helpers see which users are sampled, so sampling does not amplify privacy.
+        assert_eq!(Some(3.0), params.epsilon);
+    }
+
+    #[test]
+    fn public_tags() {
+        let mut config = ipa_config();
+        let QueryType::MaliciousOprfIpa(ref mut ipa) = config.query_type else {
+            unreachable!()
+        };
+        ipa.public_tags = Some(4);
+        let params = PrivacyParams::new(&config, &Redaction::none());
+        assert_eq!(Some(4), params.public_tags);
+        // Every tag spends the full budget on users with reports under all of them.
+        assert_eq!(Some(12.0), params.epsilon);
+    }
+
+    #[test]
+    fn redacts_sensitive_fields() {
+        let params = PrivacyParams::new(&ipa_config(), &Redaction::default());
+        assert_eq!(None, params.query_size);
+        assert_eq!(None, params.site_domain_hash);
+        assert_eq!(Some(16), params.per_user_credit_cap);
+        assert_eq!(SensitiveField::ALL.into_iter().collect(), params.redacted);
+
+        let json = serde_json::to_value(&params).unwrap();
+        assert!(json.get("query_size").is_none());
+        assert_eq!(
+            serde_json::json!(["query_size", "site_domain"]),
+            json["redacted"]
+        );
+    }
+
+    #[test]
+    fn partial_redaction() {
+        let params = PrivacyParams::new(
+            &ipa_config(),
+            &Redaction::fields([SensitiveField::SiteDomain]),
+        );
+        assert_eq!(Some(1000), params.query_size);
+        assert_eq!(None, params.site_domain_hash);
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        let params = PrivacyParams::new(&ipa_config(), &Redaction::none());
+        let json = serde_json::to_string(&params).unwrap();
+        assert_eq!(params, serde_json::from_str(&json).unwrap());
+    }
+}
@@ -1,31 +1,49 @@
 use std::{
-    collections::hash_map::Entry,
+    collections::{hash_map::Entry, HashMap},
     fmt::{Debug, Formatter},
+    time::{Duration, Instant},
 };
 
-use futures::{future::try_join, stream};
-use serde::Serialize;
+use futures::{future::join, stream};
+use serde::{Deserialize, Serialize};
 
 use super::min_status;
 use crate::{
     error::Error as ProtocolError,
     executor::IpaRuntime,
     helpers::{
-        query::{CompareStatusRequest, PrepareQuery, QueryConfig, QueryInput},
+        query::{
+            AppendInput, CompareStatusRequest, CreateFromTemplate, PeerUnavailable, PrepareQuery,
+            QueryConfig, QueryInput, QueryPolicy, QueryTemplates, QueryType, SealInput,
+            TemplateCommand, TemplateError, TemplateList, TooManyChannels, ValidationReport,
+        },
         routing::RouteId,
-        BroadcastError, Gateway, GatewayConfig, MpcTransportError, MpcTransportImpl, Role,
-        RoleAssignment, ShardTransportError, ShardTransportImpl, Transport,
+        BodyStream, BroadcastError, Gateway, GatewayConfig, HelperIdentity, MpcTransportError,
+        MpcTransportImpl, Role, RoleAssignment, ShardTransportError, ShardTransportImpl,
+        StageConcurrency, Transport,
     },
     hpke::{KeyRegistry, PrivateKeyOnly},
-    protocol::QueryId,
+    protocol::{dp::NoiseReport, ProtocolVersion, QueryId},
     query::{
-        executor,
-        state::{QueryState, QueryStatus, RemoveQuery, RunningQueries, StateError},
-        CompletionHandle, ProtocolResult,
+        batches::{read_batch, BatchError, InputBatches},
+        contributions::{self, ContributionError, InputContributions},
+        executor::{self, QueryExecutor, QueryExecutors, SealedInput},
+        listing::{
+            ListQueries, PruneQueryError, QueryList, QueryPruned, QuerySummary,
+            DEFAULT_MAX_COMPLETED_QUERIES,
+        },
+        placement::{self, NetworkMeasurements},
+        state::{QueryState, QueryStatus, RemoveQuery, RunningQueries, RunningQuery, StateError},
+        CompletionHandle, InputDeleted, PrivacyParams, ProtocolResult, ReadinessCheck, Redaction,
+        SensitiveField, Workspace,
     },
     sharding::ShardIndex,
     sync::Arc,
-    utils::NonZeroU32PowerOfTwo,
+    telemetry::{progress::QueryEta, send_buffers::SendBufferStatus, tuning::TuningReport},
+    utils::{
+        rng::{CryptoRngProvider, SystemRngProvider},
+        NonZeroU32PowerOfTwo,
+    },
 };
 
 /// [`Processor`] accepts and tracks requests to initiate new queries on this helper party
@@ -53,8 +71,18 @@ use crate::{
 pub struct Processor {
     queries: RunningQueries,
     key_registry: Arc<KeyRegistry<PrivateKeyOnly>>,
+    executors: QueryExecutors<KeyRegistry<PrivateKeyOnly>>,
+    redaction: Redaction,
+    policy: QueryPolicy,
+    templates: QueryTemplates,
     active_work: Option<NonZeroU32PowerOfTwo>,
+    stage_concurrency: StageConcurrency,
     runtime: IpaRuntime,
+    rng_provider: Arc<dyn CryptoRngProvider>,
+    workspace: Workspace,
+    max_completed_queries: usize,
+    network: Option<NetworkMeasurements>,
+    peer_timeout: Option<Duration>,
 }
 
 impl Default for Processor {
@@ -62,8 +90,18 @@ impl Default for Processor {
         Self {
             queries: RunningQueries::default(),
             key_registry: Arc::new(KeyRegistry::<PrivateKeyOnly>::empty()),
+            executors: QueryExecutors::default(),
+            redaction: Redaction::default(),
+            policy: QueryPolicy::default(),
+            templates: QueryTemplates::default(),
             active_work: None,
+            stage_concurrency: StageConcurrency::default(),
             runtime: IpaRuntime::current(),
+            rng_provider: Arc::new(SystemRngProvider),
+            workspace: Workspace::default(),
+            max_completed_queries: DEFAULT_MAX_COMPLETED_QUERIES,
+            network: None,
+            peer_timeout: None,
         }
     }
 }
@@ -76,6 +114,16 @@ pub enum NewQueryError {
     MpcTransport(#[from] MpcTransportError),
     #[error(transparent)]
     ShardBroadcastError(#[from] BroadcastError<ShardIndex, ShardTransportError>),
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+    #[error("Query type {0} is not supported by this helper")]
+    UnsupportedQueryType(String),
+    #[error(transparent)]
+    TooManyChannels(#[from] TooManyChannels),
+    #[error("Invalid query: {0}")]
+    Invalid(ValidationReport),
+    #[error("helper {0:?} did not accept the query in time, queries can't run on the two remaining helpers")]
+    HelperUnavailable(Role),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -88,6 +136,18 @@ pub enum PrepareQueryError {
     Leader,
     #[error("Query is already running")]
     AlreadyRunning,
+    #[error("This helper keeps data of an earlier query with id {0}")]
+    DataExists(QueryId),
+    #[error("Protocol version {0} is not supported by this helper")]
+    UnsupportedProtocolVersion(ProtocolVersion),
+    #[error("Query type {0} is not supported by this helper")]
+    UnsupportedQueryType(String),
+    #[error(transparent)]
+    TooManyChannels(#[from] TooManyChannels),
+    #[error("Invalid query: {0}")]
+    Invalid(ValidationReport),
+    #[error(transparent)]
+    Template(#[from] TemplateError),
     #[error(transparent)]
     StateError {
         #[from]
@@ -101,6 +161,14 @@ pub enum PrepareQueryError {
 pub enum QueryInputError {
     #[error("The query with id {0:?} does not exist")]
     NoSuchQuery(QueryId),
+    #[error("Query type {0} is not supported by this helper")]
+    UnsupportedQueryType(String),
+    #[error(transparent)]
+    Contribution(#[from] ContributionError),
+    #[error(transparent)]
+    Batch(#[from] BatchError),
+    #[error(transparent)]
+    TooManyChannels(#[from] TooManyChannels),
     #[error(transparent)]
     StateError {
         #[from]
@@ -157,17 +225,177 @@ impl Processor {
         Self {
             queries: RunningQueries::default(),
             key_registry: Arc::new(key_registry),
+            executors: QueryExecutors::default(),
+            redaction: Redaction::default(),
+            policy: QueryPolicy::default(),
+            templates: QueryTemplates::default(),
             active_work,
+            stage_concurrency: StageConcurrency::default(),
             runtime,
+            rng_provider: Arc::new(SystemRngProvider),
+            workspace: Workspace::default(),
+            max_completed_queries: DEFAULT_MAX_COMPLETED_QUERIES,
+            network: None,
+            peer_timeout: None,
+        }
+    }
+
+    /// Replaces the set of query types this processor can run. By default, all query types
+    /// supported by this build are available.
+    #[must_use]
+    pub fn with_executors(
+        mut self,
+        executors: QueryExecutors<KeyRegistry<PrivateKeyOnly>>,
+    ) -> Self {
+        self.executors = executors;
+        self
+    }
+
+    /// Sets the fields left out of the [`PrivacyParams`] this processor logs and reports in the
+    /// query status. By default, all sensitive fields are redacted.
+    #[must_use]
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    /// Sets the limits this helper places on queries, reported by [`Self::validate_query`].
+    /// The channel limit is also enforced when queries are prepared.
+    #[must_use]
+    pub fn with_policy(mut self, policy: QueryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the limits on active work of individual stages of the queries this helper leads.
+    /// Other helpers run these queries with the same limits, and queries led by other helpers
+    /// run with the limits of their leader. By default, all stages run with the active work of
+    /// the query.
+    #[must_use]
+    pub fn with_stage_concurrency(mut self, stage_concurrency: StageConcurrency) -> Self {
+        self.stage_concurrency = stage_concurrency;
+        self
+    }
+
+    /// Sets the query templates report collectors can create queries from.
+    #[must_use]
+    pub fn with_templates(mut self, templates: QueryTemplates) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    #[must_use]
+    pub fn templates(&self) -> &QueryTemplates {
+        &self.templates
+    }
+
+    /// Changes the query templates stored on this helper. Templates must be allowed by the
+    /// query policy of this helper.
+    ///
+    /// ## Errors
+    /// See [`QueryTemplates::execute`].
+    pub fn execute_template_command(
+        &self,
+        command: TemplateCommand,
+    ) -> Result<TemplateList, TemplateError> {
+        self.templates.execute(command, &self.policy)
+    }
+
+    /// Sets the source of randomness used to set up PRSS for every query. By default, it is
+    /// seeded from the operating system.
+    #[must_use]
+    pub fn with_rng_provider(mut self, rng_provider: Arc<dyn CryptoRngProvider>) -> Self {
+        self.rng_provider = rng_provider;
+        self
+    }
+
+    /// Sets the data this helper keeps on disk for its queries, and how long it keeps it. By
+    /// default, there is none.
+    #[must_use]
+    pub fn with_workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
+    /// Sets how many completed queries this processor keeps until their results are collected.
+    /// Once there are more, the ones with the lowest ids are pruned.
+    #[must_use]
+    pub fn with_max_completed_queries(mut self, max_completed_queries: usize) -> Self {
+        self.max_completed_queries = max_completed_queries;
+        self
+    }
+
+    /// Sets the measured links between helpers. Queries this helper creates assign roles to
+    /// its peers so that stages that send more data between some roles than between others
+    /// finish soonest, see [`placement`]. Without them, the helper on the right takes `H2`.
+    ///
+    /// [`placement`]: crate::query::placement
+    #[must_use]
+    pub fn with_network_measurements(mut self, network: NetworkMeasurements) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Sets how long this helper waits on its peers, both to accept the queries it creates and
+    /// to send the records of running queries, before it fails the query with
+    /// [`ProtocolError::HelperUnavailable`]. Without a timeout, which is the default, it waits
+    /// for as long as it takes.
+    ///
+    /// The timeout bounds every receive on its own, so it must be longer than any stretch of a
+    /// query in which a peer legitimately sends nothing on a channel, such as a stage that
+    /// only computes locally on a large input.
+    #[must_use]
+    pub fn with_peer_timeout(mut self, peer_timeout: Option<Duration>) -> Self {
+        self.peer_timeout = peer_timeout;
+        self
+    }
+
+    /// Runs the readiness checks that depend on this processor: it must have keys to decrypt
+    /// reports with, and room on disk for the data it keeps for queries.
+    #[must_use]
+    pub fn readiness(&self) -> Vec<ReadinessCheck> {
+        let keys = if self.key_registry.is_empty() {
+            ReadinessCheck::fail("key_registry", "no private keys are loaded")
+        } else {
+            ReadinessCheck::pass("key_registry")
+        };
+        let workspace = match self.workspace.check_writable() {
+            Ok(()) => ReadinessCheck::pass("workspace"),
+            Err(e) => ReadinessCheck::fail("workspace", e),
+        };
+
+        vec![keys, workspace]
+    }
+
+    /// Checks whether this helper can run a query with the given configuration, without
+    /// creating it. Returns all the problems found.
+    #[must_use]
+    pub fn validate_query(&self, config: &QueryConfig) -> ValidationReport {
+        let mut report = config.validate(&self.policy);
+        if !self.supports(&config.query_type) {
+            report.push(
+                "query_type",
+                format!(
+                    "{} queries are not supported by this helper",
+                    config.query_type.as_ref()
+                ),
+            );
         }
+
+        report
+    }
+
+    /// Whether an executor for `query_type` is registered on this helper.
+    fn supports(&self, query_type: &QueryType) -> bool {
+        self.executors.get(query_type).is_some()
     }
 
     /// Upon receiving a new query request:
     /// * processor generates new query id
     /// * assigns roles to helpers in the ring.
     ///     Helper that received new query request becomes `Role::H1` (aka coordinator).
-    ///     The coordinator is in theory free to choose helpers for `Role::H2` and `Role::H3`
-    ///         arbitrarily (aka followers), however, this is not currently exercised.
+    ///     The coordinator is free to choose helpers for `Role::H2` and `Role::H3` (aka
+    ///         followers). It picks them from the network measurements it has, if any.
     /// * Requests Infra and Network layer to create resources for this query
     /// * sends `prepare` request that describes the query configuration
     ///     (query id, query type, field type, roles -> endpoints or reverse)
@@ -176,56 +404,165 @@ impl Processor {
     /// * returns query configuration
     ///
     /// ## Errors
-    /// When other peers failed to acknowledge this query
-    #[allow(clippy::missing_panics_doc)]
+    /// When the query does not pass [`Self::validate_query`], when other peers failed to
+    /// acknowledge this query, or if this helper only accepts queries created from templates.
     pub async fn new_query(
         &self,
         transport: MpcTransportImpl,
         shard_transport: ShardTransportImpl,
         req: QueryConfig,
     ) -> Result<PrepareQuery, NewQueryError> {
-        let query_id = QueryId;
+        if self.templates.required() {
+            return Err(TemplateError::Required.into());
+        }
+        self.start_query(transport, shard_transport, req).await
+    }
+
+    /// Same as [`Self::new_query`], but the query configuration comes from one of the templates
+    /// stored on this helper.
+    ///
+    /// ## Errors
+    /// If the template does not exist, does not allow the requested overrides, or when other
+    /// peers failed to acknowledge this query.
+    pub async fn new_query_from_template(
+        &self,
+        transport: MpcTransportImpl,
+        shard_transport: ShardTransportImpl,
+        req: CreateFromTemplate,
+    ) -> Result<PrepareQuery, NewQueryError> {
+        let config = self
+            .templates
+            .instantiate(&req.template_id, req.overrides)?;
+        self.start_query(transport, shard_transport, config).await
+    }
+
+    async fn start_query(
+        &self,
+        transport: MpcTransportImpl,
+        shard_transport: ShardTransportImpl,
+        req: QueryConfig,
+    ) -> Result<PrepareQuery, NewQueryError> {
+        if !self.supports(&req.query_type) {
+            return Err(NewQueryError::UnsupportedQueryType(
+                req.query_type.as_ref().to_string(),
+            ));
+        }
+        self.policy.check_channels(&req)?;
+        let report = req.validate(&self.policy);
+        if !report.is_valid() {
+            return Err(NewQueryError::Invalid(report));
+        }
+        let query_id = self
+            .queries
+            .start_new(&mut self.rng_provider.rng(), req, |query_id| {
+                self.workspace.has_data(query_id)
+            });
         let handle = self.queries.handle(query_id);
-        handle.set_state(QueryState::Preparing(req))?;
         let guard = handle.remove_query_on_drop();
 
         let id = transport.identity();
         let [right, left] = id.others();
-
-        let roles = RoleAssignment::try_from([(id, Role::H1), (right, Role::H2), (left, Role::H3)])
-            .unwrap();
+        let roles = if let Some(network) = &self.network {
+            let plan = placement::recommend_led_by(id, &req, network);
+            tracing::info!("assigning roles from network measurements: {plan}");
+            plan.roles
+        } else {
+            RoleAssignment::try_from([(id, Role::H1), (right, Role::H2), (left, Role::H3)]).unwrap()
+        };
 
         let prepare_request = PrepareQuery {
             query_id,
             config: req,
-            roles: roles.clone(),
+            roles,
+            protocol_version: ProtocolVersion::CURRENT,
+            pull_receivers: transport.pull_receivers(),
+            stage_concurrency: self.stage_concurrency,
         };
-        // Inform other helpers about new query. If any of them rejects it, this join will fail
-        // TODO: If H2 succeeds and H3 fails, we need to rollback H2.
-        try_join(
-            transport.send(left, prepare_request.clone(), stream::empty()),
-            transport.send(right, prepare_request.clone(), stream::empty()),
+        transport.bind_protocol_version(query_id, prepare_request.protocol_version);
+        shard_transport.bind_protocol_version(query_id, prepare_request.protocol_version);
+        // Inform other helpers about new query. If any of them rejects it, the query fails.
+        let (left_prepared, right_prepared) = join(
+            self.prepare_peer(&transport, left, &prepare_request),
+            self.prepare_peer(&transport, right, &prepare_request),
         )
-        .await
-        .map_err(NewQueryError::MpcTransport)?;
-
-        // TODO: Similar to the todo above. If shards 1,2 and 3 succeed but 4 fails, then we need
-        // to rollback 1,2 and 3
-        shard_transport.broadcast(prepare_request.clone()).await?;
+        .await;
+        // A peer that accepted the query would otherwise wait for inputs that never come, no
+        // matter why the other one did not accept it.
+        match (&left_prepared, &right_prepared) {
+            (Ok(()), Err(_)) => {
+                notify_peer_unavailable(
+                    &transport,
+                    left,
+                    query_id,
+                    prepare_request.roles.role(right),
+                )
+                .await;
+            }
+            (Err(_), Ok(())) => {
+                notify_peer_unavailable(
+                    &transport,
+                    right,
+                    query_id,
+                    prepare_request.roles.role(left),
+                )
+                .await;
+            }
+            _ => {}
+        }
+        left_prepared?;
+        right_prepared?;
+
+        // TODO: If shards 1,2 and 3 succeed but 4 fails, then we need to rollback 1,2 and 3
+        if let Err(e) = shard_transport.broadcast(prepare_request.clone()).await {
+            // Both peers accepted the query, but this helper can't run it.
+            join(
+                notify_peer_unavailable(&transport, left, query_id, Role::H1),
+                notify_peer_unavailable(&transport, right, query_id, Role::H1),
+            )
+            .await;
+            return Err(e.into());
+        }
 
-        handle.set_state(QueryState::AwaitingInputs(query_id, req, roles))?;
+        handle.set_state(QueryState::AwaitingInputs(prepare_request.clone()))?;
 
         guard.restore();
         Ok(prepare_request)
     }
 
+    /// Sends the prepare request to `peer`. A peer that does not answer within the peer timeout
+    /// is considered unavailable.
+    async fn prepare_peer(
+        &self,
+        transport: &MpcTransportImpl,
+        peer: HelperIdentity,
+        req: &PrepareQuery,
+    ) -> Result<(), NewQueryError> {
+        let send = transport.send(peer, req.clone(), stream::empty());
+
+        // Shuttle does not provide timers, peers never time out there.
+        #[cfg(feature = "shuttle")]
+        let peer_timeout: Option<Duration> = None;
+        #[cfg(not(feature = "shuttle"))]
+        let peer_timeout = self.peer_timeout;
+
+        let Some(peer_timeout) = peer_timeout else {
+            return Ok(send.await?);
+        };
+        match ::tokio::time::timeout(peer_timeout, send).await {
+            Ok(r) => Ok(r?),
+            Err(_) => Err(NewQueryError::HelperUnavailable(req.roles.role(peer))),
+        }
+    }
+
     /// On prepare, each leader:
     /// * ensures that it is not the leader helper on this query
     /// * query is not registered yet
+    /// * query passes validation against this helper's policy
     /// * registers query
     ///
     /// ## Errors
-    /// if query is already running or this helper cannot be a follower in it
+    /// if query is already running, is invalid or this helper cannot be a follower in it, or if
+    /// this helper only runs queries created from templates and none of them admits the query.
     pub async fn prepare_helper(
         &self,
         mpc_transport: MpcTransportImpl,
@@ -245,15 +582,32 @@ impl Processor {
         if handle.status().is_some() {
             return Err(PrepareQueryError::AlreadyRunning);
         }
+        if self.workspace.has_data(req.query_id) {
+            return Err(PrepareQueryError::DataExists(req.query_id));
+        }
+        if !req.protocol_version.is_supported() {
+            return Err(PrepareQueryError::UnsupportedProtocolVersion(
+                req.protocol_version,
+            ));
+        }
+        if !self.supports(&req.config.query_type) {
+            return Err(PrepareQueryError::UnsupportedQueryType(
+                req.config.query_type.as_ref().to_string(),
+            ));
+        }
+        self.policy.check_channels(&req.config)?;
+        let report = req.config.validate(&self.policy);
+        if !report.is_valid() {
+            return Err(PrepareQueryError::Invalid(report));
+        }
+        self.templates.check(&req.config)?;
+        mpc_transport.bind_protocol_version(req.query_id, req.protocol_version);
+        shard_transport.bind_protocol_version(req.query_id, req.protocol_version);
 
         // TODO: If shards 1,2 and 3 succeed but 4 fails, then we need to rollback 1,2 and 3.
         shard_transport.broadcast(req.clone()).await?;
 
-        handle.set_state(QueryState::AwaitingInputs(
-            req.query_id,
-            req.config,
-            req.roles,
-        ))?;
+        handle.set_state(QueryState::AwaitingInputs(req))?;
 
         Ok(())
     }
@@ -279,12 +633,22 @@ impl Processor {
         if handle.status().is_some() {
             return Err(PrepareQueryError::AlreadyRunning);
         }
+        if self.workspace.has_data(req.query_id) {
+            return Err(PrepareQueryError::DataExists(req.query_id));
+        }
+        if !req.protocol_version.is_supported() {
+            return Err(PrepareQueryError::UnsupportedProtocolVersion(
+                req.protocol_version,
+            ));
+        }
+        if !self.supports(&req.config.query_type) {
+            return Err(PrepareQueryError::UnsupportedQueryType(
+                req.config.query_type.as_ref().to_string(),
+            ));
+        }
+        shard_transport.bind_protocol_version(req.query_id, req.protocol_version);
 
-        handle.set_state(QueryState::AwaitingInputs(
-            req.query_id,
-            req.config,
-            req.roles,
-        ))?;
+        handle.set_state(QueryState::AwaitingInputs(req))?;
 
         Ok(())
     }
@@ -306,157 +670,613 @@ impl Processor {
         match queries.entry(input.query_id) {
             Entry::Occupied(entry) => {
                 let state = entry.remove();
-                if let QueryState::AwaitingInputs(query_id, config, role_assignment) = state {
-                    assert_eq!(
-                        input.query_id, query_id,
-                        "received inputs for a different query"
-                    );
-                    let mut gateway_config = GatewayConfig::default();
-                    if let Some(active_work) = self.active_work {
-                        gateway_config.active = active_work;
-                    } else {
-                        gateway_config.set_active_work_from_query_config(&config);
+                let (prepare, contributions) = match (state, &input.contribution) {
+                    (QueryState::AwaitingInputs(prepare), _) => (prepare, None),
+                    (QueryState::CollectingInputs(prepare, contributions), Some(_)) => {
+                        (prepare, Some(contributions))
                     }
-                    let gateway = Gateway::new(
-                        query_id,
-                        gateway_config,
-                        role_assignment,
-                        mpc_transport,
-                        shard_transport,
-                    );
-                    queries.insert(
+                    (state, _) => {
+                        let error = StateError::InvalidState {
+                            from: QueryStatus::from(&state),
+                            to: QueryStatus::Running,
+                        };
+                        queries.insert(input.query_id, state);
+                        return Err(QueryInputError::StateError { source: error });
+                    }
+                };
+                assert_eq!(
+                    input.query_id, prepare.query_id,
+                    "received inputs for a different query"
+                );
+                let config = prepare.config;
+                let waiting = |prepare: PrepareQuery, contributions: Option<InputContributions>| {
+                    match contributions {
+                        Some(contributions) => QueryState::CollectingInputs(prepare, contributions),
+                        None => QueryState::AwaitingInputs(prepare),
+                    }
+                };
+                let Some(query_executor) = self.executors.get(&config.query_type) else {
+                    queries.insert(input.query_id, waiting(prepare, contributions));
+                    return Err(QueryInputError::UnsupportedQueryType(
+                        config.query_type.as_ref().to_string(),
+                    ));
+                };
+                if input.contribution.is_some() {
+                    if let Err(e) = contributions::check_supported(&config) {
+                        queries.insert(input.query_id, waiting(prepare, contributions));
+                        return Err(e.into());
+                    }
+                }
+                let input_stream = if let Some(contribution) = input.contribution {
+                    let mut contributions =
+                        contributions.unwrap_or_else(|| InputContributions::new(&contribution));
+                    // The first slice is always accepted, so `contributions` is never empty
+                    // after this.
+                    let added = contributions.add(&contribution, input.input_stream);
+                    if added.is_err() || !contributions.is_complete() {
+                        queries.insert(
+                            input.query_id,
+                            QueryState::CollectingInputs(prepare, contributions),
+                        );
+                        return added.map_err(Into::into);
+                    }
+                    match contributions.into_stream(
                         input.query_id,
-                        QueryState::Running(executor::execute(
-                            &self.runtime,
-                            config,
-                            Arc::clone(&self.key_registry),
-                            gateway,
-                            input.input_stream,
-                        )),
-                    );
-                    Ok(())
+                        config.size.into(),
+                        &self.redaction,
+                    ) {
+                        Ok(input_stream) => input_stream,
+                        Err(e) => {
+                            // Slices are gone, contributors have to upload them again.
+                            queries.insert(input.query_id, QueryState::AwaitingInputs(prepare));
+                            return Err(e.into());
+                        }
+                    }
                 } else {
-                    let error = StateError::InvalidState {
-                        from: QueryStatus::from(&state),
-                        to: QueryStatus::Running,
-                    };
-                    queries.insert(input.query_id, state);
-                    Err(QueryInputError::StateError { source: error })
-                }
+                    input.input_stream
+                };
+                let running = self.run(
+                    prepare,
+                    query_executor,
+                    input_stream,
+                    mpc_transport,
+                    shard_transport,
+                );
+                queries.insert(input.query_id, QueryState::Running(running));
+                Ok(())
             }
             Entry::Vacant(_) => Err(QueryInputError::NoSuchQuery(input.query_id)),
         }
     }
 
-    /// Returns the status of the running query or [`None`].
-    /// If the query was completed it updates the state to reflect that.
-    fn get_status(&self, query_id: QueryId) -> Option<QueryStatus> {
-        let mut queries = self.queries.inner.lock().unwrap();
-        let mut state = queries.remove(&query_id)?;
+    /// Adds a batch of reports to the input of a query. Batches are read in full and kept until
+    /// the input is sealed with [`Self::seal_inputs`], which starts the query on all of them.
+    ///
+    /// ## Errors
+    /// If the query does not exist, it received its input in another way, its type is not
+    /// supported by this helper, it received too many batches already or reading the batch
+    /// fails.
+    ///
+    /// ## Panics
+    /// If failed to obtain exclusive access to the query collection.
+    pub async fn append_inputs(
+        &self,
+        query_id: QueryId,
+        append: AppendInput,
+        input_stream: BodyStream,
+    ) -> Result<(), QueryInputError> {
+        // Refuse the batch before reading it, if the query can't take it.
+        self.check_appendable(query_id)?;
+        let batch = read_batch(input_stream).await?;
 
-        if let QueryState::Running(ref mut running) = state {
-            if let Some(result) = running.try_complete() {
-                state = QueryState::Completed(result);
+        let mut queries = self.queries.inner.lock().unwrap();
+        let Entry::Occupied(mut entry) = queries.entry(query_id) else {
+            return Err(QueryInputError::NoSuchQuery(query_id));
+        };
+        match entry.get_mut() {
+            QueryState::AppendingInputs(_, batches) => batches.add(append, batch)?,
+            QueryState::AwaitingInputs(prepare) => {
+                let mut batches = InputBatches::default();
+                batches.add(append, batch)?;
+                let prepare = prepare.clone();
+                entry.insert(QueryState::AppendingInputs(prepare, batches));
+            }
+            state => {
+                return Err(StateError::InvalidState {
+                    from: QueryStatus::from(&*state),
+                    to: QueryStatus::AwaitingInputs,
+                }
+                .into())
             }
         }
 
-        let status = QueryStatus::from(&state);
-        queries.insert(query_id, state);
-        Some(status)
-    }
-
-    /// This helper function is used to transform a [`BoxError`] into a
-    /// [`QueryStatusError::DifferentStatus`] and retrieve it's internal state. Returns [`None`]
-    /// if not possible.
-    #[cfg(feature = "in-memory-infra")]
-    fn downcast_state_error(box_error: &crate::error::BoxError) -> Option<QueryStatus> {
-        use crate::helpers::ApiError;
-        let api_error = box_error.downcast_ref::<ApiError>();
-        if let Some(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
-            my_status, ..
-        })) = api_error
-        {
-            return Some(*my_status);
-        }
-        None
-    }
-
-    /// This helper is used by the in-memory stack to obtain the state of other shards via a
-    /// [`QueryStatusError::DifferentStatus`] error.
-    /// TODO: Ideally broadcast should return a value, that we could use to parse the state instead
-    /// of relying on errors.
-    #[cfg(feature = "in-memory-infra")]
-    fn get_state_from_error(
-        error: &crate::helpers::InMemoryTransportError<ShardIndex>,
-    ) -> Option<QueryStatus> {
-        if let crate::helpers::InMemoryTransportError::Rejected { inner, .. } = error {
-            return Self::downcast_state_error(inner);
-        }
-        None
+        Ok(())
     }
 
-    /// This helper is used by the HTTP stack to obtain the state of other shards via a
-    /// [`QueryStatusError::DifferentStatus`] error.
-    /// TODO: Ideally broadcast should return a value, that we could use to parse the state instead
-    /// of relying on errors.
-    #[cfg(feature = "real-world-infra")]
-    fn get_state_from_error(shard_error: &crate::net::ShardError) -> Option<QueryStatus> {
-        if let crate::net::Error::ShardQueryStatusMismatch { error, .. } = &shard_error.source {
-            return Some(error.actual);
+    /// Checks that the query takes one more batch of input, before the batch is read.
+    fn check_appendable(&self, query_id: QueryId) -> Result<(), QueryInputError> {
+        let queries = self.queries.inner.lock().unwrap();
+        match queries.get(&query_id) {
+            None => Err(QueryInputError::NoSuchQuery(query_id)),
+            Some(QueryState::AppendingInputs(_, batches)) if !batches.has_room() => {
+                Err(BatchError::TooManyBatches.into())
+            }
+            Some(QueryState::AppendingInputs(..)) => Ok(()),
+            Some(QueryState::AwaitingInputs(prepare)) => {
+                let query_type = &prepare.config.query_type;
+                if !self.supports(query_type) {
+                    return Err(QueryInputError::UnsupportedQueryType(
+                        query_type.as_ref().to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            Some(state) => Err(StateError::InvalidState {
+                from: QueryStatus::from(state),
+                to: QueryStatus::AwaitingInputs,
+            }
+            .into()),
         }
-        None
     }
 
-    /// Returns the query status in this helper, by querying all shards.
+    /// Seals the input of a query that was uploaded in batches. The size of the query becomes
+    /// the sealed count and the query starts running on all batches, in the order they were
+    /// uploaded. Before it runs, helpers confirm with each other that they sealed the input with
+    /// the same count.
     ///
     /// ## Errors
-    /// If query is not registered on this helper.
+    /// If the query does not exist or is not receiving its input in batches, the batches declared
+    /// a different number of reports than the sealed count, or the sealed size makes it need more
+    /// channels than this helper allows.
     ///
     /// ## Panics
-    /// If the query collection mutex is poisoned.
-    pub async fn query_status(
+    /// If failed to obtain exclusive access to the query collection.
+    pub fn seal_inputs(
         &self,
+        mpc_transport: MpcTransportImpl,
         shard_transport: ShardTransportImpl,
         query_id: QueryId,
-    ) -> Result<QueryStatus, QueryStatusError> {
-        let shard_index = shard_transport.identity();
-        if shard_index != ShardIndex::FIRST {
-            return Err(QueryStatusError::NotLeader(shard_index));
+        seal: SealInput,
+    ) -> Result<(), QueryInputError> {
+        let mut queries = self.queries.inner.lock().unwrap();
+        let Some(state) = queries.remove(&query_id) else {
+            return Err(QueryInputError::NoSuchQuery(query_id));
+        };
+        let QueryState::AppendingInputs(prepare, batches) = state else {
+            let error = StateError::InvalidState {
+                from: QueryStatus::from(&state),
+                to: QueryStatus::Running,
+            };
+            queries.insert(query_id, state);
+            return Err(error.into());
+        };
+        let mut sealed = prepare.clone();
+        sealed.config.size = seal.count;
+        let checked = batches
+            .check_sealed(u32::from(seal.count))
+            .map_err(QueryInputError::from)
+            .and_then(|()| {
+                self.policy
+                    .check_channels(&sealed.config)
+                    .map_err(QueryInputError::from)
+            });
+        if let Err(e) = checked {
+            queries.insert(query_id, QueryState::AppendingInputs(prepare, batches));
+            return Err(e);
         }
+        tracing::info!(
+            target: "ipa_core::query::privacy",
+            query_id = %query_id,
+            batches = batches.batch_count(),
+            count = ?self.redaction.apply(SensitiveField::QuerySize, u32::from(seal.count)),
+            "query input sealed"
+        );
 
-        let mut status = self
-            .get_status(query_id)
-            .ok_or(QueryStatusError::NoSuchQuery(query_id))?;
+        let query_executor = self
+            .executors
+            .get(&sealed.config.query_type)
+            .expect("query type was checked when the first batch was appended");
+        let running = self.run(
+            sealed,
+            Arc::new(SealedInput(query_executor)),
+            batches.into_stream(),
+            mpc_transport,
+            shard_transport,
+        );
+        queries.insert(query_id, QueryState::Running(running));
 
-        let shard_query_status_req = CompareStatusRequest { query_id, status };
+        Ok(())
+    }
 
-        let shard_responses = shard_transport.broadcast(shard_query_status_req).await;
-        if let Err(e) = shard_responses {
-            for (shard, failure) in &e.failures {
-                if let Some(other) = Self::get_state_from_error(failure) {
-                    status = min_status(status, other);
-                } else {
-                    tracing::error!("failed to get status from shard {shard}: {failure:?}");
-                    return Err(e.into());
-                }
-            }
+    /// Starts running the query described by `prepare` on `input_stream`.
+    fn run(
+        &self,
+        prepare: PrepareQuery,
+        query_executor: Arc<dyn QueryExecutor<KeyRegistry<PrivateKeyOnly>>>,
+        input_stream: BodyStream,
+        mpc_transport: MpcTransportImpl,
+        shard_transport: ShardTransportImpl,
+    ) -> RunningQuery {
+        let PrepareQuery {
+            query_id,
+            config,
+            roles: role_assignment,
+            protocol_version,
+            pull_receivers,
+            stage_concurrency,
+        } = prepare;
+        PrivacyParams::new(&config, &self.redaction).log_query_start(query_id);
+        // Shards learn about the query from their leader, so this is the first time
+        // the MPC transport on them sees it.
+        mpc_transport.bind_protocol_version(query_id, protocol_version);
+        mpc_transport.set_pull_receivers(query_id, &pull_receivers);
+        let mut gateway_config = GatewayConfig {
+            protocol_version,
+            stage_concurrency,
+            peer_timeout: self.peer_timeout,
+            ..GatewayConfig::default()
+        };
+        if let Some(active_work) = self.active_work {
+            gateway_config.active = active_work;
+        } else {
+            gateway_config.set_active_work_from_query_config(&config);
         }
+        let gateway = Gateway::new(
+            query_id,
+            gateway_config,
+            role_assignment,
+            mpc_transport,
+            shard_transport,
+        );
 
-        Ok(status)
+        executor::execute(
+            &self.runtime,
+            query_executor,
+            config,
+            Arc::clone(&self.key_registry),
+            gateway,
+            input_stream,
+            self.rng_provider.as_ref(),
+        )
     }
 
-    /// Compares this shard status against the given type. Returns an error if different.
+    /// Returns the privacy parameters of the query, if it has not completed yet.
     ///
-    /// ## Errors
-    /// If query is not registered on this helper or
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    #[must_use]
+    pub fn privacy_params(&self, query_id: QueryId) -> Option<PrivacyParams> {
+        let queries = self.queries.inner.lock().unwrap();
+        let config = match queries.get(&query_id)? {
+            QueryState::Preparing(config)
+            | QueryState::AwaitingInputs(PrepareQuery { config, .. })
+            | QueryState::CollectingInputs(PrepareQuery { config, .. }, _)
+            | QueryState::AppendingInputs(PrepareQuery { config, .. }, _) => config,
+            QueryState::Running(running) => &running.config,
+            QueryState::Empty | QueryState::AwaitingCompletion | QueryState::Completed(..) => {
+                return None
+            }
+        };
+
+        Some(PrivacyParams::new(config, &self.redaction))
+    }
+
+    /// Returns the helper that leads the query: the one that received the request to create it
+    /// and assigned roles to the others. Any helper can create queries, so report collectors
+    /// learn the leader from the query status.
     ///
     /// ## Panics
     /// If the query collection mutex is poisoned.
-    pub fn shard_status(
-        &self,
-        shard_transport: &ShardTransportImpl,
-        req: &CompareStatusRequest,
-    ) -> Result<QueryStatus, QueryStatusError> {
+    #[must_use]
+    pub fn leader(&self, query_id: QueryId) -> Option<HelperIdentity> {
+        self.queries.inner.lock().unwrap().get(&query_id)?.leader()
+    }
+
+    /// Returns the tuning report of the query, once it has completed and until its results are
+    /// collected.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    #[must_use]
+    pub fn tuning_report(&self, query_id: QueryId) -> Option<TuningReport> {
+        match self.queries.inner.lock().unwrap().get(&query_id)? {
+            QueryState::Completed(_, report, _) => report.clone(),
+            _ => None,
+        }
+    }
+
+    /// Returns the noise the protocol added to the result of the query, once it has completed
+    /// and until its results are collected.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    #[must_use]
+    pub fn noise(&self, query_id: QueryId) -> Option<NoiseReport> {
+        match self.queries.inner.lock().unwrap().get(&query_id)? {
+            QueryState::Completed(Ok(result), ..) => result.noise(),
+            _ => None,
+        }
+    }
+
+    /// Returns the send buffers of the query, as seen by the last check of its watchdog, while
+    /// the query is running.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    #[must_use]
+    pub fn send_buffers(&self, query_id: QueryId) -> Option<Vec<SendBufferStatus>> {
+        match self.queries.inner.lock().unwrap().get(&query_id)? {
+            QueryState::Running(running) => Some(running.send_buffers()),
+            _ => None,
+        }
+    }
+
+    /// Returns the estimated completion time of the query while it is running, once the
+    /// protocol completed its first stage. Sharded helpers report the estimate of the shard
+    /// that serves the request.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    #[must_use]
+    pub fn eta(&self, query_id: QueryId) -> Option<QueryEta> {
+        match self.queries.inner.lock().unwrap().get(&query_id)? {
+            QueryState::Running(running) => running.eta(),
+            _ => None,
+        }
+    }
+
+    /// Returns the status of the query, see [`Self::query_status`], along with everything else
+    /// this helper knows about it.
+    ///
+    /// ## Errors
+    /// If query is not registered on this helper.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub async fn query_status_report(
+        &self,
+        shard_transport: ShardTransportImpl,
+        query_id: QueryId,
+    ) -> Result<QueryStatusReport, QueryStatusError> {
+        let status = self.query_status(shard_transport, query_id).await?;
+        Ok(QueryStatusReport {
+            status,
+            privacy_params: self.privacy_params(query_id),
+            tuning_report: self.tuning_report(query_id),
+            send_buffers: self.send_buffers(query_id),
+            noise: self.noise(query_id),
+            leader: self.leader(query_id),
+            eta: self.eta(query_id),
+        })
+    }
+
+    /// Returns the status of the running query or [`None`].
+    /// If the query was completed it updates the state to reflect that.
+    fn get_status(&self, query_id: QueryId) -> Option<QueryStatus> {
+        let (status, pruned) = {
+            let mut queries = self.queries.inner.lock().unwrap();
+            complete_finished(&mut queries);
+            let pruned = self.apply_retention(&mut queries, Some(query_id));
+            (queries.get(&query_id).map(QueryStatus::from), pruned)
+        };
+        self.inputs_finished(pruned);
+
+        status
+    }
+
+    /// Removes the completed queries that finished first, except `keep`, until at most
+    /// `max_completed_queries` of them remain. Returns the removed queries, see
+    /// [`Self::inputs_finished`].
+    fn apply_retention(
+        &self,
+        queries: &mut HashMap<QueryId, QueryState>,
+        keep: Option<QueryId>,
+    ) -> Vec<QueryId> {
+        let mut completed = queries
+            .iter()
+            .filter_map(|(query_id, state)| match state {
+                QueryState::Completed(_, _, finished) => Some((*finished, *query_id)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let Some(excess) = completed.len().checked_sub(self.max_completed_queries) else {
+            return Vec::new();
+        };
+        completed.sort_unstable();
+        let pruned = completed
+            .into_iter()
+            .map(|(_, query_id)| query_id)
+            .filter(|query_id| Some(*query_id) != keep)
+            .take(excess)
+            .collect::<Vec<_>>();
+        for query_id in &pruned {
+            queries.remove(query_id);
+            tracing::info!("pruned completed query {query_id} to stay within the retention limit");
+        }
+
+        pruned
+    }
+
+    /// Applies the input retention policy to queries that were pruned. This touches the file
+    /// system, so it must not be called with the query collection locked.
+    fn inputs_finished(&self, pruned: Vec<QueryId>) {
+        for query_id in pruned {
+            self.workspace.query_finished(query_id);
+        }
+    }
+
+    /// Lists the queries this helper keeps track of, in the order of their ids. Queries that
+    /// finished running are listed as completed.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    #[must_use]
+    pub fn list_queries(&self, req: &ListQueries) -> QueryList {
+        let (mut listed, pruned) = {
+            let mut queries = self.queries.inner.lock().unwrap();
+            complete_finished(&mut queries);
+            let pruned = self.apply_retention(&mut queries, None);
+            let listed = queries
+                .iter()
+                .filter(|(query_id, _)| req.after.is_none_or(|after| **query_id > after))
+                .map(|(query_id, state)| QuerySummary {
+                    query_id: *query_id,
+                    status: QueryStatus::from(state),
+                    failed: matches!(state, QueryState::Completed(Err(_), ..)),
+                })
+                .filter(|summary| req.state.is_none_or(|state| state == summary.status))
+                .collect::<Vec<_>>();
+            (listed, pruned)
+        };
+        self.inputs_finished(pruned);
+
+        listed.sort_unstable_by_key(|summary| summary.query_id);
+
+        let page_size = req.page_size();
+        let next = (listed.len() > page_size).then(|| listed[page_size - 1].query_id);
+        listed.truncate(page_size);
+
+        QueryList {
+            queries: listed,
+            next,
+        }
+    }
+
+    /// Removes a query that is no longer running, along with its results and the data kept for
+    /// its input.
+    ///
+    /// ## Errors
+    /// If the query does not exist or is still running, or if its input can't be deleted. The
+    /// query is removed even if its input can't be deleted, which can be retried with
+    /// [`Self::delete_input`].
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn prune(&self, query_id: QueryId) -> Result<QueryPruned, PruneQueryError> {
+        {
+            let mut queries = self.queries.inner.lock().unwrap();
+            complete_finished(&mut queries);
+            match queries.get(&query_id) {
+                Some(QueryState::Completed(..)) => {}
+                Some(state) => {
+                    return Err(PruneQueryError::QueryInProgress {
+                        query_id,
+                        status: QueryStatus::from(state),
+                    })
+                }
+                None => return Err(PruneQueryError::NoSuchQuery(query_id)),
+            }
+            queries.remove(&query_id);
+        }
+
+        let input = self.workspace.delete(query_id)?;
+
+        Ok(QueryPruned { query_id, input })
+    }
+
+    /// This helper function is used to transform a [`BoxError`] into a
+    /// [`QueryStatusError::DifferentStatus`] and retrieve it's internal state. Returns [`None`]
+    /// if not possible.
+    #[cfg(any(feature = "in-memory-infra", feature = "dyn-infra"))]
+    fn downcast_state_error(box_error: &crate::error::BoxError) -> Option<QueryStatus> {
+        use crate::helpers::ApiError;
+        let api_error = box_error.downcast_ref::<ApiError>();
+        if let Some(ApiError::QueryStatus(QueryStatusError::DifferentStatus {
+            my_status, ..
+        })) = api_error
+        {
+            return Some(*my_status);
+        }
+        None
+    }
+
+    /// This helper is used by the in-memory stack to obtain the state of other shards via a
+    /// [`QueryStatusError::DifferentStatus`] error.
+    /// TODO: Ideally broadcast should return a value, that we could use to parse the state instead
+    /// of relying on errors.
+    #[cfg(feature = "in-memory-infra")]
+    fn get_state_from_error(
+        error: &crate::helpers::InMemoryTransportError<ShardIndex>,
+    ) -> Option<QueryStatus> {
+        if let crate::helpers::InMemoryTransportError::Rejected { inner, .. } = error {
+            return Self::downcast_state_error(inner);
+        }
+        None
+    }
+
+    /// Same as above, for transports implemented outside of this crate. They are expected to
+    /// return errors produced by the remote [`RequestHandler`] as is.
+    ///
+    /// [`RequestHandler`]: crate::helpers::RequestHandler
+    #[cfg(feature = "dyn-infra")]
+    fn get_state_from_error(
+        error: &crate::helpers::DynTransportError<ShardIndex>,
+    ) -> Option<QueryStatus> {
+        Self::downcast_state_error(&error.inner)
+    }
+
+    /// This helper is used by the HTTP stack to obtain the state of other shards via a
+    /// [`QueryStatusError::DifferentStatus`] error.
+    /// TODO: Ideally broadcast should return a value, that we could use to parse the state instead
+    /// of relying on errors.
+    #[cfg(feature = "real-world-infra")]
+    fn get_state_from_error(shard_error: &crate::net::ShardError) -> Option<QueryStatus> {
+        if let crate::net::Error::ShardQueryStatusMismatch { error, .. } = &shard_error.source {
+            return Some(error.actual);
+        }
+        None
+    }
+
+    /// Returns the query status in this helper, by querying all shards.
+    ///
+    /// ## Errors
+    /// If query is not registered on this helper.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub async fn query_status(
+        &self,
+        shard_transport: ShardTransportImpl,
+        query_id: QueryId,
+    ) -> Result<QueryStatus, QueryStatusError> {
+        let shard_index = shard_transport.identity();
+        if shard_index != ShardIndex::FIRST {
+            return Err(QueryStatusError::NotLeader(shard_index));
+        }
+
+        let mut status = self
+            .get_status(query_id)
+            .ok_or(QueryStatusError::NoSuchQuery(query_id))?;
+
+        let shard_query_status_req = CompareStatusRequest { query_id, status };
+
+        let shard_responses = shard_transport.broadcast(shard_query_status_req).await;
+        if let Err(e) = shard_responses {
+            for (shard, failure) in &e.failures {
+                if let Some(other) = Self::get_state_from_error(failure) {
+                    status = min_status(status, other);
+                } else {
+                    tracing::error!("failed to get status from shard {shard}: {failure:?}");
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(status)
+    }
+
+    /// Compares this shard status against the given type. Returns an error if different.
+    ///
+    /// ## Errors
+    /// If query is not registered on this helper or
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn shard_status(
+        &self,
+        shard_transport: &ShardTransportImpl,
+        req: &CompareStatusRequest,
+    ) -> Result<QueryStatus, QueryStatusError> {
         let shard_index = shard_transport.identity();
         if shard_index == ShardIndex::FIRST {
             return Err(QueryStatusError::Leader);
@@ -489,8 +1309,13 @@ impl Processor {
         let handle = {
             let mut queries = self.queries.inner.lock().unwrap();
 
-            match queries.remove(&query_id) {
-                Some(QueryState::Completed(result)) => return result.map_err(Into::into),
+            let state = queries.remove(&query_id);
+            match state {
+                Some(QueryState::Completed(result, ..)) => {
+                    drop(queries);
+                    self.workspace.query_finished(query_id);
+                    return result.map_err(Into::into);
+                }
                 Some(QueryState::Running(handle)) => {
                     queries.insert(query_id, QueryState::AwaitingCompletion);
                     CompletionHandle::new(RemoveQuery::new(query_id, &self.queries), handle)
@@ -521,7 +1346,10 @@ impl Processor {
                 .await?;
         }
 
-        Ok(handle.await?)
+        let result = handle.await;
+        self.workspace.query_finished(query_id);
+
+        Ok(result?)
     }
 
     /// Terminates a query with the given id. If query is running, then it
@@ -533,31 +1361,182 @@ impl Processor {
     /// ## Panics
     /// If failed to obtain exclusive access to the query collection.
     pub fn kill(&self, query_id: QueryId) -> Result<QueryKilled, QueryKillStatus> {
-        let mut queries = self.queries.inner.lock().unwrap();
-        let Some(state) = queries.remove(&query_id) else {
+        let state = self.queries.inner.lock().unwrap().remove(&query_id);
+        let Some(state) = state else {
             return Err(QueryKillStatus::NoSuchQuery(query_id));
         };
 
         if let QueryState::Running(handle) = state {
             handle.join_handle.abort();
         }
+        self.workspace.query_finished(query_id);
 
         Ok(QueryKilled(query_id))
     }
+
+    /// Fails a query, because a peer helper reports that the third helper in it is unavailable.
+    /// If query is running, its task is terminated. The query then completes with
+    /// [`ProtocolError::HelperUnavailable`], so that the report collector learns why.
+    ///
+    /// ## Errors
+    /// if query is not registered on this helper.
+    ///
+    /// ## Panics
+    /// If failed to obtain exclusive access to the query collection.
+    pub fn peer_unavailable(&self, req: &PeerUnavailable) -> Result<(), QueryKillStatus> {
+        let mut queries = self.queries.inner.lock().unwrap();
+        let Some(state) = queries.get_mut(&req.query_id) else {
+            return Err(QueryKillStatus::NoSuchQuery(req.query_id));
+        };
+        tracing::warn!(
+            "query {} can't finish, because {:?} is unavailable",
+            req.query_id,
+            req.role
+        );
+
+        let tuning_report = match state {
+            // The task of a query someone waits on belongs to the waiter. It fails on its own
+            // once the unavailable helper times out.
+            QueryState::AwaitingCompletion | QueryState::Completed(..) => return Ok(()),
+            QueryState::Running(running) => {
+                running.join_handle.abort();
+                running.take_tuning_report()
+            }
+            _ => None,
+        };
+        *state = QueryState::Completed(
+            Err(ProtocolError::HelperUnavailable(req.role)),
+            tuning_report,
+            Instant::now(),
+        );
+
+        Ok(())
+    }
+
+    /// Deletes the data this helper keeps for the input of a query that is no longer running.
+    /// Deleting the input of a query that did not keep any is not an error.
+    ///
+    /// ## Errors
+    /// If the query is still running, or if the data can't be deleted.
+    ///
+    /// ## Panics
+    /// If failed to obtain exclusive access to the query collection.
+    pub fn delete_input(&self, query_id: QueryId) -> Result<InputDeleted, DeleteInputError> {
+        if let Some(state) = self.queries.inner.lock().unwrap().get(&query_id) {
+            if !matches!(state, QueryState::Completed(..)) {
+                return Err(DeleteInputError::QueryInProgress {
+                    query_id,
+                    status: QueryStatus::from(state),
+                });
+            }
+        }
+
+        Ok(self.workspace.delete(query_id)?)
+    }
+}
+
+/// Tells `peer` that the query can't run, because the helper with `unavailable` role did not
+/// accept it. Failing to tell it is only logged.
+async fn notify_peer_unavailable(
+    transport: &MpcTransportImpl,
+    peer: HelperIdentity,
+    query_id: QueryId,
+    unavailable: Role,
+) {
+    let req = PeerUnavailable {
+        query_id,
+        role: unavailable,
+    };
+    if let Err(e) = transport.send(peer, req, stream::empty()).await {
+        tracing::warn!("failed to tell {peer:?} that {unavailable:?} is unavailable: {e:?}");
+    }
+}
+
+/// Moves the queries that finished running to the completed state.
+fn complete_finished(queries: &mut HashMap<QueryId, QueryState>) {
+    for state in queries.values_mut() {
+        if let QueryState::Running(running) = state {
+            if let Some(result) = running.try_complete() {
+                *state =
+                    QueryState::Completed(result, running.take_tuning_report(), Instant::now());
+            }
+        }
+    }
 }
 
 #[derive(Clone, Serialize)]
 pub struct QueryKilled(pub QueryId);
 
+/// Status of a query and what else a helper knows about it at its current stage.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueryStatusReport {
+    pub status: QueryStatus,
+    /// Privacy parameters of the query, absent once the query has completed.
+    #[serde(default)]
+    pub privacy_params: Option<PrivacyParams>,
+    /// Buffer usage and tuning recommendations, present once the query has completed.
+    #[serde(default)]
+    pub tuning_report: Option<TuningReport>,
+    /// State of the send buffers, present while the query is running. Buffers that have not
+    /// been flushed for a while point at the channel a stalled query waits on.
+    #[serde(default)]
+    pub send_buffers: Option<Vec<SendBufferStatus>>,
+    /// Noise added to the query output, present once the query has completed and until its
+    /// results are collected.
+    #[serde(default)]
+    pub noise: Option<NoiseReport>,
+    /// The helper that created the query and leads it, once the helpers agreed on their roles
+    /// and until the query completes.
+    #[serde(default)]
+    pub leader: Option<HelperIdentity>,
+    /// Estimated completion time, while the query is running and once the protocol completed
+    /// its first stage.
+    #[serde(default)]
+    pub eta: Option<QueryEta>,
+}
+
+impl QueryStatusReport {
+    /// Report of a query in `status`, with nothing else known about it.
+    #[must_use]
+    pub fn new(status: QueryStatus) -> Self {
+        Self {
+            status,
+            privacy_params: None,
+            tuning_report: None,
+            send_buffers: None,
+            noise: None,
+            leader: None,
+            eta: None,
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum QueryKillStatus {
     #[error("failed to kill a query: {0} does not exist.")]
     NoSuchQuery(QueryId),
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum DeleteInputError {
+    #[error("the input of query {query_id} can't be deleted while the query is {status}")]
+    QueryInProgress {
+        query_id: QueryId,
+        status: QueryStatus,
+    },
+    #[error("failed to delete the input of a query: {0}")]
+    Io(#[from] std::io::Error),
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::{array, future::Future, sync::Arc};
+    use std::{
+        array,
+        collections::BTreeMap,
+        future::Future,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
     use futures::pin_mut;
     use futures_util::future::poll_immediate;
@@ -568,19 +1547,25 @@ mod tests {
         ff::{boolean_array::BA64, FieldType},
         helpers::{
             make_owned_handler,
-            query::{PrepareQuery, QueryConfig, QueryType::TestMultiply},
-            routing::Addr,
+            query::{
+                CreateFromTemplate, IpaQueryConfig, PeerUnavailable, PrepareQuery, QueryConfig,
+                QueryPolicy, QueryTemplate, QueryTemplates, QueryType, QueryType::TestMultiply,
+                TemplateError, TemplateList, TemplateOverrides,
+            },
+            routing::{Addr, RouteId},
             ApiError, HandlerBox, HelperIdentity, HelperResponse, InMemoryMpcNetwork,
-            InMemoryShardNetwork, InMemoryTransport, RequestHandler, RoleAssignment, Transport,
-            TransportIdentity,
+            InMemoryShardNetwork, InMemoryTransport, RequestHandler, Role, RoleAssignment,
+            StageConcurrency, Transport, TransportIdentity,
         },
-        protocol::QueryId,
+        protocol::{ProtocolVersion, QueryId},
         query::{
+            placement::{LinkMeasurement, NetworkMeasurements},
             processor::Processor,
             state::{QueryState, RunningQuery, StateError},
-            NewQueryError, PrepareQueryError, QueryStatus, QueryStatusError,
+            NewQueryError, PrepareQueryError, QueryExecutors, QueryStatus, QueryStatusError,
         },
         sharding::ShardIndex,
+        telemetry::progress::QueryProgress,
     };
 
     fn prepare_query() -> PrepareQuery {
@@ -588,9 +1573,19 @@ mod tests {
             query_id: QueryId,
             config: test_multiply_config(),
             roles: RoleAssignment::new(HelperIdentity::make_three()),
+            protocol_version: ProtocolVersion::CURRENT,
+            pull_receivers: Vec::new(),
+            stage_concurrency: StageConcurrency::default(),
         }
     }
 
+    /// Id of the only query `processor` keeps track of, if there is one.
+    fn only_query_id(processor: &Processor) -> Option<QueryId> {
+        let queries = processor.queries.inner.lock().unwrap();
+        assert!(queries.len() <= 1, "more than one query is registered");
+        queries.keys().next().copied()
+    }
+
     fn create_handler<F, Fut, I: TransportIdentity>(cb: F) -> Arc<dyn RequestHandler<I>>
     where
         F: Fn(Addr<I>) -> Fut + Send + Sync + 'static,
@@ -728,29 +1723,64 @@ mod tests {
         /// This initiates a new query on all shards and puts them all on running state.
         /// It also makes up a fake query result
         async fn new_running_query(&self) -> QueryId {
-            self.processor
+            let query_id = self
+                .processor
                 .new_query(
                     self.first_transport.clone_ref(),
                     self.shard_transport.clone_ref(),
                     self.query_config,
                 )
                 .await
-                .unwrap();
+                .unwrap()
+                .query_id;
             let (tx, rx) = tokio::sync::oneshot::channel();
             self.processor
                 .queries
-                .handle(QueryId)
+                .handle(query_id)
                 .set_state(QueryState::Running(RunningQuery {
+                    config: self.query_config,
+                    leader: HelperIdentity::ONE,
                     result: rx,
                     join_handle: IpaRuntime::current().spawn(async {}),
+                    tuning_report: Arc::default(),
+                    send_buffers: Arc::default(),
+                    progress: QueryProgress::default(),
                 }))
                 .unwrap();
             tx.send(Ok(Box::new(Self::COMPLETE_QUERY_RESULT))).unwrap();
 
-            QueryId
+            query_id
         }
     }
 
+    #[test]
+    fn validate_query() {
+        let processor = Processor::default().with_policy(QueryPolicy {
+            min_epsilon: Some(1.0),
+            max_breakdown_key: None,
+            max_channels: None,
+        });
+        assert!(processor.validate_query(&test_multiply_config()).is_valid());
+
+        let ipa_config = QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                epsilon: 0.5,
+                ..IpaQueryConfig::default()
+            }),
+            FieldType::Fp32BitPrime,
+            1,
+        )
+        .unwrap();
+        let report = processor.validate_query(&ipa_config);
+        assert_eq!(1, report.problems.len());
+        assert_eq!("epsilon", report.problems[0].parameter);
+
+        let processor = processor.with_executors(QueryExecutors::empty());
+        let report = processor.validate_query(&test_multiply_config());
+        assert_eq!(1, report.problems.len());
+        assert_eq!("query_type", report.problems[0].parameter);
+    }
+
     #[tokio::test]
     async fn new_query() {
         let mut args = TestComponentsArgs::default();
@@ -783,10 +1813,11 @@ mod tests {
         // poll future once to trigger query status change
         let _qc = poll_immediate(&mut qc_future).await;
 
+        let query_id = only_query_id(&t.processor).unwrap();
         assert_eq!(
             QueryStatus::Preparing,
             t.processor
-                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .query_status(t.shard_transport.clone_ref(), query_id)
                 .await
                 .unwrap()
         );
@@ -798,26 +1829,69 @@ mod tests {
 
         assert_eq!(
             PrepareQuery {
-                query_id: QueryId,
+                query_id,
                 config: t.query_config,
                 roles: expected_assignment,
+                protocol_version: ProtocolVersion::CURRENT,
+                pull_receivers: Vec::new(),
+                stage_concurrency: StageConcurrency::default(),
             },
             qc
         );
         assert_eq!(
             QueryStatus::AwaitingInputs,
             t.processor
-                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .query_status(t.shard_transport.clone_ref(), query_id)
                 .await
                 .unwrap()
         );
     }
 
     #[tokio::test]
-    async fn rejects_duplicate_query_id() {
+    async fn new_query_assigns_roles_from_network_measurements() {
+        let link = |a: i32, b: i32, rtt_ms, bandwidth_mbps| LinkMeasurement {
+            helpers: [HelperIdentity::from(a), HelperIdentity::from(b)],
+            rtt_ms,
+            bandwidth_mbps,
+        };
+        let mut t = TestComponents::new(TestComponentsArgs::default());
+        // The link between helpers 1 and 2 is slow, so helper 2 should take H3: H1 and H3
+        // exchange the least data.
+        t.processor = Processor::default().with_network_measurements(
+            NetworkMeasurements::new([
+                link(1, 2, 150.0, 100.0),
+                link(2, 3, 10.0, 1_000.0),
+                link(1, 3, 10.0, 1_000.0),
+            ])
+            .unwrap(),
+        );
+        let config = QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+            FieldType::Fp32BitPrime,
+            1_000_000,
+        )
+        .unwrap();
+
+        let qc = t
+            .processor
+            .new_query(t.first_transport, t.shard_transport.clone_ref(), config)
+            .await
+            .unwrap();
+        assert_eq!(
+            RoleAssignment::new([
+                HelperIdentity::ONE,
+                HelperIdentity::THREE,
+                HelperIdentity::TWO
+            ]),
+            qc.roles
+        );
+    }
+
+    #[tokio::test]
+    async fn assigns_unique_query_ids() {
         let t = TestComponents::new(TestComponentsArgs::default());
         let st = t.shard_transport;
-        let _ = t
+        let first = t
             .processor
             .new_query(
                 Transport::clone_ref(&t.first_transport),
@@ -826,18 +1900,108 @@ mod tests {
             )
             .await
             .unwrap();
+        let second = t
+            .processor
+            .new_query(t.first_transport, st, t.query_config)
+            .await
+            .unwrap();
+        assert_ne!(first.query_id, second.query_id);
+        assert_eq!(2, t.processor.queries.inner.lock().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_query_type() {
+        let mut t = TestComponents::new(TestComponentsArgs::default());
+        t.processor = Processor::default().with_executors(QueryExecutors::empty());
+        assert!(matches!(
+            t.processor
+                .new_query(t.first_transport, t.shard_transport, t.query_config)
+                .await,
+            Err(NewQueryError::UnsupportedQueryType(_)),
+        ));
+        assert!(t.processor.queries.inner.lock().unwrap().is_empty());
+    }
+
+    /// Queries that don't pass [`Processor::validate_query`] are rejected when they are created,
+    /// not only on dry runs.
+    #[tokio::test]
+    async fn rejects_invalid_query() {
+        let mut t = TestComponents::new(TestComponentsArgs::default());
+        t.processor = Processor::default().with_policy(QueryPolicy {
+            min_epsilon: Some(1.0),
+            ..QueryPolicy::default()
+        });
+        let config = QueryConfig::new(
+            QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                epsilon: 0.5,
+                ..IpaQueryConfig::default()
+            }),
+            FieldType::Fp32BitPrime,
+            1,
+        )
+        .unwrap();
         assert!(matches!(
             t.processor
-                .new_query(t.first_transport, st, t.query_config)
+                .new_query(t.first_transport, t.shard_transport, config)
+                .await,
+            Err(NewQueryError::Invalid(report)) if report.problems[0].parameter == "epsilon",
+        ));
+        assert!(t.processor.queries.inner.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn templates_required() {
+        let mut t = TestComponents::new(TestComponentsArgs::default());
+        let template = QueryTemplate {
+            field_type: t.query_config.field_type,
+            query_type: t.query_config.query_type,
+        };
+        t.processor = Processor::default().with_templates(
+            QueryTemplates::new(
+                TemplateList(BTreeMap::from([("multiply".to_string(), template)])),
+                true,
+            )
+            .unwrap(),
+        );
+        assert!(matches!(
+            t.processor
+                .new_query(
+                    Transport::clone_ref(&t.first_transport),
+                    t.shard_transport.clone_ref(),
+                    t.query_config,
+                )
                 .await,
-            Err(NewQueryError::State(StateError::AlreadyRunning)),
+            Err(NewQueryError::Template(TemplateError::Required)),
         ));
+
+        let qc = t
+            .processor
+            .new_query_from_template(
+                t.first_transport,
+                t.shard_transport,
+                CreateFromTemplate {
+                    template_id: "multiply".to_string(),
+                    overrides: TemplateOverrides::new(t.query_config.size),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(t.query_config, qc.config);
     }
 
     #[tokio::test]
     async fn prepare_error() {
         let mut args = TestComponentsArgs::default();
-        let h2 = helper_respond_ok();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let h2 = create_handler(move |req: Addr<HelperIdentity>| {
+            let tx = tx.clone();
+            async move {
+                if req.route == RouteId::PeerUnavailable {
+                    tx.send(req.into::<PeerUnavailable>().unwrap()).unwrap();
+                }
+                Ok(HelperResponse::ok())
+            }
+        });
         let h3 = create_handler(|_| async move {
             Err(ApiError::QueryPrepare(PrepareQueryError::WrongTarget))
         });
@@ -850,6 +2014,47 @@ mod tests {
                 .unwrap_err(),
             NewQueryError::MpcTransport(_)
         ));
+        // H2 accepted the query, so it learns that it can't run.
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            PeerUnavailable { role: Role::H3, .. }
+        ));
+        assert_eq!(None, only_query_id(&t.processor));
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "shuttle"))]
+    async fn prepare_timeout() {
+        let mut args = TestComponentsArgs::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let h2 = create_handler(move |req: Addr<HelperIdentity>| {
+            let tx = tx.clone();
+            async move {
+                if req.route == RouteId::PeerUnavailable {
+                    tx.send(req.into::<PeerUnavailable>().unwrap()).unwrap();
+                }
+                Ok(HelperResponse::ok())
+            }
+        });
+        // H3 is down and never answers.
+        let h3 = create_handler(|_| futures::future::pending::<Result<HelperResponse, ApiError>>());
+        args.mpc_handlers = [None, Some(h2), Some(h3)];
+        let mut t = TestComponents::new(args);
+        t.processor = Processor::default().with_peer_timeout(Some(Duration::from_millis(10)));
+
+        assert!(matches!(
+            t.processor
+                .new_query(t.first_transport, t.shard_transport, t.query_config)
+                .await
+                .unwrap_err(),
+            NewQueryError::HelperUnavailable(Role::H3)
+        ));
+        // H2 accepted the query, so it learns that it can't run.
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            PeerUnavailable { role: Role::H3, .. }
+        ));
+        assert_eq!(None, only_query_id(&t.processor));
     }
 
     /// Context:
@@ -892,13 +2097,7 @@ mod tests {
                 panic!("Unexpected error type");
             }
         }
-        assert!(matches!(
-            t.processor
-                .query_status(t.shard_transport, QueryId)
-                .await
-                .unwrap_err(),
-            QueryStatusError::NoSuchQuery(_)
-        ));
+        assert_eq!(None, only_query_id(&t.processor));
     }
 
     /// Context:
@@ -932,7 +2131,7 @@ mod tests {
         ));
 
         // We check the internal state of the processor
-        assert!(t.processor.get_status(QueryId).is_none());
+        assert_eq!(None, only_query_id(&t.processor));
     }
 
     mod complete {
@@ -965,7 +2164,7 @@ mod tests {
         }
 
         #[tokio::test]
-        #[should_panic(expected = "QueryCompletion(NoSuchQuery(QueryId))")]
+        #[should_panic(expected = "QueryCompletion(NoSuchQuery(QueryId(0)))")]
         async fn complete_one_shard_fails() {
             let mut args = TestComponentsArgs::default();
 
@@ -1041,6 +2240,60 @@ mod tests {
             );
         }
 
+        #[tokio::test]
+        async fn records_leader() {
+            let t = TestComponents::default();
+            // Any helper can create queries, here it is the third one.
+            let req = PrepareQuery {
+                roles: RoleAssignment::new([
+                    HelperIdentity::THREE,
+                    HelperIdentity::ONE,
+                    HelperIdentity::TWO,
+                ]),
+                ..prepare_query()
+            };
+            t.processor
+                .prepare_helper(t.first_transport, t.shard_transport, req)
+                .await
+                .unwrap();
+            assert_eq!(Some(HelperIdentity::THREE), t.processor.leader(QueryId));
+        }
+
+        #[tokio::test]
+        async fn rejects_without_template() {
+            let mut t = TestComponents::new(TestComponentsArgs::default());
+            let template = QueryTemplate {
+                field_type: FieldType::Fp32BitPrime,
+                query_type: TestMultiply,
+            };
+            t.processor = Processor::default().with_templates(
+                QueryTemplates::new(
+                    TemplateList(BTreeMap::from([("multiply".to_string(), template)])),
+                    true,
+                )
+                .unwrap(),
+            );
+            assert!(matches!(
+                t.processor
+                    .prepare_helper(
+                        Transport::clone_ref(&t.second_transport),
+                        t.shard_transport.clone_ref(),
+                        prepare_query(),
+                    )
+                    .await,
+                Err(PrepareQueryError::Template(TemplateError::Required))
+            ));
+
+            let req = PrepareQuery {
+                config: QueryConfig::new(TestMultiply, FieldType::Fp32BitPrime, 1).unwrap(),
+                ..prepare_query()
+            };
+            t.processor
+                .prepare_helper(t.second_transport, t.shard_transport, req)
+                .await
+                .unwrap();
+        }
+
         #[tokio::test]
         async fn rejects_if_coordinator() {
             let req = prepare_query();
@@ -1075,70 +2328,527 @@ mod tests {
             ));
         }
 
-        /// Context:
-        /// * From the standpoint of the leader shard in Helper 2
-        ///
-        /// This test makes sure that an error is returned if I get a [`Processor::prepare_shard`]
-        /// call. Only non-leaders (1,2,3...) should handle those calls.
+        /// Context:
+        /// * From the standpoint of the leader shard in Helper 2
+        ///
+        /// This test makes sure that an error is returned if I get a [`Processor::prepare_shard`]
+        /// call. Only non-leaders (1,2,3...) should handle those calls.
+        #[tokio::test]
+        async fn shard_not_leader() {
+            let req = prepare_query();
+            let t = TestComponents::new(TestComponentsArgs::default());
+            assert!(matches!(
+                t.processor
+                    .prepare_shard(
+                        &t.shard_network
+                            .transport(HelperIdentity::TWO, ShardIndex::FIRST),
+                        req
+                    )
+                    .unwrap_err(),
+                PrepareQueryError::Leader
+            ));
+        }
+
+        /// Helpers and shards must refuse to take part in a query that runs a protocol version
+        /// they don't support. The query must not be registered in that case.
+        #[tokio::test]
+        async fn rejects_unsupported_protocol_version() {
+            let req = PrepareQuery {
+                protocol_version: ProtocolVersion::new(4),
+                ..prepare_query()
+            };
+            let t = TestComponents::new(TestComponentsArgs::default());
+            assert!(matches!(
+                t.processor
+                    .prepare_helper(
+                        t.second_transport,
+                        t.shard_transport.clone_ref(),
+                        req.clone()
+                    )
+                    .await,
+                Err(PrepareQueryError::UnsupportedProtocolVersion(_))
+            ));
+            assert!(matches!(
+                t.processor.prepare_shard(
+                    &t.shard_network
+                        .transport(HelperIdentity::TWO, ShardIndex::from(1)),
+                    req
+                ),
+                Err(PrepareQueryError::UnsupportedProtocolVersion(_))
+            ));
+            assert!(t.processor.get_status(QueryId).is_none());
+        }
+
+        #[tokio::test]
+        async fn rejects_unsupported_query_type() {
+            let req = prepare_query();
+            let mut t = TestComponents::new(TestComponentsArgs::default());
+            t.processor = Processor::default().with_executors(QueryExecutors::empty());
+            assert!(matches!(
+                t.processor
+                    .prepare_helper(
+                        t.second_transport,
+                        t.shard_transport.clone_ref(),
+                        req.clone()
+                    )
+                    .await,
+                Err(PrepareQueryError::UnsupportedQueryType(_))
+            ));
+            assert!(matches!(
+                t.processor.prepare_shard(
+                    &t.shard_network
+                        .transport(HelperIdentity::TWO, ShardIndex::from(1)),
+                    req
+                ),
+                Err(PrepareQueryError::UnsupportedQueryType(_))
+            ));
+            assert!(t.processor.get_status(QueryId).is_none());
+        }
+
+        /// Followers validate the query against their own policy, which may be stricter than the
+        /// policy of the helper that created it.
+        #[tokio::test]
+        async fn rejects_invalid_query() {
+            let req = PrepareQuery {
+                config: QueryConfig::new(
+                    QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                        max_breakdown_key: 20,
+                        ..IpaQueryConfig::default()
+                    }),
+                    FieldType::Fp32BitPrime,
+                    1,
+                )
+                .unwrap(),
+                ..prepare_query()
+            };
+            let mut t = TestComponents::new(TestComponentsArgs::default());
+            t.processor = Processor::default().with_policy(QueryPolicy {
+                max_breakdown_key: Some(16),
+                ..QueryPolicy::default()
+            });
+            assert!(matches!(
+                t.processor
+                    .prepare_helper(t.second_transport, t.shard_transport.clone_ref(), req)
+                    .await,
+                Err(PrepareQueryError::Invalid(report))
+                    if report.problems[0].parameter == "max_breakdown_key"
+            ));
+            assert!(t.processor.get_status(QueryId).is_none());
+        }
+
+        /// Helpers must refuse to take part in a query that needs more channels than their
+        /// policy allows.
+        #[tokio::test]
+        async fn rejects_too_many_channels() {
+            let req = prepare_query();
+            let mut t = TestComponents::new(TestComponentsArgs::default());
+            t.processor = Processor::default().with_policy(QueryPolicy {
+                max_channels: Some(req.config.estimated_channels() - 1),
+                ..QueryPolicy::default()
+            });
+            assert!(matches!(
+                t.processor
+                    .prepare_helper(t.second_transport, t.shard_transport.clone_ref(), req)
+                    .await,
+                Err(PrepareQueryError::TooManyChannels(_))
+            ));
+            assert!(t.processor.get_status(QueryId).is_none());
+        }
+
+        /// This tests that both [`Processor::prepare_helper`] and [`Processor::prepare_shard`]
+        /// return an [`PrepareQueryError::AlreadyRunning`] error if the internal processor state
+        /// already has a running query.
+        #[tokio::test]
+        async fn rejects_if_query_exists() {
+            let req = prepare_query();
+            let t = TestComponents::new(TestComponentsArgs::default());
+            // We set the processor to run a query so that subsequent calls fail.
+            t.processor
+                .prepare_helper(
+                    t.second_transport.clone_ref(),
+                    t.shard_transport.clone_ref(),
+                    req.clone(),
+                )
+                .await
+                .unwrap();
+
+            // both helper and shard APIs should fail
+            assert!(matches!(
+                t.processor
+                    .prepare_helper(
+                        t.second_transport,
+                        t.shard_transport.clone_ref(),
+                        req.clone()
+                    )
+                    .await,
+                Err(PrepareQueryError::AlreadyRunning)
+            ));
+            assert!(matches!(
+                t.processor.prepare_shard(
+                    &t.shard_network
+                        .transport(HelperIdentity::ONE, ShardIndex::from(1)),
+                    req
+                ),
+                Err(PrepareQueryError::AlreadyRunning)
+            ));
+        }
+    }
+
+    mod receive_inputs {
+        use std::num::NonZeroU32;
+
+        use super::*;
+        use crate::{
+            helpers::{
+                query::{AppendInput, InputContribution, SealInput},
+                BodyStream,
+            },
+            query::{
+                batches::{BatchError, MAX_INPUT_BATCHES},
+                contributions::ContributionError,
+            },
+        };
+
+        fn contribution(contributor: u32, count: u32) -> InputContribution {
+            InputContribution {
+                contributor,
+                contributors: NonZeroU32::new(2).unwrap(),
+                count,
+                shuffle_seed: None,
+            }
+        }
+
+        async fn prepared() -> TestComponents {
+            prepared_with(test_multiply_config()).await
+        }
+
+        async fn prepared_with(config: QueryConfig) -> TestComponents {
+            let t = TestComponents::default();
+            t.processor
+                .prepare_helper(
+                    t.second_transport.clone_ref(),
+                    t.shard_transport.clone_ref(),
+                    PrepareQuery {
+                        config,
+                        ..prepare_query()
+                    },
+                )
+                .await
+                .unwrap();
+            t
+        }
+
+        /// Queries whose input can be contributed in slices.
+        fn ipa_config() -> QueryConfig {
+            QueryConfig::new(
+                QueryType::MaliciousOprfIpa(IpaQueryConfig::default()),
+                FieldType::Fp32BitPrime,
+                1,
+            )
+            .unwrap()
+        }
+
+        fn receive(
+            t: &TestComponents,
+            contribution: InputContribution,
+        ) -> Result<(), QueryInputError> {
+            t.processor.receive_inputs(
+                t.second_transport.clone_ref(),
+                t.shard_transport.clone_ref(),
+                QueryInput {
+                    query_id: QueryId,
+                    input_stream: BodyStream::empty(),
+                    contribution: Some(contribution),
+                },
+            )
+        }
+
+        async fn status(t: &TestComponents) -> QueryStatus {
+            t.processor
+                .query_status(t.shard_transport.clone_ref(), QueryId)
+                .await
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn waits_for_all_contributors() {
+            let t = prepared_with(ipa_config()).await;
+            receive(&t, contribution(1, 1)).unwrap();
+            assert_eq!(QueryStatus::AwaitingInputs, status(&t).await);
+            assert!(matches!(
+                receive(&t, contribution(1, 1)),
+                Err(QueryInputError::Contribution(
+                    ContributionError::DuplicateContributor(1)
+                ))
+            ));
+            assert_eq!(QueryStatus::AwaitingInputs, status(&t).await);
+        }
+
+        #[tokio::test]
+        async fn rejects_unsupported_query() {
+            let t = prepared().await;
+            assert!(matches!(
+                receive(&t, contribution(1, 1)),
+                Err(QueryInputError::Contribution(
+                    ContributionError::UnsupportedQuery(_)
+                ))
+            ));
+            assert_eq!(QueryStatus::AwaitingInputs, status(&t).await);
+        }
+
+        #[tokio::test]
+        async fn rejects_count_mismatch() {
+            let t = prepared_with(ipa_config()).await;
+            receive(&t, contribution(1, 1)).unwrap();
+            assert!(matches!(
+                receive(&t, contribution(2, 1)),
+                Err(QueryInputError::Contribution(
+                    ContributionError::CountMismatch {
+                        declared: 2,
+                        expected: 1
+                    }
+                ))
+            ));
+            // contributors can start over
+            receive(&t, contribution(1, 1)).unwrap();
+            assert_eq!(QueryStatus::AwaitingInputs, status(&t).await);
+        }
+
+        fn seal(t: &TestComponents, count: u32) -> Result<(), QueryInputError> {
+            t.processor.seal_inputs(
+                t.second_transport.clone_ref(),
+                t.shard_transport.clone_ref(),
+                QueryId,
+                SealInput {
+                    count: count.try_into().unwrap(),
+                },
+            )
+        }
+
+        async fn append(t: &TestComponents, reports: u32) -> Result<(), QueryInputError> {
+            t.processor
+                .append_inputs(QueryId, AppendInput { reports }, BodyStream::empty())
+                .await
+        }
+
+        #[tokio::test]
+        async fn append_until_sealed() {
+            let t = prepared().await;
+            for _ in 0..3 {
+                append(&t, 1).await.unwrap();
+                assert_eq!(QueryStatus::AwaitingInputs, status(&t).await);
+            }
+            // batches can't be mixed with other ways to upload the input
+            assert!(matches!(
+                receive(&t, contribution(1, 1)),
+                Err(QueryInputError::StateError {
+                    source: StateError::InvalidState { .. }
+                })
+            ));
+
+            seal(&t, 3).unwrap();
+            let queries = t.processor.queries.inner.lock().unwrap();
+            let Some(QueryState::Running(running)) = queries.get(&QueryId) else {
+                panic!("query is not running");
+            };
+            assert_eq!(3, u32::from(running.config.size));
+        }
+
+        #[tokio::test]
+        async fn seal_requires_batches() {
+            let t = prepared().await;
+            assert!(matches!(
+                seal(&t, 1),
+                Err(QueryInputError::StateError {
+                    source: StateError::InvalidState {
+                        from: QueryStatus::AwaitingInputs,
+                        to: QueryStatus::Running
+                    }
+                })
+            ));
+            assert!(matches!(
+                seal(&TestComponents::default(), 1),
+                Err(QueryInputError::NoSuchQuery(_))
+            ));
+
+            // sealed input can't be appended to
+            append(&t, 1).await.unwrap();
+            seal(&t, 1).unwrap();
+            assert!(matches!(
+                append(&t, 1).await,
+                Err(QueryInputError::StateError { .. })
+            ));
+        }
+
+        #[tokio::test]
+        async fn seal_rejects_count_mismatch() {
+            let t = prepared().await;
+            append(&t, 2).await.unwrap();
+            append(&t, 1).await.unwrap();
+            assert!(matches!(
+                seal(&t, 4),
+                Err(QueryInputError::Batch(BatchError::CountMismatch {
+                    declared: 3,
+                    sealed: 4
+                }))
+            ));
+            // the batches are kept, so the input can be sealed with the right count
+            assert_eq!(QueryStatus::AwaitingInputs, status(&t).await);
+            seal(&t, 3).unwrap();
+        }
+
+        #[tokio::test]
+        async fn append_rejects_too_many_batches() {
+            let t = prepared().await;
+            for _ in 0..MAX_INPUT_BATCHES {
+                append(&t, 1).await.unwrap();
+            }
+            assert!(matches!(
+                append(&t, 1).await,
+                Err(QueryInputError::Batch(BatchError::TooManyBatches))
+            ));
+        }
+    }
+
+    mod query_status {
+
+        use super::*;
+        use crate::{
+            helpers::query::{CompareStatusRequest, DpMechanism},
+            protocol::{dp::NoiseReport, QueryId},
+            query::{PrivacyParams, ProtocolResult, Redaction},
+            telemetry::{
+                progress::{self, QueryProgress},
+                send_buffers::SendBufferStatus,
+                tuning::TuningReport,
+            },
+        };
+
+        #[tokio::test]
+        async fn privacy_params() {
+            let t = TestComponents::new(TestComponentsArgs::default());
+            assert_eq!(None, t.processor.privacy_params(QueryId));
+
+            let query_id = t.new_running_query().await;
+            assert_eq!(
+                Some(PrivacyParams::new(&t.query_config, &Redaction::default())),
+                t.processor.privacy_params(query_id)
+            );
+        }
+
         #[tokio::test]
-        async fn shard_not_leader() {
-            let req = prepare_query();
+        async fn tuning_report() {
             let t = TestComponents::new(TestComponentsArgs::default());
-            assert!(matches!(
-                t.processor
-                    .prepare_shard(
-                        &t.shard_network
-                            .transport(HelperIdentity::TWO, ShardIndex::FIRST),
-                        req
-                    )
-                    .unwrap_err(),
-                PrepareQueryError::Leader
-            ));
+            let query_id = t.new_running_query().await;
+            if let Some(QueryState::Running(running)) =
+                t.processor.queries.inner.lock().unwrap().get(&query_id)
+            {
+                *running.tuning_report.lock().unwrap() = Some(TuningReport::default());
+            }
+            assert_eq!(None, t.processor.tuning_report(query_id));
+
+            assert_eq!(
+                Some(QueryStatus::Completed),
+                t.processor.get_status(query_id)
+            );
+            assert_eq!(
+                Some(TuningReport::default()),
+                t.processor.tuning_report(query_id)
+            );
         }
 
-        /// This tests that both [`Processor::prepare_helper`] and [`Processor::prepare_shard`]
-        /// return an [`PrepareQueryError::AlreadyRunning`] error if the internal processor state
-        /// already has a running query.
         #[tokio::test]
-        async fn rejects_if_query_exists() {
-            let req = prepare_query();
-            let t = TestComponents::new(TestComponentsArgs::default());
-            // We set the processor to run a query so that subsequent calls fail.
-            t.processor
-                .prepare_helper(
-                    t.second_transport.clone_ref(),
-                    t.shard_transport.clone_ref(),
-                    req.clone(),
-                )
-                .await
+        async fn noise() {
+            #[derive(Debug)]
+            struct Noisy(NoiseReport);
+
+            impl ProtocolResult for Noisy {
+                fn to_bytes(&self) -> Vec<u8> {
+                    Vec::new()
+                }
+
+                fn noise(&self) -> Option<NoiseReport> {
+                    Some(self.0.clone())
+                }
+            }
+
+            let report = NoiseReport::new::<3>(DpMechanism::Binomial { epsilon: 1.0 }, 8, 1)
+                .unwrap()
                 .unwrap();
+            let t = TestComponents::new(TestComponentsArgs::default());
+            let query_id = t.new_running_query().await;
+            assert_eq!(None, t.processor.noise(query_id));
 
-            // both helper and shard APIs should fail
-            assert!(matches!(
-                t.processor
-                    .prepare_helper(
-                        t.second_transport,
-                        t.shard_transport.clone_ref(),
-                        req.clone()
-                    )
-                    .await,
-                Err(PrepareQueryError::AlreadyRunning)
-            ));
-            assert!(matches!(
-                t.processor.prepare_shard(
-                    &t.shard_network
-                        .transport(HelperIdentity::ONE, ShardIndex::from(1)),
-                    req
-                ),
-                Err(PrepareQueryError::AlreadyRunning)
-            ));
+            // Results without noise don't report any.
+            assert_eq!(
+                Some(QueryStatus::Completed),
+                t.processor.get_status(query_id)
+            );
+            assert_eq!(None, t.processor.noise(query_id));
+
+            t.processor.queries.inner.lock().unwrap().insert(
+                query_id,
+                QueryState::Completed(Ok(Box::new(Noisy(report.clone()))), None, Instant::now()),
+            );
+            assert_eq!(Some(report), t.processor.noise(query_id));
         }
-    }
 
-    mod query_status {
+        #[tokio::test]
+        async fn send_buffers() {
+            let t = TestComponents::new(TestComponentsArgs::default());
+            assert_eq!(None, t.processor.send_buffers(QueryId));
 
-        use super::*;
-        use crate::{helpers::query::CompareStatusRequest, protocol::QueryId};
+            let query_id = t.new_running_query().await;
+            let buffer = SendBufferStatus {
+                step: "protocol/step".to_string(),
+                peer: "H2".to_string(),
+                fill: 1,
+                capacity: 8,
+                pending_record: 2,
+                total_records: Some(10),
+                idle_ms: Some(100),
+            };
+            if let Some(QueryState::Running(running)) =
+                t.processor.queries.inner.lock().unwrap().get(&query_id)
+            {
+                *running.send_buffers.lock().unwrap() = vec![buffer.clone()];
+            }
+            assert_eq!(Some(vec![buffer]), t.processor.send_buffers(query_id));
+
+            assert_eq!(
+                Some(QueryStatus::Completed),
+                t.processor.get_status(query_id)
+            );
+            assert_eq!(None, t.processor.send_buffers(query_id));
+        }
+
+        #[tokio::test]
+        async fn eta() {
+            let t = TestComponents::new(TestComponentsArgs::default());
+            assert_eq!(None, t.processor.eta(QueryId));
+
+            let query_id = t.new_running_query().await;
+            let query_progress = QueryProgress::default();
+            query_progress
+                .scope(async {
+                    progress::plan([("a", 10), ("b", 10)]);
+                    progress::stage("a", async {}).await;
+                })
+                .await;
+            if let Some(QueryState::Running(running)) =
+                t.processor.queries.inner.lock().unwrap().get_mut(&query_id)
+            {
+                running.progress = query_progress;
+            }
+            let eta = t.processor.eta(query_id).unwrap();
+            assert_eq!((1, 2), (eta.completed_stages, eta.planned_stages));
+
+            assert_eq!(
+                Some(QueryStatus::Completed),
+                t.processor.get_status(query_id)
+            );
+            assert_eq!(None, t.processor.eta(query_id));
+        }
 
         /// * From the standpoint of leader shard in Helper 1
         /// * On query_status
@@ -1207,7 +2917,7 @@ mod tests {
         /// return an error despite other shards returning their status
         #[tokio::test]
         #[should_panic(
-            expected = "(ShardIndex(3), Rejected { dest: ShardIndex(3), inner: QueryStatus(NoSuchQuery(QueryId)) })"
+            expected = "(ShardIndex(3), Rejected { dest: ShardIndex(3), inner: QueryStatus(NoSuchQuery(QueryId(0))) })"
         )]
         async fn status_query_doesnt_exist() {
             fn shard_handle(si: ShardIndex) -> Arc<dyn RequestHandler<ShardIndex>> {
@@ -1301,13 +3011,14 @@ mod tests {
         use super::{TestComponents, TestComponentsArgs};
         use crate::{
             executor::IpaRuntime,
-            helpers::Transport,
+            helpers::{HelperIdentity, Transport},
             protocol::QueryId,
             query::{
                 processor::Processor,
                 state::{QueryState, RunningQuery},
                 QueryKillStatus,
             },
+            telemetry::progress::QueryProgress,
             test_executor::run,
         };
 
@@ -1328,16 +3039,18 @@ mod tests {
                 let mut args = TestComponentsArgs::default();
                 args.mpc_handlers[0].take();
                 let t = TestComponents::new(args);
-                t.processor
+                let query_id = t
+                    .processor
                     .new_query(
                         t.first_transport.clone_ref(),
                         t.shard_transport.clone_ref(),
                         t.query_config,
                     )
                     .await
-                    .unwrap();
+                    .unwrap()
+                    .query_id;
 
-                t.processor.kill(QueryId).unwrap();
+                t.processor.kill(query_id).unwrap();
 
                 // start query again - it should work because the query was killed
                 t.processor
@@ -1365,8 +3078,13 @@ mod tests {
                 processor.queries.inner.lock().unwrap().insert(
                     QueryId,
                     QueryState::Running(RunningQuery {
+                        config: super::test_multiply_config(),
+                        leader: HelperIdentity::ONE,
                         result: rx,
                         join_handle: task,
+                        tuning_report: Arc::default(),
+                        send_buffers: Arc::default(),
+                        progress: QueryProgress::default(),
                     }),
                 );
 
@@ -1379,6 +3097,347 @@ mod tests {
         }
     }
 
+    mod peer_unavailable {
+        use super::{TestComponents, TestComponentsArgs};
+        use crate::{
+            error::Error as ProtocolError,
+            helpers::{query::PeerUnavailable, Role, Transport},
+            protocol::QueryId,
+            query::{QueryCompletionError, QueryKillStatus, QueryStatus},
+            test_executor::run,
+        };
+
+        fn h3_unavailable(query_id: QueryId) -> PeerUnavailable {
+            PeerUnavailable {
+                query_id,
+                role: Role::H3,
+            }
+        }
+
+        #[test]
+        fn non_existent_query() {
+            run(|| async {
+                let t = TestComponents::new(TestComponentsArgs::default());
+                assert!(matches!(
+                    t.processor.peer_unavailable(&h3_unavailable(QueryId)),
+                    Err(QueryKillStatus::NoSuchQuery(QueryId))
+                ));
+            });
+        }
+
+        #[test]
+        fn fails_query() {
+            run(|| async move {
+                let t = TestComponents::new(TestComponentsArgs::default());
+                let query_id = t
+                    .processor
+                    .new_query(
+                        t.first_transport.clone_ref(),
+                        t.shard_transport.clone_ref(),
+                        t.query_config,
+                    )
+                    .await
+                    .unwrap()
+                    .query_id;
+
+                t.processor
+                    .peer_unavailable(&h3_unavailable(query_id))
+                    .unwrap();
+
+                assert_eq!(
+                    QueryStatus::Completed,
+                    t.processor
+                        .query_status(t.shard_transport.clone_ref(), query_id)
+                        .await
+                        .unwrap()
+                );
+                let err = t
+                    .processor
+                    .complete(query_id, t.shard_transport.clone_ref())
+                    .await
+                    .unwrap_err();
+                assert!(matches!(
+                    err,
+                    QueryCompletionError::ExecutionError(ProtocolError::HelperUnavailable(
+                        Role::H3
+                    ))
+                ));
+            });
+        }
+    }
+
+    mod delete_input {
+        use std::{fs, path::Path};
+
+        use super::{prepare_query, TestComponents, TestComponentsArgs};
+        use crate::{
+            helpers::{HelperIdentity, Transport},
+            protocol::QueryId,
+            query::{
+                processor::Processor, state::QueryState, DeleteInputError, InputRetention,
+                PrepareQueryError, Quarantine, QueryStatus, Workspace,
+            },
+            sharding::ShardIndex,
+            test_executor::run,
+        };
+
+        fn processor(dir: &Path, retention: InputRetention) -> Processor {
+            let quarantine = Quarantine::new(dir.to_path_buf());
+            fs::write(quarantine.path(QueryId), b"00\n").unwrap();
+            Processor::default().with_workspace(Workspace::new(Some(quarantine), None, retention))
+        }
+
+        #[test]
+        fn deletes_quarantine() {
+            let dir = tempfile::tempdir().unwrap();
+            let processor = processor(dir.path(), InputRetention::UntilDeleted);
+            let deleted = processor.delete_input(QueryId).unwrap();
+            assert!(deleted.stored);
+            assert!(deleted.quarantine);
+            assert!(!deleted.prf_cache);
+            assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+        }
+
+        #[test]
+        fn refuses_running_query() {
+            let dir = tempfile::tempdir().unwrap();
+            let processor = processor(dir.path(), InputRetention::UntilDeleted);
+            processor
+                .queries
+                .inner
+                .lock()
+                .unwrap()
+                .insert(QueryId, QueryState::AwaitingCompletion);
+
+            assert!(matches!(
+                processor.delete_input(QueryId),
+                Err(DeleteInputError::QueryInProgress {
+                    query_id: QueryId,
+                    status: QueryStatus::AwaitingCompletion,
+                })
+            ));
+            assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+        }
+
+        #[test]
+        fn prepare_rejects_id_with_data() {
+            run(|| async move {
+                let dir = tempfile::tempdir().unwrap();
+                let mut t = TestComponents::new(TestComponentsArgs::default());
+                t.processor = processor(dir.path(), InputRetention::UntilDeleted);
+                let req = prepare_query();
+                assert!(matches!(
+                    t.processor
+                        .prepare_helper(
+                            Transport::clone_ref(&t.second_transport),
+                            t.shard_transport.clone_ref(),
+                            req.clone()
+                        )
+                        .await,
+                    Err(PrepareQueryError::DataExists(QueryId))
+                ));
+                assert!(matches!(
+                    t.processor.prepare_shard(
+                        &t.shard_network
+                            .transport(HelperIdentity::ONE, ShardIndex::from(1)),
+                        req
+                    ),
+                    Err(PrepareQueryError::DataExists(QueryId))
+                ));
+            });
+        }
+
+        #[test]
+        fn retention() {
+            run(|| async move {
+                let dir = tempfile::tempdir().unwrap();
+                let mut args = TestComponentsArgs::default();
+                args.mpc_handlers[0].take();
+                let mut t = TestComponents::new(args);
+                let quarantine = Quarantine::new(dir.path().to_path_buf());
+                t.processor = Processor::default().with_workspace(Workspace::new(
+                    Some(quarantine.clone()),
+                    None,
+                    InputRetention::UntilCompleted,
+                ));
+                let query_id = t
+                    .processor
+                    .new_query(t.first_transport, t.shard_transport, t.query_config)
+                    .await
+                    .unwrap()
+                    .query_id;
+                fs::write(quarantine.path(query_id), b"00\n").unwrap();
+
+                t.processor.kill(query_id).unwrap();
+                assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+            });
+        }
+    }
+
+    mod listing {
+        use std::{
+            fs,
+            num::NonZeroUsize,
+            time::{Duration, Instant},
+        };
+
+        use crate::{
+            error::Error,
+            ff::Fp31,
+            protocol::QueryId,
+            query::{
+                processor::Processor, state::QueryState, InputRetention, ListQueries,
+                ProtocolResult, PruneQueryError, Quarantine, QueryStatus, QuerySummary, Workspace,
+            },
+        };
+
+        fn completed(ok: bool) -> QueryState {
+            let result = if ok {
+                Ok(Box::<Vec<Fp31>>::default() as Box<dyn ProtocolResult>)
+            } else {
+                Err(Error::Internal)
+            };
+            QueryState::Completed(result, None, Instant::now())
+        }
+
+        /// Queries 0 to 4. The odd ones are still running, query 2 failed.
+        fn processor() -> Processor {
+            let processor = Processor::default();
+            let mut queries = processor.queries.inner.lock().unwrap();
+            for id in 0..5 {
+                let state = match id {
+                    1 | 3 => QueryState::AwaitingCompletion,
+                    2 => completed(false),
+                    _ => completed(true),
+                };
+                queries.insert(QueryId::new(id), state);
+            }
+            drop(queries);
+            processor
+        }
+
+        fn ids(processor: &Processor, req: &ListQueries) -> Vec<u32> {
+            processor
+                .list_queries(req)
+                .queries
+                .into_iter()
+                .map(|summary| summary.query_id.to_string().parse().unwrap())
+                .collect()
+        }
+
+        #[test]
+        fn lists_in_order() {
+            let list = processor().list_queries(&ListQueries::default());
+            assert_eq!(None, list.next);
+            assert_eq!(5, list.queries.len());
+            assert_eq!(
+                QuerySummary {
+                    query_id: QueryId::new(2),
+                    status: QueryStatus::Completed,
+                    failed: true,
+                },
+                list.queries[2]
+            );
+        }
+
+        #[test]
+        fn filters_by_state() {
+            let processor = processor();
+            let req = ListQueries {
+                state: Some(QueryStatus::AwaitingCompletion),
+                ..Default::default()
+            };
+            assert_eq!(vec![1, 3], ids(&processor, &req));
+        }
+
+        #[test]
+        fn pages() {
+            let processor = processor();
+            let mut req = ListQueries {
+                limit: NonZeroUsize::new(2),
+                ..Default::default()
+            };
+            let list = processor.list_queries(&req);
+            assert_eq!(Some(QueryId::new(1)), list.next);
+
+            req.after = list.next;
+            assert_eq!(vec![2, 3], ids(&processor, &req));
+            req.after = Some(QueryId::new(3));
+            let list = processor.list_queries(&req);
+            assert_eq!(1, list.queries.len());
+            assert_eq!(None, list.next);
+        }
+
+        #[test]
+        fn prunes_completed() {
+            let dir = tempfile::tempdir().unwrap();
+            let quarantine = Quarantine::new(dir.path().to_path_buf());
+            fs::write(quarantine.path(QueryId), b"00\n").unwrap();
+            let processor = processor().with_workspace(Workspace::new(
+                Some(quarantine),
+                None,
+                InputRetention::UntilDeleted,
+            ));
+
+            let pruned = processor.prune(QueryId).unwrap();
+            assert_eq!(QueryId, pruned.query_id);
+            assert!(pruned.input.quarantine);
+            assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+            assert_eq!(None, processor.get_status(QueryId));
+            assert!(matches!(
+                processor.prune(QueryId),
+                Err(PruneQueryError::NoSuchQuery(QueryId))
+            ));
+        }
+
+        #[test]
+        fn refuses_running_query() {
+            let processor = processor();
+            assert!(matches!(
+                processor.prune(QueryId::new(1)),
+                Err(PruneQueryError::QueryInProgress {
+                    status: QueryStatus::AwaitingCompletion,
+                    ..
+                })
+            ));
+            assert_eq!(
+                Some(QueryStatus::AwaitingCompletion),
+                processor.get_status(QueryId::new(1))
+            );
+        }
+
+        #[test]
+        fn keeps_recent_completed_queries() {
+            let processor = processor().with_max_completed_queries(1);
+            assert_eq!(vec![1, 3, 4], ids(&processor, &ListQueries::default()));
+        }
+
+        #[test]
+        fn prunes_queries_that_finished_first() {
+            let processor = processor().with_max_completed_queries(1);
+            // Query 0 has the lowest id, but finished last.
+            processor.queries.inner.lock().unwrap().insert(
+                QueryId::new(0),
+                QueryState::Completed(
+                    Ok(Box::<Vec<Fp31>>::default()),
+                    None,
+                    Instant::now() + Duration::from_secs(1),
+                ),
+            );
+            assert_eq!(vec![0, 1, 3], ids(&processor, &ListQueries::default()));
+        }
+
+        #[test]
+        fn keeps_queried_status() {
+            let processor = processor().with_max_completed_queries(0);
+            assert_eq!(
+                Some(QueryStatus::Completed),
+                processor.get_status(QueryId::new(2))
+            );
+            assert_eq!(vec![1, 3], ids(&processor, &ListQueries::default()));
+        }
+    }
+
     mod e2e {
         use std::time::Duration;
 
@@ -1386,6 +3445,7 @@ mod tests {
 
         use super::*;
         use crate::{
+            app::AppConfig,
             error::BoxError,
             ff::{
                 boolean_array::{BA20, BA3, BA8},
@@ -1395,6 +3455,7 @@ mod tests {
             protocol::ipa_prf::OPRFIPAInputRow,
             secret_sharing::replicated::semi_honest,
             test_fixture::{ipa::TestRawDataRecord, Reconstruct, TestApp},
+            utils::NonZeroU32PowerOfTwo,
         };
 
         #[tokio::test]
@@ -1449,6 +3510,26 @@ mod tests {
             ipa_query(&app).await
         }
 
+        #[tokio::test]
+        async fn complete_query_ipa_with_different_stage_concurrency() -> Result<(), BoxError> {
+            // Every helper runs the query with the limits of its leader, H1, so the query
+            // completes even though helpers are configured with different limits.
+            let limit = |v: usize| Some(NonZeroU32PowerOfTwo::try_from(v).unwrap());
+            let app = TestApp::with_configs([
+                AppConfig::default().with_stage_concurrency(StageConcurrency {
+                    aggregation: limit(2),
+                    ..StageConcurrency::default()
+                }),
+                AppConfig::default(),
+                AppConfig::default().with_stage_concurrency(StageConcurrency {
+                    prf: limit(2),
+                    aggregation: limit(8),
+                    ..StageConcurrency::default()
+                }),
+            ]);
+            ipa_query(&app).await
+        }
+
         #[tokio::test]
         async fn complete_query_twice() -> Result<(), BoxError> {
             let app = TestApp::default();
@@ -1505,12 +3586,11 @@ mod tests {
                         size: record_count.try_into().unwrap(),
                         field_type: FieldType::Fp31,
                         query_type: QueryType::SemiHonestOprfIpa(IpaQueryConfig {
-                            per_user_credit_cap: 8,
                             max_breakdown_key: 3,
-                            attribution_window_seconds: None,
                             with_dp: 0,
                             epsilon: 5.0,
                             plaintext_match_keys: true,
+                            ..IpaQueryConfig::default()
                         }),
                     },
                 )
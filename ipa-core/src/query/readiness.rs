@@ -0,0 +1,54 @@
+//! Checks that a helper is ready to run queries.
+//!
+//! Orchestrators only send traffic to helpers that report being ready. The query processor
+//! checks what it needs locally: keys to decrypt reports with and a workspace it can write to.
+//! The HTTP layer adds checks for the connectivity to peers.
+
+use serde::{Deserialize, Serialize};
+
+/// Outcome of a single readiness check.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadinessCheck {
+    pub name: String,
+    pub ready: bool,
+    /// Why the check failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ReadinessCheck {
+    #[must_use]
+    pub fn pass(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ready: true,
+            detail: None,
+        }
+    }
+
+    #[must_use]
+    pub fn fail(name: &str, detail: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            ready: false,
+            detail: Some(detail.to_string()),
+        }
+    }
+}
+
+/// Outcome of all the readiness checks of a helper. It is ready if all of them passed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+impl Readiness {
+    #[must_use]
+    pub fn new(checks: Vec<ReadinessCheck>) -> Self {
+        Self {
+            ready: checks.iter().all(|check| check.ready),
+            checks,
+        }
+    }
+}
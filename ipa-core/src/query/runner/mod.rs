@@ -6,6 +6,8 @@ mod reshard_tag;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 mod sharded_shuffle;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+mod share_conversion;
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 mod test_multiply;
 
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
@@ -13,8 +15,12 @@ pub(super) use add_in_prime_field::execute as test_add_in_prime_field;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 pub(super) use sharded_shuffle::execute_sharded_shuffle;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+pub(super) use share_conversion::execute_share_conversion;
+#[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 pub(super) use test_multiply::execute_test_multiply;
 
+#[cfg(all(feature = "embedded", feature = "in-memory-infra"))]
+pub(crate) use self::oprf_ipa::dispatch_input_widths;
 pub use self::{hybrid::execute_hybrid_protocol, oprf_ipa::OprfIpaQuery};
 use crate::{error::Error, query::ProtocolResult};
 
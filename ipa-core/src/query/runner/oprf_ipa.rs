@@ -1,44 +1,204 @@
-use std::{convert::Infallible, marker::PhantomData};
+use std::{
+    collections::BTreeSet,
+    convert::Infallible,
+    marker::PhantomData,
+    num::{NonZeroU32, NonZeroUsize},
+    ops::Add,
+    path::PathBuf,
+};
 
-use futures::{stream::iter, StreamExt, TryStreamExt};
-use futures_util::stream::repeat;
+use futures::{future::ready, stream::iter, StreamExt, TryStreamExt};
+use generic_array::ArrayLength;
+use typenum::{Sum, Unsigned, U16};
 
 use crate::{
     error::{Error, LengthError},
     ff::{
         boolean::Boolean,
-        boolean_array::{BooleanArray, BA20, BA3, BA8},
+        boolean_array::{BooleanArray, BA10, BA12, BA16, BA20, BA24, BA3, BA5, BA8},
         curve_points::RP25519,
         ec_prime_field::Fp25519,
-        Field, Serializable, U128Conversions,
+        ArrayAccess, Field, FieldType, Serializable, U128Conversions,
     },
     helpers::{
-        query::{DpMechanism, IpaQueryConfig, QuerySize},
-        BodyStream, LengthDelimitedStream, RecordsStream,
+        query::{IpaQueryConfig, QueryPolicy, QuerySize, UnsupportedCap, ValidationReport},
+        read_paired_input, read_site_input, read_tagged_input, Arm, BodyStream,
+        LengthDelimitedStream, ManifestStream, RecordFraming, RecordsStream,
     },
     hpke::PrivateKeyRegistry,
     protocol::{
-        basics::{BooleanArrayMul, Reveal, ShareKnownValue},
-        context::{DZKPUpgraded, MacUpgraded, UpgradableContext},
+        basics::{
+            share_validation::cross_check_replicated_shares, BooleanArrayMul, Reveal,
+            ShareKnownValue,
+        },
+        context::{Context, DZKPUpgraded, MacUpgraded, UpgradableContext},
+        dp::NoiseReport,
         ipa_prf::{
-            oprf_ipa, oprf_padding::PaddingParameters, prf_eval::PrfSharing, OPRFIPAInputRow,
-            Shuffle, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
+            oprf_ipa_with_partial_results,
+            oprf_padding::PaddingParameters,
+            prf_eval::PrfSharing,
+            prf_input_rows,
+            prf_sharding::credit_capping::{CappingParameters, PerUserCap},
+            step::IpaPrfStep,
+            AggregationParameters, BreakdownKey, IpaInput, MatchKey, OPRFIPAInputRow, Release,
+            Shuffle, UserSampling, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
         },
         prss::FromPrss,
         step::ProtocolStep::IpaPrf,
-        BooleanProtocols,
+        BooleanProtocols, ProtocolVersion, QueryId,
+    },
+    query::{
+        prf_cache::{agree_on_entry, BudgetScope, InputDigest, Lookup},
+        DecryptionFailures, PrfCache, ProtocolResult,
     },
     report::{EncryptedOprfReport, EventType},
+    results::{ResultHeader, ResultValue},
     secret_sharing::{
         replicated::semi_honest::{AdditiveShare as Replicated, AdditiveShare},
-        BitDecomposed, SharedValue, TransposeFrom, Vectorizable,
+        BitDecomposed, FieldSimd, SharedValue, TransposeFrom, Vectorizable,
     },
     sync::Arc,
 };
 
+/// Output of [`OprfIpaQuery`]. It is serialized with a [`ResultHeader`] in front of the
+/// histogram shares.
+#[derive(Debug)]
+pub struct OprfIpaResult<HV: SharedValue> {
+    pub histogram: Vec<Replicated<HV>>,
+    /// Number of histograms laid out one after the other in `histogram`.
+    pub histograms: u8,
+    /// Set only if the report collector opted into partial results.
+    pub release: Option<Release>,
+    pub protocol_version: ProtocolVersion,
+    pub field_type: FieldType,
+    /// Noise added to the histogram. Not part of the serialized result, it is reported in the
+    /// query status instead.
+    pub noise: Option<NoiseReport>,
+}
+
+impl<HV: ResultValue> ProtocolResult for OprfIpaResult<HV>
+where
+    Vec<Replicated<HV>>: ProtocolResult,
+{
+    fn to_bytes(&self) -> Vec<u8> {
+        let header = ResultHeader {
+            kind: HV::KIND,
+            value_bits: u8::try_from(HV::BITS).expect("histogram values are at most 32 bits wide"),
+            release: self.release,
+            histograms: self.histograms,
+            rows: u32::try_from(self.histogram.len()).expect("histograms fit into u32"),
+            protocol_version: self.protocol_version,
+            field_type: self.field_type,
+        };
+        let mut bytes = header.to_bytes();
+        bytes.extend(self.histogram.to_bytes());
+        bytes
+    }
+
+    fn noise(&self) -> Option<NoiseReport> {
+        self.noise.clone()
+    }
+}
+
+/// Evaluates `$body` with `$ss_bits` bound to the width of the saturating sum that enforces
+/// per-user cap `$cap`, or fails with [`UnsupportedCap`] if there is no instantiation for it.
+/// This is the only place that lists supported caps, [`IpaQueryConfig::MAX_PER_USER_CREDIT_CAP`]
+/// must match its last row.
+///
+/// The saturating sum needs `ceil(log2(cap))` bits, the exact cap is enforced by the capping
+/// circuit. DP noise is calibrated to `2^SS_BITS`, so caps that are not a power of two get
+/// slightly more noise than they need. Uncapped queries skip the capping stage and don't add
+/// noise, so the width does not matter for them.
+macro_rules! dispatch_per_user_cap {
+    ($cap:expr, $ss_bits:ident => $body:expr) => {
+        dispatch_per_user_cap!(@table $cap, $ss_bits, $body,
+            1..=2 => 1,
+            3..=4 => 2,
+            5..=8 => 3,
+            9..=16 => 4,
+            17..=32 => 5,
+            33..=64 => 6,
+            0 | 65..=128 => 7,
+        )
+    };
+    (@table $cap:expr, $ss_bits:ident, $body:expr, $($caps:pat => $bits:literal,)+) => {
+        match $cap {
+            $($caps => {
+                const $ss_bits: usize = $bits;
+                $body
+            })+
+            cap => Err($crate::error::Error::from(
+                $crate::helpers::query::UnsupportedCap { cap },
+            )),
+        }
+    };
+}
+
+/// Evaluates `$body` with `$bk`, `$tv` and `$ts` bound to the types of breakdown keys, trigger
+/// values and timestamps for `$widths`, a `(breakdown_key_bits, trigger_value_bits,
+/// timestamp_bits)` tuple. Widths without an instantiation are matched against `$unsupported`.
+/// This is the only place that lists supported input widths, everything that runs IPA queries
+/// goes through it.
+///
+/// Combinations that don't fit into a shuffled row (see `OprfIpaQuery::validate`) are left out.
+macro_rules! dispatch_input_widths {
+    (
+        $widths:expr,
+        ($bk:ident, $tv:ident, $ts:ident) => $body:expr,
+        $unsupported:pat => $fallback:expr $(,)?
+    ) => {
+        dispatch_input_widths!(@table $widths, ($bk, $tv, $ts), $body, $unsupported, $fallback,
+            (5, 3, 20) => (BA5, BA3, BA20),
+            (5, 8, 20) => (BA5, BA8, BA20),
+            (5, 16, 20) => (BA5, BA16, BA20),
+            (5, 3, 24) => (BA5, BA3, BA24),
+            (5, 8, 24) => (BA5, BA8, BA24),
+            (5, 16, 24) => (BA5, BA16, BA24),
+            (8, 3, 20) => (BA8, BA3, BA20),
+            (8, 8, 20) => (BA8, BA8, BA20),
+            (8, 16, 20) => (BA8, BA16, BA20),
+            (8, 3, 24) => (BA8, BA3, BA24),
+            (8, 8, 24) => (BA8, BA8, BA24),
+            (10, 3, 20) => (BA10, BA3, BA20),
+            (10, 8, 20) => (BA10, BA8, BA20),
+            (10, 16, 20) => (BA10, BA16, BA20),
+            (10, 3, 24) => (BA10, BA3, BA24),
+            (10, 8, 24) => (BA10, BA8, BA24),
+            (12, 3, 20) => (BA12, BA3, BA20),
+            (12, 8, 20) => (BA12, BA8, BA20),
+            (12, 3, 24) => (BA12, BA3, BA24),
+            (12, 8, 24) => (BA12, BA8, BA24),
+        )
+    };
+    (
+        @table $widths:expr, ($bk:ident, $tv:ident, $ts:ident), $body:expr, $unsupported:pat,
+        $fallback:expr, $($bits:pat => ($bk_ty:ident, $tv_ty:ident, $ts_ty:ident),)+
+    ) => {
+        match $widths {
+            $($bits => {
+                type $bk = $crate::ff::boolean_array::$bk_ty;
+                type $tv = $crate::ff::boolean_array::$tv_ty;
+                type $ts = $crate::ff::boolean_array::$ts_ty;
+                $body
+            })+
+            $unsupported => $fallback,
+        }
+    };
+}
+
+pub(crate) use dispatch_input_widths;
+
 pub struct OprfIpaQuery<C, HV, R: PrivateKeyRegistry> {
     config: IpaQueryConfig,
     key_registry: Arc<R>,
+    /// File where reports that fail to decrypt are quarantined, if the query asks for it.
+    quarantine: Option<PathBuf>,
+    /// Cache of PRF'd inputs, for queries that ask for it, and the query its entries are linked
+    /// to.
+    prf_cache: Option<(Arc<PrfCache>, QueryId)>,
+    /// Field type and protocol version the query was requested with, written into the header
+    /// of its result.
+    request: (FieldType, ProtocolVersion),
     phantom_data: PhantomData<(C, HV)>,
 }
 
@@ -47,12 +207,41 @@ impl<C, HV, R: PrivateKeyRegistry> OprfIpaQuery<C, HV, R> {
         Self {
             config,
             key_registry,
+            quarantine: None,
+            prf_cache: None,
+            request: (FieldType::Fp32BitPrime, ProtocolVersion::CURRENT),
             phantom_data: PhantomData,
         }
     }
+
+    /// Sets the field type and protocol version the query was requested with, so that report
+    /// collectors can check them in the header of the result.
+    #[must_use]
+    pub fn with_request(
+        mut self,
+        field_type: FieldType,
+        protocol_version: ProtocolVersion,
+    ) -> Self {
+        self.request = (field_type, protocol_version);
+        self
+    }
+
+    /// Quarantines reports that fail to decrypt to `file`, if the query config asks for it.
+    #[must_use]
+    pub fn with_quarantine(mut self, file: Option<PathBuf>) -> Self {
+        self.quarantine = file;
+        self
+    }
+
+    /// Keeps PRF'd inputs in `cache`, if the query config asks for it. Entries are linked to
+    /// `query_id`, so that they are deleted with the input of the query.
+    #[must_use]
+    pub fn with_prf_cache(mut self, cache: Option<Arc<PrfCache>>, query_id: QueryId) -> Self {
+        self.prf_cache = cache.map(|cache| (cache, query_id));
+        self
+    }
 }
 
-#[allow(clippy::too_many_lines)]
 impl<C, HV, R> OprfIpaQuery<C, HV, R>
 where
     C: UpgradableContext + Shuffle,
@@ -68,14 +257,37 @@ where
         PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
     Replicated<RP25519, PRF_CHUNK>:
         Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+    Replicated<Boolean, 32>: BooleanProtocols<DZKPUpgraded<C>, 32>,
+    Replicated<Boolean, 1024>: BooleanProtocols<DZKPUpgraded<C>, 1024>,
+    Replicated<Boolean, 4096>: BooleanProtocols<DZKPUpgraded<C>, 4096>,
+    Replicated<BA5>: BooleanArrayMul<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <BA5 as Vectorizable<1>>::Array>,
     Replicated<BA8>: BooleanArrayMul<DZKPUpgraded<C>>
         + Reveal<DZKPUpgraded<C>, Output = <BA8 as Vectorizable<1>>::Array>,
+    Replicated<BA10>: BooleanArrayMul<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <BA10 as Vectorizable<1>>::Array>,
+    Replicated<BA12>: BooleanArrayMul<DZKPUpgraded<C>>
+        + Reveal<DZKPUpgraded<C>, Output = <BA12 as Vectorizable<1>>::Array>,
     Replicated<BA20>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA24>: BooleanArrayMul<DZKPUpgraded<C>>,
     Replicated<BA3>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<BA16>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 32>>, Error = LengthError>,
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 256>>, Error = LengthError>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 1024>>, Error = LengthError>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 4096>>, Error = LengthError>,
+    BitDecomposed<AdditiveShare<Boolean, 32>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<HV>; 32], Error = Infallible>,
     BitDecomposed<AdditiveShare<Boolean, 256>>:
         for<'a> TransposeFrom<&'a [AdditiveShare<HV>; 256], Error = Infallible>,
+    BitDecomposed<AdditiveShare<Boolean, 1024>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<HV>; 1024], Error = Infallible>,
+    BitDecomposed<AdditiveShare<Boolean, 4096>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<HV>; 4096], Error = Infallible>,
 {
     #[tracing::instrument("oprf_ipa_query", skip_all, fields(sz=%query_size))]
     pub async fn execute(
@@ -83,114 +295,475 @@ where
         ctx: C,
         query_size: QuerySize,
         input_stream: BodyStream,
-    ) -> Result<Vec<Replicated<HV>>, Error> {
+    ) -> Result<OprfIpaResult<HV>, Error> {
+        self.validate()?;
+
+        dispatch_input_widths!(
+            (
+                self.config.breakdown_key_bits,
+                self.config.trigger_value_bits,
+                self.config.timestamp_bits,
+            ),
+            (BK, TV, TS) => {
+                self.execute_with::<BK, TV, TS, { 1 << BK::BITS }>(ctx, query_size, input_stream)
+                    .await
+            },
+            (breakdown_key_bits, trigger_value_bits, timestamp_bits) => {
+                Err(Error::InvalidQueryParameter(
+                    format!(
+                        "Unsupported input widths: {breakdown_key_bits} bit breakdown keys, \
+                         {trigger_value_bits} bit trigger values and {timestamp_bits} bit \
+                         timestamps"
+                    )
+                    .into(),
+                ))
+            }
+        )
+    }
+
+    /// Checks the query configuration with the same validator that helpers run when the
+    /// query is created, so that unsupported combinations are reported as errors rather than
+    /// failing mid-protocol.
+    fn validate(&self) -> Result<(), Error> {
+        let config = &self.config;
+        if config.per_user_credit_cap > IpaQueryConfig::MAX_PER_USER_CREDIT_CAP {
+            return Err(UnsupportedCap {
+                cap: config.per_user_credit_cap,
+            }
+            .into());
+        }
+        let mut report = ValidationReport::default();
+        config.validate(&QueryPolicy::default(), &mut report);
+        if !report.is_valid() {
+            return Err(Error::InvalidQueryParameter(report.to_string().into()));
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn execute_with<BK, TV, TS, const B: usize>(
+        self,
+        ctx: C,
+        query_size: QuerySize,
+        input_stream: BodyStream,
+    ) -> Result<OprfIpaResult<HV>, Error>
+    where
+        BK: BreakdownKey<B>,
+        TV: BooleanArray + U128Conversions,
+        TS: BooleanArray + U128Conversions,
+        Boolean: FieldSimd<B>,
+        Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+        Replicated<BK>: BooleanArrayMul<DZKPUpgraded<C>>
+            + Reveal<DZKPUpgraded<C>, Output = <BK as Vectorizable<1>>::Array>
+            + Serializable,
+        Replicated<TV>: BooleanArrayMul<DZKPUpgraded<C>> + Serializable,
+        Replicated<TS>: BooleanArrayMul<DZKPUpgraded<C>> + Serializable,
+        OPRFIPAInputRow<BK, TV, TS>: Serializable,
+        <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+            Add<<Replicated<TS> as Serializable>::Size>,
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >: Add<U16>,
+        Sum<
+            Sum<
+                Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+                <Replicated<TS> as Serializable>::Size,
+            >,
+            U16,
+        >: ArrayLength,
+        BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
+            for<'a> TransposeFrom<&'a Vec<Replicated<BK>>, Error = LengthError>,
+        BitDecomposed<Replicated<Boolean, AGG_CHUNK>>:
+            for<'a> TransposeFrom<&'a Vec<Replicated<TV>>, Error = LengthError>,
+        Vec<BitDecomposed<Replicated<Boolean, B>>>: for<'a> TransposeFrom<
+            &'a [BitDecomposed<Replicated<Boolean, AGG_CHUNK>>],
+            Error = Infallible,
+        >,
+        BitDecomposed<Replicated<Boolean, B>>:
+            for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
+        Vec<Replicated<HV>>:
+            for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+        BitDecomposed<AdditiveShare<Boolean, B>>:
+            for<'a> TransposeFrom<&'a [AdditiveShare<HV>; B], Error = Infallible>,
+    {
         let Self {
             config,
             key_registry,
+            quarantine,
+            prf_cache,
+            request: (field_type, protocol_version),
             phantom_data: _,
         } = self;
-        tracing::info!("New query: {config:?}");
+        // The site is a sensitive field, it is only logged with the privacy parameters if the
+        // helper does not redact it.
+        let logged = IpaQueryConfig {
+            site_domain_hash: None,
+            ..config
+        };
+        tracing::info!("New query: {logged:?}");
         let ctx = ctx.narrow(&IpaPrf);
+        let verify_ctx = ctx.narrow(&IpaPrfStep::VerifyOutputShares);
         let sz = usize::from(query_size);
 
+        let framing = if config.plaintext_match_keys {
+            RecordFraming::Fixed(
+                NonZeroUsize::new(<OPRFIPAInputRow<BK, TV, TS> as Serializable>::Size::USIZE)
+                    .unwrap(),
+            )
+        } else {
+            RecordFraming::LengthDelimited
+        };
+        // Inputs that end with a manifest are checked while they are read, and the check is
+        // confirmed once all records have been read, before anything else. Inputs of per-site
+        // and tagged queries are read in full, to strip the sites and tags from them. Arms of
+        // paired queries are stripped while the input is read.
+        let (manifest, arms, sites, tags, input_stream) = if config.input_manifest {
+            let (input, manifest) = ManifestStream::new(input_stream, framing);
+            (
+                Some(manifest),
+                None,
+                None,
+                None,
+                BodyStream::from_bytes_stream(input),
+            )
+        } else if config.paired_arms {
+            let (arms, input) = read_paired_input(input_stream, framing);
+            (
+                None,
+                Some(arms),
+                None,
+                None,
+                BodyStream::from_bytes_stream(input),
+            )
+        } else if let Some(source_sites) = config.source_sites {
+            let (sites, input) = read_site_input(input_stream, framing, source_sites).await?;
+            (None, None, Some(sites), None, BodyStream::new(input))
+        } else if let Some(public_tags) = config.public_tags {
+            let (tags, input) = read_tagged_input(input_stream, framing, public_tags).await?;
+            (None, None, None, Some(tags), BodyStream::new(input))
+        } else {
+            (None, None, None, None, input_stream)
+        };
+        // Per-site queries spend the budget of every site in their input.
+        let present_sites = sites
+            .as_ref()
+            .map(|sites| sites.iter().copied().collect::<BTreeSet<_>>());
+        let budget_scope = present_sites
+            .as_ref()
+            .map_or(BudgetScope::Input, BudgetScope::Sites);
+        // Arms, sites and tags are matched with records before reports that fail to decrypt are
+        // dropped.
+        let mut arms = arms.into_iter().flatten();
+        let mut sites = sites.into_iter().flatten();
+        let mut tags = tags.into_iter().flatten();
+        let site_bits = config.source_site_bits().unwrap_or_default();
+        let tag_bits = config.public_tag_bits().unwrap_or_default();
+
         let input = if config.plaintext_match_keys {
-            let mut v = RecordsStream::<OPRFIPAInputRow<BA8, BA3, BA20>, _>::new(input_stream)
+            let mut v = RecordsStream::<OPRFIPAInputRow<BK, TV, TS>, _>::new(input_stream)
                 .try_concat()
                 .await?;
-            v.truncate(sz);
+            if v.len() > sz {
+                return Err(Error::TooManyReports(sz));
+            }
+            if let Some(manifest) = &manifest {
+                manifest.verify_records(v.len())?;
+            }
+            for (row, arm) in v.iter_mut().zip(arms) {
+                assign_arm(&ctx, row, arm);
+            }
+            for (row, site) in v.iter_mut().zip(sites) {
+                assign_site(&ctx, row, site, site_bits);
+            }
+            for (row, tag) in v.iter_mut().zip(tags) {
+                assign_tag(&ctx, row, tag, tag_bits);
+            }
             v
         } else {
-            LengthDelimitedStream::<EncryptedOprfReport<BA8, BA3, BA20, _>, _>::new(input_stream)
-                .map_err(Into::<Error>::into)
-                .map_ok(|enc_reports| {
-                    iter(enc_reports.into_iter().map(|enc_report| {
-                        enc_report
-                            .decrypt(key_registry.as_ref())
-                            .map_err(Into::<Error>::into)
-                    }))
-                })
-                .try_flatten()
-                .take(sz)
-                .zip(repeat(ctx.clone()))
-                .map(|(res, ctx)| {
-                    res.map(|report| {
-                        let is_trigger = Replicated::<Boolean>::share_known_value(
-                            &ctx,
-                            match report.event_type {
-                                EventType::Source => Boolean::ZERO,
-                                EventType::Trigger => Boolean::ONE,
-                            },
-                        );
-
-                        OPRFIPAInputRow {
-                            timestamp: report.timestamp,
-                            match_key: report.match_key,
-                            is_trigger,
-                            breakdown_key: report.breakdown_key,
-                            trigger_value: report.trigger_value,
-                        }
+            let mut failures = DecryptionFailures::new(
+                config.decryption_failure_policy,
+                config.max_decryption_failure_rate,
+                quarantine,
+            );
+            // Keep a slot for every report, so that helpers can agree on which reports to drop.
+            // A report may fail to decrypt on one helper only.
+            let decrypted =
+                LengthDelimitedStream::<EncryptedOprfReport<BK, TV, TS, _>, _>::new(input_stream)
+                    .map_err(Into::<Error>::into)
+                    .and_then(|enc_reports| {
+                        let reports = enc_reports
+                            .into_iter()
+                            .map(|enc_report| {
+                                let arm = arms.next();
+                                let site = sites.next();
+                                let tag = tags.next();
+                                let decrypted = enc_report.decrypt_for_site(
+                                    key_registry.as_ref(),
+                                    config.site_domain_hash.as_ref(),
+                                );
+                                failures
+                                    .handle(enc_report.as_bytes(), decrypted)
+                                    .map(|report| report.map(|report| (report, arm, site, tag)))
+                            })
+                            .collect::<Vec<_>>();
+                        ready(Ok(iter(reports)))
                     })
+                    .try_flatten()
+                    // One more than the query size is enough to tell that the input is too big.
+                    .take(sz + 1)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+            if decrypted.len() > sz {
+                return Err(Error::TooManyReports(sz));
+            }
+            // Reports that failed to decrypt still have a slot, so this counts every record.
+            if let Some(manifest) = &manifest {
+                manifest.verify_records(decrypted.len())?;
+            }
+            let failed = decrypted.iter().map(Option::is_none).collect::<Vec<_>>();
+            let failed = failures
+                .agree(ctx.narrow(&IpaPrfStep::DecryptionFailures), &failed)
+                .await?;
+            failures.finish().await?;
+
+            decrypted
+                .into_iter()
+                .zip(failed)
+                .filter_map(|(report, failed)| report.filter(|_| !failed))
+                .map(|(report, arm, site, tag)| {
+                    let is_trigger = Replicated::<Boolean>::share_known_value(
+                        &ctx,
+                        match report.event_type {
+                            EventType::Source => Boolean::ZERO,
+                            EventType::Trigger => Boolean::ONE,
+                        },
+                    );
+
+                    let mut row = OPRFIPAInputRow {
+                        timestamp: report.timestamp,
+                        match_key: report.match_key,
+                        is_trigger,
+                        breakdown_key: report.breakdown_key,
+                        trigger_value: report.trigger_value,
+                    };
+                    if let Some(arm) = arm {
+                        assign_arm(&ctx, &mut row, arm);
+                    }
+                    if let Some(site) = site {
+                        assign_site(&ctx, &mut row, site, site_bits);
+                    }
+                    if let Some(tag) = tag {
+                        assign_tag(&ctx, &mut row, tag, tag_bits);
+                    }
+                    row
                 })
-                .try_collect::<Vec<_>>()
-                .await?
+                .collect::<Vec<_>>()
         };
 
-        let aws = config.attribution_window_seconds;
-        let dp_params: DpMechanism = match config.with_dp {
-            0 => DpMechanism::NoDp,
-            _ => DpMechanism::DiscreteLaplace {
-                epsilon: config.epsilon,
-            },
+        let aws = config.attribution_window_units();
+        let dp_params = config.dp_mechanism();
+
+        let padding_params = PaddingParameters::for_queries();
+        let allow_partial = config.allow_partial_results;
+        let counts = config.attributed_counts;
+        let capping = CappingParameters {
+            signed_trigger_values: config.signed_trigger_values,
+            per_source_event_cap: config.per_source_event_cap,
+            per_source_trigger_limit: config.per_source_trigger_limit,
+            per_user_cap: NonZeroU32::new(config.per_user_credit_cap)
+                .map_or(PerUserCap::Uncapped, PerUserCap::Exact),
+            strategy: config.capping_strategy,
+        };
+        let aggregation = AggregationParameters {
+            method: config.aggregation_method,
+            overflow: config.histogram_overflow,
+            prune_zero_rows: config.prune_zero_rows,
         };
+        let sampling = config.user_sampling_rate.map(UserSampling::new);
+        let ttc = config.time_to_conversion();
+        let timestamp_bounds = config.timestamp_bounds();
 
-        #[cfg(feature = "relaxed-dp")]
-        let padding_params = PaddingParameters::relaxed();
-        #[cfg(not(feature = "relaxed-dp"))]
-        let padding_params = PaddingParameters::default();
-        match config.per_user_credit_cap {
-            1 => oprf_ipa::<_, BA8, BA3, HV, BA20, 1, 256>(ctx, input, aws, dp_params, padding_params).await,
-            2 | 4 => oprf_ipa::<_, BA8, BA3, HV, BA20, 2, 256>(ctx, input, aws, dp_params, padding_params).await,
-            8 => oprf_ipa::<_, BA8, BA3, HV, BA20, 3, 256>(ctx, input, aws, dp_params, padding_params).await,
-            16 => oprf_ipa::<_, BA8, BA3, HV, BA20, 4, 256>(ctx, input, aws, dp_params, padding_params).await,
-            32 => oprf_ipa::<_, BA8, BA3, HV, BA20, 5, 256>(ctx, input, aws, dp_params, padding_params).await,
-            64 => oprf_ipa::<_, BA8, BA3, HV, BA20, 6, 256>(ctx, input, aws, dp_params, padding_params).await,
-            128 => oprf_ipa::<_, BA8, BA3, HV, BA20, 7, 256>(ctx, input, aws, dp_params, padding_params).await,
-            _ => panic!(
-                "Invalid value specified for per-user cap: {:?}. Must be one of 1, 2, 4, 8, 16, 32, 64, or 128.",
-                config.per_user_credit_cap
-            ),
+        // All helpers take part in the lookup, even those without a cache, so that they agree on
+        // whether the cached rows are used.
+        let input: IpaInput<BK, TV, TS> = if config.cache_prf && !input.is_empty() {
+            if prf_cache.is_none() {
+                tracing::warn!(
+                    "query asks to cache PRF values, but this helper has no PRF cache. They will \
+                     be computed from scratch."
+                );
+            }
+            let epsilon = if config.with_dp == 0 {
+                0.0
+            } else {
+                config.epsilon
+            };
+            let digest = InputDigest::new(&input, &padding_params);
+            let lookup = prf_cache.as_ref().map_or(Lookup::Miss, |(cache, _)| {
+                cache.lookup::<BK, TV, TS>(&digest, budget_scope, epsilon)
+            });
+            let hit =
+                agree_on_entry(ctx.narrow(&IpaPrfStep::PrfCacheLookup), lookup.vote()).await?;
+            if let Some((cache, query_id)) = &prf_cache {
+                cache.charge(&digest, budget_scope, epsilon)?;
+                cache.link(*query_id, &digest)?;
+            }
+            match lookup {
+                Lookup::Hit(rows) if hit => IpaInput::Prfd(rows),
+                _ => {
+                    let rows =
+                        prf_input_rows::<_, BK, TV, TS, B>(ctx.clone(), input, &padding_params)
+                            .await?;
+                    if let Some((cache, _)) = &prf_cache {
+                        if let Err(e) = cache.store(&digest, &rows) {
+                            tracing::warn!("failed to cache PRF values: {e}");
+                        }
+                    }
+                    IpaInput::Prfd(rows)
+                }
+            }
+        } else {
+            input.into()
+        };
+
+        let (histogram, release, noise) = dispatch_per_user_cap!(config.per_user_credit_cap, SS_BITS => {
+            oprf_ipa_with_partial_results::<_, BK, TV, HV, TS, SS_BITS, B>(
+                ctx,
+                input,
+                aws,
+                dp_params,
+                padding_params,
+                allow_partial,
+                counts,
+                capping,
+                aggregation,
+                sampling,
+                ttc,
+                timestamp_bounds,
+            )
+            .await
+        })?;
+
+        // Last chance to catch corrupted shares before they are handed over to the report
+        // collector, who would otherwise fail to reconstruct the result with no indication why.
+        if config.verify_output_shares {
+            cross_check_replicated_shares(verify_ctx, &histogram).await?;
         }
+
+        Ok(OprfIpaResult {
+            histogram,
+            histograms: u8::try_from(config.output_histograms())
+                .expect("queries have at most four histograms"),
+            release: allow_partial.then_some(release),
+            protocol_version,
+            field_type,
+            noise,
+        })
+    }
+}
+
+/// Moves a row of a paired query into the half of the breakdown keys that belongs to its arm,
+/// by setting the top bit of its breakdown key to the arm. Arms are public, so this does not
+/// need any communication. Query validation rejects paired queries whose `max_breakdown_key`
+/// needs the top bit, so valid breakdown keys never lose it. Breakdown keys above the maximum
+/// that don't fit into the half of their arm wrap around within it, they never end up in the
+/// other arm.
+fn assign_arm<C, BK, TV, TS>(ctx: &C, row: &mut OPRFIPAInputRow<BK, TV, TS>, arm: Arm)
+where
+    C: Context,
+    BK: BooleanArray,
+    TV: SharedValue,
+    TS: SharedValue,
+{
+    row.breakdown_key.set(
+        usize::try_from(BK::BITS - 1).unwrap(),
+        Replicated::share_known_value(ctx, Boolean::from(arm == Arm::Treatment)),
+    );
+}
+
+/// Moves a row of a per-site query into the share of the breakdown keys that belongs to its
+/// source site, by setting the top `site_bits` bits of its breakdown key to the site. Like
+/// arms, sites are public and breakdown keys that don't fit wrap around within their site.
+fn assign_site<C, BK, TV, TS>(
+    ctx: &C,
+    row: &mut OPRFIPAInputRow<BK, TV, TS>,
+    site: u8,
+    site_bits: u32,
+) where
+    C: Context,
+    BK: BooleanArray,
+    TV: SharedValue,
+    TS: SharedValue,
+{
+    for bit in 0..site_bits {
+        row.breakdown_key.set(
+            usize::try_from(BK::BITS - site_bits + bit).unwrap(),
+            Replicated::share_known_value(ctx, Boolean::from((site >> bit) & 1 == 1)),
+        );
     }
 }
 
+/// Moves a row of a tagged query into the sub-query of its tag. The tag is mixed into the top
+/// byte of the match key, so rows with different tags never belong to the same user, and takes
+/// the top `tag_bits` bits of the breakdown key, like sites do. Tags are public, so this does
+/// not need any communication either.
+fn assign_tag<C, BK, TV, TS>(ctx: &C, row: &mut OPRFIPAInputRow<BK, TV, TS>, tag: u8, tag_bits: u32)
+where
+    C: Context,
+    BK: BooleanArray,
+    TV: SharedValue,
+    TS: SharedValue,
+{
+    row.match_key += Replicated::share_known_value(
+        ctx,
+        MatchKey::truncate_from(u128::from(tag) << (MatchKey::BITS - u8::BITS)),
+    );
+    assign_site(ctx, row, tag, tag_bits);
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::{iter::zip, sync::Arc};
+    use std::{
+        convert::Infallible, fs, iter::zip, num::NonZeroU32, ops::Add, path::Path, sync::Arc,
+    };
 
+    use futures::FutureExt;
+    use generic_array::ArrayLength;
     use rand::rngs::StdRng;
     use rand_core::SeedableRng;
+    use typenum::{Sum, U16};
 
     use crate::{
+        error::Error,
         ff::{
-            boolean_array::{BA16, BA20, BA3, BA8},
-            U128Conversions,
+            boolean_array::{BooleanArray, BA10, BA16, BA20, BA24, BA3, BA32, BA5, BA8},
+            FieldType, Serializable, U128Conversions,
         },
         helpers::{
-            query::{IpaQueryConfig, QuerySize},
-            BodyStream,
+            query::{IpaQueryConfig, QuerySize, UnsupportedCap},
+            Arm, BodyStream, InputManifest,
         },
         hpke::{KeyPair, KeyRegistry},
-        query::runner::OprfIpaQuery,
+        protocol::{
+            ipa_prf::{AggregationMethod, Release},
+            ProtocolVersion, QueryId,
+        },
+        query::{
+            runner::{oprf_ipa::OprfIpaResult, OprfIpaQuery},
+            DecryptionFailurePolicy, PrfCache, ProtocolResult,
+        },
         report::{OprfReport, DEFAULT_KEY_ID},
-        secret_sharing::IntoShares,
+        results::{ResultHeader, ValueKind},
+        secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
         test_fixture::{ipa::TestRawDataRecord, join3v, Reconstruct, TestWorld},
     };
 
-    #[tokio::test]
-    async fn encrypted_reports() {
-        const EXPECTED: &[u128] = &[0, 8, 5];
-
-        let records: Vec<TestRawDataRecord> = vec![
+    /// Two users, three conversions. User `12345` converts once with `first` on breakdown 2,
+    /// user `68362` converts twice with `second` and `third`, both attributed to breakdown 1.
+    fn records(first: u32, second: u32, third: u32) -> Vec<TestRawDataRecord> {
+        vec![
             TestRawDataRecord {
                 timestamp: 0,
                 user_id: 12345,
@@ -210,14 +783,14 @@ mod tests {
                 user_id: 12345,
                 is_trigger_report: true,
                 breakdown_key: 0,
-                trigger_value: 5,
+                trigger_value: first,
             },
             TestRawDataRecord {
                 timestamp: 12,
                 user_id: 68362,
                 is_trigger_report: true,
                 breakdown_key: 0,
-                trigger_value: 2,
+                trigger_value: second,
             },
             TestRawDataRecord {
                 timestamp: 20,
@@ -231,10 +804,72 @@ mod tests {
                 user_id: 68362,
                 is_trigger_report: true,
                 breakdown_key: 1,
-                trigger_value: 7,
+                trigger_value: third,
             },
-        ];
+        ]
+    }
+
+    async fn run_encrypted<BK, TV, TS, HV>(
+        records: Vec<TestRawDataRecord>,
+        query_config: IpaQueryConfig,
+    ) -> Result<Vec<u128>, Error>
+    where
+        BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+        TV: BooleanArray + U128Conversions + IntoShares<Replicated<TV>>,
+        TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+        HV: BooleanArray + U128Conversions,
+        <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+            Add<<Replicated<TS> as Serializable>::Size>,
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >: Add<U16>,
+        Sum<
+            Sum<
+                Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+                <Replicated<TS> as Serializable>::Size,
+            >,
+            U16,
+        >: ArrayLength,
+    {
+        run_encrypted_with_caches::<BK, TV, TS, HV>(records, query_config, [None, None, None], None)
+            .await
+    }
 
+    /// Same as [`run_encrypted`], with a PRF cache for each helper. Shares and ciphertexts are
+    /// the same on every call with the same records. For paired queries, records of users with
+    /// even ids go to the treatment arm, and the output has the first three buckets of each arm.
+    /// For per-site queries, records come from the site `user_id % source_sites`, and the output
+    /// has the first three buckets of each site. Tagged queries work the same way, with the tag
+    /// `user_id % public_tags`. If `corrupted` is set to `(helper, record)`, the ciphertext of
+    /// that record is corrupted in the input of that helper only.
+    async fn run_encrypted_with_caches<BK, TV, TS, HV>(
+        records: Vec<TestRawDataRecord>,
+        query_config: IpaQueryConfig,
+        prf_caches: [Option<Arc<PrfCache>>; 3],
+        corrupted: Option<(usize, usize)>,
+    ) -> Result<Vec<u128>, Error>
+    where
+        BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+        TV: BooleanArray + U128Conversions + IntoShares<Replicated<TV>>,
+        TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+        HV: BooleanArray + U128Conversions,
+        <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+            Add<<Replicated<TS> as Serializable>::Size>,
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >: Add<U16>,
+        Sum<
+            Sum<
+                Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+                <Replicated<TS> as Serializable>::Size,
+            >,
+            U16,
+        >: ArrayLength,
+    {
         let query_size = QuerySize::try_from(records.len()).unwrap();
 
         let mut rng = StdRng::seed_from_u64(42);
@@ -243,43 +878,833 @@ mod tests {
 
         let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
 
-        let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.into_iter().share();
-        for (buf, shares) in zip(&mut buffers, shares) {
-            for share in shares {
+        let arms = records
+            .iter()
+            .map(|record| {
+                if record.user_id % 2 == 0 {
+                    Arm::Treatment
+                } else {
+                    Arm::Control
+                }
+            })
+            .collect::<Vec<_>>();
+        let sites = records
+            .iter()
+            .map(|record| {
+                query_config
+                    .source_sites
+                    .or(query_config.public_tags)
+                    .map(|sites| u8::try_from(record.user_id % u64::from(sites)).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let shares: [Vec<OprfReport<BK, TV, TS>>; 3] = records.into_iter().share_with(&mut rng);
+        for (helper, (buf, shares)) in zip(&mut buffers, shares).enumerate() {
+            for (record, ((share, arm), site)) in zip(shares, &arms).zip(&sites).enumerate() {
+                if query_config.paired_arms {
+                    buf.push(arm.to_byte());
+                }
+                buf.extend(site);
+                let start = buf.len();
                 share
                     .delimited_encrypt_to(key_id, key_registry.as_ref(), &mut rng, buf)
                     .unwrap();
+                if corrupted == Some((helper, record)) {
+                    // Skip the length prefix and the encapsulated key, to flip a bit of the
+                    // match key ciphertext.
+                    buf[start + 2 + 32] ^= 1;
+                }
+            }
+            if query_config.input_manifest {
+                InputManifest::append_to(buf, usize::from(query_size));
             }
         }
 
         let world = TestWorld::default();
         let contexts = world.contexts();
         #[allow(clippy::large_futures)]
-        let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
-            let query_config = IpaQueryConfig {
-                per_user_credit_cap: 8,
-                attribution_window_seconds: None,
-                max_breakdown_key: 3,
-                with_dp: 0,
-                epsilon: 5.0,
-                plaintext_match_keys: false,
-            };
-            let input = BodyStream::from(buffer);
+        let [r0, r1, r2] = join3v(zip(buffers, contexts).zip(prf_caches).map(
+            |((buffer, ctx), prf_cache)| {
+                let input = BodyStream::from(buffer);
+                let query = OprfIpaQuery::<_, HV, KeyRegistry<KeyPair>>::new(
+                    query_config,
+                    Arc::clone(&key_registry),
+                )
+                .with_prf_cache(prf_cache, QueryId);
+
+                // Surface query errors to the caller instead of letting `join3v` panic on them.
+                query
+                    .execute(ctx, query_size, input)
+                    .map(Ok::<_, Infallible>)
+            },
+        ))
+        .await;
+
+        let [r0, r1, r2] = [r0?, r1?, r2?];
+        assert!(
+            r0.release == r1.release && r1.release == r2.release,
+            "helpers disagree on the release of the result"
+        );
+        let results = [r0.histogram, r1.histogram, r2.histogram].reconstruct();
+        // With attributed counts or a time-to-conversion histogram, the first three buckets of
+        // these histograms follow those of the values histogram.
+        let histograms = usize::try_from(query_config.output_histograms()).unwrap()
+            * (1 + usize::from(query_config.paired_arms))
+            * usize::try_from(
+                query_config
+                    .source_site_bits()
+                    .or(query_config.public_tag_bits())
+                    .map_or(1, |bits| 1_u32 << bits),
+            )
+            .unwrap();
+        Ok(results
+            .chunks(results.len() / histograms)
+            .flat_map(|histogram| &histogram[0..3])
+            .map(U128Conversions::as_u128)
+            .collect::<Vec<u128>>())
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            epsilon: 5.0,
+            ..IpaQueryConfig::default()
+        };
 
-            OprfIpaQuery::<_, BA16, KeyRegistry<KeyPair>>::new(
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    /// A report that only one helper fails to decrypt is dropped by all of them. If the other
+    /// helpers kept it, their shares would no longer line up.
+    #[tokio::test]
+    async fn decryption_failure_on_one_helper() {
+        const EXPECTED: &[u128] = &[0, 2, 5];
+
+        let query_config = IpaQueryConfig {
+            decryption_failure_policy: DecryptionFailurePolicy::Skip,
+            max_decryption_failure_rate: 0.5,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted_with_caches::<BA8, BA3, BA20, BA16>(
+                records(5, 2, 7),
                 query_config,
-                Arc::clone(&key_registry),
+                [None, None, None],
+                Some((1, 5)),
             )
-            .execute(ctx, query_size, input)
-        }))
-        .await;
+            .await
+            .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_wide_trigger_values() {
+        const EXPECTED: &[u128] = &[0, 90, 100];
+
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: 128,
+            max_breakdown_key: 3,
+            with_dp: 0,
+            epsilon: 5.0,
+            trigger_value_bits: 16,
+            ..IpaQueryConfig::default()
+        };
 
         assert_eq!(
-            results.reconstruct()[0..3]
-                .iter()
-                .map(U128Conversions::as_u128)
-                .collect::<Vec<u128>>(),
+            run_encrypted::<BA8, BA16, BA20, BA32>(records(100, 20, 70), query_config)
+                .await
+                .unwrap(),
             EXPECTED
         );
     }
+
+    #[tokio::test]
+    async fn encrypted_reports_narrow_breakdown_keys() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            epsilon: 5.0,
+            breakdown_key_bits: 5,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA5, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_wide_breakdown_keys() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            breakdown_key_bits: 10,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA10, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_with_manifest() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            input_manifest: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_oblivious_aggregation() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            breakdown_key_bits: 5,
+            aggregation_method: AggregationMethod::Oblivious,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA5, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_prune_zero_rows() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            breakdown_key_bits: 5,
+            prune_zero_rows: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA5, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_non_power_of_two_cap() {
+        const EXPECTED: &[u128] = &[0, 6, 5];
+
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: 6,
+            max_breakdown_key: 3,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_uncapped() {
+        const EXPECTED: &[u128] = &[0, 9, 5];
+
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: 0,
+            max_breakdown_key: 3,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_cap() {
+        let cap = IpaQueryConfig::MAX_PER_USER_CREDIT_CAP + 1;
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: cap,
+            max_breakdown_key: 3,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::UnsupportedCap(UnsupportedCap { cap: c })) if c == cap
+        ));
+    }
+
+    #[test]
+    fn per_user_cap_table() {
+        fn bits(cap: u32) -> Result<usize, Error> {
+            dispatch_per_user_cap!(cap, SS_BITS => Ok(SS_BITS))
+        }
+
+        for cap in 1..=IpaQueryConfig::MAX_PER_USER_CREDIT_CAP {
+            let bits = u32::try_from(bits(cap).unwrap()).unwrap();
+            assert!(cap <= 1 << bits, "cap {cap} does not fit in {bits} bits");
+            assert!(
+                bits == 1 || cap > 1 << (bits - 1),
+                "{bits} bits are too many for cap {cap}"
+            );
+        }
+        assert!(bits(0).is_ok());
+        assert!(matches!(
+            bits(IpaQueryConfig::MAX_PER_USER_CREDIT_CAP + 1),
+            Err(Error::UnsupportedCap(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn uncapped_with_dp() {
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: 0,
+            max_breakdown_key: 3,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_verify_output_shares() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            verify_output_shares: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    /// Contents of the cache entries in `dir`.
+    fn cache_entries(dir: &Path) -> Vec<Vec<u8>> {
+        let mut entries = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rows"))
+            .map(|path| fs::read(path).unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[tokio::test]
+    async fn cached_prf() {
+        const EXPECTED: &[u128] = &[0, 8, 5];
+
+        let dirs: [_; 3] = std::array::from_fn(|_| tempfile::tempdir().unwrap());
+        let caches = dirs.each_ref().map(|dir| {
+            Some(Arc::new(PrfCache::new(
+                dir.path().to_path_buf(),
+                &[0; 32],
+                1.0,
+            )))
+        });
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            cache_prf: true,
+            ..IpaQueryConfig::default()
+        };
+
+        let mut entries = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(
+                run_encrypted_with_caches::<BA8, BA3, BA20, BA16>(
+                    records(5, 2, 7),
+                    query_config,
+                    caches.clone(),
+                    None,
+                )
+                .await
+                .unwrap(),
+                EXPECTED
+            );
+            entries.push(dirs.each_ref().map(|dir| cache_entries(dir.path())));
+        }
+
+        // Entries are sealed with a fresh nonce every time they are written, so the second
+        // query did not write them again.
+        assert!(entries[0].iter().all(|entries| entries.len() == 1));
+        assert_eq!(entries[0], entries[1]);
+    }
+
+    #[tokio::test]
+    async fn cached_prf_budget() {
+        let dirs: [_; 3] = std::array::from_fn(|_| tempfile::tempdir().unwrap());
+        let caches = dirs.each_ref().map(|dir| {
+            Some(Arc::new(PrfCache::new(
+                dir.path().to_path_buf(),
+                &[0; 32],
+                1.0,
+            )))
+        });
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            epsilon: 0.75,
+            cache_prf: true,
+            ..IpaQueryConfig::default()
+        };
+        let run = || {
+            run_encrypted_with_caches::<BA8, BA3, BA20, BA16>(
+                records(5, 2, 7),
+                query_config,
+                caches.clone(),
+                None,
+            )
+        };
+
+        run().await.unwrap();
+        assert!(matches!(run().await, Err(Error::InputBudgetExhausted)));
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports_wide_timestamps() {
+        // With one-minute timestamp units, a 540 second window spans 9 units. Only the
+        // conversion that happens 8 units after its source event is attributed.
+        const EXPECTED: &[u128] = &[0, 2, 0];
+
+        let mut records = records(5, 2, 7);
+        for record in &mut records {
+            record.timestamp += 1 << 22;
+        }
+        let query_config = IpaQueryConfig {
+            attribution_window_seconds: NonZeroU32::new(540),
+            max_breakdown_key: 3,
+            with_dp: 0,
+            epsilon: 5.0,
+            timestamp_bits: 24,
+            timestamp_granularity_seconds: NonZeroU32::new(60).unwrap(),
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA24, BA16>(records, query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn input_row_does_not_fit_shuffle_share() {
+        let query_config = IpaQueryConfig {
+            trigger_value_bits: 16,
+            timestamp_bits: 24,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA16, BA24, BA32>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn max_breakdown_key_does_not_fit() {
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 33,
+            breakdown_key_bits: 5,
+            timestamp_bits: 20,
+            timestamp_granularity_seconds: NonZeroU32::MIN,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA5, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unsupported_breakdown_key_bits() {
+        let query_config = IpaQueryConfig {
+            breakdown_key_bits: 6,
+            timestamp_bits: 20,
+            timestamp_granularity_seconds: NonZeroU32::MIN,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn partial_results_never_without_noise() {
+        // Zero epsilon is rejected by query validation. The histogram of attributed values is
+        // never released without noise, even if the query allows partial results.
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 1,
+            epsilon: 0.0,
+            allow_partial_results: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(
+            run_encrypted::<BA8, BA3, BA20, BA32>(records(5, 2, 7), query_config)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn attributed_counts() {
+        // Values are capped at 8 per user, while each conversion counts as one.
+        const EXPECTED: &[u128] = &[0, 8, 5, 0, 2, 1];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            attributed_counts: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn attributed_counts_partial_results() {
+        // Histograms that were noised are released in full, even if the query allows partial
+        // results. How histograms that could not be noised are zeroed is covered by the
+        // protocol tests.
+        const EXPECTED: &[u128] = &[0, 8, 5, 0, 2, 1];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            allow_partial_results: true,
+            attributed_counts: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn time_to_conversion() {
+        // Conversions happen 10, 8 and 10 seconds after the source event they are attributed
+        // to, which puts them into the second and third of the 5 second wide buckets.
+        const EXPECTED: &[u128] = &[0, 8, 5, 0, 2, 1, 0, 1, 2];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            attributed_counts: true,
+            time_to_conversion_bucket_seconds: NonZeroU32::new(5),
+            time_to_conversion_buckets: 4,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn timestamp_bounds() {
+        // The histogram of suppressed trigger events follows the others. Which events are
+        // suppressed is checked by the protocol tests, the output here is noised.
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            epsilon: 5.0,
+            attributed_counts: true,
+            min_timestamp: Some(11),
+            max_timestamp: Some(20),
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap()
+                .len(),
+            9
+        );
+    }
+
+    #[tokio::test]
+    async fn timestamp_bounds_without_dp() {
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            min_timestamp: Some(11),
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn timestamp_bounds_do_not_fit() {
+        let query_config = IpaQueryConfig {
+            max_timestamp: Some(1 << 20),
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn paired_arms() {
+        // User `12345` is in the control arm, user `68362` in the treatment arm. Each arm has
+        // its own histograms.
+        const EXPECTED: &[u128] = &[0, 0, 5, 0, 8, 0, 0, 0, 1, 0, 2, 0];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            attributed_counts: true,
+            paired_arms: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn paired_arms_max_breakdown_key_does_not_fit() {
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 17,
+            breakdown_key_bits: 5,
+            with_dp: 0,
+            paired_arms: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA5, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn source_sites() {
+        // User `12345` comes from site 0, user `68362` from site 1. Three sites take two bits of
+        // the breakdown key, so the output has a fourth quarter that no site uses.
+        const EXPECTED: &[u128] = &[0, 0, 5, 0, 8, 0, 0, 0, 0, 0, 0, 0];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            source_sites: Some(3),
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn source_sites_max_breakdown_key_does_not_fit() {
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 9,
+            breakdown_key_bits: 5,
+            with_dp: 0,
+            source_sites: Some(3),
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA5, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn public_tags() {
+        // User `68362` has tag 0, user `12345` tag 1.
+        const EXPECTED: &[u128] = &[0, 8, 0, 0, 0, 5];
+
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            public_tags: Some(2),
+            ..IpaQueryConfig::default()
+        };
+
+        assert_eq!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config)
+                .await
+                .unwrap(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn public_tags_with_prf_cache() {
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            public_tags: Some(2),
+            cache_prf: true,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 7, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn noise_failure_without_partial_results() {
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 1,
+            epsilon: 0.0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(
+            run_encrypted::<BA8, BA3, BA20, BA32>(records(5, 2, 7), query_config)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn result_header() {
+        let histogram = vec![Replicated::<BA8>::ZERO; 4];
+        let partial = OprfIpaResult {
+            histogram: histogram.clone(),
+            histograms: 2,
+            release: Some(Release::Partial),
+            protocol_version: ProtocolVersion::V6,
+            field_type: FieldType::Fp32BitPrime,
+            noise: None,
+        }
+        .to_bytes();
+        let complete = OprfIpaResult {
+            histogram,
+            histograms: 2,
+            release: None,
+            protocol_version: ProtocolVersion::V6,
+            field_type: FieldType::Fp32BitPrime,
+            noise: None,
+        }
+        .to_bytes();
+
+        let (header, shares) = ResultHeader::parse(0, &partial).unwrap().unwrap();
+        assert_eq!(
+            ResultHeader {
+                kind: ValueKind::BooleanArray,
+                value_bits: 8,
+                release: Some(Release::Partial),
+                histograms: 2,
+                rows: 4,
+                protocol_version: ProtocolVersion::V6,
+                field_type: FieldType::Fp32BitPrime,
+            },
+            header
+        );
+        let (header, complete_shares) = ResultHeader::parse(0, &complete).unwrap().unwrap();
+        assert_eq!(None, header.release);
+        assert_eq!(shares, complete_shares);
+    }
+
+    #[tokio::test]
+    async fn unsupported_trigger_value_bits() {
+        let query_config = IpaQueryConfig {
+            trigger_value_bits: 5,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+
+        assert!(matches!(
+            run_encrypted::<BA8, BA3, BA20, BA16>(records(5, 2, 7), query_config).await,
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
 }
@@ -0,0 +1,162 @@
+use futures::{future::ready, stream, StreamExt, TryStreamExt};
+
+use crate::{
+    error::Error,
+    ff::{boolean::Boolean, ec_prime_field::Fp25519},
+    helpers::{query::ShareConversionParams, BodyStream, Gateway, TotalRecords},
+    protocol::{
+        basics::BooleanProtocols,
+        context::{
+            dzkp_validator::DZKPValidator, Context, DZKPUpgraded, MaliciousContext,
+            MaliciousProtocolSteps, UpgradableContext,
+        },
+        ipa_prf::{boolean_ops::convert_to_fp25519, conv_proof_chunk, CONV_CHUNK, PRF_CHUNK},
+        prss::{Endpoint as PrssEndpoint, SharedRandomness},
+        step::{ProtocolStep, TestShareConversionStep},
+        RecordId,
+    },
+    query::runner::QueryResult,
+    secret_sharing::{replicated::semi_honest::AdditiveShare, BitDecomposed},
+    seq_join::seq_join,
+};
+
+/// Runs the share conversion benchmark. It converts `size` Boolean shares of `params.bits` width
+/// into Fp25519 shares, the same way OPRF IPA converts match keys before evaluating the PRF, and
+/// nothing else, so the tuning report of the query shows the time and bytes it takes.
+///
+/// Helpers generate the values to convert from PRSS. Anything the client uploads is ignored.
+pub async fn execute_share_conversion<'a>(
+    prss: &'a PrssEndpoint,
+    gateway: &'a Gateway,
+    size: usize,
+    params: ShareConversionParams,
+    input: BodyStream,
+) -> QueryResult {
+    input
+        .map_err(Error::ParseError)
+        .try_for_each(|_| ready(Ok(())))
+        .await?;
+    let ctx = MaliciousContext::new(prss, gateway).narrow(&ProtocolStep::ShareConversion);
+
+    Ok(Box::new(execute(ctx, size, params).await?))
+}
+
+#[tracing::instrument("share_conversion", skip_all)]
+pub async fn execute<C>(
+    ctx: C,
+    size: usize,
+    params: ShareConversionParams,
+) -> Result<Vec<AdditiveShare<Fp25519>>, Error>
+where
+    C: UpgradableContext,
+    AdditiveShare<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+{
+    let bits = usize::try_from(params.bits).unwrap();
+    if !(1..=usize::try_from(ShareConversionParams::MAX_BITS).unwrap()).contains(&bits) {
+        return Err(Error::InvalidQueryParameter(
+            format!("unsupported share conversion width: {bits}").into(),
+        ));
+    }
+
+    let records = size.div_ceil(CONV_CHUNK);
+    let Ok(total_records) = TotalRecords::specified(records) else {
+        return Ok(Vec::new());
+    };
+    let input_ctx = ctx
+        .narrow(&TestShareConversionStep::GenerateInput)
+        .set_total_records(total_records);
+    let validator = ctx.set_total_records(total_records).dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &TestShareConversionStep::Convert,
+            validate: &TestShareConversionStep::ConvertValidate,
+        },
+        conv_proof_chunk(),
+    );
+    let m_ctx = validator.context();
+
+    let converted = seq_join(
+        ctx.active_work(),
+        stream::iter(0..records).map(|i| {
+            let record_id = RecordId::from(i);
+            let input: BitDecomposed<AdditiveShare<Boolean, CONV_CHUNK>> =
+                input_ctx.prss().generate_with(record_id, bits);
+            convert_to_fp25519::<_, CONV_CHUNK, PRF_CHUNK>(m_ctx.clone(), record_id, input)
+        }),
+    )
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    Ok(converted
+        .into_iter()
+        .flatten()
+        .flat_map(AdditiveShare::into_unpacking_iter)
+        .take(size)
+        .collect())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::future::try_join3;
+    use generic_array::GenericArray;
+
+    use super::execute;
+    use crate::{
+        ff::{ec_prime_field::Fp25519, Serializable},
+        helpers::query::ShareConversionParams,
+        protocol::ipa_prf::CONV_CHUNK,
+        test_executor::run,
+        test_fixture::{Reconstruct, TestWorld},
+    };
+
+    /// Number of bits needed to represent `value`.
+    fn width(value: Fp25519) -> u32 {
+        let mut buf = GenericArray::default();
+        value.serialize(&mut buf);
+        let bytes = buf.as_slice();
+        bytes.iter().rposition(|&b| b != 0).map_or(0, |i| {
+            u32::try_from(i).unwrap() * 8 + (u8::BITS - bytes[i].leading_zeros())
+        })
+    }
+
+    #[test]
+    fn converts_values_of_the_requested_width() {
+        run(|| async {
+            const SIZE: usize = CONV_CHUNK + 3;
+            let world = TestWorld::default();
+            let params = ShareConversionParams { bits: 40 };
+            let [c1, c2, c3] = world.malicious_contexts();
+            let results = try_join3(
+                execute(c1, SIZE, params),
+                execute(c2, SIZE, params),
+                execute(c3, SIZE, params),
+            )
+            .await
+            .unwrap();
+            let values = [results.0, results.1, results.2].reconstruct();
+
+            assert_eq!(SIZE, values.len());
+            assert!(values.iter().all(|&v| width(v) <= params.bits));
+            // The chance of all of them fitting into 32 bits is negligible.
+            assert!(values.iter().any(|&v| width(v) > 32));
+        });
+    }
+
+    #[test]
+    fn rejects_unsupported_width() {
+        run(|| async {
+            let world = TestWorld::default();
+            let params = ShareConversionParams {
+                bits: ShareConversionParams::MAX_BITS + 1,
+            };
+            let [c1, c2, c3] = world.malicious_contexts();
+            let results = try_join3(
+                execute(c1, 10, params),
+                execute(c2, 10, params),
+                execute(c3, 10, params),
+            )
+            .await;
+
+            assert!(results.is_err());
+        });
+    }
+}
@@ -3,18 +3,28 @@ use std::{
     fmt::{Debug, Display, Formatter},
     future::Future,
     task::Poll,
+    time::Instant,
 };
 
 use ::tokio::sync::oneshot::{error::TryRecvError, Receiver};
 use futures::{ready, FutureExt};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     executor::IpaJoinHandle,
-    helpers::{query::QueryConfig, RoleAssignment},
+    helpers::{
+        query::{PrepareQuery, QueryConfig},
+        HelperIdentity, Role,
+    },
     protocol::QueryId,
-    query::runner::QueryResult,
-    sync::Mutex,
+    query::{batches::InputBatches, contributions::InputContributions, runner::QueryResult},
+    sync::{Arc, Mutex},
+    telemetry::{
+        progress::{QueryEta, QueryProgress},
+        send_buffers::SendBufferStatus,
+        tuning::TuningReport,
+    },
 };
 
 /// The status of query processing
@@ -46,10 +56,12 @@ impl From<&QueryState> for QueryStatus {
         match source {
             QueryState::Empty => panic!("Query cannot be in the empty state"),
             QueryState::Preparing(_) => QueryStatus::Preparing,
-            QueryState::AwaitingInputs(_, _, _) => QueryStatus::AwaitingInputs,
+            QueryState::AwaitingInputs(..)
+            | QueryState::CollectingInputs(..)
+            | QueryState::AppendingInputs(..) => QueryStatus::AwaitingInputs,
             QueryState::Running(_) => QueryStatus::Running,
             QueryState::AwaitingCompletion => QueryStatus::AwaitingCompletion,
-            QueryState::Completed(_) => QueryStatus::Completed,
+            QueryState::Completed(..) => QueryStatus::Completed,
         }
     }
 }
@@ -78,22 +90,33 @@ pub fn min_status(a: QueryStatus, b: QueryStatus) -> QueryStatus {
 pub enum QueryState {
     Empty,
     Preparing(QueryConfig),
-    AwaitingInputs(QueryId, QueryConfig, RoleAssignment),
+    AwaitingInputs(PrepareQuery),
+    /// Some, but not all, report collectors uploaded their slices of the query input.
+    CollectingInputs(PrepareQuery, InputContributions),
+    /// The report collector uploaded some batches of the query input and may upload more,
+    /// until it seals the input.
+    AppendingInputs(PrepareQuery, InputBatches),
     Running(RunningQuery),
     AwaitingCompletion,
-    Completed(QueryResult),
+    /// The query finished at the given instant. Helpers keep a limited number of completed
+    /// queries and drop those that finished first.
+    Completed(QueryResult, Option<TuningReport>, Instant),
 }
 
 impl QueryState {
     pub fn transition(cur_state: &Self, new_state: Self) -> Result<Self, StateError> {
-        use QueryState::{AwaitingInputs, Empty, Preparing, Running};
+        use QueryState::{
+            AppendingInputs, AwaitingInputs, CollectingInputs, Empty, Preparing, Running,
+        };
 
         match (cur_state, &new_state) {
             // If query is not running, coordinator initial state is preparing
             // and followers initial state is awaiting inputs
-            (Empty, Preparing(_) | AwaitingInputs(_, _, _))
-            | (Preparing(_), AwaitingInputs(_, _, _))
-            | (AwaitingInputs(_, _, _), Running(_)) => Ok(new_state),
+            (Empty, Preparing(_) | AwaitingInputs(..))
+            | (Preparing(_), AwaitingInputs(..))
+            | (AwaitingInputs(..), CollectingInputs(..) | AppendingInputs(..) | Running(_))
+            | (CollectingInputs(..), CollectingInputs(..) | Running(_))
+            | (AppendingInputs(..), AppendingInputs(..) | Running(_)) => Ok(new_state),
             (_, Preparing(_)) => Err(StateError::AlreadyRunning),
             (_, _) => Err(StateError::InvalidState {
                 from: cur_state.into(),
@@ -104,6 +127,11 @@ impl QueryState {
 }
 
 pub struct RunningQuery {
+    pub config: QueryConfig,
+
+    /// The helper that created this query and coordinates it.
+    pub leader: HelperIdentity,
+
     pub result: Receiver<QueryResult>,
 
     /// `JoinHandle` for the query task.
@@ -114,6 +142,33 @@ pub struct RunningQuery {
     /// We could return the result via the `JoinHandle`, except that we want to check the status
     /// of the task, and shuttle doesn't implement `JoinHandle::is_finished`.
     pub join_handle: IpaJoinHandle<()>,
+
+    /// Set by the query task right before it returns the result.
+    pub tuning_report: Arc<Mutex<Option<TuningReport>>>,
+
+    /// Send buffers as seen by the last check of the query watchdog.
+    pub send_buffers: Arc<Mutex<Vec<SendBufferStatus>>>,
+
+    /// Stages of the protocol, as they complete.
+    pub progress: QueryProgress,
+}
+
+impl QueryState {
+    /// The helper that created this query and coordinates it, once the helpers agreed on their
+    /// roles. Completed queries don't keep it.
+    #[must_use]
+    pub fn leader(&self) -> Option<HelperIdentity> {
+        match self {
+            QueryState::AwaitingInputs(prepare)
+            | QueryState::CollectingInputs(prepare, _)
+            | QueryState::AppendingInputs(prepare, _) => Some(prepare.roles.identity(Role::H1)),
+            QueryState::Running(running) => Some(running.leader),
+            QueryState::Empty
+            | QueryState::Preparing(_)
+            | QueryState::AwaitingCompletion
+            | QueryState::Completed(..) => None,
+        }
+    }
 }
 
 impl RunningQuery {
@@ -126,6 +181,18 @@ impl RunningQuery {
             Err(TryRecvError::Empty) => None,
         }
     }
+
+    pub fn take_tuning_report(&self) -> Option<TuningReport> {
+        self.tuning_report.lock().unwrap().take()
+    }
+
+    pub fn send_buffers(&self) -> Vec<SendBufferStatus> {
+        self.send_buffers.lock().unwrap().clone()
+    }
+
+    pub fn eta(&self) -> Option<QueryEta> {
+        self.progress.eta()
+    }
 }
 
 impl Future for RunningQuery {
@@ -210,6 +277,31 @@ impl RunningQueries {
             queries: self,
         }
     }
+
+    /// Starts preparing a new query under a random id that no other query on this helper uses,
+    /// and returns that id. Ids for which `has_data` returns `true` are skipped as well, because
+    /// data of an earlier query with that id is still kept.
+    ///
+    /// Ids are drawn at random rather than counted, so that queries created by different helpers,
+    /// or by the same helper before and after a restart, are unlikely to share one. Peers reject
+    /// prepare requests for ids they already know.
+    pub fn start_new<R, F>(&self, rng: &mut R, config: QueryConfig, has_data: F) -> QueryId
+    where
+        R: Rng + ?Sized,
+        F: Fn(QueryId) -> bool,
+    {
+        loop {
+            let query_id = QueryId::new(rng.gen());
+            // Checked before taking the lock, because it reads from disk.
+            if has_data(query_id) {
+                continue;
+            }
+            if let Entry::Vacant(entry) = self.inner.lock().unwrap().entry(query_id) {
+                entry.insert(QueryState::Preparing(config));
+                return query_id;
+            }
+        }
+    }
 }
 
 /// RAII guard to clean up query state when dropped.
@@ -256,7 +348,28 @@ impl Drop for RemoveQuery<'_> {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use crate::query::{state::min_status, QueryStatus};
+    use rand::rngs::mock::StepRng;
+
+    use crate::{
+        ff::FieldType,
+        helpers::query::{QueryConfig, QueryType},
+        protocol::QueryId,
+        query::{
+            state::{min_status, RunningQueries},
+            QueryStatus,
+        },
+    };
+
+    #[test]
+    fn start_new_skips_ids_with_data() {
+        let queries = RunningQueries::default();
+        let config = QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1).unwrap();
+        let mut rng = StepRng::new(0, 1);
+        let query_id = queries.start_new(&mut rng, config, |id| id == QueryId::new(0));
+        assert_eq!(QueryId::new(1), query_id);
+        let query_id = queries.start_new(&mut rng, config, |_| false);
+        assert_eq!(QueryId::new(2), query_id);
+    }
 
     #[test]
     fn test_order() {
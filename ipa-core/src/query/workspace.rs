@@ -0,0 +1,290 @@
+//! Query data that helpers keep on disk.
+//!
+//! Encrypted inputs that report collectors upload are only held in memory while the query runs.
+//! What outlives the query is written to the workspace of the helper: ciphertexts of reports that
+//! failed to decrypt, kept in the [`Quarantine`], and the PRF'd input kept in the [`PrfCache`].
+//! Report collectors can ask helpers to delete both once they are done with a query, and helpers
+//! can choose to delete them as soon as the query finishes. Every deletion is recorded in the
+//! audit log.
+//!
+//! The DP budget spent on an input is never deleted, so that uploading the same reports again
+//! does not reset it.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    protocol::QueryId,
+    query::{PrfCache, Quarantine},
+    sync::Arc,
+};
+
+/// Size of the file [`Workspace::check_writable`] writes.
+const PROBE_SIZE: usize = 64 * 1024;
+
+/// How long helpers keep the data derived from query inputs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum InputRetention {
+    /// Until the report collector asks to delete it.
+    #[default]
+    UntilDeleted,
+    /// Until the query completes or is killed.
+    UntilCompleted,
+}
+
+/// What was deleted for a query.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDeleted {
+    pub query_id: QueryId,
+    /// Whether this helper keeps query input on disk at all. If it doesn't, nothing was stored
+    /// for the query and there was nothing to delete.
+    pub stored: bool,
+    /// Whether the quarantine file of the query was deleted.
+    pub quarantine: bool,
+    /// Whether the PRF cache entry of the query was deleted.
+    pub prf_cache: bool,
+}
+
+/// Data a helper keeps on disk for its queries, see the [module documentation].
+///
+/// [module documentation]: self
+#[derive(Clone, Default)]
+pub struct Workspace {
+    quarantine: Option<Quarantine>,
+    prf_cache: Option<Arc<PrfCache>>,
+    retention: InputRetention,
+}
+
+impl Workspace {
+    #[must_use]
+    pub fn new(
+        quarantine: Option<Quarantine>,
+        prf_cache: Option<Arc<PrfCache>>,
+        retention: InputRetention,
+    ) -> Self {
+        Self {
+            quarantine,
+            prf_cache,
+            retention,
+        }
+    }
+
+    /// Returns `true` if this helper keeps any query input on disk.
+    #[must_use]
+    pub fn stores_input(&self) -> bool {
+        self.quarantine.is_some() || self.prf_cache.is_some()
+    }
+
+    /// Checks that there is room for a new file in every directory of the workspace, by writing
+    /// a small file to disk and removing it.
+    ///
+    /// ## Errors
+    /// If the probe file can't be written to one of the directories.
+    pub fn check_writable(&self) -> io::Result<()> {
+        let dirs = self
+            .quarantine
+            .iter()
+            .map(Quarantine::dir)
+            .chain(self.prf_cache.iter().map(|cache| cache.dir()));
+        for dir in dirs {
+            let path = dir.join(".readiness-probe");
+            let written = File::create(&path).and_then(|mut file| {
+                file.write_all(&[0; PROBE_SIZE])?;
+                file.sync_all()
+            });
+            remove_if_exists(&path)?;
+            written.map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", dir.display())))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if data of a query with `query_id` is kept on disk. New queries don't get
+    /// such ids, so that data of different queries never mixes and deleting the data of one
+    /// query leaves the others alone.
+    #[must_use]
+    pub fn has_data(&self, query_id: QueryId) -> bool {
+        self.quarantine
+            .as_ref()
+            .is_some_and(|quarantine| quarantine.path(query_id).exists())
+            || self
+                .prf_cache
+                .as_ref()
+                .is_some_and(|cache| cache.is_linked(query_id))
+    }
+
+    /// Deletes the data kept for `query_id`. Deleting data that does not exist is not an error.
+    ///
+    /// ## Errors
+    /// If a file can't be removed.
+    pub fn delete(&self, query_id: QueryId) -> io::Result<InputDeleted> {
+        self.delete_with_reason(query_id, "requested")
+    }
+
+    /// Applies the retention policy to a query that completed or was killed.
+    pub fn query_finished(&self, query_id: QueryId) {
+        if self.retention == InputRetention::UntilCompleted {
+            if let Err(e) = self.delete_with_reason(query_id, "retention") {
+                tracing::warn!("failed to delete the input of query {query_id}: {e}");
+            }
+        }
+    }
+
+    fn delete_with_reason(&self, query_id: QueryId, reason: &str) -> io::Result<InputDeleted> {
+        let quarantine = match &self.quarantine {
+            Some(quarantine) => remove_if_exists(&quarantine.path(query_id))?,
+            None => false,
+        };
+        let prf_cache = match &self.prf_cache {
+            Some(cache) => cache.remove_for_query(query_id)?,
+            None => false,
+        };
+        tracing::info!(
+            target: "ipa_core::query::privacy",
+            query_id = %query_id,
+            reason,
+            stored = self.stores_input(),
+            quarantine,
+            prf_cache,
+            "query input deleted"
+        );
+
+        Ok(InputDeleted {
+            query_id,
+            stored: self.stores_input(),
+            quarantine,
+            prf_cache,
+        })
+    }
+}
+
+/// Removes the file at `path`, returning whether it existed.
+pub(crate) fn remove_if_exists(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{InputDeleted, InputRetention, Workspace};
+    use crate::{
+        ff::boolean_array::{BA20, BA3, BA5},
+        protocol::QueryId,
+        query::{
+            prf_cache::{BudgetScope, InputDigest},
+            PrfCache, Quarantine,
+        },
+        sync::Arc,
+    };
+
+    fn new_workspace(dir: &Path, retention: InputRetention) -> (Workspace, Arc<PrfCache>) {
+        let cache = Arc::new(PrfCache::new(dir.to_path_buf(), &[1; 32], 1.0));
+        let workspace = Workspace::new(
+            Some(Quarantine::new(dir.to_path_buf())),
+            Some(Arc::clone(&cache)),
+            retention,
+        );
+        (workspace, cache)
+    }
+
+    /// Writes a quarantine file and a PRF cache entry for [`QueryId`].
+    fn populate(dir: &Path, cache: &PrfCache) {
+        fs::write(Quarantine::new(dir.to_path_buf()).path(QueryId), b"00\n").unwrap();
+        let digest = InputDigest::new::<BA5, BA3, BA20>(&[], &Default::default());
+        cache.store::<BA5, BA3, BA20>(&digest, &[]).unwrap();
+        cache.charge(&digest, BudgetScope::Input, 0.5).unwrap();
+        cache.link(QueryId, &digest).unwrap();
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let (workspace, cache) = new_workspace(dir.path(), InputRetention::UntilDeleted);
+        populate(dir.path(), &cache);
+
+        assert_eq!(
+            InputDeleted {
+                query_id: QueryId,
+                stored: true,
+                quarantine: true,
+                prf_cache: true,
+            },
+            workspace.delete(QueryId).unwrap()
+        );
+        // Only the budget ledger is left.
+        let files = files(dir.path());
+        assert_eq!(1, files.len(), "{files:?}");
+        assert!(files[0].ends_with(".budget"));
+
+        assert_eq!(
+            InputDeleted {
+                query_id: QueryId,
+                stored: true,
+                quarantine: false,
+                prf_cache: false,
+            },
+            workspace.delete(QueryId).unwrap()
+        );
+    }
+
+    #[test]
+    fn retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let (workspace, cache) = new_workspace(dir.path(), InputRetention::UntilDeleted);
+        populate(dir.path(), &cache);
+        workspace.query_finished(QueryId);
+        assert_eq!(4, files(dir.path()).len());
+
+        let (workspace, _) = new_workspace(dir.path(), InputRetention::UntilCompleted);
+        workspace.query_finished(QueryId);
+        assert_eq!(1, files(dir.path()).len());
+    }
+
+    #[test]
+    fn without_storage() {
+        assert_eq!(
+            InputDeleted {
+                query_id: QueryId,
+                stored: false,
+                quarantine: false,
+                prf_cache: false,
+            },
+            Workspace::default().delete(QueryId).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (workspace, _) = new_workspace(dir.path(), InputRetention::UntilDeleted);
+        workspace.check_writable().unwrap();
+        assert!(files(dir.path()).is_empty());
+        Workspace::default().check_writable().unwrap();
+
+        let missing = dir.path().join("missing");
+        let (workspace, _) = new_workspace(&missing, InputRetention::UntilDeleted);
+        let err = workspace.check_writable().unwrap_err();
+        assert!(err.to_string().contains("missing"), "{err}");
+    }
+}
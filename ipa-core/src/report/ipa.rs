@@ -23,7 +23,7 @@
 //! (via `Oprf.delmited_encrypt_to`) → `helpers::BodyStream`
 
 use std::{
-    fmt::{Display, Formatter},
+    fmt::{Debug, Display, Formatter},
     fs::File,
     io::{BufRead, BufReader},
     marker::PhantomData,
@@ -35,12 +35,14 @@ use bytes::{BufMut, Bytes};
 use generic_array::{ArrayLength, GenericArray};
 use hpke::Serializable as _;
 use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use typenum::{Sum, Unsigned, U1, U16};
 
 use crate::{
     error::BoxError,
     ff::{boolean_array::BA64, Serializable},
-    helpers::BodyStream,
+    helpers::{BodyStream, InputManifest},
     hpke::{
         open_in_place, seal_in_place, CryptError, EncapsulationSize, Info, PrivateKeyRegistry,
         PublicKeyRegistry, TagSize,
@@ -76,6 +78,65 @@ pub enum EventType {
     Source,
 }
 
+/// SHA-256 digest of a site's registrable domain (e.g. `example.com`).
+///
+/// A query may declare the site its reports were collected for. Helpers then only accept reports
+/// whose `site_domain` is that domain or one of its subdomains. Because `site_domain` is part of
+/// the associated data of the report ciphertexts, it can't be changed to move a report collected
+/// for one site into another site's query. Only the digest is shared with helpers.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SiteDomainHash(#[serde(with = "hex")] [u8; 32]);
+
+impl SiteDomainHash {
+    /// Computes the digest of `domain`. Domains are case-insensitive and may be written with a
+    /// trailing dot, neither of which affects the digest.
+    #[must_use]
+    pub fn of(domain: &str) -> Self {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        Self(Sha256::digest(domain.as_bytes()).into())
+    }
+
+    /// Parses a registrable domain given on the command line and returns its digest.
+    ///
+    /// ## Errors
+    /// If `domain` contains non-ASCII characters. Internationalized domains must be given in
+    /// their ASCII (punycode) form, which is how they appear in reports.
+    pub fn from_domain(domain: &str) -> Result<Self, NonAsciiStringError> {
+        if domain.is_ascii() {
+            Ok(Self::of(domain))
+        } else {
+            Err(NonAsciiStringError::from(domain))
+        }
+    }
+
+    /// Returns `true` if `site_domain` is the domain this digest was computed from, or one of
+    /// its subdomains.
+    #[must_use]
+    pub fn matches(&self, site_domain: &str) -> bool {
+        let site_domain = site_domain.trim_end_matches('.').to_ascii_lowercase();
+        std::iter::once(site_domain.as_str())
+            .chain(
+                site_domain
+                    .match_indices('.')
+                    .map(|(i, _)| &site_domain[i + 1..]),
+            )
+            .any(|candidate| Self::of(candidate) == *self)
+    }
+}
+
+impl Debug for SiteDomainHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SiteDomainHash({self})")
+    }
+}
+
+impl Display for SiteDomainHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 #[error("{0} is not a valid event type, only 0 and 1 are allowed.")]
 pub struct UnknownEventType(u8);
@@ -185,6 +246,8 @@ pub enum InvalidReportError {
     DeserializationError(&'static str, #[source] BoxError),
     #[error("report is too short: {0}, expected length at least: {1}")]
     Length(usize, usize),
+    #[error("site_domain {0} does not belong to the site declared by the query")]
+    SiteMismatch(String),
 }
 
 /// A struct intended for the Report Collector to hold the streams of underlying
@@ -200,6 +263,20 @@ pub struct EncryptedOprfReportStreams {
 ///  `EncryptedOprfReports` formated at newline delimited hex.
 impl From<[&PathBuf; 3]> for EncryptedOprfReportStreams {
     fn from(files: [&PathBuf; 3]) -> Self {
+        Self::from_files(files, false)
+    }
+}
+
+impl EncryptedOprfReportStreams {
+    /// Reads the reports in `files`. If `input_manifest` is set, each stream ends with an
+    /// [`InputManifest`], as expected by queries with [`IpaQueryConfig::input_manifest`].
+    ///
+    /// [`IpaQueryConfig::input_manifest`]: crate::helpers::query::IpaQueryConfig::input_manifest
+    ///
+    /// ## Panics
+    /// If a file can't be read, or if the files don't have the same number of reports.
+    #[must_use]
+    pub fn from_files(files: [&PathBuf; 3], input_manifest: bool) -> Self {
         let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
         let mut query_sizes: [usize; 3] = [0, 0, 0];
         for (i, path) in files.iter().enumerate() {
@@ -227,6 +304,12 @@ impl From<[&PathBuf; 3]> for EncryptedOprfReportStreams {
         assert_eq!(query_sizes[0], query_sizes[1]);
         assert_eq!(query_sizes[1], query_sizes[2]);
 
+        if input_manifest {
+            for buffer in &mut buffers {
+                InputManifest::append_to(buffer, query_sizes[0]);
+            }
+        }
+
         Self {
             streams: buffers.map(BodyStream::from),
             // without loss of generality, set query length to length of first input size
@@ -315,6 +398,11 @@ where
     const TV_OFFSET: usize = Self::BK_OFFSET + <Replicated<BK> as Serializable>::Size::USIZE;
     const TV_END: usize = Self::TV_OFFSET + <Replicated<TV> as Serializable>::Size::USIZE;
 
+    /// The whole encrypted report, as received.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn encap_key_mk(&self) -> &[u8] {
         &self.data[Self::ENCAP_KEY_MK_OFFSET..Self::CIPHERTEXT_MK_OFFSET]
     }
@@ -436,6 +524,26 @@ where
             site_domain: self.site_domain().to_owned(),
         })
     }
+
+    /// Same as [`Self::decrypt`], but if the query declared a `site`, the report is rejected
+    /// unless it was collected for that site. The check relies on `site_domain` being
+    /// authenticated by decryption, so it must never be used to skip decryption.
+    ///
+    /// ## Errors
+    /// If the report's `site_domain` does not match `site`, or if decryption fails.
+    pub fn decrypt_for_site<P: PrivateKeyRegistry>(
+        &self,
+        key_registry: &P,
+        site: Option<&SiteDomainHash>,
+    ) -> Result<OprfReport<BK, TV, TS>, InvalidReportError> {
+        let report = self.decrypt(key_registry)?;
+        match site {
+            Some(site) if !site.matches(&report.site_domain) => {
+                Err(InvalidReportError::SiteMismatch(report.site_domain))
+            }
+            _ => Ok(report),
+        }
+    }
 }
 
 impl<BK, TV, TS> TryFrom<Bytes> for EncryptedOprfReport<BK, TV, TS, Bytes>
@@ -926,4 +1034,60 @@ mod test {
             &expected,
         );
     }
+
+    #[test]
+    fn site_domain_hash_matches_subdomains() {
+        let site = SiteDomainHash::of("abc.com");
+        assert!(site.matches("abc.com"));
+        assert!(site.matches("www.abc.com"));
+        assert!(site.matches("Shop.WWW.ABC.com."));
+        assert!(!site.matches("abc.co"));
+        assert!(!site.matches("notabc.com"));
+        assert!(!site.matches("abc.com.evil.org"));
+    }
+
+    #[test]
+    fn site_domain_hash_serde() {
+        let site = SiteDomainHash::of("abc.com");
+        let json = serde_json::to_string(&site).unwrap();
+        assert_eq!(format!("\"{site}\""), json);
+        assert_eq!(site, serde_json::from_str(&json).unwrap());
+        SiteDomainHash::from_domain("abç.com").unwrap_err();
+    }
+
+    #[test]
+    fn decrypt_for_site() {
+        let mut rng = thread_rng();
+
+        let report = OprfReport::<BA8, BA3, BA20> {
+            match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
+            breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            trigger_value: AdditiveShare::new(rng.gen(), rng.gen()),
+            event_type: Trigger,
+            epoch: rng.gen(),
+            site_domain: String::from("www.abc.com"),
+        };
+
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let enc_report_bytes = report.encrypt(0, &key_registry, &mut rng).unwrap();
+        let enc_report =
+            EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(enc_report_bytes.as_slice())
+                .unwrap();
+
+        assert_eq!(
+            report,
+            enc_report
+                .decrypt_for_site(&key_registry, Some(&SiteDomainHash::of("abc.com")))
+                .unwrap()
+        );
+        assert_eq!(
+            report,
+            enc_report.decrypt_for_site(&key_registry, None).unwrap()
+        );
+        assert!(matches!(
+            enc_report.decrypt_for_site(&key_registry, Some(&SiteDomainHash::of("xyz.com"))),
+            Err(InvalidReportError::SiteMismatch(site)) if site == "www.abc.com"
+        ));
+    }
 }
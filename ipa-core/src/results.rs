@@ -0,0 +1,1086 @@
+//! Reconstruction of query results, for report collectors.
+//!
+//! Every helper returns its replicated shares of the query output as a byte blob.
+//! [`reconstruct`] turns the blobs returned by the three helpers into values, checking that
+//! the helpers agree with each other. [`IpaResults::reconstruct`] also knows how the output of
+//! an IPA query is laid out for a given [`IpaQueryConfig`], so report collectors don't need to
+//! understand replicated sharing or the output format to get breakdown totals.
+//!
+//! Outputs may start with a [`ResultHeader`], which describes the shares that follow, so that
+//! report collectors can check them against what they expect. Outputs without a header, written
+//! by helpers that predate it, are still accepted.
+
+use generic_array::GenericArray;
+use serde::{Deserialize, Serialize};
+use typenum::Unsigned;
+
+use crate::{
+    error::BoxError,
+    ff::{
+        boolean_array::{BA16, BA32, BA8},
+        FieldType, Fp32BitPrime, Serializable, U128Conversions,
+    },
+    helpers::query::IpaQueryConfig,
+    protocol::{
+        ipa_prf::{Release, UserSampling},
+        ProtocolVersion,
+    },
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+        SharedValue,
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("helper {helper} returned {len} bytes, which is not a whole number of {share_size} byte shares")]
+    Length {
+        helper: usize,
+        len: usize,
+        share_size: usize,
+    },
+    #[error("helpers returned different numbers of values: {0:?}")]
+    CountMismatch([usize; 3]),
+    #[error("failed to deserialize value {index} from helper {helper}: {error}")]
+    Deserialize {
+        helper: usize,
+        index: usize,
+        error: BoxError,
+    },
+    #[error("helpers hold inconsistent shares of value {index}")]
+    Inconsistent { index: usize },
+    #[error("helpers disagree on whether noise was applied: {0:?}")]
+    ReleaseMismatch([u8; 3]),
+    #[error("unknown release marker: {0}")]
+    UnknownRelease(u8),
+    #[error("expected {histograms} histograms of equal size, got {values} values")]
+    MissingHistograms { histograms: usize, values: usize },
+    #[error("value was attributed to breakdown key {0}, which is out of range")]
+    BreakdownOutOfRange(usize),
+    #[error("result header of helper {helper} is truncated")]
+    TruncatedHeader { helper: usize },
+    #[error("result header version {0} is not supported, the latest supported version is {latest}", latest = ResultHeader::VERSION)]
+    UnsupportedVersion(u8),
+    #[error("unknown kind of values: {0}")]
+    UnknownValueKind(u8),
+    #[error("unknown field type: {0}")]
+    UnknownFieldType(u8),
+    #[error("helpers returned different result headers")]
+    HeaderMismatch,
+    #[error(
+        "result holds {actual:?} values of {actual_bits} bits, expected {expected:?} values of \
+         {expected_bits} bits"
+    )]
+    ValueType {
+        actual: ValueKind,
+        actual_bits: u8,
+        expected: ValueKind,
+        expected_bits: u32,
+    },
+    #[error("result header of helper {helper} announces {rows} values, but {len} bytes follow it")]
+    RowCount {
+        helper: usize,
+        rows: u32,
+        len: usize,
+    },
+    #[error("result holds {actual} histograms, the query config expects {expected}")]
+    HistogramCount { actual: u8, expected: u32 },
+}
+
+/// Kind of values shared in a query result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    BooleanArray,
+    PrimeField,
+}
+
+impl ValueKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::BooleanArray => 0,
+            Self::PrimeField => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::BooleanArray),
+            1 => Some(Self::PrimeField),
+            _ => None,
+        }
+    }
+}
+
+/// Values that query results hold shares of.
+pub trait ResultValue: SharedValue {
+    const KIND: ValueKind;
+}
+
+macro_rules! result_values {
+    ($kind:ident: $($value:ty),+) => {
+        $(impl ResultValue for $value {
+            const KIND: ValueKind = ValueKind::$kind;
+        })+
+    };
+}
+
+result_values!(BooleanArray: BA8, BA16, BA32);
+result_values!(PrimeField: Fp32BitPrime);
+#[cfg(any(test, feature = "weak-field"))]
+result_values!(PrimeField: crate::ff::Fp31);
+
+fn field_type_to_byte(field_type: FieldType) -> u8 {
+    match field_type {
+        #[cfg(any(test, feature = "weak-field"))]
+        FieldType::Fp31 => 0,
+        FieldType::Fp32BitPrime => 1,
+    }
+}
+
+fn field_type_from_byte(byte: u8) -> Option<FieldType> {
+    match byte {
+        #[cfg(any(test, feature = "weak-field"))]
+        0 => Some(FieldType::Fp31),
+        1 => Some(FieldType::Fp32BitPrime),
+        _ => None,
+    }
+}
+
+/// Header that helpers write in front of the shares of a query result.
+///
+/// The header is laid out as follows, with integers in little-endian byte order:
+///
+/// | Offset | Size | Field                                                 |
+/// |--------|------|-------------------------------------------------------|
+/// | 0      | 4    | [`Self::MAGIC`]                                       |
+/// | 4      | 1    | Version                                               |
+/// | 5      | 1    | Length of the header, in bytes                        |
+/// | 6      | 1    | [`ValueKind`]                                         |
+/// | 7      | 1    | Width of values, in bits                              |
+/// | 8      | 1    | Bit 0: partial results allowed, bit 1: partial result |
+/// | 9      | 1    | Number of histograms                                  |
+/// | 10     | 4    | Number of values                                      |
+/// | 14     | 2    | [`ProtocolVersion`] the query ran with                |
+/// | 16     | 1    | [`FieldType`] the query was requested with            |
+///
+/// Fields may be appended to the header without changing its version, readers skip whatever
+/// follows the fields they know. Changes that older readers can't ignore bump the version,
+/// which makes them reject the result instead of misreading it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResultHeader {
+    pub kind: ValueKind,
+    pub value_bits: u8,
+    /// Set only if the query allowed partial results.
+    pub release: Option<Release>,
+    pub histograms: u8,
+    pub rows: u32,
+    pub protocol_version: ProtocolVersion,
+    pub field_type: FieldType,
+}
+
+impl ResultHeader {
+    pub const MAGIC: [u8; 4] = *b"IPAR";
+    pub const VERSION: u8 = 1;
+    /// Size of the header written by this version.
+    pub const SIZE: usize = 17;
+
+    const FLAG_RELEASE: u8 = 1;
+    const FLAG_PARTIAL: u8 = 1 << 1;
+
+    #[must_use]
+    pub fn to_bytes(self) -> Vec<u8> {
+        let flags = match self.release {
+            None => 0,
+            Some(Release::Final) => Self::FLAG_RELEASE,
+            Some(Release::Partial) => Self::FLAG_RELEASE | Self::FLAG_PARTIAL,
+        };
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        bytes.extend_from_slice(&Self::MAGIC);
+        bytes.extend_from_slice(&[
+            Self::VERSION,
+            u8::try_from(Self::SIZE).unwrap(),
+            self.kind.to_byte(),
+            self.value_bits,
+            flags,
+            self.histograms,
+        ]);
+        bytes.extend_from_slice(&self.rows.to_le_bytes());
+        bytes.extend_from_slice(&self.protocol_version.get().to_le_bytes());
+        bytes.push(field_type_to_byte(self.field_type));
+        bytes
+    }
+
+    /// Splits the header off the output of `helper`. Returns `None` if the output has no
+    /// header.
+    ///
+    /// ## Errors
+    /// If the header is truncated, has a version this reader does not support or describes
+    /// values this reader does not know.
+    pub fn parse(helper: usize, output: &[u8]) -> Result<Option<(Self, &[u8])>, Error> {
+        if !output.starts_with(&Self::MAGIC) {
+            return Ok(None);
+        }
+        let truncated = || Error::TruncatedHeader { helper };
+        let version = *output.get(4).ok_or_else(truncated)?;
+        if version != Self::VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let len = usize::from(*output.get(5).ok_or_else(truncated)?);
+        if len < Self::SIZE || output.len() < len {
+            return Err(truncated());
+        }
+        let flags = output[8];
+        let header = Self {
+            kind: ValueKind::from_byte(output[6]).ok_or(Error::UnknownValueKind(output[6]))?,
+            value_bits: output[7],
+            release: (flags & Self::FLAG_RELEASE != 0).then(|| {
+                if flags & Self::FLAG_PARTIAL == 0 {
+                    Release::Final
+                } else {
+                    Release::Partial
+                }
+            }),
+            histograms: output[9],
+            rows: u32::from_le_bytes(output[10..14].try_into().unwrap()),
+            protocol_version: ProtocolVersion::new(u16::from_le_bytes(
+                output[14..16].try_into().unwrap(),
+            )),
+            field_type: field_type_from_byte(output[16])
+                .ok_or(Error::UnknownFieldType(output[16]))?,
+        };
+        Ok(Some((header, &output[len..])))
+    }
+
+    /// Checks that the values following the header are shares of `V`.
+    fn check<V>(self, helper: usize, values: &[u8]) -> Result<(), Error>
+    where
+        V: ResultValue,
+        AdditiveShare<V>: Serializable,
+    {
+        if self.kind != V::KIND || u32::from(self.value_bits) != V::BITS {
+            return Err(Error::ValueType {
+                actual: self.kind,
+                actual_bits: self.value_bits,
+                expected: V::KIND,
+                expected_bits: V::BITS,
+            });
+        }
+        let share_size = <AdditiveShare<V> as Serializable>::Size::USIZE;
+        let len = usize::try_from(self.rows)
+            .ok()
+            .and_then(|rows| rows.checked_mul(share_size));
+        if len != Some(values.len()) {
+            return Err(Error::RowCount {
+                helper,
+                rows: self.rows,
+                len: values.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Splits headers off the outputs of the three helpers. Either all outputs have the same
+/// header, or none has one.
+fn split_headers<V>(outputs: [&[u8]; 3]) -> Result<(Option<ResultHeader>, [&[u8]; 3]), Error>
+where
+    V: ResultValue,
+    AdditiveShare<V>: Serializable,
+{
+    let mut headers = [None; 3];
+    let mut values = outputs;
+    for (helper, (output, (header, shares))) in outputs
+        .into_iter()
+        .zip(headers.iter_mut().zip(&mut values))
+        .enumerate()
+    {
+        if let Some((h, v)) = ResultHeader::parse(helper, output)? {
+            h.check::<V>(helper, v)?;
+            *header = Some(h);
+            *shares = v;
+        }
+    }
+    if headers.iter().any(|h| *h != headers[0]) {
+        return Err(Error::HeaderMismatch);
+    }
+    Ok((headers[0], values))
+}
+
+/// Reconstructs values from the shares returned by the three helpers, in helper order.
+/// Outputs that start with a [`ResultHeader`] must hold shares of `V`.
+///
+/// ## Errors
+/// If the outputs can't be parsed, or if the helpers' shares are not consistent with each
+/// other.
+pub fn reconstruct<V>(outputs: [&[u8]; 3]) -> Result<Vec<V>, Error>
+where
+    V: ResultValue,
+    AdditiveShare<V>: Serializable,
+{
+    let (_, outputs) = split_headers::<V>(outputs)?;
+    reconstruct_shares(outputs)
+}
+
+fn reconstruct_shares<V>(outputs: [&[u8]; 3]) -> Result<Vec<V>, Error>
+where
+    V: SharedValue,
+    AdditiveShare<V>: Serializable,
+{
+    let share_size = <AdditiveShare<V> as Serializable>::Size::USIZE;
+    let mut shares = [(); 3].map(|()| Vec::new());
+    for (helper, (output, shares)) in outputs.iter().zip(&mut shares).enumerate() {
+        if output.len() % share_size != 0 {
+            return Err(Error::Length {
+                helper,
+                len: output.len(),
+                share_size,
+            });
+        }
+        for (index, chunk) in output.chunks(share_size).enumerate() {
+            let share =
+                AdditiveShare::<V>::deserialize(GenericArray::from_slice(chunk)).map_err(|e| {
+                    Error::Deserialize {
+                        helper,
+                        index,
+                        error: e.into(),
+                    }
+                })?;
+            shares.push(share);
+        }
+    }
+
+    let counts = shares.each_ref().map(Vec::len);
+    if counts.iter().any(|&c| c != counts[0]) {
+        return Err(Error::CountMismatch(counts));
+    }
+
+    let [s0, s1, s2] = shares;
+    s0.iter()
+        .zip(&s1)
+        .zip(&s2)
+        .enumerate()
+        .map(|(index, ((s0, s1), s2))| {
+            // Every helper shares one half of its share with each of its neighbours.
+            if s0.right() != s1.left() || s1.right() != s2.left() || s2.right() != s0.left() {
+                return Err(Error::Inconsistent { index });
+            }
+            Ok(s0.left() + s1.left() + s2.left())
+        })
+        .collect()
+}
+
+/// Optional processing applied to reconstructed totals.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PostProcessing {
+    /// If the query sampled users, scale totals up to estimates for all users. Totals are
+    /// rounded to the nearest integer.
+    pub rescale: bool,
+    /// Clamp totals to this range, after rescaling. DP noise can push totals outside of the
+    /// range of possible values, for instance below zero.
+    pub clamp: Option<(i128, i128)>,
+}
+
+impl PostProcessing {
+    fn apply(&self, sampling: Option<UserSampling>, value: i128) -> i128 {
+        let value = match sampling {
+            // Histogram values are much narrower than the mantissa of `f64`.
+            #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+            Some(sampling) if self.rescale => sampling.rescale(value as f64).round() as i128,
+            _ => value,
+        };
+        match self.clamp {
+            Some((min, max)) => value.clamp(min, max),
+            None => value,
+        }
+    }
+}
+
+/// Output of an IPA query.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpaResults {
+    /// Whether some histograms are zeroed because noise could not be added to them, see
+    /// [`Release::Partial`]. This is only ever `true` if the query allowed partial results.
+    /// All values that are not zeroed carry DP noise.
+    pub partial: bool,
+    /// Attributed value per breakdown key, for all breakdown keys below `max_breakdown_key`.
+    /// Values are negative only if the query uses signed trigger values.
+    pub breakdowns: Vec<i128>,
+    /// Number of attributed conversions per breakdown key, if the query asked for them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counts: Option<Vec<i128>>,
+    /// Number of attributed conversions per time-to-conversion bucket, if the query asked for
+    /// them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_conversion: Option<Vec<i128>>,
+    /// Number of trigger events that were suppressed because their timestamps are outside of
+    /// the bounds of the query, if it declares any. This count carries DP noise, like all other
+    /// values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suppressed_trigger_events: Option<i128>,
+    /// Results of the treatment arm, if the query is paired. All other fields then hold the
+    /// results of the control arm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treatment: Option<Box<IpaResults>>,
+    /// Results of every source site, in site order, if the query reports them per site. All
+    /// other fields then hold the totals across sites.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sites: Option<Vec<IpaResults>>,
+    /// Results of every sub-query, in tag order, if the input is tagged. All other fields then
+    /// hold the totals across tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<IpaResults>>,
+}
+
+impl IpaResults {
+    /// Reconstructs the output of an IPA query with the given configuration from the outputs
+    /// of the three helpers, in helper order. `HV` is the type of histogram values used by the
+    /// helpers.
+    ///
+    /// ## Errors
+    /// If the outputs can't be reconstructed, or if they are not consistent with `config`.
+    pub fn reconstruct<HV>(
+        config: &IpaQueryConfig,
+        outputs: [&[u8]; 3],
+        post_processing: PostProcessing,
+    ) -> Result<Self, Error>
+    where
+        HV: ResultValue + U128Conversions,
+        AdditiveShare<HV>: Serializable,
+    {
+        let (header, outputs) = split_headers::<HV>(outputs)?;
+        let (partial, outputs) = if let Some(header) = header {
+            let expected = config.output_histograms();
+            if u32::from(header.histograms) != expected {
+                return Err(Error::HistogramCount {
+                    actual: header.histograms,
+                    expected,
+                });
+            }
+            (header.release == Some(Release::Partial), outputs)
+        } else if config.allow_partial_results {
+            // Outputs without a header carry a release marker in front of the shares if the
+            // query accepts partial results.
+            let markers = outputs.map(|output| output.first().copied().unwrap_or_default());
+            if markers.iter().any(|&m| m != markers[0]) {
+                return Err(Error::ReleaseMismatch(markers));
+            }
+            let release =
+                Release::from_byte(markers[0]).ok_or(Error::UnknownRelease(markers[0]))?;
+            (
+                release == Release::Partial,
+                outputs.map(|output| output.get(1..).unwrap_or_default()),
+            )
+        } else {
+            (true, outputs)
+        };
+
+        let mut values = reconstruct_shares::<HV>(outputs)?;
+        // Attributed conversion counts, the time-to-conversion histogram and the histogram of
+        // suppressed trigger events, if requested, follow the histogram of attributed values, in
+        // this order.
+        let has_time_to_conversion = config.time_to_conversion_bucket_seconds.is_some();
+        let has_timestamp_bounds = config.has_timestamp_bounds();
+        let histograms = usize::try_from(config.output_histograms()).unwrap();
+        if values.len() % histograms != 0 {
+            return Err(Error::MissingHistograms {
+                histograms,
+                values: values.len(),
+            });
+        }
+        let histogram_len = values.len() / histograms;
+        // Only the first bucket holds the count, the others only hold noise.
+        let suppressed = has_timestamp_bounds
+            .then(|| values.split_off(values.len() - histogram_len))
+            .map(|suppressed| {
+                let count = suppressed.first().copied().unwrap_or(HV::ZERO);
+                post_processing.apply(None, to_i128::<HV>(count, false))
+            });
+        let time_to_conversion =
+            has_time_to_conversion.then(|| values.split_off(values.len() - histogram_len));
+        let mut counts = config
+            .attributed_counts
+            .then(|| values.split_off(histogram_len));
+
+        let sampling = config
+            .user_sampling_rate
+            .filter(|&rate| UserSampling::is_valid_rate(rate))
+            .map(UserSampling::new);
+        let to_buckets = |values: Vec<HV>, buckets: u32, signed: bool| {
+            let buckets = usize::try_from(buckets).unwrap_or(usize::MAX);
+            if config.with_dp == 0 {
+                // Without noise, nothing can be attributed to buckets that don't exist.
+                if let Some(bk) = values.iter().skip(buckets).position(|v| *v != HV::ZERO) {
+                    return Err(Error::BreakdownOutOfRange(buckets + bk));
+                }
+            }
+            Ok(values
+                .into_iter()
+                .take(buckets)
+                .map(|v| post_processing.apply(sampling, to_i128::<HV>(v, signed)))
+                .collect::<Vec<_>>())
+        };
+
+        let arm =
+            |values: Vec<HV>, counts: Option<Vec<HV>>, time_to_conversion: Option<Vec<HV>>| {
+                Ok::<_, Error>(Self {
+                    partial,
+                    breakdowns: to_buckets(
+                        values,
+                        config.max_breakdown_key,
+                        config.signed_trigger_values,
+                    )?,
+                    counts: counts
+                        .map(|c| to_buckets(c, config.max_breakdown_key, false))
+                        .transpose()?,
+                    time_to_conversion: time_to_conversion
+                        .map(|c| to_buckets(c, config.time_to_conversion_buckets, false))
+                        .transpose()?,
+                    suppressed_trigger_events: None,
+                    treatment: None,
+                    sites: None,
+                    tags: None,
+                })
+            };
+
+        if config.paired_arms {
+            // The treatment arm has the upper half of the breakdown keys.
+            let half = histogram_len / 2;
+            let treatment = arm(
+                values.split_off(half),
+                counts.as_mut().map(|c| c.split_off(half)),
+                None,
+            )?;
+            Ok(Self {
+                suppressed_trigger_events: suppressed,
+                treatment: Some(Box::new(treatment)),
+                ..arm(values, counts, time_to_conversion)?
+            })
+        } else if let Some((bits, parts)) = config
+            .source_site_bits()
+            .zip(config.source_sites)
+            .or(config.public_tag_bits().zip(config.public_tags))
+        {
+            // Every site or tag has the same share of the breakdown keys, in site or tag order.
+            let width = (histogram_len >> bits).max(1);
+            let parts = usize::try_from(parts).unwrap();
+            let mut part_counts = counts.as_ref().map(|c| c.chunks(width));
+            let parts = values
+                .chunks(width)
+                .take(parts)
+                .map(|values| {
+                    let counts = part_counts
+                        .as_mut()
+                        .map(|c| c.next().unwrap_or_default().to_vec());
+                    arm(values.to_vec(), counts, None)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let totals = Self {
+                partial,
+                breakdowns: sum_buckets(parts.iter().map(|part| &part.breakdowns)),
+                counts: counts
+                    .is_some()
+                    .then(|| sum_buckets(parts.iter().filter_map(|part| part.counts.as_ref()))),
+                time_to_conversion: None,
+                suppressed_trigger_events: suppressed,
+                treatment: None,
+                sites: None,
+                tags: None,
+            };
+            Ok(if config.source_sites.is_some() {
+                Self {
+                    sites: Some(parts),
+                    ..totals
+                }
+            } else {
+                Self {
+                    tags: Some(parts),
+                    ..totals
+                }
+            })
+        } else {
+            Ok(Self {
+                suppressed_trigger_events: suppressed,
+                ..arm(values, counts, time_to_conversion)?
+            })
+        }
+    }
+}
+
+/// Adds up histograms bucket by bucket.
+fn sum_buckets<'a, I: IntoIterator<Item = &'a Vec<i128>>>(histograms: I) -> Vec<i128> {
+    histograms
+        .into_iter()
+        .fold(Vec::new(), |mut total, histogram| {
+            total.resize(total.len().max(histogram.len()), 0);
+            for (total, value) in total.iter_mut().zip(histogram) {
+                *total += value;
+            }
+            total
+        })
+}
+
+/// Interprets `value` as an unsigned integer, or as a two's complement integer if `signed` is
+/// set.
+fn to_i128<V: SharedValue + U128Conversions>(value: V, signed: bool) -> i128 {
+    let value = i128::try_from(value.as_u128()).expect("histogram values are narrower than i128");
+    if signed && value >> (V::BITS - 1) == 1 {
+        value - (1 << V::BITS)
+    } else {
+        value
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use generic_array::GenericArray;
+
+    use super::{reconstruct, Error, IpaResults, PostProcessing, ResultHeader, ValueKind};
+    use crate::{
+        ff::{boolean_array::BA16, FieldType, Serializable, U128Conversions},
+        helpers::query::IpaQueryConfig,
+        protocol::{ipa_prf::Release, ProtocolVersion},
+        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+    };
+
+    fn outputs(values: &[u128], marker: Option<Release>) -> [Vec<u8>; 3] {
+        let shares: [Vec<AdditiveShare<BA16>>; 3] =
+            values.iter().map(|&v| BA16::truncate_from(v)).share();
+        shares.map(|shares| {
+            let mut bytes = marker.map(Release::to_byte).into_iter().collect::<Vec<_>>();
+            for share in shares {
+                let mut buf =
+                    GenericArray::<u8, <AdditiveShare<BA16> as Serializable>::Size>::default();
+                share.serialize(&mut buf);
+                bytes.extend_from_slice(&buf);
+            }
+            bytes
+        })
+    }
+
+    fn with_header(header: ResultHeader, outputs: [Vec<u8>; 3]) -> [Vec<u8>; 3] {
+        outputs.map(|output| [header.to_bytes(), output].concat())
+    }
+
+    fn results(
+        config: &IpaQueryConfig,
+        outputs: &[Vec<u8>; 3],
+        post_processing: PostProcessing,
+    ) -> Result<IpaResults, Error> {
+        IpaResults::reconstruct::<BA16>(
+            config,
+            outputs.each_ref().map(Vec::as_slice),
+            post_processing,
+        )
+    }
+
+    #[test]
+    fn reconstructs_values() {
+        let outputs = outputs(&[1, 2, 0xFFFF], None);
+        let values = reconstruct::<BA16>(outputs.each_ref().map(Vec::as_slice)).unwrap();
+        assert_eq!(
+            vec![1, 2, 0xFFFF],
+            values
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn detects_inconsistent_shares() {
+        let mut outputs = outputs(&[1, 2], None);
+        outputs[1][5] ^= 1;
+        assert!(matches!(
+            reconstruct::<BA16>(outputs.each_ref().map(Vec::as_slice)),
+            Err(Error::Inconsistent { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn detects_length_mismatch() {
+        let mut outputs = outputs(&[1, 2], None);
+        outputs[2].pop();
+        assert!(matches!(
+            reconstruct::<BA16>(outputs.each_ref().map(Vec::as_slice)),
+            Err(Error::Length { helper: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn ipa_layout() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            allow_partial_results: true,
+            attributed_counts: true,
+            signed_trigger_values: true,
+            ..IpaQueryConfig::default()
+        };
+        let outputs = outputs(&[5, 0xFFFE, 7, 3, 1, 9], Some(Release::Partial));
+        let results = results(&config, &outputs, PostProcessing::default()).unwrap();
+        assert_eq!(
+            IpaResults {
+                partial: true,
+                breakdowns: vec![5, -2],
+                counts: Some(vec![3, 1]),
+                time_to_conversion: None,
+                suppressed_trigger_events: None,
+                treatment: None,
+                sites: None,
+                tags: None,
+            },
+            results
+        );
+    }
+
+    #[test]
+    fn header() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            allow_partial_results: true,
+            attributed_counts: true,
+            ..IpaQueryConfig::default()
+        };
+        let header = ResultHeader {
+            kind: ValueKind::BooleanArray,
+            value_bits: 16,
+            release: Some(Release::Partial),
+            histograms: 2,
+            rows: 4,
+            protocol_version: ProtocolVersion::CURRENT,
+            field_type: FieldType::Fp32BitPrime,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(ResultHeader::SIZE, bytes.len());
+        assert_eq!(
+            Some((header, &[][..])),
+            ResultHeader::parse(0, &bytes).unwrap()
+        );
+        let headed = with_header(header, outputs(&[5, 7, 3, 1], None));
+        assert_eq!(
+            IpaResults {
+                partial: true,
+                breakdowns: vec![5, 7],
+                counts: Some(vec![3, 1]),
+                time_to_conversion: None,
+                suppressed_trigger_events: None,
+                treatment: None,
+                sites: None,
+                tags: None,
+            },
+            results(&config, &headed, PostProcessing::default()).unwrap()
+        );
+        assert_eq!(
+            4,
+            reconstruct::<BA16>(headed.each_ref().map(Vec::as_slice))
+                .unwrap()
+                .len()
+        );
+
+        // Fields appended by later revisions of the header are skipped.
+        let mut bytes = header.to_bytes();
+        bytes[5] += 2;
+        bytes.extend([0xFF; 2]);
+        let extended = outputs(&[5, 7, 3, 1], None).map(|output| [bytes.clone(), output].concat());
+        assert!(
+            results(&config, &extended, PostProcessing::default())
+                .unwrap()
+                .partial
+        );
+    }
+
+    #[test]
+    fn header_checks() {
+        let config = IpaQueryConfig::default();
+        let header = ResultHeader {
+            kind: ValueKind::BooleanArray,
+            value_bits: 16,
+            release: None,
+            histograms: 1,
+            rows: 2,
+            protocol_version: ProtocolVersion::CURRENT,
+            field_type: FieldType::Fp32BitPrime,
+        };
+        let check = |outputs: [Vec<u8>; 3]| {
+            results(&config, &outputs, PostProcessing::default()).unwrap_err()
+        };
+
+        assert!(matches!(
+            check(with_header(
+                ResultHeader {
+                    value_bits: 32,
+                    ..header
+                },
+                outputs(&[1, 2], None)
+            )),
+            Error::ValueType {
+                actual_bits: 32,
+                expected_bits: 16,
+                ..
+            }
+        ));
+        assert!(matches!(
+            check(with_header(
+                ResultHeader {
+                    kind: ValueKind::PrimeField,
+                    ..header
+                },
+                outputs(&[1, 2], None)
+            )),
+            Error::ValueType {
+                actual: ValueKind::PrimeField,
+                expected: ValueKind::BooleanArray,
+                ..
+            }
+        ));
+        assert!(matches!(
+            check(with_header(
+                ResultHeader {
+                    histograms: 2,
+                    ..header
+                },
+                outputs(&[1, 2], None)
+            )),
+            Error::HistogramCount {
+                actual: 2,
+                expected: 1
+            }
+        ));
+        assert!(matches!(
+            check(with_header(header, outputs(&[1, 2, 3], None))),
+            Error::RowCount {
+                helper: 0,
+                rows: 2,
+                ..
+            }
+        ));
+
+        let mut outputs = with_header(header, outputs(&[1, 2], None));
+        let headerless = outputs[2].split_off(ResultHeader::SIZE);
+        assert!(matches!(
+            check([outputs[0].clone(), outputs[1].clone(), headerless]),
+            Error::HeaderMismatch
+        ));
+
+        let mut unknown_field = outputs.clone();
+        unknown_field[1][16] = 0xFF;
+        assert!(matches!(
+            check(unknown_field),
+            Error::UnknownFieldType(0xFF)
+        ));
+
+        outputs[0][4] = ResultHeader::VERSION + 1;
+        assert!(matches!(
+            check(outputs.clone()),
+            Error::UnsupportedVersion(v) if v == ResultHeader::VERSION + 1
+        ));
+        outputs[0].truncate(ResultHeader::SIZE - 1);
+        outputs[0][4] = ResultHeader::VERSION;
+        assert!(matches!(
+            check(outputs),
+            Error::TruncatedHeader { helper: 0 }
+        ));
+    }
+
+    #[test]
+    fn paired_layout() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            with_dp: 0,
+            attributed_counts: true,
+            paired_arms: true,
+            ..IpaQueryConfig::default()
+        };
+        let outputs = outputs(&[5, 7, 0, 0, 2, 3, 0, 0, 1, 2, 0, 0, 1, 1, 0, 0], None);
+        assert_eq!(
+            IpaResults {
+                partial: false,
+                breakdowns: vec![5, 7],
+                counts: Some(vec![1, 2]),
+                time_to_conversion: None,
+                suppressed_trigger_events: None,
+                treatment: Some(Box::new(IpaResults {
+                    partial: false,
+                    breakdowns: vec![2, 3],
+                    counts: Some(vec![1, 1]),
+                    time_to_conversion: None,
+                    suppressed_trigger_events: None,
+                    treatment: None,
+                    sites: None,
+                    tags: None,
+                })),
+                sites: None,
+                tags: None,
+            },
+            results(&config, &outputs, PostProcessing::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn site_layout() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            with_dp: 0,
+            attributed_counts: true,
+            source_sites: Some(3),
+            ..IpaQueryConfig::default()
+        };
+        let site = |breakdowns: Vec<i128>, counts: Vec<i128>| IpaResults {
+            partial: false,
+            breakdowns,
+            counts: Some(counts),
+            time_to_conversion: None,
+            suppressed_trigger_events: None,
+            treatment: None,
+            sites: None,
+            tags: None,
+        };
+        // Three sites take two bits of the breakdown key, the fourth quarter is unused.
+        let outputs = outputs(&[5, 7, 2, 3, 0, 1, 0, 0, 1, 2, 1, 1, 0, 1, 0, 0], None);
+        assert_eq!(
+            IpaResults {
+                partial: false,
+                breakdowns: vec![7, 11],
+                counts: Some(vec![2, 4]),
+                time_to_conversion: None,
+                suppressed_trigger_events: None,
+                treatment: None,
+                sites: Some(vec![
+                    site(vec![5, 7], vec![1, 2]),
+                    site(vec![2, 3], vec![1, 1]),
+                    site(vec![0, 1], vec![0, 1]),
+                ]),
+                tags: None,
+            },
+            results(&config, &outputs, PostProcessing::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn tag_layout() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            with_dp: 0,
+            public_tags: Some(2),
+            ..IpaQueryConfig::default()
+        };
+        let tag = |breakdowns: Vec<i128>| IpaResults {
+            partial: false,
+            breakdowns,
+            counts: None,
+            time_to_conversion: None,
+            suppressed_trigger_events: None,
+            treatment: None,
+            sites: None,
+            tags: None,
+        };
+        let outputs = outputs(&[5, 7, 0, 0, 2, 3, 0, 0], None);
+        assert_eq!(
+            IpaResults {
+                partial: false,
+                breakdowns: vec![7, 10],
+                counts: None,
+                time_to_conversion: None,
+                suppressed_trigger_events: None,
+                treatment: None,
+                sites: None,
+                tags: Some(vec![tag(vec![5, 7]), tag(vec![2, 3])]),
+            },
+            results(&config, &outputs, PostProcessing::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn timestamp_bounds_layout() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            with_dp: 0,
+            attributed_counts: true,
+            max_timestamp: Some(100),
+            ..IpaQueryConfig::default()
+        };
+        let outputs = outputs(&[5, 7, 0, 0, 1, 2, 0, 0, 3, 0, 0, 0], None);
+        assert_eq!(
+            IpaResults {
+                partial: false,
+                breakdowns: vec![5, 7],
+                counts: Some(vec![1, 2]),
+                time_to_conversion: None,
+                suppressed_trigger_events: Some(3),
+                treatment: None,
+                sites: None,
+                tags: None,
+            },
+            results(&config, &outputs, PostProcessing::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn time_to_conversion_layout() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            with_dp: 0,
+            time_to_conversion_bucket_seconds: NonZeroU32::new(3600),
+            time_to_conversion_buckets: 3,
+            ..IpaQueryConfig::default()
+        };
+        let complete = outputs(&[5, 7, 0, 0, 1, 4, 2, 0], None);
+        assert_eq!(
+            IpaResults {
+                partial: false,
+                breakdowns: vec![5, 7],
+                counts: None,
+                time_to_conversion: Some(vec![1, 4, 2]),
+                suppressed_trigger_events: None,
+                treatment: None,
+                sites: None,
+                tags: None,
+            },
+            results(&config, &complete, PostProcessing::default()).unwrap()
+        );
+
+        let truncated = outputs(&[5, 7, 0, 1, 4, 2, 0], None);
+        assert!(matches!(
+            results(&config, &truncated, PostProcessing::default()),
+            Err(Error::MissingHistograms {
+                histograms: 2,
+                values: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn out_of_range_breakdown() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 2,
+            with_dp: 0,
+            ..IpaQueryConfig::default()
+        };
+        let outputs = outputs(&[5, 1, 0, 2], None);
+        assert!(matches!(
+            results(&config, &outputs, PostProcessing::default()),
+            Err(Error::BreakdownOutOfRange(3))
+        ));
+    }
+
+    #[test]
+    fn post_processing() {
+        let config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            user_sampling_rate: Some(0.25),
+            signed_trigger_values: true,
+            ..IpaQueryConfig::default()
+        };
+        let outputs = outputs(&[5, 0xFFFF, 100], None);
+        let post_processing = PostProcessing {
+            rescale: true,
+            clamp: Some((0, 300)),
+        };
+        let processed = results(&config, &outputs, post_processing).unwrap();
+        assert_eq!(vec![20, 0, 300], processed.breakdowns);
+
+        let raw = results(&config, &outputs, PostProcessing::default()).unwrap();
+        assert_eq!(vec![5, -1, 100], raw.breakdowns);
+    }
+}
@@ -26,6 +26,7 @@ pub use vector::{
     FieldArray, FieldSimd, FieldVectorizable, SharedValueArray, StdArray, TransposeFrom,
     Vectorizable,
 };
+use zeroize::Zeroize;
 
 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
 use crate::secret_sharing::replicated::semi_honest::AdditiveShare;
@@ -83,6 +84,7 @@ pub trait SharedValue:
     + Additive
     + Sendable
     + Vectorizable<1>
+    + Zeroize
     + 'static
 {
     type Storage: Block;
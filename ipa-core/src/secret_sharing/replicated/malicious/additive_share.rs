@@ -12,6 +12,7 @@ use futures::{
 use generic_array::{ArrayLength, GenericArray};
 use subtle::ConstantTimeEq;
 use typenum::Unsigned;
+use zeroize::Zeroize;
 
 use crate::{
     ff::{Field, Gf2, Gf32Bit, PrimeField, Serializable, U128Conversions},
@@ -35,7 +36,7 @@ use crate::{
 ///
 /// This makes it possible to minimize communication overhead required to reach a desired level of statistical security.
 ///
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Zeroize)]
 pub struct AdditiveShare<V: SharedValue + ExtendableFieldSimd<N>, const N: usize = 1> {
     x: SemiHonestAdditiveShare<V, N>,
     rx: SemiHonestAdditiveShare<V::ExtendedField, N>,
@@ -6,6 +6,7 @@ use std::{
 
 use generic_array::{ArrayLength, GenericArray};
 use typenum::Unsigned;
+use zeroize::Zeroize;
 
 use crate::{
     ff::{boolean::Boolean, boolean_array::BooleanArray, ArrayAccess, Expand, Field, Serializable},
@@ -19,7 +20,7 @@ use crate::{
 ///
 /// `AdditiveShare` holds two out of three shares of an additive secret sharing, either of a single
 /// value with type `V`, or a vector of such values.
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Zeroize)]
 pub struct AdditiveShare<V: SharedValue + Vectorizable<N>, const N: usize = 1>(
     <V as Vectorizable<N>>::Array,
     <V as Vectorizable<N>>::Array,
@@ -517,6 +518,7 @@ mod tests {
         prelude::{prop, Arbitrary, Strategy},
         proptest,
     };
+    use zeroize::Zeroize;
 
     use crate::{
         ff::{Fp31, Fp32BitPrime, U128Conversions},
@@ -526,6 +528,16 @@ mod tests {
         },
     };
 
+    #[test]
+    fn zeroize() {
+        let mut share = AdditiveShare::<Fp32BitPrime, 32>::from_fns(
+            |i| Fp32BitPrime::truncate_from(u128::try_from(i).unwrap() + 1),
+            |i| Fp32BitPrime::truncate_from(u128::try_from(i).unwrap() + 7),
+        );
+        share.zeroize();
+        assert_eq!(AdditiveShare::default(), share);
+    }
+
     fn secret_share(
         a: u8,
         b: u8,
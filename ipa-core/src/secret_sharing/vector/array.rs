@@ -7,6 +7,7 @@ use std::{
 
 use generic_array::{ArrayLength, GenericArray};
 use typenum::{Unsigned, U16, U256, U32, U64};
+use zeroize::Zeroize;
 
 use crate::{
     const_assert_eq,
@@ -23,7 +24,7 @@ use crate::{
 ///    don't let us implement for `[V; N]`.
 ///  * It disables by-index access to individual elements of the array, which
 ///    should never be necessary in properly vectorized code.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Zeroize)]
 pub struct StdArray<V: SharedValue, const N: usize>(pub(super) [V; N]);
 
 impl<V, T, const N: usize> PartialEq<T> for StdArray<V, N>
@@ -3,7 +3,9 @@
 use crate::{
     ff::{
         boolean::Boolean,
-        boolean_array::{BA16, BA20, BA256, BA3, BA32, BA5, BA64, BA8},
+        boolean_array::{
+            BA10, BA1024, BA12, BA16, BA20, BA24, BA256, BA3, BA32, BA4096, BA5, BA64, BA8,
+        },
         ec_prime_field::Fp25519,
         Fp32BitPrime, Gf32Bit,
     },
@@ -66,8 +68,13 @@ macro_rules! boolean_vector {
 boolean_vector!(bav_3, 3, BA3);
 boolean_vector!(bav_5, 5, BA5);
 boolean_vector!(bav_8, 8, BA8);
+boolean_vector!(bav_10, 10, BA10);
+boolean_vector!(bav_12, 12, BA12);
 boolean_vector!(bav_16, 16, BA16);
 boolean_vector!(bav_20, 20, BA20);
+boolean_vector!(bav_24, 24, BA24);
 boolean_vector!(bav_32, 32, BA32);
 boolean_vector!(bav_64, 64, BA64);
 boolean_vector!(bav_256, 256, BA256);
+boolean_vector!(bav_1024, 1024, BA1024);
+boolean_vector!(bav_4096, 4096, BA4096);
@@ -3,6 +3,8 @@ use std::{
     ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign},
 };
 
+use zeroize::Zeroize;
+
 use crate::{
     error::LengthError,
     ff::Field,
@@ -84,6 +86,7 @@ pub trait SharedValueArray<V>:
     + Sync
     + Sized
     + Sendable
+    + Zeroize
     + TryFrom<Vec<V>, Error = LengthError>
     + FromIterator<V>
     + IntoIterator<Item = V>
@@ -36,14 +36,22 @@
 // This rule throws false positives on "MxN".
 #![allow(clippy::doc_markdown)]
 
-use std::{array, borrow::Borrow, convert::Infallible, ops::Deref};
+use std::{
+    array,
+    borrow::Borrow,
+    cmp::{max, min},
+    convert::Infallible,
+    mem,
+    num::NonZeroUsize,
+    ops::Deref,
+};
 
 use crate::{
     const_assert_eq,
     error::{LengthError, UnwrapInfallible},
     ff::{
         boolean::Boolean,
-        boolean_array::{BA16, BA256, BA3, BA32, BA5, BA64, BA8},
+        boolean_array::{BA10, BA1024, BA12, BA16, BA256, BA3, BA32, BA4096, BA5, BA64, BA8},
         ec_prime_field::Fp25519,
     },
     protocol::ipa_prf::{CONV_CHUNK, MK_BITS},
@@ -615,6 +623,10 @@ impl_transpose_shares_bool_to_ba!(BA256, 256, 256, test_transpose_shares_bool_to
 impl_transpose_shares_bool_to_ba_small!(BA8, 8, 256, test_transpose_shares_bool_to_ba_8x256);
 
 impl_transpose_shares_bool_to_ba!(BA16, 16, 256, test_transpose_shares_bool_to_ba_16x256);
+impl_transpose_shares_bool_to_ba!(BA16, 16, 1024, test_transpose_shares_bool_to_ba_16x1024);
+impl_transpose_shares_bool_to_ba!(BA16, 16, 4096, test_transpose_shares_bool_to_ba_16x4096);
+impl_transpose_shares_bool_to_ba!(BA32, 32, 1024, test_transpose_shares_bool_to_ba_32x1024);
+impl_transpose_shares_bool_to_ba!(BA32, 32, 4096, test_transpose_shares_bool_to_ba_32x4096);
 impl_transpose_shares_bool_to_ba!(BA16, 16, 32, test_transpose_shares_bool_to_ba_16x32);
 impl_transpose_shares_bool_to_ba!(BA32, 32, 256, test_transpose_shares_bool_to_ba_32x256);
 impl_transpose_shares_bool_to_ba_small!(BA8, 8, 32, test_transpose_shares_bool_to_ba_8x32);
@@ -785,6 +797,8 @@ macro_rules! impl_transpose_shares_ba_to_bool_small {
 // Usage: Aggregation input. M = AGG_CHUNK, N = BK or TV bits.
 impl_transpose_shares_ba_to_bool_small!(BA32, 256, 32, test_transpose_shares_ba_to_bool_256x32); // Addtional Usage: Quicksort. M = SORT_CHUNK, N = sort key bits.
 impl_transpose_shares_ba_to_bool_small!(BA16, 256, 16, test_transpose_shares_ba_to_bool_256x16);
+impl_transpose_shares_ba_to_bool_small!(BA12, 256, 12, test_transpose_shares_ba_to_bool_256x12);
+impl_transpose_shares_ba_to_bool_small!(BA10, 256, 10, test_transpose_shares_ba_to_bool_256x10);
 impl_transpose_shares_ba_to_bool_small!(BA8, 256, 8, test_transpose_shares_ba_to_bool_256x8);
 impl_transpose_shares_ba_to_bool_small!(BA5, 256, 5, test_transpose_shares_ba_to_bool_256x5);
 impl_transpose_shares_ba_to_bool_small!(BA3, 256, 3, test_transpose_shares_ba_to_bool_256x3);
@@ -795,6 +809,17 @@ impl_transpose_shares_ba_to_bool_small!(BA8, 32, 8, test_transpose_shares_ba_to_
 // Usage tests for aggregation based on reveal
 impl_transpose_shares_ba_to_bool_small!(BA3, 32, 3, test_transpose_shares_ba_to_bool_32x3);
 
+// Usage: Aggregation output (TV) and noise (HV) with 10 and 12 bit breakdown keys.
+// M = number of breakdowns (2^|bk|), N = TV or HV bits.
+impl_transpose_shares_ba_to_bool_small!(BA32, 1024, 32, test_transpose_shares_ba_to_bool_1024x32);
+impl_transpose_shares_ba_to_bool_small!(BA16, 1024, 16, test_transpose_shares_ba_to_bool_1024x16);
+impl_transpose_shares_ba_to_bool_small!(BA8, 1024, 8, test_transpose_shares_ba_to_bool_1024x8);
+impl_transpose_shares_ba_to_bool_small!(BA3, 1024, 3, test_transpose_shares_ba_to_bool_1024x3);
+impl_transpose_shares_ba_to_bool_small!(BA32, 4096, 32, test_transpose_shares_ba_to_bool_4096x32);
+impl_transpose_shares_ba_to_bool_small!(BA16, 4096, 16, test_transpose_shares_ba_to_bool_4096x16);
+impl_transpose_shares_ba_to_bool_small!(BA8, 4096, 8, test_transpose_shares_ba_to_bool_4096x8);
+impl_transpose_shares_ba_to_bool_small!(BA3, 4096, 3, test_transpose_shares_ba_to_bool_4096x3);
+
 // Usage: Laplace noise mechanism. M = number of breakdowns (2^|bk|), N = OV bits.
 impl_transpose_shares_ba_to_bool!(BA32, 32, 32, test_transpose_shares_ba_to_bool_32x32);
 impl_transpose_shares_ba_to_bool!(BA16, 32, 16, test_transpose_shares_ba_to_bool_32x16);
@@ -895,6 +920,95 @@ macro_rules! impl_aggregation_transpose {
 // Arguments: BA{M}, BA{N}, M, N
 impl_aggregation_transpose!(BA256, BA256, 256, 256, test_aggregation_transpose_256x256);
 impl_aggregation_transpose!(BA32, BA256, 32, 256, test_aggregation_transpose_32x256);
+impl_aggregation_transpose!(
+    BA1024,
+    BA256,
+    1024,
+    256,
+    test_aggregation_transpose_1024x256
+);
+impl_aggregation_transpose!(
+    BA4096,
+    BA256,
+    4096,
+    256,
+    test_aggregation_transpose_4096x256
+);
+
+/// Values in `N` columns, to be transposed into rows of `N` values.
+///
+/// Transposing all the columns at once needs memory for both the columns and the rows, which for
+/// 256 columns of aggregation input is a lot. Columns are kept in blocks of a fixed number of rows
+/// instead, and [`into_rows`] transposes them one block at a time, releasing the columns of a
+/// block once its rows are out. Columns that are shorter than the longest one are padded with
+/// zeros.
+///
+/// [`into_rows`]: Self::into_rows
+pub struct BlockedColumns<T, const N: usize> {
+    columns: [Vec<Vec<T>>; N],
+    block_rows: NonZeroUsize,
+    rows: usize,
+}
+
+impl<T: Default, const N: usize> BlockedColumns<T, N> {
+    #[must_use]
+    pub fn new(block_rows: NonZeroUsize) -> Self {
+        Self {
+            columns: array::from_fn(|_| Vec::new()),
+            block_rows,
+            rows: 0,
+        }
+    }
+
+    /// Appends `value` to `column`.
+    ///
+    /// ## Panics
+    /// If `column` is not less than `N`.
+    pub fn push(&mut self, column: usize, value: T) {
+        let blocks = &mut self.columns[column];
+        match blocks.last_mut() {
+            Some(block) if block.len() < self.block_rows.get() => block.push(value),
+            _ => blocks.push(vec![value]),
+        }
+        let len = (blocks.len() - 1) * self.block_rows.get() + blocks[blocks.len() - 1].len();
+        self.rows = max(self.rows, len);
+    }
+
+    /// Length of the longest column.
+    #[must_use]
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Transposes the columns into rows, in the order values were pushed. Only the block that is
+    /// being transposed is held in memory twice.
+    pub fn into_rows<R>(self) -> impl Iterator<Item = R>
+    where
+        R: for<'a> TransposeFrom<&'a [T; N], Error = Infallible> + Default,
+    {
+        let Self {
+            mut columns,
+            block_rows,
+            rows,
+        } = self;
+        let block_rows = block_rows.get();
+        (0..rows.div_ceil(block_rows)).flat_map(move |b| {
+            let mut block = columns.each_mut().map(|column| {
+                column
+                    .get_mut(b)
+                    .map(mem::take)
+                    .unwrap_or_default()
+                    .into_iter()
+            });
+            (0..min(block_rows, rows - b * block_rows)).map(move |_| {
+                let row = block
+                    .each_mut()
+                    .map(|values| values.next().unwrap_or_default());
+                R::transposed_from(&row).unwrap_infallible()
+            })
+        })
+    }
+}
 
 #[cfg(all(test, unit_test))]
 mod tests {
@@ -1284,6 +1398,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn blocked_columns() {
+        let mut rng = thread_rng();
+        let mut columns: [Vec<AdditiveShare<BA3>>; 32] = array::from_fn(|_| Vec::new());
+        let mut blocked = BlockedColumns::<_, 32>::new(NonZeroUsize::new(3).unwrap());
+        for _ in 0..100 {
+            let column = rng.gen_range(0..32);
+            let value = AdditiveShare::<BA3>::new(rng.gen(), rng.gen());
+            columns[column].push(value.clone());
+            blocked.push(column, value);
+        }
+        let rows = columns.iter().map(Vec::len).max().unwrap();
+        assert_eq!(rows, blocked.rows());
+
+        let expected = (0..rows)
+            .map(|i| {
+                let row = columns
+                    .each_ref()
+                    .map(|column| column.get(i).cloned().unwrap_or_default());
+                BitDecomposed::<AdditiveShare<Boolean, 32>>::transposed_from(&row)
+                    .unwrap_infallible()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(expected, blocked.into_rows().collect::<Vec<_>>());
+    }
+
     pub(super) fn test_aggregation_transpose<
         const SM: usize, // Source rows (== dest cols)
         const DM: usize, // Destination rows (== source cols)
@@ -63,3 +63,56 @@ pub mod duration {
         Ok(secs.map(Duration::from_secs_f64))
     }
 }
+
+/// Names of the fields of a struct that derives [`Deserialize`], as they appear in its
+/// serialized form.
+///
+/// Formats that can't tell a struct apart from a map, such as query strings, use them to reject
+/// fields the struct does not have.
+///
+/// [`Deserialize`]: serde::Deserialize
+#[cfg(feature = "web-app")]
+pub mod fields {
+    use serde::{
+        de::{value::Error, Error as _, Visitor},
+        forward_to_deserialize_any, Deserialize, Deserializer,
+    };
+
+    /// Deserializer that does not deserialize anything, but records the fields `T` asks for.
+    struct StructFields<'a>(&'a mut Option<&'static [&'static str]>);
+
+    impl<'de> Deserializer<'de> for StructFields<'_> {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = Some(fields);
+            Err(Error::custom("only the fields were requested"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    /// Returns the names of the fields of `T`.
+    ///
+    /// ## Panics
+    /// If `T` does not deserialize from a struct.
+    #[must_use]
+    pub fn of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+        let mut fields = None;
+        let _ = T::deserialize(StructFields(&mut fields));
+        fields.unwrap_or_else(|| panic!("{} is not a struct", std::any::type_name::<T>()))
+    }
+}
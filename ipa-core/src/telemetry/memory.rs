@@ -0,0 +1,192 @@
+//! Heap usage of protocol stages.
+//!
+//! Memory is what limits the size of the queries a helper can run, so the tuning report shows
+//! how much of it every top-level stage of the protocol needed. Protocols wrap their stages in
+//! [`stage`], which records the heap size when the stage started and the peak heap size while it
+//! ran into the [`MemoryProfile`] of the query.
+//!
+//! Measurements require the `alloc-profiling` feature and a binary that installs
+//! [`TrackingAllocator`] as its global allocator. Without them, [`stage`] does nothing and
+//! reports come out without memory stats. The allocator counts allocations of the whole process,
+//! so when several queries run at once, each of them is charged for the memory of all of them.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Heap usage of a single stage, in bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageMemory {
+    pub step: String,
+    /// Heap size when the stage started.
+    pub start_bytes: usize,
+    /// Largest heap size while the stage ran.
+    pub peak_bytes: usize,
+}
+
+tokio::task_local! {
+    static PROFILE: MemoryProfile;
+}
+
+/// Heap usage of all the stages of a query, in the order they completed.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryProfile {
+    stages: Arc<Mutex<Vec<StageMemory>>>,
+}
+
+impl MemoryProfile {
+    /// Runs `f` with this profile collecting the stages it goes through. Stages must run on the
+    /// task that polls `f`; stages of tasks spawned by it are not recorded.
+    pub async fn scope<F: Future>(&self, f: F) -> F::Output {
+        PROFILE.scope(self.clone(), f).await
+    }
+
+    /// Returns the stages recorded so far.
+    ///
+    /// ## Panics
+    /// If the profile mutex is poisoned.
+    #[must_use]
+    pub fn stages(&self) -> Vec<StageMemory> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    fn record(&self, stage: StageMemory) {
+        self.stages.lock().unwrap().push(stage);
+    }
+}
+
+/// Runs the protocol stage `f` and records its heap usage under `step` into the profile of the
+/// current query, if there is one.
+pub async fn stage<F: Future>(step: &str, f: F) -> F::Output {
+    #[cfg(feature = "alloc-profiling")]
+    {
+        let start_bytes = tracking::reset_peak();
+        let output = f.await;
+        let stage = StageMemory {
+            step: step.to_string(),
+            start_bytes,
+            peak_bytes: tracking::peak(),
+        };
+        // Running outside of a query is fine, there is just nowhere to report to.
+        let _ = PROFILE.try_with(|profile| profile.record(stage));
+        output
+    }
+    #[cfg(not(feature = "alloc-profiling"))]
+    {
+        let _ = step;
+        f.await
+    }
+}
+
+#[cfg(feature = "alloc-profiling")]
+pub use tracking::TrackingAllocator;
+
+#[cfg(feature = "alloc-profiling")]
+mod tracking {
+    use std::{
+        alloc::{GlobalAlloc, Layout},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    /// Global allocator that keeps track of the heap size on top of the allocator `A`.
+    pub struct TrackingAllocator<A>(pub A);
+
+    impl<A> TrackingAllocator<A> {
+        fn grow(size: usize) {
+            let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+
+        fn shrink(size: usize) {
+            ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+        }
+    }
+
+    // SAFETY: all allocations are made by `A`, this only counts them.
+    unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = self.0.alloc(layout);
+            if !ptr.is_null() {
+                Self::grow(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = self.0.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                Self::grow(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.0.dealloc(ptr, layout);
+            Self::shrink(layout.size());
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = self.0.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                if new_size > layout.size() {
+                    Self::grow(new_size - layout.size());
+                } else {
+                    Self::shrink(layout.size() - new_size);
+                }
+            }
+            new_ptr
+        }
+    }
+
+    /// Starts a new peak measurement. Returns the current heap size.
+    pub fn reset_peak() -> usize {
+        let allocated = ALLOCATED.load(Ordering::Relaxed);
+        PEAK.store(allocated, Ordering::Relaxed);
+        allocated
+    }
+
+    /// Largest heap size since the last call to [`reset_peak`].
+    pub fn peak() -> usize {
+        PEAK.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{stage, MemoryProfile};
+
+    #[tokio::test]
+    async fn records_stages_in_scope() {
+        let profile = MemoryProfile::default();
+        let output = profile
+            .scope(async {
+                let a = stage("a", async { vec![0_u8; 1024].len() }).await;
+                let b = stage("b", async { 1 }).await;
+                a + b
+            })
+            .await;
+        assert_eq!(1025, output);
+
+        let steps = profile
+            .stages()
+            .into_iter()
+            .map(|s| s.step)
+            .collect::<Vec<_>>();
+        if cfg!(feature = "alloc-profiling") {
+            assert_eq!(vec!["a", "b"], steps);
+        } else {
+            assert!(steps.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn no_scope() {
+        assert_eq!(1, stage("a", async { 1 }).await);
+    }
+}
@@ -1,5 +1,10 @@
+pub mod memory;
+pub mod progress;
+pub mod rounds;
+pub mod send_buffers;
 pub mod stats;
 mod step_stats;
+pub mod tuning;
 
 pub use step_stats::CsvExporter as StepStatsCsvExporter;
 
@@ -13,10 +18,15 @@ pub mod metrics {
     pub const REQUESTS_RECEIVED: &str = "requests.received";
     pub const RECORDS_SENT: &str = "records.sent";
     pub const BYTES_SENT: &str = "bytes.sent";
+    pub const SEND_BUFFER_FLUSHES: &str = "send.buffer.flushes";
+    pub const SEND_BUFFERS_STUCK: &str = "send.buffers.stuck";
+    pub const SEND_PREEMPTIONS: &str = "send.preemptions";
     pub const INDEXED_PRSS_GENERATED: &str = "i.prss.gen";
     pub const SEQUENTIAL_PRSS_GENERATED: &str = "s.prss.gen";
     pub use ::ipa_step::descriptive::labels::STEP_NARROWED;
     pub const DZKP_BATCH_INCREMENTS: &str = "batch.realloc.front";
+    pub const DECRYPTION_FAILURES: &str = "decryption.failures";
+    pub const CONNECTIONS_REFUSED: &str = "connections.refused";
 
     #[cfg(feature = "web-app")]
     pub mod web {
@@ -0,0 +1,236 @@
+//! Progress of running queries, and estimates of when they complete.
+//!
+//! Protocols announce the top-level stages they are going to run with [`plan`], along with the
+//! number of records every stage processes, and wrap every stage in [`stage`]. The throughput of
+//! the stages that completed, in records per second, tells how long the remaining ones are going
+//! to take. Estimates are available once the first stage completes and get more accurate with
+//! every stage that follows, so report collectors can schedule the collection of results instead
+//! of polling blindly.
+//!
+//! Stages differ in their cost per record, so estimates are rough. They are meant to tell when
+//! it is worth asking again, not to bound the time a query takes.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::telemetry::memory;
+
+tokio::task_local! {
+    static PROGRESS: QueryProgress;
+}
+
+/// Estimated completion time of a running query.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryEta {
+    /// Time since the query started running.
+    pub elapsed_ms: u64,
+    /// Estimated time until the protocol completes.
+    pub remaining_ms: u64,
+    pub completed_stages: usize,
+    pub planned_stages: usize,
+}
+
+struct PlannedStage {
+    step: String,
+    records: usize,
+    /// Set once the stage completed.
+    duration: Option<Duration>,
+}
+
+#[derive(Default)]
+struct Stages {
+    planned: Vec<PlannedStage>,
+    /// Start of the stage that is running, if any.
+    running: Option<Instant>,
+}
+
+/// Stages of a query, as planned by the protocol and measured while they run.
+#[derive(Clone)]
+pub struct QueryProgress {
+    start: Instant,
+    stages: Arc<Mutex<Stages>>,
+}
+
+impl Default for QueryProgress {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            stages: Arc::default(),
+        }
+    }
+}
+
+impl QueryProgress {
+    /// Runs `f` with this progress tracking the stages it goes through. Like
+    /// [`memory::MemoryProfile::scope`], stages must run on the task that polls `f`.
+    pub async fn scope<F: Future>(&self, f: F) -> F::Output {
+        PROGRESS.scope(self.clone(), f).await
+    }
+
+    /// Estimates the time until the query completes. Returns `None` until a planned stage
+    /// completes.
+    ///
+    /// ## Panics
+    /// If the progress mutex is poisoned.
+    #[must_use]
+    pub fn eta(&self) -> Option<QueryEta> {
+        self.eta_at(Instant::now())
+    }
+
+    fn eta_at(&self, now: Instant) -> Option<QueryEta> {
+        let stages = self.stages.lock().unwrap();
+        let (completed, remaining): (Vec<_>, Vec<_>) = stages
+            .planned
+            .iter()
+            .partition(|stage| stage.duration.is_some());
+        if completed.is_empty() {
+            return None;
+        }
+
+        // Stages without records still take time, so every stage counts at least one.
+        let records = |stages: &[&PlannedStage]| {
+            stages
+                .iter()
+                .map(|stage| stage.records.max(1))
+                .sum::<usize>()
+        };
+        let completed_time = completed
+            .iter()
+            .filter_map(|stage| stage.duration)
+            .sum::<Duration>();
+        // Record counts are far below the precision of `f64`.
+        #[allow(clippy::cast_precision_loss)]
+        let remaining_time = completed_time
+            .mul_f64(records(&remaining) as f64 / records(&completed) as f64)
+            .saturating_sub(
+                stages
+                    .running
+                    .map_or(Duration::ZERO, |start| now.saturating_duration_since(start)),
+            );
+
+        let millis = |d: Duration| u64::try_from(d.as_millis()).unwrap_or(u64::MAX);
+        Some(QueryEta {
+            elapsed_ms: millis(now.saturating_duration_since(self.start)),
+            remaining_ms: millis(remaining_time),
+            completed_stages: completed.len(),
+            planned_stages: stages.planned.len(),
+        })
+    }
+
+    fn plan<'a, I: IntoIterator<Item = (&'a str, usize)>>(&self, stages: I) {
+        self.stages.lock().unwrap().planned = stages
+            .into_iter()
+            .map(|(step, records)| PlannedStage {
+                step: step.to_string(),
+                records,
+                duration: None,
+            })
+            .collect();
+    }
+
+    fn start_stage(&self, start: Instant) {
+        self.stages.lock().unwrap().running = Some(start);
+    }
+
+    /// Marks the first planned stage named `step` that has not completed yet as completed.
+    /// Stages that were not planned don't count towards the estimate.
+    fn complete_stage(&self, step: &str, duration: Duration) {
+        let mut stages = self.stages.lock().unwrap();
+        stages.running = None;
+        if let Some(stage) = stages
+            .planned
+            .iter_mut()
+            .find(|stage| stage.duration.is_none() && stage.step == step)
+        {
+            stage.duration = Some(duration);
+        }
+    }
+}
+
+/// Announces the top-level stages the current query is going to run, in order, along with the
+/// number of records every one of them processes. Replaces any earlier plan.
+pub fn plan<'a, I: IntoIterator<Item = (&'a str, usize)>>(stages: I) {
+    // Running outside of a query is fine, there is just nobody to report to.
+    let _ = PROGRESS.try_with(|progress| progress.plan(stages));
+}
+
+/// Runs the protocol stage `f`, recording how long it took into the progress of the current
+/// query, if there is one. Also records its heap usage, see [`memory::stage`].
+pub async fn stage<F: Future>(step: &str, f: F) -> F::Output {
+    let start = Instant::now();
+    let _ = PROGRESS.try_with(|progress| progress.start_stage(start));
+    let output = memory::stage(step, f).await;
+    let _ = PROGRESS.try_with(|progress| progress.complete_stage(step, start.elapsed()));
+    output
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{plan, stage, QueryProgress};
+
+    #[test]
+    fn no_estimate_before_first_stage() {
+        let progress = QueryProgress::default();
+        progress.plan([("a", 10), ("b", 10)]);
+        assert_eq!(None, progress.eta());
+    }
+
+    #[test]
+    fn estimate_from_throughput() {
+        let progress = QueryProgress::default();
+        progress.plan([("a", 100), ("b", 100), ("c", 200)]);
+        progress.complete_stage("a", Duration::from_secs(2));
+
+        // 100 records took 2 seconds, the remaining 300 should take 6.
+        let now = Instant::now();
+        progress.start_stage(now);
+        let eta = progress.eta_at(now).unwrap();
+        assert_eq!(6_000, eta.remaining_ms);
+        assert_eq!((1, 3), (eta.completed_stages, eta.planned_stages));
+
+        // Time spent in the running stage counts.
+        let eta = progress.eta_at(now + Duration::from_secs(1)).unwrap();
+        assert_eq!(5_000, eta.remaining_ms);
+
+        // Stages that were not planned don't.
+        progress.complete_stage("d", Duration::from_secs(100));
+        progress.complete_stage("b", Duration::from_secs(4));
+        let eta = progress.eta_at(Instant::now()).unwrap();
+        assert_eq!(6_000, eta.remaining_ms);
+
+        progress.complete_stage("c", Duration::from_secs(1));
+        let eta = progress.eta().unwrap();
+        assert_eq!(
+            (0, 3, 3),
+            (eta.remaining_ms, eta.completed_stages, eta.planned_stages)
+        );
+    }
+
+    #[tokio::test]
+    async fn records_stages_in_scope() {
+        let progress = QueryProgress::default();
+        let output = progress
+            .scope(async {
+                plan([("a", 1), ("b", 1)]);
+                stage("a", async { 1 }).await
+            })
+            .await;
+        assert_eq!(1, output);
+
+        let eta = progress.eta().unwrap();
+        assert_eq!((1, 2), (eta.completed_stages, eta.planned_stages));
+    }
+
+    #[tokio::test]
+    async fn no_scope() {
+        plan([("a", 1)]);
+        assert_eq!(1, stage("a", async { 1 }).await);
+    }
+}
@@ -0,0 +1,280 @@
+//! Communication rounds of protocol stages.
+//!
+//! Latency of a protocol between distant helpers is dominated by the number of times helpers
+//! have to wait for each other, not by the number of bytes they send. The rounds of a stage are
+//! the number of steps inside it that receive data one after the other. A refactor that makes a
+//! step wait on another step it used to run concurrently with shows up as more rounds, even when
+//! the number of bytes sent stays the same.
+//!
+//! Every context carries a [`Depth`]: the number of rounds of the computation that led to it.
+//! Contexts derived from it, by narrowing or cloning, start at the depth of their parent, and
+//! the depth they reach is passed back to their ancestors. Work that runs on a context derived
+//! after another step finished continues from the depth that step reached, while work on
+//! contexts derived at the same time, like the branches of a parallel join or the records of a
+//! step, does not see the rounds of its siblings.
+//!
+//! A step is a single round for all records that go through it. The first receive on a step
+//! fixes its depth to one more than the depth of the context that waited for it, and receives
+//! of later records reuse that depth. Records processed in later windows of active work therefore
+//! don't add rounds, and steps that renumber records are counted like any other.
+//!
+//! The depth follows the order in which contexts are derived rather than the data that flows
+//! between them, so the counts are an upper bound. Work done on a context derived after an
+//! unrelated step finished is counted after it.
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{protocol::Gate, sync::Arc};
+
+/// Number of sequential communication rounds of a top-level stage.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageRounds {
+    pub step: String,
+    pub rounds: u32,
+}
+
+/// Depths of the steps of a top-level stage.
+struct Stage {
+    /// Lowest depth of a context that entered the stage.
+    start: AtomicU32,
+    /// Highest depth of a step that received data in the stage.
+    end: AtomicU32,
+    steps: DashMap<Gate, u32>,
+}
+
+impl Default for Stage {
+    fn default() -> Self {
+        Self {
+            start: AtomicU32::new(u32::MAX),
+            end: AtomicU32::new(0),
+            steps: DashMap::default(),
+        }
+    }
+}
+
+impl Stage {
+    /// Returns the depth of `gate`, fixing it to `depth` if this is the first receive on it.
+    fn step(&self, gate: &Gate, depth: u32) -> u32 {
+        let depth = match self.steps.get(gate) {
+            Some(step) => *step,
+            None => *self.steps.entry(gate.clone()).or_insert(depth),
+        };
+        self.end.fetch_max(depth, Ordering::Relaxed);
+        depth
+    }
+}
+
+/// Collects the rounds of every top-level stage of a query.
+#[derive(Default)]
+pub struct RoundCounter {
+    stages: DashMap<String, Arc<Stage>>,
+}
+
+impl RoundCounter {
+    fn enter(&self, stage: &str, depth: u32) -> Arc<Stage> {
+        let stage = match self.stages.get(stage) {
+            Some(stage) => Arc::clone(&stage),
+            None => Arc::clone(&self.stages.entry(stage.to_string()).or_default()),
+        };
+        stage.start.fetch_min(depth, Ordering::Relaxed);
+        stage
+    }
+
+    /// Returns the rounds of every stage that received data, ordered by stage.
+    #[must_use]
+    pub fn stages(&self) -> Vec<StageRounds> {
+        self.stages
+            .iter()
+            .filter_map(|entry| {
+                let (start, end) = (
+                    entry.start.load(Ordering::Relaxed),
+                    entry.end.load(Ordering::Relaxed),
+                );
+                (end > start).then(|| (entry.key().clone(), end - start))
+            })
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(step, rounds)| StageRounds { step, rounds })
+            .collect()
+    }
+}
+
+struct Node {
+    depth: AtomicU32,
+    parent: Option<Arc<Node>>,
+}
+
+impl Node {
+    /// Raises the depth of this node and its ancestors to at least `depth`. A node is never
+    /// deeper than its parent, so this stops at the first ancestor that is deep enough.
+    fn raise(&self, depth: u32) {
+        let mut node = Some(self);
+        while let Some(current) = node {
+            if current.depth.load(Ordering::Relaxed) >= depth {
+                break;
+            }
+            current.depth.fetch_max(depth, Ordering::Relaxed);
+            node = current.parent.as_deref();
+        }
+    }
+}
+
+/// Number of communication rounds of the computation a context belongs to.
+///
+/// Cloning it derives a new depth that starts where this one is and reports back to it, see the
+/// [module documentation](self).
+pub struct Depth {
+    node: Arc<Node>,
+    stage: Option<Arc<Stage>>,
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self {
+            node: Arc::new(Node {
+                depth: AtomicU32::new(0),
+                parent: None,
+            }),
+            stage: None,
+        }
+    }
+}
+
+impl Clone for Depth {
+    fn clone(&self) -> Self {
+        Self {
+            node: Arc::new(Node {
+                depth: AtomicU32::new(self.current()),
+                parent: Some(Arc::clone(&self.node)),
+            }),
+            stage: self.stage.clone(),
+        }
+    }
+}
+
+impl Depth {
+    /// Derives the depth of a context narrowed to `gate`. If that enters a top-level stage, the
+    /// stage is registered with `counter`.
+    #[must_use]
+    pub fn narrow(&self, gate: &Gate, counter: &RoundCounter) -> Self {
+        let mut depth = self.clone();
+        if depth.stage.is_none() {
+            depth.stage = stage(gate).map(|stage| counter.enter(stage, depth.current()));
+        }
+        depth
+    }
+
+    /// Returns a handle that updates this depth rather than deriving a new one from it.
+    #[must_use]
+    pub fn share(&self) -> Self {
+        Self {
+            node: Arc::clone(&self.node),
+            stage: self.stage.clone(),
+        }
+    }
+
+    /// Current depth, to be passed to [`Self::received`] once the data arrives.
+    #[must_use]
+    pub fn current(&self) -> u32 {
+        self.node.depth.load(Ordering::Relaxed)
+    }
+
+    /// Records that data for `gate` arrived on a receive that started at depth `start`.
+    pub fn received(&self, gate: &Gate, start: u32) {
+        let depth = match &self.stage {
+            Some(stage) => stage.step(gate, start + 1),
+            None => start + 1,
+        };
+        self.node.raise(depth);
+    }
+}
+
+/// Top-level stage that `gate` belongs to: the protocol and the first step under it, for
+/// example `ipa_prf/attribution`. Returns `None` for gates above that.
+fn stage(gate: &Gate) -> Option<&str> {
+    let path = gate.as_ref().trim_start_matches('/');
+    let mut separators = path.match_indices('/').map(|(i, _)| i);
+    separators.next()?;
+    Some(separators.next().map_or(path, |end| &path[..end]))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{Depth, RoundCounter, StageRounds};
+    use crate::protocol::Gate;
+
+    fn rounds(counter: &RoundCounter) -> Vec<(String, u32)> {
+        counter
+            .stages()
+            .into_iter()
+            .map(|StageRounds { step, rounds }| (step, rounds))
+            .collect()
+    }
+
+    /// Receives on `gate` through a context narrowed to it from `depth`.
+    fn receive(depth: &Depth, gate: &str, counter: &RoundCounter) {
+        let gate = Gate::from(gate);
+        let depth = depth.narrow(&gate, counter);
+        depth.received(&gate, depth.current());
+    }
+
+    #[test]
+    fn sequential_steps() {
+        let counter = RoundCounter::default();
+        let root = Depth::default();
+        let sort = root.narrow(&Gate::from("/ipa_prf/sort"), &counter);
+        receive(&sort, "/ipa_prf/sort/a", &counter);
+        receive(&sort, "/ipa_prf/sort/b", &counter);
+        let attribution = root.narrow(&Gate::from("/ipa_prf/attribution"), &counter);
+        receive(&attribution, "/ipa_prf/attribution/c", &counter);
+
+        assert_eq!(
+            vec![
+                ("ipa_prf/attribution".to_string(), 1),
+                ("ipa_prf/sort".to_string(), 2)
+            ],
+            rounds(&counter)
+        );
+    }
+
+    #[test]
+    fn concurrent_steps() {
+        let counter = RoundCounter::default();
+        let sort = Depth::default().narrow(&Gate::from("/ipa_prf/sort"), &counter);
+        // both branches are derived before either of them receives
+        let (a, b) = (sort.clone(), sort.clone());
+        receive(&a, "/ipa_prf/sort/a", &counter);
+        receive(&b, "/ipa_prf/sort/b", &counter);
+        receive(&a, "/ipa_prf/sort/a/c", &counter);
+
+        assert_eq!(vec![("ipa_prf/sort".to_string(), 2)], rounds(&counter));
+    }
+
+    #[test]
+    fn records_share_step_depth() {
+        let counter = RoundCounter::default();
+        let sort = Depth::default().narrow(&Gate::from("/ipa_prf/sort"), &counter);
+        for _ in 0..3 {
+            // every record is derived after the previous one finished
+            let record = sort.clone();
+            receive(&record, "/ipa_prf/sort/a", &counter);
+            receive(&record, "/ipa_prf/sort/b", &counter);
+        }
+
+        assert_eq!(vec![("ipa_prf/sort".to_string(), 2)], rounds(&counter));
+        assert_eq!(2, sort.current());
+    }
+
+    #[test]
+    fn ignores_gates_above_stages() {
+        let counter = RoundCounter::default();
+        receive(&Depth::default(), "/ipa_prf", &counter);
+        assert!(counter.stages().is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+//! Live view of the gateway send buffers.
+//!
+//! When a protocol stalls because a channel's batch never fills up, the only visible symptom is
+//! that nothing happens. [`SendBufferStatus`] captures what every buffer is doing right now, so
+//! the stuck channel and the record it is waiting for can be found without attaching a debugger.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Point-in-time state of a single send buffer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendBufferStatus {
+    pub step: String,
+    pub peer: String,
+    /// Number of bytes held in the buffer.
+    pub fill: usize,
+    /// Buffer capacity, in bytes.
+    pub capacity: usize,
+    /// The oldest record that has not been written to the buffer yet. All records after it are
+    /// waiting for it.
+    pub pending_record: usize,
+    pub total_records: Option<usize>,
+    /// Time since the buffer was last flushed to the network, or since the first write if it
+    /// has never been flushed. Absent if nothing was written yet.
+    pub idle_ms: Option<u64>,
+}
+
+impl SendBufferStatus {
+    /// Returns `true` if this buffer holds data that has not been flushed for at least `age`.
+    /// Empty buffers are waiting for the protocol to produce records, not for the network, so
+    /// they are never stuck.
+    #[must_use]
+    pub fn is_stuck(&self, age: Duration) -> bool {
+        self.fill > 0
+            && self
+                .idle_ms
+                .is_some_and(|idle| u128::from(idle) >= age.as_millis())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use super::SendBufferStatus;
+
+    #[test]
+    fn is_stuck() {
+        let status = SendBufferStatus {
+            step: "protocol".to_string(),
+            peer: "H2".to_string(),
+            fill: 1,
+            capacity: 8,
+            pending_record: 1,
+            total_records: Some(2),
+            idle_ms: Some(1000),
+        };
+        assert!(status.is_stuck(Duration::from_secs(1)));
+        assert!(!status.is_stuck(Duration::from_secs(2)));
+        assert!(!SendBufferStatus {
+            idle_ms: None,
+            ..status.clone()
+        }
+        .is_stuck(Duration::ZERO));
+        assert!(!SendBufferStatus { fill: 0, ..status }.is_stuck(Duration::ZERO));
+    }
+}
@@ -209,6 +209,51 @@ impl MetricAssertion<'_> {
         self.clone()
     }
 
+    /// Validates that metric total value (i.e. ignoring dimensionality) does not exceed the
+    /// budget. Unlike [`Self::total`], this does not need to be updated when a protocol gets
+    /// cheaper, but still fails when it becomes more expensive.
+    /// ## Panics
+    /// Panics if value is greater than `budget`
+    pub fn total_at_most<I: TryInto<u64>>(&self, budget: I) -> Self {
+        let budget = budget.try_into().ok().unwrap();
+        let actual = self.snapshot.total_value;
+        assert!(
+            actual <= budget,
+            "expected {} to be emitted at most {budget} times, got {actual}",
+            self.name
+        );
+        self.clone()
+    }
+
+    /// Validates that metric value, summed over `gate` and all the steps narrowed from it, does
+    /// not exceed the budget. Steps that did not emit this metric count as zero.
+    /// ## Panics
+    /// Panics if value is greater than `budget`
+    pub fn per_step_at_most<I: TryInto<u64>>(&self, gate: &Gate, budget: I) -> Self {
+        let budget = budget.try_into().ok().unwrap();
+        let actual = self.under_step(gate);
+        assert!(
+            actual <= budget,
+            "expected {} to be emitted at most {budget} times at {gate:?}, got {actual}",
+            self.name
+        );
+        self.clone()
+    }
+
+    /// Returns the metric value summed over `gate` and all the steps narrowed from it.
+    #[must_use]
+    pub fn under_step(&self, gate: &Gate) -> u64 {
+        let gate = gate.as_ref();
+        self.get_dimension(labels::STEP)
+            .iter()
+            .filter(|(step, _)| {
+                step.strip_prefix(gate)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map(|(_, &value)| value)
+            .sum()
+    }
+
     fn get_dimension(&self, name: &'static str) -> &HashMap<String, u64> {
         self.snapshot.dimensions.get(name).unwrap_or_else(|| {
             panic!(
@@ -0,0 +1,310 @@
+//! Tuning report produced at the end of every query.
+//!
+//! The gateway measures how every step used its send buffers: how long the step was sending for,
+//! how much data it sent, how full the buffers got and how often writers had to wait for the
+//! network to drain them. [`TuningReport::new`] turns these measurements into recommendations
+//! for the gateway configuration, so operators don't need to read raw metrics to find out that
+//! `active_work` is too low for their workload.
+//!
+//! With the `alloc-profiling` feature, the report also shows how much heap memory the top-level
+//! stages of the protocol used, see [`memory`]. It always shows how many sequential
+//! communication rounds every top-level stage took, see [`rounds`].
+//!
+//! [`memory`]: crate::telemetry::memory
+//! [`rounds`]: crate::telemetry::rounds
+//!
+//! Retransmissions happen inside TCP, below the transport, and are not visible to helpers, so
+//! they are not part of the report.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    protocol::QueryId,
+    telemetry::{memory::StageMemory, rounds::StageRounds},
+};
+
+/// Stages that sent fewer records than this are too small to draw conclusions from.
+const MIN_RECORDS: usize = 64;
+
+/// Writers waiting on a full buffer for at least this share of writes (in percent) means the
+/// buffer is too small.
+const BLOCKED_WRITES_PERCENT: usize = 5;
+
+/// Only the slowest stages are included in the report, to keep status responses small.
+/// Recommendations cover all of them.
+const MAX_STAGES: usize = 32;
+
+/// Send buffer measurements for a single step, summed over all peers.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageStats {
+    pub step: String,
+    /// Time between the first and the last record sent by this step.
+    pub duration_ms: u64,
+    pub records: usize,
+    pub bytes: usize,
+    /// Number of chunks handed to the network.
+    pub flushes: usize,
+    /// Number of times a record could not be written because the send buffer was full.
+    pub blocked_writes: usize,
+    /// Size of a single record, in bytes.
+    pub record_size: usize,
+    /// Send buffer capacity, in bytes.
+    pub capacity: usize,
+    /// Largest number of bytes held in a send buffer at once.
+    pub peak_occupancy: usize,
+}
+
+/// A suggested change to the helper configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recommendation {
+    /// Step that prompted the recommendation.
+    pub step: String,
+    /// Name of the configuration parameter to change.
+    pub parameter: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TuningReport {
+    pub duration_ms: u64,
+    pub active_work: usize,
+    pub read_size: usize,
+    /// The slowest stages of the query, slowest first.
+    pub stages: Vec<StageStats>,
+    pub recommendations: Vec<Recommendation>,
+    /// Heap usage of the top-level protocol stages, in the order they ran. Empty unless memory
+    /// profiling is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<StageMemory>,
+    /// Sequential communication rounds of the top-level protocol stages, ordered by stage.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rounds: Vec<StageRounds>,
+}
+
+impl TuningReport {
+    /// Builds a report for a query that took `duration` to run with the given gateway
+    /// configuration.
+    #[must_use]
+    pub fn new(
+        duration: Duration,
+        active_work: usize,
+        read_size: usize,
+        mut stages: Vec<StageStats>,
+    ) -> Self {
+        stages.sort_by(|a, b| {
+            b.duration_ms
+                .cmp(&a.duration_ms)
+                .then_with(|| a.step.cmp(&b.step))
+        });
+        let recommendations = stages
+            .iter()
+            .flat_map(|stage| stage.recommend(active_work, read_size))
+            .collect();
+        stages.truncate(MAX_STAGES);
+
+        Self {
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            active_work,
+            read_size,
+            stages,
+            recommendations,
+            memory: Vec::new(),
+            rounds: Vec::new(),
+        }
+    }
+
+    /// Adds the heap usage of the query stages, as collected by [`stage`].
+    ///
+    /// [`stage`]: crate::telemetry::memory::stage
+    #[must_use]
+    pub fn with_memory(mut self, memory: Vec<StageMemory>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Adds the communication rounds of the query stages, as counted by [`RoundCounter`].
+    ///
+    /// [`RoundCounter`]: crate::telemetry::rounds::RoundCounter
+    #[must_use]
+    pub fn with_rounds(mut self, rounds: Vec<StageRounds>) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Emits this report as a single JSON record.
+    pub fn log(&self, query_id: QueryId) {
+        match serde_json::to_string(self) {
+            Ok(report) => tracing::info!(
+                target: "ipa_core::telemetry::tuning",
+                query_id = %query_id,
+                tuning_report = %report,
+                "query finished"
+            ),
+            Err(e) => tracing::warn!(
+                target: "ipa_core::telemetry::tuning",
+                query_id = %query_id,
+                "failed to serialize tuning report: {e}"
+            ),
+        }
+    }
+}
+
+impl StageStats {
+    fn recommend(&self, active_work: usize, read_size: usize) -> Vec<Recommendation> {
+        let mut recommendations = Vec::new();
+        if self.records < MIN_RECORDS || self.record_size == 0 {
+            return recommendations;
+        }
+        let mut recommend = |message: String| {
+            recommendations.push(Recommendation {
+                step: self.step.clone(),
+                parameter: "active_work".to_string(),
+                message,
+            });
+        };
+
+        // Batches can't be larger than the send buffer, which holds `active_work` records.
+        if self.capacity < read_size && self.flushes > 1 {
+            let needed = read_size.div_ceil(self.record_size).next_power_of_two();
+            recommend(format!(
+                "active_work {active_work} is too low for {} byte records: batches are capped \
+                 at {} bytes instead of {read_size}. Use at least {needed}.",
+                self.record_size, self.capacity
+            ));
+        } else if self.blocked_writes * 100 >= self.records * BLOCKED_WRITES_PERCENT {
+            recommend(format!(
+                "Send buffer was full on {} of {} writes. Increase active_work above \
+                 {active_work}.",
+                self.blocked_writes, self.records
+            ));
+        }
+
+        recommendations
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use super::{StageStats, TuningReport};
+    use crate::telemetry::{memory::StageMemory, rounds::StageRounds};
+
+    fn stage(step: &str, duration_ms: u64) -> StageStats {
+        StageStats {
+            step: step.to_string(),
+            duration_ms,
+            records: 1024,
+            bytes: 1024 * 16,
+            flushes: 8,
+            blocked_writes: 0,
+            record_size: 16,
+            capacity: 16 * 1024,
+            peak_occupancy: 2048,
+        }
+    }
+
+    fn parameters(report: &TuningReport) -> Vec<(&str, &str)> {
+        report
+            .recommendations
+            .iter()
+            .map(|r| (r.step.as_str(), r.parameter.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn no_recommendations() {
+        let report = TuningReport::new(
+            Duration::from_secs(1),
+            1024,
+            2048,
+            vec![stage("a", 10), stage("b", 20)],
+        );
+        assert_eq!(1000, report.duration_ms);
+        assert_eq!(
+            vec!["b", "a"],
+            report
+                .stages
+                .iter()
+                .map(|s| s.step.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(report.recommendations.is_empty());
+    }
+
+    #[test]
+    fn small_batches() {
+        let report = TuningReport::new(
+            Duration::ZERO,
+            8,
+            2048,
+            vec![StageStats {
+                capacity: 8 * 16,
+                ..stage("a", 10)
+            }],
+        );
+        assert_eq!(vec![("a", "active_work")], parameters(&report));
+        assert!(report.recommendations[0]
+            .message
+            .contains("Use at least 128"));
+    }
+
+    #[test]
+    fn blocked_writes() {
+        let report = TuningReport::new(
+            Duration::ZERO,
+            1024,
+            2048,
+            vec![
+                StageStats {
+                    blocked_writes: 100,
+                    ..stage("a", 10)
+                },
+                StageStats {
+                    blocked_writes: 10,
+                    ..stage("b", 10)
+                },
+            ],
+        );
+        assert_eq!(vec![("a", "active_work")], parameters(&report));
+    }
+
+    #[test]
+    fn ignores_small_stages() {
+        let report = TuningReport::new(
+            Duration::ZERO,
+            1024,
+            2048,
+            vec![StageStats {
+                records: 10,
+                blocked_writes: 10,
+                ..stage("a", 10)
+            }],
+        );
+        assert!(report.recommendations.is_empty());
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        let report = TuningReport::new(Duration::ZERO, 8, 2048, vec![stage("a", 10)]);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(report, serde_json::from_str(&json).unwrap());
+        assert!(!json.contains("memory"));
+        assert!(!json.contains("rounds"));
+
+        let report = report
+            .with_memory(vec![StageMemory {
+                step: "a".to_string(),
+                start_bytes: 1024,
+                peak_bytes: 4096,
+            }])
+            .with_rounds(vec![StageRounds {
+                step: "a".to_string(),
+                rounds: 3,
+            }]);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(report, serde_json::from_str(&json).unwrap());
+    }
+}
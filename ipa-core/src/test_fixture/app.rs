@@ -64,8 +64,15 @@ fn unzip_tuple_array<T, U, V>(input: [(T, U, V); 3]) -> ([T; 3], [U; 3], [V; 3])
 
 impl Default for TestApp {
     fn default() -> Self {
-        let (setup, handlers, _shard_handlers) =
-            unzip_tuple_array(array::from_fn(|_| AppSetup::new(AppConfig::default())));
+        Self::with_configs(array::from_fn(|_| AppConfig::default()))
+    }
+}
+
+impl TestApp {
+    /// Creates helpers that are configured with `configs`, in the order of their identities.
+    #[must_use]
+    pub fn with_configs(configs: [AppConfig; 3]) -> Self {
+        let (setup, handlers, _shard_handlers) = unzip_tuple_array(configs.map(AppSetup::new));
 
         let mpc_network = InMemoryMpcNetwork::new(handlers.map(Some));
         let shard_network = InMemoryShardNetwork::with_shards(1);
@@ -85,9 +92,7 @@ impl Default for TestApp {
             shard_network,
         }
     }
-}
 
-impl TestApp {
     /// Initiates a new query on all helpers and drives it to completion.
     ///
     /// ## Errors
@@ -115,6 +120,7 @@ impl TestApp {
                 self.drivers[i].execute_query(QueryInput {
                     query_id,
                     input_stream: input.into(),
+                    contribution: None,
                 })
             })
             .collect::<Result<Vec<_>, _>>()?;
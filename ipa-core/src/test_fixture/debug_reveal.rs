@@ -0,0 +1,173 @@
+//! Plaintext intermediates of protocols running in [`TestWorld`].
+//!
+//! Finding a bug in MPC code usually starts with looking at the values a protocol computes at
+//! some step, which no helper can see on its own. [`TestWorld`] runs all three helpers in the
+//! same task, so [`debug_reveal`] can collect the shares of every helper out of band and log
+//! the reconstructed values once the last of them arrives. Nothing is sent over the network and
+//! the protocol runs exactly as it would without it.
+//!
+//! This module only exists in test builds, so calls from protocol code must be gated on the
+//! same configuration, for example:
+//!
+//! ```ignore
+//! #[cfg(all(any(test, feature = "test-fixture"), feature = "in-memory-infra"))]
+//! crate::test_fixture::debug_reveal(&ctx, "keys", rows.iter().map(|row| &row.breakdown_key));
+//! ```
+//!
+//! Values are logged at `TRACE` level with this module as the target, so they only show up when
+//! asked for, e.g. with `RUST_LOG=ipa_core::test_fixture::debug_reveal=trace`. Protocols that
+//! don't run inside [`TestWorld`] (for example the helpers of a `TestApp`) log nothing.
+//!
+//! [`TestWorld`]: crate::test_fixture::TestWorld
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tracing::Level;
+
+use crate::{
+    ff::U128Conversions,
+    protocol::{context::Context, Gate},
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
+    test_fixture::{Disagreement, TryReconstruct},
+};
+
+/// Shares submitted so far, by helper.
+type Pending = [Option<Box<dyn Any + Send>>; 3];
+
+#[derive(Clone, Default)]
+struct Reveals {
+    pending: Arc<Mutex<HashMap<(Gate, String), Pending>>>,
+}
+
+tokio::task_local! {
+    static REVEALS: Reveals;
+}
+
+/// Runs the helpers of a single [`TestWorld`] run, so that [`debug_reveal`] calls made by
+/// them can find each other.
+///
+/// [`TestWorld`]: crate::test_fixture::TestWorld
+pub(super) async fn scope<F: Future>(f: F) -> F::Output {
+    REVEALS.scope(Reveals::default(), f).await
+}
+
+/// Whether [`debug_reveal`] logs anything. Callers check it before computing shares that only
+/// [`debug_reveal`] needs.
+#[must_use]
+pub fn debug_reveal_enabled() -> bool {
+    tracing::enabled!(Level::TRACE)
+}
+
+/// Logs the values of `shares` at the current step of `ctx`, under `label`, once all three
+/// helpers called it. See the [module documentation](self) for details.
+///
+/// ## Panics
+/// If helpers submit a different type of shares for the same step and label.
+pub fn debug_reveal<'a, C, I, V>(ctx: &C, label: &str, shares: I)
+where
+    C: Context,
+    I: IntoIterator<Item = &'a Replicated<V>>,
+    V: SharedValue + U128Conversions,
+{
+    if !debug_reveal_enabled() {
+        return;
+    }
+    match submit(ctx, label, shares) {
+        Some(Ok(values)) => tracing::trace!(
+            "{label} at {:?}: {:?}",
+            ctx.gate(),
+            values
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<_>>()
+        ),
+        Some(Err(e)) => tracing::trace!("{label} at {:?}: {e}", ctx.gate()),
+        None => {}
+    }
+}
+
+/// Keeps the shares of this helper until the other two submit theirs. The last helper to call
+/// it gets the reconstructed values back.
+fn submit<'a, C, I, V>(ctx: &C, label: &str, shares: I) -> Option<Result<Vec<V>, Disagreement>>
+where
+    C: Context,
+    I: IntoIterator<Item = &'a Replicated<V>>,
+    V: SharedValue,
+{
+    REVEALS
+        .try_with(|reveals| {
+            let mut pending = reveals.pending.lock().unwrap();
+            let key = (ctx.gate().clone(), label.to_string());
+            let entry = pending.entry(key.clone()).or_default();
+            entry[ctx.role()] = Some(Box::new(shares.into_iter().cloned().collect::<Vec<_>>()));
+            if entry.iter().any(Option::is_none) {
+                return None;
+            }
+
+            let shares = pending.remove(&key).unwrap().map(|shares| {
+                *shares
+                    .unwrap()
+                    .downcast::<Vec<Replicated<V>>>()
+                    .expect("all helpers reveal the same type of shares")
+            });
+            Some(TryReconstruct::<Vec<V>>::try_reconstruct(&shares))
+        })
+        .ok()
+        .flatten()
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{scope, submit};
+    use crate::{
+        ff::{Fp31, U128Conversions},
+        secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
+        test_executor::run,
+        test_fixture::{Runner, TestWorld},
+    };
+
+    #[test]
+    fn reveals_to_last_helper() {
+        run(|| async {
+            let world = TestWorld::default();
+            let input = [1_u128, 2, 30].map(Fp31::truncate_from).to_vec();
+            let [h1, h2, h3] = world
+                .semi_honest(
+                    input.clone().into_iter(),
+                    |ctx, shares: Vec<Replicated<Fp31>>| async move {
+                        submit(&ctx, "input", &shares).map(Result::unwrap)
+                    },
+                )
+                .await;
+            assert_eq!(None, h1);
+            assert_eq!(None, h2);
+            assert_eq!(Some(input), h3);
+        });
+    }
+
+    #[test]
+    fn requires_scope() {
+        run(|| async {
+            let world = TestWorld::default();
+            let contexts = world.contexts();
+            let shares = [Replicated::<Fp31>::ZERO];
+            for ctx in &contexts {
+                assert_eq!(None, submit(ctx, "input", &shares));
+            }
+
+            let revealed = scope(async {
+                contexts
+                    .iter()
+                    .filter_map(|ctx| submit(ctx, "input", &shares))
+                    .collect::<Vec<_>>()
+            })
+            .await;
+            assert_eq!(vec![Ok(vec![Fp31::ZERO])], revealed);
+        });
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::{Debug, Display, Formatter};
+
+use crate::{telemetry::metrics::BYTES_SENT, test_fixture::TestWorld};
+
+/// Runs the same protocol with semi-honest and with malicious contexts and compares the
+/// outcomes.
+///
+/// Both runs use their own [`TestWorld`], created from the same [`TestWorldConfig`] with
+/// metrics enabled. Since both worlds are seeded the same way, the protocol sees the same input
+/// shares and the same PRSS in both runs. The reconstructed outputs must be equal, otherwise
+/// this panics. Returns a [`DualRunReport`] with the output and the communication overhead of
+/// the malicious run, and logs it at info level.
+///
+/// The protocol is given as a closure, which is expanded once for each security model, so it
+/// can use any protocol that is implemented for both contexts. Input must be `Clone`. This
+/// must be called from an async context.
+///
+/// ```ignore
+/// let report: DualRunReport<Boolean> = dual_run!(
+///     TestWorldConfig::default(),
+///     (a, b),
+///     |ctx, (a, b): (Replicated<Boolean>, Replicated<Boolean>)| async move {
+///         let validator = ctx.set_total_records(1).dzkp_validator(TEST_DZKP_STEPS, 1);
+///         let product = a
+///             .multiply(&b, validator.context(), RecordId::FIRST)
+///             .await
+///             .unwrap();
+///         validator.validate().await.unwrap();
+///         product
+///     }
+/// );
+/// ```
+///
+/// [`TestWorldConfig`]: crate::test_fixture::TestWorldConfig
+#[macro_export]
+macro_rules! dual_run {
+    ($config:expr, $input:expr, |$ctx:pat_param, $shares:pat_param| $body:expr $(,)?) => {{
+        let config: $crate::test_fixture::TestWorldConfig = $config;
+        let config = config.enable_metrics();
+        let input = $input;
+
+        let semi_honest = $crate::test_fixture::TestWorld::new_with(&config);
+        let semi_honest_output = $crate::test_fixture::Reconstruct::reconstruct(
+            &$crate::test_fixture::Runner::semi_honest(
+                &semi_honest,
+                ::std::clone::Clone::clone(&input),
+                |$ctx, $shares| $body,
+            )
+            .await,
+        );
+
+        let malicious = $crate::test_fixture::TestWorld::new_with(&config);
+        let malicious_output = $crate::test_fixture::Reconstruct::reconstruct(
+            &$crate::test_fixture::Runner::malicious(&malicious, input, |$ctx, $shares| $body)
+                .await,
+        );
+
+        let report = $crate::test_fixture::DualRunReport::new(
+            semi_honest_output,
+            malicious_output,
+            &semi_honest,
+            &malicious,
+        );
+        ::tracing::info!("{report}");
+        report
+    }};
+}
+
+/// Outcome of [`dual_run!`].
+#[derive(Debug)]
+pub struct DualRunReport<O> {
+    /// Reconstructed output, which is the same for both runs.
+    pub output: O,
+    /// Bytes sent by all helpers in the semi-honest run.
+    pub semi_honest_bytes: u64,
+    /// Bytes sent by all helpers in the malicious run.
+    pub malicious_bytes: u64,
+}
+
+impl<O: PartialEq + Debug> DualRunReport<O> {
+    /// ## Panics
+    /// If the outputs of the two runs differ.
+    #[must_use]
+    pub fn new(
+        semi_honest_output: O,
+        malicious_output: O,
+        semi_honest: &TestWorld,
+        malicious: &TestWorld,
+    ) -> Self {
+        assert_eq!(
+            semi_honest_output, malicious_output,
+            "semi-honest and malicious runs produced different outputs"
+        );
+        Self {
+            output: semi_honest_output,
+            semi_honest_bytes: semi_honest.metrics_snapshot().get_counter(BYTES_SENT),
+            malicious_bytes: malicious.metrics_snapshot().get_counter(BYTES_SENT),
+        }
+    }
+}
+
+impl<O> DualRunReport<O> {
+    /// Ratio of the bytes sent in the malicious run to the bytes sent in the semi-honest run, or
+    /// `None` if the semi-honest run did not communicate at all.
+    #[must_use]
+    pub fn overhead(&self) -> Option<f64> {
+        // Byte counts of test runs are far below the mantissa of `f64`.
+        #[allow(clippy::cast_precision_loss)]
+        (self.semi_honest_bytes != 0)
+            .then(|| self.malicious_bytes as f64 / self.semi_honest_bytes as f64)
+    }
+}
+
+impl<O> Display for DualRunReport<O> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "semi-honest run sent {} bytes, malicious run sent {} bytes",
+            self.semi_honest_bytes, self.malicious_bytes
+        )?;
+        if let Some(overhead) = self.overhead() {
+            write!(f, " ({overhead:.2}x)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use crate::{
+        ff::{boolean::Boolean, Field},
+        protocol::{
+            basics::SecureMul,
+            context::{dzkp_validator::DZKPValidator, Context, UpgradableContext, TEST_DZKP_STEPS},
+            RecordId,
+        },
+        rand::{thread_rng, Rng},
+        secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
+        test_fixture::{DualRunReport, TestWorld, TestWorldConfig},
+    };
+
+    #[tokio::test]
+    async fn malicious_multiplication_matches_semi_honest() {
+        let mut rng = thread_rng();
+        let (a, b) = (rng.gen::<Boolean>(), rng.gen::<Boolean>());
+
+        let report: DualRunReport<Boolean> =
+            dual_run!(TestWorldConfig::default(), (a, b), |ctx,
+                                                           (a, b): (
+                Replicated<Boolean>,
+                Replicated<Boolean>
+            )| async move {
+                let validator = ctx.set_total_records(1).dzkp_validator(TEST_DZKP_STEPS, 1);
+                let product = a
+                    .multiply(&b, validator.context(), RecordId::FIRST)
+                    .await
+                    .unwrap();
+                validator.validate().await.unwrap();
+                product
+            });
+
+        assert_eq!(a * b, report.output);
+        // Proofs are only sent in the malicious run.
+        assert!(report.overhead().unwrap() > 1.0, "{report}");
+    }
+
+    #[test]
+    #[should_panic(expected = "semi-honest and malicious runs produced different outputs")]
+    fn different_outputs() {
+        let world = TestWorld::default();
+        let _ = DualRunReport::new(Boolean::ZERO, Boolean::ONE, &world, &world);
+    }
+}
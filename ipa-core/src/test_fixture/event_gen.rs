@@ -1,6 +1,7 @@
 use std::{
     collections::HashSet,
     num::{NonZeroU32, NonZeroU64},
+    ops::RangeInclusive,
 };
 
 use crate::{rand::Rng, test_fixture::ipa::TestRawDataRecord};
@@ -45,6 +46,30 @@ pub enum ReportFilter {
     SourceOnly,
 }
 
+/// Shape of the distribution of a value within its configured range.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Distribution {
+    /// Every value in the range is equally likely.
+    #[default]
+    Uniform,
+    /// The probability of the `k`-th value in the range decays as `k^-s`, approximating a Zipf
+    /// distribution. Larger exponents `s` give heavier skew towards the low end of the range.
+    PowerLaw,
+}
+
+/// How event timestamps are spread over the `[0, max_timestamp)` interval.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum TimestampPattern {
+    /// Timestamps are spread evenly over the whole interval.
+    #[default]
+    Uniform,
+    /// Events cluster into `burst_count` randomly placed bursts of `burst_width` seconds,
+    /// like traffic following a campaign launch or a daily peak.
+    Bursty,
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "clap", derive(clap::Args))]
 pub struct Config {
@@ -68,6 +93,31 @@ pub struct Config {
     pub report_filter: ReportFilter,
     #[cfg_attr(feature = "clap", arg(long, required_if_eq("report_filter", "TriggerOnly"), default_value = "0.02", value_parser = validate_probability))]
     pub conversion_probability: Option<f32>,
+    /// Probability that an event is a conversion, when both impressions and conversions are
+    /// generated.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "0.5", value_parser = validate_probability))]
+    pub trigger_probability: f32,
+    /// Distribution of the number of events per user, between `min_events_per_user` and
+    /// `max_events_per_user`.
+    #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = Distribution::Uniform))]
+    pub events_per_user_distribution: Distribution,
+    /// Exponent of the power law used for the number of events per user.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "1.0"))]
+    pub events_per_user_exponent: f64,
+    /// Distribution of conversion values, between 1 and `max_trigger_value`.
+    #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = Distribution::Uniform))]
+    pub trigger_value_distribution: Distribution,
+    /// Exponent of the power law used for conversion values.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "1.0"))]
+    pub trigger_value_exponent: f64,
+    #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = TimestampPattern::Uniform))]
+    pub timestamp_pattern: TimestampPattern,
+    /// Number of bursts when timestamps are bursty.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "10"))]
+    pub burst_count: NonZeroU32,
+    /// Length of each burst, in seconds, when timestamps are bursty.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "3600"))]
+    pub burst_width: NonZeroTimestamp,
 }
 
 fn validate_probability(value: &str) -> Result<f32, String> {
@@ -110,6 +160,14 @@ impl Config {
             max_events_per_user: NonZeroU32::try_from(max_events_per_user).unwrap(),
             report_filter: ReportFilter::All,
             conversion_probability: None,
+            trigger_probability: 0.5,
+            events_per_user_distribution: Distribution::Uniform,
+            events_per_user_exponent: 1.0,
+            trigger_value_distribution: Distribution::Uniform,
+            trigger_value_exponent: 1.0,
+            timestamp_pattern: TimestampPattern::Uniform,
+            burst_count: NonZeroU32::new(10).unwrap(),
+            burst_width: NonZeroTimestamp::new(3600).unwrap(),
         }
     }
 
@@ -120,6 +178,30 @@ impl Config {
     }
 }
 
+impl Distribution {
+    fn sample<R: Rng>(self, rng: &mut R, range: RangeInclusive<u32>, exponent: f64) -> u32 {
+        match self {
+            Self::Uniform => rng.gen_range(range),
+            Self::PowerLaw => {
+                let (lo, hi) = range.into_inner();
+                // Sample from a continuous power law over [1, n + 1) by inverting its CDF,
+                // then round down to get the 1-based rank of the value in the range.
+                let n = f64::from(hi - lo) + 1.0;
+                let u = rng.gen::<f64>();
+                let x = if (exponent - 1.0).abs() < f64::EPSILON {
+                    (n + 1.0).powf(u)
+                } else {
+                    let a = 1.0 - exponent;
+                    ((n + 1.0).powf(a) - 1.0).mul_add(u, 1.0).powf(1.0 / a)
+                };
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let offset = (x.floor() as u32).saturating_sub(1).min(hi - lo);
+                lo + offset
+            }
+        }
+    }
+}
+
 struct UserStats {
     user_id: UserId,
     generated: u32,
@@ -157,6 +239,8 @@ pub struct EventGenerator<R: Rng> {
     rng: R,
     users: Vec<UserStats>,
     used_ids: HashSet<UserId>,
+    /// Start of each burst, if timestamps are bursty.
+    bursts: Vec<Timestamp>,
 }
 
 impl<R: Rng> EventGenerator<R> {
@@ -166,7 +250,7 @@ impl<R: Rng> EventGenerator<R> {
 
     /// # Panics
     /// If the configuration is not valid.
-    pub fn with_config(rng: R, config: Config) -> Self {
+    pub fn with_config(mut rng: R, config: Config) -> Self {
         assert!(config.min_events_per_user <= config.max_events_per_user);
         // Ensure that rejection-sampling of non-duplicate timestamps
         // will complete in a reasonable amount of time.
@@ -177,11 +261,45 @@ impl<R: Rng> EventGenerator<R> {
             mt = config.max_timestamp,
             me = config.max_events_per_user,
         );
+        let bursts = match config.timestamp_pattern {
+            TimestampPattern::Uniform => Vec::new(),
+            TimestampPattern::Bursty => {
+                // Same reasoning as above, assuming the worst case of all events of a user
+                // landing in the same burst.
+                assert!(
+                    2 * config.max_events_per_user.get() <= config.burst_width.get(),
+                    "burst_width ({bw}) must be at least twice max_events_per_user ({me}) \
+                     to support generation of a unique timestamp for each event",
+                    bw = config.burst_width,
+                    me = config.max_events_per_user,
+                );
+                let last_start = config
+                    .max_timestamp
+                    .get()
+                    .saturating_sub(config.burst_width.get());
+                (0..config.burst_count.get())
+                    .map(|_| rng.gen_range(0..=last_start))
+                    .collect()
+            }
+        };
         Self {
             config,
             rng,
             users: vec![],
             used_ids: HashSet::new(),
+            bursts,
+        }
+    }
+
+    fn gen_timestamp(&mut self) -> Timestamp {
+        let max_timestamp = self.config.max_timestamp.get();
+        match self.config.timestamp_pattern {
+            TimestampPattern::Uniform => self.rng.gen_range(0..max_timestamp),
+            TimestampPattern::Bursty => {
+                let start = self.bursts[self.rng.gen_range(0..self.bursts.len())];
+                let ts = start + self.rng.gen_range(0..self.config.burst_width.get());
+                ts.min(max_timestamp - 1)
+            }
         }
     }
 
@@ -192,7 +310,7 @@ impl<R: Rng> EventGenerator<R> {
         // already-used timestamps. `EventGenerator::with_config` checks that `max_timestamp`
         // exceeds `max_events_per_user` by a margin large enough that this is likely to complete.
         let current_ts = loop {
-            let ts = self.rng.gen_range(0..self.config.max_timestamp.get());
+            let ts = self.gen_timestamp();
             if self.users[idx].used_timestamps.insert(ts) {
                 break ts;
             }
@@ -200,7 +318,7 @@ impl<R: Rng> EventGenerator<R> {
 
         match self.config.report_filter {
             ReportFilter::All => {
-                if self.rng.gen() {
+                if self.rng.gen::<f32>() < self.config.trigger_probability {
                     self.gen_trigger(user_id, current_ts)
                 } else {
                     self.gen_source(user_id, current_ts)
@@ -221,7 +339,11 @@ impl<R: Rng> EventGenerator<R> {
     }
 
     fn gen_trigger(&mut self, user_id: UserId, timestamp: Timestamp) -> TestRawDataRecord {
-        let trigger_value = self.rng.gen_range(1..=self.config.max_trigger_value.get());
+        let trigger_value = self.config.trigger_value_distribution.sample(
+            &mut self.rng,
+            1..=self.config.max_trigger_value.get(),
+            self.config.trigger_value_exponent,
+        );
 
         TestRawDataRecord {
             user_id: user_id.into(),
@@ -261,8 +383,10 @@ impl<R: Rng> EventGenerator<R> {
 
             break Some(UserStats::new(
                 user_id,
-                self.rng.gen_range(
+                self.config.events_per_user_distribution.sample(
+                    &mut self.rng,
                     self.config.min_events_per_user.get()..=self.config.max_events_per_user.get(),
+                    self.config.events_per_user_exponent,
                 ),
             ));
         }
@@ -299,7 +423,8 @@ impl<R: Rng> Iterator for EventGenerator<R> {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use rand::thread_rng;
+    use rand::{rngs::StdRng, thread_rng};
+    use rand_core::SeedableRng;
 
     use super::*;
 
@@ -357,6 +482,77 @@ mod tests {
         assert!(gen.next().is_some());
     }
 
+    #[test]
+    fn power_law_is_skewed() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let samples = (0..10_000)
+            .map(|_| Distribution::PowerLaw.sample(&mut rng, 5..=104, 1.2))
+            .collect::<Vec<_>>();
+        assert!(samples.iter().all(|v| (5..=104).contains(v)));
+        // With exponent 1.2 over 100 values, the lowest value alone is more likely than the
+        // whole upper half of the range.
+        let lowest = samples.iter().filter(|&&v| v == 5).count();
+        let upper_half = samples.iter().filter(|&&v| v >= 55).count();
+        assert!(lowest > upper_half, "{lowest} <= {upper_half}");
+    }
+
+    #[test]
+    fn power_law_single_value() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for exponent in [0.5, 1.0, 2.0] {
+            assert_eq!(7, Distribution::PowerLaw.sample(&mut rng, 7..=7, exponent));
+        }
+    }
+
+    #[test]
+    fn bursty_timestamps() {
+        let config = Config {
+            timestamp_pattern: TimestampPattern::Bursty,
+            burst_count: NonZeroU32::new(3).unwrap(),
+            burst_width: NonZeroTimestamp::new(100).unwrap(),
+            max_events_per_user: NonZeroU32::new(10).unwrap(),
+            ..Config::default()
+        };
+        let gen = EventGenerator::with_config(StdRng::seed_from_u64(42), config);
+        let bursts = gen.bursts.clone();
+        for event in gen.take(1000) {
+            assert!(
+                bursts
+                    .iter()
+                    .any(|&start| (u64::from(start)..u64::from(start) + 100)
+                        .contains(&event.timestamp)),
+                "timestamp {} is outside of all bursts",
+                event.timestamp
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "burst_width (10) must be at least twice max_events_per_user")]
+    fn invalid_burst_width() {
+        let _ = EventGenerator::with_config(
+            thread_rng(),
+            Config {
+                timestamp_pattern: TimestampPattern::Bursty,
+                max_events_per_user: NonZeroU32::new(10).unwrap(),
+                burst_width: NonZeroTimestamp::new(10).unwrap(),
+                ..Config::default()
+            },
+        );
+    }
+
+    #[test]
+    fn trigger_probability() {
+        let gen = EventGenerator::with_config(
+            thread_rng(),
+            Config {
+                trigger_probability: 1.0,
+                ..Config::default()
+            },
+        );
+        assert!(gen.take(100).all(|event| event.is_trigger_report));
+    }
+
     mod proptests {
         use std::collections::HashMap;
 
@@ -448,6 +644,7 @@ mod tests {
                         ReportFilter::TriggerOnly => Some(0.02),
                         _ => None,
                     },
+                    ..Config::default()
                 }
             }
         }
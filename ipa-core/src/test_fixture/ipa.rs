@@ -59,6 +59,57 @@ fn insert_sorted(user_records: &mut Vec<TestRawDataRecord>, record: TestRawDataR
     }
 }
 
+/// Drops the source events that can't get any credit under last-touch attribution: those
+/// followed by another source event of the same user, with the same breakdown key, before any
+/// trigger event. Such duplicates are common in real data and make queries bigger without
+/// changing their results, because every trigger event is attributed to the most recent source
+/// event only. The remaining records keep their order.
+///
+/// Users with several records at the same timestamp are left as they are, since attribution
+/// does not define the order of such records. The arm of a record is not known here, so
+/// inputs of paired queries must not be pre-aggregated, see [`can_pre_aggregate_sources`].
+#[must_use]
+pub fn pre_aggregate_sources(input: &[TestRawDataRecord]) -> Vec<TestRawDataRecord> {
+    let mut user_events = HashMap::<_, Vec<_>>::new();
+    for (i, row) in input.iter().enumerate() {
+        user_events.entry(row.user_id).or_default().push(i);
+    }
+
+    let mut keep = vec![true; input.len()];
+    for mut events in user_events.into_values() {
+        events.sort_by_key(|&i| input[i].timestamp);
+        if events
+            .windows(2)
+            .any(|w| input[w[0]].timestamp == input[w[1]].timestamp)
+        {
+            continue;
+        }
+        for w in events.windows(2) {
+            let (current, next) = (&input[w[0]], &input[w[1]]);
+            if !current.is_trigger_report
+                && !next.is_trigger_report
+                && current.breakdown_key == next.breakdown_key
+            {
+                keep[w[0]] = false;
+            }
+        }
+    }
+
+    input
+        .iter()
+        .zip(keep)
+        .filter_map(|(row, keep)| keep.then(|| row.clone()))
+        .collect()
+}
+
+/// Whether [`pre_aggregate_sources`] preserves the results of a query with this configuration.
+/// Attribution is always last-touch, but the records of paired, per-site and tagged queries are
+/// preceded by their arm, site or tag, which is part of the breakdown key the helpers use.
+#[must_use]
+pub fn can_pre_aggregate_sources(config: &crate::helpers::query::IpaQueryConfig) -> bool {
+    !config.paired_arms && config.source_sites.is_none() && config.public_tags.is_none()
+}
+
 /// Executes IPA protocol in the clear, that is without any MPC helpers involved in the computation.
 /// Useful to validate that MPC output makes sense by comparing the breakdowns produced by MPC IPA
 /// with this function's results. Note that MPC version of IPA may apply DP noise to the aggregates,
@@ -68,6 +119,8 @@ fn insert_sorted(user_records: &mut Vec<TestRawDataRecord>, record: TestRawDataR
 /// order those records are considered by the attribution algorithm is undefined, and the output
 /// may be non-deterministic.
 ///
+/// A `per_user_cap` of `0` means user contributions are not capped.
+///
 /// ## Panics
 /// Will panic if you run in on Intel 80286 or any other 16 bit hardware.
 pub fn ipa_in_the_clear(
@@ -77,6 +130,10 @@ pub fn ipa_in_the_clear(
     max_breakdown: u32,
     order: &CappingOrder,
 ) -> Vec<u32> {
+    let per_user_cap = match per_user_cap {
+        0 => u32::MAX,
+        cap => cap,
+    };
     // build a view that is convenient for attribution. match key -> events sorted by timestamp
     // that is more memory intensive, but should be faster to compute. We can always opt-out and
     // execute IPA in place
@@ -407,6 +464,50 @@ mod tests {
         actual
     }
 
+    fn record(user_id: u64, timestamp: u64, breakdown_key: Option<u32>) -> TestRawDataRecord {
+        TestRawDataRecord {
+            timestamp,
+            user_id,
+            is_trigger_report: breakdown_key.is_none(),
+            breakdown_key: breakdown_key.unwrap_or(0),
+            trigger_value: u32::from(breakdown_key.is_none()) * 3,
+        }
+    }
+
+    #[test]
+    fn pre_aggregate_sources() {
+        let input = vec![
+            record(1, 30, Some(1)),
+            record(1, 10, Some(1)),
+            record(1, 20, Some(1)),
+            record(1, 40, None),
+            record(1, 50, Some(2)),
+            record(1, 60, Some(1)),
+            record(1, 70, None),
+            // Same timestamps leave the user as it is.
+            record(2, 10, Some(1)),
+            record(2, 10, Some(1)),
+            record(2, 20, None),
+            // Different breakdown keys are kept.
+            record(3, 10, Some(1)),
+            record(3, 20, Some(2)),
+            record(3, 30, None),
+        ];
+        let output = super::pre_aggregate_sources(&input);
+
+        let mut expected = input.clone();
+        expected.remove(2);
+        expected.remove(1);
+        assert_eq!(expected, output);
+
+        for window in [None, NonZeroU32::new(15)] {
+            assert_eq!(
+                ipa_in_the_clear(&input, 4, window, 3, &CappingOrder::CapOldestFirst),
+                ipa_in_the_clear(&output, 4, window, 3, &CappingOrder::CapOldestFirst),
+            );
+        }
+    }
+
     #[test]
     fn insert_sorted() {
         insert_sorted_test([1, 2, 3, 4]);
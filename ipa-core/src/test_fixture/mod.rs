@@ -1,3 +1,5 @@
+#[cfg(feature = "in-memory-infra")]
+mod debug_reveal;
 pub mod input;
 mod sharing;
 #[cfg(feature = "in-memory-infra")]
@@ -10,6 +12,8 @@ mod app;
 
 #[cfg(feature = "in-memory-infra")]
 pub mod circuit;
+#[cfg(feature = "in-memory-infra")]
+mod dual_run;
 mod event_gen;
 pub mod hybrid;
 pub mod hybrid_event_gen;
@@ -25,14 +29,21 @@ use std::{fmt::Debug, future::Future};
 
 #[cfg(feature = "in-memory-infra")]
 pub use app::TestApp;
-pub use event_gen::{Config as EventGeneratorConfig, EventGenerator};
+#[cfg(feature = "in-memory-infra")]
+pub use debug_reveal::{debug_reveal, debug_reveal_enabled};
+#[cfg(feature = "in-memory-infra")]
+pub use dual_run::DualRunReport;
+pub use event_gen::{
+    Config as EventGeneratorConfig, Distribution as EventDistribution, EventGenerator,
+    TimestampPattern,
+};
 use futures::{FutureExt, TryFuture};
 pub use hybrid_event_gen::{
     Config as HybridGeneratorConfig, EventGenerator as HybridEventGenerator,
 };
 use rand::{distributions::Standard, prelude::Distribution, rngs::mock::StepRng};
 use rand_core::{CryptoRng, RngCore};
-pub use sharing::{get_bits, into_bits, Reconstruct, ReconstructArr};
+pub use sharing::{get_bits, into_bits, Disagreement, Reconstruct, ReconstructArr, TryReconstruct};
 #[cfg(feature = "in-memory-infra")]
 pub use world::{
     Distribute, Random as RandomInputDistribution, RoundRobin as RoundRobinInputDistribution,
@@ -1,7 +1,13 @@
-use std::{borrow::Borrow, iter::zip, ops::Deref};
+use std::{
+    borrow::Borrow,
+    fmt::{Display, Formatter},
+    iter::zip,
+    ops::Deref,
+};
 
 use crate::{
     ff::{PrimeField, U128Conversions},
+    helpers::Role,
     secret_sharing::{
         replicated::{
             malicious::{AdditiveShare as MaliciousReplicated, ExtendableField},
@@ -51,22 +57,114 @@ pub trait ReconstructArr<T> {
     fn reconstruct_arr(&self) -> T;
 }
 
-impl<V: SharedValue> Reconstruct<V> for [&Replicated<V>; 3] {
-    fn reconstruct(&self) -> V {
-        let s0 = &self[0];
-        let s1 = &self[1];
-        let s2 = &self[2];
+/// Diagnostic version of [`Reconstruct`], which reports where the shares disagree instead of
+/// panicking.
+pub trait TryReconstruct<T> {
+    /// Reconstructs the value, if the given input is a valid replicated secret share.
+    ///
+    /// # Errors
+    /// If the shares of the helpers are not consistent. The error points to the first output
+    /// that failed to reconstruct.
+    fn try_reconstruct(&self) -> Result<T, Disagreement>;
+}
 
-        assert_eq!(
-            s0.left() + s1.left() + s2.left(),
-            s0.right() + s1.right() + s2.right(),
-        );
+/// Shares of the three helpers that do not form a valid replicated secret share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disagreement {
+    /// Position of the divergent record in the output, outermost index first. Empty if the
+    /// output is a single value.
+    pub index: Vec<usize>,
+    /// Left and right share held by each helper.
+    pub shares: [(String, String); 3],
+    /// Pairs of adjacent helpers whose views of the share they hold in common differ. The
+    /// first helper's right share must be equal to the second helper's left share.
+    pub divergent: Vec<(Role, Role)>,
+}
+
+impl Display for Disagreement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shares do not reconstruct")?;
+        if !self.index.is_empty() {
+            write!(f, " at index {:?}", self.index)?;
+        }
+        writeln!(f, ":")?;
+        for (role, (left, right)) in zip(Role::all(), &self.shares) {
+            writeln!(f, "  {role}: left={left}, right={right}")?;
+        }
+        for (role, next) in &self.divergent {
+            writeln!(f, "  {role} right share does not match {next} left share")?;
+        }
+        Ok(())
+    }
+}
 
-        assert_eq!(s0.right(), s1.left());
-        assert_eq!(s1.right(), s2.left());
-        assert_eq!(s2.right(), s0.left());
+impl<V: SharedValue> TryReconstruct<V> for [&Replicated<V>; 3] {
+    fn try_reconstruct(&self) -> Result<V, Disagreement> {
+        let divergent = (0..3)
+            .filter(|&i| self[i].right() != self[(i + 1) % 3].left())
+            .map(|i| (Role::all()[i], Role::all()[(i + 1) % 3]))
+            .collect::<Vec<_>>();
+
+        // If adjacent helpers agree on the shares they hold in common, the sums of left and
+        // right shares are equal as well.
+        if divergent.is_empty() {
+            Ok(self[0].left() + self[1].left() + self[2].left())
+        } else {
+            Err(Disagreement {
+                index: Vec::new(),
+                shares: self.map(|s| (format!("{:?}", s.left()), format!("{:?}", s.right()))),
+                divergent,
+            })
+        }
+    }
+}
 
-        s0.left() + s1.left() + s2.left()
+impl<V: SharedValue> TryReconstruct<V> for [Replicated<V>; 3] {
+    fn try_reconstruct(&self) -> Result<V, Disagreement> {
+        self.each_ref().try_reconstruct()
+    }
+}
+
+impl<I, T> TryReconstruct<Vec<T>> for [&[I]; 3]
+where
+    for<'i> [&'i I; 3]: TryReconstruct<T>,
+{
+    fn try_reconstruct(&self) -> Result<Vec<T>, Disagreement> {
+        assert_eq!(self[0].len(), self[1].len());
+        assert_eq!(self[0].len(), self[2].len());
+        zip(self[0].iter(), zip(self[1].iter(), self[2].iter()))
+            .enumerate()
+            .map(|(i, (x0, (x1, x2)))| {
+                [x0, x1, x2].try_reconstruct().map_err(|mut e| {
+                    e.index.insert(0, i);
+                    e
+                })
+            })
+            .collect()
+    }
+}
+
+impl<I, T> TryReconstruct<Vec<T>> for [Vec<I>; 3]
+where
+    for<'i> [&'i [I]; 3]: TryReconstruct<Vec<T>>,
+{
+    fn try_reconstruct(&self) -> Result<Vec<T>, Disagreement> {
+        self.each_ref().map(Deref::deref).try_reconstruct()
+    }
+}
+
+impl<I, T> TryReconstruct<Vec<T>> for [&Vec<I>; 3]
+where
+    for<'i> [&'i [I]; 3]: TryReconstruct<Vec<T>>,
+{
+    fn try_reconstruct(&self) -> Result<Vec<T>, Disagreement> {
+        self.map(Deref::deref).try_reconstruct()
+    }
+}
+
+impl<V: SharedValue> Reconstruct<V> for [&Replicated<V>; 3] {
+    fn reconstruct(&self) -> V {
+        self.try_reconstruct().unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
@@ -282,3 +380,58 @@ impl<F: ExtendableField> ValidateMalicious<F>
         [v0.clone(), v1.clone(), v2.clone()].validate(r);
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{Disagreement, Reconstruct, TryReconstruct};
+    use crate::{
+        ff::{Fp31, U128Conversions},
+        helpers::Role,
+        secret_sharing::replicated::{
+            semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing,
+        },
+    };
+
+    fn share(left: u128, right: u128) -> Replicated<Fp31> {
+        Replicated::new(Fp31::truncate_from(left), Fp31::truncate_from(right))
+    }
+
+    #[test]
+    fn consistent() {
+        let shares = [share(1, 2), share(2, 3), share(3, 1)];
+        assert_eq!(shares.try_reconstruct(), Ok(Fp31::truncate_from(6_u128)));
+        assert_eq!(shares.reconstruct(), Fp31::truncate_from(6_u128));
+    }
+
+    #[test]
+    fn first_divergent_record() {
+        let shares = [
+            vec![vec![share(1, 2)], vec![share(0, 0), share(1, 2)]],
+            vec![vec![share(2, 3)], vec![share(0, 0), share(4, 3)]],
+            vec![vec![share(3, 1)], vec![share(0, 1), share(3, 1)]],
+        ];
+        let result: Result<Vec<Vec<Fp31>>, _> = shares.try_reconstruct();
+        let err = result.unwrap_err();
+        assert_eq!(
+            err,
+            Disagreement {
+                index: vec![1, 0],
+                shares: [
+                    (String::from("0_mod31"), String::from("0_mod31")),
+                    (String::from("0_mod31"), String::from("0_mod31")),
+                    (String::from("0_mod31"), String::from("1_mod31")),
+                ],
+                divergent: vec![(Role::H3, Role::H1)],
+            }
+        );
+        assert!(err
+            .to_string()
+            .contains("H3 right share does not match H1 left share"));
+    }
+
+    #[test]
+    #[should_panic(expected = "H1 right share does not match H2 left share")]
+    fn reconstruct_reports_helpers() {
+        let _ = [share(1, 2), share(5, 3), share(3, 1)].reconstruct();
+    }
+}
@@ -8,6 +8,7 @@ use std::{
     io::stdout,
     iter::{self, zip},
     marker::PhantomData,
+    num::NonZeroUsize,
     sync::Mutex,
     time::Duration,
 };
@@ -23,7 +24,7 @@ use tracing::{Instrument, Level, Span};
 
 use crate::{
     helpers::{
-        in_memory_config::{passthrough, DynStreamInterceptor},
+        in_memory_config::{passthrough, DynStreamInterceptor, Framing},
         Gateway, GatewayConfig, HelperIdentity, InMemoryMpcNetwork, InMemoryShardNetwork,
         InMemoryTransport, Role, RoleAssignment, TotalRecords, Transport,
     },
@@ -48,7 +49,7 @@ use crate::{
     sharding::{NotSharded, ShardBinding, ShardIndex, Sharded},
     telemetry::{stats::Metrics, StepStatsCsvExporter},
     test_fixture::{
-        logging, make_participants,
+        debug_reveal, logging, make_participants,
         metrics::MetricsHandle,
         shard_configurator::{Configurator, ShardConfigurator},
         sharing::ValidateMalicious,
@@ -159,6 +160,11 @@ pub struct TestWorldConfig {
     /// [`passthrough`]: crate::helpers::in_memory_config::passthrough
     pub stream_interceptor: DynStreamInterceptor,
 
+    /// How streams between helpers and shards are framed by the in-memory transport.
+    /// [`Framing::Strict`] emulates the HTTP transport, which does not preserve the boundaries
+    /// of chunks produced by the sender.
+    pub framing: Framing,
+
     /// Timeout for tests run by this `TestWorld`.
     ///
     /// If `None`, there is no timeout.
@@ -336,8 +342,11 @@ impl<S: ShardingScheme> TestWorld<S> {
         let global_prss_rng_seed = rng.next_u64();
 
         let shard_count = ShardIndex::try_from(S::SHARDS).unwrap();
-        let shard_network =
-            InMemoryShardNetwork::with_stream_interceptor(shard_count, &config.stream_interceptor);
+        let shard_network = InMemoryShardNetwork::with_stream_interceptor(
+            shard_count,
+            &config.stream_interceptor,
+            config.framing,
+        );
 
         let shards = shard_count
             .iter()
@@ -421,6 +430,7 @@ impl Default for TestWorldConfig {
             seed: thread_rng().next_u64(),
             initial_gate: None,
             stream_interceptor: passthrough(),
+            framing: Framing::default(),
             timeout: Some(Duration::from_secs(10)),
         }
     }
@@ -439,6 +449,14 @@ impl TestWorldConfig {
         self
     }
 
+    /// Makes the in-memory transport re-frame every stream into frames of at most `max_frame`
+    /// bytes, like the HTTP transport may do.
+    #[must_use]
+    pub fn with_strict_framing(mut self, max_frame: NonZeroUsize) -> Self {
+        self.framing = Framing::Strict(max_frame);
+        self
+    }
+
     #[must_use]
     pub fn with_timeout_secs(mut self, timeout_secs: u64) -> Self {
         self.timeout = Some(Duration::from_secs(timeout_secs));
@@ -851,6 +869,7 @@ impl<S: ShardingScheme> ShardWorld<S> {
         let network = InMemoryMpcNetwork::with_stream_interceptor(
             InMemoryMpcNetwork::noop_handlers(),
             &config.stream_interceptor,
+            config.framing,
             shard_constructor.shard_id(),
         );
 
@@ -892,11 +911,13 @@ impl<S: ShardingScheme> ShardWorld<S> {
         R: Future<Output = O> + Send,
     {
         #[allow(clippy::disallowed_methods)] // It's just 3 items.
-        let output = join_all(zip(contexts, input_shares).map(|(ctx, shares)| {
-            let role = ctx.role();
-            helper_fn(ctx, shares).instrument(tracing::trace_span!("", role = ?role))
-        }))
-        .instrument(span)
+        let output = debug_reveal::scope(
+            join_all(zip(contexts, input_shares).map(|(ctx, shares)| {
+                let role = ctx.role();
+                helper_fn(ctx, shares).instrument(tracing::trace_span!("", role = ?role))
+            }))
+            .instrument(span),
+        )
         .await;
 
         <[_; 3]>::try_from(output).unwrap()
@@ -971,10 +992,11 @@ mod tests {
     use std::{
         collections::{HashMap, HashSet},
         iter,
+        num::NonZeroUsize,
         sync::{Arc, Mutex},
     };
 
-    use futures_util::future::try_join4;
+    use futures_util::future::{try_join, try_join4};
 
     use crate::{
         ff::{
@@ -1002,6 +1024,7 @@ mod tests {
             replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
             SharedValue,
         },
+        seq_join::SeqJoin,
         sharding::{ShardConfiguration, ShardIndex},
         test_executor::{run, run_random},
         test_fixture::{world::WithShards, Reconstruct, Runner, TestWorld, TestWorldConfig},
@@ -1065,6 +1088,46 @@ mod tests {
         });
     }
 
+    #[test]
+    fn strict_framing() {
+        const STEP: &str = "framing";
+        const COUNT: usize = 10;
+        fn value(i: usize) -> BA64 {
+            BA64::truncate_from(0x0101_0101_0101_0101_u128 * (u128::try_from(i).unwrap() + 1))
+        }
+
+        run(|| async move {
+            // Frames of 3 bytes never line up with 8-byte records.
+            let config =
+                TestWorldConfig::default().with_strict_framing(NonZeroUsize::new(3).unwrap());
+            let world = TestWorld::new_with(config);
+
+            let received = world
+                .semi_honest((), |ctx, ()| async move {
+                    let ctx = ctx.narrow(STEP).set_total_records(COUNT);
+                    let send_channel = ctx.send_channel::<BA64>(ctx.role().peer(Direction::Right));
+                    let recv_channel = ctx.recv_channel::<BA64>(ctx.role().peer(Direction::Left));
+                    let (_, received) = try_join(
+                        ctx.parallel_join(
+                            (0..COUNT).map(|i| send_channel.send(RecordId::from(i), value(i))),
+                        ),
+                        ctx.parallel_join(
+                            (0..COUNT).map(|i| recv_channel.receive(RecordId::from(i))),
+                        ),
+                    )
+                    .await
+                    .unwrap();
+
+                    received
+                })
+                .await;
+
+            for values in received {
+                assert_eq!((0..COUNT).map(value).collect::<Vec<_>>(), values);
+            }
+        });
+    }
+
     #[test]
     fn interceptor_can_corrupt_data() {
         const STEP: &str = "corruption";
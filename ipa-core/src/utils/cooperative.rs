@@ -0,0 +1,76 @@
+//! Local computations that give the runtime a chance to run other tasks.
+//!
+//! Protocols run on the same tokio workers as the transport. A loop that transposes or combines
+//! shares of every record in a large query can keep a worker busy for hundreds of milliseconds,
+//! which delays sending and receiving data for everything else. Such loops should go through
+//! [`collect`], which yields back to the runtime after every [`chunk_size`] items.
+
+use std::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Number of items processed between yields, unless set with [`set_chunk_size`].
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+static CHUNK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_CHUNK_SIZE);
+
+/// Sets the number of items local computations process between yields, for the whole process.
+pub fn set_chunk_size(size: NonZeroUsize) {
+    CHUNK_SIZE.store(size.get(), Ordering::Relaxed);
+}
+
+/// Number of items local computations process between yields.
+#[must_use]
+pub fn chunk_size() -> usize {
+    CHUNK_SIZE.load(Ordering::Relaxed)
+}
+
+/// Collects `items` into a vector, yielding to the runtime after every [`chunk_size`] items.
+/// The work happens in the iterator, so this is meant to be called with a [`map`] over the
+/// input.
+///
+/// [`map`]: Iterator::map
+pub async fn collect<I: IntoIterator>(items: I) -> Vec<I::Item> {
+    let chunk_size = chunk_size();
+    let items = items.into_iter();
+    let mut output = Vec::with_capacity(items.size_hint().0);
+    for item in items {
+        output.push(item);
+        if output.len() % chunk_size == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    output
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::Poll,
+    };
+
+    use futures::poll;
+
+    use super::{chunk_size, collect};
+
+    #[tokio::test]
+    async fn yields_between_chunks() {
+        let size = chunk_size();
+        let processed = AtomicUsize::new(0);
+        let mut f = Box::pin(collect((0..3 * size).map(|i| {
+            processed.fetch_add(1, Ordering::Relaxed);
+            i
+        })));
+
+        assert_eq!(Poll::Pending, poll!(&mut f).map(|v| v.len()));
+        assert_eq!(size, processed.load(Ordering::Relaxed));
+        assert_eq!(Poll::Pending, poll!(&mut f).map(|v| v.len()));
+        assert_eq!(2 * size, processed.load(Ordering::Relaxed));
+
+        let output = f.await;
+        assert_eq!((0..3 * size).collect::<Vec<_>>(), output);
+    }
+}
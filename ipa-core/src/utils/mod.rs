@@ -1,7 +1,9 @@
 pub mod array;
 pub mod arraychunks;
+pub mod cooperative;
 #[cfg(target_pointer_width = "64")]
 mod power_of_two;
+pub mod rng;
 
 #[cfg(target_pointer_width = "64")]
 pub use power_of_two::{non_zero_prev_power_of_two, NonZeroU32PowerOfTwo};
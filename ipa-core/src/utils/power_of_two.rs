@@ -1,5 +1,7 @@
 use std::{fmt::Display, num::NonZeroUsize, str::FromStr};
 
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, thiserror::Error)]
 #[error("{0} is not a power of two or not within the 1..u32::MAX range")]
 pub struct ConvertError<I: Display>(I);
@@ -12,7 +14,8 @@ impl<I: PartialEq + Display> PartialEq for ConvertError<I> {
 
 /// This construction guarantees the value to be a power of two and
 /// within the range 0..2^32-1
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "usize", into = "usize")]
 pub struct NonZeroU32PowerOfTwo(u32);
 
 impl Display for NonZeroU32PowerOfTwo {
@@ -0,0 +1,110 @@
+//! Injectable source of cryptographically secure randomness.
+//!
+//! Helpers need local randomness to generate keys and to set up PRSS, which in turn drives
+//! padding and every other shared random value in a query. Instead of reaching for
+//! `thread_rng` directly, code that does this takes randomness from a [`CryptoRngProvider`].
+//! Deployments can plug in a hardware RNG, and tests can make a helper fully deterministic with
+//! `SeededRngProvider`, which only the `test-fixture` feature provides.
+
+#[cfg(any(test, feature = "test-fixture"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rand::rngs::StdRng;
+use rand_core::{CryptoRngCore, SeedableRng};
+
+/// A cryptographically secure random number generator handed out by a [`CryptoRngProvider`].
+pub type BoxedCryptoRng = Box<dyn CryptoRngCore + Send>;
+
+/// Hands out random number generators. Every call to [`Self::rng`] returns an independent
+/// generator, so that concurrent queries do not share state.
+///
+/// Any `Fn() -> BoxedCryptoRng` closure is a provider, which is the simplest way to integrate
+/// a hardware RNG.
+pub trait CryptoRngProvider: Send + Sync {
+    fn rng(&self) -> BoxedCryptoRng;
+}
+
+impl<F: Fn() -> BoxedCryptoRng + Send + Sync> CryptoRngProvider for F {
+    fn rng(&self) -> BoxedCryptoRng {
+        self()
+    }
+}
+
+/// Seeds every generator from the operating system entropy source. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRngProvider;
+
+impl CryptoRngProvider for SystemRngProvider {
+    fn rng(&self) -> BoxedCryptoRng {
+        Box::new(StdRng::from_entropy())
+    }
+}
+
+/// Derives generators from a fixed seed. Two providers created with the same seed hand out the
+/// same sequence of generators.
+///
+/// This is only suitable for tests. Anyone who knows the seed can predict all the randomness a
+/// helper uses, including its PRSS secrets.
+#[cfg(any(test, feature = "test-fixture"))]
+#[derive(Debug)]
+pub struct SeededRngProvider {
+    seed: u64,
+    next: AtomicU64,
+}
+
+#[cfg(any(test, feature = "test-fixture"))]
+impl SeededRngProvider {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            next: AtomicU64::new(0),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-fixture"))]
+impl CryptoRngProvider for SeededRngProvider {
+    fn rng(&self) -> BoxedCryptoRng {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut seed = <StdRng as SeedableRng>::Seed::default();
+        seed[..8].copy_from_slice(&self.seed.to_le_bytes());
+        seed[8..16].copy_from_slice(&index.to_le_bytes());
+        Box::new(StdRng::from_seed(seed))
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::{Rng, RngCore};
+
+    use super::{BoxedCryptoRng, CryptoRngProvider, SeededRngProvider, SystemRngProvider};
+
+    fn sample(provider: &dyn CryptoRngProvider) -> [u64; 4] {
+        provider.rng().gen()
+    }
+
+    #[test]
+    fn seeded() {
+        let p1 = SeededRngProvider::new(42);
+        let p2 = SeededRngProvider::new(42);
+        let first = sample(&p1);
+        assert_eq!(first, sample(&p2));
+        assert_ne!(first, sample(&p1));
+        assert_ne!(first, sample(&SeededRngProvider::new(43)));
+    }
+
+    #[test]
+    fn system() {
+        assert_ne!(sample(&SystemRngProvider), sample(&SystemRngProvider));
+    }
+
+    #[test]
+    fn closure() {
+        let provider = || -> BoxedCryptoRng { SeededRngProvider::new(1).rng() };
+        assert_eq!(
+            provider.rng().next_u64(),
+            SeededRngProvider::new(1).rng().next_u64()
+        );
+    }
+}
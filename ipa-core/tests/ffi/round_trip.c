@@ -0,0 +1,38 @@
+/*
+ * C side of the FFI round trip tests in src/ffi.rs. It uses the library only through
+ * include/ipa_core.h, the way a report collector would.
+ */
+
+#include "ipa_core.h"
+
+IpaStatus ipa_test_encrypt_conversion(const uint8_t *public_keys, IpaBuffer out[3]) {
+  IpaReport report = {
+      .event_type = IPA_EVENT_CONVERSION,
+      .match_key = 0xDEADBEEF,
+      .value = 5,
+      .conversion_site_domain = "www.example.com",
+      .timestamp = 100,
+      .epsilon = 1.0,
+      .sensitivity = 1.0,
+  };
+  return ipa_encrypt_report(&report, public_keys, out);
+}
+
+IpaStatus ipa_test_reconstruct(const IpaBuffer outputs[3], const uint64_t *expected,
+                               size_t expected_len) {
+  IpaValues values;
+  IpaStatus status = ipa_reconstruct_results(outputs, 32, &values);
+  if (status != IPA_STATUS_OK) {
+    return status;
+  }
+  if (values.len != expected_len) {
+    status = IPA_STATUS_RECONSTRUCTION_FAILED;
+  }
+  for (size_t i = 0; status == IPA_STATUS_OK && i < values.len; i++) {
+    if (values.data[i] != expected[i]) {
+      status = IPA_STATUS_RECONSTRUCTION_FAILED;
+    }
+  }
+  ipa_values_free(values);
+  return status;
+}
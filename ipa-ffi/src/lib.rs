@@ -0,0 +1,4 @@
+//! Shared and static libraries for report collectors that are not written in Rust. The functions
+//! they export are defined in [`ipa_core::ffi`], see its documentation.
+
+pub use ipa_core::ffi::*;
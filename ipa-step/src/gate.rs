@@ -106,7 +106,13 @@ fn compact_gate_impl<S: CompactStep>(gate_name: &str) -> TokenStream {
                 if s == "/" {
                     Ok(Self::default())
                 } else {
-                    GATE_LOOKUP.find(s).map(#ident).ok_or_else(|| format!(#from_panic))
+                    // The lookup only compares hashes, so check that `s` is the step it found
+                    // and not some other string that happens to collide with it.
+                    GATE_LOOKUP
+                        .find(s)
+                        .filter(|&i| STR_LOOKUP[usize::try_from(i).unwrap() - 1] == s)
+                        .map(#ident)
+                        .ok_or_else(|| format!(#from_panic))
                 }
             }
         }